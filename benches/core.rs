@@ -0,0 +1,107 @@
+//! Benchmarks for the database hot paths, run with `cargo bench`. The
+//! workloads are shared with `sn0int bench`. Criterion keeps the results of
+//! the previous run in `target/criterion`, use `--save-baseline` and
+//! `--baseline` to compare against a specific one.
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use sn0int::bench;
+use sn0int::db::Database;
+use std::cell::Cell;
+use tempfile::TempDir;
+
+const ROWS: usize = 1000;
+/// Events of the module that keeps reporting the same entity
+const DUPLICATES: usize = 5_000;
+
+/// Every benchmark that writes gets a fresh database, so the size of the
+/// workspace doesn't change between samples
+struct Scratch {
+    dir: TempDir,
+    next: Cell<usize>,
+}
+
+impl Scratch {
+    fn new() -> Scratch {
+        Scratch {
+            dir: tempfile::tempdir().unwrap(),
+            next: Cell::new(0),
+        }
+    }
+
+    fn db(&self) -> Database {
+        let i = self.next.replace(self.next.get() + 1);
+        let path = self.dir.path().join(format!("{}.sqlite", i));
+        Database::scratch(path.to_str().unwrap(), "bench").unwrap()
+    }
+
+    /// A database with `ROWS` domains that all have a subdomain
+    fn filled(&self) -> Database {
+        let db = self.db();
+        bench::insert(&db, ROWS).unwrap();
+        bench::add_subdomains(&db).unwrap();
+        db
+    }
+}
+
+fn insert(c: &mut Criterion) {
+    let scratch = Scratch::new();
+    let mut group = c.benchmark_group("insert");
+    group.sample_size(10);
+
+    group.throughput(Throughput::Elements(ROWS as u64));
+    group.bench_function("new", |b| {
+        b.iter_batched(|| scratch.db(), |db| {
+            bench::insert(&db, ROWS).unwrap();
+            db
+        }, BatchSize::PerIteration)
+    });
+    group.bench_function("existing", |b| {
+        b.iter_batched(|| {
+            let db = scratch.db();
+            bench::insert(&db, ROWS).unwrap();
+            db
+        }, |db| {
+            bench::insert_existing(&db, ROWS).unwrap();
+            db
+        }, BatchSize::PerIteration)
+    });
+
+    group.throughput(Throughput::Elements(DUPLICATES as u64));
+    for (name, cached) in [("duplicates", true), ("duplicates-uncached", false)] {
+        group.bench_function(name, |b| {
+            b.iter_batched(|| scratch.db(), |db| {
+                bench::insert_duplicates(&db, DUPLICATES, cached).unwrap();
+                db
+            }, BatchSize::PerIteration)
+        });
+    }
+
+    group.finish();
+}
+
+fn select(c: &mut Criterion) {
+    let scratch = Scratch::new();
+    let db = scratch.filled();
+    c.bench_function("select", |b| {
+        b.iter(|| bench::select(&db, 1).unwrap())
+    });
+    c.bench_function("related", |b| {
+        b.iter(|| bench::related(&db, 1).unwrap())
+    });
+}
+
+fn scope(c: &mut Criterion) {
+    let scratch = Scratch::new();
+    let db = scratch.filled();
+    c.bench_function("scope", |b| {
+        b.iter(|| bench::scope(&db, 1).unwrap())
+    });
+}
+
+fn filter_compile(c: &mut Criterion) {
+    c.bench_function("filter-compile", |b| {
+        b.iter(|| bench::compile_filter(1).unwrap())
+    });
+}
+
+criterion_group!(benches, insert, select, scope, filter_compile);
+criterion_main!(benches);
@@ -5,7 +5,7 @@ extern crate sn0int;
 
 fn stage1(sh: &mut boxxy::Shell, _args: Vec<String>) -> Result<(), boxxy::Error> {
     shprintln!(sh, "[*] starting stage1");
-    sn0int::sandbox::init().unwrap();
+    sn0int::sandbox::init(sn0int::sandbox::Profile::Network).unwrap();
     shprintln!(sh, "[+] activated!");
     Ok(())
 }
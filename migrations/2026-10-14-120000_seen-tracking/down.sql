@@ -0,0 +1,42 @@
+ALTER TABLE accounts DROP COLUMN seen_count;
+ALTER TABLE accounts DROP COLUMN seen_first;
+ALTER TABLE accounts DROP COLUMN seen_last;
+ALTER TABLE breaches DROP COLUMN seen_count;
+ALTER TABLE breaches DROP COLUMN seen_first;
+ALTER TABLE breaches DROP COLUMN seen_last;
+ALTER TABLE cryptoaddrs DROP COLUMN seen_count;
+ALTER TABLE cryptoaddrs DROP COLUMN seen_first;
+ALTER TABLE cryptoaddrs DROP COLUMN seen_last;
+ALTER TABLE devices DROP COLUMN seen_count;
+ALTER TABLE devices DROP COLUMN seen_first;
+ALTER TABLE devices DROP COLUMN seen_last;
+ALTER TABLE domains DROP COLUMN seen_count;
+ALTER TABLE domains DROP COLUMN seen_first;
+ALTER TABLE domains DROP COLUMN seen_last;
+ALTER TABLE emails DROP COLUMN seen_count;
+ALTER TABLE emails DROP COLUMN seen_first;
+ALTER TABLE emails DROP COLUMN seen_last;
+ALTER TABLE images DROP COLUMN seen_count;
+ALTER TABLE images DROP COLUMN seen_first;
+ALTER TABLE images DROP COLUMN seen_last;
+ALTER TABLE ipaddrs DROP COLUMN seen_count;
+ALTER TABLE ipaddrs DROP COLUMN seen_first;
+ALTER TABLE ipaddrs DROP COLUMN seen_last;
+ALTER TABLE netblocks DROP COLUMN seen_count;
+ALTER TABLE netblocks DROP COLUMN seen_first;
+ALTER TABLE netblocks DROP COLUMN seen_last;
+ALTER TABLE networks DROP COLUMN seen_count;
+ALTER TABLE networks DROP COLUMN seen_first;
+ALTER TABLE networks DROP COLUMN seen_last;
+ALTER TABLE phonenumbers DROP COLUMN seen_count;
+ALTER TABLE phonenumbers DROP COLUMN seen_first;
+ALTER TABLE phonenumbers DROP COLUMN seen_last;
+ALTER TABLE ports DROP COLUMN seen_count;
+ALTER TABLE ports DROP COLUMN seen_first;
+ALTER TABLE ports DROP COLUMN seen_last;
+ALTER TABLE subdomains DROP COLUMN seen_count;
+ALTER TABLE subdomains DROP COLUMN seen_first;
+ALTER TABLE subdomains DROP COLUMN seen_last;
+ALTER TABLE urls DROP COLUMN seen_count;
+ALTER TABLE urls DROP COLUMN seen_first;
+ALTER TABLE urls DROP COLUMN seen_last;
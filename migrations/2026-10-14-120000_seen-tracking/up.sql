@@ -0,0 +1,58 @@
+ALTER TABLE accounts ADD COLUMN seen_count INTEGER DEFAULT 1 NOT NULL;
+ALTER TABLE accounts ADD COLUMN seen_first DATETIME;
+ALTER TABLE accounts ADD COLUMN seen_last DATETIME;
+ALTER TABLE breaches ADD COLUMN seen_count INTEGER DEFAULT 1 NOT NULL;
+ALTER TABLE breaches ADD COLUMN seen_first DATETIME;
+ALTER TABLE breaches ADD COLUMN seen_last DATETIME;
+ALTER TABLE cryptoaddrs ADD COLUMN seen_count INTEGER DEFAULT 1 NOT NULL;
+ALTER TABLE cryptoaddrs ADD COLUMN seen_first DATETIME;
+ALTER TABLE cryptoaddrs ADD COLUMN seen_last DATETIME;
+ALTER TABLE devices ADD COLUMN seen_count INTEGER DEFAULT 1 NOT NULL;
+ALTER TABLE devices ADD COLUMN seen_first DATETIME;
+ALTER TABLE devices ADD COLUMN seen_last DATETIME;
+ALTER TABLE domains ADD COLUMN seen_count INTEGER DEFAULT 1 NOT NULL;
+ALTER TABLE domains ADD COLUMN seen_first DATETIME;
+ALTER TABLE domains ADD COLUMN seen_last DATETIME;
+ALTER TABLE emails ADD COLUMN seen_count INTEGER DEFAULT 1 NOT NULL;
+ALTER TABLE emails ADD COLUMN seen_first DATETIME;
+ALTER TABLE emails ADD COLUMN seen_last DATETIME;
+ALTER TABLE images ADD COLUMN seen_count INTEGER DEFAULT 1 NOT NULL;
+ALTER TABLE images ADD COLUMN seen_first DATETIME;
+ALTER TABLE images ADD COLUMN seen_last DATETIME;
+ALTER TABLE ipaddrs ADD COLUMN seen_count INTEGER DEFAULT 1 NOT NULL;
+ALTER TABLE ipaddrs ADD COLUMN seen_first DATETIME;
+ALTER TABLE ipaddrs ADD COLUMN seen_last DATETIME;
+ALTER TABLE netblocks ADD COLUMN seen_count INTEGER DEFAULT 1 NOT NULL;
+ALTER TABLE netblocks ADD COLUMN seen_first DATETIME;
+ALTER TABLE netblocks ADD COLUMN seen_last DATETIME;
+ALTER TABLE networks ADD COLUMN seen_count INTEGER DEFAULT 1 NOT NULL;
+ALTER TABLE networks ADD COLUMN seen_first DATETIME;
+ALTER TABLE networks ADD COLUMN seen_last DATETIME;
+ALTER TABLE phonenumbers ADD COLUMN seen_count INTEGER DEFAULT 1 NOT NULL;
+ALTER TABLE phonenumbers ADD COLUMN seen_first DATETIME;
+ALTER TABLE phonenumbers ADD COLUMN seen_last DATETIME;
+ALTER TABLE ports ADD COLUMN seen_count INTEGER DEFAULT 1 NOT NULL;
+ALTER TABLE ports ADD COLUMN seen_first DATETIME;
+ALTER TABLE ports ADD COLUMN seen_last DATETIME;
+ALTER TABLE subdomains ADD COLUMN seen_count INTEGER DEFAULT 1 NOT NULL;
+ALTER TABLE subdomains ADD COLUMN seen_first DATETIME;
+ALTER TABLE subdomains ADD COLUMN seen_last DATETIME;
+ALTER TABLE urls ADD COLUMN seen_count INTEGER DEFAULT 1 NOT NULL;
+ALTER TABLE urls ADD COLUMN seen_first DATETIME;
+ALTER TABLE urls ADD COLUMN seen_last DATETIME;
+
+-- there is no record of when existing entities have been discovered
+UPDATE accounts SET seen_first = CURRENT_TIMESTAMP, seen_last = CURRENT_TIMESTAMP;
+UPDATE breaches SET seen_first = CURRENT_TIMESTAMP, seen_last = CURRENT_TIMESTAMP;
+UPDATE cryptoaddrs SET seen_first = CURRENT_TIMESTAMP, seen_last = CURRENT_TIMESTAMP;
+UPDATE devices SET seen_first = CURRENT_TIMESTAMP, seen_last = CURRENT_TIMESTAMP;
+UPDATE domains SET seen_first = CURRENT_TIMESTAMP, seen_last = CURRENT_TIMESTAMP;
+UPDATE emails SET seen_first = CURRENT_TIMESTAMP, seen_last = CURRENT_TIMESTAMP;
+UPDATE images SET seen_first = CURRENT_TIMESTAMP, seen_last = CURRENT_TIMESTAMP;
+UPDATE ipaddrs SET seen_first = CURRENT_TIMESTAMP, seen_last = CURRENT_TIMESTAMP;
+UPDATE netblocks SET seen_first = CURRENT_TIMESTAMP, seen_last = CURRENT_TIMESTAMP;
+UPDATE networks SET seen_first = CURRENT_TIMESTAMP, seen_last = CURRENT_TIMESTAMP;
+UPDATE phonenumbers SET seen_first = CURRENT_TIMESTAMP, seen_last = CURRENT_TIMESTAMP;
+UPDATE ports SET seen_first = CURRENT_TIMESTAMP, seen_last = CURRENT_TIMESTAMP;
+UPDATE subdomains SET seen_first = CURRENT_TIMESTAMP, seen_last = CURRENT_TIMESTAMP;
+UPDATE urls SET seen_first = CURRENT_TIMESTAMP, seen_last = CURRENT_TIMESTAMP;
@@ -0,0 +1,12 @@
+CREATE TABLE provenance (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    family VARCHAR NOT NULL,
+    value VARCHAR NOT NULL,
+    time DATETIME NOT NULL,
+    action VARCHAR NOT NULL,
+    module VARCHAR,
+    version VARCHAR,
+    target VARCHAR,
+    details VARCHAR
+);
+CREATE INDEX provenance_family_value ON provenance(family, value);
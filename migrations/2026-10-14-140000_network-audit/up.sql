@@ -0,0 +1,16 @@
+CREATE TABLE network_audit (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    time DATETIME NOT NULL,
+    module VARCHAR,
+    target VARCHAR,
+    kind VARCHAR NOT NULL,
+    host VARCHAR NOT NULL,
+    port INTEGER,
+    method VARCHAR,
+    url VARCHAR,
+    headers VARCHAR,
+    status INTEGER,
+    bytes INTEGER,
+    error VARCHAR
+);
+CREATE INDEX network_audit_time ON network_audit(time);
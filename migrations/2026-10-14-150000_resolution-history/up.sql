@@ -0,0 +1,15 @@
+CREATE TABLE resolutions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    subdomain_id INTEGER NOT NULL,
+    ip_addr_id INTEGER NOT NULL,
+    first_seen DATETIME NOT NULL,
+    last_seen DATETIME NOT NULL,
+    FOREIGN KEY(subdomain_id) REFERENCES subdomains(id) ON DELETE CASCADE,
+    FOREIGN KEY(ip_addr_id) REFERENCES ipaddrs(id) ON DELETE CASCADE,
+    CONSTRAINT resolution_unique UNIQUE (subdomain_id, ip_addr_id)
+);
+
+-- there is no record of when existing links have been discovered
+INSERT INTO resolutions (subdomain_id, ip_addr_id, first_seen, last_seen)
+    SELECT subdomain_id, ip_addr_id, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP
+    FROM subdomain_ipaddrs;
@@ -0,0 +1,5 @@
+CREATE TABLE views (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    name VARCHAR NOT NULL UNIQUE,
+    query VARCHAR NOT NULL
+);
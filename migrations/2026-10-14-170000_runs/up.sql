@@ -0,0 +1,6 @@
+CREATE TABLE runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    module VARCHAR NOT NULL,
+    version VARCHAR NOT NULL,
+    started DATETIME NOT NULL
+);
@@ -0,0 +1,2 @@
+ALTER TABLE ports DROP COLUMN ja3s;
+ALTER TABLE ports DROP COLUMN ja4s;
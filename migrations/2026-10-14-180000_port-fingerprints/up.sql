@@ -0,0 +1,2 @@
+ALTER TABLE ports ADD COLUMN ja3s VARCHAR;
+ALTER TABLE ports ADD COLUMN ja4s VARCHAR;
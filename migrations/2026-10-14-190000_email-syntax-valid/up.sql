@@ -0,0 +1,2 @@
+-- existing rows are checked when the migrations have been applied
+ALTER TABLE emails ADD COLUMN syntax_valid BOOLEAN;
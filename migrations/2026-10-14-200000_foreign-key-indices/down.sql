@@ -0,0 +1,7 @@
+DROP INDEX subdomains_domain_id;
+DROP INDEX urls_subdomain_id;
+DROP INDEX ports_ip_addr_id;
+DROP INDEX subdomain_ipaddrs_ip_addr_id;
+DROP INDEX network_devices_device_id;
+DROP INDEX breach_emails_email_id;
+DROP INDEX resolutions_ip_addr_id;
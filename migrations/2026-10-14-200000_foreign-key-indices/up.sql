@@ -0,0 +1,10 @@
+-- every entity table already has a unique index on value from its UNIQUE
+-- constraint, the foreign keys weren't indexed. The unique constraints of
+-- the link tables cover their first column only.
+CREATE INDEX subdomains_domain_id ON subdomains(domain_id);
+CREATE INDEX urls_subdomain_id ON urls(subdomain_id);
+CREATE INDEX ports_ip_addr_id ON ports(ip_addr_id);
+CREATE INDEX subdomain_ipaddrs_ip_addr_id ON subdomain_ipaddrs(ip_addr_id);
+CREATE INDEX network_devices_device_id ON network_devices(device_id);
+CREATE INDEX breach_emails_email_id ON breach_emails(email_id);
+CREATE INDEX resolutions_ip_addr_id ON resolutions(ip_addr_id);
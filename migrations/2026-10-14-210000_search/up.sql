@@ -0,0 +1,220 @@
+-- every entity is indexed with its value and its text columns. The rowid is
+-- the id times 32 plus the position of the table in `Table::ALL`, so the
+-- triggers find the row without a scan.
+CREATE VIRTUAL TABLE search_index USING fts5(
+    family UNINDEXED,
+    entity_id UNINDEXED,
+    unscoped UNINDEXED,
+    value,
+    text
+);
+
+-- domains
+INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+    SELECT id * 32 + 0, 'domain', id, unscoped, value, '' FROM domains;
+CREATE TRIGGER domains_search_insert AFTER INSERT ON domains BEGIN
+    INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+        VALUES (new.id * 32 + 0, 'domain', new.id, new.unscoped, new.value, '');
+END;
+CREATE TRIGGER domains_search_update AFTER UPDATE OF value, unscoped ON domains BEGIN
+    UPDATE search_index SET unscoped = new.unscoped, value = new.value, text = ''
+        WHERE rowid = old.id * 32 + 0;
+END;
+CREATE TRIGGER domains_search_delete AFTER DELETE ON domains BEGIN
+    DELETE FROM search_index WHERE rowid = old.id * 32 + 0;
+END;
+
+-- subdomains
+INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+    SELECT id * 32 + 1, 'subdomain', id, unscoped, value, '' FROM subdomains;
+CREATE TRIGGER subdomains_search_insert AFTER INSERT ON subdomains BEGIN
+    INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+        VALUES (new.id * 32 + 1, 'subdomain', new.id, new.unscoped, new.value, '');
+END;
+CREATE TRIGGER subdomains_search_update AFTER UPDATE OF value, unscoped ON subdomains BEGIN
+    UPDATE search_index SET unscoped = new.unscoped, value = new.value, text = ''
+        WHERE rowid = old.id * 32 + 1;
+END;
+CREATE TRIGGER subdomains_search_delete AFTER DELETE ON subdomains BEGIN
+    DELETE FROM search_index WHERE rowid = old.id * 32 + 1;
+END;
+
+-- ipaddrs
+INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+    SELECT id * 32 + 2, 'ipaddr', id, unscoped, value, trim(COALESCE(country, '') || ' ' || COALESCE(city, '') || ' ' || COALESCE(as_org, '') || ' ' || COALESCE(description, '') || ' ' || COALESCE(reverse_dns, '')) FROM ipaddrs;
+CREATE TRIGGER ipaddrs_search_insert AFTER INSERT ON ipaddrs BEGIN
+    INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+        VALUES (new.id * 32 + 2, 'ipaddr', new.id, new.unscoped, new.value, trim(COALESCE(new.country, '') || ' ' || COALESCE(new.city, '') || ' ' || COALESCE(new.as_org, '') || ' ' || COALESCE(new.description, '') || ' ' || COALESCE(new.reverse_dns, '')));
+END;
+CREATE TRIGGER ipaddrs_search_update AFTER UPDATE OF value, unscoped, country, city, as_org, description, reverse_dns ON ipaddrs BEGIN
+    UPDATE search_index SET unscoped = new.unscoped, value = new.value, text = trim(COALESCE(new.country, '') || ' ' || COALESCE(new.city, '') || ' ' || COALESCE(new.as_org, '') || ' ' || COALESCE(new.description, '') || ' ' || COALESCE(new.reverse_dns, ''))
+        WHERE rowid = old.id * 32 + 2;
+END;
+CREATE TRIGGER ipaddrs_search_delete AFTER DELETE ON ipaddrs BEGIN
+    DELETE FROM search_index WHERE rowid = old.id * 32 + 2;
+END;
+
+-- urls
+INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+    SELECT id * 32 + 4, 'url', id, unscoped, value, trim(COALESCE(title, '') || ' ' || COALESCE(redirect, '')) FROM urls;
+CREATE TRIGGER urls_search_insert AFTER INSERT ON urls BEGIN
+    INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+        VALUES (new.id * 32 + 4, 'url', new.id, new.unscoped, new.value, trim(COALESCE(new.title, '') || ' ' || COALESCE(new.redirect, '')));
+END;
+CREATE TRIGGER urls_search_update AFTER UPDATE OF value, unscoped, title, redirect ON urls BEGIN
+    UPDATE search_index SET unscoped = new.unscoped, value = new.value, text = trim(COALESCE(new.title, '') || ' ' || COALESCE(new.redirect, ''))
+        WHERE rowid = old.id * 32 + 4;
+END;
+CREATE TRIGGER urls_search_delete AFTER DELETE ON urls BEGIN
+    DELETE FROM search_index WHERE rowid = old.id * 32 + 4;
+END;
+
+-- emails
+INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+    SELECT id * 32 + 5, 'email', id, unscoped, value, trim(COALESCE(displayname, '')) FROM emails;
+CREATE TRIGGER emails_search_insert AFTER INSERT ON emails BEGIN
+    INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+        VALUES (new.id * 32 + 5, 'email', new.id, new.unscoped, new.value, trim(COALESCE(new.displayname, '')));
+END;
+CREATE TRIGGER emails_search_update AFTER UPDATE OF value, unscoped, displayname ON emails BEGIN
+    UPDATE search_index SET unscoped = new.unscoped, value = new.value, text = trim(COALESCE(new.displayname, ''))
+        WHERE rowid = old.id * 32 + 5;
+END;
+CREATE TRIGGER emails_search_delete AFTER DELETE ON emails BEGIN
+    DELETE FROM search_index WHERE rowid = old.id * 32 + 5;
+END;
+
+-- phonenumbers
+INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+    SELECT id * 32 + 6, 'phonenumber', id, unscoped, value, trim(COALESCE(name, '') || ' ' || COALESCE(country, '') || ' ' || COALESCE(carrier, '') || ' ' || COALESCE(caller_name, '')) FROM phonenumbers;
+CREATE TRIGGER phonenumbers_search_insert AFTER INSERT ON phonenumbers BEGIN
+    INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+        VALUES (new.id * 32 + 6, 'phonenumber', new.id, new.unscoped, new.value, trim(COALESCE(new.name, '') || ' ' || COALESCE(new.country, '') || ' ' || COALESCE(new.carrier, '') || ' ' || COALESCE(new.caller_name, '')));
+END;
+CREATE TRIGGER phonenumbers_search_update AFTER UPDATE OF value, unscoped, name, country, carrier, caller_name ON phonenumbers BEGIN
+    UPDATE search_index SET unscoped = new.unscoped, value = new.value, text = trim(COALESCE(new.name, '') || ' ' || COALESCE(new.country, '') || ' ' || COALESCE(new.carrier, '') || ' ' || COALESCE(new.caller_name, ''))
+        WHERE rowid = old.id * 32 + 6;
+END;
+CREATE TRIGGER phonenumbers_search_delete AFTER DELETE ON phonenumbers BEGIN
+    DELETE FROM search_index WHERE rowid = old.id * 32 + 6;
+END;
+
+-- devices
+INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+    SELECT id * 32 + 7, 'device', id, unscoped, value, trim(COALESCE(name, '') || ' ' || COALESCE(hostname, '') || ' ' || COALESCE(vendor, '')) FROM devices;
+CREATE TRIGGER devices_search_insert AFTER INSERT ON devices BEGIN
+    INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+        VALUES (new.id * 32 + 7, 'device', new.id, new.unscoped, new.value, trim(COALESCE(new.name, '') || ' ' || COALESCE(new.hostname, '') || ' ' || COALESCE(new.vendor, '')));
+END;
+CREATE TRIGGER devices_search_update AFTER UPDATE OF value, unscoped, name, hostname, vendor ON devices BEGIN
+    UPDATE search_index SET unscoped = new.unscoped, value = new.value, text = trim(COALESCE(new.name, '') || ' ' || COALESCE(new.hostname, '') || ' ' || COALESCE(new.vendor, ''))
+        WHERE rowid = old.id * 32 + 7;
+END;
+CREATE TRIGGER devices_search_delete AFTER DELETE ON devices BEGIN
+    DELETE FROM search_index WHERE rowid = old.id * 32 + 7;
+END;
+
+-- networks
+INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+    SELECT id * 32 + 8, 'network', id, unscoped, value, trim(COALESCE(description, '')) FROM networks;
+CREATE TRIGGER networks_search_insert AFTER INSERT ON networks BEGIN
+    INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+        VALUES (new.id * 32 + 8, 'network', new.id, new.unscoped, new.value, trim(COALESCE(new.description, '')));
+END;
+CREATE TRIGGER networks_search_update AFTER UPDATE OF value, unscoped, description ON networks BEGIN
+    UPDATE search_index SET unscoped = new.unscoped, value = new.value, text = trim(COALESCE(new.description, ''))
+        WHERE rowid = old.id * 32 + 8;
+END;
+CREATE TRIGGER networks_search_delete AFTER DELETE ON networks BEGIN
+    DELETE FROM search_index WHERE rowid = old.id * 32 + 8;
+END;
+
+-- accounts
+INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+    SELECT id * 32 + 10, 'account', id, unscoped, value, trim(COALESCE(service, '') || ' ' || COALESCE(username, '') || ' ' || COALESCE(displayname, '') || ' ' || COALESCE(email, '') || ' ' || COALESCE(url, '')) FROM accounts;
+CREATE TRIGGER accounts_search_insert AFTER INSERT ON accounts BEGIN
+    INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+        VALUES (new.id * 32 + 10, 'account', new.id, new.unscoped, new.value, trim(COALESCE(new.service, '') || ' ' || COALESCE(new.username, '') || ' ' || COALESCE(new.displayname, '') || ' ' || COALESCE(new.email, '') || ' ' || COALESCE(new.url, '')));
+END;
+CREATE TRIGGER accounts_search_update AFTER UPDATE OF value, unscoped, service, username, displayname, email, url ON accounts BEGIN
+    UPDATE search_index SET unscoped = new.unscoped, value = new.value, text = trim(COALESCE(new.service, '') || ' ' || COALESCE(new.username, '') || ' ' || COALESCE(new.displayname, '') || ' ' || COALESCE(new.email, '') || ' ' || COALESCE(new.url, ''))
+        WHERE rowid = old.id * 32 + 10;
+END;
+CREATE TRIGGER accounts_search_delete AFTER DELETE ON accounts BEGIN
+    DELETE FROM search_index WHERE rowid = old.id * 32 + 10;
+END;
+
+-- breaches
+INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+    SELECT id * 32 + 11, 'breach', id, unscoped, value, '' FROM breaches;
+CREATE TRIGGER breaches_search_insert AFTER INSERT ON breaches BEGIN
+    INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+        VALUES (new.id * 32 + 11, 'breach', new.id, new.unscoped, new.value, '');
+END;
+CREATE TRIGGER breaches_search_update AFTER UPDATE OF value, unscoped ON breaches BEGIN
+    UPDATE search_index SET unscoped = new.unscoped, value = new.value, text = ''
+        WHERE rowid = old.id * 32 + 11;
+END;
+CREATE TRIGGER breaches_search_delete AFTER DELETE ON breaches BEGIN
+    DELETE FROM search_index WHERE rowid = old.id * 32 + 11;
+END;
+
+-- images
+INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+    SELECT id * 32 + 13, 'image', id, unscoped, value, trim(COALESCE(filename, '')) FROM images;
+CREATE TRIGGER images_search_insert AFTER INSERT ON images BEGIN
+    INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+        VALUES (new.id * 32 + 13, 'image', new.id, new.unscoped, new.value, trim(COALESCE(new.filename, '')));
+END;
+CREATE TRIGGER images_search_update AFTER UPDATE OF value, unscoped, filename ON images BEGIN
+    UPDATE search_index SET unscoped = new.unscoped, value = new.value, text = trim(COALESCE(new.filename, ''))
+        WHERE rowid = old.id * 32 + 13;
+END;
+CREATE TRIGGER images_search_delete AFTER DELETE ON images BEGIN
+    DELETE FROM search_index WHERE rowid = old.id * 32 + 13;
+END;
+
+-- ports
+INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+    SELECT id * 32 + 14, 'port', id, unscoped, value, trim(COALESCE(banner, '') || ' ' || COALESCE(service, '') || ' ' || COALESCE(version, '')) FROM ports;
+CREATE TRIGGER ports_search_insert AFTER INSERT ON ports BEGIN
+    INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+        VALUES (new.id * 32 + 14, 'port', new.id, new.unscoped, new.value, trim(COALESCE(new.banner, '') || ' ' || COALESCE(new.service, '') || ' ' || COALESCE(new.version, '')));
+END;
+CREATE TRIGGER ports_search_update AFTER UPDATE OF value, unscoped, banner, service, version ON ports BEGIN
+    UPDATE search_index SET unscoped = new.unscoped, value = new.value, text = trim(COALESCE(new.banner, '') || ' ' || COALESCE(new.service, '') || ' ' || COALESCE(new.version, ''))
+        WHERE rowid = old.id * 32 + 14;
+END;
+CREATE TRIGGER ports_search_delete AFTER DELETE ON ports BEGIN
+    DELETE FROM search_index WHERE rowid = old.id * 32 + 14;
+END;
+
+-- netblocks
+INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+    SELECT id * 32 + 15, 'netblock', id, unscoped, value, trim(COALESCE(as_org, '') || ' ' || COALESCE(description, '')) FROM netblocks;
+CREATE TRIGGER netblocks_search_insert AFTER INSERT ON netblocks BEGIN
+    INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+        VALUES (new.id * 32 + 15, 'netblock', new.id, new.unscoped, new.value, trim(COALESCE(new.as_org, '') || ' ' || COALESCE(new.description, '')));
+END;
+CREATE TRIGGER netblocks_search_update AFTER UPDATE OF value, unscoped, as_org, description ON netblocks BEGIN
+    UPDATE search_index SET unscoped = new.unscoped, value = new.value, text = trim(COALESCE(new.as_org, '') || ' ' || COALESCE(new.description, ''))
+        WHERE rowid = old.id * 32 + 15;
+END;
+CREATE TRIGGER netblocks_search_delete AFTER DELETE ON netblocks BEGIN
+    DELETE FROM search_index WHERE rowid = old.id * 32 + 15;
+END;
+
+-- cryptoaddrs
+INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+    SELECT id * 32 + 16, 'cryptoaddr', id, unscoped, value, trim(COALESCE(currency, '') || ' ' || COALESCE(description, '')) FROM cryptoaddrs;
+CREATE TRIGGER cryptoaddrs_search_insert AFTER INSERT ON cryptoaddrs BEGIN
+    INSERT INTO search_index (rowid, family, entity_id, unscoped, value, text)
+        VALUES (new.id * 32 + 16, 'cryptoaddr', new.id, new.unscoped, new.value, trim(COALESCE(new.currency, '') || ' ' || COALESCE(new.description, '')));
+END;
+CREATE TRIGGER cryptoaddrs_search_update AFTER UPDATE OF value, unscoped, currency, description ON cryptoaddrs BEGIN
+    UPDATE search_index SET unscoped = new.unscoped, value = new.value, text = trim(COALESCE(new.currency, '') || ' ' || COALESCE(new.description, ''))
+        WHERE rowid = old.id * 32 + 16;
+END;
+CREATE TRIGGER cryptoaddrs_search_delete AFTER DELETE ON cryptoaddrs BEGIN
+    DELETE FROM search_index WHERE rowid = old.id * 32 + 16;
+END;
@@ -0,0 +1 @@
+ALTER TABLE network_audit DROP COLUMN ipaddr;
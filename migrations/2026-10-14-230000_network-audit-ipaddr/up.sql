@@ -0,0 +1 @@
+ALTER TABLE network_audit ADD COLUMN ipaddr VARCHAR;
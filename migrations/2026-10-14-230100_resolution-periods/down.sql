@@ -0,0 +1,26 @@
+PRAGMA foreign_keys=off;
+
+ALTER TABLE resolutions RENAME TO _resolutions_old;
+
+CREATE TABLE resolutions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    subdomain_id INTEGER NOT NULL,
+    ip_addr_id INTEGER NOT NULL,
+    first_seen DATETIME NOT NULL,
+    last_seen DATETIME NOT NULL,
+    FOREIGN KEY(subdomain_id) REFERENCES subdomains(id) ON DELETE CASCADE,
+    FOREIGN KEY(ip_addr_id) REFERENCES ipaddrs(id) ON DELETE CASCADE,
+    CONSTRAINT resolution_unique UNIQUE (subdomain_id, ip_addr_id)
+);
+
+-- only the most recent period of every address can be kept
+INSERT INTO resolutions (subdomain_id, ip_addr_id, first_seen, last_seen)
+    SELECT subdomain_id, ip_addr_id, MIN(first_seen), MAX(last_seen)
+    FROM _resolutions_old
+    GROUP BY subdomain_id, ip_addr_id;
+
+DROP TABLE _resolutions_old;
+
+CREATE INDEX resolutions_ip_addr_id ON resolutions(ip_addr_id);
+
+PRAGMA foreign_keys=on;
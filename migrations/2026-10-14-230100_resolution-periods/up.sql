@@ -0,0 +1,26 @@
+PRAGMA foreign_keys=off;
+
+-- a subdomain may resolve to the same address again after it changed, every
+-- period is kept in its own row
+ALTER TABLE resolutions RENAME TO _resolutions_old;
+
+CREATE TABLE resolutions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    subdomain_id INTEGER NOT NULL,
+    ip_addr_id INTEGER NOT NULL,
+    first_seen DATETIME NOT NULL,
+    last_seen DATETIME NOT NULL,
+    FOREIGN KEY(subdomain_id) REFERENCES subdomains(id) ON DELETE CASCADE,
+    FOREIGN KEY(ip_addr_id) REFERENCES ipaddrs(id) ON DELETE CASCADE
+);
+
+INSERT INTO resolutions (id, subdomain_id, ip_addr_id, first_seen, last_seen)
+    SELECT id, subdomain_id, ip_addr_id, first_seen, last_seen
+    FROM _resolutions_old;
+
+DROP TABLE _resolutions_old;
+
+CREATE INDEX resolutions_subdomain_ip_addr_id ON resolutions(subdomain_id, ip_addr_id);
+CREATE INDEX resolutions_ip_addr_id ON resolutions(ip_addr_id);
+
+PRAGMA foreign_keys=on;
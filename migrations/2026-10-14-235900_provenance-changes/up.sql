@@ -0,0 +1,3 @@
+-- the new values of an update as json object, the details are only meant to
+-- be read by humans
+ALTER TABLE provenance ADD COLUMN changes VARCHAR;
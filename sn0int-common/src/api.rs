@@ -1,4 +1,5 @@
 use crate::id::ModuleID;
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -9,6 +10,15 @@ pub struct WhoamiResponse {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PublishRequest {
     pub code: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<ModuleSignature>,
+}
+
+/// A detached ed25519 signature of a release, both fields are base64
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModuleSignature {
+    pub public_key: String,
+    pub signature: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -24,6 +34,8 @@ pub struct DownloadResponse {
     pub name: String,
     pub version: String,
     pub code: String,
+    #[serde(default)]
+    pub signature: Option<ModuleSignature>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -41,6 +53,30 @@ impl ModuleInfoResponse {
     }
 }
 
+/// How search results are ordered, featured modules come first if this isn't set
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchSort {
+    /// Most downloaded first
+    Downloads,
+    /// Most recently published first
+    Recent,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SearchRequest {
+    pub q: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emits: Option<String>,
+    /// The entity group a module reads from, eg. `subdomains`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort: Option<SearchSort>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SearchResponse {
     pub author: String,
@@ -49,6 +85,18 @@ pub struct SearchResponse {
     pub latest: String,
     pub downloads: i64,
     pub featured: bool,
+    /// Average of all ratings, `None` if the module hasn't been rated yet
+    #[serde(default)]
+    pub rating: Option<f32>,
+    #[serde(default)]
+    pub ratings: i64,
+    #[serde(default)]
+    pub emits: Vec<String>,
+    #[serde(default)]
+    pub source: Option<String>,
+    /// Unix time of the latest release
+    #[serde(default)]
+    pub published: Option<u64>,
 }
 
 impl SearchResponse {
@@ -57,6 +105,19 @@ impl SearchResponse {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RateRequest {
+    pub stars: u8,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RateResponse {
+    pub author: String,
+    pub name: String,
+    pub rating: Option<f32>,
+    pub ratings: i64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LatestResponse {
     pub time: Option<u64>,
@@ -0,0 +1,115 @@
+use crate::errors::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// An optional feature a module has to declare before it's allowed to use it
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Capability {
+    Http,
+    Websocket,
+    Socket,
+    Mqtt,
+    Blob,
+    Notify,
+    Stdin,
+}
+
+/// What a module without a `-- Capabilities:` declaration is allowed to use
+pub const DEFAULT_CAPABILITIES: &[Capability] = &[Capability::Http];
+
+/// Every capability, what modules could use before they had to declare them
+pub const ALL_CAPABILITIES: &[Capability] = &[
+    Capability::Http,
+    Capability::Websocket,
+    Capability::Socket,
+    Capability::Mqtt,
+    Capability::Blob,
+    Capability::Notify,
+    Capability::Stdin,
+];
+
+impl Capability {
+    /// The declared capabilities, or the default if there's no declaration
+    pub fn declared_or_default(declared: Option<&[Capability]>) -> &[Capability] {
+        declared.unwrap_or(DEFAULT_CAPABILITIES)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Capability::Http => "http",
+            Capability::Websocket => "websocket",
+            Capability::Socket => "socket",
+            Capability::Mqtt => "mqtt",
+            Capability::Blob => "blob",
+            Capability::Notify => "notify",
+            Capability::Stdin => "stdin",
+        }
+    }
+
+    /// If the capability opens connections from the module process, everything
+    /// else is handled by the parent
+    pub fn uses_network(&self) -> bool {
+        matches!(self, Capability::Http | Capability::Websocket | Capability::Socket | Capability::Mqtt)
+    }
+
+    /// Parse a list like `http, blob`
+    ///
+    /// `none` declares that the module doesn't use any of them.
+    pub fn parse_list(s: &str) -> Result<Vec<Capability>> {
+        if s.trim() == "none" {
+            return Ok(Vec::new());
+        }
+
+        s.split(',').map(|x| x.trim().parse()).collect()
+    }
+}
+
+impl FromStr for Capability {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Capability> {
+        match s {
+            "http" => Ok(Capability::Http),
+            "websocket" => Ok(Capability::Websocket),
+            "socket" => Ok(Capability::Socket),
+            "mqtt" => Ok(Capability::Mqtt),
+            "blob" => Ok(Capability::Blob),
+            "notify" => Ok(Capability::Notify),
+            "stdin" => Ok(Capability::Stdin),
+            x => bail!("Unknown capability: {:?}", x),
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(w, "{}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_parse_list() {
+        let list = Capability::parse_list("http, blob,stdin").unwrap();
+        assert_eq!(
+            list,
+            vec![Capability::Http, Capability::Blob, Capability::Stdin]
+        );
+    }
+
+    #[test]
+    fn verify_parse_none() {
+        let list = Capability::parse_list("none").unwrap();
+        assert_eq!(list, vec![]);
+    }
+
+    #[test]
+    fn verify_parse_unknown() {
+        assert!(Capability::parse_list("http, filesystem").is_err());
+    }
+}
@@ -0,0 +1,195 @@
+use crate::errors::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// A destination a module is allowed to connect to
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum EgressRule {
+    /// Exactly this hostname
+    Host(String),
+    /// Any subdomain of this domain, written as `*.example.com`
+    Subdomains(String),
+    /// An ip address or a network in cidr notation
+    Network(IpAddr, u8),
+}
+
+impl EgressRule {
+    pub fn matches_host(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_lowercase();
+        match self {
+            EgressRule::Host(x) => *x == host,
+            EgressRule::Subdomains(x) => host
+                .strip_suffix(x.as_str())
+                .map(|prefix| prefix.ends_with('.') && prefix.len() > 1)
+                .unwrap_or(false),
+            EgressRule::Network(..) => match host.parse() {
+                Ok(addr) => self.matches_addr(&addr),
+                Err(_) => false,
+            },
+        }
+    }
+
+    pub fn matches_addr(&self, addr: &IpAddr) -> bool {
+        match (self, addr) {
+            (EgressRule::Network(IpAddr::V4(net), prefix), IpAddr::V4(addr)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(*prefix)).unwrap_or(0);
+                u32::from(*net) & mask == u32::from(*addr) & mask
+            }
+            (EgressRule::Network(IpAddr::V6(net), prefix), IpAddr::V6(addr)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(*prefix)).unwrap_or(0);
+                u128::from(*net) & mask == u128::from(*addr) & mask
+            }
+            _ => false,
+        }
+    }
+
+    #[inline]
+    pub fn is_network(&self) -> bool {
+        matches!(self, EgressRule::Network(..))
+    }
+
+    /// Parse a list like `example.com, *.example.org, 10.0.0.0/8`
+    ///
+    /// `none` declares that the module doesn't connect to anything.
+    pub fn parse_list(s: &str) -> Result<Vec<EgressRule>> {
+        if s.trim() == "none" {
+            return Ok(Vec::new());
+        }
+
+        s.split(',')
+            .map(str::trim)
+            .filter(|x| !x.is_empty())
+            .map(EgressRule::from_str)
+            .collect()
+    }
+}
+
+impl FromStr for EgressRule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<EgressRule> {
+        if let Some((addr, prefix)) = s.split_once('/') {
+            let addr = addr
+                .parse::<IpAddr>()
+                .with_context(|| anyhow!("Invalid network in egress rule: {:?}", s))?;
+            let prefix = prefix
+                .parse::<u8>()
+                .with_context(|| anyhow!("Invalid prefix in egress rule: {:?}", s))?;
+            let max = if addr.is_ipv4() { 32 } else { 128 };
+            if prefix > max {
+                bail!("Invalid prefix in egress rule: {:?}", s);
+            }
+            return Ok(EgressRule::Network(addr, prefix));
+        }
+
+        if let Ok(addr) = s.parse::<IpAddr>() {
+            let prefix = if addr.is_ipv4() { 32 } else { 128 };
+            return Ok(EgressRule::Network(addr, prefix));
+        }
+
+        let (wildcard, host) = match s.strip_prefix("*.") {
+            Some(host) => (true, host),
+            None => (false, s),
+        };
+
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '.';
+        if host.is_empty() || !host.chars().all(valid) || host.starts_with('.') {
+            bail!("Invalid host in egress rule: {:?}", s);
+        }
+
+        let host = host.trim_end_matches('.').to_lowercase();
+        if wildcard {
+            Ok(EgressRule::Subdomains(host))
+        } else {
+            Ok(EgressRule::Host(host))
+        }
+    }
+}
+
+impl fmt::Display for EgressRule {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EgressRule::Host(host) => write!(w, "{}", host),
+            EgressRule::Subdomains(host) => write!(w, "*.{}", host),
+            EgressRule::Network(addr, 32) if addr.is_ipv4() => write!(w, "{}", addr),
+            EgressRule::Network(addr, 128) if addr.is_ipv6() => write!(w, "{}", addr),
+            EgressRule::Network(addr, prefix) => write!(w, "{}/{}", addr, prefix),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(s: &str) -> EgressRule {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_list() {
+        let rules = EgressRule::parse_list("example.com, *.Example.org,10.0.0.0/8, ::1").unwrap();
+        assert_eq!(
+            rules,
+            vec![
+                EgressRule::Host("example.com".into()),
+                EgressRule::Subdomains("example.org".into()),
+                EgressRule::Network("10.0.0.0".parse().unwrap(), 8),
+                EgressRule::Network("::1".parse().unwrap(), 128),
+            ]
+        );
+        assert_eq!(EgressRule::parse_list("none").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!("10.0.0.0/33".parse::<EgressRule>().is_err());
+        assert!("10.0.0.0/x".parse::<EgressRule>().is_err());
+        assert!("https://example.com".parse::<EgressRule>().is_err());
+        assert!("*.".parse::<EgressRule>().is_err());
+        assert!("*".parse::<EgressRule>().is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        for x in &[
+            "example.com",
+            "*.example.com",
+            "10.0.0.0/8",
+            "1.1.1.1",
+            "fe80::/10",
+        ] {
+            assert_eq!(rule(x).to_string(), *x);
+        }
+    }
+
+    #[test]
+    fn test_matches_host() {
+        assert!(rule("example.com").matches_host("example.com"));
+        assert!(rule("example.com").matches_host("EXAMPLE.com."));
+        assert!(!rule("example.com").matches_host("www.example.com"));
+
+        assert!(rule("*.example.com").matches_host("www.example.com"));
+        assert!(rule("*.example.com").matches_host("a.b.example.com"));
+        assert!(!rule("*.example.com").matches_host("example.com"));
+        assert!(!rule("*.example.com").matches_host("badexample.com"));
+
+        assert!(rule("127.0.0.0/8").matches_host("127.0.0.1"));
+        assert!(!rule("127.0.0.0/8").matches_host("localhost"));
+    }
+
+    #[test]
+    fn test_matches_addr() {
+        let addr = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert!(rule("192.168.0.0/16").matches_addr(&addr("192.168.13.37")));
+        assert!(!rule("192.168.0.0/16").matches_addr(&addr("192.169.0.1")));
+        assert!(rule("0.0.0.0/0").matches_addr(&addr("1.2.3.4")));
+        assert!(rule("1.2.3.4").matches_addr(&addr("1.2.3.4")));
+        assert!(!rule("1.2.3.4").matches_addr(&addr("1.2.3.5")));
+        assert!(rule("fe80::/10").matches_addr(&addr("fe80::1")));
+        assert!(!rule("fe80::/10").matches_addr(&addr("10.0.0.1")));
+        assert!(!rule("example.com").matches_addr(&addr("10.0.0.1")));
+    }
+}
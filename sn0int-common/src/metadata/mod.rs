@@ -4,8 +4,13 @@ use nom::combinator::map_res;
 use nom::multi::fold_many0;
 use nom::IResult;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::str::FromStr;
 
+mod capability;
+pub use self::capability::{Capability, ALL_CAPABILITIES, DEFAULT_CAPABILITIES};
+mod egress;
+pub use self::egress::EgressRule;
 mod stealth;
 pub use self::stealth::Stealth;
 
@@ -15,6 +20,11 @@ pub enum EntryType {
     Version,
     Source,
     KeyringAccess,
+    KeyringOptional,
+    Egress,
+    Capabilities,
+    Emits,
+    Dns,
     Stealth,
     Author,
     Repository,
@@ -30,6 +40,11 @@ impl FromStr for EntryType {
             "Version" => Ok(EntryType::Version),
             "Source" => Ok(EntryType::Source),
             "Keyring-Access" => Ok(EntryType::KeyringAccess),
+            "Keyring-Optional" => Ok(EntryType::KeyringOptional),
+            "Egress" => Ok(EntryType::Egress),
+            "Capabilities" => Ok(EntryType::Capabilities),
+            "Emits" => Ok(EntryType::Emits),
+            "DNS" => Ok(EntryType::Dns),
             "Stealth" => Ok(EntryType::Stealth),
             "Author" => Ok(EntryType::Author),
             "Repository" => Ok(EntryType::Repository),
@@ -82,6 +97,17 @@ impl Source {
     }
 }
 
+impl fmt::Display for Source {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Accounts(Some(param))
+            | Source::CryptoAddrs(Some(param))
+            | Source::KeyRing(param) => write!(w, "{}:{}", self.group_as_str(), param),
+            _ => write!(w, "{}", self.group_as_str()),
+        }
+    }
+}
+
 impl FromStr for Source {
     type Err = Error;
 
@@ -148,6 +174,16 @@ pub struct Metadata {
     pub version: String,
     pub source: Option<Source>,
     pub keyring_access: Vec<String>,
+    /// Namespaces in `keyring_access` the module also works without
+    pub keyring_optional: Vec<String>,
+    /// `None` if the module doesn't declare where it connects to
+    pub egress: Option<Vec<EgressRule>>,
+    /// `None` if the module doesn't declare which capabilities it uses
+    pub capabilities: Option<Vec<Capability>>,
+    /// The entity families the module adds to the database
+    pub emits: Vec<String>,
+    /// If the module is allowed to resolve names, enabled by default
+    pub dns: bool,
     pub stealth: Stealth,
     pub authors: Vec<String>,
     pub repository: Option<String>,
@@ -168,6 +204,11 @@ impl FromStr for Metadata {
                 EntryType::Version => data.version = Some(v),
                 EntryType::Source => data.source = Some(v),
                 EntryType::KeyringAccess => data.keyring_access.push(v),
+                EntryType::KeyringOptional => data.keyring_optional.push(v),
+                EntryType::Egress => data.egress.push(v),
+                EntryType::Capabilities => data.capabilities.push(v),
+                EntryType::Emits => data.emits.push(v),
+                EntryType::Dns => data.dns = Some(v),
                 EntryType::Stealth => data.stealth = Some(v),
                 EntryType::Author => data.authors.push(v),
                 EntryType::Repository => data.repository = Some(v),
@@ -185,6 +226,11 @@ pub struct NewMetadata<'a> {
     pub version: Option<&'a str>,
     pub source: Option<&'a str>,
     pub keyring_access: Vec<&'a str>,
+    pub keyring_optional: Vec<&'a str>,
+    pub egress: Vec<&'a str>,
+    pub capabilities: Vec<&'a str>,
+    pub emits: Vec<&'a str>,
+    pub dns: Option<&'a str>,
     pub stealth: Option<&'a str>,
     pub authors: Vec<&'a str>,
     pub repository: Option<&'a str>,
@@ -203,7 +249,52 @@ impl<'a> NewMetadata<'a> {
             Some(x) => Some(x.parse()?),
             _ => None,
         };
-        let keyring_access = self.keyring_access.into_iter().map(String::from).collect();
+        let mut keyring_access = self
+            .keyring_access
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        let keyring_optional = self
+            .keyring_optional
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        for namespace in &keyring_optional {
+            if !keyring_access.contains(namespace) {
+                keyring_access.push(namespace.clone());
+            }
+        }
+        let egress = if self.egress.is_empty() {
+            None
+        } else {
+            let mut rules = Vec::new();
+            for x in self.egress {
+                rules.extend(EgressRule::parse_list(x)?);
+            }
+            Some(rules)
+        };
+        let capabilities = if self.capabilities.is_empty() {
+            None
+        } else {
+            let mut capabilities = Vec::new();
+            for x in self.capabilities {
+                for capability in Capability::parse_list(x)? {
+                    if !capabilities.contains(&capability) {
+                        capabilities.push(capability);
+                    }
+                }
+            }
+            Some(capabilities)
+        };
+        let mut emits = Vec::new();
+        for x in self.emits {
+            emits.extend(parse_emits(x)?);
+        }
+        let dns = match self.dns {
+            Some("true") | None => true,
+            Some("false") => false,
+            Some(x) => bail!("Invalid value for DNS, expected true or false: {:?}", x),
+        };
         let stealth = match self.stealth {
             Some(x) => x.parse()?,
             _ => Stealth::Normal,
@@ -220,6 +311,11 @@ impl<'a> NewMetadata<'a> {
             version: version.to_string(),
             source,
             keyring_access,
+            keyring_optional,
+            egress,
+            capabilities,
+            emits,
+            dns,
             stealth,
             authors,
             repository,
@@ -228,6 +324,67 @@ impl<'a> NewMetadata<'a> {
     }
 }
 
+/// Entity families that can be declared with `Emits`
+pub const EMITTABLE: &[&str] = &[
+    "domain",
+    "subdomain",
+    "ipaddr",
+    "url",
+    "email",
+    "phonenumber",
+    "device",
+    "network",
+    "account",
+    "breach",
+    "image",
+    "port",
+    "netblock",
+    "cryptoaddr",
+];
+
+/// The entity groups a module can read from, as stored by the registry
+pub const SOURCE_GROUPS: &[&str] = &[
+    "domains",
+    "subdomains",
+    "ipaddrs",
+    "urls",
+    "emails",
+    "phonenumbers",
+    "networks",
+    "devices",
+    "accounts",
+    "breaches",
+    "images",
+    "ports",
+    "netblocks",
+    "cryptoaddrs",
+    "notifications",
+    "keyring",
+];
+
+/// Resolve `subdomain` or `subdomains` to the group name of the source
+pub fn source_group(name: &str) -> Option<&'static str> {
+    SOURCE_GROUPS.iter().copied().find(|group| {
+        *group == name
+            || group.strip_suffix('s') == Some(name)
+            || group.strip_suffix("es") == Some(name)
+    })
+}
+
+/// Parse a list like `subdomain, ipaddr`
+fn parse_emits(s: &str) -> Result<Vec<String>> {
+    s.split(',')
+        .map(|x| {
+            let x = x.trim();
+            if EMITTABLE.contains(&x) {
+                Ok(x.to_string())
+            } else {
+                bail!("Unknown entity family in Emits: {:?}", x)
+            }
+        })
+        .collect()
+}
+
 fn metaline(input: &str) -> IResult<&str, (EntryType, &str)> {
     let (input, _) = tag("-- ")(input)?;
     let (input, name) = map_res(take_until(": "), EntryType::from_str)(input)?;
@@ -252,6 +409,19 @@ fn metalines(input: &str) -> IResult<&str, Vec<(EntryType, &str)>> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn verify_source_display() {
+        for x in &[
+            "domains",
+            "accounts:github",
+            "cryptoaddrs:bitcoin",
+            "keyring:shodan",
+        ] {
+            assert_eq!(x.parse::<Source>().unwrap().to_string(), *x);
+        }
+        assert_eq!(Source::Accounts(None).to_string(), "accounts");
+    }
+
     #[test]
     fn verify_simple() {
         let metadata = Metadata::from_str(
@@ -274,6 +444,11 @@ mod tests {
                 authors: vec![],
                 repository: None,
                 keyring_access: Vec::new(),
+                keyring_optional: Vec::new(),
+                egress: None,
+                capabilities: None,
+                emits: Vec::new(),
+                dns: true,
             }
         );
     }
@@ -307,6 +482,11 @@ mod tests {
                 ],
                 repository: Some("https://github.com/kpcyrd/sn0int".to_string()),
                 keyring_access: Vec::new(),
+                keyring_optional: Vec::new(),
+                egress: None,
+                capabilities: None,
+                emits: Vec::new(),
+                dns: true,
             }
         );
     }
@@ -332,10 +512,142 @@ mod tests {
                 authors: vec![],
                 repository: None,
                 keyring_access: Vec::new(),
+                keyring_optional: Vec::new(),
+                egress: None,
+                capabilities: None,
+                emits: Vec::new(),
+                dns: true,
             }
         );
     }
 
+    #[test]
+    fn verify_keyring_optional() {
+        let metadata = Metadata::from_str(
+            r#"-- Description: Hello world, this is my description
+-- Version: 1.0.0
+-- Keyring-Access: shodan
+-- Keyring-Optional: censys
+-- License: WTFPL
+
+"#,
+        )
+        .expect("parse");
+        assert_eq!(metadata.keyring_access, vec!["shodan", "censys"]);
+        assert_eq!(metadata.keyring_optional, vec!["censys"]);
+    }
+
+    #[test]
+    fn verify_egress() {
+        let metadata = Metadata::from_str(
+            r#"-- Description: Hello world, this is my description
+-- Version: 1.0.0
+-- Egress: api.example.com, *.example.org
+-- Egress: 10.0.0.0/8
+-- License: WTFPL
+
+"#,
+        )
+        .expect("parse");
+        assert_eq!(
+            metadata.egress,
+            Some(vec![
+                EgressRule::Host("api.example.com".into()),
+                EgressRule::Subdomains("example.org".into()),
+                EgressRule::Network("10.0.0.0".parse().unwrap(), 8),
+            ])
+        );
+
+        let metadata = Metadata::from_str(
+            r#"-- Description: Hello world, this is my description
+-- Version: 1.0.0
+-- Egress: none
+-- License: WTFPL
+
+"#,
+        )
+        .expect("parse");
+        assert_eq!(metadata.egress, Some(vec![]));
+    }
+
+    #[test]
+    fn verify_capabilities() {
+        let metadata = Metadata::from_str(
+            r#"-- Description: Hello world, this is my description
+-- Version: 1.0.0
+-- Capabilities: http, blob
+-- Capabilities: http
+-- License: WTFPL
+
+"#,
+        )
+        .expect("parse");
+        assert_eq!(
+            metadata.capabilities,
+            Some(vec![Capability::Http, Capability::Blob])
+        );
+
+        let metadata = Metadata::from_str(
+            r#"-- Description: Hello world, this is my description
+-- Version: 1.0.0
+-- Capabilities: none
+-- License: WTFPL
+
+"#,
+        )
+        .expect("parse");
+        assert_eq!(metadata.capabilities, Some(vec![]));
+    }
+
+    #[test]
+    fn verify_emits() {
+        let metadata = Metadata::from_str(
+            r#"-- Description: Hello world, this is my description
+-- Version: 1.0.0
+-- Emits: subdomain, ipaddr
+-- Emits: url
+-- License: WTFPL
+
+"#,
+        )
+        .expect("parse");
+        assert_eq!(metadata.emits, vec!["subdomain", "ipaddr", "url"]);
+
+        let metadata = Metadata::from_str(
+            r#"-- Description: Hello world, this is my description
+-- Version: 1.0.0
+-- Emits: subdomains
+-- License: WTFPL
+
+"#,
+        );
+        assert!(metadata.is_err());
+    }
+
+    #[test]
+    fn verify_dns() {
+        let metadata = Metadata::from_str(
+            r#"-- Description: Hello world, this is my description
+-- Version: 1.0.0
+-- DNS: false
+-- License: WTFPL
+
+"#,
+        )
+        .expect("parse");
+        assert!(!metadata.dns);
+
+        let metadata = Metadata::from_str(
+            r#"-- Description: Hello world, this is my description
+-- Version: 1.0.0
+-- DNS: no
+-- License: WTFPL
+
+"#,
+        );
+        assert!(metadata.is_err());
+    }
+
     #[test]
     fn verify_require_license() {
         let metadata = Metadata::from_str(
@@ -384,4 +696,13 @@ mod tests {
         let x = Source::from_str("accounts:github.com").unwrap();
         assert_eq!(x, Source::Accounts(Some("github.com".into())));
     }
+
+    #[test]
+    fn verify_source_group() {
+        assert_eq!(source_group("subdomain"), Some("subdomains"));
+        assert_eq!(source_group("subdomains"), Some("subdomains"));
+        assert_eq!(source_group("breach"), Some("breaches"));
+        assert_eq!(source_group("keyring"), Some("keyring"));
+        assert_eq!(source_group("foo"), None);
+    }
 }
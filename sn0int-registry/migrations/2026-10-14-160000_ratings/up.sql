@@ -0,0 +1,27 @@
+ALTER TABLE modules ADD COLUMN emits TEXT[] NOT NULL DEFAULT '{}';
+
+CREATE TABLE ratings (
+    id SERIAL PRIMARY KEY,
+    module_id INTEGER NOT NULL REFERENCES modules(id) ON DELETE CASCADE,
+    author VARCHAR NOT NULL,
+    stars SMALLINT NOT NULL CHECK (stars BETWEEN 1 AND 5),
+    CONSTRAINT ratings_uniq UNIQUE (module_id, author)
+);
+
+CREATE OR REPLACE FUNCTION modules_vector_update() RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        new.search_vector = to_tsvector('pg_catalog.english',
+            NEW.name || ' ' || NEW.author || ' ' || COALESCE(NEW.source, '') || ' ' || array_to_string(NEW.emits, ' ') || ' ' || NEW.description
+        );
+    END IF;
+    IF TG_OP = 'UPDATE' THEN
+        IF NEW.description <> OLD.description OR NEW.source <> OLD.source OR NEW.emits <> OLD.emits THEN
+            new.search_vector = to_tsvector('pg_catalog.english',
+                NEW.name || ' ' || NEW.author || ' ' || COALESCE(NEW.source, '') || ' ' || array_to_string(NEW.emits, ' ') || ' ' || NEW.description
+            );
+        END IF;
+    END IF;
+    RETURN NEW;
+END
+$$ LANGUAGE 'plpgsql';
@@ -0,0 +1,2 @@
+ALTER TABLE releases DROP COLUMN signature;
+ALTER TABLE releases DROP COLUMN public_key;
@@ -0,0 +1,2 @@
+ALTER TABLE releases ADD COLUMN public_key VARCHAR;
+ALTER TABLE releases ADD COLUMN signature VARCHAR;
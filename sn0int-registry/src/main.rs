@@ -78,6 +78,7 @@ fn run() -> Result<()> {
             routes::api::info,
             routes::api::download,
             routes::api::publish,
+            routes::api::rate,
             routes::api::whoami,
         ])
         .mount("/api/v0/badges", routes![
@@ -1,13 +1,14 @@
 use crate::errors::*;
 use diesel::prelude::*;
 use diesel::pg::PgConnection;
-use diesel::sql_types::BigInt;
+use diesel::sql_types::{BigInt, Bool, Float, Nullable, Timestamp};
 use diesel_full_text_search::{plainto_tsquery, TsQueryExtensions};
 use crate::schema::*;
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
 use std::time::SystemTime;
 use sn0int_common::ModuleID;
+use sn0int_common::api::{ModuleSignature, SearchSort};
 use sn0int_common::metadata::Metadata;
 
 
@@ -57,6 +58,7 @@ type AllModuleColumns = (
     modules::featured,
     modules::source,
     modules::redirect,
+    modules::emits,
 );
 
 pub const ALL_MODULE_COLUMNS: AllModuleColumns = (
@@ -68,6 +70,7 @@ pub const ALL_MODULE_COLUMNS: AllModuleColumns = (
     modules::featured,
     modules::source,
     modules::redirect,
+    modules::emits,
 );
 
 #[derive(AsChangeset, Identifiable, Queryable, Serialize, PartialEq, Debug)]
@@ -81,6 +84,24 @@ pub struct Module {
     pub featured: bool,
     pub source: Option<String>,
     pub redirect: Option<String>,
+    pub emits: Vec<String>,
+}
+
+pub struct SearchResult {
+    pub module: Module,
+    pub downloads: i64,
+    pub rating: Option<f32>,
+    pub ratings: i64,
+    pub published: Option<SystemTime>,
+}
+
+/// Everything besides the query may be omitted
+#[derive(Debug, Default)]
+pub struct SearchFilter<'a> {
+    pub emits: Option<&'a str>,
+    pub source: Option<&'a str>,
+    pub author: Option<&'a str>,
+    pub sort: Option<SearchSort>,
 }
 
 impl Module {
@@ -118,6 +139,7 @@ impl Module {
                             .set(&BumpModule {
                                 description: Some(description),
                                 redirect: Some(None),
+                                emits: Some(&metadata.emits),
                             })
                             .returning(ALL_MODULE_COLUMNS)
                             .get_result(connection)
@@ -129,6 +151,7 @@ impl Module {
                 latest: None,
                 source,
                 redirect: None,
+                emits: &metadata.emits,
             }, connection),
         }
     }
@@ -154,11 +177,13 @@ impl Module {
         Ok(())
     }
 
-    pub fn add_version(&self, version: &str, code: &str, connection: &PgConnection) -> Result<()> {
+    pub fn add_version(&self, version: &str, code: &str, signature: Option<&ModuleSignature>, connection: &PgConnection) -> Result<()> {
         let _release = Release::create(&NewRelease {
             module_id: self.id,
             version,
             code,
+            public_key: signature.map(|x| x.public_key.as_str()),
+            signature: signature.map(|x| x.signature.as_str()),
         }, connection)?;
 
         diesel::update(modules::table.filter(modules::columns::id.eq(self.id)))
@@ -176,10 +201,17 @@ impl Module {
         Ok(())
     }
 
-    pub fn search(query: &str, connection: &PgConnection) -> Result<Vec<(Module, i64)>> {
+    /// Full-text search, the query may be empty if a filter is set
+    pub fn search(query: &str, filter: &SearchFilter, connection: &PgConnection) -> Result<Vec<SearchResult>> {
         let q = plainto_tsquery(query);
-
-        let x: Vec<(i32, String, String, String, Option<String>, bool, Option<String>, Option<String>, i64)> = modules::table.select((
+        let emits = filter.emits.map(|x| vec![x.to_string()]).unwrap_or_default();
+        let order = match filter.sort {
+            Some(SearchSort::Downloads) => "sum DESC",
+            Some(SearchSort::Recent) => "published DESC NULLS LAST",
+            None => "modules.featured DESC, sum DESC",
+        };
+
+        let x: Vec<(i32, String, String, String, Option<String>, bool, Option<String>, Option<String>, Vec<String>, i64, Option<f32>, i64, Option<SystemTime>)> = modules::table.select((
                 modules::id,
                 modules::author,
                 modules::name,
@@ -188,19 +220,24 @@ impl Module {
                 modules::featured,
                 modules::source,
                 modules::redirect,
+                modules::emits,
                 diesel::dsl::sql::<BigInt>("coalesce(sum(releases.downloads), 0) AS sum"),
+                diesel::dsl::sql::<Nullable<Float>>("(SELECT avg(ratings.stars)::real FROM ratings WHERE ratings.module_id = modules.id)"),
+                diesel::dsl::sql::<BigInt>("(SELECT count(*) FROM ratings WHERE ratings.module_id = modules.id)"),
+                diesel::dsl::sql::<Nullable<Timestamp>>("max(releases.published) AS published"),
             ))
             .left_join(releases::table)
             .group_by(modules::id)
-            .filter(q.matches(modules::search_vector))
-            .order((
-                modules::featured.desc(),
-                diesel::dsl::sql::<BigInt>("sum").desc(),
-            ))
+            .filter(q.matches(modules::search_vector).or(query.is_empty().into_sql::<Bool>()))
+            // an empty array is contained in every array
+            .filter(modules::emits.contains(emits))
+            .filter(modules::source.eq(filter.source).or(filter.source.is_none().into_sql::<Bool>()))
+            .filter(modules::author.eq(filter.author.unwrap_or_default()).or(filter.author.is_none().into_sql::<Bool>()))
+            .order(diesel::dsl::sql::<BigInt>(order))
             .load(connection)?;
 
-        Ok(x.into_iter().map(|(id, author, name, description, latest, featured, source, redirect, downloads)| (
-            Module {
+        Ok(x.into_iter().map(|(id, author, name, description, latest, featured, source, redirect, emits, downloads, rating, ratings, published)| SearchResult {
+            module: Module {
                 id,
                 author,
                 name,
@@ -209,9 +246,13 @@ impl Module {
                 featured,
                 source,
                 redirect,
+                emits,
             },
             downloads,
-        )).collect())
+            rating,
+            ratings,
+            published,
+        }).collect())
     }
 
     pub fn quickstart(connection: &PgConnection) -> Result<Vec<Module>> {
@@ -227,7 +268,7 @@ impl Module {
     }
 
     pub fn start_page(connection: &PgConnection) -> Result<HashMap<String, Vec<Module>>> {
-        let x: Vec<(i32, String, String, String, Option<String>, bool, Option<String>, Option<String>, i64)> = modules::table.select((
+        let x: Vec<(i32, String, String, String, Option<String>, bool, Option<String>, Option<String>, Vec<String>, i64)> = modules::table.select((
                 modules::id,
                 modules::author,
                 modules::name,
@@ -236,6 +277,7 @@ impl Module {
                 modules::featured,
                 modules::source,
                 modules::redirect,
+                modules::emits,
                 diesel::dsl::sql::<BigInt>("coalesce(sum(releases.downloads), 0) AS sum"),
             ))
             .left_join(releases::table)
@@ -249,7 +291,7 @@ impl Module {
 
         let mut categories: HashMap<_, Vec<_>> = HashMap::new();
 
-        for (id, author, name, description, latest, featured, source, redirect, _downloads) in x {
+        for (id, author, name, description, latest, featured, source, redirect, emits, _downloads) in x {
             let module = Module {
                 id,
                 author,
@@ -259,6 +301,7 @@ impl Module {
                 featured,
                 source,
                 redirect,
+                emits,
             };
 
             let source = match &module.source {
@@ -295,6 +338,7 @@ pub struct NewModule<'a> {
     latest: Option<&'a str>,
     source: Option<&'a str>,
     redirect: Option<&'a str>,
+    emits: &'a [String],
 }
 
 #[derive(AsChangeset)]
@@ -302,6 +346,7 @@ pub struct NewModule<'a> {
 pub struct BumpModule<'a> {
     description: Option<&'a str>,
     redirect: Option<Option<&'a str>>,
+    emits: Option<&'a [String]>,
 }
 
 #[derive(AsChangeset, Identifiable, Queryable, Associations, Serialize, PartialEq, Debug)]
@@ -314,6 +359,8 @@ pub struct Release {
     pub downloads: i32,
     pub code: String,
     pub published: SystemTime,
+    pub public_key: Option<String>,
+    pub signature: Option<String>,
 }
 
 impl Release {
@@ -394,4 +441,52 @@ pub struct NewRelease<'a> {
     module_id: i32,
     version: &'a str,
     code: &'a str,
+    public_key: Option<&'a str>,
+    signature: Option<&'a str>,
+}
+
+#[derive(Identifiable, Queryable, Associations, Serialize, PartialEq, Debug)]
+#[belongs_to(Module)]
+#[table_name="ratings"]
+pub struct Rating {
+    pub id: i32,
+    pub module_id: i32,
+    pub author: String,
+    pub stars: i16,
+}
+
+impl Rating {
+    /// Every user has one rating per module, rating again replaces it
+    pub fn rate(module: &Module, author: &str, stars: i16, connection: &PgConnection) -> Result<()> {
+        diesel::insert_into(ratings::table)
+            .values(&NewRating {
+                module_id: module.id,
+                author,
+                stars,
+            })
+            .on_conflict((ratings::module_id, ratings::author))
+            .do_update()
+            .set(ratings::stars.eq(stars))
+            .execute(connection)?;
+        Ok(())
+    }
+
+    pub fn summary(module: &Module, connection: &PgConnection) -> Result<(Option<f32>, i64)> {
+        ratings::table
+            .filter(ratings::module_id.eq(module.id))
+            .select((
+                diesel::dsl::sql::<Nullable<Float>>("avg(ratings.stars)::real"),
+                diesel::dsl::sql::<BigInt>("count(*)"),
+            ))
+            .first(connection)
+            .map_err(Error::from)
+    }
+}
+
+#[derive(Insertable)]
+#[table_name="ratings"]
+pub struct NewRating<'a> {
+    module_id: i32,
+    author: &'a str,
+    stars: i16,
 }
@@ -35,22 +35,49 @@ pub fn latest(connection: db::Connection) -> ApiResult<ApiResponse<LatestRespons
 #[derive(Debug, FromForm)]
 pub struct Search {
     q: String,
+    emits: Option<String>,
+    source: Option<String>,
+    author: Option<String>,
+    sort: Option<String>,
 }
 
 #[get("/search?<q..>")]
 pub fn search(q: Form<Search>, connection: db::Connection) -> ApiResult<ApiResponse<Vec<SearchResponse>>> {
-    info!("Searching: {:?}", q.q);
+    info!("Searching: {:?} (emits: {:?}, source: {:?}, author: {:?}, sort: {:?})", q.q, q.emits, q.source, q.author, q.sort);
 
-    let modules = Module::search(&q.q, &connection)?;
+    let sort = match q.sort.as_deref() {
+        Some("downloads") => Some(SearchSort::Downloads),
+        Some("recent") => Some(SearchSort::Recent),
+        Some(_) => bad_request!("Unknown sort order"),
+        None => None,
+    };
+
+    let filter = SearchFilter {
+        emits: q.emits.as_deref(),
+        source: q.source.as_deref(),
+        author: q.author.as_deref(),
+        sort,
+    };
+    let modules = Module::search(&q.q, &filter, &connection)?;
     let modules = modules.into_iter()
-        .flat_map(|(module, downloads)| {
+        .flat_map(|result| {
+            let module = result.module;
             Ok::<_, ()>(SearchResponse {
                 author: module.author,
                 name: module.name,
                 description: module.description,
                 latest: module.latest.ok_or(())?,
                 featured: module.featured,
-                downloads,
+                downloads: result.downloads,
+                rating: result.rating,
+                ratings: result.ratings,
+                emits: module.emits,
+                source: module.source,
+                published: result.published.map(|x| {
+                    x.duration_since(UNIX_EPOCH)
+                        .expect("Time went backwards")
+                        .as_secs()
+                }),
             })
         })
         .collect();
@@ -99,6 +126,13 @@ pub fn download(author: String, name: String, version: String, connection: db::C
         name,
         version,
         code: release.code,
+        signature: match (release.public_key, release.signature) {
+            (Some(public_key), Some(signature)) => Some(ModuleSignature {
+                public_key,
+                signature,
+            }),
+            _ => None,
+        },
     }))
 }
 
@@ -135,7 +169,7 @@ pub fn publish(name: String, upload: Json<PublishRequest>, session: AuthHeader,
                     bad_request!("Version number already in use")
                 }
             },
-            None => module.add_version(&version, &upload.code, &connection)
+            None => module.add_version(&version, &upload.code, upload.signature.as_ref(), &connection)
                 .private_context("Failed to add release")?,
         }
 
@@ -149,6 +183,33 @@ pub fn publish(name: String, upload: Json<PublishRequest>, session: AuthHeader,
     }))
 }
 
+#[post("/rate/<author>/<name>", format="application/json", data="<rate>")]
+pub fn rate(author: String, name: String, rate: Json<RateRequest>, session: AuthHeader, connection: db::Connection) -> ApiResult<ApiResponse<RateResponse>> {
+    let user = session.verify(&connection)
+        .bad_request()
+        .public_context("Invalid auth token")?;
+
+    if !(1..=5).contains(&rate.stars) {
+        bad_request!("Rating has to be between 1 and 5 stars")
+    }
+
+    let module = Module::find(&author, &name, &connection)
+        .not_found()
+        .public_context("Module does not exist")?;
+
+    info!("Rating {:?}/{:?} by {:?}: {}", author, name, user, rate.stars);
+    Rating::rate(&module, &user, i16::from(rate.stars), &connection)
+        .private_context("Failed to write rating")?;
+    let (rating, ratings) = Rating::summary(&module, &connection)?;
+
+    Ok(ApiResponse::Success(RateResponse {
+        author: module.author,
+        name: module.name,
+        rating,
+        ratings,
+    }))
+}
+
 #[get("/whoami")]
 pub fn whoami(session: AuthHeader, connection: db::Connection) -> ApiResult<ApiResponse<WhoamiResponse>> {
     let user = session.verify(&connection)
@@ -23,6 +23,19 @@ table! {
         featured -> Bool,
         source -> Nullable<Varchar>,
         redirect -> Nullable<Varchar>,
+        emits -> Array<Text>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_full_text_search::{TsVector as Tsvector};
+
+    ratings (id) {
+        id -> Int4,
+        module_id -> Int4,
+        author -> Varchar,
+        stars -> Int2,
     }
 }
 
@@ -37,13 +50,17 @@ table! {
         downloads -> Int4,
         code -> Text,
         published -> Timestamp,
+        public_key -> Nullable<Varchar>,
+        signature -> Nullable<Varchar>,
     }
 }
 
+joinable!(ratings -> modules (module_id));
 joinable!(releases -> modules (module_id));
 
 allow_tables_to_appear_in_same_query!(
     auth_tokens,
     modules,
+    ratings,
     releases,
 );
@@ -1,3 +1,4 @@
+use crate::errors::*;
 use blake2::Blake2bVar;
 use bytes::Bytes;
 use data_encoding::BASE64;
@@ -57,6 +58,9 @@ impl<'de> Deserialize<'de> for Blob {
 
 pub trait BlobState {
     fn register_blob(&self, blob: Blob) -> String;
+
+    /// Fail if the module isn't allowed to create blobs
+    fn check_blob(&self) -> Result<()>;
 }
 
 #[cfg(test)]
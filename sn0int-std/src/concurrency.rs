@@ -0,0 +1,58 @@
+use crate::errors::*;
+
+/// Access to the concurrency budget that is shared by all threads of a run
+pub trait ConcurrencyState {
+    /// Block until the run has a free slot for a network operation
+    fn acquire_slot(&self) -> Result<()>;
+
+    fn release_slot(&self);
+}
+
+/// A slot of the concurrency budget, it's returned when this is dropped
+pub struct Slot<'a> {
+    state: &'a dyn ConcurrencyState,
+}
+
+impl<'a> Slot<'a> {
+    pub fn acquire(state: &'a dyn ConcurrencyState) -> Result<Slot<'a>> {
+        state.acquire_slot()?;
+        Ok(Slot { state })
+    }
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        self.state.release_slot();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicIsize, Ordering};
+
+    #[derive(Default)]
+    struct Counter(AtomicIsize);
+
+    impl ConcurrencyState for Counter {
+        fn acquire_slot(&self) -> Result<()> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn release_slot(&self) {
+            self.0.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_slot_released_on_drop() {
+        let counter = Counter::default();
+        {
+            let _a = Slot::acquire(&counter).unwrap();
+            let _b = Slot::acquire(&counter).unwrap();
+            assert_eq!(counter.0.load(Ordering::SeqCst), 2);
+        }
+        assert_eq!(counter.0.load(Ordering::SeqCst), 0);
+    }
+}
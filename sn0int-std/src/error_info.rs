@@ -0,0 +1,319 @@
+use crate::engine::structs::LuaMap;
+use crate::errors::*;
+use crate::hlua::AnyLuaValue;
+use chrootable_https::hyper;
+use failure::Fail;
+use serde::{Deserialize, Serialize};
+use std::error::Error as StdError;
+use std::io;
+use std::net::IpAddr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorKind {
+    Dns,
+    Timeout,
+    Tls,
+    Connection,
+    HttpStatus,
+    Capability,
+    Offline,
+    Io,
+    Other,
+}
+
+impl ErrorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::Dns => "dns",
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::Tls => "tls",
+            ErrorKind::Connection => "connection",
+            ErrorKind::HttpStatus => "http-status",
+            ErrorKind::Capability => "capability",
+            ErrorKind::Offline => "offline",
+            ErrorKind::Io => "io",
+            ErrorKind::Other => "other",
+        }
+    }
+
+    /// Some errors are only available as strings by the time they reach us
+    fn from_msg(msg: &str) -> Option<ErrorKind> {
+        let msg = msg.to_lowercase();
+        if msg.contains("dns") || msg.contains("no record found") || msg.contains("nxdomain") {
+            Some(ErrorKind::Dns)
+        } else if msg.contains("timed out") || msg.contains("timeout") {
+            Some(ErrorKind::Timeout)
+        } else if msg.contains("certificate") || msg.contains("tls") || msg.contains("handshake") {
+            Some(ErrorKind::Tls)
+        } else if msg.contains("connection refused") || msg.contains("connection reset") {
+            Some(ErrorKind::Connection)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("http status error: {status}")]
+pub struct HttpStatusError {
+    pub status: u16,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Capability not granted to this module: {capability}")]
+pub struct CapabilityError {
+    pub capability: &'static str,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Connection to {host:?} is blocked by the egress policy of this module")]
+pub struct EgressError {
+    pub host: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Network access is disabled in offline mode")]
+pub struct OfflineError;
+
+#[derive(Debug, thiserror::Error)]
+#[error("couldn't connect: {errors:?}")]
+pub struct ConnectError {
+    pub errors: Vec<(IpAddr, Error)>,
+}
+
+/// An error that has been sent over ipc, only its classification survived
+#[derive(Debug, thiserror::Error)]
+#[error("{}", .0.message)]
+pub struct RemoteError(pub ErrorInfo);
+
+/// The error of a runtime function in a form that modules can match on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErrorInfo {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub status: Option<u16>,
+    pub os_error: Option<i32>,
+}
+
+impl ErrorInfo {
+    pub fn new(err: &Error) -> ErrorInfo {
+        let mut info = ErrorInfo {
+            kind: ErrorKind::Other,
+            message: err.to_string(),
+            status: None,
+            os_error: None,
+        };
+        info.kind = info.classify(err);
+        info
+    }
+
+    /// Turn the error back into one that is classified the same way
+    pub fn into_error(self) -> Error {
+        match self.kind {
+            // the exit status of a module depends on this type
+            ErrorKind::Offline => OfflineError.into(),
+            _ => RemoteError(self).into(),
+        }
+    }
+
+    fn classify(&mut self, err: &Error) -> ErrorKind {
+        for cause in err.iter_chain() {
+            if let Some(kind) = self.classify_fail(cause) {
+                return kind;
+            }
+        }
+
+        let msg = err
+            .iter_chain()
+            .map(|cause| cause.to_string())
+            .collect::<Vec<_>>()
+            .join(": ");
+        ErrorKind::from_msg(&msg).unwrap_or(ErrorKind::Other)
+    }
+
+    fn classify_fail(&mut self, err: &dyn Fail) -> Option<ErrorKind> {
+        if let Some(err) = err.downcast_ref::<HttpStatusError>() {
+            self.status = Some(err.status);
+            Some(ErrorKind::HttpStatus)
+        } else if err.downcast_ref::<CapabilityError>().is_some() {
+            Some(ErrorKind::Capability)
+        } else if err.downcast_ref::<OfflineError>().is_some() {
+            Some(ErrorKind::Offline)
+        } else if let Some(RemoteError(info)) = err.downcast_ref::<RemoteError>() {
+            self.status = info.status;
+            self.os_error = info.os_error;
+            Some(info.kind)
+        } else if let Some(err) = err.downcast_ref::<ConnectError>() {
+            // every address failed, the first one is representative
+            let (_, err) = err.errors.first()?;
+            Some(self.classify(err))
+        } else if let Some(err) = err.downcast_ref::<io::Error>() {
+            self.classify_std(err)
+        } else if let Some(err) = err.downcast_ref::<hyper::Error>() {
+            self.classify_std(err)
+        } else if let Some(err) = err.downcast_ref::<rustls::TLSError>() {
+            self.classify_std(err)
+        } else if let Some(err) = err.downcast_ref::<webpki::Error>() {
+            self.classify_std(err)
+        } else {
+            None
+        }
+    }
+
+    fn classify_std(&mut self, err: &(dyn StdError + 'static)) -> Option<ErrorKind> {
+        let mut next = Some(err);
+        while let Some(err) = next {
+            if err.is::<rustls::TLSError>() || err.is::<webpki::Error>() {
+                return Some(ErrorKind::Tls);
+            }
+
+            if let Some(err) = err.downcast_ref::<io::Error>() {
+                if let Some(inner) = err.get_ref() {
+                    if let Some(kind) = self.classify_std(inner) {
+                        return Some(kind);
+                    }
+                }
+
+                self.os_error = err.raw_os_error();
+                let kind = match err.kind() {
+                    io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => ErrorKind::Timeout,
+                    io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::NotConnected
+                    | io::ErrorKind::AddrNotAvailable
+                    | io::ErrorKind::BrokenPipe => ErrorKind::Connection,
+                    _ => ErrorKind::from_msg(&err.to_string()).unwrap_or(ErrorKind::Io),
+                };
+                return Some(kind);
+            }
+
+            next = err.source();
+        }
+        None
+    }
+
+    pub fn to_lua(&self) -> AnyLuaValue {
+        let mut map = LuaMap::new();
+        map.insert_str("kind", self.kind.as_str());
+        map.insert_str("message", self.message.as_str());
+        if let Some(status) = self.status {
+            map.insert_num("status", f64::from(status));
+        }
+        if let Some(os_error) = self.os_error {
+            map.insert_num("os_error", f64::from(os_error));
+        }
+        map.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kind(err: Error) -> ErrorKind {
+        ErrorInfo::new(&err).kind
+    }
+
+    #[test]
+    fn test_http_status() {
+        let info = ErrorInfo::new(&HttpStatusError { status: 500 }.into());
+        assert_eq!(info.kind, ErrorKind::HttpStatus);
+        assert_eq!(info.status, Some(500));
+        assert_eq!(info.message, "http status error: 500");
+    }
+
+    #[test]
+    fn test_capability() {
+        let info = ErrorInfo::new(&CapabilityError { capability: "dns" }.into());
+        assert_eq!(info.kind, ErrorKind::Capability);
+        assert_eq!(info.message, "Capability not granted to this module: dns");
+    }
+
+    #[test]
+    fn test_offline() {
+        let err = Error::from(OfflineError).context("Failed to connect");
+        let info = ErrorInfo::new(&err.into());
+        assert_eq!(info.kind, ErrorKind::Offline);
+    }
+
+    #[test]
+    fn test_io_timeout() {
+        let err = io::Error::new(io::ErrorKind::TimedOut, "connection timed out");
+        assert_eq!(kind(err.into()), ErrorKind::Timeout);
+        let err = io::Error::from(io::ErrorKind::WouldBlock);
+        assert_eq!(kind(err.into()), ErrorKind::Timeout);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_io_connection_refused() {
+        let info = ErrorInfo::new(&io::Error::from_raw_os_error(111).into());
+        assert_eq!(info.kind, ErrorKind::Connection);
+        assert_eq!(info.os_error, Some(111));
+    }
+
+    #[test]
+    fn test_io_other() {
+        let err = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert_eq!(kind(err.into()), ErrorKind::Io);
+    }
+
+    #[test]
+    fn test_tls() {
+        let err = rustls::TLSError::WebPKIError(webpki::Error::UnknownIssuer);
+        assert_eq!(kind(err.into()), ErrorKind::Tls);
+        // rustls reports handshake failures through io errors
+        let err = io::Error::new(
+            io::ErrorKind::InvalidData,
+            rustls::TLSError::WebPKIError(webpki::Error::CertExpired),
+        );
+        assert_eq!(kind(err.into()), ErrorKind::Tls);
+    }
+
+    #[test]
+    fn test_dns() {
+        assert_eq!(kind(format_err!("no dns records found")), ErrorKind::Dns);
+        // chrootable-https turns resolver errors into strings
+        let err = io::Error::other("no record found");
+        assert_eq!(kind(err.into()), ErrorKind::Dns);
+    }
+
+    #[test]
+    fn test_connect_error() {
+        let err = ConnectError {
+            errors: vec![(
+                "127.0.0.1".parse().unwrap(),
+                io::Error::from(io::ErrorKind::ConnectionRefused).into(),
+            )],
+        };
+        assert_eq!(kind(err.into()), ErrorKind::Connection);
+    }
+
+    #[test]
+    fn test_context_is_ignored() {
+        let err = Error::from(io::Error::from(io::ErrorKind::TimedOut));
+        let err = Error::from(err.context("Failed to connect"));
+        let info = ErrorInfo::new(&err);
+        assert_eq!(info.kind, ErrorKind::Timeout);
+        assert_eq!(info.message, "Failed to connect");
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_remote_error() {
+        let err = io::Error::from_raw_os_error(111).into();
+        let info = ErrorInfo::new(&err);
+        let remote = ErrorInfo::new(&info.clone().into_error());
+        assert_eq!(remote, info);
+
+        let info = ErrorInfo::new(&OfflineError.into());
+        assert!(info.into_error().downcast_ref::<OfflineError>().is_some());
+    }
+
+    #[test]
+    fn test_other() {
+        assert_eq!(kind(format_err!("something went wrong")), ErrorKind::Other);
+    }
+}
@@ -1,9 +1,11 @@
 use hlua_badtouch as hlua;
 
 pub mod blobs;
+pub mod concurrency;
 pub mod crt;
 pub mod crypto;
 pub mod engine;
+pub mod error_info;
 mod errors;
 pub mod geo;
 pub mod geoip;
@@ -1,8 +1,7 @@
 use crate::errors::*;
 use crate::hlua::AnyLuaValue;
 use crate::json::LuaJsonValue;
-use crate::sockets::{SocketOptions, Stream};
-use chrootable_https::DnsResolver;
+use crate::sockets::{Connector, SocketOptions, Stream};
 use mqtt::control::fixed_header::FixedHeaderError;
 use mqtt::control::ConnectReturnCode;
 use mqtt::encodable::{Decodable, Encodable};
@@ -21,7 +20,7 @@ use url::Url;
 const DEFAULT_PING_INTERVAL: u64 = 90;
 const DEFAULT_KEEP_ALIVE: u16 = 120;
 
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Default, Clone, Deserialize)]
 pub struct MqttOptions {
     pub username: Option<String>,
     pub password: Option<String>,
@@ -99,8 +98,8 @@ impl MqttClient {
         }
     }
 
-    pub fn connect<R: DnsResolver>(
-        resolver: &R,
+    pub fn connect(
+        connector: &dyn Connector,
         url: Url,
         options: &MqttOptions,
     ) -> Result<MqttClient> {
@@ -124,7 +123,7 @@ impl MqttClient {
         let read_timeout = options.read_timeout.unwrap_or(DEFAULT_PING_INTERVAL);
 
         let stream = Stream::connect_stream(
-            resolver,
+            connector,
             host,
             port,
             &SocketOptions {
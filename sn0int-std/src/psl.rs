@@ -4,6 +4,7 @@ use chrootable_https::Client;
 use publicsuffix::{List, Psl as _};
 use std::fs::{self, File};
 use std::io::Read;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
@@ -22,7 +23,11 @@ pub enum PslReader {
 }
 
 impl PslReader {
-    pub fn open_or_download<F>(cache_dir: &Path, indicator: F) -> Result<PslReader>
+    pub fn open_or_download<F>(
+        cache_dir: &Path,
+        proxy: Option<SocketAddr>,
+        indicator: F,
+    ) -> Result<PslReader>
     where
         F: Fn(Box<dyn Fn() -> Result<PslReader>>) -> Result<PslReader>,
     {
@@ -30,7 +35,7 @@ impl PslReader {
         let reader = match Self::open_from(&path) {
             Ok(r) => r,
             Err(_) => indicator(Box::new(move || {
-                PslReader::download(&path, publicsuffix::LIST_URL)?;
+                PslReader::download(&path, publicsuffix::LIST_URL, proxy)?;
                 Self::open_from(&path)
             }))?,
         };
@@ -59,8 +64,11 @@ impl PslReader {
         Ok(path)
     }
 
-    pub fn download(path: &Path, url: &str) -> Result<()> {
-        let client = Client::with_system_resolver_v4()?;
+    pub fn download(path: &Path, url: &str, proxy: Option<SocketAddr>) -> Result<()> {
+        let client = match proxy {
+            Some(proxy) => Client::with_socks5(proxy),
+            None => Client::with_system_resolver_v4()?,
+        };
         let resp = client
             .get(url)
             .wait_for_response()
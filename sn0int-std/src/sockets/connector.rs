@@ -0,0 +1,332 @@
+use crate::error_info::ConnectError;
+use crate::errors::*;
+use chrootable_https::dns::{DnsResolver, RecordType, Resolver};
+use chrootable_https::socks5::{self, ProxyDest};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+/// A tcp connection that has been requested by a module
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConnectRequest {
+    pub host: String,
+    pub port: u16,
+    pub proxy: Option<SocketAddr>,
+    pub connect_timeout: Option<Duration>,
+}
+
+impl ConnectRequest {
+    pub fn new(host: &str, port: u16) -> ConnectRequest {
+        ConnectRequest {
+            host: host.to_string(),
+            port,
+            proxy: None,
+            connect_timeout: None,
+        }
+    }
+
+    /// The host without the brackets of an ipv6 literal
+    pub fn host(&self) -> &str {
+        self.host.trim_start_matches('[').trim_end_matches(']')
+    }
+}
+
+/// An established connection, the address is unknown if it has been opened
+/// through a proxy
+#[derive(Debug)]
+pub struct Connection {
+    pub stream: TcpStream,
+    pub addr: Option<SocketAddr>,
+}
+
+/// A udp socket that can only talk to the address it has been connected to
+#[derive(Debug)]
+pub struct Datagram {
+    pub socket: UdpSocket,
+    pub addr: SocketAddr,
+}
+
+pub trait Connector {
+    /// Open a tcp connection, this fails if the connection isn't allowed
+    fn connect(&self, req: &ConnectRequest) -> Result<Connection>;
+
+    /// Open a udp socket for quic, this fails if the connection isn't allowed
+    fn connect_udp(&self, req: &ConnectRequest) -> Result<Datagram>;
+}
+
+/// Connect without any restrictions
+impl Connector for Resolver {
+    fn connect(&self, req: &ConnectRequest) -> Result<Connection> {
+        if let Some(proxy) = req.proxy {
+            let stream = connect_socks5(proxy, req.host(), req.port)?;
+            return Ok(Connection { stream, addr: None });
+        }
+
+        let addrs = resolve(self, req.host())?;
+        connect_addrs(&addrs, req.port, req.connect_timeout)
+    }
+
+    fn connect_udp(&self, req: &ConnectRequest) -> Result<Datagram> {
+        if req.proxy.is_some() {
+            bail!("udp can't be sent through a socks5 proxy");
+        }
+
+        let addrs = resolve(self, req.host())?;
+        connect_udp_addrs(&addrs, req.port)
+    }
+}
+
+/// Resolve both the ipv4 and ipv6 addresses of a name, ip addresses are
+/// returned as-is
+pub fn resolve<R: DnsResolver>(resolver: &R, host: &str) -> Result<Vec<IpAddr>> {
+    if let Ok(addr) = host.parse::<IpAddr>() {
+        return Ok(vec![addr]);
+    }
+
+    let lookup = |record| {
+        resolver
+            .resolve(host, record)
+            .wait_for_response()
+            .and_then(|reply| reply.success())
+    };
+
+    let addrs = match (lookup(RecordType::A), lookup(RecordType::AAAA)) {
+        (Err(err), Err(_)) => return Err(err),
+        (v4, v6) => v4
+            .unwrap_or_default()
+            .into_iter()
+            .chain(v6.unwrap_or_default())
+            .collect::<Vec<_>>(),
+    };
+
+    if addrs.is_empty() {
+        bail!("no dns records found");
+    }
+    Ok(addrs)
+}
+
+/// Try the addresses in order until a connection succeeds
+pub fn connect_addrs(
+    addrs: &[IpAddr],
+    port: u16,
+    timeout: Option<Duration>,
+) -> Result<Connection> {
+    let mut errors = Vec::new();
+
+    for addr in addrs {
+        let addr = SocketAddr::new(*addr, port);
+        debug!("connecting to {}", addr);
+
+        let stream = match timeout {
+            Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+            None => TcpStream::connect(addr),
+        };
+
+        match stream {
+            Ok(stream) => {
+                debug!("successfully connected to {:?}", addr);
+                return Ok(Connection {
+                    stream,
+                    addr: Some(addr),
+                });
+            }
+            Err(err) => errors.push((addr.ip(), err.into())),
+        }
+    }
+
+    if errors.is_empty() {
+        bail!("no dns records found");
+    } else {
+        Err(ConnectError { errors }.into())
+    }
+}
+
+/// Connect a udp socket to the first address, there's no handshake that could
+/// tell us if another address would work better
+pub fn connect_udp_addrs(addrs: &[IpAddr], port: u16) -> Result<Datagram> {
+    let addr = addrs
+        .first()
+        .map(|addr| SocketAddr::new(*addr, port))
+        .ok_or_else(|| format_err!("no dns records found"))?;
+
+    let bind = match addr {
+        SocketAddr::V4(_) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+        SocketAddr::V6(_) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0),
+    };
+    debug!("connecting udp socket to {}", addr);
+    let socket = UdpSocket::bind(bind)?;
+    socket.connect(addr)?;
+    Ok(Datagram { socket, addr })
+}
+
+#[cfg(unix)]
+fn unwrap_socket(socket: tokio::net::TcpStream) -> Result<TcpStream> {
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::io::FromRawFd;
+    let socket2 = unsafe { TcpStream::from_raw_fd(socket.as_raw_fd()) };
+    let socket = socket2.try_clone()?;
+    std::mem::forget(socket2);
+    // tokio switched the socket to non-blocking
+    socket.set_nonblocking(false)?;
+    Ok(socket)
+}
+
+#[cfg(windows)]
+fn unwrap_socket(socket: tokio::net::TcpStream) -> Result<TcpStream> {
+    bail!("Unwrapping tokio sockets into std sockets isn't supported on windows")
+}
+
+/// Connect through a socks5 proxy, the name is resolved by the proxy
+pub fn connect_socks5(proxy: SocketAddr, host: &str, port: u16) -> Result<TcpStream> {
+    debug!(
+        "connecting to {:?}:{:?} with socks5 on {:?}",
+        host, port, proxy
+    );
+
+    let addr = match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ipaddr)) => ProxyDest::Ipv4Addr(ipaddr),
+        Ok(IpAddr::V6(ipaddr)) => ProxyDest::Ipv6Addr(ipaddr),
+        _ => ProxyDest::Domain(host.to_string()),
+    };
+
+    let fut = socks5::connect(&proxy, addr, port);
+
+    let mut rt = Runtime::new()?;
+    let socket = rt.block_on(fut)?;
+
+    unwrap_socket(socket)
+}
+
+/// The RESOLVE command of tor's socks5 extensions
+const SOCKS5_RESOLVE: u8 = 0xf0;
+
+/// Resolve a name with tor's socks5 extension, `None` if it doesn't exist
+pub fn resolve_socks5(proxy: SocketAddr, name: &str, timeout: Option<Duration>) -> Result<Option<IpAddr>> {
+    debug!("resolving {:?} with socks5 on {:?}", name, proxy);
+    if name.len() > 255 {
+        bail!("name is too long for socks5: {:?}", name);
+    }
+
+    let mut stream = match timeout {
+        Some(timeout) => TcpStream::connect_timeout(&proxy, timeout)?,
+        None => TcpStream::connect(proxy)?,
+    };
+    stream.set_read_timeout(timeout)?;
+    stream.set_write_timeout(timeout)?;
+
+    // version, one auth method, unauthenticated
+    stream.write_all(&[0x05, 0x01, 0x00])?;
+    let mut buf = [0; 2];
+    stream.read_exact(&mut buf)?;
+    if buf != [0x05, 0x00] {
+        bail!("socks5 authentication failed");
+    }
+
+    // version, command, reserved, domain, the port is ignored
+    let mut req = vec![0x05, SOCKS5_RESOLVE, 0x00, 0x03, name.len() as u8];
+    req.extend(name.as_bytes());
+    req.extend(&[0x00, 0x00]);
+    stream.write_all(&req)?;
+
+    let mut buf = [0; 4];
+    stream.read_exact(&mut buf)?;
+    if buf[0] != 0x05 {
+        bail!("wrong socks5 version: {}", buf[0]);
+    }
+    match buf[1] {
+        0x00 => (),
+        // tor reports names that don't resolve as unreachable
+        0x04 => return Ok(None),
+        status => bail!("socks5 resolve failed with status {}", status),
+    }
+
+    let addr = match buf[3] {
+        0x01 => {
+            let mut ip = [0; 4];
+            stream.read_exact(&mut ip)?;
+            IpAddr::from(ip)
+        }
+        0x04 => {
+            let mut ip = [0; 16];
+            stream.read_exact(&mut ip)?;
+            IpAddr::from(ip)
+        }
+        atyp => bail!("unexpected socks5 address type: {}", atyp),
+    };
+    let mut port = [0; 2];
+    stream.read_exact(&mut port)?;
+
+    Ok(Some(addr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn fake_tor(reply: &'static [u8]) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut sock, _) = listener.accept().unwrap();
+            let mut hello = [0; 3];
+            sock.read_exact(&mut hello).unwrap();
+            sock.write_all(&[0x05, 0x00]).unwrap();
+            let mut req = [0; 5];
+            sock.read_exact(&mut req).unwrap();
+            assert_eq!(&req[..4], &[0x05, SOCKS5_RESOLVE, 0x00, 0x03]);
+            let mut name = vec![0; req[4] as usize + 2];
+            sock.read_exact(&mut name).unwrap();
+            assert_eq!(&name[..name.len() - 2], b"example.com");
+            sock.write_all(reply).unwrap();
+        });
+        addr
+    }
+
+    #[test]
+    fn test_resolve_socks5() {
+        let proxy = fake_tor(&[0x05, 0x00, 0x00, 0x01, 93, 184, 216, 34, 0x00, 0x00]);
+        let addr = resolve_socks5(proxy, "example.com", None).unwrap();
+        assert_eq!(addr, Some("93.184.216.34".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_resolve_socks5_nxdomain() {
+        let proxy = fake_tor(&[0x05, 0x04, 0x00, 0x01, 0, 0, 0, 0, 0x00, 0x00]);
+        let addr = resolve_socks5(proxy, "example.com", None).unwrap();
+        assert_eq!(addr, None);
+    }
+
+    #[test]
+    fn test_resolve_ipaddr() {
+        let resolver = Resolver::empty();
+        let addrs = resolve(&resolver, "::1").unwrap();
+        assert_eq!(addrs, vec!["::1".parse::<IpAddr>().unwrap()]);
+    }
+
+    #[test]
+    fn test_connect_addrs_fallback() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // nothing listens on the first address
+        let addrs = vec!["127.0.0.2".parse().unwrap(), "127.0.0.1".parse().unwrap()];
+        let conn = connect_addrs(&addrs, port, Some(Duration::from_secs(3))).unwrap();
+        assert_eq!(conn.addr, Some(SocketAddr::new(addrs[1], port)));
+    }
+
+    #[test]
+    fn test_connect_udp_addrs() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        let datagram = connect_udp_addrs(&["127.0.0.1".parse().unwrap()], port).unwrap();
+        assert_eq!(datagram.addr, server.local_addr().unwrap());
+
+        datagram.socket.send(b"ohai").unwrap();
+        let mut buf = [0; 4];
+        let (_, from) = server.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf, b"ohai");
+        assert_eq!(from, datagram.socket.local_addr().unwrap());
+    }
+}
@@ -0,0 +1,581 @@
+use crate::errors::*;
+use data_encoding::HEXLOWER;
+use md5::Md5;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::io::{self, Read, Write};
+
+/// Handshake bytes that are kept to compute the fingerprints, a hello message
+/// fits into a single tls record in practice
+const MAX_RECORDED: usize = 32 * 1024;
+
+const HANDSHAKE: u8 = 22;
+const CLIENT_HELLO: u8 = 1;
+const SERVER_HELLO: u8 = 2;
+
+const EXT_SERVER_NAME: u16 = 0x0000;
+const EXT_SUPPORTED_GROUPS: u16 = 0x000a;
+const EXT_EC_POINT_FORMATS: u16 = 0x000b;
+const EXT_SIGNATURE_ALGORITHMS: u16 = 0x000d;
+const EXT_ALPN: u16 = 0x0010;
+const EXT_SUPPORTED_VERSIONS: u16 = 0x002b;
+
+/// Wraps the tcp stream during the handshake and keeps a copy of what's sent
+/// and received
+pub struct Recorder<'a, S> {
+    stream: &'a mut S,
+    pub sent: Vec<u8>,
+    pub received: Vec<u8>,
+}
+
+impl<'a, S> Recorder<'a, S> {
+    pub fn new(stream: &'a mut S) -> Recorder<'a, S> {
+        Recorder {
+            stream,
+            sent: Vec::new(),
+            received: Vec::new(),
+        }
+    }
+}
+
+fn record(buf: &mut Vec<u8>, data: &[u8]) {
+    let n = MAX_RECORDED.saturating_sub(buf.len()).min(data.len());
+    buf.extend_from_slice(&data[..n]);
+}
+
+impl<'a, S: Read> Read for Recorder<'a, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stream.read(buf)?;
+        record(&mut self.received, &buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<'a, S: Write> Write for Recorder<'a, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.stream.write(buf)?;
+        record(&mut self.sent, &buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// The ja3 and ja4 fingerprints of a handshake, the client side describes
+/// our own tls stack, the server side describes the host we connected to
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Fingerprints {
+    pub ja3: Option<String>,
+    pub ja3s: Option<String>,
+    pub ja4: Option<String>,
+    pub ja4s: Option<String>,
+}
+
+impl Fingerprints {
+    pub fn new(sent: &[u8], received: &[u8]) -> Fingerprints {
+        let mut fp = Fingerprints::default();
+
+        match handshake_message(sent, CLIENT_HELLO).and_then(|x| ClientHello::parse(&x)) {
+            Ok(hello) => {
+                fp.ja3 = Some(md5_hex(&hello.ja3_string()));
+                fp.ja4 = Some(hello.ja4());
+            }
+            Err(err) => debug!("Failed to fingerprint client hello: {}", err),
+        }
+
+        match handshake_message(received, SERVER_HELLO).and_then(|x| ServerHello::parse(&x)) {
+            Ok(hello) => {
+                fp.ja3s = Some(md5_hex(&hello.ja3s_string()));
+                fp.ja4s = Some(hello.ja4s());
+            }
+            Err(err) => debug!("Failed to fingerprint server hello: {}", err),
+        }
+
+        fp
+    }
+}
+
+/// Reassemble the first handshake message from the plaintext records
+fn handshake_message(mut data: &[u8], expected: u8) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+
+    while data.len() >= 5 && data[0] == HANDSHAKE {
+        let len = u16::from_be_bytes([data[3], data[4]]) as usize;
+        let fragment = data
+            .get(5..5 + len)
+            .ok_or_else(|| format_err!("Truncated tls record"))?;
+        buf.extend_from_slice(fragment);
+        data = &data[5 + len..];
+
+        if buf.len() >= 4 {
+            let msg_len = u32::from_be_bytes([0, buf[1], buf[2], buf[3]]) as usize;
+            if buf.len() >= 4 + msg_len {
+                if buf[0] != expected {
+                    bail!("Unexpected handshake message: {}", buf[0]);
+                }
+                buf.truncate(4 + msg_len);
+                return Ok(buf.split_off(4));
+            }
+        }
+    }
+
+    bail!("No complete handshake message")
+}
+
+fn is_grease(x: u16) -> bool {
+    x & 0x0f0f == 0x0a0a && x >> 8 == x & 0xff
+}
+
+fn md5_hex(s: &str) -> String {
+    HEXLOWER.encode(&Md5::digest(s.as_bytes()))
+}
+
+fn sha256_12(items: &[String]) -> String {
+    if items.is_empty() {
+        return String::from("000000000000");
+    }
+    sha256_12_str(&items.join(","))
+}
+
+fn sha256_12_str(s: &str) -> String {
+    let mut hash = HEXLOWER.encode(&Sha256::digest(s.as_bytes()));
+    hash.truncate(12);
+    hash
+}
+
+fn join_dec(items: &[u16], sep: &str) -> String {
+    items
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>()
+        .join(sep)
+}
+
+fn hex4(items: &[u16]) -> Vec<String> {
+    items.iter().map(|x| format!("{:04x}", x)).collect()
+}
+
+fn ja4_version(version: u16) -> &'static str {
+    match version {
+        0x0304 => "13",
+        0x0303 => "12",
+        0x0302 => "11",
+        0x0301 => "10",
+        0x0300 => "s3",
+        0x0200 => "s2",
+        0xfeff => "d1",
+        0xfefd => "d2",
+        0xfefc => "d3",
+        _ => "00",
+    }
+}
+
+/// The first and last character of the alpn value, or their hex digits if
+/// they aren't alphanumeric
+fn ja4_alpn(alpn: Option<&[u8]>) -> String {
+    let (first, last) = match alpn {
+        Some(x) if !x.is_empty() => (x[0], x[x.len() - 1]),
+        _ => return String::from("00"),
+    };
+
+    if first.is_ascii_alphanumeric() && last.is_ascii_alphanumeric() {
+        format!("{}{}", first as char, last as char)
+    } else {
+        let first = format!("{:02x}", first);
+        let last = format!("{:02x}", last);
+        format!("{}{}", &first[..1], &last[1..])
+    }
+}
+
+struct Parser<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Parser<'a> {
+    fn new(data: &'a [u8]) -> Parser<'a> {
+        Parser { data }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.data.len() < n {
+            bail!("Unexpected end of hello message");
+        }
+        let (x, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(x)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let x = self.take(2)?;
+        Ok(u16::from_be_bytes([x[0], x[1]]))
+    }
+
+    fn vec8(&mut self) -> Result<&'a [u8]> {
+        let n = self.u8()? as usize;
+        self.take(n)
+    }
+
+    fn vec16(&mut self) -> Result<&'a [u8]> {
+        let n = self.u16()? as usize;
+        self.take(n)
+    }
+
+    fn u16_list(data: &[u8]) -> Vec<u16> {
+        data.chunks_exact(2)
+            .map(|x| u16::from_be_bytes([x[0], x[1]]))
+            .collect()
+    }
+
+    fn extensions(&mut self) -> Result<Vec<(u16, &'a [u8])>> {
+        let mut exts = Vec::new();
+        if self.data.is_empty() {
+            return Ok(exts);
+        }
+
+        let mut p = Parser::new(self.vec16()?);
+        while !p.data.is_empty() {
+            let ext = p.u16()?;
+            let data = p.vec16()?;
+            exts.push((ext, data));
+        }
+        Ok(exts)
+    }
+}
+
+fn first_alpn(data: &[u8]) -> Result<&[u8]> {
+    let mut p = Parser::new(data);
+    let mut list = Parser::new(p.vec16()?);
+    list.vec8()
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct ClientHello {
+    version: u16,
+    ciphers: Vec<u16>,
+    extensions: Vec<u16>,
+    groups: Vec<u16>,
+    point_formats: Vec<u8>,
+    signature_algorithms: Vec<u16>,
+    supported_versions: Vec<u16>,
+    sni: bool,
+    alpn: Option<Vec<u8>>,
+}
+
+impl ClientHello {
+    fn parse(data: &[u8]) -> Result<ClientHello> {
+        let mut p = Parser::new(data);
+        let mut hello = ClientHello {
+            version: p.u16()?,
+            ..Default::default()
+        };
+        p.take(32)?;
+        p.vec8()?;
+        hello.ciphers = Parser::u16_list(p.vec16()?)
+            .into_iter()
+            .filter(|x| !is_grease(*x))
+            .collect();
+        p.vec8()?;
+
+        for (ext, data) in p.extensions()? {
+            if is_grease(ext) {
+                continue;
+            }
+            hello.extensions.push(ext);
+
+            let mut p = Parser::new(data);
+            match ext {
+                EXT_SERVER_NAME => hello.sni = true,
+                EXT_SUPPORTED_GROUPS => hello.groups = Parser::u16_list(p.vec16()?),
+                EXT_EC_POINT_FORMATS => hello.point_formats = p.vec8()?.to_vec(),
+                EXT_SIGNATURE_ALGORITHMS => {
+                    hello.signature_algorithms = Parser::u16_list(p.vec16()?)
+                }
+                EXT_SUPPORTED_VERSIONS => hello.supported_versions = Parser::u16_list(p.vec8()?),
+                EXT_ALPN => hello.alpn = Some(first_alpn(data)?.to_vec()),
+                _ => (),
+            }
+        }
+
+        hello.groups.retain(|x| !is_grease(*x));
+        hello.signature_algorithms.retain(|x| !is_grease(*x));
+        hello.supported_versions.retain(|x| !is_grease(*x));
+
+        Ok(hello)
+    }
+
+    fn ja3_string(&self) -> String {
+        let point_formats = self
+            .point_formats
+            .iter()
+            .map(|x| *x as u16)
+            .collect::<Vec<_>>();
+        format!(
+            "{},{},{},{},{}",
+            self.version,
+            join_dec(&self.ciphers, "-"),
+            join_dec(&self.extensions, "-"),
+            join_dec(&self.groups, "-"),
+            join_dec(&point_formats, "-"),
+        )
+    }
+
+    fn ja4(&self) -> String {
+        let version = self
+            .supported_versions
+            .iter()
+            .max()
+            .copied()
+            .unwrap_or(self.version);
+
+        let a = format!(
+            "t{}{}{:02}{:02}{}",
+            ja4_version(version),
+            if self.sni { 'd' } else { 'i' },
+            self.ciphers.len().min(99),
+            self.extensions.len().min(99),
+            ja4_alpn(self.alpn.as_deref()),
+        );
+
+        let mut ciphers = hex4(&self.ciphers);
+        ciphers.sort();
+
+        let mut exts = self
+            .extensions
+            .iter()
+            .filter(|x| **x != EXT_SERVER_NAME && **x != EXT_ALPN)
+            .copied()
+            .collect::<Vec<_>>();
+        exts.sort_unstable();
+        let exts = hex4(&exts);
+
+        let c = if exts.is_empty() {
+            String::from("000000000000")
+        } else if self.signature_algorithms.is_empty() {
+            sha256_12(&exts)
+        } else {
+            let sigs = hex4(&self.signature_algorithms).join(",");
+            sha256_12_str(&format!("{}_{}", exts.join(","), sigs))
+        };
+
+        format!("{}_{}_{}", a, sha256_12(&ciphers), c)
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct ServerHello {
+    version: u16,
+    cipher: u16,
+    extensions: Vec<u16>,
+    supported_version: Option<u16>,
+    alpn: Option<Vec<u8>>,
+}
+
+impl ServerHello {
+    fn parse(data: &[u8]) -> Result<ServerHello> {
+        let mut p = Parser::new(data);
+        let mut hello = ServerHello {
+            version: p.u16()?,
+            ..Default::default()
+        };
+        p.take(32)?;
+        p.vec8()?;
+        hello.cipher = p.u16()?;
+        p.u8()?;
+
+        for (ext, data) in p.extensions()? {
+            hello.extensions.push(ext);
+
+            let mut p = Parser::new(data);
+            match ext {
+                EXT_SUPPORTED_VERSIONS => hello.supported_version = Some(p.u16()?),
+                EXT_ALPN => hello.alpn = Some(first_alpn(data)?.to_vec()),
+                _ => (),
+            }
+        }
+
+        Ok(hello)
+    }
+
+    fn ja3s_string(&self) -> String {
+        format!(
+            "{},{},{}",
+            self.version,
+            self.cipher,
+            join_dec(&self.extensions, "-"),
+        )
+    }
+
+    fn ja4s(&self) -> String {
+        let version = self.supported_version.unwrap_or(self.version);
+        format!(
+            "t{}{:02}{}_{:04x}_{}",
+            ja4_version(version),
+            self.extensions.len().min(99),
+            ja4_alpn(self.alpn.as_deref()),
+            self.cipher,
+            sha256_12(&hex4(&self.extensions)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ext(buf: &mut Vec<u8>, ext: u16, data: &[u8]) {
+        buf.extend(ext.to_be_bytes());
+        buf.extend((data.len() as u16).to_be_bytes());
+        buf.extend(data);
+    }
+
+    fn records(msg_type: u8, body: &[u8]) -> Vec<u8> {
+        let mut msg = vec![msg_type];
+        msg.extend(&(body.len() as u32).to_be_bytes()[1..]);
+        msg.extend(body);
+
+        // split the message to make sure it's reassembled
+        let mut buf = Vec::new();
+        for chunk in msg.chunks(64) {
+            buf.extend([HANDSHAKE, 0x03, 0x01]);
+            buf.extend((chunk.len() as u16).to_be_bytes());
+            buf.extend(chunk);
+        }
+        buf
+    }
+
+    fn client_hello() -> Vec<u8> {
+        let mut exts = Vec::new();
+        ext(&mut exts, 0x3a3a, &[]);
+        ext(
+            &mut exts,
+            EXT_SERVER_NAME,
+            b"\x00\x0e\x00\x00\x0bexample.com",
+        );
+        ext(
+            &mut exts,
+            EXT_SUPPORTED_GROUPS,
+            b"\x00\x06\x2a\x2a\x00\x1d\x00\x17",
+        );
+        ext(&mut exts, EXT_EC_POINT_FORMATS, b"\x01\x00");
+        ext(
+            &mut exts,
+            EXT_SIGNATURE_ALGORITHMS,
+            b"\x00\x04\x04\x03\x08\x04",
+        );
+        ext(&mut exts, EXT_ALPN, b"\x00\x0c\x02h2\x08http/1.1");
+        ext(&mut exts, EXT_SUPPORTED_VERSIONS, b"\x04\x03\x04\x03\x03");
+
+        let mut body = vec![0x03, 0x03];
+        body.extend([0u8; 32]);
+        body.push(0);
+        body.extend(b"\x00\x06\x0a\x0a\x13\x01\xc0\x2f");
+        body.extend(b"\x01\x00");
+        body.extend((exts.len() as u16).to_be_bytes());
+        body.extend(exts);
+        records(CLIENT_HELLO, &body)
+    }
+
+    fn server_hello() -> Vec<u8> {
+        let mut exts = Vec::new();
+        ext(&mut exts, EXT_SUPPORTED_VERSIONS, b"\x03\x04");
+        ext(&mut exts, 0x0033, &[0u8; 36]);
+
+        let mut body = vec![0x03, 0x03];
+        body.extend([0u8; 32]);
+        body.push(0);
+        body.extend(b"\x13\x01\x00");
+        body.extend((exts.len() as u16).to_be_bytes());
+        body.extend(exts);
+
+        let mut buf = records(SERVER_HELLO, &body);
+        // the encrypted records afterwards are ignored
+        buf.extend(b"\x17\x03\x03\x00\x02\xff\xff");
+        buf
+    }
+
+    #[test]
+    fn test_grease() {
+        assert!(is_grease(0x0a0a));
+        assert!(is_grease(0xfafa));
+        assert!(!is_grease(0x0a1a));
+        assert!(!is_grease(0x1301));
+    }
+
+    #[test]
+    fn test_client_hello() {
+        let msg = handshake_message(&client_hello(), CLIENT_HELLO).unwrap();
+        let hello = ClientHello::parse(&msg).unwrap();
+        assert_eq!(
+            hello,
+            ClientHello {
+                version: 0x0303,
+                ciphers: vec![0x1301, 0xc02f],
+                extensions: vec![0, 10, 11, 13, 16, 43],
+                groups: vec![29, 23],
+                point_formats: vec![0],
+                signature_algorithms: vec![0x0403, 0x0804],
+                supported_versions: vec![0x0304, 0x0303],
+                sni: true,
+                alpn: Some(b"h2".to_vec()),
+            }
+        );
+        assert_eq!(
+            hello.ja3_string(),
+            "771,4865-49199,0-10-11-13-16-43,29-23,0"
+        );
+
+        let ja4 = hello.ja4();
+        assert!(ja4.starts_with("t13d0206h2_"), "{}", ja4);
+        assert_eq!(
+            ja4,
+            format!(
+                "t13d0206h2_{}_{}",
+                sha256_12_str("1301,c02f"),
+                sha256_12_str("000a,000b,000d,002b_0403,0804"),
+            )
+        );
+    }
+
+    #[test]
+    fn test_server_hello() {
+        let msg = handshake_message(&server_hello(), SERVER_HELLO).unwrap();
+        let hello = ServerHello::parse(&msg).unwrap();
+        assert_eq!(hello.ja3s_string(), "771,4865,43-51");
+        assert_eq!(
+            hello.ja4s(),
+            format!("t130200_1301_{}", sha256_12_str("002b,0033"))
+        );
+    }
+
+    #[test]
+    fn test_fingerprints() {
+        let fp = Fingerprints::new(&client_hello(), &server_hello());
+        assert_eq!(
+            fp.ja3.as_deref(),
+            Some(md5_hex("771,4865-49199,0-10-11-13-16-43,29-23,0").as_str())
+        );
+        assert_eq!(fp.ja3s.as_deref(), Some(md5_hex("771,4865,43-51").as_str()));
+        assert!(fp.ja4.is_some());
+        assert!(fp.ja4s.is_some());
+    }
+
+    #[test]
+    fn test_truncated() {
+        let fp = Fingerprints::new(&client_hello()[..50], b"\x15\x03\x03\x00\x02\x02\x28");
+        assert_eq!(fp, Fingerprints::default());
+    }
+
+    #[test]
+    fn test_alpn() {
+        assert_eq!(ja4_alpn(Some(b"h2")), "h2");
+        assert_eq!(ja4_alpn(Some(b"http/1.1")), "h1");
+        assert_eq!(ja4_alpn(Some(b"\xab\xcd")), "ad");
+        assert_eq!(ja4_alpn(None), "00");
+    }
+}
@@ -3,11 +3,8 @@ use crate::errors::*;
 use crate::hlua::AnyLuaValue;
 use crate::json::LuaJsonValue;
 use bufstream::BufStream;
-use chrootable_https::dns::{DnsResolver, RecordType};
-use chrootable_https::socks5::{self, ProxyDest};
 use regex::Regex;
 use serde::Deserialize;
-use tokio::runtime::Runtime;
 
 use std::fmt;
 use std::io;
@@ -15,27 +12,19 @@ use std::io::prelude::*;
 use std::io::BufRead;
 use std::net::SocketAddr;
 use std::net::TcpStream;
-use std::net::{IpAddr, Ipv4Addr};
 use std::str;
 use std::time::Duration;
 
+mod connector;
+mod fingerprint;
 mod tls;
+pub use self::connector::{
+    connect_addrs, connect_socks5, connect_udp_addrs, resolve, resolve_socks5, ConnectRequest,
+    Connection, Connector, Datagram,
+};
+pub use self::fingerprint::Fingerprints;
 pub use self::tls::TlsData;
 
-#[cfg(unix)]
-fn unwrap_socket(socket: tokio::net::TcpStream) -> Result<TcpStream> {
-    use std::os::unix::io::AsRawFd;
-    use std::os::unix::io::FromRawFd;
-    let socket2 = unsafe { TcpStream::from_raw_fd(socket.as_raw_fd()) };
-    let socket = socket2.try_clone()?;
-    Ok(socket)
-}
-
-#[cfg(windows)]
-fn unwrap_socket(socket: tokio::net::TcpStream) -> Result<TcpStream> {
-    bail!("Unwrapping tokio sockets into std sockets isn't supported on windows")
-}
-
 #[derive(Debug, Default, Deserialize)]
 pub struct SocketOptions {
     #[serde(default)]
@@ -68,11 +57,19 @@ impl SocketOptions {
     pub fn apply(&self, stream: &Stream) -> Result<()> {
         let socket = match stream {
             Stream::Tcp(s) => s,
-            Stream::Tls(s) => s.get_ref(),
+            Stream::Tls(s, _) => s.get_ref(),
         };
         self.apply_tcp(socket)
     }
 
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        if self.connect_timeout > 0 {
+            Some(Duration::from_secs(self.connect_timeout))
+        } else {
+            None
+        }
+    }
+
     pub fn apply_tcp(&self, socket: &TcpStream) -> Result<()> {
         let read_timeout = self.read_timeout;
         if read_timeout > 0 {
@@ -90,82 +87,37 @@ impl SocketOptions {
 
 pub enum Stream {
     Tcp(TcpStream),
-    Tls(Box<rustls::StreamOwned<rustls::ClientSession, TcpStream>>),
+    Tls(
+        Box<rustls::StreamOwned<rustls::ClientSession, TcpStream>>,
+        Box<TlsData>,
+    ),
 }
 
 impl Stream {
-    pub fn connect_stream<R: DnsResolver>(
-        resolver: &R,
-        host: &str,
-        port: u16,
-        options: &SocketOptions,
-    ) -> Result<Stream> {
-        let addrs = match host.parse::<IpAddr>() {
-            Ok(addr) => vec![addr],
-            Err(_) => resolver
-                .resolve(host, RecordType::A)
-                .wait_for_response()?
-                .success()?,
-        };
-
-        let mut errors = Vec::new();
-
-        for addr in addrs {
-            match Stream::connect_addr(host, (addr, port).into(), options) {
-                Ok(socket) => {
-                    return Ok(socket);
-                }
-                Err(err) => errors.push((addr, err)),
-            }
-        }
-
-        if errors.is_empty() {
-            bail!("no dns records found");
-        } else {
-            bail!("couldn't connect: {:?}", errors);
+    /// The handshake details if this is a tls stream
+    pub fn tls_data(&self) -> Option<&TlsData> {
+        match self {
+            Stream::Tcp(_) => None,
+            Stream::Tls(_, tls) => Some(tls),
         }
     }
 
-    fn connect_addr(host: &str, addr: SocketAddr, options: &SocketOptions) -> Result<Stream> {
-        debug!("connecting to {}", addr);
-
-        let connect_timeout = options.connect_timeout;
-        let socket = if connect_timeout > 0 {
-            TcpStream::connect_timeout(&addr, Duration::from_secs(connect_timeout))?
-        } else {
-            TcpStream::connect(addr)?
-        };
-        debug!("successfully connected to {:?}", addr);
-
-        options.apply_tcp(&socket)?;
-
-        tls::wrap_if_enabled(socket, host, options)
-    }
-
-    pub fn connect_socks5_stream(
-        proxy: SocketAddr,
+    pub fn connect_stream(
+        connector: &dyn Connector,
         host: &str,
         port: u16,
         options: &SocketOptions,
     ) -> Result<Stream> {
-        debug!(
-            "connecting to {:?}:{:?} with socks5 on {:?}",
-            host, port, proxy
-        );
-
-        let addr = match host.parse::<Ipv4Addr>() {
-            Ok(ipaddr) => ProxyDest::Ipv4Addr(ipaddr),
-            _ => ProxyDest::Domain(host.to_string()),
-        };
-
-        let fut = socks5::connect(&proxy, addr, port);
+        let conn = connector.connect(&ConnectRequest {
+            host: host.to_string(),
+            port,
+            proxy: options.proxy,
+            connect_timeout: options.connect_timeout(),
+        })?;
 
-        let mut rt = Runtime::new()?;
-        let socket = rt.block_on(fut)?;
+        options.apply_tcp(&conn.stream)?;
 
-        let socket = unwrap_socket(socket)?;
-
-        tls::wrap_if_enabled(socket, host, options)
+        tls::wrap_if_enabled(conn.stream, host, options)
     }
 }
 
@@ -173,7 +125,7 @@ impl fmt::Debug for Stream {
     fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Stream::Tcp(s) => write!(w, "Stream::Tcp {{ {:?} }}", s),
-            Stream::Tls(_) => write!(w, "Stream::Tls {{ ... }}"),
+            Stream::Tls(..) => write!(w, "Stream::Tls {{ ... }}"),
         }
     }
 }
@@ -182,7 +134,7 @@ impl Read for Stream {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         match self {
             Stream::Tcp(s) => s.read(buf),
-            Stream::Tls(s) => s.read(buf),
+            Stream::Tls(s, _) => s.read(buf),
         }
     }
 }
@@ -191,14 +143,14 @@ impl Write for Stream {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         match self {
             Stream::Tcp(s) => s.write(buf),
-            Stream::Tls(s) => s.write(buf),
+            Stream::Tls(s, _) => s.write(buf),
         }
     }
 
     fn flush(&mut self) -> io::Result<()> {
         match self {
             Stream::Tcp(s) => s.flush(),
-            Stream::Tls(s) => s.flush(),
+            Stream::Tls(s, _) => s.flush(),
         }
     }
 }
@@ -218,23 +170,13 @@ impl Socket {
         }
     }
 
-    pub fn connect<R: DnsResolver>(
-        resolver: &R,
-        host: &str,
-        port: u16,
-        options: &SocketOptions,
-    ) -> Result<Socket> {
-        let stream = Stream::connect_stream(resolver, host, port, options)?;
-        Ok(Socket::new(stream))
-    }
-
-    pub fn connect_socks5(
-        proxy: SocketAddr,
+    pub fn connect(
+        connector: &dyn Connector,
         host: &str,
         port: u16,
         options: &SocketOptions,
     ) -> Result<Socket> {
-        let stream = Stream::connect_socks5_stream(proxy, host, port, options)?;
+        let stream = Stream::connect_stream(connector, host, port, options)?;
         Ok(Socket::new(stream))
     }
 
@@ -254,6 +196,10 @@ impl Socket {
         options.apply(self.stream.get_ref())
     }
 
+    pub fn tls_data(&self) -> Option<&TlsData> {
+        self.stream.get_ref().tls_data()
+    }
+
     pub fn send(&mut self, data: &[u8]) -> Result<()> {
         match str::from_utf8(data) {
             Ok(data) => debug!("send: {:?}", data),
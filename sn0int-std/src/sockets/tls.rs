@@ -1,3 +1,4 @@
+use super::fingerprint::{Fingerprints, Recorder};
 use super::{SocketOptions, Stream};
 use crate::errors::*;
 use crate::hlua::AnyLuaValue;
@@ -9,13 +10,19 @@ use std::result;
 use std::str;
 use std::sync::Arc;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct TlsData {
     cert: Option<String>,
     cert_chain: Vec<String>,
+    #[serde(flatten)]
+    fingerprints: Fingerprints,
 }
 
 impl TlsData {
+    pub fn fingerprints(&self) -> &Fingerprints {
+        &self.fingerprints
+    }
+
     pub fn to_lua(&self) -> Result<AnyLuaValue> {
         let v = serde_json::to_value(self)?;
         let v = LuaJsonValue::from(v).into();
@@ -73,21 +80,23 @@ fn get_dns_name(config: &mut ClientConfig, host: &str) -> webpki::DNSName {
 
 fn setup(mut stream: TcpStream, mut session: ClientSession) -> Result<(Stream, TlsData)> {
     info!("starting tls handshake");
+    let mut recorder = Recorder::new(&mut stream);
     if session.is_handshaking() {
         session
-            .complete_io(&mut stream)
+            .complete_io(&mut recorder)
             .context("Failed to read reply to tls client hello")?;
     }
 
     if session.wants_write() {
         session
-            .complete_io(&mut stream)
+            .complete_io(&mut recorder)
             .context("wants_write->complete_io failed")?;
     }
 
     let mut tls = TlsData {
         cert: None,
         cert_chain: Vec::new(),
+        fingerprints: Fingerprints::new(&recorder.sent, &recorder.received),
     };
 
     if let Some(certs) = session.get_peer_certificates() {
@@ -102,7 +111,7 @@ fn setup(mut stream: TcpStream, mut session: ClientSession) -> Result<(Stream, T
 
     info!("successfully established tls connection");
     let stream = rustls::StreamOwned::new(session, stream);
-    let stream = Stream::Tls(Box::new(stream));
+    let stream = Stream::Tls(Box::new(stream), Box::new(tls.clone()));
     Ok((stream, tls))
 }
 
@@ -1,27 +1,33 @@
+pub use self::client::Response;
 use crate::blobs::{Blob, BlobState};
+use crate::concurrency::{ConcurrencyState, Slot};
 use crate::engine::structs::LuaMap;
 use crate::errors::*;
 use crate::hlua::AnyLuaValue;
 use crate::json::LuaJsonValue;
+use crate::sockets::{ConnectRequest, Connection, Connector};
 use chrootable_https::http::request::Builder;
 use chrootable_https::http::uri::Parts;
 use chrootable_https::http::HttpTryFrom;
+pub use chrootable_https::Resolver;
 use chrootable_https::{Body, Request, Uri};
-pub use chrootable_https::{Client, HttpClient, Resolver, Response};
 use data_encoding::BASE64;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
 use std::fmt::Write;
 use std::iter;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::ops::Deref;
-use std::sync::Arc;
 use std::time::Duration;
 use url::Url;
 
+pub mod client;
+#[cfg(feature = "http3")]
+mod h3;
+
 pub fn url_set_qs<S: Serialize + fmt::Debug>(url: Uri, query: &S) -> Result<Uri> {
     let mut parts = Parts::from(url);
 
@@ -40,10 +46,23 @@ pub fn url_set_qs<S: Serialize + fmt::Debug>(url: Uri, query: &S) -> Result<Uri>
     Uri::from_parts(parts).map_err(Error::from)
 }
 
-pub trait WebState {
-    fn http(&self, proxy: &Option<SocketAddr>) -> Result<Arc<chrootable_https::Client<Resolver>>>;
-
+pub trait WebState: ConcurrencyState + Connector {
     fn register_in_jar(&self, session: &str, key: String, value: String);
+
+    /// Record a request in the network audit log, if it's enabled
+    fn audit_http(&self, audit: HttpAudit) -> Result<()>;
+}
+
+/// A request that has been sent to a server, including failed attempts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HttpAudit {
+    pub method: String,
+    pub url: String,
+    pub headers: BTreeMap<String, String>,
+    pub status: Option<u16>,
+    pub bytes: Option<usize>,
+    pub error: Option<String>,
+    pub ipaddr: Option<IpAddr>,
 }
 
 #[derive(Debug)]
@@ -70,6 +89,71 @@ impl HttpSession {
     }
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HttpVersion {
+    #[default]
+    Auto,
+    H1,
+    H2,
+    /// Only available if sn0int has been built with the http3 feature
+    H3,
+}
+
+/// A redirect that has been followed while sending a request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Redirect {
+    pub status: u16,
+    pub url: String,
+    pub location: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RedirectPolicy {
+    pub cross_origin: bool,
+    pub preserve_method: bool,
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> RedirectPolicy {
+        RedirectPolicy {
+            cross_origin: true,
+            preserve_method: false,
+        }
+    }
+}
+
+impl RedirectPolicy {
+    /// Resolve the next request of a redirect, returns `None` if the
+    /// redirect shouldn't be followed
+    pub fn next(
+        &self,
+        url: &Url,
+        method: &str,
+        status: u16,
+        location: &str,
+    ) -> Result<Option<(Url, String)>> {
+        let method = match status {
+            // these are defined to keep the method
+            307 | 308 => method.to_string(),
+            // 303 always switches to GET
+            303 => String::from("GET"),
+            // clients traditionally switch to GET for these
+            301 | 302 if self.preserve_method => method.to_string(),
+            301 | 302 => String::from("GET"),
+            _ => return Ok(None),
+        };
+
+        let next = url.join(location)?;
+        if !self.cross_origin && next.origin() != url.origin() {
+            debug!("Not following cross-origin redirect to {:?}", next.as_str());
+            return Ok(None);
+        }
+
+        Ok(Some((next, method)))
+    }
+}
+
 #[derive(Debug, Default, Deserialize)]
 pub struct RequestOptions {
     pub query: Option<HashMap<String, String>>,
@@ -80,6 +164,9 @@ pub struct RequestOptions {
     pub form: Option<serde_json::Value>,
     #[serde(default)]
     pub follow_redirects: usize,
+    pub redirect_cross_origin: Option<bool>,
+    #[serde(default)]
+    pub redirect_preserve_method: bool,
     pub body: Option<String>,
     pub timeout: Option<u64>,
     #[serde(default)]
@@ -87,6 +174,8 @@ pub struct RequestOptions {
     pub proxy: Option<SocketAddr>,
     #[serde(default)]
     pub binary: bool,
+    #[serde(default)]
+    pub http_version: HttpVersion,
 }
 
 impl RequestOptions {
@@ -109,11 +198,18 @@ pub struct HttpRequest {
     basic_auth: Option<(String, String)>,
     user_agent: String,
     follow_redirects: usize,
+    #[serde(default)]
+    redirect_policy: RedirectPolicy,
+    #[serde(default, skip_serializing)]
+    redirects: Vec<Redirect>,
+    #[serde(default, skip_serializing)]
+    final_url: Option<String>,
     body: Option<ReqBody>,
     timeout: Option<Duration>,
     into_blob: bool,
     proxy: Option<SocketAddr>,
     binary: bool,
+    http_version: HttpVersion,
 }
 
 impl HttpRequest {
@@ -137,11 +233,18 @@ impl HttpRequest {
             basic_auth: options.basic_auth,
             user_agent,
             follow_redirects: options.follow_redirects,
+            redirect_policy: RedirectPolicy {
+                cross_origin: options.redirect_cross_origin.unwrap_or(true),
+                preserve_method: options.redirect_preserve_method,
+            },
+            redirects: Vec::new(),
+            final_url: None,
             body: None,
             timeout,
             into_blob: options.into_blob,
             proxy: options.proxy,
             binary: options.binary,
+            http_version: options.http_version,
         };
 
         if let Some(json) = options.json {
@@ -189,36 +292,59 @@ impl HttpRequest {
         }
 
         // finalize request
-        let body = match self.body {
-            Some(ReqBody::Raw(ref x)) => Body::from(x.clone()),
-            Some(ReqBody::Form(ref x)) => {
-                // if Content-Type is not set, set header
-                if !observed_headers.contains("content-type") {
-                    req.header("Content-Type", "application/x-www-form-urlencoded");
-                }
-                Body::from(serde_urlencoded::to_string(x)?)
-            }
-            Some(ReqBody::Json(ref x)) => {
-                // if Content-Type is not set, set header
-                if !observed_headers.contains("content-type") {
-                    req.header("Content-Type", "application/json");
-                }
-                Body::from(serde_json::to_string(x)?)
-            }
-            None => Body::empty(),
-        };
+        let body = self.mkbody(&mut req, &observed_headers)?;
         let mut req = req.body(body)?;
 
-        debug!("Getting http client");
-        let client = state.http(&self.proxy)?;
+        let mut method = self.method.clone();
+        let mut visited = HashSet::new();
+        self.redirects.clear();
 
         let res = loop {
+            visited.insert((method.clone(), url.to_string()));
+
+            let mut audit = HttpAudit {
+                method: method.clone(),
+                url: url.to_string(),
+                headers: req
+                    .headers()
+                    .iter()
+                    .map(|(k, v)| {
+                        let v = String::from_utf8_lossy(v.as_bytes()).to_string();
+                        (k.to_string(), v)
+                    })
+                    .collect(),
+                status: None,
+                bytes: None,
+                error: None,
+                ipaddr: None,
+            };
+
             // send request
             debug!("Sending http request: {:?}", req);
-            let res = client
-                .request(req)
-                .with_timeout(self.timeout)
-                .wait_for_response()?;
+            let slot = Slot::acquire(state)?;
+            // redirects may point anywhere, every hop is a new connection
+            let res = if self.http_version == HttpVersion::H3 {
+                self.send_h3(state, &url, req)
+            } else {
+                self.connect(state, &url)
+                    .and_then(|conn| client::request(conn, req, self.http_version, self.timeout))
+            };
+            drop(slot);
+
+            let res = match res {
+                Ok(res) => {
+                    audit.status = Some(res.status);
+                    audit.bytes = Some(res.body.len());
+                    audit.ipaddr = res.ipaddr;
+                    state.audit_http(audit)?;
+                    res
+                }
+                Err(err) => {
+                    audit.error = Some(err.to_string());
+                    state.audit_http(audit)?;
+                    return Err(err);
+                }
+            };
 
             for cookie in &res.cookies {
                 HttpRequest::register_cookies_on_state(&self.session, state, cookie);
@@ -226,22 +352,106 @@ impl HttpRequest {
 
             if self.follow_redirects > 0 && res.status >= 300 && res.status < 400 {
                 if let Some(location) = res.headers.get("location") {
-                    let base = Url::parse(&url.to_string())?;
-                    let joined = base.join(location)?;
-                    url = joined.to_string().parse()?;
-
-                    req = self.mkrequest("GET", &url).body(Body::empty())?;
-                    self.follow_redirects -= 1;
-                    continue;
+                    let current = Url::parse(&url.to_string())?;
+                    if let Some((next, next_method)) = self
+                        .redirect_policy
+                        .next(&current, &method, res.status, location)?
+                    {
+                        if visited.contains(&(next_method.clone(), next.to_string())) {
+                            bail!(
+                                "Redirect loop detected: {:?} redirects back to {:?}",
+                                current.as_str(),
+                                next.as_str()
+                            );
+                        }
+
+                        self.redirects.push(Redirect {
+                            status: res.status,
+                            url: current.to_string(),
+                            location: next.to_string(),
+                        });
+
+                        url = next.to_string().parse()?;
+                        let mut builder = self.mkrequest(next_method.as_str(), &url);
+                        // only resend the body if the method was preserved
+                        let body = if next_method == self.method && next_method != "GET" {
+                            self.mkbody(&mut builder, &observed_headers)?
+                        } else {
+                            Body::empty()
+                        };
+                        req = builder.body(body)?;
+                        method = next_method;
+                        self.follow_redirects -= 1;
+                        continue;
+                    }
                 }
             }
 
             break res;
         };
 
+        self.final_url = Some(url.to_string());
+
         Ok(res)
     }
 
+    fn connect_request(&self, url: &Uri) -> Result<ConnectRequest> {
+        let host = url.host().ok_or_else(|| format_err!("url has no host"))?;
+        let port = match (url.port_u16(), url.scheme_str()) {
+            (Some(port), _) => port,
+            (None, Some("https")) => 443,
+            (None, _) => 80,
+        };
+
+        Ok(ConnectRequest {
+            host: host.to_string(),
+            port,
+            proxy: self.proxy,
+            connect_timeout: self.timeout,
+        })
+    }
+
+    fn connect(&self, state: &dyn WebState, url: &Uri) -> Result<Connection> {
+        state.connect(&self.connect_request(url)?)
+    }
+
+    #[cfg(feature = "http3")]
+    fn send_h3(&self, state: &dyn WebState, url: &Uri, req: Request<Body>) -> Result<Response> {
+        if url.scheme_str() != Some("https") {
+            bail!("http3 is only available for https:// urls");
+        }
+        state
+            .connect_udp(&self.connect_request(url)?)
+            .and_then(|datagram| h3::request(datagram, req, self.timeout))
+    }
+
+    #[cfg(not(feature = "http3"))]
+    fn send_h3(&self, _state: &dyn WebState, _url: &Uri, _req: Request<Body>) -> Result<Response> {
+        bail!("sn0int has been built without http3 support")
+    }
+
+    fn mkbody(&self, req: &mut Builder, observed_headers: &HashSet<String>) -> Result<Body> {
+        let body = match self.body {
+            Some(ReqBody::Raw(ref x)) => Body::from(x.clone()),
+            Some(ReqBody::Form(ref x)) => {
+                // if Content-Type is not set, set header
+                if !observed_headers.contains("content-type") {
+                    req.header("Content-Type", "application/x-www-form-urlencoded");
+                }
+                Body::from(serde_urlencoded::to_string(x)?)
+            }
+            Some(ReqBody::Json(ref x)) => {
+                // if Content-Type is not set, set header
+                if !observed_headers.contains("content-type") {
+                    req.header("Content-Type", "application/json");
+                }
+                Body::from(serde_json::to_string(x)?)
+            }
+            None => Body::empty(),
+        };
+        Ok(body)
+    }
+
     /// create a basic request, reusable when following redirects
     fn mkrequest<T>(&self, method: &str, url: T) -> Builder
     where
@@ -282,6 +492,11 @@ impl HttpRequest {
         // map result to LuaMap
         let mut resp = LuaMap::new();
         resp.insert_num("status", f64::from(res.status));
+        resp.insert_str("version", res.version);
+        if let Some(url) = &self.final_url {
+            resp.insert_str("url", url.as_str());
+        }
+        resp.insert_serde("redirects", &self.redirects)?;
 
         let mut headers = LuaMap::new();
         for (key, value) in res.headers {
@@ -294,6 +509,7 @@ impl HttpRequest {
         }
 
         if self.into_blob {
+            state.check_blob()?;
             let blob = Blob::create(res.body);
             let id = state.register_blob(blob);
             resp.insert_str("blob", id);
@@ -366,3 +582,63 @@ pub enum ReqBody {
     Form(serde_json::Value),
     Json(serde_json::Value),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn next(
+        policy: RedirectPolicy,
+        method: &str,
+        status: u16,
+        location: &str,
+    ) -> Option<(String, String)> {
+        let url = Url::parse("https://example.com/a/b").unwrap();
+        policy
+            .next(&url, method, status, location)
+            .unwrap()
+            .map(|(url, method)| (url.to_string(), method))
+    }
+
+    #[test]
+    fn test_redirect_relative() {
+        let r = next(RedirectPolicy::default(), "GET", 302, "c");
+        assert_eq!(
+            r,
+            Some(("https://example.com/a/c".to_string(), "GET".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_redirect_method() {
+        let policy = RedirectPolicy::default();
+        assert_eq!(next(policy, "POST", 301, "/x").unwrap().1, "GET");
+        assert_eq!(next(policy, "POST", 303, "/x").unwrap().1, "GET");
+        assert_eq!(next(policy, "POST", 307, "/x").unwrap().1, "POST");
+        assert_eq!(next(policy, "POST", 308, "/x").unwrap().1, "POST");
+
+        let policy = RedirectPolicy {
+            preserve_method: true,
+            ..Default::default()
+        };
+        assert_eq!(next(policy, "POST", 302, "/x").unwrap().1, "POST");
+        assert_eq!(next(policy, "POST", 303, "/x").unwrap().1, "GET");
+    }
+
+    #[test]
+    fn test_redirect_cross_origin() {
+        let policy = RedirectPolicy {
+            cross_origin: false,
+            ..Default::default()
+        };
+        assert_eq!(next(policy, "GET", 302, "https://example.org/"), None);
+        assert!(next(policy, "GET", 302, "https://example.com/x").is_some());
+        // a scheme downgrade is a different origin as well
+        assert_eq!(next(policy, "GET", 302, "http://example.com/x"), None);
+    }
+
+    #[test]
+    fn test_redirect_not_a_redirect() {
+        assert_eq!(next(RedirectPolicy::default(), "GET", 304, "/x"), None);
+    }
+}
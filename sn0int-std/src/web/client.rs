@@ -0,0 +1,284 @@
+use super::HttpVersion;
+use crate::errors::*;
+use crate::sockets::Connection;
+use bytes::Bytes;
+use chrootable_https::header::HOST;
+use chrootable_https::http::response::Parts;
+use chrootable_https::http::{HeaderValue, Version};
+use chrootable_https::hyper::client::conn;
+use chrootable_https::hyper::rt::{Future, Stream};
+use chrootable_https::{Body, Request, Uri};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::prelude::future;
+use tokio::reactor::Handle;
+use tokio::runtime::Runtime;
+use tokio::timer::Timeout;
+use tokio_rustls::rustls::{ClientConfig, Session};
+use tokio_rustls::webpki::DNSNameRef;
+use tokio_rustls::TlsConnector;
+
+const ALPN_H2: &[u8] = b"h2";
+const ALPN_HTTP1: &[u8] = b"http/1.1";
+pub(crate) const ALPN_H3: &[u8] = b"h3";
+
+/// A response, including the protocol version the server answered with
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub version: &'static str,
+    pub headers: HashMap<String, String>,
+    pub cookies: Vec<String>,
+    pub ipaddr: Option<IpAddr>,
+    pub body: Bytes,
+}
+
+/// HTTP/3 isn't known to this version of the http crate, it's only set by
+/// the quic client
+fn version_str(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "HTTP/0.9",
+        Version::HTTP_10 => "HTTP/1.0",
+        Version::HTTP_2 => "HTTP/2",
+        _ => "HTTP/1.1",
+    }
+}
+
+/// The protocols that are offered during the tls handshake
+fn alpn_protocols(version: HttpVersion) -> Vec<Vec<u8>> {
+    let protocols = match version {
+        HttpVersion::Auto => vec![ALPN_H2, ALPN_HTTP1],
+        HttpVersion::H1 => vec![ALPN_HTTP1],
+        HttpVersion::H2 => vec![ALPN_H2],
+        HttpVersion::H3 => vec![ALPN_H3],
+    };
+    protocols.into_iter().map(|p| p.to_vec()).collect()
+}
+
+/// Send a single request over a connection that has been opened for it
+pub fn request(
+    conn: Connection,
+    mut req: Request<Body>,
+    version: HttpVersion,
+    timeout: Option<Duration>,
+) -> Result<Response> {
+    let uri = req.uri().clone();
+    let host = uri
+        .host()
+        .ok_or_else(|| format_err!("url has no host"))?
+        .to_string();
+    let tls = match uri.scheme_str() {
+        Some("https") => true,
+        Some("http") => false,
+        scheme => bail!("unsupported url scheme: {:?}", scheme),
+    };
+
+    // the timeout covers the whole exchange, including both handshakes
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+    let mut rt = Runtime::new()?;
+    let ipaddr = conn.addr.map(|addr| addr.ip());
+    let stream = conn.stream;
+    let socket = rt.block_on(future::lazy(move || {
+        TcpStream::from_std(stream, &Handle::default())
+    }))?;
+
+    let (parts, body) = if tls {
+        let mut config = ClientConfig::new();
+        config
+            .root_store
+            .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+        config.ct_logs = Some(&ct_logs::LOGS);
+        config.set_protocols(&alpn_protocols(version));
+
+        let dns_name = DNSNameRef::try_from_ascii_str(&host)
+            .map_err(|_| format_err!("invalid dns name: {:?}", host))?;
+        let connector = TlsConnector::from(Arc::new(config));
+        let handshake = connector.connect(dns_name, socket).map_err(Error::from);
+        let stream = block_on(&mut rt, handshake, deadline)
+            .context("tls handshake failed")?;
+
+        let (_, session) = stream.get_ref();
+        let http2 = session.get_alpn_protocol() == Some(ALPN_H2);
+        if version == HttpVersion::H2 && !http2 {
+            bail!("server did not negotiate http/2");
+        }
+
+        if !http2 {
+            origin_form(&mut req, &uri, &host)?;
+        }
+        send(&mut rt, stream, req, http2, deadline)?
+    } else {
+        // cleartext http/2 is only used if requested, with prior knowledge
+        let http2 = version == HttpVersion::H2;
+        if !http2 {
+            origin_form(&mut req, &uri, &host)?;
+        }
+        send(&mut rt, socket, req, http2, deadline)?
+    };
+
+    Ok(build_response(ipaddr, parts, body))
+}
+
+/// http/1 requests only contain the path, the host is sent as a header
+fn origin_form(req: &mut Request<Body>, uri: &Uri, host: &str) -> Result<()> {
+    if !req.headers().contains_key(HOST) {
+        let value = match uri.port_u16() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        };
+        req.headers_mut()
+            .insert(HOST, HeaderValue::from_str(&value)?);
+    }
+
+    let path = uri.path_and_query().map(|x| x.as_str()).unwrap_or("/");
+    *req.uri_mut() = path.parse()?;
+    Ok(())
+}
+
+fn send<T>(
+    rt: &mut Runtime,
+    io: T,
+    req: Request<Body>,
+    http2: bool,
+    deadline: Option<Instant>,
+) -> Result<(Parts, Bytes)>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let handshake = conn::Builder::new()
+        .http2_only(http2)
+        .handshake::<_, Body>(io)
+        .map_err(Error::from);
+    let (mut sender, connection) = block_on(rt, handshake, deadline)?;
+    rt.spawn(connection.map_err(|err| debug!("http connection closed: {}", err)));
+
+    let fut = sender
+        .send_request(req)
+        .and_then(|res| {
+            let (parts, body) = res.into_parts();
+            body.concat2().map(|body| (parts, body.into_bytes()))
+        })
+        .map_err(Error::from);
+    block_on(rt, fut, deadline)
+}
+
+/// Run a step of the exchange, if there's a deadline it's shared by all of them
+fn block_on<F>(rt: &mut Runtime, fut: F, deadline: Option<Instant>) -> Result<F::Item>
+where
+    F: Future<Error = Error> + Send + 'static,
+    F::Item: Send + 'static,
+{
+    match deadline {
+        Some(deadline) => rt.block_on(Timeout::new_at(fut, deadline).map_err(|err| {
+            err.into_inner()
+                .unwrap_or_else(|| format_err!("Request timed out"))
+        })),
+        None => rt.block_on(fut),
+    }
+}
+
+fn build_response(ipaddr: Option<IpAddr>, parts: Parts, body: Bytes) -> Response {
+    let cookies = parts
+        .headers
+        .get_all("set-cookie")
+        .into_iter()
+        .flat_map(|x| x.to_str().map(|x| x.to_owned()).ok())
+        .collect();
+
+    let mut headers = HashMap::new();
+    for (k, v) in &parts.headers {
+        if let Ok(v) = v.to_str() {
+            headers.insert(k.as_str().to_string(), v.to_string());
+        }
+    }
+
+    Response {
+        status: parts.status.as_u16(),
+        version: version_str(parts.version),
+        headers,
+        cookies,
+        ipaddr,
+        body,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_origin_form() {
+        let uri = "http://example.com:8080/a/b?c=d".parse::<Uri>().unwrap();
+        let mut req = Request::builder()
+            .uri(uri.clone())
+            .body(Body::empty())
+            .unwrap();
+        origin_form(&mut req, &uri, "example.com").unwrap();
+        assert_eq!(req.uri().to_string(), "/a/b?c=d");
+        assert_eq!(req.headers()[HOST], "example.com:8080");
+    }
+
+    fn stalled(url: &str, version: HttpVersion) -> Error {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // accept the connection and never answer
+        std::thread::spawn(move || {
+            let (_stream, _) = listener.accept().unwrap();
+            std::thread::sleep(Duration::from_secs(10));
+        });
+
+        let stream = std::net::TcpStream::connect(addr).unwrap();
+        let conn = Connection {
+            stream,
+            addr: Some(addr),
+        };
+        let req = Request::builder()
+            .uri(url)
+            .body(Body::empty())
+            .unwrap();
+
+        let start = Instant::now();
+        let err = request(conn, req, version, Some(Duration::from_millis(200))).unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        err
+    }
+
+    #[test]
+    fn test_timeout_tls_handshake() {
+        let err = stalled("https://localhost/", HttpVersion::Auto);
+        assert_eq!(err.to_string(), "tls handshake failed");
+        assert_eq!(err.find_root_cause().to_string(), "Request timed out");
+    }
+
+    #[test]
+    fn test_timeout_no_response() {
+        let err = stalled("http://localhost/", HttpVersion::H1);
+        assert_eq!(err.to_string(), "Request timed out");
+        let err = stalled("http://localhost/", HttpVersion::H2);
+        assert_eq!(err.to_string(), "Request timed out");
+    }
+
+    #[test]
+    fn test_alpn_protocols() {
+        assert_eq!(
+            alpn_protocols(HttpVersion::Auto),
+            vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+        );
+        assert_eq!(alpn_protocols(HttpVersion::H1), vec![b"http/1.1".to_vec()]);
+        assert_eq!(alpn_protocols(HttpVersion::H2), vec![b"h2".to_vec()]);
+    }
+
+    #[test]
+    fn test_version_str() {
+        let (parts, _) = chrootable_https::http::Response::new(()).into_parts();
+        let res = build_response(None, parts, Bytes::new());
+        assert_eq!(res.version, "HTTP/1.1");
+        assert_eq!(version_str(Version::HTTP_10), "HTTP/1.0");
+        assert_eq!(version_str(Version::HTTP_2), "HTTP/2");
+    }
+}
@@ -0,0 +1,162 @@
+use super::client::{Response, ALPN_H3};
+use crate::errors::*;
+use crate::sockets::Datagram;
+use bytes::Bytes;
+use bytes1::Buf;
+use chrootable_https::hyper::rt::{Future, Stream};
+use chrootable_https::{Body, Request};
+use std::collections::HashMap;
+use std::future;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn tls_config() -> rustls_quic::ClientConfig {
+    let mut roots = rustls_quic::RootCertStore::empty();
+    roots.add_trust_anchors(webpki_roots_quic::TLS_SERVER_ROOTS.iter().map(|ta| {
+        rustls_quic::OwnedTrustAnchor::from_subject_spki_name_constraints(
+            ta.subject,
+            ta.spki,
+            ta.name_constraints,
+        )
+    }));
+    let mut config = rustls_quic::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = vec![ALPN_H3.to_vec()];
+    config
+}
+
+/// The request has been built for hyper, which uses an older version of the
+/// http crate than h3
+fn convert_request(req: Request<Body>) -> Result<(http::Request<()>, Vec<u8>)> {
+    let (parts, body) = req.into_parts();
+    let body = body.concat2().wait()?.to_vec();
+
+    let mut builder = http::Request::builder()
+        .method(parts.method.as_str())
+        .uri(parts.uri.to_string());
+    for (key, value) in &parts.headers {
+        builder = builder.header(key.as_str(), value.as_bytes());
+    }
+    Ok((builder.body(())?, body))
+}
+
+/// Send a single request over a quic connection that has been opened for it
+pub fn request(datagram: Datagram, req: Request<Body>, timeout: Option<Duration>) -> Result<Response> {
+    let host = req
+        .uri()
+        .host()
+        .ok_or_else(|| format_err!("url has no host"))?
+        .to_string();
+    let (req, body) = convert_request(req)?;
+
+    let rt = tokio1::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    rt.block_on(async move {
+        let fut = send(datagram, host, req, body);
+        match timeout {
+            Some(timeout) => tokio1::time::timeout(timeout, fut)
+                .await
+                .map_err(|_| format_err!("Request timed out"))?,
+            None => fut.await,
+        }
+    })
+}
+
+async fn send(datagram: Datagram, host: String, req: http::Request<()>, body: Vec<u8>) -> Result<Response> {
+    let addr = datagram.addr;
+    let mut endpoint = quinn::Endpoint::new(
+        quinn::EndpointConfig::default(),
+        None,
+        datagram.socket,
+        Arc::new(quinn::TokioRuntime),
+    )?;
+    endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(tls_config())));
+
+    let conn = endpoint
+        .connect(addr, &host)?
+        .await
+        .context("quic handshake failed")?;
+    let (mut driver, mut sender) = h3::client::new(h3_quinn::Connection::new(conn))
+        .await
+        .map_err(h3_error)?;
+    tokio1::spawn(async move {
+        if let Err(err) = future::poll_fn(|cx| driver.poll_close(cx)).await {
+            debug!("http3 connection closed: {}", err);
+        }
+    });
+
+    let mut stream = sender.send_request(req).await.map_err(h3_error)?;
+    if !body.is_empty() {
+        stream
+            .send_data(bytes1::Bytes::from(body))
+            .await
+            .map_err(h3_error)?;
+    }
+    stream.finish().await.map_err(h3_error)?;
+
+    let res = stream.recv_response().await.map_err(h3_error)?;
+    let mut body = Vec::new();
+    while let Some(mut chunk) = stream.recv_data().await.map_err(h3_error)? {
+        while chunk.has_remaining() {
+            let bytes = chunk.chunk();
+            body.extend_from_slice(bytes);
+            let len = bytes.len();
+            chunk.advance(len);
+        }
+    }
+    endpoint.close(0u32.into(), b"");
+
+    Ok(build_response(addr, res, body))
+}
+
+fn h3_error(err: h3::Error) -> Error {
+    format_err!("http3 request failed: {}", err)
+}
+
+fn build_response(addr: std::net::SocketAddr, res: http::Response<()>, body: Vec<u8>) -> Response {
+    let cookies = res
+        .headers()
+        .get_all("set-cookie")
+        .into_iter()
+        .flat_map(|x| x.to_str().map(|x| x.to_owned()).ok())
+        .collect();
+
+    let mut headers = HashMap::new();
+    for (k, v) in res.headers() {
+        if let Ok(v) = v.to_str() {
+            headers.insert(k.as_str().to_string(), v.to_string());
+        }
+    }
+
+    Response {
+        status: res.status().as_u16(),
+        version: "HTTP/3",
+        headers,
+        cookies,
+        ipaddr: Some(addr.ip()),
+        body: Bytes::from(body),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert_request() {
+        let req = Request::builder()
+            .method("POST")
+            .uri("https://example.com/a?b=c")
+            .header("X-Foo", "bar")
+            .body(Body::from("ohai"))
+            .unwrap();
+        let (req, body) = convert_request(req).unwrap();
+        assert_eq!(req.method(), "POST");
+        assert_eq!(req.uri(), "https://example.com/a?b=c");
+        assert_eq!(req.headers()["x-foo"], "bar");
+        assert_eq!(body, b"ohai");
+    }
+}
@@ -1,8 +1,7 @@
 use crate::errors::*;
 use crate::hlua::AnyLuaValue;
 use crate::json::LuaJsonValue;
-use crate::sockets::{SocketOptions, Stream};
-use chrootable_https::DnsResolver;
+use crate::sockets::{Connector, SocketOptions, Stream};
 use http::Request;
 use serde::Deserialize;
 use std::collections::HashMap;
@@ -11,7 +10,7 @@ use std::net::SocketAddr;
 use tungstenite::protocol::{self, Message};
 use url::Url;
 
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Default, Clone, Deserialize)]
 pub struct WebSocketOptions {
     pub headers: Option<HashMap<String, String>>,
     pub proxy: Option<SocketAddr>,
@@ -63,8 +62,8 @@ impl WebSocket {
         Ok(WebSocket { sock })
     }
 
-    pub fn connect<R: DnsResolver>(
-        resolver: &R,
+    pub fn connect(
+        connector: &dyn Connector,
         url: Url,
         options: &WebSocketOptions,
     ) -> Result<WebSocket> {
@@ -85,7 +84,7 @@ impl WebSocket {
         };
 
         let stream = Stream::connect_stream(
-            resolver,
+            connector,
             host,
             port,
             &SocketOptions {
@@ -5,7 +5,6 @@ use crate::utils;
 use chrootable_https::{self, HttpClient, Body, Request, Uri};
 use chrootable_https::http::request::Builder as RequestBuilder;
 use chrootable_https::header::CONTENT_TYPE;
-use maplit::hashmap;
 use serde::de::DeserializeOwned;
 use serde::ser::Serialize;
 use sn0int_common::api::*;
@@ -20,7 +19,12 @@ pub struct Client {
 }
 
 impl Client {
+    #[inline]
     pub fn new(config: &Config) -> Result<Client> {
+        Client::with_server(config, &config.core.registry)
+    }
+
+    pub fn with_server(config: &Config, server: &str) -> Result<Client> {
         let client = if let Some(proxy) = config.network.proxy {
             chrootable_https::Client::with_socks5(proxy)
         } else {
@@ -33,7 +37,7 @@ impl Client {
         };
 
         Ok(Client {
-            server: config.core.registry.clone(),
+            server: server.to_string(),
             client,
             session: None,
             os_version,
@@ -71,12 +75,7 @@ impl Client {
             .wait_for_response()?;
         info!("response: {:?}", resp);
 
-        let reply = serde_json::from_slice::<ApiResponse<T>>(&resp.body)?;
-        info!("api: {:?}", reply);
-        let reply = reply.success()?;
-        info!("api(success): {:?}", reply);
-
-        Ok(reply)
+        parse_reply(resp.status, &resp.body)
     }
 
     pub fn get<T: DeserializeOwned + fmt::Debug>(&self, url: &str) -> Result<T> {
@@ -116,10 +115,11 @@ impl Client {
         Ok(resp.user)
     }
 
-    pub fn publish_module(&self, name: &str, body: String) -> Result<PublishResponse> {
+    pub fn publish_module(&self, name: &str, body: String, signature: ModuleSignature) -> Result<PublishResponse> {
         let url = format!("{}/api/v0/publish/{}", self.server, name);
         let reply = self.post::<PublishResponse, _>(&url, &PublishRequest {
             code: body,
+            signature: Some(signature),
         })?;
         Ok(reply)
     }
@@ -136,10 +136,16 @@ impl Client {
         Ok(reply)
     }
 
-    pub fn search(&self, query: &str) -> Result<Vec<SearchResponse>> {
+    pub fn search(&self, search: &SearchRequest) -> Result<Vec<SearchResponse>> {
         let url = format!("{}/api/v0/search", self.server);
-        let reply = self.get_with::<Vec<SearchResponse>, _>(&url, &hashmap!{
-            "q" => query,
+        let reply = self.get_with::<Vec<SearchResponse>, _>(&url, search)?;
+        Ok(reply)
+    }
+
+    pub fn rate_module(&self, module: &ModuleID, stars: u8) -> Result<RateResponse> {
+        let url = format!("{}/api/v0/rate/{}/{}", self.server, module.author, module.name);
+        let reply = self.post::<RateResponse, _>(&url, &RateRequest {
+            stars,
         })?;
         Ok(reply)
     }
@@ -156,3 +162,45 @@ impl Client {
         Ok(reply)
     }
 }
+
+/// A 404 is returned as `NotFoundError`, so a missing resource can be told
+/// apart from a registry that failed to answer
+fn parse_reply<T: DeserializeOwned + fmt::Debug>(status: u16, body: &[u8]) -> Result<T> {
+    let reply = serde_json::from_slice::<ApiResponse<T>>(body);
+    if status == 404 {
+        let msg = match reply {
+            Ok(ApiResponse::Error(err)) => err,
+            _ => String::from("Resource was not found"),
+        };
+        return Err(NotFoundError(msg).into());
+    }
+
+    let reply = reply?;
+    info!("api: {:?}", reply);
+    let reply = reply.success()?;
+    info!("api(success): {:?}", reply);
+
+    Ok(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reply() {
+        let reply = parse_reply::<String>(200, br#"{"success":"ohai"}"#).unwrap();
+        assert_eq!(reply, "ohai");
+
+        let err = parse_reply::<String>(404, br#"{"error":"Module does not exist"}"#).unwrap_err();
+        assert_eq!(err.downcast_ref::<NotFoundError>().unwrap().0, "Module does not exist");
+        let err = parse_reply::<String>(404, b"<html>").unwrap_err();
+        assert!(err.downcast_ref::<NotFoundError>().is_some());
+
+        // anything else isn't a missing module
+        let err = parse_reply::<String>(500, br#"{"error":"Internal server error"}"#).unwrap_err();
+        assert!(err.downcast_ref::<NotFoundError>().is_none());
+        let err = parse_reply::<String>(502, b"<html>").unwrap_err();
+        assert!(err.downcast_ref::<NotFoundError>().is_none());
+    }
+}
@@ -1,11 +1,19 @@
-use clap::{CommandFactory, Parser};
+use clap::{ArgAction, CommandFactory, Parser};
 use clap_complete::Shell;
 use crate::cmd;
 use crate::errors::*;
+use crate::fmt::colors::ColorChoice;
+use crate::logging::Subsystem;
+use crate::git::GitSource;
+use crate::sandbox;
 use crate::options;
 use crate::workspaces::Workspace;
 use sn0int_common::ModuleID;
+use sn0int_common::api::SearchSort;
+use std::fmt;
 use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
 
 #[derive(Debug, Parser)]
 #[command(version)]
@@ -13,6 +21,23 @@ pub struct Args {
     /// Select a different workspace instead of the default
     #[arg(short = 'w', long="workspace", env="SN0INT_WORKSPACE")]
     pub workspace: Option<Workspace>,
+    /// Block all network access of modules
+    #[arg(long="offline")]
+    pub offline: bool,
+    /// When to use colors, `auto` also respects NO_COLOR
+    #[arg(long="color", value_enum, default_value="auto", global=true)]
+    pub color: ColorChoice,
+    /// More output, once for warnings of sn0int itself, twice for info and
+    /// three times for debug. This also applies to modules like `run -v` does
+    #[arg(short='v', long="verbose", action(ArgAction::Count))]
+    pub verbose: u8,
+    /// Only show warnings and errors, also of modules
+    #[arg(short='q', long="quiet", conflicts_with="verbose")]
+    pub quiet: bool,
+    /// Show the debug logs of a part of sn0int, without raising the verbosity
+    /// of everything else
+    #[arg(long="debug", value_enum)]
+    pub debug: Vec<Subsystem>,
 
     #[command(subcommand)]
     pub subcommand: Option<SubCommand>,
@@ -22,6 +47,25 @@ impl Args {
     pub fn is_sandbox(&self) -> bool {
         matches!(self.subcommand, Some(SubCommand::Sandbox(_)))
     }
+
+    /// Errors are printed as json if the command writes json
+    pub fn is_json(&self) -> bool {
+        match &self.subcommand {
+            Some(SubCommand::Select(select)) => select.json,
+            Some(SubCommand::Stats(stats)) => stats.json,
+            Some(SubCommand::Provenance(provenance)) => provenance.json,
+            Some(SubCommand::Timeline(timeline)) => timeline.json,
+            Some(SubCommand::Diff(diff)) => diff.json,
+            _ => false,
+        }
+    }
+
+    pub fn workspace(&self) -> Workspace {
+        match self.workspace {
+            Some(ref workspace) => workspace.clone(),
+            None => Workspace::from_str("default").unwrap(),
+        }
+    }
 }
 
 #[derive(Debug, Parser)]
@@ -37,16 +81,16 @@ pub enum SubCommand {
     Login(Login),
     /// Create a new module
     #[command(name="new")]
-    New(New),
+    New(cmd::new_cmd::Args),
     /// Publish a script to the registry
     #[command(name="publish")]
     Publish(Publish),
     /// Install a module from the registry
     #[command(name="install")]
     Install(Install),
-    /// Search in the registry
-    #[command(name="search")]
-    Search(Search),
+    /// Show details about an installed module
+    #[command(name="info")]
+    Info(cmd::info_cmd::Args),
     /// The sn0int package manager
     #[command(name="pkg")]
     Pkg(cmd::pkg_cmd::Args),
@@ -62,6 +106,24 @@ pub enum SubCommand {
     /// Query logged activity
     #[command(name="activity")]
     Activity(cmd::activity_cmd::Args),
+    /// Review connections made by modules
+    #[command(name="audit")]
+    Audit(cmd::audit_cmd::Args),
+    /// Show the recorded history of an entity
+    #[command(name="provenance")]
+    Provenance(cmd::provenance_cmd::Args),
+    /// Show everything that is linked to an entity as a tree
+    #[command(name="tree")]
+    Tree(cmd::tree_cmd::Args),
+    /// Find entities that contain some text, in any table
+    #[command(name="search")]
+    Search(cmd::search_cmd::Args),
+    /// Check and repair the database of the workspace
+    #[command(name="db")]
+    Db(cmd::db_cmd::Args),
+    /// Show the stored blobs and delete the ones nothing references
+    #[command(name="blobs")]
+    Blobs(cmd::blobs_cmd::Args),
     /// Include entities in the scope
     #[command(name="scope")]
     Scope(cmd::scope_cmd::Args),
@@ -77,6 +139,12 @@ pub enum SubCommand {
     /// Manage workspaces
     #[command(name="workspace")]
     Workspace(cmd::workspace_cmd::Args),
+    /// Upgrade the database of a workspace to the current schema
+    #[command(name="migrate")]
+    Migrate(cmd::migrate_cmd::Args),
+    /// Measure database operations in a temporary workspace
+    #[command(name="bench")]
+    Bench(cmd::bench_cmd::Args),
     /// Calendar
     #[command(name="cal")]
     Cal(cmd::cal_cmd::Args),
@@ -89,9 +157,33 @@ pub enum SubCommand {
     /// Export a workspace for external processing
     #[command(name="export")]
     Export(cmd::export_cmd::Args),
+    /// Import entities produced by other tools
+    #[command(name="import")]
+    Import(cmd::import_cmd::Args),
+    /// Manage the keyring
+    #[command(name="keyring")]
+    Keyring(cmd::keyring_cmd::Args),
+    /// Manage saved filters of a workspace
+    #[command(name="view")]
+    View(cmd::view_cmd::Args),
+    /// Verify the files and signature of an export manifest
+    #[command(name="verify")]
+    Verify(cmd::verify_cmd::Args),
     /// Show statistics about your current workspace
     #[command(name="stats")]
     Stats(cmd::stats_cmd::Args),
+    /// Serve the workspace as a read-only json api
+    #[command(name="serve")]
+    Serve(cmd::serve_cmd::Args),
+    /// Show when entities have been added, grouped by day
+    #[command(name="timeline")]
+    Timeline(cmd::timeline_cmd::Args),
+    /// Run modules on a schedule, see `scheduler status` for the last runs
+    #[command(name="scheduler")]
+    Scheduler(cmd::scheduler_cmd::Args),
+    /// Compare the workspace with a backup or an earlier point in time
+    #[command(name="diff")]
+    Diff(cmd::diff_cmd::Args),
     /// Run a lua repl
     #[command(name="repl")]
     Repl,
@@ -140,33 +232,81 @@ pub struct Run {
 pub struct Sandbox {
     /// This value is only used for process listings
     _label: String,
+    /// The syscall filter to install, selected by the parent
+    #[arg(long, default_value="network")]
+    pub profile: sandbox::Profile,
 }
 
 #[derive(Debug, Parser)]
 pub struct Login {
-}
-
-#[derive(Debug, Parser)]
-pub struct New {
-    /// Path to the new file
-    pub path: String,
+    /// Login to this registry instead of the default one
+    #[arg(long="registry")]
+    pub registry: Option<String>,
 }
 
 #[derive(Debug, Parser)]
 pub struct Publish {
+    /// Publish to this registry instead of the default one
+    #[arg(long="registry")]
+    pub registry: Option<String>,
     /// The scripts to publish
     #[arg(required = true)]
     pub paths: Vec<String>,
 }
 
+/// A module from the registry or a git repository
+#[derive(Debug, Clone, PartialEq)]
+pub enum Package {
+    Registry(ModuleID),
+    Git(GitSource),
+}
+
+impl FromStr for Package {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Package> {
+        if s.starts_with("git+") {
+            s.parse().map(Package::Git)
+        } else {
+            s.parse().map(Package::Registry)
+                .map_err(|err| format_err!("{}", err))
+        }
+    }
+}
+
+impl fmt::Display for Package {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Package::Registry(module) => write!(w, "{}", module),
+            Package::Git(source) => write!(w, "{}", source),
+        }
+    }
+}
+
 #[derive(Debug, Parser)]
 pub struct Install {
-    /// The script to install
-    pub module: ModuleID,
+    /// The script to install, either author/name from the registry or
+    /// git+https://github.com/org/repo[#ref][:path]
+    #[arg(required_unless_present="locked")]
+    pub module: Option<Package>,
     /// Specify the version, defaults to the latest version
     pub version: Option<String>,
+    /// Install the exact modules of a lockfile
+    #[arg(long="locked", conflicts_with_all=["module", "version"])]
+    pub locked: Option<PathBuf>,
     #[arg(short = 'f', long="force")]
     pub force: bool,
+    /// Accept the capabilities of the module without asking
+    #[arg(short = 'y', long="yes")]
+    pub yes: bool,
+    /// Install the module even if its signature doesn't match the pinned key
+    /// of the author
+    #[arg(long="insecure-skip-verify")]
+    pub insecure_skip_verify: bool,
+    /// Install from this registry, by default the configured registries are
+    /// asked in order
+    #[arg(long="registry")]
+    pub registry: Option<String>,
 }
 
 #[derive(Debug, Parser)]
@@ -174,8 +314,36 @@ pub struct Search {
     /// Only show modules that aren't installed yet
     #[arg(long="new")]
     pub new: bool,
-    /// The search query
-    pub query: String,
+    /// Only show modules that add this type of entity, eg. email
+    #[arg(long="emits")]
+    pub emits: Option<String>,
+    /// Only show modules that read this type of entity, eg. subdomain
+    #[arg(long="source")]
+    pub source: Option<String>,
+    /// Only show modules published by this author
+    #[arg(long="author")]
+    pub author: Option<String>,
+    /// Order the results by downloads or by the latest release
+    #[arg(long="sort", value_enum)]
+    pub sort: Option<SearchSort>,
+    /// Print the results as json, one line per module
+    #[arg(long="json")]
+    pub json: bool,
+    /// Search this registry instead of the default one
+    #[arg(long="registry")]
+    pub registry: Option<String>,
+    /// The search query, may be omitted if a filter is used
+    #[arg(required_unless_present_any=["emits", "source", "author"])]
+    pub query: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct Rate {
+    /// The module to rate
+    pub module: ModuleID,
+    /// Between 1 and 5 stars
+    #[arg(value_parser=clap::value_parser!(u8).range(1..=5))]
+    pub stars: u8,
 }
 
 /// Generate shell completions
@@ -4,30 +4,45 @@ use std::path::PathBuf;
 use std::thread;
 use std::time::Duration;
 use crate::api::Client;
-use crate::config::Config;
+use crate::config::{Config, Registry};
 use crate::paths;
 use crate::term;
 
 
-fn path() -> Result<PathBuf> {
+/// Every registry has its own session, the default one keeps the old path
+fn path(registry: &Registry) -> Result<PathBuf> {
     let path = paths::sn0int_dir()?;
-    Ok(path.join("auth"))
+    if registry.is_default() {
+        Ok(path.join("auth"))
+    } else {
+        Ok(path.join(format!("auth-{}", registry.name)))
+    }
 }
 
-pub fn load_token() -> Result<String> {
-    let session = fs::read_to_string(path()?)?;
+pub fn load_token(registry: &Registry) -> Result<String> {
+    let session = fs::read_to_string(path(registry)?)?;
     Ok(session.trim().to_string())
 }
 
-pub fn save_token(session: &str) -> Result<()> {
-    fs::write(path()?, format!("{}\n", session))?;
+/// Load the session of a registry, or explain how to get one
+pub fn load_session(registry: &Registry) -> Result<String> {
+    load_token(registry).map_err(|_| if registry.is_default() {
+        format_err!("Failed to load auth token, login first")
+    } else {
+        format_err!("Failed to load auth token for {:?}, login with --registry {} first", registry.name, registry.name)
+    })
+}
+
+pub fn save_token(registry: &Registry, session: &str) -> Result<()> {
+    fs::write(path(registry)?, format!("{}\n", session))?;
     Ok(())
 }
 
-pub fn run_login(config: &Config) -> Result<()> {
-    let mut client = Client::new(config)?;
+pub fn run_login(config: &Config, registry: Option<&str>) -> Result<()> {
+    let registry = config.registry(registry)?;
+    let mut client = Client::with_server(config, &registry.url)?;
 
-    if let Ok(session) = load_token() {
+    if let Ok(session) = load_token(&registry) {
         client.authenticate(session);
         if let Ok(user) = client.verify_session() {
             term::info(&format!("Logged in as {:?}", user));
@@ -37,7 +52,7 @@ pub fn run_login(config: &Config) -> Result<()> {
 
     let session = Client::random_session();
     client.authenticate(session.clone());
-    let url = format!("{}/auth/{}", config.core.registry, session);
+    let url = format!("{}/auth/{}", registry.url, session);
 
     term::success(&format!("Opening url: {}", url));
     opener::open(url)?;
@@ -46,7 +61,7 @@ pub fn run_login(config: &Config) -> Result<()> {
         thread::sleep(Duration::from_secs(5));
 
         if let Ok(user) = client.verify_session() {
-            save_token(&session)?;
+            save_token(&registry, &session)?;
             term::info(&format!("Logged in as {:?}", user));
             return Ok(());
         }
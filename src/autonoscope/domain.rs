@@ -69,10 +69,10 @@ impl AutoRule<NewSubdomain> for DomainRule {
     }
 }
 
-impl AutoRule<Url> for DomainRule {
-    #[inline]
-    fn matches(&self, url: &Url) -> Result<bool> {
-        let url = url.value.parse::<url::Url>()?;
+impl DomainRule {
+    /// Urls are matched by their domain, urls with an ip address never match
+    pub fn matches_url(&self, url: &str) -> Result<bool> {
+        let url = url.parse::<url::Url>()?;
         if let Some(domain) = url.domain() {
             self.matches(domain)
         } else {
@@ -81,15 +81,17 @@ impl AutoRule<Url> for DomainRule {
     }
 }
 
+impl AutoRule<Url> for DomainRule {
+    #[inline]
+    fn matches(&self, url: &Url) -> Result<bool> {
+        self.matches_url(&url.value)
+    }
+}
+
 impl AutoRule<NewUrl> for DomainRule {
     #[inline]
     fn matches(&self, url: &NewUrl) -> Result<bool> {
-        let url = url.value.parse::<url::Url>()?;
-        if let Some(domain) = url.domain() {
-            self.matches(domain)
-        } else {
-            Ok(false)
-        }
+        self.matches_url(&url.value)
     }
 }
 
@@ -0,0 +1,107 @@
+use crate::errors::*;
+use crate::autonoscope::{Autonoscope, DomainRule, ToRule, AutoRule, RulePrecision};
+use crate::models::*;
+use std::convert::TryFrom;
+
+/// Matches the domain of an email address, a rule for `example.com` also
+/// matches `john@mail.example.com`
+#[derive(Debug, PartialEq)]
+pub struct EmailRule {
+    value: String,
+    domain: DomainRule,
+}
+
+impl ToString for EmailRule {
+    fn to_string(&self) -> String {
+        self.value.clone()
+    }
+}
+
+impl TryFrom<&str> for EmailRule {
+    type Error = Error;
+
+    fn try_from(rule: &str) -> Result<EmailRule> {
+        let domain = rule.strip_prefix('@').unwrap_or(rule);
+        if domain.contains('@') {
+            bail!("Email rules take the domain of the address, like example.com");
+        }
+
+        Ok(EmailRule {
+            value: rule.to_string(),
+            domain: DomainRule::try_from(domain)?,
+        })
+    }
+}
+
+impl TryFrom<Autonoscope> for EmailRule {
+    type Error = Error;
+
+    #[inline]
+    fn try_from(rule: Autonoscope) -> Result<EmailRule> {
+        EmailRule::try_from(rule.value.as_str())
+    }
+}
+
+impl AutoRule<Email> for EmailRule {
+    #[inline]
+    fn matches(&self, email: &Email) -> Result<bool> {
+        self.matches(email.value.as_str())
+    }
+}
+
+impl AutoRule<NewEmail> for EmailRule {
+    #[inline]
+    fn matches(&self, email: &NewEmail) -> Result<bool> {
+        self.matches(email.value.as_str())
+    }
+}
+
+impl AutoRule<str> for EmailRule {
+    fn matches(&self, email: &str) -> Result<bool> {
+        if let Some((_, domain)) = email.rsplit_once('@') {
+            self.domain.matches(domain)
+        } else {
+            Ok(false)
+        }
+    }
+}
+
+impl RulePrecision for EmailRule {
+    #[inline]
+    fn precision(&self) -> usize {
+        self.domain.precision()
+    }
+}
+
+impl ToRule for EmailRule {
+    fn to_rule(&self) -> (&'static str, String) {
+        ("email", self.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_email_rule_equals() {
+        let rule = EmailRule::try_from("example.com").unwrap();
+        assert!(rule.matches("john@example.com").unwrap());
+        assert!(!rule.matches("john@example.com.evil.com").unwrap());
+        assert!(!rule.matches("example.com").unwrap());
+        assert_eq!(rule.precision(), 2);
+    }
+
+    #[test]
+    fn test_email_rule_subdomain() {
+        let rule = EmailRule::try_from("@example.com").unwrap();
+        assert!(rule.matches("john@mail.example.com").unwrap());
+        assert!(!rule.matches("john@gmail.com").unwrap());
+    }
+
+    #[test]
+    fn test_email_rule_invalid() {
+        assert!(EmailRule::try_from("john@example.com").is_err());
+    }
+}
@@ -0,0 +1,212 @@
+use crate::autonoscope::{RuleSet, RuleType};
+use crate::errors::*;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+
+/// A rule in an exported rule set
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleEntry {
+    #[serde(rename="type")]
+    pub object: String,
+    pub value: String,
+    pub scoped: bool,
+}
+
+impl RuleEntry {
+    pub fn new(object: &str, value: &str, scoped: bool) -> RuleEntry {
+        RuleEntry {
+            object: object.to_string(),
+            value: value.to_string(),
+            scoped,
+        }
+    }
+
+    /// The value is stored like the rule displays it, `192.0.2.1` becomes
+    /// `192.0.2.1/32`
+    fn validate(&mut self) -> Result<()> {
+        let object = self.object.parse::<RuleType>()
+            .map_err(|_| format_err!("Unknown rule type {:?}", self.object))?;
+        self.value = RuleSet::normalize(&object, &self.value)?;
+        Ok(())
+    }
+}
+
+/// All rules of the set, in the order they are evaluated
+pub fn export_rules(rules: &RuleSet) -> Vec<RuleEntry> {
+    rules.rules()
+        .into_iter()
+        .map(|(object, value, scoped)| RuleEntry::new(object, &value, scoped))
+        .collect()
+}
+
+/// Lines without a type are guessed from their value, like the lists of
+/// domains and networks a scope is usually defined with
+fn parse_line(line: &str) -> Result<RuleEntry> {
+    let (scoped, value) = if let Some(value) = line.strip_prefix('-') {
+        (false, value.trim())
+    } else {
+        (true, line.strip_prefix('+').unwrap_or(line).trim())
+    };
+
+    let entry = if value.parse::<ipnetwork::IpNetwork>().is_ok() {
+        RuleEntry::new("ip", value, scoped)
+    } else if value.contains("://") {
+        RuleEntry::new("url", value, scoped)
+    } else if value.starts_with('@') {
+        RuleEntry::new("email", value, scoped)
+    } else {
+        // a domain rule already covers every subdomain
+        let value = value.strip_prefix("*.").unwrap_or(value);
+        if value.chars().any(|c| c.is_whitespace() || "/:@*?#".contains(c)) {
+            bail!("Not a domain, network, url or email: {:?}", value);
+        }
+        RuleEntry::new("domain", value, scoped)
+    };
+    Ok(entry)
+}
+
+/// Read the rules of an export, or a list with one domain, network, url or
+/// `@domain` of an email address per line. Lines starting with `-` are out of
+/// scope, every rule is validated.
+pub fn parse_rules(text: &str) -> Result<Vec<RuleEntry>> {
+    if text.trim_start().starts_with('[') {
+        let mut entries = serde_json::from_str::<Vec<RuleEntry>>(text)
+            .context("Failed to parse rules")?;
+        for (i, entry) in entries.iter_mut().enumerate() {
+            entry.validate()
+                .context(format!("Rule #{} is invalid", i + 1))?;
+        }
+        Ok(entries)
+    } else {
+        let mut entries = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let entry = parse_line(line)
+                .and_then(|mut entry| entry.validate().map(|_| entry))
+                .context(format!("Line {} is invalid", i + 1))?;
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportPlan {
+    /// Rules that don't exist yet
+    pub added: Vec<RuleEntry>,
+    /// Rules that exist already with the same scope
+    pub duplicates: Vec<RuleEntry>,
+    /// Rules that exist already with the opposite scope
+    pub conflicts: Vec<RuleEntry>,
+}
+
+impl ImportPlan {
+    /// Compare the imported rules with the existing ones. A rule that's in
+    /// the import twice with a different scope can't be resolved.
+    pub fn new(rules: &RuleSet, entries: Vec<RuleEntry>) -> Result<ImportPlan> {
+        let existing = export_rules(rules)
+            .into_iter()
+            .map(|entry| ((entry.object, entry.value), entry.scoped))
+            .collect::<HashMap<_, _>>();
+
+        let mut plan = ImportPlan::default();
+        let mut seen = HashMap::new();
+        for entry in entries {
+            let key = (entry.object.clone(), entry.value.clone());
+            match seen.insert(key.clone(), entry.scoped) {
+                Some(scoped) if scoped != entry.scoped => bail!("{} rule {:?} is both in and out of scope", entry.object, entry.value),
+                Some(_) => {
+                    plan.duplicates.push(entry);
+                    continue;
+                },
+                None => (),
+            }
+
+            match existing.get(&key) {
+                None => plan.added.push(entry),
+                Some(scoped) if *scoped == entry.scoped => plan.duplicates.push(entry),
+                Some(_) => plan.conflicts.push(entry),
+            }
+        }
+        Ok(plan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::autonoscope::{DomainRule, IpRule, Rule};
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_parse_text() {
+        let entries = parse_rules("# scope of the engagement\n+example.com\n*.example.org\n-dev.example.com\n\n10.0.0.0/8\n- 192.0.2.1\nhttps://example.net/app/\n-@gmail.com\n").unwrap();
+        assert_eq!(entries, vec![
+            RuleEntry::new("domain", "example.com", true),
+            RuleEntry::new("domain", "example.org", true),
+            RuleEntry::new("domain", "dev.example.com", false),
+            RuleEntry::new("ip", "10.0.0.0/8", true),
+            RuleEntry::new("ip", "192.0.2.1/32", false),
+            RuleEntry::new("url", "https://example.net/app/", true),
+            RuleEntry::new("email", "@gmail.com", false),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        let err = parse_rules("example.com\nhttps://\n").unwrap_err();
+        assert_eq!(err.to_string(), "Line 2 is invalid");
+        let err = parse_rules("example.com\n\nexample .com\n").unwrap_err();
+        assert_eq!(err.to_string(), "Line 3 is invalid");
+        let err = parse_rules(r#"[{"type":"regex","value":"^dev-(","scoped":false}]"#).unwrap_err();
+        assert_eq!(err.to_string(), "Rule #1 is invalid");
+        let err = parse_rules(r#"[{"type":"asn","value":"1337","scoped":false}]"#).unwrap_err();
+        assert_eq!(err.to_string(), "Rule #1 is invalid");
+    }
+
+    #[test]
+    fn test_export_import() {
+        let set = RuleSet {
+            domains: vec![
+                Rule::new(DomainRule::try_from("example.com").unwrap(), true),
+            ],
+            ips: vec![
+                Rule::new(IpRule::try_from("10.0.0.0/8").unwrap(), false),
+            ],
+            urls: vec![],
+            emails: vec![],
+            regexes: vec![],
+        };
+        let json = serde_json::to_string(&export_rules(&set)).unwrap();
+        assert_eq!(json, r#"[{"type":"domain","value":"example.com","scoped":true},{"type":"ip","value":"10.0.0.0/8","scoped":false}]"#);
+
+        let entries = parse_rules("example.com\n+10.0.0.0/8\nexample.org\nexample.org\n").unwrap();
+        let plan = ImportPlan::new(&set, entries).unwrap();
+        assert_eq!(plan, ImportPlan {
+            added: vec![RuleEntry::new("domain", "example.org", true)],
+            duplicates: vec![
+                RuleEntry::new("domain", "example.com", true),
+                RuleEntry::new("domain", "example.org", true),
+            ],
+            conflicts: vec![RuleEntry::new("ip", "10.0.0.0/8", true)],
+        });
+
+        let entries = parse_rules("example.org\n-example.org\n").unwrap();
+        assert!(ImportPlan::new(&set, entries).is_err());
+    }
+
+    #[test]
+    fn test_add_rules() {
+        let mut db = crate::db::Database::memory().unwrap();
+        db.autonoscope_add_rule(&RuleType::Ip, "10.0.0.0/8", true).unwrap();
+        let entries = parse_rules("-10.0.0.0/8\nexample.com\n").unwrap();
+        db.autonoscope_add_rules(&entries).unwrap();
+        assert_eq!(db.autonoscope_rules(), vec![
+            ("domain", "example.com".to_string(), true),
+            ("ip", "10.0.0.0/8".to_string(), false),
+        ]);
+    }
+}
@@ -48,17 +48,30 @@ impl AutoRule<NewIpAddr> for IpRule {
     }
 }
 
+impl IpRule {
+    /// Ports are stored with their protocol, like `tcp/192.0.2.1:443`
+    pub fn matches_port(&self, port: &str) -> Result<bool> {
+        let addr = port.split_once('/')
+            .map(|(_, addr)| addr)
+            .unwrap_or(port)
+            .parse::<net::SocketAddr>()?;
+        self.matches(&addr.ip())
+    }
+
+    pub fn matches_netblock(&self, netblock: &str) -> Result<bool> {
+        match_netblock_str(&self.network, netblock)
+    }
+}
+
 impl AutoRule<Port> for IpRule {
     fn matches(&self, port: &Port) -> Result<bool> {
-        let addr = port.value.parse::<net::SocketAddr>()?;
-        self.matches(&addr.ip())
+        self.matches_port(&port.value)
     }
 }
 
 impl AutoRule<NewPort> for IpRule {
     fn matches(&self, port: &NewPort) -> Result<bool> {
-        let addr = port.value.parse::<net::SocketAddr>()?;
-        self.matches(&addr.ip())
+        self.matches_port(&port.value)
     }
 }
 
@@ -234,4 +247,12 @@ mod tests {
             unscoped: false,
         }).unwrap());
     }
+
+    #[test]
+    fn test_ip_rule_port() {
+        let rule = IpRule::try_from("192.0.2.0/24").unwrap();
+        assert!(rule.matches_port("tcp/192.0.2.1:443").unwrap());
+        assert!(rule.matches_port("192.0.2.1:443").unwrap());
+        assert!(!rule.matches_port("udp/[2001:db8::1]:53").unwrap());
+    }
 }
@@ -1,4 +1,4 @@
-use crate::db::DatabaseSock;
+use crate::db::{DatabaseSock, Family};
 use crate::errors::*;
 use diesel::prelude::*;
 use crate::schema::*;
@@ -10,8 +10,14 @@ use std::str::FromStr;
 
 mod domain;
 pub use self::domain::DomainRule;
+mod import;
+pub use self::import::{export_rules, parse_rules, ImportPlan, RuleEntry};
+mod email;
+pub use self::email::EmailRule;
 mod ip;
 pub use self::ip::IpRule;
+mod regex;
+pub use self::regex::RegexRule;
 mod url;
 pub use self::url::UrlRule;
 
@@ -37,13 +43,18 @@ pub struct RuleSet {
     domains: Vec<Rule<DomainRule>>,
     ips: Vec<Rule<IpRule>>,
     urls: Vec<Rule<UrlRule>>,
+    emails: Vec<Rule<EmailRule>>,
+    regexes: Vec<Rule<RegexRule>>,
 }
 
-#[inline(always)]
-fn sort_precision_desc<T: RulePrecision>(a: &T, b: &T) -> Ordering {
+/// Most specific first, a noscope rule goes before a scope rule that's just as
+/// specific. The value is only compared so the order is always the same.
+fn sort_precision_desc<T: ToRule + RulePrecision>(a: &Rule<T>, b: &Rule<T>) -> Ordering {
     a.precision()
         .cmp(&b.precision())
         .reverse()
+        .then(a.scoped.cmp(&b.scoped))
+        .then_with(|| a.to_rule().1.cmp(&b.to_rule().1))
 }
 
 impl RuleSet {
@@ -59,9 +70,19 @@ impl RuleSet {
         &self.urls
     }
 
+    pub fn emails(&self) -> &[Rule<EmailRule>] {
+        &self.emails
+    }
+
+    pub fn regexes(&self) -> &[Rule<RegexRule>] {
+        &self.regexes
+    }
+
     pub fn load(db: &DatabaseSock) -> Result<Self> {
         use crate::schema::autonoscope::dsl::*;
-        let rules = autonoscope.load::<Autonoscope>(db)?;
+        let rules = autonoscope
+            .order(id.asc())
+            .load::<Autonoscope>(db)?;
 
         let mut set = RuleSet::default();
         for rule in rules {
@@ -71,6 +92,8 @@ impl RuleSet {
                 RuleType::Domain => set.domains.push(Rule::new(rule.try_into()?, is_scoped)),
                 RuleType::Ip => set.ips.push(Rule::new(rule.try_into()?, is_scoped)),
                 RuleType::Url => set.urls.push(Rule::new(rule.try_into()?, is_scoped)),
+                RuleType::Email => set.emails.push(Rule::new(rule.try_into()?, is_scoped)),
+                RuleType::Regex => set.regexes.push(Rule::new(rule.try_into()?, is_scoped)),
             }
         }
         set.sort_rules();
@@ -82,6 +105,8 @@ impl RuleSet {
         self.domains.sort_by(sort_precision_desc);
         self.ips.sort_by(sort_precision_desc);
         self.urls.sort_by(sort_precision_desc);
+        self.emails.sort_by(sort_precision_desc);
+        self.regexes.sort_by(sort_precision_desc);
     }
 
     pub fn add_rule(&mut self, db: &DatabaseSock, object: &RuleType, value: &str, scoped: bool) -> Result<()> {
@@ -106,12 +131,59 @@ impl RuleSet {
                 self.db_add(db, &rule)?;
                 self.urls.push(rule);
             },
+            RuleType::Email => {
+                let rule = EmailRule::try_from(value)?;
+                let rule = Rule::new(rule, scoped);
+                self.db_add(db, &rule)?;
+                self.emails.push(rule);
+            },
+            RuleType::Regex => {
+                let rule = RegexRule::try_from(value)?;
+                let rule = Rule::new(rule, scoped);
+                self.db_add(db, &rule)?;
+                self.regexes.push(rule);
+            },
         }
         self.sort_rules();
 
         Ok(())
     }
 
+    /// Parse a rule without adding it, the value is returned the way the
+    /// rule is stored
+    pub fn normalize(object: &RuleType, value: &str) -> Result<String> {
+        let (_, value) = match object {
+            RuleType::Domain => DomainRule::try_from(value)?.to_rule(),
+            RuleType::Ip => IpRule::try_from(value)?.to_rule(),
+            RuleType::Url => UrlRule::try_from(value)?.to_rule(),
+            RuleType::Email => EmailRule::try_from(value)?.to_rule(),
+            RuleType::Regex => RegexRule::try_from(value)?.to_rule(),
+        };
+        Ok(value)
+    }
+
+    /// Add or replace rules without updating a loaded rule set, the rules
+    /// need to be reloaded afterwards
+    pub fn write_rules(db: &DatabaseSock, rules: &[RuleEntry]) -> Result<()> {
+        use crate::schema::autonoscope::dsl::*;
+        for rule in rules {
+            let rule_type = rule.object.parse::<RuleType>()?;
+            let rule_value = Self::normalize(&rule_type, &rule.value)?;
+            diesel::delete(autonoscope
+                .filter(object.eq(rule_type.as_str()))
+                .filter(value.eq(&rule_value)))
+                .execute(db)?;
+            diesel::insert_into(autonoscope)
+                .values(NewAutonoscope {
+                    object: rule_type.as_str().to_string(),
+                    value: rule_value,
+                    scoped: rule.scoped,
+                })
+                .execute(db)?;
+        }
+        Ok(())
+    }
+
     fn db_add<I: Into<NewAutonoscope>>(&mut self, db: &DatabaseSock, rule: I) -> Result<()> {
         use crate::schema::autonoscope::dsl::*;
         diesel::insert_into(autonoscope)
@@ -134,6 +206,14 @@ impl RuleSet {
                 self.urls.retain(|x| x.to_string().as_str() != rule);
                 self.db_delete(db, obj, rule)?;
             },
+            RuleType::Email => {
+                self.emails.retain(|x| x.to_string().as_str() != rule);
+                self.db_delete(db, obj, rule)?;
+            },
+            RuleType::Regex => {
+                self.regexes.retain(|x| x.to_string().as_str() != rule);
+                self.db_delete(db, obj, rule)?;
+            },
         }
         Ok(())
     }
@@ -149,18 +229,20 @@ impl RuleSet {
 
     pub fn rules(&self) -> Vec<(&'static str, String, bool)> {
         let mut rules = Vec::new();
+        Self::push_rules_display(&mut rules, &self.regexes);
         Self::push_rules_display(&mut rules, &self.domains);
         Self::push_rules_display(&mut rules, &self.ips);
         Self::push_rules_display(&mut rules, &self.urls);
+        Self::push_rules_display(&mut rules, &self.emails);
         rules
     }
 
     pub fn is_empty(&self) -> bool {
-        self.domains.is_empty() && self.ips.is_empty() && self.urls.is_empty()
+        self.domains.is_empty() && self.ips.is_empty() && self.urls.is_empty() && self.emails.is_empty() && self.regexes.is_empty()
     }
 
     pub fn len(&self) -> usize {
-        self.domains.len() + self.ips.len() + self.urls.len()
+        self.domains.len() + self.ips.len() + self.urls.len() + self.emails.len() + self.regexes.len()
     }
 
     #[inline]
@@ -172,44 +254,106 @@ impl RuleSet {
     }
 
     pub fn matches(&self, object: &Insert) -> Result<bool> {
-        let scoped = match object {
-            Insert::Domain(domain) => Self::matches_any(&self.domains, domain)?,
-            Insert::Subdomain(subdomain) => Self::matches_any(&self.domains, subdomain)?,
-            Insert::IpAddr(ip_addr) => Self::matches_any(&self.ips, ip_addr)?,
-            Insert::Url(url) => {
-                if let Some(result) = Self::matches_any(&self.domains, url)? {
-                    Some(result)
-                } else {
-                    Self::matches_any(&self.urls, url)?
-                }
-            },
-            // Insert::Email(email) => unimplemented!(),
+        let scoped = self.matching_rule(object)?
+            .map(|(_, scoped)| scoped);
+        Ok(scoped.unwrap_or(true))
+    }
+
+    /// The first rule that matches the object and whether it's scoped by it
+    pub fn matching_rule(&self, object: &Insert) -> Result<Option<((&'static str, String), bool)>> {
+        let (family, value) = match object {
+            Insert::Domain(domain) => (Family::Domain, &domain.value),
+            Insert::Subdomain(subdomain) => (Family::Subdomain, &subdomain.value),
+            Insert::IpAddr(ip_addr) => (Family::Ipaddr, &ip_addr.value),
+            Insert::Url(url) => (Family::Url, &url.value),
+            Insert::Email(email) => (Family::Email, &email.value),
             // Insert::Account(account) => unimplemented!(),
-            Insert::Port(port) => Self::matches_any(&self.ips, port)?,
-            Insert::Netblock(netblock) => Self::matches_any(&self.ips, netblock)?,
-            _ => None,
+            Insert::Port(port) => (Family::Port, &port.value),
+            Insert::Netblock(netblock) => (Family::Netblock, &netblock.value),
+            _ => return Ok(None),
         };
-        Ok(scoped.unwrap_or(true))
+        self.matching_value(&family, value)
+    }
+
+    /// The first rule that matches the value of an entity. Within a rule
+    /// type, the most specific rule wins and noscope wins over scope if
+    /// they're equally specific. Across rule types noscope wins as well,
+    /// otherwise a matching regex rule wins over the other rule types.
+    pub fn matching_value(&self, family: &Family, value: &str) -> Result<Option<((&'static str, String), bool)>> {
+        let regex = if matches!(family, Family::Domain | Family::Subdomain | Family::Ipaddr | Family::Url | Family::Email) {
+            self.matching_regex(value)?
+        } else {
+            None
+        };
+        let rule = self.matching_typed(family, value)?;
+
+        Ok(deny_first(regex, rule))
+    }
+
+    /// The first rule for the type of an entity that matches its value
+    fn matching_typed(&self, family: &Family, value: &str) -> Result<Option<((&'static str, String), bool)>> {
+        match family {
+            Family::Domain | Family::Subdomain => Self::matches_any(&self.domains, value),
+            Family::Ipaddr => Self::matches_any(&self.ips, value),
+            Family::Url => {
+                let domain = Self::first_match(&self.domains, |rule| rule.matches_url(value))?;
+                let url = Self::matches_any(&self.urls, value)?;
+                Ok(deny_first(domain, url))
+            },
+            Family::Email => Self::matches_any(&self.emails, value),
+            Family::Port => Self::first_match(&self.ips, |rule| rule.matches_port(value)),
+            Family::Netblock => Self::first_match(&self.ips, |rule| rule.matches_netblock(value)),
+            _ => Ok(None),
+        }
+    }
+
+    /// The first regex rule that matches the value of an entity
+    pub fn matching_regex(&self, value: &str) -> Result<Option<((&'static str, String), bool)>> {
+        Self::matches_any(&self.regexes, value)
     }
 
-    fn matches_any<T1, T2>(rules: &[Rule<T1>], object: &T2) -> Result<Option<bool>>
+    fn matches_any<T1, T2>(rules: &[Rule<T1>], object: &T2) -> Result<Option<((&'static str, String), bool)>>
         where T1: AutoRule<T2>,
             T1: ToRule,
+            T2: ?Sized,
+    {
+        Self::first_match(rules, |rule| rule.matches(object))
+    }
+
+    fn first_match<T, F>(rules: &[Rule<T>], matches: F) -> Result<Option<((&'static str, String), bool)>>
+        where T: ToRule,
+            F: Fn(&T) -> Result<bool>,
     {
         for rule in rules {
-            if rule.matches(object)? {
-                return Ok(Some(rule.scoped));
+            if matches(rule)? {
+                return Ok(Some((rule.to_rule(), rule.scoped)));
             }
         }
         Ok(None)
     }
 }
 
+/// The matches of two rule types, a noscope match wins over a scope match
+/// and the first one wins otherwise
+fn deny_first<T>(first: Option<(T, bool)>, second: Option<(T, bool)>) -> Option<(T, bool)> {
+    match (first, second) {
+        (Some((_, true)), Some(second @ (_, false))) => Some(second),
+        (first, second) => first.or(second),
+    }
+}
+
+/// How a rule is referred to in the provenance of an entity, like `domain:example.com`
+pub fn rule_label(rule: &(&str, String)) -> String {
+    format!("{}:{}", rule.0, rule.1)
+}
+
 #[derive(Debug, Clone)]
 pub enum RuleType {
     Domain,
     Ip,
     Url,
+    Email,
+    Regex,
 }
 
 impl RuleType {
@@ -218,6 +362,8 @@ impl RuleType {
             RuleType::Domain => "domain",
             RuleType::Ip => "ip",
             RuleType::Url => "url",
+            RuleType::Email => "email",
+            RuleType::Regex => "regex",
         }
     }
 
@@ -227,6 +373,8 @@ impl RuleType {
                 RuleType::Domain.as_str(),
                 RuleType::Ip.as_str(),
                 RuleType::Url.as_str(),
+                RuleType::Email.as_str(),
+                RuleType::Regex.as_str(),
             ];
         }
 
@@ -242,6 +390,8 @@ impl FromStr for RuleType {
             "domain" => Ok(RuleType::Domain),
             "ip" => Ok(RuleType::Ip),
             "url" => Ok(RuleType::Url),
+            "email" => Ok(RuleType::Email),
+            "regex" => Ok(RuleType::Regex),
             _ => bail!("unknown rule type"),
         }
     }
@@ -316,6 +466,8 @@ mod tests {
             ],
             ips: vec![],
             urls: vec![],
+            emails: vec![],
+            regexes: vec![],
         };
         set.sort_rules();
         assert_eq!(set, RuleSet {
@@ -326,6 +478,8 @@ mod tests {
             ],
             ips: vec![],
             urls: vec![],
+            emails: vec![],
+            regexes: vec![],
         });
     }
 
@@ -339,6 +493,8 @@ mod tests {
                 Rule::new(IpRule::try_from("10.5.6.0/24").unwrap(), true),
             ],
             urls: vec![],
+            emails: vec![],
+            regexes: vec![],
         };
         set.sort_rules();
         // TODO: add ipv6
@@ -350,6 +506,8 @@ mod tests {
                 Rule::new(IpRule::try_from("0.0.0.0/0").unwrap(), true),
             ],
             urls: vec![],
+            emails: vec![],
+            regexes: vec![],
         });
     }
 
@@ -363,6 +521,8 @@ mod tests {
                 Rule::new(UrlRule::try_from("https://example.com/").unwrap(), true),
                 Rule::new(UrlRule::try_from("https://example.com/foo/bar/?asdf=1").unwrap(), true),
             ],
+            emails: vec![],
+            regexes: vec![],
         };
         set.sort_rules();
         assert_eq!(set, RuleSet {
@@ -373,6 +533,162 @@ mod tests {
                 Rule::new(UrlRule::try_from("http://example.com/foo/").unwrap(), true),
                 Rule::new(UrlRule::try_from("https://example.com/").unwrap(), true),
             ],
+            emails: vec![],
+            regexes: vec![],
+        });
+    }
+
+    #[test]
+    fn test_matching_rule() {
+        let set = RuleSet {
+            domains: vec![
+                Rule::new(DomainRule::try_from("example.com").unwrap(), false),
+            ],
+            ips: vec![],
+            urls: vec![],
+            emails: vec![],
+            regexes: vec![],
+        };
+        let domain = |value: &str| Insert::Domain(NewDomain {
+            value: value.to_string(),
+            unscoped: false,
+        });
+
+        let rule = set.matching_rule(&domain("example.com")).unwrap();
+        assert_eq!(rule, Some((("domain", "example.com".to_string()), false)));
+        assert_eq!(rule_label(&rule.unwrap().0), "domain:example.com");
+        assert!(!set.matches(&domain("example.com")).unwrap());
+
+        assert_eq!(set.matching_rule(&domain("example.org")).unwrap(), None);
+        assert!(set.matches(&domain("example.org")).unwrap());
+    }
+
+    #[test]
+    fn test_matching_rule_regex() {
+        let set = RuleSet {
+            domains: vec![
+                Rule::new(DomainRule::try_from("example.com").unwrap(), true),
+            ],
+            ips: vec![],
+            urls: vec![],
+            emails: vec![],
+            regexes: vec![
+                Rule::new(RegexRule::try_from(r"^dev-.*\.example\.com$").unwrap(), false),
+                Rule::new(RegexRule::try_from(r"^10\.").unwrap(), false),
+            ],
+        };
+        let subdomain = |value: &str| Insert::Subdomain(NewSubdomain {
+            domain_id: 1,
+            value: value.to_string(),
+            resolvable: None,
+            unscoped: false,
+        });
+
+        // the regex rule wins over the domain rule
+        let rule = set.matching_rule(&subdomain("dev-1.example.com")).unwrap();
+        assert_eq!(rule, Some((("regex", r"^dev-.*\.example\.com$".to_string()), false)));
+        let rule = set.matching_rule(&subdomain("www.example.com")).unwrap();
+        assert_eq!(rule, Some((("domain", "example.com".to_string()), true)));
+
+        let ipaddr = Insert::IpAddr(NewIpAddr {
+            family: "4".to_string(),
+            value: "10.0.0.1".to_string(),
+            unscoped: false,
+            continent: None,
+            continent_code: None,
+            country: None,
+            country_code: None,
+            city: None,
+            latitude: None,
+            longitude: None,
+            asn: None,
+            as_org: None,
+            description: None,
+            reverse_dns: None,
         });
+        assert!(!set.matches(&ipaddr).unwrap());
+    }
+
+    #[test]
+    fn test_matching_rule_noscope_wins_over_regex() {
+        let set = RuleSet {
+            domains: vec![
+                Rule::new(DomainRule::try_from("example.com").unwrap(), false),
+            ],
+            ips: vec![],
+            urls: vec![],
+            emails: vec![],
+            regexes: vec![
+                Rule::new(RegexRule::try_from("^prod-").unwrap(), true),
+            ],
+        };
+        let rule = set.matching_value(&Family::Subdomain, "prod-1.example.com").unwrap();
+        assert_eq!(rule, Some((("domain", "example.com".to_string()), false)));
+        let rule = set.matching_value(&Family::Subdomain, "prod-1.example.org").unwrap();
+        assert_eq!(rule, Some((("regex", "^prod-".to_string()), true)));
+    }
+
+    #[test]
+    fn test_matching_rule_email() {
+        let set = RuleSet {
+            domains: vec![],
+            ips: vec![],
+            urls: vec![],
+            emails: vec![
+                Rule::new(EmailRule::try_from("gmail.com").unwrap(), false),
+                Rule::new(EmailRule::try_from("target.com").unwrap(), true),
+            ],
+            regexes: vec![],
+        };
+        let email = |value: &str| Insert::Email(NewEmail {
+            value: value.to_string(),
+            displayname: None,
+            valid: None,
+            unscoped: false,
+            syntax_valid: None,
+        });
+
+        assert!(!set.matches(&email("john@gmail.com")).unwrap());
+        let rule = set.matching_rule(&email("john@mail.target.com")).unwrap();
+        assert_eq!(rule, Some((("email", "target.com".to_string()), true)));
+        assert_eq!(set.matching_rule(&email("john@example.com")).unwrap(), None);
+    }
+
+    #[test]
+    fn test_rule_sort_ties() {
+        let mut set = RuleSet {
+            domains: vec![],
+            ips: vec![
+                Rule::new(IpRule::try_from("10.0.0.0/8").unwrap(), true),
+                Rule::new(IpRule::try_from("10.0.0.1/8").unwrap(), false),
+                Rule::new(IpRule::try_from("10.5.0.0/16").unwrap(), true),
+            ],
+            urls: vec![],
+            emails: vec![],
+            regexes: vec![
+                Rule::new(RegexRule::try_from("^dev-").unwrap(), true),
+                Rule::new(RegexRule::try_from("example").unwrap(), true),
+                Rule::new(RegexRule::try_from(r"\.example\.com$").unwrap(), false),
+            ],
+        };
+        set.sort_rules();
+        assert_eq!(set.ips, vec![
+            Rule::new(IpRule::try_from("10.5.0.0/16").unwrap(), true),
+            Rule::new(IpRule::try_from("10.0.0.1/8").unwrap(), false),
+            Rule::new(IpRule::try_from("10.0.0.0/8").unwrap(), true),
+        ]);
+
+        // the most specific rule wins, noscope wins if they're equally specific
+        let rule = set.matching_value(&Family::Ipaddr, "10.5.1.1").unwrap();
+        assert_eq!(rule, Some((("ip", "10.5.0.0/16".to_string()), true)));
+        let rule = set.matching_value(&Family::Ipaddr, "10.6.1.1").unwrap();
+        assert_eq!(rule, Some((("ip", "10.0.0.1/8".to_string()), false)));
+        let rule = set.matching_value(&Family::Port, "tcp/10.6.1.1:443").unwrap();
+        assert_eq!(rule, Some((("ip", "10.0.0.1/8".to_string()), false)));
+
+        let rule = set.matching_value(&Family::Subdomain, "dev-1.example.com").unwrap();
+        assert_eq!(rule, Some((("regex", r"\.example\.com$".to_string()), false)));
+        let rule = set.matching_value(&Family::Subdomain, "dev-1.example.org").unwrap();
+        assert_eq!(rule, Some((("regex", "^dev-".to_string()), true)));
     }
 }
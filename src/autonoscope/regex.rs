@@ -0,0 +1,89 @@
+use crate::errors::*;
+use crate::autonoscope::{Autonoscope, ToRule, AutoRule, RulePrecision};
+use regex::Regex;
+use std::convert::TryFrom;
+
+/// Matches the value of an entity with a regular expression, use `^` and `$`
+/// to match the whole value
+#[derive(Debug)]
+pub struct RegexRule {
+    pattern: String,
+    regex: Regex,
+}
+
+impl PartialEq for RegexRule {
+    fn eq(&self, other: &RegexRule) -> bool {
+        self.pattern == other.pattern
+    }
+}
+
+impl ToString for RegexRule {
+    fn to_string(&self) -> String {
+        self.pattern.clone()
+    }
+}
+
+impl TryFrom<&str> for RegexRule {
+    type Error = Error;
+
+    fn try_from(rule: &str) -> Result<RegexRule> {
+        let regex = Regex::new(rule)
+            .context("Invalid regex")?;
+        Ok(RegexRule {
+            pattern: rule.to_string(),
+            regex,
+        })
+    }
+}
+
+impl TryFrom<Autonoscope> for RegexRule {
+    type Error = Error;
+
+    #[inline]
+    fn try_from(rule: Autonoscope) -> Result<RegexRule> {
+        RegexRule::try_from(rule.value.as_str())
+    }
+}
+
+impl AutoRule<str> for RegexRule {
+    #[inline]
+    fn matches(&self, value: &str) -> Result<bool> {
+        Ok(self.regex.is_match(value))
+    }
+}
+
+// there's no meaningful way to compare patterns, if several regex rules match
+// the noscope rules win
+impl RulePrecision for RegexRule {
+    #[inline]
+    fn precision(&self) -> usize {
+        0
+    }
+}
+
+impl ToRule for RegexRule {
+    fn to_rule(&self) -> (&'static str, String) {
+        ("regex", self.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_regex_rule() {
+        let rule = RegexRule::try_from(r"^dev-.*\.example\.com$").unwrap();
+        assert!(rule.matches("dev-1.example.com").unwrap());
+        assert!(rule.matches("dev-api.staging.example.com").unwrap());
+        assert!(!rule.matches("www.example.com").unwrap());
+        assert!(!rule.matches("dev-1.example.com.evil.com").unwrap());
+    }
+
+    #[test]
+    fn test_regex_rule_invalid() {
+        let err = RegexRule::try_from("^dev-(.*$").unwrap_err();
+        assert_eq!(err.to_string(), "Invalid regex");
+    }
+}
@@ -0,0 +1,221 @@
+use crate::db::{Database, Filter, Table};
+use crate::db::batch::Batch;
+use crate::errors::*;
+use crate::models::*;
+use diesel::prelude::*;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// The expression used to measure filter compilation, close to what's typed
+/// into `select` and used by modules
+const FILTER: &[&str] = &["where", "value", "like", "%7%", "and", "seen_count", ">=", "1", "or", "seen_last>=30d"];
+
+#[derive(Debug, Serialize)]
+pub struct Timing {
+    pub name: &'static str,
+    pub iterations: usize,
+    #[serde(serialize_with = "serialize_nanos")]
+    pub elapsed: Duration,
+}
+
+fn serialize_nanos<S: serde::Serializer>(d: &Duration, s: S) -> std::result::Result<S::Ok, S::Error> {
+    s.serialize_u128(d.as_nanos())
+}
+
+impl Timing {
+    pub fn per_iteration(&self) -> Duration {
+        self.elapsed / self.iterations.max(1) as u32
+    }
+}
+
+pub fn measure<F: FnMut() -> Result<()>>(name: &'static str, iterations: usize, mut f: F) -> Result<Timing> {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f()?;
+    }
+    Ok(Timing {
+        name,
+        iterations,
+        elapsed: start.elapsed(),
+    })
+}
+
+fn domain(i: usize) -> Insert {
+    Insert::Domain(NewDomain {
+        value: format!("bench-{}.example.com", i),
+        unscoped: false,
+    })
+}
+
+/// Insert `rows` new domains, one write transaction each like a module does
+pub fn insert(db: &Database, rows: usize) -> Result<Timing> {
+    let mut i = 0;
+    measure("insert", rows, || {
+        db.insert_generic(domain(i))?;
+        i += 1;
+        Ok(())
+    })
+}
+
+/// Insert domains that already exist, this only bumps their seen fields
+pub fn insert_existing(db: &Database, rows: usize) -> Result<Timing> {
+    let mut i = 0;
+    measure("insert-existing", rows, || {
+        db.insert_generic(domain(i))?;
+        i += 1;
+        Ok(())
+    })
+}
+
+/// A module run that reports the same ipaddr for every event, like every
+/// subdomain resolving to the same address. The writes are batched like in a
+/// run, `cached` decides if the lookups of the run are cached.
+pub fn insert_duplicates(db: &Database, events: usize, cached: bool) -> Result<Timing> {
+    let ipaddr = Insert::IpAddr(NewIpAddr {
+        family: "4".to_string(),
+        value: "192.0.2.1".to_string(),
+        continent: None,
+        continent_code: None,
+        country: None,
+        country_code: None,
+        city: None,
+        latitude: None,
+        longitude: None,
+        asn: None,
+        as_org: None,
+        description: None,
+        reverse_dns: Some("bench.example.com".to_string()),
+        unscoped: false,
+    });
+    let name = if cached { "insert-duplicates" } else { "insert-duplicates-uncached" };
+
+    let mut batch = Batch::new(500);
+    db.cache_lookups(cached);
+    let timing = measure(name, events, || {
+        batch.row(db)?;
+        db.insert_generic(ipaddr.clone())?;
+        if batch.is_due() {
+            batch.commit(db)?;
+        }
+        Ok(())
+    });
+    let timing = timing.and_then(|timing| {
+        batch.commit(db)?;
+        Ok(timing)
+    });
+    db.cache_lookups(false);
+    timing
+}
+
+pub fn select(db: &Database, iterations: usize) -> Result<Timing> {
+    let filter = Filter::parse_for(Table::Domains, &to_args(FILTER))?;
+    measure("select", iterations, || {
+        db.filter::<Domain>(&filter)?;
+        Ok(())
+    })
+}
+
+/// Move a tenth of the domains out of scope and back again
+pub fn scope(db: &Database, iterations: usize) -> Result<Timing> {
+    let filter = Filter::parse_for(Table::Domains, &to_args(&["where", "value", "like", "%7.example.com"]))?;
+    measure("scope", iterations, || {
+        db.set_scoped::<Domain>(&filter, false)?;
+        db.set_scoped::<Domain>(&filter, true)?;
+        Ok(())
+    })
+}
+
+/// Give every domain a subdomain, in one statement since this only prepares
+/// the workspace
+pub fn add_subdomains(db: &Database) -> Result<()> {
+    diesel::sql_query("INSERT INTO subdomains (domain_id, value, unscoped) SELECT id, 'www.' || value, 0 FROM domains")
+        .execute(db.db())?;
+    Ok(())
+}
+
+/// Look up the subdomains of a domain like `related-to` does, this goes
+/// through the foreign key of subdomains
+pub fn related(db: &Database, iterations: usize) -> Result<Timing> {
+    let mut i = 0;
+    measure("related", iterations, || {
+        let filter = Filter::any().and_related(Table::Subdomains, &format!("domain:bench-{}.example.com", i))?;
+        db.filter::<Subdomain>(&filter)?;
+        i += 1;
+        Ok(())
+    })
+}
+
+/// Delete domains one by one, the delete cascades to their subdomains
+pub fn delete(db: &Database, iterations: usize) -> Result<Timing> {
+    let mut i = 0;
+    measure("delete", iterations, || {
+        let filter = Filter::parse_for(Table::Domains, &to_args(&["where", "value", "=", &format!("bench-{}.example.com", i)]))?;
+        db.delete::<Domain>(&filter)?;
+        i += 1;
+        Ok(())
+    })
+}
+
+pub fn compile_filter(iterations: usize) -> Result<Timing> {
+    let args = to_args(FILTER);
+    measure("filter-compile", iterations, || {
+        Filter::parse_for(Table::Domains, &args)?;
+        Ok(())
+    })
+}
+
+fn to_args(args: &[&str]) -> Vec<String> {
+    args.iter()
+        .map(|x| x.to_string())
+        .collect()
+}
+
+/// A workload that touches the hot paths of the database, the database is
+/// expected to be empty
+pub fn run(db: &Database, rows: usize) -> Result<Vec<Timing>> {
+    Ok(vec![
+        insert(db, rows)?,
+        insert_existing(db, rows)?,
+        select(db, 100)?,
+        scope(db, 10)?,
+        add_subdomains(db).and_then(|_| related(db, 100))?,
+        delete(db, 10)?,
+        compile_filter(10_000)?,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let db = Database::memory().unwrap();
+        let timings = run(&db, 20).unwrap();
+        let names = timings.iter()
+            .map(|t| (t.name, t.iterations))
+            .collect::<Vec<_>>();
+        assert_eq!(names, &[
+            ("insert", 20),
+            ("insert-existing", 20),
+            ("select", 100),
+            ("scope", 10),
+            ("related", 100),
+            ("delete", 10),
+            ("filter-compile", 10_000),
+        ]);
+        assert_eq!(db.list::<Domain>().unwrap().len(), 10);
+        assert_eq!(db.list::<Subdomain>().unwrap().len(), 10);
+    }
+
+    #[test]
+    fn test_insert_duplicates() {
+        let db = Database::memory().unwrap();
+        for cached in [true, false] {
+            insert_duplicates(&db, 600, cached).unwrap();
+        }
+        let ipaddrs = db.list::<IpAddr>().unwrap();
+        assert_eq!(ipaddrs.len(), 1);
+        assert_eq!(ipaddrs[0].seen_count, 1200);
+    }
+}
@@ -1,13 +1,22 @@
+use crate::db::Database;
+use crate::encryption::WorkspaceKey;
 use crate::errors::*;
 use crate::paths;
 use crate::worker::{EventWithCallback, Event2};
 use crate::workspaces::Workspace;
 
 use bytes::Bytes;
-use std::fs;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Text};
+use serde::Serialize;
+use sodiumoxide::crypto::secretbox;
+use std::collections::HashMap;
+use std::fs::{self, File};
 use std::path::{Path, PathBuf};
+use std::process;
 use std::result;
 use std::sync::mpsc;
+use std::time::{Duration, SystemTime};
 
 pub use sn0int_std::blobs::Blob;
 
@@ -20,8 +29,59 @@ impl EventWithCallback for Blob {
     }
 }
 
+/// The columns that contain the id of a blob, as table and column
+const BLOB_COLUMNS: &[(&str, &str)] = &[
+    ("images", "value"),
+];
+
+/// Unreferenced blobs that have been written recently are kept, a module may
+/// have stored the blob without adding the entity that uses it yet
+const GC_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+#[derive(QueryableByName)]
+struct Reference {
+    #[sql_type = "Text"]
+    blob: String,
+    #[sql_type = "BigInt"]
+    count: i64,
+}
+
+/// How often every blob is referenced by the database
+pub fn references(db: &Database) -> Result<HashMap<String, usize>> {
+    let mut references = HashMap::new();
+    for (table, column) in BLOB_COLUMNS {
+        let query = format!("SELECT {1} AS blob, COUNT(*) AS count FROM {0} WHERE {1} IS NOT NULL GROUP BY {1}", table, column);
+        for reference in diesel::sql_query(query).load::<Reference>(db.db())? {
+            *references.entry(reference.blob).or_insert(0) += reference.count as usize;
+        }
+    }
+    Ok(references)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BlobUsage {
+    pub id: String,
+    pub size: u64,
+    pub references: usize,
+    #[serde(skip)]
+    modified: SystemTime,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct GcReport {
+    /// The blobs that aren't referenced by anything
+    pub unreferenced: Vec<BlobUsage>,
+    /// Unreferenced blobs that are kept because they have just been written
+    pub recent: usize,
+    /// The bytes that are freed by deleting the unreferenced blobs
+    pub reclaimable: u64,
+    pub deleted: bool,
+}
+
 pub struct BlobStorage {
     path: PathBuf,
+    /// Blobs of encrypted workspaces are stored as nonce and ciphertext
+    key: Option<secretbox::Key>,
 }
 
 impl BlobStorage {
@@ -29,9 +89,26 @@ impl BlobStorage {
     pub fn new<I: Into<PathBuf>>(path: I) -> BlobStorage {
         BlobStorage {
             path: path.into(),
+            key: None,
         }
     }
 
+    /// Encrypt the blobs with the key of the workspace, if it's encrypted
+    #[inline]
+    pub fn with_key(mut self, key: Option<&WorkspaceKey>) -> BlobStorage {
+        self.key = key.map(|key| key.blobs().clone());
+        self
+    }
+
+    /// The size of a blob on disk
+    fn stored_len(&self, len: usize) -> u64 {
+        let overhead = match self.key {
+            Some(_) => secretbox::NONCEBYTES + secretbox::MACBYTES,
+            None => 0,
+        };
+        (len + overhead) as u64
+    }
+
     #[inline]
     pub fn workspace(workspace: &Workspace) -> Result<BlobStorage> {
         let path = paths::blobs_dir(workspace)?;
@@ -55,23 +132,65 @@ impl BlobStorage {
         let path = self.join(id)?;
 
         debug!("Loading blob from {:?}", path);
-        let bytes = fs::read(path)
+        let mut bytes = fs::read(path)
             .context("Failed to read blob")?;
 
+        if let Some(key) = &self.key {
+            let nonce = bytes.get(..secretbox::NONCEBYTES)
+                .and_then(secretbox::Nonce::from_slice)
+                .ok_or_else(|| format_err!("Blob is too short to be encrypted"))?;
+            bytes = secretbox::open(&bytes[secretbox::NONCEBYTES..], &nonce, key)
+                .map_err(|_| format_err!("Failed to decrypt blob"))?;
+        }
+
         Ok(Blob {
             id: id.to_string(),
             bytes: Bytes::from(bytes),
         })
     }
 
-    pub fn save(&self, blob: &Blob) -> Result<()> {
+    /// The stored bytes still match the id of the blob
+    fn is_intact(&self, id: &str) -> bool {
+        self.load(id)
+            .map(|blob| Blob::hash(&blob.bytes) == id)
+            .unwrap_or(false)
+    }
+
+    /// The id of a blob is the hash of its bytes, if the same bytes have been
+    /// stored before the existing blob is used. Returns false in that case.
+    pub fn save(&self, blob: &Blob) -> Result<bool> {
         let path = self.join(&blob.id)?;
 
-        debug!("Writing blob to {:?}", path);
-        fs::write(path, &blob.bytes)
+        if let Ok(md) = fs::metadata(&path) {
+            if md.len() == self.stored_len(blob.bytes.len()) && self.is_intact(&blob.id) {
+                debug!("Blob already exists: {:?}", path);
+                // the blob is about to be referenced again, keep it out of gc
+                File::options()
+                    .write(true)
+                    .open(&path)
+                    .and_then(|f| f.set_modified(SystemTime::now()))
+                    .context("Failed to update blob")?;
+                return Ok(false);
+            }
+        }
+
+        // the blob is written next to its final path and then moved there, so
+        // it's never visible with partial content
+        let tmp = self.path.join(format!("{}.{}.tmp", blob.id, process::id()));
+        debug!("Writing blob to {:?}", tmp);
+        if let Some(key) = &self.key {
+            let nonce = secretbox::gen_nonce();
+            let mut bytes = nonce.as_ref().to_vec();
+            bytes.extend(secretbox::seal(&blob.bytes, &nonce, key));
+            fs::write(&tmp, bytes)
+        } else {
+            fs::write(&tmp, &blob.bytes)
+        }.context("Failed to write blob")?;
+        debug!("Moving blob to {:?}", path);
+        fs::rename(&tmp, &path)
             .context("Failed to write blob")?;
 
-        Ok(())
+        Ok(true)
     }
 
     pub fn delete(&self, id: &str) -> Result<()> {
@@ -82,6 +201,8 @@ impl BlobStorage {
         Ok(())
     }
 
+    /// The ids of the stored blobs, files that are still being written are
+    /// skipped
     pub fn list(&self) -> Result<Vec<String>> {
         let mut blobs = Vec::new();
         for entry in fs::read_dir(&self.path)? {
@@ -89,6 +210,9 @@ impl BlobStorage {
                 .file_name()
                 .into_string()
                 .map_err(|_| format_err!("Invalid filename"))?;
+            if blob.ends_with(".tmp") {
+                continue;
+            }
             blobs.push(blob);
         }
         Ok(blobs)
@@ -101,6 +225,51 @@ impl BlobStorage {
             .context("Failed to stat blob")?;
         Ok(md.len())
     }
+
+    /// The size and references of every stored blob, sorted by id
+    pub fn usage(&self, db: &Database) -> Result<Vec<BlobUsage>> {
+        let references = references(db)?;
+        let mut usage = Vec::new();
+        for id in self.list()? {
+            let md = fs::metadata(self.join(&id)?)
+                .context("Failed to stat blob")?;
+            usage.push(BlobUsage {
+                references: references.get(&id).copied().unwrap_or(0),
+                id,
+                size: md.len(),
+                modified: md.modified()?,
+            });
+        }
+        usage.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(usage)
+    }
+
+    /// Find the blobs that nothing references anymore, they are only deleted
+    /// if `delete` is set
+    pub fn gc(&self, db: &Database, delete: bool) -> Result<GcReport> {
+        let now = SystemTime::now();
+        let mut report = GcReport::default();
+        for blob in self.usage(db)? {
+            if blob.references > 0 {
+                continue;
+            }
+            let age = now.duration_since(blob.modified).unwrap_or_default();
+            if age < GC_GRACE_PERIOD {
+                report.recent += 1;
+                continue;
+            }
+            report.reclaimable += blob.size;
+            report.unreferenced.push(blob);
+        }
+
+        if delete {
+            for blob in &report.unreferenced {
+                self.delete(&blob.id)?;
+            }
+            report.deleted = true;
+        }
+        Ok(report)
+    }
 }
 
 
@@ -138,6 +307,103 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_blobstorage_save_existing() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let s = BlobStorage::new(dir.path());
+
+        let (_, blob) = blob();
+        assert!(s.save(&blob).unwrap());
+        assert!(!s.save(&blob).unwrap());
+        assert_eq!(s.list().unwrap(), &[blob.id]);
+    }
+
+    #[test]
+    fn test_blobstorage_save_corrupted() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let s = BlobStorage::new(dir.path());
+
+        let (bytes, blob) = blob();
+        // same length as the blob, but different content
+        fs::write(s.join(&blob.id).unwrap(), b"qwer").unwrap();
+        assert!(s.save(&blob).unwrap());
+        assert_eq!(s.load(&blob.id).unwrap().bytes, bytes);
+        assert_eq!(s.list().unwrap(), &[blob.id]);
+    }
+
+    #[test]
+    fn test_blobstorage_list_skips_tmp() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let s = BlobStorage::new(dir.path());
+
+        let (_, blob) = blob();
+        fs::write(dir.path().join(format!("{}.1337.tmp", blob.id)), b"as").unwrap();
+        assert!(s.list().unwrap().is_empty());
+    }
+
+    fn age(s: &BlobStorage, id: &str) {
+        let two_hours_ago = SystemTime::now() - Duration::from_secs(2 * 60 * 60);
+        File::options().write(true).open(s.join(id).unwrap()).unwrap()
+            .set_modified(two_hours_ago).unwrap();
+    }
+
+    #[test]
+    fn test_blobstorage_gc() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let s = BlobStorage::new(dir.path());
+        let db = Database::memory().unwrap();
+
+        let blobs = ["asdf", "referenced", "new"].iter()
+            .map(|x| Blob::create(Bytes::from(x.as_bytes().to_vec())))
+            .collect::<Vec<_>>();
+        for blob in &blobs {
+            s.save(blob).unwrap();
+        }
+        age(&s, &blobs[0].id);
+        age(&s, &blobs[1].id);
+        diesel::sql_query("INSERT INTO images (value, unscoped) VALUES (?, 0)")
+            .bind::<Text, _>(&blobs[1].id)
+            .execute(db.db())
+            .unwrap();
+
+        let usage = s.usage(&db).unwrap();
+        let referenced = usage.iter().find(|x| x.id == blobs[1].id).unwrap();
+        assert_eq!((referenced.size, referenced.references), (10, 1));
+
+        let report = s.gc(&db, false).unwrap();
+        assert_eq!(report.unreferenced.iter().map(|x| x.id.as_str()).collect::<Vec<_>>(), &[blobs[0].id.as_str()]);
+        assert_eq!((report.recent, report.reclaimable, report.deleted), (1, 4, false));
+        assert_eq!(s.list().unwrap().len(), 3);
+
+        // storing a blob again keeps it from being deleted
+        s.save(&blobs[0]).unwrap();
+        assert!(s.gc(&db, true).unwrap().unreferenced.is_empty());
+        age(&s, &blobs[0].id);
+        assert!(s.gc(&db, true).unwrap().deleted);
+        assert!(s.load(&blobs[0].id).is_err());
+        assert_eq!(s.list().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_blobstorage_encrypted() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let (_, key) = crate::encryption::tests::create("correct horse");
+        let s = BlobStorage::new(dir.path()).with_key(Some(&key));
+
+        let (bytes, blob) = blob();
+        assert!(s.save(&blob).unwrap());
+        assert!(!s.save(&blob).unwrap());
+        assert_eq!(s.load(&blob.id).unwrap(), blob);
+
+        let stored = fs::read(s.join(&blob.id).unwrap()).unwrap();
+        assert_eq!(stored.len() as u64, s.stored_len(bytes.len()));
+        assert!(!stored.windows(bytes.len()).any(|x| x == &bytes[..]));
+
+        let (_, other) = crate::encryption::tests::create("battery staple");
+        let s = BlobStorage::new(dir.path()).with_key(Some(&other));
+        assert!(s.load(&blob.id).is_err());
+    }
+
     #[test]
     fn test_path_validation() {
         let dir = tempfile::tempdir().expect("tempdir");
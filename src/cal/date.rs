@@ -2,8 +2,8 @@ use crate::errors::*;
 
 use chrono::Utc;
 use chrono::prelude::*;
-use crate::cal::{ActivityGrade, DateArg};
-use crate::models::*;
+use crate::cal::{self, ActivityGrade, DateArg, BOLD, RESET};
+use crate::fmt::colors;
 use std::collections::HashMap;
 use std::collections::VecDeque;
 use std::fmt::Write;
@@ -64,7 +64,7 @@ fn days_in_month(year: i32, month: u32) -> i64 {
     end.signed_duration_since(start).num_days()
 }
 
-fn setup_graph_map(events: &[Activity]) -> (HashMap<NaiveDate, u64>, u64) {
+fn setup_graph_map(events: &[NaiveDateTime]) -> (HashMap<NaiveDate, u64>, u64) {
     debug!("Found {} events in selected range", events.len());
 
     let mut cur = None;
@@ -73,7 +73,7 @@ fn setup_graph_map(events: &[Activity]) -> (HashMap<NaiveDate, u64>, u64) {
 
     let mut map = HashMap::new();
     for event in events {
-        let date = event.time.date();
+        let date = event.date();
         if let Some(cur) = cur.as_mut() {
             if date == *cur {
                 ctr += 1;
@@ -109,15 +109,18 @@ pub struct DateContext {
     events: HashMap<NaiveDate, u64>,
     max: u64,
     today: NaiveDate,
+    colors: bool,
 }
 
 impl DateContext {
-    pub fn new(events: &[Activity], today: NaiveDate) -> DateContext {
+    /// The times of the events need to be sorted
+    pub fn new(events: &[NaiveDateTime], today: NaiveDate) -> DateContext {
         let (events, max) = setup_graph_map(events);
         DateContext {
             events,
             max,
             today,
+            colors: colors::enabled(),
         }
     }
 
@@ -131,6 +134,11 @@ impl DateContext {
         self.today < *date
     }
 
+    /// The number of events on a day, days without any events are zero
+    pub fn count_for_day(&self, date: &NaiveDate) -> u64 {
+        self.events.get(date).copied().unwrap_or(0)
+    }
+
     fn activity_for_day(&self, date: &NaiveDate) -> ActivityGrade {
         if let Some(events) = self.events.get(date) {
             ActivityGrade::from_ratio(*events, self.max)
@@ -237,17 +245,18 @@ impl DateSpec {
 
                     if !ctx.is_future(&date) {
                         let activity = ctx.activity_for_day(&date);
-                        w.push_str(activity.as_term_str());
+                        w.push_str(activity.as_term_str(ctx.colors));
                     }
 
                     if ctx.is_today(&date) {
-                        w.push_str("\x1b[1m#");
+                        w.push_str(cal::style(ctx.colors, BOLD));
+                        w.push('#');
                     } else {
                         w.push(' ');
                     }
                     write!(w, "{:2}", cur_day).expect("out of memory");
                     week_written += 3;
-                    w.push_str("\x1b[0m");
+                    w.push_str(cal::style(ctx.colors, RESET));
 
                     // detect end of the week
                     if cur_week_day == Weekday::Sat {
@@ -298,6 +307,7 @@ mod tests {
             events: HashMap::new(),
             max: 0,
             today: NaiveDate::from_ymd_opt(2020, 5, 30).unwrap(),
+            colors: true,
         }
     }
 
@@ -326,11 +336,26 @@ mod tests {
             events,
             max: 0,
             today: NaiveDate::from_ymd_opt(2020, 6, 6).unwrap(),
+            colors: true,
         };
         let grade = ctx.activity_for_day(&NaiveDate::from_ymd_opt(2020, 6, 6).unwrap());
         assert_eq!(grade, ActivityGrade::None);
     }
 
+    #[test]
+    fn test_count_for_day() {
+        let events = ["2020-05-01T10:00:00", "2020-05-01T23:59:59", "2020-05-03T00:00:00"]
+            .iter()
+            .map(|time| time.parse::<NaiveDateTime>().unwrap())
+            .collect::<Vec<_>>();
+        let ctx = DateContext::new(&events, NaiveDate::from_ymd_opt(2020, 5, 30).unwrap());
+        assert_eq!(ctx.count_for_day(&NaiveDate::from_ymd_opt(2020, 5, 1).unwrap()), 2);
+        assert_eq!(ctx.count_for_day(&NaiveDate::from_ymd_opt(2020, 5, 2).unwrap()), 0);
+        assert_eq!(ctx.count_for_day(&NaiveDate::from_ymd_opt(2020, 5, 3).unwrap()), 1);
+        assert_eq!(ctx.activity_for_day(&NaiveDate::from_ymd_opt(2020, 5, 2).unwrap()), ActivityGrade::None);
+        assert_eq!(ctx.activity_for_day(&NaiveDate::from_ymd_opt(2020, 5, 3).unwrap()), ActivityGrade::Two);
+    }
+
     #[test]
     fn test_datespec_year_month() {
         let ds = DateSpec::YearMonth((2020, 5));
@@ -345,6 +370,18 @@ mod tests {
  31\u{1b}[0m                  ");
     }
 
+    #[test]
+    fn test_datespec_year_month_without_colors() {
+        let ds = DateSpec::YearMonth((2020, 5));
+        let ctx = DateContext {
+            colors: false,
+            ..context()
+        };
+        let out = ds.to_term_string(&ctx);
+        assert!(!out.contains('\x1b'), "{:?}", out);
+        assert!(out.ends_with(" 24 25 26 27 28 29#30\n 31                  "), "{:?}", out);
+    }
+
     #[test]
     fn test_datespec_year_month_ends_on_sat() {
         let ds = DateSpec::YearMonth((2020, 10));
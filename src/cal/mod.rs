@@ -46,8 +46,24 @@ pub enum ActivityGrade {
     Four,
 }
 
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// The escape code if colors are enabled, nothing otherwise
+#[inline]
+fn style(colors: bool, code: &'static str) -> &'static str {
+    if colors {
+        code
+    } else {
+        ""
+    }
+}
+
 impl ActivityGrade {
-    fn as_term_str(&self) -> &'static str {
+    fn as_term_str(&self, colors: bool) -> &'static str {
+        if !colors {
+            return "";
+        }
         match self {
             ActivityGrade::None => "\x1b[97m\x1b[48;5;238m",
             ActivityGrade::One => "\x1b[30m\x1b[48;5;148m",
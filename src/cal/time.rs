@@ -2,8 +2,8 @@ use crate::errors::*;
 
 use chrono::Duration;
 use chrono::prelude::*;
-use crate::cal::{ActivityGrade, DateArg};
-use crate::models::*;
+use crate::cal::{self, ActivityGrade, DateArg, BOLD, RESET};
+use crate::fmt::colors;
 use std::collections::HashMap;
 use std::fmt::Write;
 
@@ -17,7 +17,7 @@ fn round_to_slice(time: &NaiveDateTime, slice_duration: u32) -> NaiveDateTime {
     date.and_hms_opt(hour, slice, 0).expect("Invalid hour/min/sec")
 }
 
-fn setup_graph_map(events: &[Activity], slice_duration: u32) -> (HashMap<NaiveDateTime, u64>, u64) {
+fn setup_graph_map(events: &[NaiveDateTime], slice_duration: u32) -> (HashMap<NaiveDateTime, u64>, u64) {
     debug!("Found {} events in selected range", events.len());
 
     let mut cur = None;
@@ -26,7 +26,7 @@ fn setup_graph_map(events: &[Activity], slice_duration: u32) -> (HashMap<NaiveDa
 
     let mut map = HashMap::new();
     for event in events {
-        let time = round_to_slice(&event.time, slice_duration);
+        let time = round_to_slice(event, slice_duration);
 
         if let Some(cur) = cur.as_mut() {
             if time == *cur {
@@ -65,10 +65,12 @@ pub struct DateTimeContext {
     now: NaiveDateTime,
     pub slice_width: u32,
     pub slice_duration: u32,
+    colors: bool,
 }
 
 impl DateTimeContext {
-    pub fn new(events: &[Activity], now: NaiveDateTime, slice_width: u32, slice_duration: u32) -> DateTimeContext {
+    /// The times of the events need to be sorted
+    pub fn new(events: &[NaiveDateTime], now: NaiveDateTime, slice_width: u32, slice_duration: u32) -> DateTimeContext {
         let (events, max) = setup_graph_map(events, slice_duration);
         DateTimeContext {
             events,
@@ -76,6 +78,7 @@ impl DateTimeContext {
             now: round_to_slice(&now, slice_duration),
             slice_width,
             slice_duration,
+            colors: colors::enabled(),
         }
     }
 
@@ -136,18 +139,18 @@ impl DateTimeSpec {
         &self.end
     }
 
-    fn push_date(&self, w: &mut String, date: &NaiveDate) {
+    fn push_date(&self, w: &mut String, date: &NaiveDate, colors: bool) {
         let weekday = date.weekday();
         let is_weekend = weekday == Weekday::Sat || weekday == Weekday::Sun;
 
         if is_weekend {
-            w.push_str("\x1b[1m");
+            w.push_str(cal::style(colors, BOLD));
         }
 
         w.push_str(&date.format("%Y-%m-%d (%a) ").to_string());
 
         if is_weekend {
-            w.push_str("\x1b[0m");
+            w.push_str(cal::style(colors, RESET));
         }
     }
 
@@ -170,7 +173,7 @@ impl DateTimeSpec {
         // add days
         let mut date = self.start;
         while date <= self.end {
-            self.push_date(&mut w, &date);
+            self.push_date(&mut w, &date, ctx.colors);
 
             let mut hours = 0;
             let mut mins = 0;
@@ -180,9 +183,9 @@ impl DateTimeSpec {
 
                 if !ctx.is_future(&time) {
                     let activity = ctx.activity_for_slice(&time);
-                    w.push_str(activity.as_term_str());
+                    w.push_str(activity.as_term_str(ctx.colors));
                 } else {
-                    w.push_str("\x1b[0m");
+                    w.push_str(cal::style(ctx.colors, RESET));
                 }
 
                 for _ in 0..ctx.slice_width {
@@ -195,7 +198,7 @@ impl DateTimeSpec {
                     mins = 0;
                 }
             }
-            w.push_str("\x1b[0m");
+            w.push_str(cal::style(ctx.colors, RESET));
 
             if date < self.end {
                 w.push('\n');
@@ -0,0 +1,257 @@
+use crate::errors::*;
+use crate::engine::Module;
+use crate::paths;
+use serde::{Serialize, Deserialize};
+use sn0int_common::metadata::{Capability, Metadata, ALL_CAPABILITIES, DEFAULT_CAPABILITIES};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The capabilities the user confirmed when installing a module from the
+/// registry, `None` means the module didn't declare any and was accepted with
+/// the default capabilities
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AcceptedCapabilities {
+    #[serde(default)]
+    modules: HashMap<String, Option<BTreeSet<Capability>>>,
+    /// Modules that were installed before capabilities existed, they keep
+    /// all capabilities until an update has been reviewed
+    #[serde(default)]
+    legacy: BTreeSet<String>,
+}
+
+/// The accepted set, modules that were accepted without a declaration only
+/// got the default capabilities
+fn accepted_set(accepted: &Option<BTreeSet<Capability>>) -> BTreeSet<Capability> {
+    match accepted {
+        Some(accepted) => accepted.clone(),
+        None => DEFAULT_CAPABILITIES.iter().copied().collect(),
+    }
+}
+
+/// Check if everything in `declared` has been accepted before
+fn covers(accepted: &Option<BTreeSet<Capability>>, declared: Option<&[Capability]>) -> bool {
+    let accepted = accepted_set(accepted);
+    Capability::declared_or_default(declared).iter()
+        .all(|c| accepted.contains(c))
+}
+
+fn join(capabilities: &[Capability]) -> String {
+    capabilities.iter()
+        .map(Capability::as_str)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+pub fn format_list(capabilities: Option<&[Capability]>) -> String {
+    match capabilities {
+        None => format!("undeclared (defaults to {})", join(DEFAULT_CAPABILITIES)),
+        Some([]) => String::from("none"),
+        Some(capabilities) => join(capabilities),
+    }
+}
+
+impl AcceptedCapabilities {
+    #[inline]
+    fn path() -> Result<PathBuf> {
+        let path = paths::sn0int_dir()?;
+        Ok(path.join("capabilities.json"))
+    }
+
+    pub fn load() -> Result<AcceptedCapabilities> {
+        let path = AcceptedCapabilities::path()?;
+        if !path.exists() {
+            let accepted = AcceptedCapabilities::migrate(&paths::module_dir()?)?;
+            accepted.save()?;
+            return Ok(accepted);
+        }
+
+        let data = fs::read(&path)
+            .context("Failed to read accepted capabilities")?;
+        let accepted = serde_json::from_slice(&data)
+            .context("Failed to parse accepted capabilities")?;
+        Ok(accepted)
+    }
+
+    /// Runs once on the first start without a capabilities file, every module
+    /// that is installed already has been written before capabilities existed
+    fn migrate(folder: &Path) -> Result<AcceptedCapabilities> {
+        let mut accepted = AcceptedCapabilities::default();
+
+        for author in fs::read_dir(folder)? {
+            let author = author?;
+            if !author.path().is_dir() {
+                continue;
+            }
+            let author_name = author.file_name()
+                .into_string()
+                .map_err(|_| format_err!("Failed to decode filename"))?;
+
+            for module in fs::read_dir(author.path())? {
+                let module = module?;
+                let module_name = module.file_name()
+                    .into_string()
+                    .map_err(|_| format_err!("Failed to decode filename"))?;
+                if let Some(module_name) = module_name.strip_suffix(".lua") {
+                    accepted.legacy.insert(format!("{}/{}", author_name, module_name));
+                }
+            }
+        }
+
+        Ok(accepted)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let data = serde_json::to_string(&self)?;
+        fs::write(AcceptedCapabilities::path()?, data)
+            .context("Failed to write accepted capabilities")?;
+        Ok(())
+    }
+
+    /// Returns true if installing this code requires a confirmation from the user
+    pub fn needs_review(&self, canonical: &str, metadata: &Metadata) -> bool {
+        match self.modules.get(canonical) {
+            Some(accepted) => !covers(accepted, metadata.capabilities.as_deref()),
+            None => true,
+        }
+    }
+
+    pub fn accept(&mut self, canonical: &str, metadata: &Metadata) {
+        let capabilities = metadata.capabilities.as_ref()
+            .map(|c| c.iter().copied().collect());
+        self.modules.insert(canonical.to_string(), capabilities);
+        self.legacy.remove(canonical);
+    }
+
+    pub fn remove(&mut self, canonical: &str) {
+        self.modules.remove(canonical);
+        self.legacy.remove(canonical);
+    }
+
+    /// Returns true if the module was installed before capabilities existed
+    /// and hasn't been reviewed since
+    pub fn is_legacy(&self, canonical: &str) -> bool {
+        self.legacy.contains(canonical)
+    }
+
+    /// The capabilities a module is allowed to use at runtime
+    ///
+    /// Modules that have not been installed from the registry are limited to
+    /// what they declare, registry modules to what has been accepted as well.
+    /// Modules without a declaration get the default capabilities, unless
+    /// they were installed before capabilities existed.
+    pub fn effective(&self, module: &Module) -> Vec<Capability> {
+        let canonical = module.canonical();
+        let declared = match module.declared_capabilities() {
+            None if self.is_legacy(&canonical) => ALL_CAPABILITIES,
+            declared => Capability::declared_or_default(declared),
+        };
+        match self.modules.get(&canonical) {
+            None => declared.to_vec(),
+            Some(accepted) => {
+                let accepted = accepted_set(accepted);
+                declared.iter()
+                    .filter(|c| accepted.contains(c))
+                    .copied()
+                    .collect()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(capabilities: &str) -> Metadata {
+        format!("-- Description: Hello world\n-- Version: 1.0.0\n{}-- License: WTFPL\n\n", capabilities)
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_review_new_module() {
+        let accepted = AcceptedCapabilities::default();
+        assert!(accepted.needs_review("kpcyrd/ctlogs", &metadata("-- Capabilities: http\n")));
+    }
+
+    #[test]
+    fn test_review_unchanged() {
+        let mut accepted = AcceptedCapabilities::default();
+        accepted.accept("kpcyrd/ctlogs", &metadata("-- Capabilities: http, blob\n"));
+        assert!(!accepted.needs_review("kpcyrd/ctlogs", &metadata("-- Capabilities: http\n")));
+        assert!(!accepted.needs_review("kpcyrd/ctlogs", &metadata("-- Capabilities: blob, http\n")));
+    }
+
+    #[test]
+    fn test_review_grown() {
+        let mut accepted = AcceptedCapabilities::default();
+        accepted.accept("kpcyrd/ctlogs", &metadata("-- Capabilities: http\n"));
+        assert!(accepted.needs_review("kpcyrd/ctlogs", &metadata("-- Capabilities: http, stdin\n")));
+    }
+
+    #[test]
+    fn test_review_undeclared_accepted() {
+        let mut accepted = AcceptedCapabilities::default();
+        accepted.accept("kpcyrd/ctlogs", &metadata(""));
+        assert!(!accepted.needs_review("kpcyrd/ctlogs", &metadata("")));
+        assert!(!accepted.needs_review("kpcyrd/ctlogs", &metadata("-- Capabilities: http\n")));
+        assert!(accepted.needs_review("kpcyrd/ctlogs", &metadata("-- Capabilities: socket\n")));
+    }
+
+    #[test]
+    fn test_review_declared_to_undeclared() {
+        let mut accepted = AcceptedCapabilities::default();
+        accepted.accept("kpcyrd/ctlogs", &metadata("-- Capabilities: http, blob\n"));
+        assert!(!accepted.needs_review("kpcyrd/ctlogs", &metadata("")));
+        accepted.accept("kpcyrd/ctlogs", &metadata("-- Capabilities: blob\n"));
+        assert!(accepted.needs_review("kpcyrd/ctlogs", &metadata("")));
+    }
+
+    fn module(capabilities: &str) -> Module {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ctlogs.lua");
+        let code = format!("-- Description: Hello world\n-- Version: 1.0.0\n{}-- License: WTFPL\n\nfunction run() end\n", capabilities);
+        fs::write(&path, code).unwrap();
+        Module::load(&path, "kpcyrd", "ctlogs", false).unwrap()
+    }
+
+    #[test]
+    fn test_migrate_installed() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("kpcyrd")).unwrap();
+        fs::write(dir.path().join("kpcyrd/ctlogs.lua"), "").unwrap();
+        fs::write(dir.path().join("kpcyrd/README.md"), "").unwrap();
+
+        let accepted = AcceptedCapabilities::migrate(dir.path()).unwrap();
+        assert!(accepted.is_legacy("kpcyrd/ctlogs"));
+        assert!(!accepted.is_legacy("kpcyrd/README"));
+        assert!(accepted.needs_review("kpcyrd/ctlogs", &metadata("")));
+    }
+
+    #[test]
+    fn test_effective_legacy() {
+        let mut accepted = AcceptedCapabilities::default();
+        accepted.legacy.insert("kpcyrd/ctlogs".to_string());
+        assert_eq!(accepted.effective(&module("")), ALL_CAPABILITIES);
+        assert_eq!(accepted.effective(&module("-- Capabilities: blob\n")), &[Capability::Blob]);
+
+        // reviewing an update ends the grace period
+        accepted.accept("kpcyrd/ctlogs", &metadata(""));
+        assert!(!accepted.is_legacy("kpcyrd/ctlogs"));
+        assert_eq!(accepted.effective(&module("")), DEFAULT_CAPABILITIES);
+    }
+
+    #[test]
+    fn test_effective_new_module() {
+        let accepted = AcceptedCapabilities::default();
+        assert_eq!(accepted.effective(&module("")), DEFAULT_CAPABILITIES);
+    }
+
+    #[test]
+    fn test_format_list() {
+        assert_eq!(format_list(None), "undeclared (defaults to http)");
+        assert_eq!(format_list(Some(&[])), "none");
+        assert_eq!(format_list(Some(&[Capability::Http, Capability::Blob])), "http, blob");
+    }
+}
@@ -0,0 +1,164 @@
+use crate::capabilities;
+use sn0int_common::metadata::{Capability, EgressRule, Metadata};
+use std::fmt;
+
+/// A field of the module header that differs between two versions
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub field: &'static str,
+    pub old: String,
+    pub new: String,
+    /// Changes to what a module may access need to be confirmed
+    pub needs_review: bool,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(w, "{}: {} -> {}", self.field, self.old, self.new)
+    }
+}
+
+fn format_list(list: &[String]) -> String {
+    if list.is_empty() {
+        String::from("none")
+    } else {
+        list.join(", ")
+    }
+}
+
+fn format_sorted<T: ToString>(list: &[T]) -> String {
+    let mut list = list.iter()
+        .map(T::to_string)
+        .collect::<Vec<_>>();
+    list.sort();
+    list.dedup();
+    format_list(&list)
+}
+
+/// Check if a new release may use a capability the installed one couldn't
+fn capabilities_widen(old: Option<&[Capability]>, new: Option<&[Capability]>) -> bool {
+    let old = Capability::declared_or_default(old);
+    Capability::declared_or_default(new).iter()
+        .any(|c| !old.contains(c))
+}
+
+/// Check if a rule allows everything another rule allows
+fn rule_covers(old: &EgressRule, new: &EgressRule) -> bool {
+    match (old, new) {
+        _ if old == new => true,
+        (EgressRule::Subdomains(_), EgressRule::Host(host)) => old.matches_host(host),
+        (EgressRule::Subdomains(_), EgressRule::Subdomains(domain)) => old.matches_host(domain),
+        (EgressRule::Network(_, prefix), EgressRule::Network(addr, new_prefix)) => {
+            prefix <= new_prefix && old.matches_addr(addr)
+        },
+        _ => false,
+    }
+}
+
+/// Check if a new release may connect somewhere the installed one couldn't,
+/// a module without a declaration may connect anywhere
+fn egress_widens(old: Option<&[EgressRule]>, new: Option<&[EgressRule]>) -> bool {
+    match (old, new) {
+        (None, _) => false,
+        (Some(_), None) => true,
+        (Some(old), Some(new)) => !new.iter()
+            .all(|n| old.iter().any(|o| rule_covers(o, n))),
+    }
+}
+
+/// Compare the headers of an installed module and a new release, changes
+/// that give the module more access need to be reviewed
+pub fn diff(old: &Metadata, new: &Metadata) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let mut push = |field, old: String, new: String, needs_review| {
+        if old != new {
+            changes.push(Change {
+                field,
+                old,
+                new,
+                needs_review,
+            });
+        }
+    };
+
+    push("description", old.description.clone(), new.description.clone(), false);
+    push("capabilities",
+         capabilities::format_list(old.capabilities.as_deref()),
+         capabilities::format_list(new.capabilities.as_deref()),
+         capabilities_widen(old.capabilities.as_deref(), new.capabilities.as_deref()));
+    push("keyring-access", format_sorted(&old.keyring_access), format_sorted(&new.keyring_access), true);
+    push("emits", format_sorted(&old.emits), format_sorted(&new.emits), false);
+    let egress = |m: &Metadata| m.egress.as_deref()
+        .map(format_sorted)
+        .unwrap_or_else(|| String::from("undeclared"));
+    push("egress", egress(old), egress(new),
+         egress_widens(old.egress.as_deref(), new.egress.as_deref()));
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(extra: &str) -> Metadata {
+        format!("-- Description: Query ct logs\n-- Version: 0.1.0\n-- License: GPL-3.0\n{}\n", extra)
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_diff_unchanged() {
+        let old = metadata("-- Keyring-Access: shodan\n-- Keyring-Access: censys\n");
+        let new = metadata("-- Keyring-Access: censys\n-- Keyring-Access: shodan\n");
+        assert_eq!(diff(&old, &new), Vec::new());
+    }
+
+    #[test]
+    fn test_diff_sensitive() {
+        let old = metadata("-- Capabilities: http\n");
+        let new = metadata("-- Capabilities: http, socket\n-- Keyring-Access: shodan\n-- Emits: subdomain\n");
+        let changes = diff(&old, &new);
+        let fields = changes.iter()
+            .map(|c| (c.field, c.needs_review))
+            .collect::<Vec<_>>();
+        assert_eq!(fields, &[
+            ("capabilities", true),
+            ("keyring-access", true),
+            ("emits", false),
+        ]);
+        assert_eq!(changes[1].to_string(), "keyring-access: none -> shodan");
+    }
+
+    fn reviewed(old: &str, new: &str) -> Vec<(&'static str, bool)> {
+        diff(&metadata(old), &metadata(new)).iter()
+            .map(|c| (c.field, c.needs_review))
+            .collect()
+    }
+
+    #[test]
+    fn test_diff_capabilities() {
+        assert_eq!(reviewed("-- Capabilities: http, socket\n", "-- Capabilities: http\n"),
+                   &[("capabilities", false)]);
+        assert_eq!(reviewed("", "-- Capabilities: http\n"), &[("capabilities", false)]);
+        assert_eq!(reviewed("-- Capabilities: http\n", "-- Capabilities: socket\n"),
+                   &[("capabilities", true)]);
+        assert_eq!(reviewed("-- Capabilities: none\n", ""), &[("capabilities", true)]);
+    }
+
+    #[test]
+    fn test_diff_egress() {
+        assert_eq!(reviewed("-- Egress: *.example.com\n", "-- Egress: api.example.com\n"),
+                   &[("egress", false)]);
+        assert_eq!(reviewed("", "-- Egress: example.com\n"), &[("egress", false)]);
+        assert_eq!(reviewed("-- Egress: 10.0.0.0/8\n", "-- Egress: 10.1.0.0/16\n"),
+                   &[("egress", false)]);
+        assert_eq!(reviewed("-- Egress: example.com\n", "-- Egress: example.com, example.org\n"),
+                   &[("egress", true)]);
+        assert_eq!(reviewed("-- Egress: api.example.com\n", "-- Egress: *.example.com\n"),
+                   &[("egress", true)]);
+        assert_eq!(reviewed("-- Egress: 10.1.0.0/16\n", "-- Egress: 10.0.0.0/8\n"),
+                   &[("egress", true)]);
+        assert_eq!(reviewed("-- Egress: example.com\n", ""), &[("egress", true)]);
+    }
+}
@@ -4,14 +4,14 @@ use crate::cmd::Cmd;
 use crate::errors::*;
 use crate::models::*;
 use crate::shell::Shell;
+use crate::shell::pager::Pager;
 use regex::Regex;
 use std::convert::TryFrom;
-use std::io;
 use std::str::FromStr;
 
 #[derive(Debug, Clone)]
 pub struct TimeSpec {
-    datetime: NaiveDateTime,
+    pub datetime: NaiveDateTime,
 }
 
 impl TimeSpec {
@@ -79,6 +79,9 @@ pub struct Args {
     /// Only query events that are tied to a location
     #[arg(short = 'l', long="location")]
     location: bool,
+    /// Print everything, even if it doesn't fit on the terminal
+    #[arg(long="no-pager")]
+    no_pager: bool,
 }
 
 impl Cmd for Args {
@@ -90,8 +93,8 @@ impl Cmd for Args {
             location: self.location,
         };
 
-        let mut stdout = io::stdout();
         let events = Activity::query(rl.db(), &filter)?;
+        let mut stdout = Pager::new(rl, self.no_pager);
 
         if self.initial {
             if let Some(first) = events.get(0) {
@@ -109,7 +112,7 @@ impl Cmd for Args {
             activity.write_to(&mut stdout)?;
         }
 
-        Ok(())
+        stdout.finish()
     }
 }
 
@@ -1,7 +1,9 @@
 use crate::errors::*;
 use crate::blobs::Blob;
 use crate::cmd::Cmd;
+use crate::compression;
 use crate::db::DbChange;
+use crate::db::batch::Batch;
 use crate::gfx;
 use crate::models::*;
 use crate::shell::Shell;
@@ -10,7 +12,7 @@ use crate::utils;
 use crate::term;
 use std::fmt::Debug;
 use std::fs;
-use std::io::{self, BufRead};
+use std::io::BufRead;
 use std::net;
 use std::net::SocketAddr;
 use std::path::Path;
@@ -112,12 +114,22 @@ impl Cmd for Args {
 }
 
 fn stream_stdin<T: InsertFromString>(rl: &mut Shell) -> Result<()> {
-    let stdin = io::stdin();
-    for line in stdin.lock().lines() {
+    let mut batch = Batch::new(rl.config().core.batch_size);
+    let result = stream_stdin_batched::<T>(rl, &mut batch);
+    let committed = batch.commit(rl.db())
+        .context("Failed to write the last lines, they have been rolled back");
+    result?;
+    committed?;
+    Ok(())
+}
+
+fn stream_stdin_batched<T: InsertFromString>(rl: &mut Shell, batch: &mut Batch) -> Result<()> {
+    for (i, line) in compression::stdin()?.lines().enumerate() {
         let line = line?;
         match T::from_string(rl, line) {
             Ok(insert) => {
                 debug!("Received {:?}", insert);
+                batch.row(rl.db())?;
                 let result = rl.db().insert_generic(insert.clone())?;
 
                 if let Some((DbChange::Insert, _)) = result {
@@ -126,6 +138,12 @@ fn stream_stdin<T: InsertFromString>(rl: &mut Shell) -> Result<()> {
                         term::success(&log);
                     }
                 }
+
+                if batch.is_due() {
+                    let rows = batch.len();
+                    batch.commit(rl.db())
+                        .context(format_err!("Failed to write the {} lines up to line {}, they have been rolled back", rows, i + 1))?;
+                }
             },
             Err(err) => term::error(&format!("Error: {:#}", err)),
         }
@@ -337,12 +355,13 @@ impl IntoInsert for AddEmail {
 
 impl InsertFromString for AddEmail {
     fn from_string(_rl: &mut Shell, email: String) -> Result<Insert> {
-        // TODO: consider doing basic validation
+        let syntax_valid = Some(is_syntax_valid(&email));
         Ok(Insert::Email(NewEmail {
             value: email,
             displayname: None,
             valid: None,
             unscoped: false,
+            syntax_valid,
         }))
     }
 }
@@ -721,6 +740,8 @@ impl InsertFromString for AddPort {
             banner: None,
             service: None,
             version: None,
+            ja3s: None,
+            ja4s: None,
 
             unscoped: false,
         }))
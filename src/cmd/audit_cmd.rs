@@ -0,0 +1,80 @@
+use clap::Parser;
+use crate::cmd::Cmd;
+use crate::cmd::activity_cmd::TimeSpec;
+use crate::errors::*;
+use crate::models::*;
+use crate::shell::Shell;
+use crate::shell::pager::Pager;
+use crate::term;
+use std::convert::TryFrom;
+use std::io::Write;
+
+#[derive(Debug, Parser)]
+#[group(skip)]
+pub struct Args {
+    #[command(subcommand)]
+    subcommand: Subcommand,
+}
+
+#[derive(Debug, Parser)]
+pub enum Subcommand {
+    /// List recorded connections
+    #[command(name="list")]
+    List(Filter),
+    /// Export recorded connections as json, one line per connection
+    #[command(name="export")]
+    Export(Filter),
+}
+
+#[derive(Debug, Parser)]
+pub struct Filter {
+    /// Only show connections of a given module
+    #[arg(short = 'm', long="module")]
+    module: Option<String>,
+    /// Only show connections starting from that datetime
+    #[arg(long="since")]
+    since: Option<TimeSpec>,
+    /// Only show connections until this datetime
+    #[arg(long="until")]
+    until: Option<TimeSpec>,
+    /// Print everything, even if it doesn't fit on the terminal
+    #[arg(long="no-pager")]
+    no_pager: bool,
+}
+
+impl Filter {
+    fn query(self, rl: &Shell) -> Result<Vec<NetworkAudit>> {
+        let filter = NetworkAuditFilter {
+            module: self.module,
+            since: self.since.map(|t| t.datetime),
+            until: self.until.map(|t| t.datetime),
+        };
+        NetworkAudit::query(rl.db(), &filter)
+    }
+}
+
+impl Cmd for Args {
+    fn run(self, rl: &mut Shell) -> Result<()> {
+        if !rl.config().network.audit {
+            term::warn("The network audit log is disabled, set network.audit in your config to record connections");
+        }
+
+        match self.subcommand {
+            Subcommand::List(filter) => {
+                let mut stdout = Pager::new(rl, filter.no_pager);
+                for entry in filter.query(rl)? {
+                    writeln!(stdout, "{}", entry)?;
+                }
+                stdout.finish()
+            },
+            Subcommand::Export(filter) => {
+                let mut stdout = Pager::new(rl, filter.no_pager);
+                for entry in filter.query(rl)? {
+                    JsonNetworkAudit::try_from(entry)?
+                        .write_to(&mut stdout)?;
+                }
+                stdout.finish()
+            },
+        }
+    }
+}
@@ -1,9 +1,14 @@
 use crate::errors::*;
 use crate::autonoscope;
 use crate::cmd::Cmd;
+use crate::cmd::rescope_cmd;
+use crate::db::Family;
 use crate::fmt::colors::*;
 use crate::shell::Shell;
+use crate::term;
 use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
 use clap::Parser;
 
 #[derive(Debug, Parser)]
@@ -21,6 +26,18 @@ pub enum Subcommand {
     Delete(Delete),
     #[command(name="list")]
     List,
+    /// Show which rule decides the scope of an entity
+    #[command(name="test")]
+    Test(Test),
+    /// Evaluate the rules for every entity that already exists
+    #[command(name="apply")]
+    Apply(Apply),
+    /// Write all rules to a json file
+    #[command(name="export")]
+    Export(Export),
+    /// Add the rules of an export or a list of domains and networks
+    #[command(name="import")]
+    Import(Import),
 }
 
 #[derive(Debug, Parser)]
@@ -35,6 +52,98 @@ pub struct Delete {
     value: String,
 }
 
+#[derive(Debug, Parser)]
+pub struct Test {
+    /// The type of the entity, like subdomain or ipaddr
+    family: Family,
+    /// The value of the entity
+    value: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct Apply {
+    /// Only apply the rules to one type of entity
+    #[arg(long="entity")]
+    entity: Option<Family>,
+    /// Only show the changes, without updating the database
+    #[arg(short='n', long="dry-run")]
+    dry_run: bool,
+    /// Skip entities that have last been scoped or noscoped by hand
+    #[arg(long="respect-manual")]
+    respect_manual: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct Export {
+    /// Write to this file instead of stdout
+    #[arg(short='f', long="file")]
+    file: Option<PathBuf>,
+}
+
+#[derive(Debug, Parser)]
+pub struct Import {
+    /// A json export, or one domain, network, url or @domain of emails per line, prefixed with - if it's out of scope
+    file: PathBuf,
+    /// Replace existing rules that have the opposite scope
+    #[arg(long="overwrite")]
+    overwrite: bool,
+    /// Only show what would be imported
+    #[arg(short='n', long="dry-run")]
+    dry_run: bool,
+}
+
+fn export(rl: &mut Shell, args: Export) -> Result<()> {
+    let rules = autonoscope::export_rules(rl.db().autonoscope());
+    let json = serde_json::to_string_pretty(&rules)?;
+    if let Some(path) = args.file {
+        fs::write(&path, json + "\n")
+            .context("Failed to write rules")?;
+        term::success(&format!("Exported {} rules to {:?}", rules.len(), path));
+    } else {
+        println!("{}", json);
+    }
+    Ok(())
+}
+
+fn import(rl: &mut Shell, args: Import) -> Result<()> {
+    let text = fs::read_to_string(&args.file)
+        .context("Failed to read rules")?;
+    let entries = autonoscope::parse_rules(&text)?;
+    let plan = autonoscope::ImportPlan::new(rl.db().autonoscope(), entries)?;
+
+    for entry in &plan.duplicates {
+        term::info(&format!("Skipping duplicate {} rule {:?}", entry.object, entry.value));
+    }
+    for entry in &plan.conflicts {
+        let existing = if entry.scoped { "noscope" } else { "scope" };
+        term::warn(&format!("{} rule {:?} already exists as {} rule", entry.object, entry.value, existing));
+    }
+    if !plan.conflicts.is_empty() && !args.overwrite {
+        bail!("{} rules conflict with existing rules, use --overwrite to replace them", plan.conflicts.len());
+    }
+
+    let mut rules = plan.added;
+    rules.extend(plan.conflicts);
+    if args.dry_run {
+        for entry in &rules {
+            let scope = if entry.scoped { "  scope" } else { "noscope" };
+            println!("{} {} {:?}", scope, entry.object, entry.value);
+        }
+        term::success(&format!("{} rules would be imported, database not updated", rules.len()));
+    } else {
+        rl.db_mut().autonoscope_add_rules(&rules)?;
+        term::success(&format!("Imported {} rules", rules.len()));
+    }
+    Ok(())
+}
+
+/// The rules are applied like `rescope -y`, the entity limits it to one
+/// type of entity
+fn apply(rl: &mut Shell, args: Apply) -> Result<()> {
+    rescope_cmd::Args::auto(args.entity, args.dry_run, args.respect_manual)?
+        .run(rl)
+}
+
 fn display_rule<T: Color>(object: &str, rule: &str) -> Result<()> {
     let mut out = String::new();
     T::display(&mut out, object)?;
@@ -61,6 +170,24 @@ pub fn run_with_scope_param(rl: &mut Shell, args: Args, scoped: bool) -> Result<
             }
             Ok(())
         },
+        Subcommand::Apply(args) => apply(rl, args),
+        Subcommand::Export(args) => export(rl, args),
+        Subcommand::Import(args) => import(rl, args),
+        Subcommand::Test(test) => {
+            let family = test.family.as_str();
+            match rl.db().autonoscope().matching_value(&test.family, &test.value)? {
+                Some(((object, rule), true)) => {
+                    term::success(&format!("{} {:?} is in scope", family, test.value));
+                    display_rule::<Green>(&format!("  scope {}", object), &rule)?;
+                },
+                Some(((object, rule), false)) => {
+                    term::success(&format!("{} {:?} is out of scope", family, test.value));
+                    display_rule::<Red>(&format!("noscope {}", object), &rule)?;
+                },
+                None => term::success(&format!("{} {:?} is in scope, no rule matches", family, test.value)),
+            }
+            Ok(())
+        },
     }
 }
 
@@ -0,0 +1,69 @@
+use crate::bench::{self, Timing};
+use crate::cmd::LiteCmd;
+use crate::config::Config;
+use crate::db::Database;
+use crate::errors::*;
+use crate::paths;
+use crate::term;
+use crate::utils;
+use clap::Parser;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+pub struct Args {
+    /// The number of entities that are inserted
+    #[arg(long="rows", default_value="2000")]
+    rows: usize,
+    /// Create the temporary workspace in this folder instead of next to the
+    /// other workspaces
+    #[arg(long="dir")]
+    dir: Option<PathBuf>,
+    /// Print the timings as json
+    #[arg(long="json")]
+    json: bool,
+}
+
+fn print_timings(timings: &[Timing]) {
+    println!("{:16} {:>10} {:>12} {:>12}", "benchmark", "iterations", "total", "per iter");
+    for t in timings {
+        println!("{:16} {:>10} {:>12} {:>12}",
+            t.name,
+            t.iterations,
+            format!("{:.2?}", t.elapsed),
+            format!("{:.2?}", t.per_iteration()));
+    }
+}
+
+impl LiteCmd for Args {
+    fn run(self, _config: &Config) -> Result<()> {
+        let dir = match self.dir {
+            Some(dir) => dir,
+            None => paths::data_dir()?,
+        };
+        let dir = dir.join(format!(".bench-{}", utils::random_string(8)));
+        fs::create_dir_all(&dir)
+            .context(format_err!("Failed to create {:?}", dir))?;
+
+        let path = dir.join("db.sqlite");
+        let path = path.to_str()
+            .ok_or_else(|| format_err!("Failed to convert db path to utf-8"))?;
+        if !self.json {
+            term::info(&format!("Running benchmarks with {} rows in {:?}", self.rows, dir));
+        }
+
+        let timings = Database::scratch(path, "bench")
+            .and_then(|db| bench::run(&db, self.rows));
+        fs::remove_dir_all(&dir)
+            .context(format_err!("Failed to remove {:?}", dir))?;
+        let timings = timings?;
+
+        if self.json {
+            println!("{}", serde_json::to_string(&timings)?);
+        } else {
+            print_timings(&timings);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+use clap::Parser;
+use crate::blobs::GcReport;
+use crate::cmd::Cmd;
+use crate::errors::*;
+use crate::shell::Shell;
+use crate::shell::pager::Pager;
+use crate::term;
+use std::io::Write;
+
+#[derive(Debug, Parser)]
+#[group(skip)]
+pub struct Args {
+    #[command(subcommand)]
+    subcommand: Subcommand,
+}
+
+#[derive(Debug, Parser)]
+pub enum Subcommand {
+    /// List the stored blobs with their size and how often they are referenced
+    #[command(name="list")]
+    List(List),
+    /// Find blobs that aren't referenced anymore and the space they take up
+    #[command(name="gc")]
+    Gc(Gc),
+}
+
+#[derive(Debug, Parser)]
+pub struct List {
+    /// Print the blobs as json
+    #[arg(long="json")]
+    json: bool,
+    /// Print everything, even if it doesn't fit on the terminal
+    #[arg(long="no-pager")]
+    no_pager: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct Gc {
+    /// Delete the unreferenced blobs, without this nothing is deleted
+    #[arg(long="delete")]
+    delete: bool,
+    /// Print the report as json
+    #[arg(long="json")]
+    json: bool,
+}
+
+fn bytes(size: u64) -> String {
+    humansize::format_size(size, humansize::BINARY)
+}
+
+pub fn print_gc(report: &GcReport) {
+    for blob in &report.unreferenced {
+        term::warn(&format!("{} isn't referenced ({})", blob.id, bytes(blob.size)));
+    }
+    if report.recent > 0 {
+        term::info(&format!("Keeping {} unreferenced blobs that have been written in the last hour", report.recent));
+    }
+
+    let count = report.unreferenced.len();
+    if count == 0 {
+        term::success("No blobs to delete");
+    } else if report.deleted {
+        term::success(&format!("Deleted {} blobs, freed {}", count, bytes(report.reclaimable)));
+    } else {
+        term::info(&format!("{} blobs can be deleted to reclaim {}, run blobs gc --delete", count, bytes(report.reclaimable)));
+    }
+}
+
+impl Cmd for Args {
+    fn run(self, rl: &mut Shell) -> Result<()> {
+        match self.subcommand {
+            Subcommand::List(args) => {
+                let usage = rl.blobs().usage(rl.db())?;
+                if args.json {
+                    println!("{}", serde_json::to_string(&usage)?);
+                } else {
+                    let mut out = Pager::new(rl, args.no_pager);
+                    for blob in &usage {
+                        writeln!(out, "{} {:>10} {:>4} refs", blob.id, bytes(blob.size), blob.references)?;
+                    }
+                    out.finish()?;
+                }
+            },
+            Subcommand::Gc(args) => {
+                let report = rl.blobs().gc(rl.db(), args.delete)?;
+                if args.json {
+                    println!("{}", serde_json::to_string(&report)?);
+                } else {
+                    print_gc(&report);
+                }
+            },
+        }
+        Ok(())
+    }
+}
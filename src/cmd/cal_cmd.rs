@@ -1,9 +1,10 @@
 use crate::errors::*;
-use chrono::Utc;
+use chrono::{NaiveDate, NaiveDateTime, Utc};
 use crate::cal::DateArg;
 use crate::cal::date::{DateContext, DateSpec};
 use crate::cal::time::{DateTimeContext, DateTimeSpec};
 use crate::cmd::Cmd;
+use crate::db::{Database, Family};
 use crate::models::*;
 use crate::shell::Shell;
 use clap::Parser;
@@ -19,22 +20,58 @@ pub struct Args {
     /// Group events by hour
     #[arg(short = 'H', long, group = "view")]
     hourly: bool,
+    /// Show the entities added by a module instead of activity, like
+    /// kpcyrd/ctlogs or ctlogs
+    #[arg(long)]
+    module: Option<String>,
+    /// Show the entities of a type that have been added instead of activity
+    #[arg(long)]
+    entity: Option<Family>,
+    /// Print the number of events of every day below the calendar
+    #[arg(long)]
+    counts: bool,
     args: Vec<DateArg>,
 }
 
+impl Args {
+    /// The times of the activity events, or of the entities that have been
+    /// added if a module or type is selected
+    fn events(&self, db: &Database, since: NaiveDateTime, until: NaiveDateTime) -> Result<Vec<NaiveDateTime>> {
+        if self.module.is_some() || self.entity.is_some() {
+            Provenance::insert_times(db, self.module.as_deref(), self.entity.as_ref(), since, until)
+        } else {
+            let filter = ActivityFilter {
+                topic: None,
+                since: Some(since),
+                until: Some(until),
+                location: false,
+            };
+            let events = Activity::query(db, &filter)?;
+            Ok(events.into_iter()
+                .map(|event| event.time)
+                .collect())
+        }
+    }
+}
+
+/// One line for every day until `end`, days without events are included so
+/// the list lines up with the calendar
+fn print_counts(ctx: &DateContext, start: NaiveDate, end: NaiveDate) {
+    let today = Utc::now().date_naive();
+    for date in start.iter_days().take_while(|date| *date < end && *date <= today) {
+        println!("{} {:>6}", date.format("%Y-%m-%d"), ctx.count_for_day(&date));
+    }
+}
+
 impl Cmd for Args {
     #[inline]
     fn run(self, rl: &mut Shell) -> Result<()> {
         if self.time || self.hourly {
             let dts = DateTimeSpec::from_args(&self.args, self.context)
                 .context("Failed to parse date spec")?;
-            let filter = ActivityFilter {
-                topic: None,
-                since: Some(dts.start().and_hms_opt(0, 0, 0).expect("Invalid hour/min/sec")),
-                until: Some(dts.end().and_hms_opt(23, 59, 59).expect("Invalid hour/min/sec")),
-                location: false,
-            };
-            let events = Activity::query(rl.db(), &filter)?;
+            let events = self.events(rl.db(),
+                dts.start().and_hms_opt(0, 0, 0).expect("Invalid hour/min/sec"),
+                dts.end().and_hms_opt(23, 59, 59).expect("Invalid hour/min/sec"))?;
 
             let (slice_width, slice_duration) = if self.hourly {
                 (3, 60)
@@ -44,18 +81,24 @@ impl Cmd for Args {
 
             let ctx = DateTimeContext::new(&events, Utc::now().naive_utc(), slice_width, slice_duration);
             println!("{}", dts.to_term_string(&ctx));
+
+            if self.counts {
+                let ctx = DateContext::new(&events, Utc::now().date_naive());
+                let end = dts.end().succ_opt().expect("Date out of range");
+                print_counts(&ctx, *dts.start(), end);
+            }
         } else {
             let ds = DateSpec::from_args(&self.args, self.context)
                 .context("Failed to parse date spec")?;
-            let filter = ActivityFilter {
-                topic: None,
-                since: Some(ds.start().and_hms_opt(0, 0, 0).expect("Invalid hour/min/sec")),
-                until: Some(ds.end().and_hms_opt(23, 59, 59).expect("Invalid hour/min/sec")),
-                location: false,
-            };
-            let events = Activity::query(rl.db(), &filter)?;
+            let events = self.events(rl.db(),
+                ds.start().and_hms_opt(0, 0, 0).expect("Invalid hour/min/sec"),
+                ds.end().and_hms_opt(23, 59, 59).expect("Invalid hour/min/sec"))?;
             let ctx = DateContext::new(&events, Utc::now().date_naive());
             println!("{}", ds.to_term_string(&ctx));
+
+            if self.counts {
+                print_counts(&ctx, ds.start(), ds.end());
+            }
         }
         Ok(())
     }
@@ -0,0 +1,108 @@
+use clap::Parser;
+use crate::cmd::Cmd;
+use crate::db::maintenance::{self, FsckReport, VacuumReport};
+use crate::errors::*;
+use crate::shell::Shell;
+use crate::term;
+
+#[derive(Debug, Parser)]
+#[group(skip)]
+pub struct Args {
+    #[command(subcommand)]
+    subcommand: Subcommand,
+}
+
+#[derive(Debug, Parser)]
+pub enum Subcommand {
+    /// Find orphaned rows, missing or unreferenced blobs and values that aren't normalized
+    #[command(name="fsck")]
+    Fsck(Maintenance),
+    /// Check the database file for corruption and rebuild it to reclaim unused space
+    #[command(name="vacuum")]
+    Vacuum(Maintenance),
+}
+
+#[derive(Debug, Parser)]
+pub struct Maintenance {
+    /// Repair the problems that were found, without this the database isn't modified
+    #[arg(long="fix")]
+    fix: bool,
+    /// Print the report as json
+    #[arg(long="json")]
+    json: bool,
+}
+
+fn bytes(size: i64) -> String {
+    humansize::format_size(size.max(0) as u64, humansize::BINARY)
+}
+
+fn print_fsck(report: &FsckReport) {
+    for orphans in &report.orphans {
+        term::warn(&format!("{}: {} rows point to {} that don't exist", orphans.table, orphans.rows.len(), orphans.parent));
+    }
+    for blob in &report.missing_blobs {
+        term::warn(&format!("image #{}: blob {:?} doesn't exist", blob.id, blob.blob));
+    }
+    for blob in &report.dangling_blobs {
+        term::warn(&format!("blob {:?} isn't referenced by any image", blob));
+    }
+    for entity in &report.denormalized {
+        let msg = match (&entity.normalized, entity.duplicate) {
+            (None, _) => format!("{} #{}: {:?} is invalid", entity.family, entity.id, entity.value),
+            (Some(normalized), Some(duplicate)) => format!("{} #{}: {:?} is a duplicate of #{} {:?}", entity.family, entity.id, entity.value, duplicate, normalized),
+            (Some(normalized), None) => format!("{} #{}: {:?} should be {:?}", entity.family, entity.id, entity.value, normalized),
+        };
+        term::warn(&msg);
+    }
+
+    let problems = report.problems();
+    let fixable = report.fixable();
+    if problems == 0 {
+        term::success("No problems found");
+    } else if report.fixed {
+        term::success(&format!("Fixed {} of {} problems", fixable, problems));
+    } else if fixable > 0 {
+        term::info(&format!("Found {} problems, run db fsck --fix to repair {} of them", problems, fixable));
+    } else {
+        term::info(&format!("Found {} problems, none of them can be repaired automatically", problems));
+    }
+}
+
+fn print_vacuum(report: &VacuumReport) {
+    for msg in &report.integrity {
+        term::error(&format!("integrity check: {}", msg));
+    }
+    if report.integrity.is_empty() {
+        term::success("Integrity check passed");
+    }
+    term::info(&format!("Database size is {}, {} of it unused", bytes(report.size), bytes(report.free)));
+    if let Some(vacuumed) = report.vacuumed {
+        term::success(&format!("Rebuilt database, size is now {}", bytes(vacuumed)));
+    } else if report.integrity.is_empty() {
+        term::info("Run db vacuum --fix to rebuild the database and its indices");
+    }
+}
+
+impl Cmd for Args {
+    fn run(self, rl: &mut Shell) -> Result<()> {
+        match self.subcommand {
+            Subcommand::Fsck(args) => {
+                let report = maintenance::fsck(rl.db(), rl.blobs(), args.fix)?;
+                if args.json {
+                    println!("{}", serde_json::to_string(&report)?);
+                } else {
+                    print_fsck(&report);
+                }
+            },
+            Subcommand::Vacuum(args) => {
+                let report = maintenance::vacuum(rl.db(), args.fix)?;
+                if args.json {
+                    println!("{}", serde_json::to_string(&report)?);
+                } else {
+                    print_vacuum(&report);
+                }
+            },
+        }
+        Ok(())
+    }
+}
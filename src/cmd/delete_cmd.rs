@@ -1,6 +1,6 @@
 use crate::errors::*;
 use crate::cmd::Cmd;
-use crate::filters::{Target, Filter};
+use crate::filters::Target;
 use crate::shell::Shell;
 use clap::Parser;
 use crate::models::*;
@@ -15,20 +15,20 @@ pub struct Args {
 impl Cmd for Args {
     fn run(self, rl: &mut Shell) -> Result<()> {
         let rows = match &self.subcommand {
-            Target::Domains(filter) => delete::<Domain>(rl, filter),
-            Target::Subdomains(filter) => delete::<Subdomain>(rl, filter),
-            Target::IpAddrs(filter) => delete::<IpAddr>(rl, filter),
-            Target::Urls(filter) => delete::<Url>(rl, filter),
-            Target::Emails(filter) => delete::<Email>(rl, filter),
-            Target::PhoneNumbers(filter) => delete::<PhoneNumber>(rl, filter),
-            Target::Devices(filter) => delete::<Device>(rl, filter),
-            Target::Networks(filter) => delete::<Network>(rl, filter),
-            Target::Accounts(filter) => delete::<Account>(rl, filter),
-            Target::Breaches(filter) => delete::<Breach>(rl, filter),
-            Target::Images(filter) => delete::<Image>(rl, filter),
-            Target::Ports(filter) => delete::<Port>(rl, filter),
-            Target::Netblocks(filter) => delete::<Netblock>(rl, filter),
-            Target::CryptoAddrs(filter) => delete::<CryptoAddr>(rl, filter),
+            Target::Domains(_) => delete::<Domain>(rl, &self.subcommand),
+            Target::Subdomains(_) => delete::<Subdomain>(rl, &self.subcommand),
+            Target::IpAddrs(_) => delete::<IpAddr>(rl, &self.subcommand),
+            Target::Urls(_) => delete::<Url>(rl, &self.subcommand),
+            Target::Emails(_) => delete::<Email>(rl, &self.subcommand),
+            Target::PhoneNumbers(_) => delete::<PhoneNumber>(rl, &self.subcommand),
+            Target::Devices(_) => delete::<Device>(rl, &self.subcommand),
+            Target::Networks(_) => delete::<Network>(rl, &self.subcommand),
+            Target::Accounts(_) => delete::<Account>(rl, &self.subcommand),
+            Target::Breaches(_) => delete::<Breach>(rl, &self.subcommand),
+            Target::Images(_) => delete::<Image>(rl, &self.subcommand),
+            Target::Ports(_) => delete::<Port>(rl, &self.subcommand),
+            Target::Netblocks(_) => delete::<Netblock>(rl, &self.subcommand),
+            Target::CryptoAddrs(_) => delete::<CryptoAddr>(rl, &self.subcommand),
         }?;
         term::info(&format!("Deleted {} rows", rows));
         Ok(())
@@ -41,6 +41,6 @@ pub fn run(rl: &mut Shell, args: &[String]) -> Result<()> {
 }
 
 #[inline]
-fn delete<T: Model + Detailed>(rl: &mut Shell, filter: &Filter) -> Result<usize> {
-    T::delete(rl.db(), &filter.parse()?)
+fn delete<T: Model + Detailed>(rl: &mut Shell, target: &Target) -> Result<usize> {
+    rl.db().delete::<T>(&target.parse(rl.db())?)
 }
@@ -0,0 +1,599 @@
+use clap::Parser;
+use colored::Colorize;
+use crate::cmd::Cmd;
+use crate::cmd::scope_cmd::Since;
+use crate::compression;
+use crate::db::{Database, Family, Filter, Table};
+use crate::errors::*;
+use crate::models::*;
+use crate::paths;
+use crate::shell::Shell;
+use crate::shell::pager::Pager;
+use crate::term;
+use crate::utils;
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const SQLITE_MAGIC: &[u8] = b"SQLite format 3\0";
+/// Columns that change whenever an entity is observed again, they aren't
+/// changes of the entity itself
+const BOOKKEEPING: &[&str] = &["seen_first", "seen_last", "seen_count"];
+
+#[derive(Debug, Parser)]
+pub struct Args {
+    /// A json export or a backup of the database, like the ones created by
+    /// `sn0int migrate`
+    #[arg(required_unless_present="since")]
+    path: Option<PathBuf>,
+    /// Compare with the workspace at the time of the `last` run, a run id, an
+    /// age like `7d` or a datetime, using the provenance of the entities
+    #[arg(long, conflicts_with="path")]
+    since: Option<Since>,
+    /// Print json output, one line per entity
+    #[arg(long)]
+    pub json: bool,
+    /// Print everything, even if it doesn't fit on the terminal
+    #[arg(long="no-pager")]
+    no_pager: bool,
+}
+
+/// The fields of every entity in a table by value. The ids that link rows
+/// differ between workspaces and are left out, like the bookkeeping columns.
+type Entities = BTreeMap<String, serde_json::Map<String, Value>>;
+
+#[derive(Debug, PartialEq, Serialize)]
+struct FieldChange {
+    /// Unknown if the change is taken from the provenance
+    #[serde(skip_serializing_if = "Option::is_none")]
+    old: Option<Value>,
+    new: Value,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+enum Change {
+    Added,
+    Removed,
+    Changed {
+        fields: BTreeMap<String, FieldChange>,
+    },
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct Entry {
+    #[serde(serialize_with = "serialize_table")]
+    table: Table,
+    value: String,
+    #[serde(flatten)]
+    change: Change,
+}
+
+fn serialize_table<S: serde::Serializer>(table: &Table, s: S) -> std::result::Result<S::Ok, S::Error> {
+    s.serialize_str(table.as_str())
+}
+
+fn add_entity(entities: &mut Entities, entity: Value) -> Result<()> {
+    let mut fields = match entity {
+        Value::Object(fields) => fields,
+        _ => bail!("Expected entity to be an object"),
+    };
+    let value = match fields.remove("value") {
+        Some(Value::String(value)) => value,
+        _ => bail!("Entity has no value"),
+    };
+    fields.retain(|name, _| name != "id" && !name.ends_with("_id") && !BOOKKEEPING.contains(&name.as_str()));
+    entities.insert(value, fields);
+    Ok(())
+}
+
+fn load<T: Model + Serialize>(db: &Database) -> Result<Entities> {
+    let mut entities = Entities::new();
+    db.for_each_chunk::<T, _>(&Filter::any(), CHUNK_SIZE, |chunk| {
+        for entity in chunk {
+            add_entity(&mut entities, serde_json::to_value(&entity)?)?;
+        }
+        Ok(())
+    })?;
+    Ok(entities)
+}
+
+/// The tables that link entities don't have a value and are skipped
+fn load_table(db: &Database, table: Table) -> Result<Option<Entities>> {
+    let entities = match table {
+        Table::Domains => load::<Domain>(db)?,
+        Table::Subdomains => load::<Subdomain>(db)?,
+        Table::Ipaddrs => load::<IpAddr>(db)?,
+        Table::Urls => load::<Url>(db)?,
+        Table::Emails => load::<Email>(db)?,
+        Table::Phonenumbers => load::<PhoneNumber>(db)?,
+        Table::Devices => load::<Device>(db)?,
+        Table::Networks => load::<Network>(db)?,
+        Table::Accounts => load::<Account>(db)?,
+        Table::Breaches => load::<Breach>(db)?,
+        Table::Images => load::<Image>(db)?,
+        Table::Ports => load::<Port>(db)?,
+        Table::Netblocks => load::<Netblock>(db)?,
+        Table::Cryptoaddrs => load::<CryptoAddr>(db)?,
+        Table::SubdomainIpaddrs | Table::NetworkDevices | Table::BreachEmails => return Ok(None),
+    };
+    Ok(Some(entities))
+}
+
+fn is_link(table: Table) -> bool {
+    matches!(table, Table::SubdomainIpaddrs | Table::NetworkDevices | Table::BreachEmails)
+}
+
+fn snapshot(db: &Database) -> Result<HashMap<Table, Entities>> {
+    let mut tables = HashMap::new();
+    for table in Table::ALL {
+        if let Some(entities) = load_table(db, *table)? {
+            tables.insert(*table, entities);
+        }
+    }
+    Ok(tables)
+}
+
+/// Read the tables of an export in the `json` or `json-blobs` format, tables
+/// that are missing from the export are left out of the comparison
+fn snapshot_json(mut export: Value) -> Result<HashMap<Table, Entities>> {
+    if let Some(models) = export.get_mut("models") {
+        export = models.take();
+    }
+    let export = match export {
+        Value::Object(export) => export,
+        _ => bail!("Expected export to be a json object"),
+    };
+
+    let mut tables = HashMap::new();
+    for (name, rows) in export {
+        let table = Table::from_str(&name)
+            .map_err(|_| format_err!("Unknown table in export: {:?}", name))?;
+        if is_link(table) {
+            continue;
+        }
+        let rows = match rows {
+            Value::Array(rows) => rows,
+            _ => bail!("Expected {} to be a list", name),
+        };
+
+        let mut entities = Entities::new();
+        for row in rows {
+            add_entity(&mut entities, row)
+                .context(format!("Invalid entity in {}", name))?;
+        }
+        tables.insert(table, entities);
+    }
+    Ok(tables)
+}
+
+/// Backups are copied before they are opened, so an old backup can be
+/// migrated to the current schema without modifying it
+fn snapshot_backup(path: &Path) -> Result<HashMap<Table, Entities>> {
+    let dir = paths::data_dir()?
+        .join(format!(".diff-{}", utils::random_string(8)));
+    fs::create_dir_all(&dir)
+        .context(format_err!("Failed to create {:?}", dir))?;
+
+    let copy = dir.join("db.sqlite");
+    let tables = fs::copy(path, &copy)
+        .context(format_err!("Failed to copy {:?}", path))
+        .map_err(Error::from)
+        .and_then(|_| {
+            let copy = copy.to_str()
+                .ok_or_else(|| format_err!("Failed to convert db path to utf-8"))?;
+            let db = Database::scratch(copy, "backup")
+                .context("Failed to open backup, it may be encrypted or damaged")?;
+            snapshot(&db)
+        });
+    fs::remove_dir_all(&dir)
+        .context(format_err!("Failed to remove {:?}", dir))?;
+    tables
+}
+
+fn snapshot_file(path: &Path) -> Result<HashMap<Table, Entities>> {
+    let mut magic = Vec::new();
+    File::open(path)
+        .context(format_err!("Failed to open {:?}", path))?
+        .take(SQLITE_MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+
+    if magic == SQLITE_MAGIC {
+        snapshot_backup(path)
+    } else {
+        let export = serde_json::from_reader(compression::open(path)?)
+            .context(format_err!("Failed to parse {:?} as json export", path))?;
+        snapshot_json(export)
+    }
+}
+
+fn compare(table: Table, old: &Entities, new: &Entities) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    for (value, fields) in new {
+        let change = match old.get(value) {
+            None => Change::Added,
+            Some(old) => {
+                let mut changed = BTreeMap::new();
+                for (name, new) in fields {
+                    let old = old.get(name).unwrap_or(&Value::Null);
+                    if old != new {
+                        changed.insert(name.clone(), FieldChange {
+                            old: Some(old.clone()),
+                            new: new.clone(),
+                        });
+                    }
+                }
+                for (name, old) in old {
+                    if !fields.contains_key(name) && !old.is_null() {
+                        changed.insert(name.clone(), FieldChange {
+                            old: Some(old.clone()),
+                            new: Value::Null,
+                        });
+                    }
+                }
+                if changed.is_empty() {
+                    continue;
+                }
+                Change::Changed { fields: changed }
+            },
+        };
+        entries.push(Entry { table, value: value.clone(), change });
+    }
+    for value in old.keys() {
+        if !new.contains_key(value) {
+            entries.push(Entry { table, value: value.clone(), change: Change::Removed });
+        }
+    }
+    entries
+}
+
+fn diff_file(db: &Database, path: &Path) -> Result<Vec<Entry>> {
+    let old = snapshot_file(path)?;
+    let mut entries = Vec::new();
+    for table in Table::ALL {
+        // missing from the export, don't pretend everything has been added
+        let old = match old.get(table) {
+            Some(old) => old,
+            None => continue,
+        };
+        if let Some(new) = load_table(db, *table)? {
+            entries.extend(compare(*table, old, &new));
+        }
+    }
+    Ok(entries)
+}
+
+fn is_field_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// A string value that has been split at a comma inside of it
+fn is_open_string(value: &str) -> bool {
+    value.starts_with('"') && serde_json::from_str::<String>(value).is_err()
+}
+
+/// Split the details of an update like `title => "foo", status => 200` into
+/// fields. Values may contain commas, so a part only starts a new field if
+/// it looks like one and isn't inside of a string. Only needed for updates
+/// of older versions, which didn't record the changes as json.
+fn parse_details(details: &str) -> Vec<(String, Value)> {
+    let mut fields = Vec::<(String, String)>::new();
+    for part in details.split(", ") {
+        let open = fields.last().is_some_and(|(_, value)| is_open_string(value));
+        match part.split_once(" => ") {
+            Some((name, value)) if !open && is_field_name(name) => {
+                fields.push((name.to_string(), value.to_string()));
+            },
+            _ => if let Some((_, value)) = fields.last_mut() {
+                value.push_str(", ");
+                value.push_str(part);
+            },
+        }
+    }
+    fields.into_iter()
+        .map(|(name, value)| {
+            let value = serde_json::from_str(&value)
+                .unwrap_or(Value::String(value));
+            (name, value)
+        })
+        .collect()
+}
+
+fn parse_changes(changes: &str) -> Result<Vec<(String, Value)>> {
+    match serde_json::from_str(changes).context("Failed to parse changes of update")? {
+        Value::Object(fields) => Ok(fields.into_iter().collect()),
+        _ => bail!("Expected changes of update to be an object"),
+    }
+}
+
+/// Rebuild the changes since a point in time from the provenance. An entity
+/// existed before if its first change isn't an insert, and still exists if
+/// the last one isn't a delete.
+fn diff_since(db: &Database, since: NaiveDateTime) -> Result<Vec<Entry>> {
+    let mut entities = BTreeMap::<(String, String), Vec<Provenance>>::new();
+    for entry in Provenance::since(db, since)? {
+        entities.entry((entry.family.clone(), entry.value.clone()))
+            .or_default()
+            .push(entry);
+    }
+
+    let insert: &str = ProvenanceAction::Insert.into();
+    let delete: &str = ProvenanceAction::Delete.into();
+    let update: &str = ProvenanceAction::Update.into();
+    let scope: &str = ProvenanceAction::Scope.into();
+    let noscope: &str = ProvenanceAction::Noscope.into();
+
+    let mut entries = Vec::new();
+    for ((family, value), changes) in entities {
+        let table = match Family::from_str(&family) {
+            Ok(family) if !is_link(family.table()) => family.table(),
+            _ => continue,
+        };
+        let existed = changes[0].action != insert;
+        let exists = changes[changes.len() - 1].action != delete;
+
+        let change = match (existed, exists) {
+            (false, true) => Change::Added,
+            (true, false) => Change::Removed,
+            (false, false) => continue,
+            (true, true) => {
+                let mut fields = BTreeMap::new();
+                for change in &changes {
+                    let updates = if change.action == update {
+                        match (&change.changes, &change.details) {
+                            (Some(changes), _) => parse_changes(changes)?,
+                            (None, Some(details)) => parse_details(details),
+                            (None, None) => continue,
+                        }
+                    } else if change.action == scope || change.action == noscope {
+                        vec![("unscoped".to_string(), Value::Bool(change.action == noscope))]
+                    } else {
+                        continue;
+                    };
+                    for (name, new) in updates {
+                        fields.insert(name, FieldChange { old: None, new });
+                    }
+                }
+                if fields.is_empty() {
+                    continue;
+                }
+                Change::Changed { fields }
+            },
+        };
+        entries.push(Entry { table, value, change });
+    }
+    Ok(entries)
+}
+
+fn display_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => format!("{:?}", s),
+        Value::Array(bytes) if bytes.iter().all(Value::is_u64) => format!("[{} bytes]", bytes.len()),
+        value => value.to_string(),
+    }
+}
+
+fn summary(table: Table, entries: &[Entry]) -> String {
+    let count = |f: fn(&Change) -> bool| entries.iter().filter(|e| f(&e.change)).count();
+    format!("{}: {} added, {} removed, {} changed",
+        table.as_str(),
+        count(|c| *c == Change::Added),
+        count(|c| *c == Change::Removed),
+        count(|c| matches!(c, Change::Changed { .. })))
+}
+
+impl Cmd for Args {
+    fn run(self, rl: &mut Shell) -> Result<()> {
+        let mut entries = match (&self.path, &self.since) {
+            (Some(path), _) => diff_file(rl.db(), path)?,
+            (None, Some(since)) => diff_since(rl.db(), since.resolve(rl.db())?.0)?,
+            (None, None) => bail!("A file or --since is required"),
+        };
+        let position = |table: &Table| Table::ALL.iter().position(|t| t == table);
+        entries.sort_by(|a, b| (position(&a.table), &a.value).cmp(&(position(&b.table), &b.value)));
+
+        if entries.is_empty() && !self.json {
+            term::info("No differences");
+            return Ok(());
+        }
+
+        let mut out = Pager::new(rl, self.no_pager || self.json);
+        if self.json {
+            for entry in &entries {
+                writeln!(out, "{}", serde_json::to_string(entry)?)?;
+            }
+            return out.finish();
+        }
+
+        for (i, table) in entries.chunk_by(|a, b| a.table == b.table).enumerate() {
+            if i > 0 {
+                writeln!(out)?;
+            }
+            writeln!(out, "{}", summary(table[0].table, table).bold())?;
+            for entry in table {
+                match &entry.change {
+                    Change::Added => writeln!(out, "  {} {:?}", "+".green(), entry.value)?,
+                    Change::Removed => writeln!(out, "  {} {:?}", "-".red(), entry.value)?,
+                    Change::Changed { fields } => {
+                        let fields = fields.iter()
+                            .map(|(name, change)| format!("{} => {}", name, display_value(&change.new).yellow()))
+                            .collect::<Vec<_>>();
+                        writeln!(out, "  {} {:?} ({})", "~".yellow(), entry.value, fields.join(", "))?;
+                    },
+                }
+            }
+        }
+
+        out.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use serde_json::json;
+
+    fn entities(rows: Value) -> Entities {
+        let mut entities = Entities::new();
+        for row in rows.as_array().unwrap() {
+            add_entity(&mut entities, row.clone()).unwrap();
+        }
+        entities
+    }
+
+    #[test]
+    fn test_compare() {
+        let old = entities(json!([
+            {"id": 1, "value": "a.com", "unscoped": false},
+            {"id": 2, "value": "b.com", "unscoped": false},
+            {"id": 3, "value": "c.com", "unscoped": false, "title": "old"},
+        ]));
+        let new = entities(json!([
+            {"id": 7, "value": "a.com", "unscoped": false, "seen_last": "2020-03-14T16:20:23", "seen_count": 3},
+            {"id": 8, "value": "c.com", "unscoped": true, "title": null},
+            {"id": 9, "value": "d.com", "unscoped": false},
+        ]));
+
+        let entries = compare(Table::Domains, &old, &new);
+        let changes = entries.iter()
+            .map(|e| (e.value.as_str(), &e.change))
+            .collect::<Vec<_>>();
+        assert_eq!(changes, vec![
+            ("c.com", &Change::Changed { fields: BTreeMap::from([
+                ("title".to_string(), FieldChange { old: Some(json!("old")), new: Value::Null }),
+                ("unscoped".to_string(), FieldChange { old: Some(json!(false)), new: json!(true) }),
+            ])}),
+            ("d.com", &Change::Added),
+            ("b.com", &Change::Removed),
+        ]);
+
+        let json = serde_json::to_string(&entries[0]).unwrap();
+        assert_eq!(json, r#"{"table":"domains","value":"c.com","change":"changed","fields":{"title":{"old":"old","new":null},"unscoped":{"old":false,"new":true}}}"#);
+    }
+
+    #[test]
+    fn test_snapshot_json() {
+        let export = json!({
+            "models": {
+                "domains": [{"id": 1, "value": "example.com", "unscoped": false}],
+                "subdomains": [{"id": 1, "domain_id": 1, "value": "www.example.com", "unscoped": false}],
+                "subdomain_ipaddrs": [{"id": 1, "subdomain_id": 1, "ip_addr_id": 1}],
+            },
+            "blobs": [],
+        });
+        let tables = snapshot_json(export).unwrap();
+        assert_eq!(tables.len(), 2);
+        assert_eq!(tables[&Table::Subdomains]["www.example.com"], *json!({"unscoped": false}).as_object().unwrap());
+
+        assert!(snapshot_json(json!({"nope": []})).is_err());
+    }
+
+    #[test]
+    fn test_parse_details() {
+        assert_eq!(parse_details(r#"title => "a, b => c", status => 200"#), vec![
+            ("title".to_string(), json!("a, b => c")),
+            ("status".to_string(), json!(200)),
+        ]);
+        assert_eq!(parse_details("seen_last => 2020-03-14T16:20:23"), vec![
+            ("seen_last".to_string(), json!("2020-03-14T16:20:23")),
+        ]);
+    }
+
+    #[test]
+    fn test_diff_since() {
+        let db = Database::memory().unwrap();
+        let insert = |value: &str| {
+            db.insert_generic(Insert::Domain(NewDomain {
+                value: value.to_string(),
+                unscoped: false,
+            })).unwrap();
+        };
+        let subdomain = |resolvable| {
+            db.insert_generic(Insert::Subdomain(NewSubdomain {
+                domain_id: 1,
+                value: "www.old.com".to_string(),
+                resolvable,
+                unscoped: false,
+            })).unwrap();
+        };
+        insert("old.com");
+        insert("gone.com");
+        subdomain(None);
+
+        let since = Utc::now().naive_utc();
+        insert("new.com");
+        insert("tmp.com");
+        subdomain(Some(true));
+        db.set_scoped::<Domain>(&Filter::column_eq("value", "old.com"), false).unwrap();
+        db.delete::<Domain>(&Filter::column_eq("value", "gone.com")).unwrap();
+        db.delete::<Domain>(&Filter::column_eq("value", "tmp.com")).unwrap();
+
+        let entries = diff_since(&db, since).unwrap();
+        let changes = entries.iter()
+            .map(|e| (e.value.as_str(), &e.change))
+            .collect::<Vec<_>>();
+        assert_eq!(changes, vec![
+            ("gone.com", &Change::Removed),
+            ("new.com", &Change::Added),
+            ("old.com", &Change::Changed { fields: BTreeMap::from([
+                ("unscoped".to_string(), FieldChange { old: None, new: json!(true) }),
+            ])}),
+            ("www.old.com", &Change::Changed { fields: BTreeMap::from([
+                ("resolvable".to_string(), FieldChange { old: None, new: json!(true) }),
+            ])}),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_changes() {
+        assert_eq!(parse_changes(r#"{"title":"a, b => c","status":200}"#).unwrap(), vec![
+            ("status".to_string(), json!(200)),
+            ("title".to_string(), json!("a, b => c")),
+        ]);
+        assert!(parse_changes("title => \"a\"").is_err());
+    }
+
+    #[test]
+    fn test_diff_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("backup.db");
+        let insert = |db: &Database, value: &str| {
+            db.insert_generic(Insert::Domain(NewDomain {
+                value: value.to_string(),
+                unscoped: false,
+            })).unwrap();
+        };
+
+        let backup = Database::scratch(path.to_str().unwrap(), "backup").unwrap();
+        insert(&backup, "a.com");
+        insert(&backup, "b.com");
+        drop(backup);
+
+        // seen at other times and more often, which isn't a change
+        let db = Database::memory().unwrap();
+        insert(&db, "a.com");
+        insert(&db, "c.com");
+        insert(&db, "c.com");
+        db.set_scoped::<Domain>(&Filter::column_eq("value", "a.com"), false).unwrap();
+
+        let entries = diff_file(&db, &path).unwrap();
+        let changes = entries.iter()
+            .map(|e| (e.value.as_str(), &e.change))
+            .collect::<Vec<_>>();
+        assert_eq!(changes, vec![
+            ("a.com", &Change::Changed { fields: BTreeMap::from([
+                ("unscoped".to_string(), FieldChange { old: Some(json!(false)), new: json!(true) }),
+            ])}),
+            ("c.com", &Change::Added),
+            ("b.com", &Change::Removed),
+        ]);
+        // the copy of the backup has been removed again, the backup is untouched
+        assert!(path.exists());
+    }
+}
@@ -1,13 +1,19 @@
 use clap::Parser;
 use clap::ValueEnum;
-use crate::blobs::Blob;
+use crate::blobs::BlobStorage;
 use crate::cmd::Cmd;
-use crate::db::ttl;
+use crate::db::{ttl, Database, Filter};
 use crate::errors::*;
+use crate::manifest::{HashWriter, Manifest, ManifestFile, SigningKey};
+use crate::migrations;
 use crate::models::*;
 use crate::shell::Shell;
+use crate::term;
 use serde::{Serialize, Deserialize};
-use std::io::{self, Write};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
 use strum_macros::{EnumString, IntoStaticStr};
 
 #[derive(Debug, Parser)]
@@ -15,26 +21,110 @@ pub struct Args {
     /// Specify the export format
     #[arg(short = 'f', long="format", value_enum)]
     format: Format,
+    /// Write the export to a file instead of stdout
+    #[arg(short = 'o', long="output")]
+    output: Option<PathBuf>,
+    /// Write a manifest with hashes and entity counts next to the export
+    #[arg(long="manifest", requires="output")]
+    manifest: bool,
+    /// Sign the manifest with your signing key, implies --manifest
+    #[arg(long="sign", requires="output")]
+    sign: bool,
+    /// Only export entities matching a saved view
+    #[arg(long="view")]
+    view: Option<String>,
+    /// Leave out entities matching this filter, like "where unscoped=1"
+    #[arg(long="exclude-filter")]
+    exclude_filter: Vec<String>,
 }
 
 impl Cmd for Args {
     fn run(self, rl: &mut Shell) -> Result<()> {
         ttl::reap_expired(rl)?;
         match self.format {
-            Format::Json => export::<JsonFormat>(rl),
-            Format::JsonBlobs => export::<JsonBlobsFormat>(rl),
+            Format::Json => export::<JsonFormat>(rl, &self),
+            Format::JsonBlobs => export::<JsonBlobsFormat>(rl, &self),
         }
     }
 }
 
-fn export<T: ExportFormat + Serialize>(rl: &mut Shell) -> Result<()> {
-    let export = T::load(rl)?;
-    let mut stdout = io::stdout();
-    serde_json::to_writer(&mut stdout, &export)?;
-    stdout.write_all(b"\n")?;
+fn export<T: ExportFormat>(rl: &mut Shell, args: &Args) -> Result<()> {
+    let view = match &args.view {
+        Some(view) => Some(View::get(rl.db(), view)?.filter()),
+        None => None,
+    };
+    let exclude = args.exclude_filter.iter()
+        .map(|expr| Filter::parse_expr(None, expr))
+        .collect::<Result<Vec<_>>>()?;
+    let selection = Selection {
+        view,
+        exclude,
+    };
+
+    let output = match &args.output {
+        Some(output) => output,
+        None => {
+            let stdout = io::stdout();
+            let mut w = BufWriter::new(stdout.lock());
+            T::write(rl.db(), rl.blobs(), &selection, &mut w)?;
+            w.write_all(b"\n")?;
+            w.flush()?;
+            return Ok(());
+        },
+    };
+
+    let file_name = output.file_name()
+        .and_then(|f| f.to_str())
+        .ok_or_else(|| format_err!("Output path has no valid file name"))?;
+    let file = File::create(output)
+        .context(format!("Failed to create {:?}", output))?;
+    let mut w = HashWriter::new(BufWriter::new(file));
+    let counts = T::write(rl.db(), rl.blobs(), &selection, &mut w)
+        .and_then(|counts| {
+            w.write_all(b"\n")?;
+            Ok(counts)
+        })
+        .context(format!("Failed to write {:?}", output))?;
+    let file = w.finish(file_name.to_string())
+        .context(format!("Failed to write {:?}", output))?;
+
+    if args.manifest || args.sign {
+        let manifest = write_manifest(rl, args, output, file, counts)?;
+        term::success(&format!("Wrote manifest to {:?}", manifest));
+    }
+
     Ok(())
 }
 
+fn write_manifest(rl: &mut Shell, args: &Args, output: &Path, file: ManifestFile, counts: BTreeMap<String, usize>) -> Result<PathBuf> {
+    let schema_version = migrations::applied_versions(rl.db().db())?
+        .pop();
+    let format: &str = args.format.clone().into();
+
+    let path = output.with_file_name(format!("{}.manifest.json", file.path));
+    let mut manifest = Manifest {
+        workspace: rl.workspace().to_string(),
+        schema_version,
+        exported_at: chrono::Utc::now().naive_utc(),
+        format: format.to_string(),
+        files: vec![file],
+        counts,
+        signature: None,
+    };
+
+    if args.sign {
+        let key = SigningKey::load_or_create()?;
+        manifest.sign(&key)?;
+        term::info(&format!("Signed manifest with {}", key.public_key()));
+    }
+
+    let mut json = serde_json::to_vec_pretty(&manifest)?;
+    json.push(b'\n');
+    fs::write(&path, json)
+        .context(format!("Failed to write {:?}", path))?;
+    Ok(path)
+}
+
 #[derive(Debug, Clone, ValueEnum, Serialize, Deserialize)]
 #[derive(EnumString, IntoStaticStr)]
 #[strum(serialize_all = "kebab_case")]
@@ -43,72 +133,204 @@ pub enum Format {
     JsonBlobs,
 }
 
+/// The entities that are exported
+struct Selection {
+    view: Option<Filter>,
+    exclude: Vec<Filter>,
+}
+
+/// Tables that don't have the columns used by the view are left out, exclude
+/// filters are ignored for tables they don't apply to
+fn for_each_chunk<T, F>(db: &Database, table: &str, selection: &Selection, f: F) -> Result<()>
+    where T: Model, F: FnMut(Vec<T>) -> Result<()>
+{
+    let columns = db.columns(table)?;
+    let view = match &selection.view {
+        Some(view) if !view.applies_to(&columns) => return Ok(()),
+        Some(view) => view.clone(),
+        None => Filter::any(),
+    };
+    let filter = selection.exclude.iter()
+        .filter(|exclude| exclude.applies_to(&columns))
+        .fold(view, |filter, exclude| filter.and_not(exclude));
+    T::for_each_chunk(db, &filter, CHUNK_SIZE, f)
+}
+
 trait ExportFormat {
-    fn load(rl: &mut Shell) -> Result<Box<Self>>;
+    /// Write the export and return the number of exported entities by type
+    fn write(db: &Database, blobs: &BlobStorage, selection: &Selection, w: &mut dyn Write) -> Result<BTreeMap<String, usize>>;
 }
 
-#[derive(Serialize, Deserialize)]
-struct JsonFormat {
-    accounts: Vec<Account>,
-    breaches: Vec<Breach>,
-    breach_emails: Vec<BreachEmail>,
-    devices: Vec<Device>,
-    domains: Vec<Domain>,
-    emails: Vec<Email>,
-    images: Vec<Image>,
-    ipaddrs: Vec<IpAddr>,
-    netblocks: Vec<Netblock>,
-    networks: Vec<Netblock>,
-    network_devices: Vec<NetworkDevice>,
-    phonenumbers: Vec<PhoneNumber>,
-    ports: Vec<Port>,
-    subdomains: Vec<Subdomain>,
-    subdomain_ipaddrs: Vec<SubdomainIpAddr>,
-    urls: Vec<Url>,
+/// Writes every table as a field of a json object, the entities are written
+/// one chunk at a time
+struct JsonTables<'a> {
+    db: &'a Database,
+    selection: &'a Selection,
+    w: &'a mut dyn Write,
+    counts: BTreeMap<String, usize>,
 }
 
-impl ExportFormat for JsonFormat {
-    fn load(rl: &mut Shell) -> Result<Box<JsonFormat>> {
-        let db = rl.db();
-
-        Ok(Box::new(JsonFormat {
-            accounts: Account::list(db)?,
-            breaches: Breach::list(db)?,
-            breach_emails: BreachEmail::list(db)?,
-            devices: Device::list(db)?,
-            domains: Domain::list(db)?,
-            emails: Email::list(db)?,
-            images: Image::list(db)?,
-            ipaddrs: IpAddr::list(db)?,
-            netblocks: Netblock::list(db)?,
-            networks: Netblock::list(db)?,
-            network_devices: NetworkDevice::list(db)?,
-            phonenumbers: PhoneNumber::list(db)?,
-            ports: Port::list(db)?,
-            subdomains: Subdomain::list(db)?,
-            subdomain_ipaddrs: SubdomainIpAddr::list(db)?,
-            urls: Url::list(db)?,
-        }))
+impl<'a> JsonTables<'a> {
+    fn new(db: &'a Database, selection: &'a Selection, w: &'a mut dyn Write) -> JsonTables<'a> {
+        JsonTables {
+            db,
+            selection,
+            w,
+            counts: BTreeMap::new(),
+        }
+    }
+
+    fn table<T: Model + Serialize>(mut self, name: &str) -> Result<Self> {
+        let separator = if self.counts.is_empty() { "{" } else { "," };
+        write!(self.w, "{}{:?}:[", separator, name)?;
+
+        let mut count = 0;
+        let w = &mut *self.w;
+        for_each_chunk::<T, _>(self.db, name, self.selection, |chunk| {
+            for row in chunk {
+                if count > 0 {
+                    w.write_all(b",")?;
+                }
+                serde_json::to_writer(&mut *w, &row)?;
+                count += 1;
+            }
+            Ok(())
+        })?;
+
+        self.w.write_all(b"]")?;
+        self.counts.insert(name.to_string(), count);
+        Ok(self)
+    }
+
+    fn finish(self) -> Result<BTreeMap<String, usize>> {
+        self.w.write_all(b"}")?;
+        Ok(self.counts)
     }
 }
 
-#[derive(Serialize, Deserialize)]
-struct JsonBlobsFormat {
-    models: JsonFormat,
-    blobs: Vec<Blob>,
+struct JsonFormat;
+
+impl ExportFormat for JsonFormat {
+    fn write(db: &Database, _blobs: &BlobStorage, selection: &Selection, w: &mut dyn Write) -> Result<BTreeMap<String, usize>> {
+        JsonTables::new(db, selection, w)
+            .table::<Account>("accounts")?
+            .table::<Breach>("breaches")?
+            .table::<BreachEmail>("breach_emails")?
+            .table::<Device>("devices")?
+            .table::<Domain>("domains")?
+            .table::<Email>("emails")?
+            .table::<Image>("images")?
+            .table::<IpAddr>("ipaddrs")?
+            .table::<Netblock>("netblocks")?
+            .table::<Network>("networks")?
+            .table::<NetworkDevice>("network_devices")?
+            .table::<PhoneNumber>("phonenumbers")?
+            .table::<Port>("ports")?
+            .table::<Subdomain>("subdomains")?
+            .table::<SubdomainIpAddr>("subdomain_ipaddrs")?
+            .table::<Url>("urls")?
+            .finish()
+    }
 }
 
+/// The entities like `json`, followed by every blob. Blobs are loaded one
+/// at a time.
+struct JsonBlobsFormat;
+
 impl ExportFormat for JsonBlobsFormat {
-    fn load(rl: &mut Shell) -> Result<Box<JsonBlobsFormat>> {
-        let models = *JsonFormat::load(rl)?;
-        let storage = rl.blobs();
-        let blobs = storage.list()?
-            .into_iter()
-            .map(|id| storage.load(&id))
-            .collect::<Result<Vec<_>>>()?;
-        Ok(Box::new(JsonBlobsFormat {
-            models,
-            blobs,
-        }))
+    fn write(db: &Database, blobs: &BlobStorage, selection: &Selection, w: &mut dyn Write) -> Result<BTreeMap<String, usize>> {
+        w.write_all(b"{\"models\":")?;
+        let mut counts = JsonFormat::write(db, blobs, selection, w)?;
+
+        w.write_all(b",\"blobs\":[")?;
+        let ids = blobs.list()?;
+        for (i, id) in ids.iter().enumerate() {
+            if i > 0 {
+                w.write_all(b",")?;
+            }
+            serde_json::to_writer(&mut *w, &blobs.load(id)?)?;
+        }
+        w.write_all(b"]}")?;
+
+        counts.insert("blobs".to_string(), ids.len());
+        Ok(counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::prelude::*;
+
+    fn export<T: ExportFormat>(db: &Database, blobs: &BlobStorage, selection: &Selection) -> (serde_json::Value, BTreeMap<String, usize>) {
+        let mut buf = Vec::new();
+        let counts = T::write(db, blobs, selection, &mut buf).unwrap();
+        (serde_json::from_slice(&buf).unwrap(), counts)
+    }
+
+    #[test]
+    fn test_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let blobs = BlobStorage::new(dir.path());
+        let db = Database::memory().unwrap();
+        db.db().execute("INSERT INTO domains (value, unscoped) VALUES ('example.com', 0), ('example.org', 1)").unwrap();
+        db.db().execute("INSERT INTO emails (value, unscoped) VALUES ('root@example.com', 0)").unwrap();
+
+        let (json, counts) = export::<JsonFormat>(&db, &blobs, &Selection { view: None, exclude: Vec::new() });
+        let tables = json.as_object().unwrap().keys().collect::<Vec<_>>();
+        assert_eq!(tables.len(), 16);
+        assert_eq!(json["domains"].as_array().unwrap().len(), 2);
+        assert_eq!(json["emails"][0]["value"], "root@example.com");
+        assert_eq!(counts["domains"], 2);
+        assert_eq!(counts["urls"], 0);
+
+        // tables without the column are left out of the view
+        let view = Filter::parse_expr(None, "displayname=root").unwrap();
+        let (json, _) = export::<JsonFormat>(&db, &blobs, &Selection { view: Some(view), exclude: Vec::new() });
+        assert!(json["domains"].as_array().unwrap().is_empty());
+
+        let exclude = vec![Filter::parse_expr(None, "unscoped=1").unwrap()];
+        let (json, counts) = export::<JsonBlobsFormat>(&db, &blobs, &Selection { view: None, exclude });
+        assert_eq!(json["models"]["domains"][0]["value"], "example.com");
+        assert_eq!(json["models"]["domains"].as_array().unwrap().len(), 1);
+        assert_eq!(json["blobs"], serde_json::json!([]));
+        assert_eq!(counts["blobs"], 0);
+    }
+
+    #[test]
+    fn test_json_view() {
+        let dir = tempfile::tempdir().unwrap();
+        let blobs = BlobStorage::new(dir.path());
+        let db = Database::memory().unwrap();
+        db.db().execute("INSERT INTO domains (value, unscoped) VALUES ('example.com', 0), ('example.org', 1)").unwrap();
+        db.db().execute("INSERT INTO emails (value, valid, unscoped) VALUES ('root@example.com', 1, 0), ('bounce@example.com', 0, 0)").unwrap();
+
+        View::save(&db, "clean-emails", &Filter::parse_expr(None, "valid=1").unwrap()).unwrap();
+        let view = View::get(&db, "clean-emails").unwrap().filter();
+        let exclude = vec![Filter::parse_expr(None, "value like 'bounce%'").unwrap()];
+        let (json, counts) = export::<JsonFormat>(&db, &blobs, &Selection { view: Some(view.clone()), exclude });
+        assert_eq!(json["emails"].as_array().unwrap().len(), 1);
+        assert_eq!(json["emails"][0]["value"], "root@example.com");
+        assert_eq!(counts["emails"], 1);
+        assert_eq!(counts["domains"], 0);
+
+        // exclude filters only apply to the tables that have their columns
+        let exclude = vec![Filter::parse_expr(None, "unscoped=1").unwrap(),
+                           Filter::parse_expr(None, "valid=0").unwrap()];
+        let (json, counts) = export::<JsonFormat>(&db, &blobs, &Selection { view: None, exclude });
+        assert_eq!(json["domains"][0]["value"], "example.com");
+        assert_eq!(counts["domains"], 1);
+        assert_eq!(counts["emails"], 1);
+    }
+
+    #[test]
+    fn test_json_broken_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let blobs = BlobStorage::new(dir.path());
+        let db = Database::memory().unwrap();
+        // errors other than a missing column are not ignored
+        let view = Filter::new("value = ");
+        let mut buf = Vec::new();
+        assert!(JsonFormat::write(&db, &blobs, &Selection { view: Some(view), exclude: Vec::new() }, &mut buf).is_err());
     }
 }
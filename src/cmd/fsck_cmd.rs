@@ -1,12 +1,11 @@
 use clap::{ArgAction, Parser};
 use crate::blobs::Blob;
 use crate::cmd::Cmd;
+use crate::cmd::blobs_cmd;
 use crate::errors::*;
-use crate::models::*;
 use crate::shell::Shell;
 use crate::term;
 use crate::worker;
-use std::collections::HashSet;
 
 #[derive(Debug, Parser)]
 pub struct Args {
@@ -25,14 +24,8 @@ impl Cmd for Args {
     fn run(self, rl: &mut Shell) -> Result<()> {
         let blobs = rl.blobs();
 
-        let hashset = worker::spawn_fn("Building reference set...", || {
-            let mut hashset = HashSet::new();
-
-            for x in rl.db().list::<Image>()? {
-                hashset.insert(x.value);
-            }
-
-            Ok(hashset)
+        let references = worker::spawn_fn("Building reference set...", || {
+            crate::blobs::references(rl.db())
         }, true)?;
 
         for blob in blobs.list()? {
@@ -44,7 +37,7 @@ impl Cmd for Args {
                 if Blob::hash(&blob.bytes) != blob.id {
                     Ok(State::Corrupted)
                 // ensure blob is referenced by any table
-                } else if !hashset.contains(&blob.id) {
+                } else if !references.contains_key(&blob.id) {
                     Ok(State::Dangling)
                 } else {
                     Ok(State::Valid)
@@ -59,9 +52,6 @@ impl Cmd for Args {
                 },
                 Ok(State::Dangling) => {
                     term::warn(&format!("{}... dangling", blob));
-                    if self.gc || self.gc_all {
-                        blobs.delete(&blob)?;
-                    }
                 },
                 Ok(State::Corrupted) => {
                     term::error(&format!("{}... corrupted", blob));
@@ -75,6 +65,13 @@ impl Cmd for Args {
             }
         }
 
+        // dangling blobs go through the gc, it keeps the blobs a running
+        // module may not have referenced yet
+        if self.gc || self.gc_all {
+            let report = blobs.gc(rl.db(), true)?;
+            blobs_cmd::print_gc(&report);
+        }
+
         Ok(())
     }
 }
@@ -1,29 +1,40 @@
 use crate::errors::*;
+use crate::fmt::colors::{paint, Style};
 
 use crate::shell::Shell;
 
 
 #[inline]
 fn help(name: &str, descr: &str) {
-    println!("    \x1b[32m{:13}\x1b[0m {}", name, descr);
+    println!("    {} {}", paint(Style::Green, format!("{:13}", name)), descr);
 }
 
 pub fn run(_rl: &mut Shell, _args: &[String]) -> Result<()> {
 
-    println!("\n\x1b[33mCOMMANDS:\x1b[0m");
+    println!("\n{}", paint(Style::Yellow, "COMMANDS:"));
     help("add",         "Add new entities to the database");
+    help("audit",       "Review connections made by modules");
     help("autonoscope", "Manage rules to automatically remove entities from scope");
     help("autoscope",   "Manage rules to automatically add entities to scope");
+    help("blobs",       "Show stored blobs and delete unreferenced ones");
+    help("db",          "Check the database for problems and repair it");
     help("delete",      "Delete entities from the database");
+    help("diff",        "Compare the workspace with a backup or an earlier point in time");
+    help("history",     "Show and search the history of this workspace");
     help("keyring",     "Manage saved credentials");
     help("pkg",         "Manage installed modules");
     help("noscope",     "Exclude entities from scope");
+    help("provenance",  "Show where an entity came from and how it changed");
     help("run",         "Run the currently selected module");
     help("scope",       "Include entities in the scope again");
+    help("search",      "Find entities that contain some text");
     help("select",      "Select entities from the database");
     help("stats",       "Show statistics about your current workspace");
     help("target",      "Preview targeted entities or narrow them down");
+    help("timeline",    "Show when entities have been added");
+    help("tree",        "Show everything that is linked to an entity");
     help("use",         "Select a module");
+    help("view",        "Manage saved filters");
     help("workspace",   "Switch to a different workspace");
     help("help",        "Prints this message");
     println!("\nRun <command> -h for more help.\n");
@@ -0,0 +1,73 @@
+use clap::Parser;
+use crate::cmd::Cmd;
+use crate::errors::*;
+use crate::shell::Shell;
+use crate::shell::pager::Pager;
+use std::io::Write;
+
+#[derive(Debug, Parser)]
+pub struct Args {
+    /// Only show lines that contain this text, ignoring case
+    search: Option<String>,
+    /// Only show the last lines
+    #[arg(short='n', long)]
+    limit: Option<usize>,
+    /// Print everything, even if it doesn't fit on the terminal
+    #[arg(long="no-pager")]
+    no_pager: bool,
+}
+
+impl Args {
+    /// The matching lines with their position in the history, starting at 1
+    fn matches<'a, I: IntoIterator<Item=&'a String>>(&self, history: I) -> Vec<(usize, &'a String)> {
+        let search = self.search.as_ref().map(|search| search.to_lowercase());
+        let mut lines = history.into_iter()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line))
+            .filter(|(_, line)| match &search {
+                Some(search) => line.to_lowercase().contains(search),
+                None => true,
+            })
+            .collect::<Vec<_>>();
+        if let Some(limit) = self.limit {
+            let skip = lines.len().saturating_sub(limit);
+            lines.drain(..skip);
+        }
+        lines
+    }
+}
+
+impl Cmd for Args {
+    fn run(self, rl: &mut Shell) -> Result<()> {
+        let lines = self.matches(rl.history().iter())
+            .into_iter()
+            .map(|(i, line)| format!("{:>5}  {}", i, line))
+            .collect::<Vec<_>>();
+
+        let mut out = Pager::new(rl, self.no_pager);
+        for line in lines {
+            writeln!(out, "{}", line)?;
+        }
+        out.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        let history = ["use ctlogs", "run", "select subdomains", "use dns-resolve", "run"]
+            .iter()
+            .map(|line| line.to_string())
+            .collect::<Vec<_>>();
+
+        let args = Args::parse_from(["history", "USE"]);
+        assert_eq!(args.matches(&history), vec![(1, &history[0]), (4, &history[3])]);
+        let args = Args::parse_from(["history", "-n", "2"]);
+        assert_eq!(args.matches(&history), vec![(4, &history[3]), (5, &history[4])]);
+        let args = Args::parse_from(["history", "-n", "1", "run"]);
+        assert_eq!(args.matches(&history), vec![(5, &history[4])]);
+    }
+}
@@ -0,0 +1,411 @@
+use clap::Parser;
+use clap::ValueEnum;
+use crate::cmd::Cmd;
+use crate::compression;
+use crate::db::{Database, DbChange, Family, Table};
+use crate::db::batch::Batch;
+use crate::errors::*;
+use crate::models::*;
+use crate::shell::Shell;
+use crate::term;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::io::BufRead;
+use std::mem;
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+pub struct Args {
+    /// The table the entities are imported into, eg. emails
+    table: Table,
+    /// The file to import, stdin is read if this is omitted. gzip, zstd and
+    /// bzip2 compressed input is detected automatically
+    path: Option<PathBuf>,
+    /// Specify the input format
+    #[arg(short = 'f', long="format", value_enum)]
+    format: Format,
+    /// What to do with fields that don't exist on the entity
+    #[arg(long="unknown-fields", value_enum, default_value="reject")]
+    unknown_fields: UnknownFields,
+    /// Preview what the import would change without writing to the database
+    #[arg(short = 'n', long="dry-run")]
+    dry_run: bool,
+    /// List every update and conflict of the preview
+    #[arg(long, requires="dry_run")]
+    details: bool,
+    /// How many lines are written in one transaction, defaults to
+    /// core.batch-size
+    #[arg(long="batch-size")]
+    batch_size: Option<usize>,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum Format {
+    /// One json object per line
+    Ndjson,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum UnknownFields {
+    /// Skip the line
+    Reject,
+    /// Import the line and ignore the fields
+    Warn,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Summary {
+    inserted: usize,
+    updated: usize,
+    unchanged: usize,
+    skipped: usize,
+}
+
+impl Summary {
+    fn add(&mut self, other: Summary) {
+        self.inserted += other.inserted;
+        self.updated += other.updated;
+        self.unchanged += other.unchanged;
+        self.skipped += other.skipped;
+    }
+
+    #[inline]
+    fn total(&self) -> usize {
+        self.inserted + self.updated + self.unchanged + self.skipped
+    }
+}
+
+/// The lines of a batch are only counted once the batch has been committed
+struct Batched {
+    batch: Batch,
+    summary: Summary,
+    pending: Summary,
+    first: usize,
+    last: usize,
+}
+
+impl Batched {
+    fn new(size: usize) -> Batched {
+        Batched {
+            batch: Batch::new(size),
+            summary: Summary::default(),
+            pending: Summary::default(),
+            first: 0,
+            last: 0,
+        }
+    }
+
+    fn row(&mut self, db: &Database, line: usize) -> Result<()> {
+        if self.batch.is_empty() {
+            self.first = line;
+        }
+        self.last = line;
+        self.batch.row(db)
+    }
+
+    fn commit(&mut self, db: &Database) {
+        let pending = mem::take(&mut self.pending);
+        if let Err(err) = self.batch.commit(db) {
+            term::error(&format!("Failed to write lines {}-{}, all of them have been rolled back: {}", self.first, self.last, err));
+            self.summary.skipped += pending.total();
+        } else {
+            self.summary.add(pending);
+        }
+    }
+}
+
+/// What importing a single line would do
+#[derive(Debug, PartialEq)]
+enum Plan {
+    New,
+    /// The fields that would change
+    Update(String),
+    Unchanged,
+    /// The line can't be merged with the existing data
+    Conflict(String),
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Preview {
+    new: usize,
+    unchanged: usize,
+    skipped: usize,
+    /// line number, value and changed fields
+    updates: Vec<(usize, String, String)>,
+    /// line number, value and reason
+    conflicts: Vec<(usize, String, String)>,
+}
+
+impl Preview {
+    fn add(&mut self, line: usize, value: String, plan: Plan) {
+        match plan {
+            Plan::New => self.new += 1,
+            Plan::Update(fields) => self.updates.push((line, value, fields)),
+            Plan::Unchanged => self.unchanged += 1,
+            Plan::Conflict(reason) => self.conflicts.push((line, value, reason)),
+        }
+    }
+
+    fn print(&self, details: bool) {
+        let rows = [
+            ("new", self.new),
+            ("update", self.updates.len()),
+            ("unchanged", self.unchanged),
+            ("conflict", self.conflicts.len()),
+            ("skipped", self.skipped),
+        ];
+        for (label, count) in &rows {
+            println!("{:12} {:>8}", label, count);
+        }
+
+        if details {
+            for (line, value, fields) in &self.updates {
+                println!("line {}: update {:?}: {}", line, value, fields);
+            }
+            for (line, value, reason) in &self.conflicts {
+                println!("line {}: conflict {:?}: {}", line, value, reason);
+            }
+        }
+    }
+}
+
+impl Cmd for Args {
+    fn run(self, rl: &mut Shell) -> Result<()> {
+        match self.format {
+            Format::Ndjson => {
+                let reader = match &self.path {
+                    Some(path) => compression::open(path)?,
+                    None => compression::stdin()?,
+                };
+                if self.dry_run {
+                    let preview = rl.db().dry_run(|| preview_ndjson(rl, &self, reader))?;
+                    term::info(&format!("Preview of importing {} lines into {}, nothing has been written",
+                        preview.new + preview.updates.len() + preview.unchanged + preview.conflicts.len() + preview.skipped,
+                        self.table.as_str()));
+                    preview.print(self.details);
+                    return Ok(());
+                }
+
+                let summary = import_ndjson(rl, &self, reader)?;
+                term::success(&format!("Imported {} {} ({} new, {} updated, {} unchanged), skipped {} lines",
+                    summary.inserted + summary.updated + summary.unchanged,
+                    self.table.as_str(),
+                    summary.inserted,
+                    summary.updated,
+                    summary.unchanged,
+                    summary.skipped));
+            },
+        }
+        Ok(())
+    }
+}
+
+fn import_family(table: &Table) -> Result<Family> {
+    let family = table.family();
+    if let Family::Image = family {
+        bail!("Images can't be imported, add them with `add image` instead");
+    }
+    Ok(family)
+}
+
+fn import_ndjson<R: BufRead>(rl: &mut Shell, args: &Args, reader: R) -> Result<Summary> {
+    let family = import_family(&args.table)?;
+    let db = rl.db();
+
+    let mut batched = Batched::new(args.batch_size.unwrap_or(rl.config().core.batch_size));
+    for (i, line) in reader.lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                batched.commit(db);
+                return Err(Error::from(err).context("Failed to read line").into());
+            },
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let insert = match parse_line(&family, &line, args.unknown_fields) {
+            Ok(insert) => insert,
+            Err(err) => {
+                term::warn(&format!("Skipping line {}: {}", i + 1, err));
+                batched.summary.skipped += 1;
+                continue;
+            },
+        };
+
+        batched.row(db, i + 1)?;
+        let pending = &mut batched.pending;
+        match db.insert_generic(insert) {
+            Ok(Some((DbChange::Insert, _))) => pending.inserted += 1,
+            Ok(Some((DbChange::Update(_), _))) => pending.updated += 1,
+            Ok(_) => pending.unchanged += 1,
+            Err(err) => {
+                term::warn(&format!("Skipping line {}: {}", i + 1, err));
+                pending.skipped += 1;
+            },
+        }
+
+        if batched.batch.is_due() {
+            batched.commit(db);
+        }
+    }
+    batched.commit(db);
+
+    Ok(batched.summary)
+}
+
+/// Run every line through the regular upsert, the caller is responsible for
+/// rolling back the changes
+fn preview_ndjson<R: BufRead>(rl: &Shell, args: &Args, reader: R) -> Result<Preview> {
+    let family = import_family(&args.table)?;
+    let db = rl.db();
+
+    let mut preview = Preview::default();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.context("Failed to read line")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let insert = match parse_line(&family, &line, args.unknown_fields) {
+            Ok(insert) => insert,
+            Err(err) => {
+                term::warn(&format!("Skipping line {}: {}", i + 1, err));
+                preview.skipped += 1;
+                continue;
+            },
+        };
+
+        let value = insert.value(db)
+            .map(|v| v.to_string())
+            .unwrap_or_else(|_| line.trim().to_string());
+        let plan = match db.insert_generic(insert) {
+            Ok(Some((DbChange::Insert, _))) => Plan::New,
+            Ok(Some((DbChange::Update(update), _))) => Plan::Update(update.to_plain_str()),
+            Ok(Some((DbChange::None, _))) => Plan::Unchanged,
+            Ok(None) => Plan::Conflict(String::from("existing entity is out of scope")),
+            Err(err) => Plan::Conflict(err.to_string()),
+        };
+        preview.add(i + 1, value, plan);
+    }
+
+    Ok(preview)
+}
+
+fn parse_line(family: &Family, line: &str, unknown_fields: UnknownFields) -> Result<Insert> {
+    let value: serde_json::Value = serde_json::from_str(line)?;
+    let insert = match family {
+        Family::Domain => Insert::Domain(parse::<InsertDomain>(value, unknown_fields)?),
+        Family::Subdomain => Insert::Subdomain(parse::<InsertSubdomain>(value, unknown_fields)?),
+        Family::Ipaddr => Insert::IpAddr(parse::<InsertIpAddr>(value, unknown_fields)?),
+        Family::SubdomainIpaddr => Insert::SubdomainIpAddr(parse::<InsertSubdomainIpAddr>(value, unknown_fields)?),
+        Family::Url => Insert::Url(parse::<InsertUrl>(value, unknown_fields)?),
+        Family::Email => Insert::Email(parse::<InsertEmail>(value, unknown_fields)?),
+        Family::Phonenumber => Insert::PhoneNumber(parse::<InsertPhoneNumber>(value, unknown_fields)?),
+        Family::Device => Insert::Device(parse::<InsertDevice>(value, unknown_fields)?),
+        Family::Network => Insert::Network(parse::<InsertNetwork>(value, unknown_fields)?),
+        Family::NetworkDevice => Insert::NetworkDevice(parse::<InsertNetworkDevice>(value, unknown_fields)?),
+        Family::Account => Insert::Account(parse::<InsertAccount>(value, unknown_fields)?),
+        Family::Breach => Insert::Breach(parse::<InsertBreach>(value, unknown_fields)?),
+        Family::BreachEmail => Insert::BreachEmail(parse::<InsertBreachEmail>(value, unknown_fields)?),
+        Family::Image => bail!("Images can't be imported"),
+        Family::Port => Insert::Port(parse::<InsertPort>(value, unknown_fields)?),
+        Family::Netblock => Insert::Netblock(parse::<InsertNetblock>(value, unknown_fields)?),
+        Family::Cryptoaddr => Insert::CryptoAddr(parse::<InsertCryptoAddr>(value, unknown_fields)?),
+    };
+    Ok(insert)
+}
+
+fn parse<T>(value: serde_json::Value, unknown_fields: UnknownFields) -> Result<T::Target>
+    where T: InsertToNew + Serialize + DeserializeOwned
+{
+    let object: T = serde_json::from_value(value.clone())?;
+
+    // fields that are lost by the round trip don't exist on the entity
+    let known = serde_json::to_value(&object)?;
+    let unknown = match (&value, &known) {
+        (serde_json::Value::Object(value), serde_json::Value::Object(known)) => value.keys()
+            .filter(|k| !known.contains_key(k.as_str()))
+            .map(|k| k.as_str())
+            .collect::<Vec<_>>(),
+        _ => Vec::new(),
+    };
+
+    if !unknown.is_empty() {
+        let unknown = unknown.join(", ");
+        match unknown_fields {
+            UnknownFields::Reject => bail!("Unknown fields: {}", unknown),
+            UnknownFields::Warn => term::warn(&format!("Ignoring unknown fields: {}", unknown)),
+        }
+    }
+
+    object.try_into_new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_email() {
+        let insert = parse_line(&Family::Email, r#"{"value":"Root@Example.com","displayname":"root","valid":null}"#, UnknownFields::Reject).unwrap();
+        let email = match insert {
+            Insert::Email(email) => email,
+            _ => panic!("expected email"),
+        };
+        assert_eq!(email, NewEmail {
+            value: "root@example.com".to_string(),
+            displayname: Some("root".to_string()),
+            valid: None,
+            unscoped: false,
+            syntax_valid: Some(true),
+        });
+    }
+
+    #[test]
+    fn test_parse_missing_optional() {
+        let insert = parse_line(&Family::Email, r#"{"value":"root@example.com"}"#, UnknownFields::Reject).unwrap();
+        let email = match insert {
+            Insert::Email(email) => email,
+            _ => panic!("expected email"),
+        };
+        assert_eq!(email, NewEmail {
+            value: "root@example.com".to_string(),
+            displayname: None,
+            valid: None,
+            unscoped: false,
+            syntax_valid: Some(true),
+        });
+    }
+
+    #[test]
+    fn test_parse_unknown_fields() {
+        let line = r#"{"value":"root@example.com","source":"theharvester"}"#;
+        assert!(parse_line(&Family::Email, line, UnknownFields::Reject).is_err());
+        assert!(parse_line(&Family::Email, line, UnknownFields::Warn).is_ok());
+    }
+
+    #[test]
+    fn test_preview_add() {
+        let mut preview = Preview::default();
+        preview.add(1, "a@example.com".to_string(), Plan::New);
+        preview.add(2, "a@example.com".to_string(), Plan::Update("displayname => \"a\"".to_string()));
+        preview.add(3, "b@example.com".to_string(), Plan::Unchanged);
+        preview.add(4, "c@example.com".to_string(), Plan::Conflict("existing entity is out of scope".to_string()));
+        assert_eq!(preview, Preview {
+            new: 1,
+            unchanged: 1,
+            skipped: 0,
+            updates: vec![(2, "a@example.com".to_string(), "displayname => \"a\"".to_string())],
+            conflicts: vec![(4, "c@example.com".to_string(), "existing entity is out of scope".to_string())],
+        });
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        assert!(parse_line(&Family::Email, "{\"value\":", UnknownFields::Reject).is_err());
+        assert!(parse_line(&Family::Email, "[]", UnknownFields::Reject).is_err());
+        assert!(parse_line(&Family::Email, r#"{"displayname":"root"}"#, UnknownFields::Reject).is_err());
+    }
+}
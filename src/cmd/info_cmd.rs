@@ -0,0 +1,56 @@
+use crate::errors::*;
+use crate::cmd::LiteCmd;
+use crate::config::Config;
+use crate::engine::{Library, Module};
+use clap::Parser;
+use colored::Colorize;
+use sn0int_common::metadata::Source;
+
+#[derive(Debug, Parser)]
+pub struct Args {
+    /// The module to show
+    module: String,
+}
+
+fn source(source: &Option<Source>) -> String {
+    match source {
+        Some(source) => source.to_string(),
+        None => String::from("-"),
+    }
+}
+
+fn egress(module: &Module) -> String {
+    match module.egress() {
+        Some([]) => String::from("none"),
+        Some(rules) => rules.iter()
+            .map(|rule| rule.to_string())
+            .collect::<Vec<_>>()
+            .join(", "),
+        None => String::from("undeclared"),
+    }
+}
+
+fn print_info(module: &Module) {
+    println!("{} {}", module.canonical().bold(), module.version().blue());
+    println!("    {}", module.description());
+    println!();
+    println!("{:>16}: {}", "source", source(module.source()));
+    println!("{:>16}: {}", "stealth", format!("{:?}", module.stealth()).to_lowercase());
+    if !module.keyring_access().is_empty() {
+        println!("{:>16}: {}", "keyring access", module.keyring_access().join(", "));
+    }
+    println!("{:>16}: {}", "egress", egress(module));
+    if !module.emits().is_empty() {
+        println!("{:>16}: {}", "emits", module.emits().join(", "));
+    }
+    println!("{:>16}: {}", "capabilities", module.capabilities());
+}
+
+impl LiteCmd for Args {
+    fn run(self, config: &Config) -> Result<()> {
+        let library = Library::new(false, config)?;
+        let module = library.get(&self.module)?;
+        print_info(module);
+        Ok(())
+    }
+}
@@ -1,23 +1,48 @@
 use clap::Parser;
+use crate::cmd::Cmd;
 use crate::errors::*;
-use crate::keyring::{KeyName, KeyRing};
+use crate::keyring::{EncryptedExport, ExportFile, KeyName, KeyRing, PASSPHRASE_ENV};
 use crate::shell::Shell;
+use crate::shell::pager::Pager;
+use crate::term;
 use crate::utils;
+use crate::workspaces::Workspace;
+use chrono::Utc;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Parser)]
-pub enum Args {
+#[group(skip)]
+pub struct Args {
+    #[command(subcommand)]
+    subcommand: Subcommand,
+}
+
+#[derive(Debug, Parser)]
+pub enum Subcommand {
     /// Add a new key to the keyring
     Add(KeyRingAdd),
     /// Delete a key from the keyring
     Delete(KeyRingDelete),
+    /// Bind a key to a workspace or make it global
+    Move(KeyRingMove),
     /// Get a key from the keyring
     Get(KeyRingGet),
     /// List keys in the keyring
     List(KeyRingList),
+    /// Export all keys, encrypted with a passphrase
+    Export(KeyRingExport),
+    /// Import keys from an export
+    Import(KeyRingImport),
 }
 
 #[derive(Debug, Parser)]
 pub struct KeyRingAdd {
+    /// Only use this key in the given workspace
+    #[arg(short = 'w', long="workspace")]
+    workspace: Option<Workspace>,
     key: KeyName,
     secret: Option<String>,
 }
@@ -27,6 +52,17 @@ pub struct KeyRingDelete {
     key: KeyName,
 }
 
+#[derive(Debug, Parser)]
+pub struct KeyRingMove {
+    key: KeyName,
+    /// The workspace the key is bound to
+    #[arg(short = 'w', long="workspace", required_unless_present="global")]
+    workspace: Option<Workspace>,
+    /// Make the key available in all workspaces
+    #[arg(long="global", conflicts_with="workspace")]
+    global: bool,
+}
+
 #[derive(Debug, Parser)]
 pub struct KeyRingGet {
     key: KeyName,
@@ -38,15 +74,46 @@ pub struct KeyRingGet {
 #[derive(Debug, Parser)]
 pub struct KeyRingList {
     namespace: Option<String>,
+    /// Show how often the keys have been used
+    #[arg(long="usage")]
+    usage: bool,
+    /// Print everything, even if it doesn't fit on the terminal
+    #[arg(long="no-pager")]
+    no_pager: bool,
 }
 
-pub fn run(rl: &mut Shell, args: &[String]) -> Result<()> {
-    let args = Args::try_parse_from(args)?;
-    match args {
-        Args::Add(add) => keyring_add(rl, add),
-        Args::Delete(delete) => keyring_delete(rl, delete),
-        Args::Get(get) => keyring_get(rl.keyring(), &get),
-        Args::List(list) => keyring_list(rl.keyring(), list),
+#[derive(Debug, Parser)]
+pub struct KeyRingExport {
+    /// The file the export is written to
+    #[arg(short = 'f', long="file")]
+    file: PathBuf,
+    /// Write the secrets without encryption
+    #[arg(long="plaintext")]
+    plaintext: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct KeyRingImport {
+    file: PathBuf,
+    /// Replace existing keys that have a different secret
+    #[arg(long="overwrite")]
+    overwrite: bool,
+}
+
+impl Cmd for Args {
+    fn run(self, rl: &mut Shell) -> Result<()> {
+        match self.subcommand {
+            Subcommand::Add(add) => keyring_add(rl, add),
+            Subcommand::Delete(delete) => keyring_delete(rl, delete),
+            Subcommand::Move(mv) => keyring_move(rl, mv),
+            Subcommand::Get(get) => keyring_get(rl.keyring(), &get),
+            Subcommand::List(list) => {
+                let out = Pager::new(rl, list.no_pager);
+                keyring_list(rl.keyring(), list, out)
+            },
+            Subcommand::Export(export) => keyring_export(rl.keyring(), &export),
+            Subcommand::Import(import) => keyring_import(rl, &import),
+        }
     }
 }
 
@@ -59,18 +126,38 @@ fn keyring_add(rl: &mut Shell, add: KeyRingAdd) -> Result<()> {
         None => utils::question_opt("Secretkey")?,
     };
 
-    keyring.insert(add.key, secret)?;
+    // re-adding a key without --workspace keeps its binding
+    keyring.insert(add.key.clone(), secret)?;
+    if let Some(workspace) = add.workspace {
+        keyring.bind(&add.key, Some(workspace.to_string()))?;
+    }
     rl.reload_keyring_cache();
     Ok(())
 }
 
 fn keyring_delete(rl: &mut Shell, delete: KeyRingDelete) -> Result<()> {
     let keyring = rl.keyring_mut();
+    if keyring.is_ephemeral(&delete.key) {
+        term::warn(&format!("Key {} is set with an environment variable and can't be deleted", delete.key));
+    }
     keyring.delete(delete.key)?;
     rl.reload_keyring_cache();
     Ok(())
 }
 
+fn keyring_move(rl: &mut Shell, mv: KeyRingMove) -> Result<()> {
+    let workspace = mv.workspace.map(|x| x.to_string());
+    rl.keyring_mut().bind(&mv.key, workspace.clone())?;
+    rl.reload_keyring_cache();
+
+    if let Some(workspace) = workspace {
+        term::success(&format!("Key {} is only available in workspace {:?}", mv.key, workspace));
+    } else {
+        term::success(&format!("Key {} is available in all workspaces", mv.key));
+    }
+    Ok(())
+}
+
 fn keyring_get(keyring: &KeyRing, get: &KeyRingGet) -> Result<()> {
     if let Some(key) = keyring.get(&get.key) {
         if get.quiet {
@@ -83,20 +170,121 @@ fn keyring_get(keyring: &KeyRing, get: &KeyRingGet) -> Result<()> {
             if let Some(secret_key) = key.secret_key {
                 println!("Secret:       {:?}", secret_key);
             }
+            if let Some(workspace) = key.workspace {
+                println!("Workspace:    {:?}", workspace);
+            }
         }
     }
     Ok(())
 }
 
-fn keyring_list(keyring: &KeyRing, list: KeyRingList) -> Result<()> {
-    let list = match list.namespace {
-        Some(namespace) => keyring.list_for(&namespace),
+fn keyring_list(keyring: &KeyRing, list: KeyRingList, mut out: Pager) -> Result<()> {
+    let keys = match &list.namespace {
+        Some(namespace) => keyring.list_for(namespace),
         None => keyring.list(),
     };
 
-    for key in list {
-        println!("{}:{}", key.namespace, key.name);
+    let now = Utc::now().naive_utc();
+    for key in keys {
+        let mut line = format!("{}:{}", key.namespace, key.name);
+        if let Some(workspace) = keyring.workspace_of(&key) {
+            line.push_str(&format!(" [{}]", workspace));
+        }
+        if keyring.is_ephemeral(&key) {
+            line.push_str(" (ephemeral)");
+        }
+        if let Some(until) = keyring.exhausted_until(&key, now) {
+            line.push_str(&format!(" (exhausted until {})", until));
+        }
+        if list.usage {
+            match keyring.usage(&key) {
+                Some(usage) => {
+                    line.push_str(&format!(": {} requests in the last run, {} total", usage.last_run, usage.total));
+                    if let Some(last_used) = usage.last_used {
+                        line.push_str(&format!(", last used {}", last_used.format("%Y-%m-%d %H:%M:%S")));
+                    }
+                },
+                None => line.push_str(": never used"),
+            }
+        }
+        writeln!(out, "{}", line)?;
+    }
+
+    out.finish()
+}
+
+fn passphrase(confirm: bool) -> Result<String> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+
+    let passphrase = utils::question_password("Passphrase")?;
+    if passphrase.is_empty() {
+        bail!("Passphrase can not be empty");
     }
+    if confirm && utils::question_password("Repeat passphrase")? != passphrase {
+        bail!("Passphrases don't match");
+    }
+    Ok(passphrase)
+}
 
+#[cfg(unix)]
+fn write_private(path: &Path, data: &[u8]) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .context(format!("Failed to create {:?}", path))?;
+    file.write_all(data)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, data: &[u8]) -> Result<()> {
+    fs::write(path, data)
+        .context(format!("Failed to create {:?}", path))?;
+    Ok(())
+}
+
+fn keyring_export(keyring: &KeyRing, export: &KeyRingExport) -> Result<()> {
+    let entries = keyring.export();
+
+    let mut data = if export.plaintext {
+        term::warn("Writing secrets without encryption, anybody who can read this file can use your keys");
+        serde_json::to_vec(&entries)?
+    } else {
+        let passphrase = passphrase(true)?;
+        serde_json::to_vec(&EncryptedExport::encrypt(&entries, &passphrase)?)?
+    };
+    data.push(b'\n');
+
+    write_private(&export.file, &data)?;
+    term::success(&format!("Exported {} keys to {:?}", entries.len(), export.file));
+    Ok(())
+}
+
+fn keyring_import(rl: &mut Shell, import: &KeyRingImport) -> Result<()> {
+    let data = fs::read(&import.file)
+        .context(format!("Failed to read {:?}", import.file))?;
+    let file = serde_json::from_slice::<ExportFile>(&data)
+        .context("Failed to parse keyring export")?;
+
+    let entries = match file {
+        ExportFile::Encrypted(export) => export.decrypt(&passphrase(false)?)?,
+        ExportFile::Plaintext(entries) => entries,
+    };
+
+    let summary = rl.keyring_mut().import(entries, import.overwrite)?;
+    rl.reload_keyring_cache();
+
+    for key in &summary.conflicts {
+        term::warn(&format!("Key {} already exists with a different secret or workspace, use --overwrite to replace it", key));
+    }
+    term::success(&format!("Imported keys ({} new, {} updated, {} unchanged, {} conflicts)",
+        summary.added, summary.updated, summary.unchanged, summary.conflicts.len()));
     Ok(())
 }
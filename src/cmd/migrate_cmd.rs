@@ -0,0 +1,70 @@
+use clap::Parser;
+use chrono::Utc;
+use crate::config::Config;
+use crate::db::Database;
+use crate::errors::*;
+use crate::migrations::{self, Status};
+use crate::paths;
+use crate::term;
+use crate::workspaces::Workspace;
+use diesel::prelude::*;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+pub struct Args {
+    /// Only show pending migrations
+    #[arg(long="dry-run")]
+    dry_run: bool,
+}
+
+fn backup(db: &SqliteConnection, workspace: &Workspace) -> Result<PathBuf> {
+    // make sure everything from the write ahead log is in the main file
+    db.execute("PRAGMA wal_checkpoint(TRUNCATE)")
+        .context("Failed to checkpoint write ahead log")?;
+
+    let src = workspace.db_path()?;
+    let dest = paths::workspace_dir(workspace)?
+        .join(format!("db.sqlite.{}.bak", Utc::now().format("%Y%m%d%H%M%S")));
+    fs::copy(&src, &dest)
+        .context("Failed to backup database")?;
+
+    Ok(dest)
+}
+
+impl Args {
+    pub fn run(self, workspace: Workspace, config: &Config) -> Result<()> {
+        workspace.migrate()?;
+        let db = Database::connect(&workspace, &config.database)?;
+
+        match migrations::status(&db)? {
+            Status::UpToDate => {
+                term::success(&format!("Workspace {:?} is up to date", workspace.as_str()));
+                return Ok(());
+            },
+            Status::Unknown(unknown) => {
+                bail!("Workspace {:?} has been migrated by a newer version of sn0int: {:?}",
+                    workspace.as_str(), unknown);
+            },
+            Status::Fresh => (),
+            Status::Pending(pending) => {
+                for version in pending {
+                    term::info(&format!("Pending migration: {}", version));
+                }
+            },
+        }
+
+        if self.dry_run {
+            return Ok(());
+        }
+
+        let path = backup(&db, &workspace)?;
+        term::info(&format!("Database has been backed up to {:?}", path));
+
+        let applied = migrations::run_verbose(&db)
+            .context("Failed to run migrations")?;
+        term::success(&format!("Applied {} migrations", applied.len()));
+
+        Ok(())
+    }
+}
@@ -18,24 +18,42 @@ pub trait LiteCmd: clap::Parser + Sized {
 
 pub mod activity_cmd;
 pub mod add_cmd;
+pub mod audit_cmd;
 pub mod autonoscope_cmd;
 pub mod autoscope_cmd;
+pub mod bench_cmd;
+pub mod blobs_cmd;
 pub mod cal_cmd;
+pub mod db_cmd;
 pub mod delete_cmd;
+pub mod diff_cmd;
 pub mod export_cmd;
 pub mod fsck_cmd;
 pub mod help_cmd;
+pub mod history_cmd;
+pub mod import_cmd;
+pub mod info_cmd;
+pub mod migrate_cmd;
+pub mod new_cmd;
 pub mod run_cmd;
 pub mod use_cmd;
+pub mod verify_cmd;
+pub mod view_cmd;
 pub mod select_cmd;
 pub mod keyring_cmd;
 pub mod noscope_cmd;
 pub mod notify_cmd;
 pub mod pkg_cmd;
+pub mod provenance_cmd;
 pub mod rescope_cmd;
 pub mod set_cmd;
+pub mod scheduler_cmd;
 pub mod scope_cmd;
+pub mod search_cmd;
+pub mod serve_cmd;
 pub mod stats_cmd;
 pub mod target_cmd;
+pub mod timeline_cmd;
+pub mod tree_cmd;
 pub mod quickstart_cmd;
 pub mod workspace_cmd;
@@ -0,0 +1,173 @@
+use crate::errors::*;
+use crate::cmd::LiteCmd;
+use crate::config::Config;
+use crate::term;
+use clap::Parser;
+use sn0int_common::ModuleID;
+use sn0int_common::metadata::{Capability, Source};
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Parser)]
+pub struct Args {
+    /// The path of the new file, or the name of the module. A name is created
+    /// in the folder of its namespace if one is configured, `<author>/<name>`
+    /// picks the namespace if more than one is configured
+    module: String,
+    /// The entities the module is executed with, eg. domains
+    #[arg(long="source")]
+    source: Option<Source>,
+    /// Declare access to a keyring namespace
+    #[arg(long="keyring")]
+    keyring: Vec<String>,
+    /// Declare a capability the module uses, eg. http
+    #[arg(long="capability")]
+    capabilities: Vec<Capability>,
+}
+
+/// Find the file for a new module, names are created in the folder of a
+/// namespace from the config. Anything else is used as a path, like it
+/// always has been.
+fn module_path(config: &Config, module: &str) -> Result<PathBuf> {
+    if module.ends_with(".lua") {
+        return Ok(PathBuf::from(module));
+    }
+
+    let folders = config.namespace_folders()?;
+    let namespace = if let Ok(id) = module.parse::<ModuleID>() {
+        folders.into_iter()
+            .find(|(author, _)| **author == id.author)
+            .map(|(_, folder)| (folder, id.name))
+    } else if module.contains('/') {
+        None
+    } else {
+        match folders.as_slice() {
+            [(_, folder)] => Some((folder.clone(), module.to_string())),
+            [] => None,
+            _ => bail!("More than one namespace is configured, use <author>/{}", module),
+        }
+    };
+
+    match namespace {
+        Some((folder, name)) => Ok(folder.join(format!("{}.lua", name))),
+        None => Ok(PathBuf::from(module)),
+    }
+}
+
+fn boilerplate(args: &Args) -> Result<String> {
+    let mut out = String::new();
+    writeln!(out, "-- Description: TODO your description here")?;
+    writeln!(out, "-- Version: 0.1.0")?;
+    if let Some(source) = &args.source {
+        writeln!(out, "-- Source: {}", source)?;
+    }
+    for namespace in &args.keyring {
+        writeln!(out, "-- Keyring-Access: {}", namespace)?;
+    }
+    if !args.capabilities.is_empty() {
+        let capabilities = args.capabilities.iter()
+            .map(Capability::as_str)
+            .collect::<Vec<_>>();
+        writeln!(out, "-- Capabilities: {}", capabilities.join(", "))?;
+    }
+    writeln!(out, "-- License: GPL-3.0")?;
+    writeln!(out)?;
+
+    if args.source.is_some() {
+        writeln!(out, "function run(arg)")?;
+    } else {
+        writeln!(out, "function run()")?;
+    }
+    for namespace in &args.keyring {
+        writeln!(out, "    local creds = keyring('{}')", namespace)?;
+        writeln!(out, "    if #creds == 0 then")?;
+        writeln!(out, "        return 'No {} credentials in keyring'", namespace)?;
+        writeln!(out, "    end")?;
+        writeln!(out)?;
+    }
+    if args.capabilities.contains(&Capability::Http) {
+        writeln!(out, "    local session = http_mksession()")?;
+        writeln!(out, "    local req = http_request(session, 'GET', 'https://example.com/', {{}})")?;
+        writeln!(out, "    local resp = http_send(req)")?;
+        writeln!(out, "    if last_err() then return end")?;
+        writeln!(out, "    if resp['status'] ~= 200 then")?;
+        writeln!(out, "        return 'http error: ' .. resp['status']")?;
+        writeln!(out, "    end")?;
+        writeln!(out)?;
+    }
+    writeln!(out, "    -- TODO your code here, check last_err() after every call that may fail")?;
+    writeln!(out, "end")?;
+    Ok(out)
+}
+
+fn create_new(path: &Path, data: &str) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .context(format_err!("Failed to create {:?}", path))?;
+    file.write_all(data.as_bytes())?;
+    Ok(())
+}
+
+impl LiteCmd for Args {
+    fn run(self, config: &Config) -> Result<()> {
+        let path = module_path(config, &self.module)?;
+        create_new(&path, &boilerplate(&self)?)?;
+        term::info(&format!("Created {:?}", path));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::ctx::Script;
+    use sn0int_common::metadata::Metadata;
+
+    fn args(cmdline: &[&str]) -> Args {
+        Args::try_parse_from([&["new"], cmdline].concat()).unwrap()
+    }
+
+    #[test]
+    fn test_boilerplate() {
+        let args = args(&["foo", "--source", "domains", "--keyring", "shodan", "--capability", "http"]);
+        let code = boilerplate(&args).unwrap();
+
+        let metadata = code.parse::<Metadata>().unwrap();
+        assert_eq!(metadata.source, Some(Source::Domains));
+        assert_eq!(metadata.keyring_access, &["shodan"]);
+        assert_eq!(metadata.capabilities, Some(vec![Capability::Http]));
+        Script::load_unchecked(code).unwrap();
+    }
+
+    #[test]
+    fn test_boilerplate_plain() {
+        let code = boilerplate(&args(&["foo"])).unwrap();
+        let metadata = code.parse::<Metadata>().unwrap();
+        assert_eq!(metadata.source, None);
+        assert_eq!(metadata.capabilities, None);
+        assert!(code.contains("function run()\n"));
+    }
+
+    #[test]
+    fn test_module_path() {
+        let mut config = Config::default();
+        assert_eq!(module_path(&config, "./first.lua").unwrap(), Path::new("./first.lua"));
+        // without namespaces the name is a path, like before
+        assert_eq!(module_path(&config, "first").unwrap(), Path::new("first"));
+        assert_eq!(module_path(&config, "me/first").unwrap(), Path::new("me/first"));
+
+        config.namespaces.insert("me".to_string(), PathBuf::from("/opt/modules/me"));
+        assert_eq!(module_path(&config, "first").unwrap(), Path::new("/opt/modules/me/first.lua"));
+        assert_eq!(module_path(&config, "me/first").unwrap(), Path::new("/opt/modules/me/first.lua"));
+        assert_eq!(module_path(&config, "other/first").unwrap(), Path::new("other/first"));
+        assert_eq!(module_path(&config, "./other/first").unwrap(), Path::new("./other/first"));
+
+        config.namespaces.insert("corp".to_string(), PathBuf::from("/opt/modules/corp"));
+        assert!(module_path(&config, "first").is_err());
+        assert_eq!(module_path(&config, "corp/first").unwrap(), Path::new("/opt/modules/corp/first.lua"));
+    }
+}
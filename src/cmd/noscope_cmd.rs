@@ -1,7 +1,7 @@
 use clap::Parser;
 use crate::cmd::Cmd;
 use crate::errors::*;
-use crate::filters::{Target, Filter};
+use crate::filters::Target;
 use crate::models::*;
 use crate::shell::Shell;
 use crate::term;
@@ -15,20 +15,20 @@ pub struct Args {
 impl Cmd for Args {
     fn run(self, rl: &mut Shell) -> Result<()> {
         let rows = match self.subcommand {
-            Target::Domains(filter) => noscope::<Domain>(rl, &filter),
-            Target::Subdomains(filter) => noscope::<Subdomain>(rl, &filter),
-            Target::IpAddrs(filter) => noscope::<IpAddr>(rl, &filter),
-            Target::Urls(filter) => noscope::<Url>(rl, &filter),
-            Target::Emails(filter) => noscope::<Email>(rl, &filter),
-            Target::PhoneNumbers(filter) => noscope::<PhoneNumber>(rl, &filter),
-            Target::Devices(filter) => noscope::<Device>(rl, &filter),
-            Target::Networks(filter) => noscope::<Network>(rl, &filter),
-            Target::Accounts(filter) => noscope::<Account>(rl, &filter),
-            Target::Breaches(filter) => noscope::<Breach>(rl, &filter),
-            Target::Images(filter) => noscope::<Image>(rl, &filter),
-            Target::Ports(filter) => noscope::<Port>(rl, &filter),
-            Target::Netblocks(filter) => noscope::<Netblock>(rl, &filter),
-            Target::CryptoAddrs(filter) => noscope::<CryptoAddr>(rl, &filter),
+            Target::Domains(_) => noscope::<Domain>(rl, &self.subcommand),
+            Target::Subdomains(_) => noscope::<Subdomain>(rl, &self.subcommand),
+            Target::IpAddrs(_) => noscope::<IpAddr>(rl, &self.subcommand),
+            Target::Urls(_) => noscope::<Url>(rl, &self.subcommand),
+            Target::Emails(_) => noscope::<Email>(rl, &self.subcommand),
+            Target::PhoneNumbers(_) => noscope::<PhoneNumber>(rl, &self.subcommand),
+            Target::Devices(_) => noscope::<Device>(rl, &self.subcommand),
+            Target::Networks(_) => noscope::<Network>(rl, &self.subcommand),
+            Target::Accounts(_) => noscope::<Account>(rl, &self.subcommand),
+            Target::Breaches(_) => noscope::<Breach>(rl, &self.subcommand),
+            Target::Images(_) => noscope::<Image>(rl, &self.subcommand),
+            Target::Ports(_) => noscope::<Port>(rl, &self.subcommand),
+            Target::Netblocks(_) => noscope::<Netblock>(rl, &self.subcommand),
+            Target::CryptoAddrs(_) => noscope::<CryptoAddr>(rl, &self.subcommand),
         }?;
         term::info(&format!("Updated {} rows", rows));
         Ok(())
@@ -41,6 +41,6 @@ pub fn run(rl: &mut Shell, args: &[String]) -> Result<()> {
 }
 
 #[inline]
-fn noscope<T: Model + Detailed>(rl: &mut Shell, filter: &Filter) -> Result<usize> {
-    T::noscope(rl.db(), &filter.parse()?)
+fn noscope<T: Model + Detailed>(rl: &mut Shell, target: &Target) -> Result<usize> {
+    rl.db().set_scoped::<T>(&target.parse(rl.db())?, false)
 }
@@ -2,7 +2,7 @@ use clap::{ArgAction, Parser};
 use crate::cmd::Cmd;
 use crate::engine::Module;
 use crate::errors::*;
-use crate::notify::{self, Notification};
+use crate::notify::{self, Notification, Route};
 use crate::options::{self, Opt};
 use crate::shell::Shell;
 use crate::term;
@@ -25,6 +25,8 @@ pub enum Subcommand {
     Exec(ExecArgs),
     /// Try to deliver all messages in our outbox
     Deliver,
+    /// Show which notification rules would fire for an event
+    Test(TestArgs),
 }
 
 #[derive(Debug, Parser)]
@@ -32,6 +34,9 @@ pub struct SendArgs {
     /// Evaluate the routing rules, but do not actually send a notification
     #[arg(short = 'n', long)]
     pub dry_run: bool,
+    /// Only send to this notification config, without checking its rules
+    #[arg(long)]
+    pub route: Option<String>,
     pub topic: String,
     #[command(flatten)]
     pub notification: Notification,
@@ -48,6 +53,13 @@ pub struct ExecArgs {
     pub notification: Notification,
 }
 
+#[derive(Debug, Parser)]
+pub struct TestArgs {
+    pub topic: String,
+    /// The entity of the event as json, used for filters
+    pub entity: Option<String>,
+}
+
 fn print_summary(module: &Module, sent: usize, errors: usize) {
     let mut out = if sent == 1 {
         String::from("Sent 1 notification")
@@ -66,11 +78,45 @@ fn print_summary(module: &Module, sent: usize, errors: usize) {
 
 fn send(args: SendArgs, rl: &mut Shell) -> Result<()> {
     rl.signal_register().catch_ctrl();
-    notify::run_router(rl, &mut term::Term, &mut Ratelimiter::new(), args.dry_run, &args.topic, &args.notification)?;
+    let mut ratelimit = Ratelimiter::new();
+    if let Some(route) = &args.route {
+        notify::send_route(rl, &mut term::Term, &mut ratelimit, args.dry_run, route, &args.topic, &args.notification)?;
+    } else {
+        notify::run_router(rl, &mut term::Term, &mut ratelimit, args.dry_run, &args.topic, &args.notification)?;
+    }
+    notify::flush_due(rl, &mut term::Term);
     rl.signal_register().reset_ctrlc();
     Ok(())
 }
 
+fn test(args: TestArgs, rl: &mut Shell) -> Result<()> {
+    let entity = args.entity
+        .map(|x| serde_json::from_str::<serde_json::Value>(&x))
+        .transpose()
+        .context("Failed to parse entity json")?;
+
+    let mut configs = rl.config().notifications.iter().collect::<Vec<_>>();
+    configs.sort_by_key(|(name, _)| name.as_str());
+
+    let mut matches = 0;
+    for (name, config) in configs {
+        match config.route(name, rl.workspace(), &args.topic, rl.db(), entity.as_ref()) {
+            Route::Matches => {
+                term::success(&format!("{:?} matches", name));
+                matches += 1;
+            },
+            Route::Workspace => debug!("{:?} is not enabled for this workspace", name),
+            Route::Topic => debug!("{:?} doesn't match the topic", name),
+            Route::Filter => term::info(&format!("{:?} matches the topic, but not the filter", name)),
+        }
+    }
+
+    if matches == 0 {
+        term::warn("No notification rule matches this event");
+    }
+    Ok(())
+}
+
 fn exec(args: ExecArgs, rl: &mut Shell) -> Result<()> {
     let module = rl.library().get(&args.module)?.clone();
     let options = Opt::collect(&args.options);
@@ -91,6 +137,7 @@ impl Cmd for Args {
             Subcommand::Outbox => todo!(),
             Subcommand::Exec(args) => exec(args, rl),
             Subcommand::Deliver => todo!(),
+            Subcommand::Test(args) => test(args, rl),
         }
     }
 }
@@ -1,12 +1,17 @@
 use crate::errors::*;
-use crate::args::Install;
+use crate::args::{Install, Package};
 use crate::api::Client;
 use crate::args;
-use crate::config::Config;
+use crate::config::{Config, DEFAULT_REGISTRY};
 use crate::cmd::{Cmd, LiteCmd};
 use crate::engine::{Library, Module};
-use crate::registry::{self, InstallTask, UpdateTask, Updater};
+use crate::git::{GitOrigin, GitOrigins};
+use crate::links::{self, Links};
+use crate::lockfile::{LockedModule, Lockfile};
+use crate::registry::{self, InstallTask, Outdated, UpdateTask, Updater};
 use crate::shell::Shell;
+use crate::signing::Verification;
+use crate::term;
 use crate::update::AutoUpdater;
 use crate::worker;
 use colored::{Color, Colorize};
@@ -14,6 +19,7 @@ use sn0int_common::ModuleID;
 use sn0int_common::metadata::Stealth;
 use std::collections::HashSet;
 use std::fmt::Write;
+use std::path::PathBuf;
 use std::sync::Arc;
 use clap::Parser;
 
@@ -34,21 +40,39 @@ pub enum SubCommand {
     /// List installed modules
     #[command(name="list")]
     List(List),
-    /// Install module from registry
+    /// Install module from registry or a git repository
     #[command(name="install")]
     Install(args::Install),
     /// Search modules in registry
     #[command(name="search")]
     Search(args::Search),
+    /// Rate a module in the registry
+    #[command(name="rate")]
+    Rate(args::Rate),
+    /// Show installed modules that have a newer release
+    #[command(name="outdated")]
+    Outdated,
     /// Update modules
     #[command(name="update")]
     Update(Update),
     /// Uninstall a module
     #[command(name="uninstall")]
     Uninstall(Uninstall),
+    /// Keep a module at its version during updates
+    #[command(name="pin")]
+    Pin(Pin),
+    /// Allow updates of a pinned module again
+    #[command(name="unpin")]
+    Unpin(Unpin),
     /// Install all featured modules
     #[command(name="quickstart")]
     Quickstart,
+    /// Load a module from a file during development
+    #[command(name="link")]
+    Link(Link),
+    /// Remove a linked module
+    #[command(name="unlink")]
+    Unlink(Unlink),
 }
 
 #[derive(Debug, Parser)]
@@ -82,6 +106,18 @@ pub struct Reload {
 
 #[derive(Debug, Parser)]
 pub struct Update {
+    /// Update every installed module, this is the default if no module is given
+    #[arg(long="all", conflicts_with="modules")]
+    pub all: bool,
+    /// Only update these modules
+    pub modules: Vec<ModuleID>,
+    /// Also update pinned modules and remove their pin
+    #[arg(long="unpin")]
+    pub unpin: bool,
+    /// Update modules even if their signature doesn't match the pinned key
+    /// of the author
+    #[arg(long="insecure-skip-verify")]
+    pub insecure_skip_verify: bool,
 }
 
 #[derive(Debug, Parser)]
@@ -89,6 +125,31 @@ pub struct Uninstall {
     module: ModuleID,
 }
 
+#[derive(Debug, Parser)]
+pub struct Pin {
+    module: ModuleID,
+    /// Install this version before pinning it
+    version: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct Unpin {
+    module: ModuleID,
+}
+
+#[derive(Debug, Parser)]
+pub struct Link {
+    /// The module file, it's read again every time the module is executed
+    path: PathBuf,
+    /// The name of the module, defaults to `<directory>/<file name>` of the path
+    module: Option<ModuleID>,
+}
+
+#[derive(Debug, Parser)]
+pub struct Unlink {
+    module: ModuleID,
+}
+
 #[derive(PartialEq)]
 enum ModuleReload {
     Yes,
@@ -101,7 +162,7 @@ fn write_tag(out: &mut String, color: Color, txt: &str) -> Result<()> {
     Ok(())
 }
 
-fn print_module(module: &Module, is_outdated: bool) -> Result<()> {
+fn print_module(module: &Module, is_outdated: bool, locked: Option<&LockedModule>, origin: Option<&GitOrigin>) -> Result<()> {
     let mut out = String::new();
     write!(&mut out, "{}/{} {}", module.author().purple(),
                                  module.name(),
@@ -114,12 +175,93 @@ fn print_module(module: &Module, is_outdated: bool) -> Result<()> {
         Stealth::Offline => write_tag(&mut out, Color::Green, "offline")?,
     };
 
+    if let Some(locked) = locked {
+        if locked.pinned {
+            write_tag(&mut out, Color::Cyan, "pinned")?;
+        }
+
+        match locked.verification {
+            Verification::Verified(_) => write_tag(&mut out, Color::Green, "verified")?,
+            Verification::Unsigned => write_tag(&mut out, Color::Yellow, "unsigned")?,
+            Verification::Skipped => write_tag(&mut out, Color::Red, "unverified")?,
+        }
+    }
+
     if is_outdated {
         write_tag(&mut out, Color::Red, "outdated")?;
     }
 
+    if module.linked().is_some() {
+        write_tag(&mut out, Color::Cyan, "linked")?;
+    }
+
     println!("{}", out.bold());
     println!("    {}", module.description());
+    if let Some(path) = module.linked() {
+        println!("    linked: {:?}", path);
+    }
+    if let Some(origin) = origin {
+        println!("    origin: {}", origin);
+    }
+    if let Some(LockedModule { verification: Verification::Verified(key), .. }) = locked {
+        println!("    signed by: {}", key);
+    }
+    if let Some(registry) = locked.and_then(|x| x.registry.as_ref()) {
+        if registry != DEFAULT_REGISTRY {
+            println!("    registry: {}", registry);
+        }
+    }
+    Ok(())
+}
+
+fn print_outdated(outdated: &Outdated, pinned: bool) -> Result<()> {
+    let (author, name) = outdated.canonical.split_once('/')
+        .unwrap_or(("", &outdated.canonical));
+    let mut out = format!("{}/{} {} -> {}", author.purple(),
+                                            name,
+                                            outdated.installed.blue(),
+                                            outdated.latest.blue());
+    if pinned {
+        write_tag(&mut out, Color::Cyan, "pinned")?;
+    }
+    if outdated.needs_review() {
+        write_tag(&mut out, Color::Yellow, "needs review")?;
+    }
+    println!("{}", out.bold());
+
+    if let Some(redirect) = &outdated.redirect {
+        println!("    replaced by {}", redirect);
+    }
+    for change in &outdated.changes {
+        println!("    {}", change);
+    }
+    if outdated.registry != DEFAULT_REGISTRY {
+        println!("    registry: {}", outdated.registry);
+    }
+    Ok(())
+}
+
+fn link(link: &Link) -> Result<()> {
+    let path = link.path.canonicalize()
+        .context(format_err!("Failed to resolve {:?}", link.path))?;
+    let id = match &link.module {
+        Some(id) => id.clone(),
+        None => links::module_id_from_path(&path)?,
+    };
+
+    // the metadata is validated before the link is saved
+    let module = Module::load_linked(&path, &id.author, &id.name)?;
+    let canonical = module.canonical();
+
+    let mut links = Links::load()?;
+    links.insert(canonical.clone(), path.clone());
+    links.save()?;
+
+    term::info(&format!("Linked {} v{} to {:?}", canonical, module.version(), path));
+    term::info(&format!("Capabilities of {}: {}", canonical, module.capabilities()));
+    if !module.keyring_access().is_empty() {
+        term::info(&format!("Keyring access of {}: {}", canonical, module.keyring_access().join(", ")));
+    }
     Ok(())
 }
 
@@ -127,6 +269,8 @@ fn run_subcommand(subcommand: SubCommand, library: &Library, config: &Config) ->
     match subcommand {
         SubCommand::List(list) => {
             let autoupdate = AutoUpdater::load()?;
+            let origins = GitOrigins::load()?;
+            let lockfile = Lockfile::load()?;
 
             let filter = glob::Pattern::new(&list.pattern)?;
 
@@ -153,7 +297,7 @@ fn run_subcommand(subcommand: SubCommand, library: &Library, config: &Config) ->
                     continue;
                 }
 
-                print_module(module, is_outdated)?;
+                print_module(module, is_outdated, lockfile.get(&canonical), origins.get(&canonical))?;
             }
             Ok(ModuleReload::No)
         },
@@ -166,30 +310,112 @@ fn run_subcommand(subcommand: SubCommand, library: &Library, config: &Config) ->
             registry::run_search(library, &search, config)?;
             Ok(ModuleReload::No)
         },
-        SubCommand::Update(_) => {
+        SubCommand::Rate(rate) => {
+            registry::run_rate(&rate, config)?;
+            Ok(ModuleReload::No)
+        },
+        SubCommand::Outdated => {
+            let updater = Updater::new(config)?;
+            let origins = GitOrigins::load()?;
+            updater.sync_lockfile(library)?;
+
+            let modules = library.list()
+                .into_iter()
+                .filter(|module| !module.is_private() && origins.get(&module.canonical()).is_none())
+                .collect::<Vec<_>>();
+
+            let label = format!("Checking {} modules for updates", modules.len());
+            let results = worker::spawn_fn(&label, || {
+                Ok(modules.iter()
+                    .map(|module| (module.canonical(), updater.outdated(module)))
+                    .collect::<Vec<_>>())
+            }, true)?;
+
+            let mut found = false;
+            for (canonical, result) in results {
+                match result {
+                    Ok(Some(outdated)) => {
+                        let pinned = updater.is_pinned(&canonical);
+                        print_outdated(&outdated, pinned)?;
+                        found = true;
+                    },
+                    Ok(None) => (),
+                    Err(err) => term::warn(&format!("Failed to check {}: {:#}", canonical, err)),
+                }
+            }
+            if !found {
+                term::info("All modules are up to date");
+            }
+            Ok(ModuleReload::No)
+        },
+        SubCommand::Update(update) => {
             let mut autoupdate = AutoUpdater::load()?;
-            let updater = Arc::new(Updater::new(config)?);
+            let mut updater = Updater::new(config)?;
+            updater.skip_verify(update.insecure_skip_verify);
+            let updater = Arc::new(updater);
+            let origins = GitOrigins::load()?;
+            updater.sync_lockfile(library)?;
+
+            let selected = update.modules.iter()
+                .map(|module| library.get(&module.to_string()).map(Module::canonical))
+                .collect::<Result<HashSet<_>>>()?;
 
+            let mut before = Vec::new();
             let modules = library.list()
                 .into_iter()
                 .filter_map(|module| {
                     let canonical = module.canonical();
 
+                    if !selected.is_empty() && !selected.contains(&canonical) {
+                        return None;
+                    }
+
                     if module.is_private() {
                         debug!("{} is a private module, skipping", canonical);
                         return None;
                     }
 
-                    Some(UpdateTask::new(module.clone(), updater.clone()))
+                    if updater.is_pinned(&canonical) {
+                        if !update.unpin {
+                            debug!("{} is pinned, skipping", canonical);
+                            return None;
+                        }
+                        if let Err(err) = updater.pin(module, false) {
+                            term::warn(&format!("Failed to unpin {}: {}", canonical, err));
+                            return None;
+                        }
+                    }
+
+                    let origin = origins.get(&canonical).cloned();
+                    before.push((canonical, updater.locked(&module.canonical())));
+                    Some(UpdateTask::new(module.clone(), updater.clone(), origin))
                 })
                 .collect::<Vec<_>>();
 
+            let mut succeeded = HashSet::new();
             worker::spawn_multi(modules, |name| {
                 autoupdate.updated(&name);
+                succeeded.insert(name);
             }, 3)?;
+            updater.review_pending()?;
 
             autoupdate.save()?;
 
+            let updated = before.iter()
+                .filter(|(canonical, locked)| {
+                    let after = updater.locked(canonical);
+                    after.map(|x| x.sha256) != locked.as_ref().map(|x| x.sha256.clone())
+                })
+                .count();
+            let failed = before.iter()
+                .map(|(canonical, _)| canonical.as_str())
+                .filter(|canonical| !succeeded.contains(*canonical))
+                .collect::<Vec<_>>();
+            term::info(&format!("Updated {} of {} modules, {} failed", updated, before.len(), failed.len()));
+            if !failed.is_empty() {
+                term::warn(&format!("Failed to update: {}", failed.join(", ")));
+            }
+
             // trigger reload
             Ok(ModuleReload::Yes)
         },
@@ -199,6 +425,53 @@ fn run_subcommand(subcommand: SubCommand, library: &Library, config: &Config) ->
             // trigger reload
             Ok(ModuleReload::Yes)
         },
+        SubCommand::Pin(pin) => {
+            let updater = Updater::new(config)?;
+            updater.sync_lockfile(library)?;
+
+            let canonical = pin.module.to_string();
+            let module = library.get(&canonical)?;
+            if module.is_private() {
+                bail!("{} is a private module", canonical);
+            }
+
+            let (module, reload) = match pin.version {
+                Some(version) if version != module.version() => {
+                    if updater.is_from_git(&canonical) {
+                        bail!("{} has been installed from git and can only be pinned to the installed commit", canonical);
+                    }
+                    let installed = updater.install(Install {
+                        module: Some(Package::Registry(pin.module.clone())),
+                        version: Some(version.clone()),
+                        locked: None,
+                        force: false,
+                        yes: false,
+                        insecure_skip_verify: false,
+                        registry: updater.locked_registry(&canonical),
+                    })?;
+                    let installed = match installed {
+                        Some(module) => Some(module),
+                        None => updater.review_pending()?.into_iter().next(),
+                    };
+                    let module = installed
+                        .ok_or_else(|| format_err!("{} v{} hasn't been installed", canonical, version))?;
+                    (module, ModuleReload::Yes)
+                },
+                _ => (module.clone(), ModuleReload::No),
+            };
+
+            updater.pin(&module, true)?;
+            term::info(&format!("Pinned {} to v{}", canonical, module.version()));
+            Ok(reload)
+        },
+        SubCommand::Unpin(unpin) => {
+            let updater = Updater::new(config)?;
+            let canonical = unpin.module.to_string();
+            let module = library.get(&canonical)?;
+            updater.pin(module, false)?;
+            term::info(&format!("Unpinned {}", canonical));
+            Ok(ModuleReload::No)
+        },
         SubCommand::Quickstart => {
             let client = Client::new(config)?;
             let updater = Arc::new(Updater::new(config)?);
@@ -220,9 +493,13 @@ fn run_subcommand(subcommand: SubCommand, library: &Library, config: &Config) ->
                     if !installed.contains(&id) {
                         info!("Queueing for install: {}", id);
                         Some(InstallTask::new(Install {
-                            module: id,
+                            module: Some(Package::Registry(id)),
                             version: None,
+                            locked: None,
                             force: false,
+                            yes: false,
+                            insecure_skip_verify: false,
+                            registry: None,
                         }, updater.clone()))
                     } else {
                         info!("Skipping already installed module: {}", id);
@@ -234,12 +511,26 @@ fn run_subcommand(subcommand: SubCommand, library: &Library, config: &Config) ->
             worker::spawn_multi(modules, |name| {
                 autoupdate.updated(&name);
             }, 3)?;
+            updater.review_pending()?;
 
             autoupdate.save()?;
 
             // trigger reload
             Ok(ModuleReload::Yes)
         },
+        SubCommand::Link(l) => {
+            link(&l)?;
+            Ok(ModuleReload::Yes)
+        },
+        SubCommand::Unlink(unlink) => {
+            let canonical = unlink.module.to_string();
+            let mut links = Links::load()?;
+            let path = links.remove(&canonical)
+                .ok_or_else(|| format_err!("{} isn't linked", canonical))?;
+            links.save()?;
+            term::info(&format!("Unlinked {} from {:?}", canonical, path));
+            Ok(ModuleReload::Yes)
+        },
     }
 }
 
@@ -0,0 +1,46 @@
+use clap::Parser;
+use crate::cmd::Cmd;
+use crate::db::Family;
+use crate::errors::*;
+use crate::models::*;
+use crate::shell::Shell;
+use crate::shell::pager::Pager;
+use std::io::Write;
+
+#[derive(Debug, Parser)]
+pub struct Args {
+    /// The type of the entity, like email or subdomain
+    family: Family,
+    /// The value of the entity
+    value: String,
+    /// Print the history as json, one line per change
+    #[arg(long)]
+    pub json: bool,
+    /// Print everything, even if it doesn't fit on the terminal
+    #[arg(long="no-pager")]
+    no_pager: bool,
+}
+
+impl Cmd for Args {
+    fn run(self, rl: &mut Shell) -> Result<()> {
+        let history = Provenance::history(rl.db(), &self.family, &self.value)?;
+        if history.is_empty() {
+            bail!("No history recorded for {} {:?}", self.family.as_str(), self.value);
+        }
+
+        let mut stdout = Pager::new(rl, self.no_pager || self.json);
+        if self.json {
+            for entry in history {
+                serde_json::to_writer(&mut stdout, &entry)?;
+                writeln!(stdout)?;
+            }
+        } else {
+            writeln!(stdout, "{} {:?}", self.family.as_str(), self.value)?;
+            for entry in history {
+                writeln!(stdout, "    {}", entry)?;
+            }
+        }
+
+        stdout.finish()
+    }
+}
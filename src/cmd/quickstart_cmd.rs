@@ -1,5 +1,6 @@
 use crate::errors::*;
 use crate::cmd::Cmd;
+use crate::fmt::colors::{paint, Style};
 use crate::cmd::pkg_cmd::{ArgsInteractive as PkgArgs, SubCommand, SubCommandInteractive};
 use crate::shell::Shell;
 use crate::term;
@@ -12,7 +13,7 @@ pub struct Args {
 pub fn run(rl: &mut Shell, args: &[String]) -> Result<()> {
     let _args = Args::try_parse_from(args)?;
 
-    term::warn("The \x1b[1mquickstart\x1b[0m command is deprecated, use \x1b[1mpkg quickstart\x1b[0m");
+    term::warn(&format!("The {} command is deprecated, use {}", paint(Style::Bold, "quickstart"), paint(Style::Bold, "pkg quickstart")));
 
     let args = PkgArgs {
         subcommand: SubCommandInteractive::Base(SubCommand::Quickstart),
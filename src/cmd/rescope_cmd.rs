@@ -1,8 +1,8 @@
 use crate::errors::*;
 
-use crate::autonoscope::{AutoRule, ToRule};
+use crate::autonoscope;
 use crate::cmd::Cmd;
-use crate::db::Database;
+use crate::db::{Database, Family};
 use crate::filters::{Filter, Target};
 use crate::shell::Shell;
 use std::collections::HashSet;
@@ -10,7 +10,8 @@ use std::fmt;
 use clap::Parser;
 use crate::models::*;
 use crate::utils;
-use crate::term;
+use crate::fmt::colors::Style;
+use crate::term::{self, Tag};
 
 #[derive(Debug, Parser)]
 pub struct Args {
@@ -23,6 +24,9 @@ pub struct Args {
     /// Only show changes, do not apply them to the database
     #[arg(short='n', long)]
     dry_run: bool,
+    /// Skip entities that have last been scoped or noscoped by hand
+    #[arg(long="respect-manual")]
+    respect_manual: bool,
     /// Only rescope entities matching specific filter
     #[command(subcommand)]
     target: Option<Target>,
@@ -33,23 +37,30 @@ enum Entity {
     Subdomain(Subdomain),
     IpAddr(IpAddr),
     Url(Url),
+    Email(Email),
     Port(Port),
     Netblock(Netblock),
 }
 
 impl Entity {
-    fn set_scoped(&self, db: &Database, value: bool) -> Result<()> {
+    fn set_scoped(&self, db: &Database, value: bool, rule: &str) -> Result<()> {
         match self {
-            Entity::Domain(entity) => entity.set_scoped(db, value),
-            Entity::Subdomain(entity) => entity.set_scoped(db, value),
-            Entity::IpAddr(entity) => entity.set_scoped(db, value),
-            Entity::Url(entity) => entity.set_scoped(db, value),
-            Entity::Port(entity) => entity.set_scoped(db, value),
-            Entity::Netblock(entity) => entity.set_scoped(db, value),
+            Entity::Domain(entity) => rescope(db, entity, value, rule),
+            Entity::Subdomain(entity) => rescope(db, entity, value, rule),
+            Entity::IpAddr(entity) => rescope(db, entity, value, rule),
+            Entity::Url(entity) => rescope(db, entity, value, rule),
+            Entity::Email(entity) => rescope(db, entity, value, rule),
+            Entity::Port(entity) => rescope(db, entity, value, rule),
+            Entity::Netblock(entity) => rescope(db, entity, value, rule),
         }
     }
 }
 
+fn rescope<T: Scopable>(db: &Database, entity: &T, value: bool, rule: &str) -> Result<()> {
+    entity.set_scoped(db, value)?;
+    db.log_scope_change(entity, value, Some(format!("rescope {}", rule)))
+}
+
 enum Input {
     Yes,
     No,
@@ -80,16 +91,29 @@ fn get_input() -> Result<Input> {
     }
 }
 
+/// The changes of the entities of one type
+#[derive(Default)]
+struct Counts {
+    scoped: usize,
+    unscoped: usize,
+    /// Skipped because their scope has been set by hand
+    protected: usize,
+}
+
 #[derive(Default)]
 struct Context {
-    update_queue: Vec<(Entity, bool)>,
+    update_queue: Vec<(Entity, bool, String)>,
     always_rules: HashSet<(&'static str, String)>,
     never_rules: HashSet<(&'static str, String)>,
     done: bool,
     target: Option<Target>,
+    /// The entities whose scope has last been changed by hand, as family and
+    /// value
+    manual: HashSet<(String, String)>,
+    counts: Vec<(&'static str, Counts)>,
 }
 
-fn rescope_to_queue<T, F1, F2, F3>(ctx: &mut Context, db: &Database, interactive: bool, get_filter: F1, matches_rule: F2, wrap: F3) -> Result<()>
+fn rescope_to_queue<T, F1, F2, F3>(ctx: &mut Context, db: &Database, interactive: bool, family: Family, get_filter: F1, matches_rule: F2, wrap: F3) -> Result<()>
     where
         T: Model + Scopable + fmt::Debug,
         F1: Fn(&Target) -> Option<&Filter>,
@@ -103,9 +127,9 @@ fn rescope_to_queue<T, F1, F2, F3>(ctx: &mut Context, db: &Database, interactive
 
     // check if there are filters to be applied
     let filter = if let Some(target) = &ctx.target {
-        if let Some(filter) = get_filter(target) {
+        if get_filter(target).is_some() {
             // we've selected this specific entity type and there's a filter
-            filter.parse_optional()
+            target.parse_optional(db)
                 .context("Filter is invalid")?
         } else {
             // we do not wish to process this entity type
@@ -117,6 +141,7 @@ fn rescope_to_queue<T, F1, F2, F3>(ctx: &mut Context, db: &Database, interactive
     };
 
     let entities = db.filter::<T>(&filter)?;
+    let mut counts = Counts::default();
 
     for entity in entities {
         let currently_scoped = entity.scoped();
@@ -129,10 +154,15 @@ fn rescope_to_queue<T, F1, F2, F3>(ctx: &mut Context, db: &Database, interactive
             }
 
             if currently_scoped != should_be {
+                if ctx.manual.contains(&(family.as_str().to_string(), entity.to_string())) {
+                    counts.protected += 1;
+                    continue;
+                }
+
                 let prefix = if should_be {
-                    "\x1b[1m[\x1b[32m+\x1b[0;1m]\x1b[0m"
+                    Tag(Style::Green, '+')
                 } else {
-                    "\x1b[1m[\x1b[31m-\x1b[0;1m]\x1b[0m"
+                    Tag(Style::Red, '-')
                 };
 
                 println!("{} Setting entity {:?} => {:?}: {:?}", prefix, currently_scoped, should_be, entity);
@@ -147,6 +177,7 @@ fn rescope_to_queue<T, F1, F2, F3>(ctx: &mut Context, db: &Database, interactive
                 };
 
                 // process user input
+                let label = autonoscope::rule_label(&rule);
                 let input = match input {
                     Input::Always => {
                         ctx.always_rules.insert(rule);
@@ -164,81 +195,89 @@ fn rescope_to_queue<T, F1, F2, F3>(ctx: &mut Context, db: &Database, interactive
                 };
 
                 if let Input::Yes = input {
-                    ctx.update_queue.push((wrap(entity), should_be));
+                    if should_be {
+                        counts.scoped += 1;
+                    } else {
+                        counts.unscoped += 1;
+                    }
+                    ctx.update_queue.push((wrap(entity), should_be, label));
                 }
             }
         }
     }
 
+    ctx.counts.push((family.as_str(), counts));
     Ok(())
 }
 
 impl Cmd for Args {
+    #[inline]
     fn run(self, rl: &mut Shell) -> Result<()> {
-        let rules = rl.db().autonoscope();
+        self.rescope(rl.db())
+    }
+}
+
+impl Args {
+    /// Apply the rules to every entity without asking, used by
+    /// `autonoscope apply`
+    pub fn auto(family: Option<Family>, dry_run: bool, respect_manual: bool) -> Result<Args> {
+        let target = match family {
+            Some(family) => Some(Target::any_of(&family)
+                .ok_or_else(|| format_err!("Autonoscope rules don't apply to {}", family.as_str()))?),
+            None => None,
+        };
+        Ok(Args {
+            interactive: false,
+            auto_confirm: true,
+            dry_run,
+            respect_manual,
+            target,
+        })
+    }
+
+    fn rescope(self, db: &Database) -> Result<()> {
+        let rules = db.autonoscope();
         term::success(&format!("Loaded {} rules", rules.len()));
 
         let mut ctx = Context {
             target: self.target,
             ..Default::default()
         };
+        if self.respect_manual {
+            ctx.manual = Provenance::manual_scope(db)?;
+        }
 
-        rescope_to_queue::<Domain, _, _, _>(&mut ctx, rl.db(), self.interactive, |t| t.domains(), |entity| {
-            for rule in rules.domains() {
-                if rule.matches(entity.value.as_str())? {
-                    return Ok(Some((rule.to_rule(), rule.scoped)));
-                }
-            }
-            Ok(None)
+        rescope_to_queue::<Domain, _, _, _>(&mut ctx, db, self.interactive, Family::Domain, |t| t.domains(), |entity| {
+            rules.matching_value(&Family::Domain, &entity.value)
         }, Entity::Domain)?;
-        rescope_to_queue::<Subdomain, _, _, _>(&mut ctx, rl.db(), self.interactive, |t| t.subdomains(), |entity| {
-            for rule in rules.domains() {
-                if rule.matches(entity.value.as_str())? {
-                    return Ok(Some((rule.to_rule(), rule.scoped)));
-                }
-            }
-            Ok(None)
+        rescope_to_queue::<Subdomain, _, _, _>(&mut ctx, db, self.interactive, Family::Subdomain, |t| t.subdomains(), |entity| {
+            rules.matching_value(&Family::Subdomain, &entity.value)
         }, Entity::Subdomain)?;
-
-        rescope_to_queue::<IpAddr, _, _, _>(&mut ctx, rl.db(), self.interactive, |t| t.ipaddrs(), |entity| {
-            for rule in rules.ips() {
-                if rule.matches(entity)? {
-                    return Ok(Some((rule.to_rule(), rule.scoped)));
-                }
-            }
-            Ok(None)
+        rescope_to_queue::<IpAddr, _, _, _>(&mut ctx, db, self.interactive, Family::Ipaddr, |t| t.ipaddrs(), |entity| {
+            rules.matching_value(&Family::Ipaddr, &entity.value)
         }, Entity::IpAddr)?;
-
-        rescope_to_queue::<Url, _, _, _>(&mut ctx, rl.db(), self.interactive, |t| t.urls(), |entity| {
-            for rule in rules.domains() {
-                if rule.matches(entity)? {
-                    return Ok(Some((rule.to_rule(), rule.scoped)));
-                }
-            }
-            for rule in rules.urls() {
-                if rule.matches(entity)? {
-                    return Ok(Some((rule.to_rule(), rule.scoped)));
-                }
-            }
-            Ok(None)
+        rescope_to_queue::<Url, _, _, _>(&mut ctx, db, self.interactive, Family::Url, |t| t.urls(), |entity| {
+            rules.matching_value(&Family::Url, &entity.value)
         }, Entity::Url)?;
-        rescope_to_queue::<Port, _, _, _>(&mut ctx, rl.db(), self.interactive, |t| t.ports(), |entity| {
-            for rule in rules.ips() {
-                if rule.matches(entity)? {
-                    return Ok(Some((rule.to_rule(), rule.scoped)));
-                }
-            }
-            Ok(None)
+        rescope_to_queue::<Email, _, _, _>(&mut ctx, db, self.interactive, Family::Email, |t| t.emails(), |entity| {
+            rules.matching_value(&Family::Email, &entity.value)
+        }, Entity::Email)?;
+        rescope_to_queue::<Port, _, _, _>(&mut ctx, db, self.interactive, Family::Port, |t| t.ports(), |entity| {
+            rules.matching_value(&Family::Port, &entity.value)
         }, Entity::Port)?;
-        rescope_to_queue::<Netblock, _, _, _>(&mut ctx, rl.db(), self.interactive, |t| t.netblocks(), |entity| {
-            for rule in rules.ips() {
-                if rule.matches(entity)? {
-                    return Ok(Some((rule.to_rule(), rule.scoped)));
-                }
-            }
-            Ok(None)
+        rescope_to_queue::<Netblock, _, _, _>(&mut ctx, db, self.interactive, Family::Netblock, |t| t.netblocks(), |entity| {
+            rules.matching_value(&Family::Netblock, &entity.value)
         }, Entity::Netblock)?;
 
+        for (family, counts) in &ctx.counts {
+            if counts.scoped + counts.unscoped > 0 {
+                term::info(&format!("{}: {} entered scope, {} left scope", family, counts.scoped, counts.unscoped));
+            }
+            if counts.protected > 0 {
+                term::info(&format!("{}: skipped {} that have been scoped by hand", family, counts.protected));
+            }
+        }
+
         if ctx.update_queue.is_empty() {
             term::success("Nothing has changed, not updating database");
         } else {
@@ -253,9 +292,13 @@ impl Cmd for Args {
             if confirm {
                 term::info(&format!("Applying {} changes to database", ctx.update_queue.len()));
 
-                for (update, value) in ctx.update_queue {
-                    update.set_scoped(rl.db(), value)?;
-                }
+                // either every change is applied or none of them
+                db.write(|| {
+                    for (update, value, rule) in &ctx.update_queue {
+                        update.set_scoped(db, *value, rule)?;
+                    }
+                    Ok(())
+                })?;
             } else {
                 term::info("Database not updated");
             }
@@ -264,3 +307,55 @@ impl Cmd for Args {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::autonoscope::RuleType;
+    use crate::db::Filter;
+
+    fn domain(value: &str) -> Insert {
+        Insert::Domain(NewDomain {
+            value: value.to_string(),
+            unscoped: false,
+        })
+    }
+
+    #[test]
+    fn test_rescope_auto() {
+        let mut db = Database::memory().unwrap();
+        for value in ["a.com", "b.com", "c.com", "example.com"] {
+            db.insert_generic(domain(value)).unwrap();
+        }
+        // scoped by hand, the provenance records no rule for it
+        db.set_scoped::<Domain>(&Filter::column_eq("value", "b.com"), false).unwrap();
+        db.set_scoped::<Domain>(&Filter::column_eq("value", "b.com"), true).unwrap();
+        db.autonoscope_add_rule(&RuleType::Domain, "com", false).unwrap();
+        db.autonoscope_add_rule(&RuleType::Domain, "example.com", true).unwrap();
+
+        Args::auto(Some(Family::Domain), true, true).unwrap().rescope(&db).unwrap();
+        // nothing has been written
+        assert_eq!(db.count_scoped(Family::Domain.table()).unwrap(), 4);
+
+        Args::auto(Some(Family::Domain), false, true).unwrap().rescope(&db).unwrap();
+        let scoped = db.filter::<Domain>(&Filter::any().and_scoped()).unwrap()
+            .into_iter()
+            .map(|x| x.value)
+            .collect::<Vec<_>>();
+        assert_eq!(scoped, &["b.com", "example.com"]);
+
+        Args::auto(None, false, false).unwrap().rescope(&db).unwrap();
+        assert_eq!(db.count_scoped(Family::Domain.table()).unwrap(), 1);
+
+        let history = Provenance::history(&db, &Family::Domain, "a.com").unwrap();
+        let last = history.last().unwrap();
+        assert_eq!(last.rule(), Some("domain:com"));
+        assert!(!last.is_scoped());
+    }
+
+    #[test]
+    fn test_rescope_auto_family() {
+        let err = Args::auto(Some(Family::Account), false, false).unwrap_err();
+        assert_eq!(err.to_string(), "Autonoscope rules don't apply to account");
+    }
+}
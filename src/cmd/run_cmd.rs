@@ -1,14 +1,18 @@
-use chrootable_https::dns::Resolver;
 use clap::{ArgAction, Parser};
 use crate::args;
 use crate::blobs::{Blob, BlobStorage};
+use crate::capabilities::AcceptedCapabilities;
 use crate::cmd::Cmd;
 use crate::db::{ttl, Filter};
-use crate::engine::Module;
+use crate::db::precondition::Precondition;
+use crate::engine::{Entrypoint, Module};
 use crate::errors::*;
-use crate::ipc::common::StartCommand;
-use crate::keyring::KeyRing;
+use crate::ipc::{self, common::StartCommand, files::ReadGrants};
+use crate::keyring::{KeyName, KeyRing};
 use crate::models::*;
+use crate::notify;
+use crate::output::{self, OutputFormat, RunEvent};
+use crate::sandbox::{Profile, SandboxMode};
 use crate::shell::Shell;
 use crate::term;
 use crate::utils;
@@ -18,6 +22,7 @@ use sn0int_common::metadata::Source;
 use sn0int_std::ratelimits::Ratelimiter;
 use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 
 #[derive(Debug, Parser)]
 pub struct Args {
@@ -26,23 +31,53 @@ pub struct Args {
     /// Run investigations concurrently
     #[arg(short = 'j', default_value="1")]
     pub threads: usize,
+    /// Limit the dns lookups, http requests and connections that are in
+    /// flight at the same time across all threads, overrides
+    /// network.max_concurrency
+    #[arg(long="max-concurrency")]
+    pub max_concurrency: Option<usize>,
     /// Verbose logging, once to print inserts even if they don't add new
     /// data, twice to activate the debug() function
     #[arg(short = 'v', long, action(ArgAction::Count))]
     pub verbose: u8,
+    /// Hide the info() and debug() lines of the module, warnings and errors
+    /// are still shown
+    #[arg(short = 'q', long, conflicts_with="verbose")]
+    pub quiet: bool,
     /// Set a specific socks5 proxy to use
     #[arg(short = 'X', long)]
     pub proxy: Option<SocketAddr>,
     /// Set a different default user agent
     #[arg(long)]
     pub user_agent: Option<String>,
+    /// Skip targets unless their related entities match, e.g. "no breaches"
+    /// or "subdomains where value like dev.%"
+    #[arg(long="if")]
+    pub precondition: Option<String>,
+    /// Allow the module to read a file, or the files inside a folder, with
+    /// read_file and read_dir
+    #[arg(long="allow-read")]
+    pub allow_read: Vec<PathBuf>,
+    /// Use a syscall filter based on the declared capabilities of the
+    /// module (strict) or the broad filter that works for every module (compat)
+    #[arg(long="sandbox", default_value="strict")]
+    pub sandbox: SandboxMode,
+    /// Abort instead of asking for keys the module requires but that aren't
+    /// in the keyring
+    #[arg(long="no-prompt")]
+    pub no_prompt: bool,
+    /// Write every event of the run as a line of json to stdout
+    #[arg(long="output", value_enum, default_value="human")]
+    pub output: OutputFormat,
 }
 
 #[derive(Debug, Clone)]
 pub struct Params<'a> {
     pub module: Option<&'a String>,
     pub threads: usize,
+    pub max_concurrency: Option<usize>,
     pub verbose: u8,
+    pub quiet: bool,
     pub stdin: bool,
     pub grants: &'a [String],
     pub grant_full_keyring: bool,
@@ -50,6 +85,11 @@ pub struct Params<'a> {
     pub exit_on_error: bool,
     pub proxy: Option<SocketAddr>,
     pub user_agent: Option<&'a String>,
+    pub precondition: Option<&'a String>,
+    pub allow_read: &'a [PathBuf],
+    pub sandbox: SandboxMode,
+    pub no_prompt: bool,
+    pub output: OutputFormat,
 }
 
 impl<'a> Params<'a> {
@@ -62,7 +102,11 @@ impl<'a> Params<'a> {
                 .map(|m| m.to_owned())
                 .ok_or_else(|| format_err!("No module selected"))?
         };
-        Ok(module)
+        module.reload_linked()
+    }
+
+    pub fn get_profile(&self, module: &Module) -> Profile {
+        Profile::for_module(module, self.sandbox)
     }
 
     pub fn get_proxy(&self, rl: &Shell) -> Option<SocketAddr> {
@@ -73,6 +117,19 @@ impl<'a> Params<'a> {
         }
     }
 
+    pub fn get_precondition(&self) -> Result<Option<Precondition>> {
+        let precondition = match self.precondition {
+            Some(precondition) => precondition.parse::<Precondition>()
+                .context("Invalid precondition")?,
+            None => return Ok(None),
+        };
+        Ok(Some(precondition))
+    }
+
+    pub fn get_read_grants(&self) -> Result<ReadGrants> {
+        ReadGrants::new(self.allow_read)
+    }
+
     pub fn get_user_agent(&self, rl: &Shell) -> Option<String> {
         if let Some(user_agent) = self.user_agent {
             Some(user_agent.to_string())
@@ -87,7 +144,9 @@ impl<'a> From<&'a args::Run> for Params<'a> {
         Params {
             module: args.run.module.as_ref(),
             threads: args.run.threads,
+            max_concurrency: args.run.max_concurrency,
             verbose: args.run.verbose,
+            quiet: args.run.quiet,
             stdin: args.stdin,
             grants: &args.grants,
             grant_full_keyring: args.grant_full_keyring,
@@ -95,6 +154,11 @@ impl<'a> From<&'a args::Run> for Params<'a> {
             exit_on_error: args.exit_on_error,
             proxy: args.run.proxy,
             user_agent: args.run.user_agent.as_ref(),
+            precondition: args.run.precondition.as_ref(),
+            allow_read: &args.run.allow_read,
+            sandbox: args.run.sandbox,
+            no_prompt: args.run.no_prompt,
+            output: args.run.output,
         }
     }
 }
@@ -104,7 +168,9 @@ impl<'a> From<&'a Args> for Params<'a> {
         Params {
             module: args.module.as_ref(),
             threads: args.threads,
+            max_concurrency: args.max_concurrency,
             verbose: args.verbose,
+            quiet: args.quiet,
             stdin: false,
             grants: &[],
             grant_full_keyring: false,
@@ -112,6 +178,11 @@ impl<'a> From<&'a Args> for Params<'a> {
             exit_on_error: false,
             proxy: args.proxy,
             user_agent: args.user_agent.as_ref(),
+            precondition: args.precondition.as_ref(),
+            allow_read: &args.allow_read,
+            sandbox: args.sandbox,
+            no_prompt: args.no_prompt,
+            output: args.output,
         }
     }
 }
@@ -130,13 +201,46 @@ fn prepare_arg<T: Serialize + Model>(bs: &BlobStorage, x: T) -> Result<(serde_js
     Ok((arg, Some(pretty), blobs))
 }
 
-fn prepare_args<T: Scopable + Serialize + Model>(rl: &Shell, filter: &Filter, param: Option<&String>) -> Result<Vec<(serde_json::Value, Option<String>, Vec<Blob>)>> {
+/// Some sources only run on entities with a specific column, like accounts
+/// of a service
+fn with_param(filter: &Filter, column: &str, param: Option<&String>) -> Filter {
+    match param {
+        Some(param) => filter.clone().and(&Filter::column_eq(column, param)),
+        None => filter.clone(),
+    }
+}
+
+/// The entities are loaded in chunks, only the arguments that are prepared
+/// for them are kept
+fn prepare_args<T: Scopable + Serialize + Model>(rl: &Shell, filter: &Filter, precondition: Option<&Precondition>) -> Result<Vec<(serde_json::Value, Option<String>, Vec<Blob>)>> {
     let db = rl.db();
     let bs = rl.blobs();
-    db.filter_with_param::<T>(filter, param)?
-        .into_iter()
-        .map(|x| prepare_arg(bs, x))
-        .collect()
+
+    if let Some(precondition) = precondition {
+        precondition.check(T::table())?;
+    }
+
+    let mut args = Vec::new();
+    let mut total = 0;
+    db.for_each_chunk::<T, _>(filter, CHUNK_SIZE, |chunk| {
+        total += chunk.len();
+        for target in chunk {
+            if let Some(precondition) = precondition {
+                if !precondition.matches(db.db(), T::table(), target.id())? {
+                    continue;
+                }
+            }
+            args.push(prepare_arg(bs, target)?);
+        }
+        Ok(())
+    })?;
+
+    let skipped = total - args.len();
+    if skipped > 0 {
+        term::info(&format!("Skipping {} of {} targets that don't match the precondition", skipped, total));
+    }
+
+    Ok(args)
 }
 
 pub fn prepare_keyring(keyring: &mut KeyRing, module: &Module, params: &Params) -> Result<()> {
@@ -162,29 +266,79 @@ pub fn prepare_keyring(keyring: &mut KeyRing, module: &Module, params: &Params)
     Ok(())
 }
 
-fn get_args(rl: &mut Shell, module: &Module) -> Result<Vec<(serde_json::Value, Option<String>, Vec<Blob>)>> {
+/// Check that there are keys for every namespace the module requires, ask for
+/// the missing ones unless prompts are disabled
+pub fn ensure_keys(rl: &mut Shell, module: &Module, params: &Params) -> Result<()> {
+    let missing = rl.keyring().missing_namespaces(module.keyring_access(), rl.workspace());
+    let (optional, mut required): (Vec<_>, Vec<_>) = missing.into_iter()
+        .partition(|namespace| module.is_keyring_optional(namespace));
+
+    for namespace in optional {
+        term::warn(&format!("There are no {:?} keys, the module is going to run without them", namespace));
+    }
+
+    if !params.no_prompt {
+        let mut still_missing = Vec::new();
+        for namespace in required {
+            term::warn(&format!("The module requires a {:?} key, but there are no keys for this namespace", namespace));
+            let access_key = match utils::question_opt(&format!("Access key for {:?} (empty to abort)", namespace))? {
+                Some(access_key) => access_key,
+                None => {
+                    still_missing.push(namespace);
+                    continue;
+                },
+            };
+            let key = KeyName::new(namespace.to_string(), access_key);
+            let secret = utils::question_opt("Secretkey")?;
+            rl.keyring_mut().insert(key.clone(), secret)?;
+            rl.reload_keyring_cache();
+            term::success(&format!("Added {} to the keyring", key));
+        }
+        required = still_missing;
+    }
+
+    if !required.is_empty() {
+        let required = required.iter()
+            .map(|x| format!("{:?}", x))
+            .collect::<Vec<_>>();
+        bail!("Missing keys for {}, add them with `keyring add <namespace>:<access_key>` (modules that work without keys declare them with Keyring-Optional)", required.join(", "));
+    }
+
+    Ok(())
+}
+
+fn get_args(rl: &mut Shell, module: &Module, precondition: Option<&Precondition>) -> Result<Vec<(serde_json::Value, Option<String>, Vec<Blob>)>> {
     let filter = rl.scoped_targets();
 
+    if precondition.is_some() {
+        match module.source() {
+            Some(Source::Notifications) | Some(Source::KeyRing(_)) | None => {
+                bail!("Preconditions are only supported for modules that run on entities")
+            },
+            _ => (),
+        }
+    }
+
     match module.source() {
-        Some(Source::Domains) => prepare_args::<Domain>(rl, &filter, None),
-        Some(Source::Subdomains) => prepare_args::<Subdomain>(rl, &filter, None),
-        Some(Source::IpAddrs) => prepare_args::<IpAddr>(rl, &filter, None),
-        Some(Source::Urls) => prepare_args::<Url>(rl, &filter, None),
-        Some(Source::Emails) => prepare_args::<Email>(rl, &filter, None),
-        Some(Source::PhoneNumbers) => prepare_args::<PhoneNumber>(rl, &filter, None),
-        Some(Source::Networks) => prepare_args::<Network>(rl, &filter, None),
-        Some(Source::Devices) => prepare_args::<Device>(rl, &filter, None),
-        Some(Source::Accounts(service)) => prepare_args::<Account>(rl, &filter, service.as_ref()),
-        Some(Source::Breaches) => prepare_args::<Breach>(rl, &filter, None),
-        Some(Source::Images) => prepare_args::<Image>(rl, &filter, None),
-        Some(Source::Ports) => prepare_args::<Port>(rl, &filter, None),
-        Some(Source::Netblocks) => prepare_args::<Netblock>(rl, &filter, None),
-        Some(Source::CryptoAddrs(currency)) => prepare_args::<CryptoAddr>(rl, &filter, currency.as_ref()),
+        Some(Source::Domains) => prepare_args::<Domain>(rl, &filter, precondition),
+        Some(Source::Subdomains) => prepare_args::<Subdomain>(rl, &filter, precondition),
+        Some(Source::IpAddrs) => prepare_args::<IpAddr>(rl, &filter, precondition),
+        Some(Source::Urls) => prepare_args::<Url>(rl, &filter, precondition),
+        Some(Source::Emails) => prepare_args::<Email>(rl, &filter, precondition),
+        Some(Source::PhoneNumbers) => prepare_args::<PhoneNumber>(rl, &filter, precondition),
+        Some(Source::Networks) => prepare_args::<Network>(rl, &filter, precondition),
+        Some(Source::Devices) => prepare_args::<Device>(rl, &filter, precondition),
+        Some(Source::Accounts(service)) => prepare_args::<Account>(rl, &with_param(&filter, "service", service.as_ref()), precondition),
+        Some(Source::Breaches) => prepare_args::<Breach>(rl, &filter, precondition),
+        Some(Source::Images) => prepare_args::<Image>(rl, &filter, precondition),
+        Some(Source::Ports) => prepare_args::<Port>(rl, &filter, precondition),
+        Some(Source::Netblocks) => prepare_args::<Netblock>(rl, &filter, precondition),
+        Some(Source::CryptoAddrs(currency)) => prepare_args::<CryptoAddr>(rl, &with_param(&filter, "currency", currency.as_ref()), precondition),
         Some(Source::Notifications) => bail!("Notification modules can't be executed like this"),
         Some(Source::KeyRing(namespace)) => {
             let keyring = rl.keyring();
             if keyring.is_access_granted(module, namespace) {
-                keyring.get_all_for(namespace).into_iter()
+                keyring.get_all_for(namespace, rl.workspace()).into_iter()
                     .map(|key| {
                         let pretty = format!("{}:{}", key.namespace, key.access_key);
                         let arg = serde_json::to_value(key)?;
@@ -201,25 +355,32 @@ fn get_args(rl: &mut Shell, module: &Module) -> Result<Vec<(serde_json::Value, O
 
 pub fn dump_sandbox_init_msg(rl: &mut Shell, params: Params, options: HashMap<String, String>) -> Result<()> {
     let module = params.get_module(rl)?;
-    let proxy = params.get_proxy(rl);
     let user_agent = params.get_user_agent(rl);
 
     prepare_keyring(rl.keyring_mut(), &module, &params)?;
-    let keyring = rl.keyring().request_keys(&module);
+    let keyring = rl.keyring().request_keys(&module, rl.workspace());
 
-    let dns_config = Resolver::from_system_v4()?;
+    let memory_limit = rl.config().sandbox.memory_limit_bytes();
+    let timeout = rl.config().sandbox.source_timeout();
+    let audit = rl.config().network.audit;
+    let capabilities = AcceptedCapabilities::load()?.effective(&module);
 
-    let args = get_args(rl, &module)?;
+    let precondition = params.get_precondition()?;
+    let args = get_args(rl, &module, precondition.as_ref())?;
     for (arg, _pretty_arg, blobs) in args {
         let start_cmd = StartCommand::new(params.verbose,
                                           keyring.clone(),
-                                          dns_config.clone(),
-                                          proxy,
                                           user_agent.clone(),
                                           options.clone(),
                                           module.clone(),
+                                          Entrypoint::Run,
                                           arg,
-                                          blobs);
+                                          blobs,
+                                          memory_limit,
+                                          timeout,
+                                          Some(capabilities.clone()),
+                                          audit,
+                                          false);
         let out = serde_json::to_string(&start_cmd)?;
         println!("{}", out);
     }
@@ -232,15 +393,57 @@ pub fn execute(rl: &mut Shell, params: Params, options: HashMap<String, String>)
     let proxy = params.get_proxy(rl);
     let user_agent = params.get_user_agent(rl);
 
+    ipc::parent::ensure_egress_declared(&module, &rl.config().sandbox)?;
+    ipc::parent::ensure_required_proxy(proxy, &rl.config().network)?;
+    let precondition = params.get_precondition()?;
+    let read_grants = params.get_read_grants()?;
     prepare_keyring(rl.keyring_mut(), &module, &params)?;
-    let args = get_args(rl, &module)?;
+    ensure_keys(rl, &module, &params)?;
+    let args = get_args(rl, &module, precondition.as_ref())?;
+    if params.verbose > 0 {
+        term::info(&format!("Using {} sandbox profile", params.get_profile(&module).as_str()));
+    }
+
+    let json = params.output == OutputFormat::Json;
+    let targets = args.len();
+    if json {
+        output::emit(None, &RunEvent::RunStarted {
+            module: module.canonical(),
+            module_version: module.version().to_string(),
+            workspace: rl.workspace().to_string(),
+            targets,
+        });
+    }
+
+    // notification modules are started by the worker too, only the module
+    // that has been requested counts as a run for `--since last`
+    if !args.is_empty() {
+        if let Err(err) = rl.db().insert_run(&module.canonical(), module.version()) {
+            warn!("Failed to record run: {}", err);
+        }
+    }
 
     rl.signal_register().catch_ctrl();
-    let errors = worker::spawn(rl, &module, &mut Ratelimiter::new(), args, &params, proxy, user_agent, options);
+    let failures = worker::spawn(rl, &module, &mut Ratelimiter::new(), args, &params, proxy, user_agent, options, read_grants);
+    notify::finish_run(rl, &mut term::Term);
     rl.signal_register().reset_ctrlc();
 
-    if errors > 0 {
-        term::info(&format!("Finished {} ({} errors)", module.canonical(), errors));
+    if json {
+        output::emit(None, &RunEvent::RunFinished {
+            module: module.canonical(),
+            targets,
+            errors: failures.errors,
+            offline: failures.offline,
+        });
+        if failures.errors > 0 && params.exit_on_error {
+            bail!("Some scripts failed");
+        }
+    } else if failures.errors > 0 {
+        if failures.offline > 0 {
+            term::info(&format!("Finished {} ({} errors, {} due to offline mode)", module.canonical(), failures.errors, failures.offline));
+        } else {
+            term::info(&format!("Finished {} ({} errors)", module.canonical(), failures.errors));
+        }
 
         if params.exit_on_error {
             bail!("Some scripts failed");
@@ -0,0 +1,80 @@
+use crate::cmd::LiteCmd;
+use crate::config::Config;
+use crate::errors::*;
+use crate::scheduler::{self, JobState, State};
+use crate::term;
+use clap::Parser;
+use colored::Colorize;
+
+#[derive(Debug, Parser)]
+pub struct Args {
+    #[command(subcommand)]
+    subcommand: Option<SubCommand>,
+    /// Run every job that is due one time, then exit
+    #[arg(long)]
+    once: bool,
+}
+
+#[derive(Debug, Parser)]
+pub enum SubCommand {
+    /// Show when jobs run next and which ones failed
+    Status,
+}
+
+fn time(time: Option<chrono::NaiveDateTime>) -> String {
+    time.map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|| String::from("-"))
+}
+
+/// A run that started but never finished is either still running or the
+/// scheduler has been stopped during it
+fn status(job: &JobState) -> String {
+    if job.last_run.is_none() {
+        String::from("never ran")
+    } else if job.last_finished < job.last_run {
+        String::from("running")
+    } else if let Some(error) = &job.last_error {
+        format!("failed {}x: {}", job.failures, error).red().to_string()
+    } else {
+        job.last_summary.clone().unwrap_or_default()
+    }
+}
+
+fn print_status(config: &Config) -> Result<()> {
+    let state = State::load()?;
+    let jobs = &config.scheduler.jobs;
+    if jobs.is_empty() {
+        term::info("No jobs are configured");
+        return Ok(());
+    }
+
+    let mut rows = jobs.iter()
+        .map(|(name, job)| {
+            let state = state.job(name);
+            (state.next_run(job.interval), name, job, state)
+        })
+        .collect::<Vec<_>>();
+    rows.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+
+    println!("{:16} {:16} {:24} {:19} {:19} status", "job", "workspace", "module", "next run", "last run");
+    for (next_run, name, job, state) in rows {
+        let next_run = if next_run.is_none() { String::from("now") } else { time(next_run) };
+        println!("{:16} {:16} {:24} {:19} {:19} {}",
+            name,
+            job.workspace,
+            job.module,
+            next_run,
+            time(state.last_run),
+            status(&state));
+    }
+    Ok(())
+}
+
+impl LiteCmd for Args {
+    fn run(self, config: &Config) -> Result<()> {
+        match self.subcommand {
+            Some(SubCommand::Status) => print_status(config),
+            None => scheduler::run(config, self.once),
+        }
+    }
+}
@@ -1,34 +1,109 @@
 use crate::errors::*;
 use crate::cmd::Cmd;
-use crate::filters::{Target, Filter};
+use crate::db::{Database, Family, Filter};
+use crate::filters::Target;
 use crate::shell::Shell;
+use chrono::{NaiveDate, NaiveDateTime, Utc};
 use clap::Parser;
 use crate::models::*;
-use crate::term;
+use crate::fmt::colors::Style;
+use crate::term::{self, Tag};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::str::FromStr;
 
 #[derive(Debug, Parser)]
 pub struct Args {
     #[command(subcommand)]
-    subcommand: Target,
+    subcommand: Subcommand,
+}
+
+#[derive(Debug, Parser)]
+pub enum Subcommand {
+    /// Show entities that entered or left scope
+    #[command(name="changes")]
+    Changes(Changes),
+    #[command(flatten)]
+    Target(Target),
+}
+
+#[derive(Debug, Parser)]
+pub struct Changes {
+    /// `last` for the most recent run, a run id, an age like `12h` or a datetime
+    #[arg(long, default_value="last")]
+    since: Since,
+    /// Only show changes caused by an autonoscope rule, like `domain:example.com`
+    #[arg(long)]
+    rule: Option<String>,
+    /// Undo the changes of the rule that are still in effect
+    #[arg(long, requires="rule")]
+    revert: bool,
+    /// Print the changes as json, one line per change
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Since {
+    LastRun,
+    Run(i32),
+    Time(NaiveDateTime),
+}
+
+impl Since {
+    pub fn resolve(&self, db: &Database) -> Result<(NaiveDateTime, Option<Run>)> {
+        let run = match self {
+            Since::LastRun => Run::last(db)?,
+            Since::Run(id) => Run::get(db, *id)?,
+            Since::Time(time) => return Ok((*time, None)),
+        };
+        Ok((run.started, Some(run)))
+    }
+}
+
+impl FromStr for Since {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Since> {
+        if s == "last" {
+            Ok(Since::LastRun)
+        } else if let Ok(id) = s.strip_prefix('#').unwrap_or(s).parse::<i32>() {
+            Ok(Since::Run(id))
+        } else if let Some(age) = Filter::parse_age(s) {
+            Ok(Since::Time(Utc::now().naive_utc() - age))
+        } else if let Ok(time) = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S") {
+            Ok(Since::Time(time))
+        } else if let Ok(time) = NaiveDateTime::from_str(s) {
+            Ok(Since::Time(time))
+        } else if let Ok(date) = NaiveDate::from_str(s) {
+            Ok(Since::Time(date.and_hms_opt(0, 0, 0).unwrap()))
+        } else {
+            bail!("Expected `last`, a run id, an age like `12h` or a datetime")
+        }
+    }
 }
 
 impl Cmd for Args {
     fn run(self, rl: &mut Shell) -> Result<()> {
-        let rows = match self.subcommand {
-            Target::Domains(filter) => scope::<Domain>(rl, &filter),
-            Target::Subdomains(filter) => scope::<Subdomain>(rl, &filter),
-            Target::IpAddrs(filter) => scope::<IpAddr>(rl, &filter),
-            Target::Urls(filter) => scope::<Url>(rl, &filter),
-            Target::Emails(filter) => scope::<Email>(rl, &filter),
-            Target::PhoneNumbers(filter) => scope::<PhoneNumber>(rl, &filter),
-            Target::Devices(filter) => scope::<Device>(rl, &filter),
-            Target::Networks(filter) => scope::<Network>(rl, &filter),
-            Target::Accounts(filter) => scope::<Account>(rl, &filter),
-            Target::Breaches(filter) => scope::<Breach>(rl, &filter),
-            Target::Images(filter) => scope::<Image>(rl, &filter),
-            Target::Ports(filter) => scope::<Port>(rl, &filter),
-            Target::Netblocks(filter) => scope::<Netblock>(rl, &filter),
-            Target::CryptoAddrs(filter) => scope::<CryptoAddr>(rl, &filter),
+        let target = match self.subcommand {
+            Subcommand::Changes(changes) => return changes.run(rl),
+            Subcommand::Target(target) => target,
+        };
+        let rows = match target {
+            Target::Domains(_) => scope::<Domain>(rl, &target),
+            Target::Subdomains(_) => scope::<Subdomain>(rl, &target),
+            Target::IpAddrs(_) => scope::<IpAddr>(rl, &target),
+            Target::Urls(_) => scope::<Url>(rl, &target),
+            Target::Emails(_) => scope::<Email>(rl, &target),
+            Target::PhoneNumbers(_) => scope::<PhoneNumber>(rl, &target),
+            Target::Devices(_) => scope::<Device>(rl, &target),
+            Target::Networks(_) => scope::<Network>(rl, &target),
+            Target::Accounts(_) => scope::<Account>(rl, &target),
+            Target::Breaches(_) => scope::<Breach>(rl, &target),
+            Target::Images(_) => scope::<Image>(rl, &target),
+            Target::Ports(_) => scope::<Port>(rl, &target),
+            Target::Netblocks(_) => scope::<Netblock>(rl, &target),
+            Target::CryptoAddrs(_) => scope::<CryptoAddr>(rl, &target),
         }?;
         term::info(&format!("Updated {} rows", rows));
         Ok(())
@@ -41,6 +116,153 @@ pub fn run(rl: &mut Shell, args: &[String]) -> Result<()> {
 }
 
 #[inline]
-fn scope<T: Model + Detailed>(rl: &mut Shell, filter: &Filter) -> Result<usize> {
-    T::scope(rl.db(), &filter.parse()?)
+fn scope<T: Model + Detailed>(rl: &mut Shell, target: &Target) -> Result<usize> {
+    rl.db().set_scoped::<T>(&target.parse(rl.db())?, true)
+}
+
+impl Changes {
+    fn run(self, rl: &mut Shell) -> Result<()> {
+        let (since, run) = self.since.resolve(rl.db())?;
+        let changes = Provenance::scope_changes(rl.db(), since)?
+            .into_iter()
+            .filter(|change| self.rule.is_none() || change.rule() == self.rule.as_deref())
+            .collect::<Vec<_>>();
+
+        if self.revert {
+            let rule = self.rule.as_deref().unwrap_or_default();
+            let reverted = revert_rule(rl.db(), since, rule)?;
+            term::info(&format!("Reverted {} of {} changes caused by {}", reverted, changes.len(), rule));
+            return Ok(());
+        }
+
+        let mut stdout = io::stdout();
+        if self.json {
+            for change in &changes {
+                serde_json::to_writer(&mut stdout, change)?;
+                writeln!(stdout)?;
+            }
+            return Ok(());
+        }
+
+        if let Some(run) = &run {
+            term::info(&format!("Changes since run #{} of {} v{} ({})",
+                run.id, run.module, run.version, run.started.format("%Y-%m-%d %H:%M:%S")));
+        }
+        let mut entered = 0;
+        for change in &changes {
+            let prefix = if change.is_scoped() {
+                entered += 1;
+                Tag(Style::Green, '+')
+            } else {
+                Tag(Style::Red, '-')
+            };
+            writeln!(stdout, "{} {} {:?}: {}", prefix, change.family, change.value, change)?;
+        }
+        term::info(&format!("{} entered scope, {} left scope", entered, changes.len() - entered));
+
+        Ok(())
+    }
+}
+
+/// Undo the changes of a rule that are still in effect. Entities whose scope
+/// has been changed again later, by hand or by another rule, are left alone.
+fn revert_rule(db: &Database, since: NaiveDateTime, rule: &str) -> Result<usize> {
+    let changes = Provenance::scope_changes(db, since)?;
+    let mut latest = HashMap::new();
+    for change in &changes {
+        latest.insert((&change.family, &change.value), change);
+    }
+
+    let mut reverted = 0;
+    for change in latest.values() {
+        if change.rule() == Some(rule) && revert(db, change, rule)? {
+            reverted += 1;
+        }
+    }
+    Ok(reverted)
+}
+
+/// Undo a scope change if the entity still has the scope the change set
+fn revert(db: &Database, change: &Provenance, rule: &str) -> Result<bool> {
+    let scoped = !change.is_scoped();
+    let details = format!("revert {}", rule);
+    match Family::from_str(&change.family)? {
+        Family::Domain => revert_entity::<Domain>(db, &change.value, scoped, details),
+        Family::Subdomain => revert_entity::<Subdomain>(db, &change.value, scoped, details),
+        Family::Ipaddr => revert_entity::<IpAddr>(db, &change.value, scoped, details),
+        Family::Url => revert_entity::<Url>(db, &change.value, scoped, details),
+        Family::Email => revert_entity::<Email>(db, &change.value, scoped, details),
+        Family::Port => revert_entity::<Port>(db, &change.value, scoped, details),
+        Family::Netblock => revert_entity::<Netblock>(db, &change.value, scoped, details),
+        _ => Ok(false),
+    }
+}
+
+fn revert_entity<T: Scopable + Model<ID=str>>(db: &Database, value: &str, scoped: bool, details: String) -> Result<bool> {
+    match T::get_opt(db, value)? {
+        Some(entity) if entity.scoped() != scoped => {
+            entity.set_scoped(db, scoped)?;
+            db.log_scope_change(&entity, scoped, Some(details))?;
+            Ok(true)
+        },
+        _ => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_since() {
+        assert_eq!(Since::from_str("last").unwrap(), Since::LastRun);
+        assert_eq!(Since::from_str("12").unwrap(), Since::Run(12));
+        assert_eq!(Since::from_str("#12").unwrap(), Since::Run(12));
+        assert_eq!(Since::from_str("2020-03-14 16:20:23").unwrap(),
+                   Since::Time(NaiveDateTime::from_str("2020-03-14T16:20:23").unwrap()));
+        assert_eq!(Since::from_str("2020-03-14").unwrap(),
+                   Since::Time(NaiveDateTime::from_str("2020-03-14T00:00:00").unwrap()));
+        assert!(matches!(Since::from_str("12h").unwrap(), Since::Time(_)));
+        assert!(Since::from_str("yesterday").is_err());
+    }
+
+    fn domain(db: &Database, value: &str) -> Domain {
+        db.insert_generic(Insert::Domain(NewDomain {
+            value: value.to_string(),
+            unscoped: false,
+        })).unwrap();
+        Domain::get(db, value).unwrap()
+    }
+
+    fn apply_rule(db: &Database, domain: &Domain, rule: &str, scoped: bool) {
+        domain.set_scoped(db, scoped).unwrap();
+        db.log_scope_change(domain, scoped, Some(format!("autonoscope {}", rule))).unwrap();
+    }
+
+    #[test]
+    fn test_revert_rule() {
+        let db = Database::memory().unwrap();
+        let since = NaiveDateTime::from_str("2000-01-01T00:00:00").unwrap();
+        let a = domain(&db, "a.example.com");
+        let b = domain(&db, "b.example.com");
+        let c = domain(&db, "c.example.com");
+        apply_rule(&db, &a, "domain:example.com", false);
+        apply_rule(&db, &b, "domain:example.com", false);
+        apply_rule(&db, &c, "domain:example.com", false);
+
+        // changed by hand and back by another rule, the value matches but the
+        // change isn't the one of the reverted rule anymore
+        db.set_scoped::<Domain>(&Filter::column_eq("value", "b.example.com"), true).unwrap();
+        apply_rule(&db, &Domain::get(&db, "b.example.com").unwrap(), "domain:b.example.com", false);
+        // changed by hand
+        db.set_scoped::<Domain>(&Filter::column_eq("value", "c.example.com"), true).unwrap();
+
+        assert_eq!(revert_rule(&db, since, "domain:example.com").unwrap(), 1);
+        assert!(Domain::get(&db, "a.example.com").unwrap().scoped());
+        assert!(!Domain::get(&db, "b.example.com").unwrap().scoped());
+        assert!(Domain::get(&db, "c.example.com").unwrap().scoped());
+
+        // the revert is a change of its own, running it again does nothing
+        assert_eq!(revert_rule(&db, since, "domain:example.com").unwrap(), 0);
+    }
 }
@@ -0,0 +1,54 @@
+use clap::Parser;
+use crate::cmd::Cmd;
+use crate::db::Family;
+use crate::db::search;
+use crate::errors::*;
+use crate::shell::Shell;
+use crate::shell::pager::Pager;
+use crate::term;
+use std::io::Write;
+
+#[derive(Debug, Parser)]
+pub struct Args {
+    /// Words that have to show up in the value or the text of an entity, like
+    /// the title of an url or the banner of a port
+    #[arg(required = true)]
+    term: Vec<String>,
+    /// Only search entities of this type, like url or port
+    #[arg(short = 't', long = "type")]
+    family: Option<Family>,
+    /// How many of the best matches are shown
+    #[arg(short = 'n', long, default_value="100")]
+    limit: usize,
+    /// Hide the database ids, they are meaningless outside of this workspace
+    #[arg(long="no-ids")]
+    no_ids: bool,
+    /// Print everything, even if it doesn't fit on the terminal
+    #[arg(long="no-pager")]
+    no_pager: bool,
+}
+
+impl Cmd for Args {
+    fn run(self, rl: &mut Shell) -> Result<()> {
+        if let Some(family @ (Family::SubdomainIpaddr | Family::NetworkDevice | Family::BreachEmail)) = &self.family {
+            bail!("{} is a link between entities and can't be searched", family.as_str());
+        }
+
+        let query = self.term.join(" ");
+        let hits = search::search(rl.db(), &query, self.family.as_ref(), self.limit)?;
+        if hits.is_empty() {
+            term::info(&format!("Nothing found for {:?}", query));
+            return Ok(());
+        }
+
+        let mut out = Pager::new(rl, self.no_pager);
+        for hit in &hits {
+            if self.no_ids {
+                writeln!(out, "{:#}", hit)?;
+            } else {
+                writeln!(out, "{}", hit)?;
+            }
+        }
+        out.finish()
+    }
+}
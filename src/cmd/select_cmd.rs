@@ -2,10 +2,14 @@ use clap::Parser;
 use crate::cmd::Cmd;
 use crate::db::ttl;
 use crate::errors::*;
-use crate::filters::{Target, Filter};
+use crate::db::Filter;
+use crate::filters::Target;
 use crate::models::*;
 use crate::shell::Shell;
+use crate::shell::pager::Pager;
 use serde::Serialize;
+use std::cmp;
+use std::io::Write;
 
 #[derive(Debug, Parser)]
 pub struct Args {
@@ -13,7 +17,7 @@ pub struct Args {
     subcommand: Target,
     /// Print json output
     #[structopt(long, group="output")]
-    json: bool,
+    pub json: bool,
     /// Only print the value instead of the whole object
     #[structopt(long, group="output")]
     values: bool,
@@ -23,6 +27,18 @@ pub struct Args {
     /// Count rows returned
     #[structopt(short = 'c', group="output")]
     count: bool,
+    /// Hide the database ids, they are meaningless outside of this workspace
+    #[structopt(long="no-ids")]
+    no_ids: bool,
+    /// Expand the history of entities, like previous ip addresses of subdomains
+    #[structopt(long)]
+    history: bool,
+    /// Print everything, even if it doesn't fit on the terminal
+    #[structopt(long="no-pager")]
+    no_pager: bool,
+    /// Stop after this many entities
+    #[structopt(short = 'n', long)]
+    limit: Option<usize>,
 }
 
 #[derive(PartialEq)]
@@ -36,7 +52,10 @@ enum Output {
 
 struct Printer<'a, 'b> {
     rl: &'a mut Shell<'b>,
+    out: Pager,
     output: Output,
+    no_ids: bool,
+    history: bool,
 }
 
 impl<'a, 'b> Printer<'a, 'b> {
@@ -53,69 +72,107 @@ impl<'a, 'b> Printer<'a, 'b> {
             Output::Normal
         };
 
+        let out = Pager::new(rl, args.no_pager);
         Printer {
             rl,
+            out,
             output,
+            no_ids: args.no_ids,
+            history: args.history,
         }
     }
 
-    pub fn select<T: Model + Detailed + Serialize>(&self, filter: &Filter) -> Result<()> {
-        let query = self.rl.db().filter::<T>(&filter.parse_optional()?)?;
+    pub fn select<T: Model + Detailed + Serialize>(mut self, filter: &Filter, limit: Option<usize>) -> Result<()> {
+        let rl = &*self.rl;
+        let db = rl.db();
 
         if self.output == Output::Count {
-            println!("{}", query.len());
-        } else {
-            for obj in query {
-                match self.output {
-                    Output::Normal => println!("{}", obj.detailed(self.rl.db())?),
-                    Output::Json => {
-                        let v = serde_json::to_string(&obj)?;
-                        println!("{}", v);
-                    },
-                    Output::Values => {
-                        println!("{}", obj.to_string());
-                    },
-                    Output::Paths => {
-                        let blob = obj.blob()
-                            .ok_or_else(|| format_err!("This model isn't linked to blob storage"))?;
+            let mut count = 0;
+            db.for_each_chunk::<T, _>(filter, CHUNK_SIZE, |chunk| {
+                count += chunk.len();
+                Ok(())
+            })?;
+            if let Some(limit) = limit {
+                count = cmp::min(count, limit);
+            }
+            writeln!(self.out, "{}", count)?;
+            return self.out.finish();
+        }
 
-                        let path = self.rl.blobs()
-                            .join(blob)?;
+        let mut print = |obj: T| -> Result<()> {
+            match self.output {
+                Output::Normal => {
+                    let detailed = if self.history {
+                        obj.detailed_history(db)?
+                    } else {
+                        obj.detailed(db)?
+                    };
+                    if self.no_ids {
+                        writeln!(self.out, "{:#}", detailed)?;
+                    } else {
+                        writeln!(self.out, "{}", detailed)?;
+                    }
+                },
+                Output::Json => {
+                    let v = serde_json::to_string(&obj)?;
+                    writeln!(self.out, "{}", v)?;
+                },
+                Output::Values => {
+                    writeln!(self.out, "{}", obj.to_string())?;
+                },
+                Output::Paths => {
+                    let blob = obj.blob()
+                        .ok_or_else(|| format_err!("This model isn't linked to blob storage"))?;
 
-                        let path = path.to_str()
-                            .ok_or_else(|| format_err!("Path is invalid utf-8"))?;
+                    let path = rl.blobs()
+                        .join(blob)?;
 
-                        println!("{}", path);
-                    },
-                    Output::Count => unreachable!(),
-                }
+                    let path = path.to_str()
+                        .ok_or_else(|| format_err!("Path is invalid utf-8"))?;
+
+                    writeln!(self.out, "{}", path)?;
+                },
+                Output::Count => unreachable!(),
             }
+            Ok(())
+        };
+
+        match limit {
+            Some(limit) => {
+                for obj in db.filter_range::<T>(filter, 0, limit)? {
+                    print(obj)?;
+                }
+            },
+            None => db.for_each_chunk::<T, _>(filter, CHUNK_SIZE, |chunk| {
+                chunk.into_iter().try_for_each(&mut print)
+            })?,
         }
 
-        Ok(())
+        self.out.finish()
     }
 }
 
 impl Cmd for Args {
     fn run(self, rl: &mut Shell) -> Result<()> {
         ttl::reap_expired(rl)?;
+        let filter = self.subcommand.parse_optional(rl.db())?;
         let printer = Printer::new(rl, &self);
 
         match &self.subcommand {
-            Target::Domains(filter) => printer.select::<Domain>(filter),
-            Target::Subdomains(filter) => printer.select::<Subdomain>(filter),
-            Target::IpAddrs(filter) => printer.select::<IpAddr>(filter),
-            Target::Urls(filter) => printer.select::<Url>(filter),
-            Target::Emails(filter) => printer.select::<Email>(filter),
-            Target::PhoneNumbers(filter) => printer.select::<PhoneNumber>(filter),
-            Target::Devices(filter) => printer.select::<Device>(filter),
-            Target::Networks(filter) => printer.select::<Network>(filter),
-            Target::Accounts(filter) => printer.select::<Account>(filter),
-            Target::Breaches(filter) => printer.select::<Breach>(filter),
-            Target::Images(filter) => printer.select::<Image>(filter),
-            Target::Ports(filter) => printer.select::<Port>(filter),
-            Target::Netblocks(filter) => printer.select::<Netblock>(filter),
-            Target::CryptoAddrs(filter) => printer.select::<CryptoAddr>(filter),
+            Target::Domains(_) => printer.select::<Domain>(&filter, self.limit),
+            Target::Subdomains(_) => printer.select::<Subdomain>(&filter, self.limit),
+            Target::IpAddrs(_) => printer.select::<IpAddr>(&filter, self.limit),
+            Target::Urls(_) => printer.select::<Url>(&filter, self.limit),
+            Target::Emails(_) => printer.select::<Email>(&filter, self.limit),
+            Target::PhoneNumbers(_) => printer.select::<PhoneNumber>(&filter, self.limit),
+            Target::Devices(_) => printer.select::<Device>(&filter, self.limit),
+            Target::Networks(_) => printer.select::<Network>(&filter, self.limit),
+            Target::Accounts(_) => printer.select::<Account>(&filter, self.limit),
+            Target::Breaches(_) => printer.select::<Breach>(&filter, self.limit),
+            Target::Images(_) => printer.select::<Image>(&filter, self.limit),
+            Target::Ports(_) => printer.select::<Port>(&filter, self.limit),
+            Target::Netblocks(_) => printer.select::<Netblock>(&filter, self.limit),
+            Target::CryptoAddrs(_) => printer.select::<CryptoAddr>(&filter, self.limit),
         }
     }
 }
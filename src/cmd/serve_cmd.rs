@@ -0,0 +1,35 @@
+use crate::cmd::Cmd;
+use crate::errors::*;
+use crate::serve::{self, Api};
+use crate::shell::Shell;
+use crate::term;
+use clap::Parser;
+use diesel::Connection;
+use std::net::{SocketAddr, TcpListener};
+
+#[derive(Debug, Parser)]
+pub struct Args {
+    /// The address to listen on
+    #[arg(long, default_value="127.0.0.1:8080")]
+    listen: SocketAddr,
+}
+
+impl Cmd for Args {
+    fn run(self, rl: &mut Shell) -> Result<()> {
+        let token = match &rl.config().serve.token {
+            Some(token) if !token.is_empty() => token.clone(),
+            _ => bail!("The api requires a token, set `token` in the [serve] section of the config"),
+        };
+
+        // the api is read-only, make sure nothing slips through a filter
+        rl.db().db().execute("PRAGMA query_only = ON")
+            .context("Failed to make the database read-only")?;
+
+        let listener = TcpListener::bind(self.listen)
+            .context(format!("Failed to listen on {}", self.listen))?;
+        term::success(&format!("Serving workspace {:?} on http://{}/api/v1/", rl.db().name(), self.listen));
+
+        let api = Api::new(rl.db(), rl.blobs(), token);
+        serve::run(&api, listener)
+    }
+}
@@ -1,9 +1,12 @@
 use crate::args;
+use chrono::{Duration, NaiveDateTime, Utc};
 use colored::Colorize;
 use crate::blobs::BlobStorage;
 use crate::cmd::Cmd;
-use crate::db::{ttl, Database};
+use crate::cmd::scope_cmd::Since;
+use crate::db::{ttl, Database, Table};
 use crate::errors::*;
+use crate::fmt::colors::ColorChoice;
 use crate::models::*;
 use crate::shell::{self, Shell};
 use crate::workspaces;
@@ -21,10 +24,14 @@ pub struct Args {
     quiet: bool,
     /// Show workspace statistics in json
     #[arg(short, long)]
-    json: bool,
+    pub json: bool,
     /// Go through all workspaces
     #[arg(short, long)]
     all: bool,
+    /// Also count the entities added since `last` run, a run id, an age like
+    /// `30d` or a datetime
+    #[arg(long)]
+    since: Option<Since>,
 }
 
 impl Args {
@@ -46,16 +53,60 @@ impl Args {
     fn show_count(&self, label: &str, count: usize) {
         self.show_amount(label, count, &count.separated_string());
     }
+
+    fn show_family(&self, family: &FamilyStats) {
+        if self.quiet && family.total == 0 {
+            return;
+        }
+
+        let label = format!("{:20}", family.family);
+        let total = format!("{:>20}", family.total.separated_string());
+        let mut line = if family.total > 0 {
+            format!("{} {}", label.green(), total.yellow())
+        } else {
+            format!("{} {}", label, total)
+        };
+
+        line.push_str(&format!(" {:>10} {:>10} {:>8} {:>8}",
+            family.scoped.separated_string(),
+            family.unscoped.separated_string(),
+            added(family.added_24h),
+            added(family.added_7d)));
+        if let Some(since) = family.added_since {
+            line.push_str(&format!(" {:>8}", added(since)));
+        }
+        println!("{}", line);
+    }
 }
 
-fn count_models<T: Model>(db: &Database) -> Result<usize> {
-    let query = db.list::<T>()?;
-    let count = query.len();
-    Ok(count)
+#[inline]
+fn added(count: i64) -> String {
+    format!("+{}", count.separated_string())
 }
 
+/// The tables of the entities that are counted, by their label
+const FAMILIES: &[(&str, Table)] = &[
+    ("domains", Table::Domains),
+    ("subdomains", Table::Subdomains),
+    ("ipaddrs", Table::Ipaddrs),
+    ("urls", Table::Urls),
+    ("emails", Table::Emails),
+    ("phonenumbers", Table::Phonenumbers),
+    ("devices", Table::Devices),
+    ("networks", Table::Networks),
+    ("accounts", Table::Accounts),
+    ("breaches", Table::Breaches),
+    ("images", Table::Images),
+    ("ports", Table::Ports),
+    ("netblocks", Table::Netblocks),
+    ("cryptoaddrs", Table::Cryptoaddrs),
+];
+
+/// How many modules are listed by the entities they contributed
+const TOP_MODULES: i64 = 5;
+
 #[derive(Debug, Serialize, Deserialize)]
-struct Stats {
+pub struct Stats {
     workspace: String,
     domains: usize,
     subdomains: usize,
@@ -73,6 +124,49 @@ struct Stats {
     cryptoaddrs: usize,
     activity: usize,
     blobs: Option<BlobStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    since: Option<NaiveDateTime>,
+    families: Vec<FamilyStats>,
+    top_modules: Vec<ModuleStats>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct FamilyStats {
+    family: String,
+    total: i64,
+    scoped: i64,
+    unscoped: i64,
+    added_24h: i64,
+    added_7d: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    added_since: Option<i64>,
+}
+
+impl FamilyStats {
+    fn count(db: &Database, family: &str, table: Table, now: NaiveDateTime, since: Option<NaiveDateTime>) -> Result<FamilyStats> {
+        let total = db.count_rows(table)?;
+        let scoped = db.count_scoped(table)?;
+        let added_since = match since {
+            Some(since) => Some(db.count_added_since(table, since)?),
+            None => None,
+        };
+        Ok(FamilyStats {
+            family: family.to_string(),
+            total,
+            scoped,
+            unscoped: total - scoped,
+            added_24h: db.count_added_since(table, now - Duration::hours(24))?,
+            added_7d: db.count_added_since(table, now - Duration::days(7))?,
+            added_since,
+        })
+    }
+}
+
+/// A module by the number of entities it inserted
+#[derive(Debug, Serialize, Deserialize)]
+struct ModuleStats {
+    module: String,
+    entities: i64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -83,29 +177,51 @@ struct BlobStats {
 }
 
 impl Stats {
-    fn count(workspace: String, db: &Database) -> Result<Stats> {
+    pub fn count(workspace: String, db: &Database, since: Option<NaiveDateTime>) -> Result<Stats> {
+        let now = Utc::now().naive_utc();
+        let families = FAMILIES.iter()
+            .map(|(family, table)| FamilyStats::count(db, family, *table, now, since))
+            .collect::<Result<Vec<_>>>()?;
+        let total = |name: &str| {
+            families.iter()
+                .find(|family| family.family == name)
+                .map(|family| family.total as usize)
+                .unwrap_or_default()
+        };
+
+        let top_modules = Provenance::top_modules(db, since, TOP_MODULES)?
+            .into_iter()
+            .map(|(module, entities)| ModuleStats {
+                module,
+                entities,
+            })
+            .collect();
+
         Ok(Stats {
             workspace,
-            domains: count_models::<Domain>(db)?,
-            subdomains: count_models::<Subdomain>(db)?,
-            ipaddrs: count_models::<IpAddr>(db)?,
-            urls: count_models::<Url>(db)?,
-            emails: count_models::<Email>(db)?,
-            phonenumbers: count_models::<PhoneNumber>(db)?,
-            devices: count_models::<Device>(db)?,
-            networks: count_models::<Network>(db)?,
-            accounts: count_models::<Account>(db)?,
-            breaches: count_models::<Breach>(db)?,
-            images: count_models::<Image>(db)?,
-            ports: count_models::<Port>(db)?,
-            netblocks: count_models::<Netblock>(db)?,
-            cryptoaddrs: count_models::<CryptoAddr>(db)?,
+            domains: total("domains"),
+            subdomains: total("subdomains"),
+            ipaddrs: total("ipaddrs"),
+            urls: total("urls"),
+            emails: total("emails"),
+            phonenumbers: total("phonenumbers"),
+            devices: total("devices"),
+            networks: total("networks"),
+            accounts: total("accounts"),
+            breaches: total("breaches"),
+            images: total("images"),
+            ports: total("ports"),
+            netblocks: total("netblocks"),
+            cryptoaddrs: total("cryptoaddrs"),
             activity: Activity::count(db)?,
             blobs: None,
+            since,
+            families,
+            top_modules,
         })
     }
 
-    fn add_blob_usage(&mut self, storage: &BlobStorage) -> Result<()> {
+    pub fn add_blob_usage(&mut self, storage: &BlobStorage) -> Result<()> {
         let blobs = storage.list()?;
 
         let mut total_size = 0;
@@ -142,6 +258,11 @@ impl Cmd for Args {
 
                 let mut rl = shell::init(&args::Args {
                     workspace: Some(ws),
+                    offline: config.network.offline,
+                    color: ColorChoice::Auto,
+                    verbose: 0,
+                    quiet: false,
+                    debug: Vec::new(),
                     subcommand: None,
                 }, config, false)?;
                 self.clone().run(&mut rl)?;
@@ -158,7 +279,11 @@ impl Cmd for Args {
                 println!("{:>41}", workspace.bold());
             }
 
-            let mut stats = Stats::count(workspace.into(), db)?;
+            let since = match &self.since {
+                Some(since) => Some(since.resolve(db)?.0),
+                None => None,
+            };
+            let mut stats = Stats::count(workspace.into(), db, since)?;
             if !self.short {
                 stats.add_blob_usage(rl.blobs())?;
             }
@@ -167,29 +292,76 @@ impl Cmd for Args {
                 let stats = serde_json::to_string(&stats)?;
                 println!("{}", stats);
             } else {
-                self.show_count("domains", stats.domains);
-                self.show_count("subdomains", stats.subdomains);
-                self.show_count("ipaddrs", stats.ipaddrs);
-                self.show_count("urls", stats.urls);
-                self.show_count("emails", stats.emails);
-                self.show_count("phonenumbers", stats.phonenumbers);
-                self.show_count("devices", stats.devices);
-                self.show_count("networks", stats.networks);
-                self.show_count("accounts", stats.accounts);
-                self.show_count("breaches", stats.breaches);
-                self.show_count("images", stats.images);
-                self.show_count("ports", stats.ports);
-                self.show_count("netblocks", stats.netblocks);
-                self.show_count("cryptoaddrs", stats.cryptoaddrs);
+                let mut header = format!("{:20} {:>20} {:>10} {:>10} {:>8} {:>8}", "", "total", "scoped", "unscoped", "24h", "7d");
+                if let Some(since) = stats.since {
+                    header.push_str(&format!(" {:>8} ({})", "since", since.format("%Y-%m-%d %H:%M:%S")));
+                }
+                println!("{}", header.dimmed());
+                for family in &stats.families {
+                    self.show_family(family);
+                }
                 self.show_count("activity", stats.activity);
 
                 if let Some(blobs) = stats.blobs {
                     self.show_count("blobs", blobs.count);
                     self.show_amount("blobs (size)", blobs.total_size as usize, &blobs.total_human_size);
                 }
+
+                if !stats.top_modules.is_empty() {
+                    println!();
+                    println!("{}", "top modules".bold());
+                    for module in &stats.top_modules {
+                        self.show_count(&module.module, module.entities as usize);
+                    }
+                }
             }
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Filter;
+    use diesel::Connection;
+
+    fn domain(value: &str) -> Insert {
+        Insert::Domain(NewDomain {
+            value: value.to_string(),
+            unscoped: false,
+        })
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut db = Database::memory().unwrap();
+        db.insert_generic(domain("example.com")).unwrap();
+        for (module, value) in [("kpcyrd/ctlogs", "a.com"), ("kpcyrd/ctlogs", "b.com"), ("kpcyrd/crtsh", "c.com")] {
+            db.set_origin(Some(Origin {
+                module: module.to_string(),
+                version: "0.1.0".to_string(),
+                target: None,
+                source: None,
+            }));
+            db.insert_generic(domain(value)).unwrap();
+        }
+        db.set_scoped::<Domain>(&Filter::column_eq("value", "c.com"), false).unwrap();
+        db.db().execute("UPDATE domains SET seen_first = '2020-03-14 16:20:23' WHERE value = 'example.com'").unwrap();
+
+        let since = Utc::now().naive_utc() - Duration::hours(1);
+        let stats = Stats::count("test".to_string(), &db, Some(since)).unwrap();
+        assert_eq!(stats.domains, 4);
+        let domains = &stats.families[0];
+        assert_eq!(domains.family, "domains");
+        assert_eq!((domains.total, domains.scoped, domains.unscoped), (4, 3, 1));
+        assert_eq!((domains.added_24h, domains.added_7d, domains.added_since), (3, 3, Some(3)));
+        assert_eq!(stats.subdomains, 0);
+
+        let modules = stats.top_modules.iter()
+            .map(|m| (m.module.as_str(), m.entities))
+            .collect::<Vec<_>>();
+        assert_eq!(modules, vec![("kpcyrd/ctlogs", 2), ("kpcyrd/crtsh", 1)]);
+    }
+}
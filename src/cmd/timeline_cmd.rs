@@ -0,0 +1,131 @@
+use chrono::{Duration, NaiveDate};
+use clap::Parser;
+use colored::Colorize;
+use crate::cmd::Cmd;
+use crate::cmd::scope_cmd::Since;
+use crate::db::Filter;
+use crate::errors::*;
+use crate::filters::Target;
+use crate::models::*;
+use crate::shell::Shell;
+use crate::shell::pager::Pager;
+use std::io::Write;
+
+#[derive(Debug, Parser)]
+pub struct Args {
+    #[command(subcommand)]
+    target: Option<Target>,
+    /// Only show entities added since `last` run, a run id, an age like
+    /// `30d` or a datetime
+    #[arg(long)]
+    since: Option<Since>,
+    /// Skip this many entities
+    #[arg(long, default_value = "0")]
+    offset: i64,
+    /// Stop after this many entities
+    #[arg(short = 'n', long)]
+    limit: Option<i64>,
+    /// Print json output, one line per entity
+    #[arg(long)]
+    pub json: bool,
+    /// Print everything, even if it doesn't fit on the terminal
+    #[arg(long="no-pager")]
+    no_pager: bool,
+}
+
+fn source(entry: &Provenance) -> String {
+    match (&entry.module, &entry.version, &entry.target) {
+        (Some(module), Some(version), Some(target)) => format!("{} v{} on {:?}", module, version, target),
+        (Some(module), Some(version), None) => format!("{} v{}", module, version),
+        (Some(module), None, _) => module.clone(),
+        _ => String::from("(manual)"),
+    }
+}
+
+impl Cmd for Args {
+    fn run(self, rl: &mut Shell) -> Result<()> {
+        let db = rl.db();
+        let mut filter = TimelineFilter::default();
+        if let Some(target) = &self.target {
+            let entities = target.parse_optional(db)?;
+            if entities == Filter::any() {
+                filter.family = Some(target.table().family());
+            } else {
+                filter.with_entities(target.table(), &entities);
+            }
+        }
+        if let Some(since) = &self.since {
+            filter.since = Some(since.resolve(db)?.0);
+        }
+
+        let entries = Provenance::timeline(db, &filter, self.offset, self.limit)?;
+
+        let mut out = Pager::new(rl, self.no_pager || self.json);
+        if self.json {
+            for entry in &entries {
+                writeln!(out, "{}", serde_json::to_string(entry)?)?;
+            }
+            return out.finish();
+        }
+
+        let mut day = None::<NaiveDate>;
+        for entry in &entries {
+            let date = entry.time.date();
+            if day != Some(date) {
+                // the count covers the whole day, not just this page
+                let start = date.and_hms_opt(0, 0, 0).expect("Invalid hour/min/sec");
+                let day_filter = TimelineFilter {
+                    family: filter.family.clone(),
+                    entities: filter.entities.clone(),
+                    since: Some(filter.since.map_or(start, |since| since.max(start))),
+                    until: Some(start + Duration::days(1) - Duration::nanoseconds(1)),
+                };
+                let count = Provenance::count_timeline(db, &day_filter)?;
+
+                if day.is_some() {
+                    writeln!(out)?;
+                }
+                let header = format!("{} ({} added)", date.format("%Y-%m-%d"), count);
+                writeln!(out, "{}", header.bold())?;
+                day = Some(date);
+            }
+
+            writeln!(out, "  {} {:<10} {:?} {}",
+                entry.time.format("%H:%M:%S"),
+                entry.family,
+                entry.value,
+                source(entry).dimmed())?;
+        }
+
+        out.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+    use std::str::FromStr;
+
+    fn entry(module: Option<&str>, target: Option<&str>) -> Provenance {
+        Provenance {
+            id: 1,
+            family: "subdomain".to_string(),
+            value: "www.example.com".to_string(),
+            time: NaiveDateTime::from_str("2020-03-14T16:20:23").unwrap(),
+            action: "insert".to_string(),
+            module: module.map(String::from),
+            version: module.map(|_| "0.1.0".to_string()),
+            target: target.map(String::from),
+            details: None,
+            changes: None,
+        }
+    }
+
+    #[test]
+    fn test_source() {
+        assert_eq!(source(&entry(Some("kpcyrd/ctlogs"), Some("example.com"))), "kpcyrd/ctlogs v0.1.0 on \"example.com\"");
+        assert_eq!(source(&entry(Some("kpcyrd/ctlogs"), None)), "kpcyrd/ctlogs v0.1.0");
+        assert_eq!(source(&entry(None, None)), "(manual)");
+    }
+}
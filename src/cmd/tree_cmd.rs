@@ -0,0 +1,45 @@
+use clap::Parser;
+use crate::cmd::Cmd;
+use crate::db::Family;
+use crate::db::tree::Node;
+use crate::errors::*;
+use crate::shell::Shell;
+use crate::shell::pager::Pager;
+use std::io::Write;
+
+#[derive(Debug, Parser)]
+pub struct Args {
+    /// The type of the entity, like domain or ipaddr
+    family: Family,
+    /// The value of the entity
+    value: String,
+    /// How many links are followed from the entity
+    #[arg(short = 'd', long, default_value="3")]
+    depth: usize,
+    /// Hide the database ids, they are meaningless outside of this workspace
+    #[arg(long="no-ids")]
+    no_ids: bool,
+    /// Print everything, even if it doesn't fit on the terminal
+    #[arg(long="no-pager")]
+    no_pager: bool,
+}
+
+impl Cmd for Args {
+    fn run(self, rl: &mut Shell) -> Result<()> {
+        match self.family {
+            Family::SubdomainIpaddr | Family::NetworkDevice | Family::BreachEmail => {
+                bail!("{} is a link between entities, start from one of the entities instead", self.family.as_str());
+            },
+            _ => (),
+        }
+
+        let tree = Node::build(rl.db(), self.family.table(), &self.value, self.depth)?;
+        let mut out = Pager::new(rl, self.no_pager);
+        if self.no_ids {
+            writeln!(out, "{:#}", tree)?;
+        } else {
+            writeln!(out, "{}", tree)?;
+        }
+        out.finish()
+    }
+}
@@ -0,0 +1,48 @@
+use crate::errors::*;
+use crate::cmd::LiteCmd;
+use crate::config::Config;
+use crate::manifest::Manifest;
+use crate::term;
+use clap::Parser;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Parser)]
+pub struct Args {
+    /// The manifest written by export --manifest
+    manifest: PathBuf,
+    /// Require the manifest to be signed by this public key
+    #[arg(long="key")]
+    key: Option<String>,
+}
+
+impl LiteCmd for Args {
+    fn run(self, _config: &Config) -> Result<()> {
+        let manifest = Manifest::load(&self.manifest)?;
+
+        let signer = manifest.verify_signature()?;
+        match (&signer, &self.key) {
+            (Some(signer), Some(key)) if signer != key => bail!("Manifest is signed by {}, not {}", signer, key),
+            (None, Some(_)) => bail!("Manifest is not signed"),
+            _ => (),
+        }
+
+        let base = self.manifest.parent()
+            .unwrap_or_else(|| Path::new("."));
+        manifest.verify_files(base)?;
+
+        term::success(&format!("Verified {} file(s) of workspace {:?}, exported at {}",
+            manifest.files.len(), manifest.workspace, manifest.exported_at));
+        if let Some(version) = &manifest.schema_version {
+            term::info(&format!("Schema version: {}", version));
+        }
+        for (entity, count) in &manifest.counts {
+            term::info(&format!("{}: {}", entity, count));
+        }
+        match signer {
+            Some(signer) => term::success(&format!("Signed by {}", signer)),
+            None => term::warn("Manifest is not signed"),
+        }
+
+        Ok(())
+    }
+}
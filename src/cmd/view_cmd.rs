@@ -0,0 +1,101 @@
+use clap::Parser;
+use crate::cmd::Cmd;
+use crate::db::Filter;
+use crate::errors::*;
+use crate::models::View;
+use crate::shell::Shell;
+use crate::term;
+
+#[derive(Debug, Parser)]
+#[group(skip)]
+pub struct Args {
+    #[command(subcommand)]
+    subcommand: Subcommand,
+}
+
+#[derive(Debug, Parser)]
+pub enum Subcommand {
+    /// Save a filter as a view, an existing view is replaced
+    #[command(name="save")]
+    Save(Save),
+    /// List all views
+    #[command(name="list")]
+    List,
+    /// Delete a view
+    #[command(name="delete")]
+    Delete(Delete),
+}
+
+#[derive(Debug, Parser)]
+pub struct Save {
+    name: String,
+    /// The filter, like `valid=1 AND unscoped=0`
+    #[arg(required=true)]
+    filter: Vec<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct Delete {
+    name: String,
+}
+
+/// Accept the filter as a single quoted argument as well as separate words
+fn parse_filter(args: &[String]) -> Result<Filter> {
+    let mut args = match args {
+        [arg] => shellwords::split(arg)
+            .map_err(|_| format_err!("Filter has mismatched quotes"))?,
+        args => args.to_vec(),
+    };
+
+    if args.first().map(|x| x.to_lowercase()) != Some(String::from("where")) {
+        args.insert(0, String::from("where"));
+    }
+
+    Filter::parse(&args)
+}
+
+impl Cmd for Args {
+    fn run(self, rl: &mut Shell) -> Result<()> {
+        match self.subcommand {
+            Subcommand::Save(save) => {
+                let filter = parse_filter(&save.filter)?;
+                View::save(rl.db(), &save.name, &filter)?;
+                term::success(&format!("Saved view {:?}", save.name));
+            },
+            Subcommand::List => {
+                for view in View::list(rl.db())? {
+                    println!("{}: {}", view.name, view.query.trim());
+                }
+            },
+            Subcommand::Delete(delete) => {
+                if !View::delete(rl.db(), &delete.name)? {
+                    return Err(NotFoundError(format!("View doesn't exist: {:?}", delete.name)).into());
+                }
+                term::success(&format!("Deleted view {:?}", delete.name));
+            },
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_quoted() {
+        let filter = parse_filter(&["valid=1 AND unscoped=0".to_string()]).unwrap();
+        assert_eq!(filter, Filter::new(" valid = '1' AND unscoped = '0'"));
+    }
+
+    #[test]
+    fn test_parse_words() {
+        let filter = parse_filter(&["where".to_string(), "valid=1".to_string()]).unwrap();
+        assert_eq!(filter, Filter::new(" valid = '1'"));
+    }
+
+    #[test]
+    fn test_parse_related_to() {
+        assert!(parse_filter(&["related-to domain:example.com".to_string()]).is_err());
+    }
+}
@@ -1,8 +1,9 @@
 use clap::Parser;
 use crate::blobs::BlobStorage;
 use crate::cmd::{Cmd, LiteCmd};
-use crate::config::Config;
+use crate::config::{Config, DatabaseConfig};
 use crate::db::Database;
+use crate::encryption;
 use crate::errors::*;
 use crate::shell::Shell;
 use crate::term;
@@ -10,7 +11,10 @@ use crate::utils;
 use crate::workspaces::{self, Workspace};
 
 #[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true)]
 pub struct Args {
+    #[command(subcommand)]
+    subcommand: Option<Subcommand>,
     /// Delete a workspaceb
     #[arg(long = "delete", group = "action")]
     delete: bool,
@@ -23,6 +27,25 @@ pub struct Args {
     workspaces: Vec<Workspace>,
 }
 
+#[derive(Debug, Parser)]
+pub enum Subcommand {
+    /// Encrypt the database and the blobs of a workspace with a passphrase
+    #[command(name="encrypt")]
+    Encrypt(Encrypt),
+}
+
+#[derive(Debug, Parser)]
+pub struct Encrypt {
+    workspace: Workspace,
+}
+
+fn encrypt(workspace: Workspace, config: &DatabaseConfig) -> Result<()> {
+    let passphrase = encryption::passphrase(&workspace, config, true)?;
+    encryption::encrypt_workspace(&workspace, &passphrase)?;
+    term::success(&format!("Encrypted workspace {:?}", workspace.as_str()));
+    Ok(())
+}
+
 fn delete(workspace: Workspace, force: bool) -> Result<()> {
     if !force && !utils::no_else_yes(&format!("Do you really want to delete {:?}", workspace.as_str()))? {
         return Ok(());
@@ -49,8 +72,10 @@ fn usage(mut workspaces: Vec<Workspace>) -> Result<()> {
 fn change(rl: &mut Shell, workspace: Workspace) -> Result<()> {
     workspace.migrate()?;
 
-    let blobs = BlobStorage::workspace(&workspace)?;
-    let db = Database::establish(workspace)?;
+    let key = encryption::unlock(&workspace, &rl.config().database)?;
+    let blobs = BlobStorage::workspace(&workspace)?
+        .with_key(key.as_ref());
+    let db = Database::establish(workspace, &rl.config().database)?;
     rl.set_blobstorage(blobs);
     rl.set_db(db);
     Ok(())
@@ -63,8 +88,15 @@ fn list() -> Result<()> {
     Ok(())
 }
 
-fn run(mut args: Args, rl: Option<&mut Shell>) -> Result<()> {
-    if args.delete {
+fn run(mut args: Args, rl: Option<&mut Shell>, config: &Config) -> Result<()> {
+    if let Some(Subcommand::Encrypt(Encrypt { workspace })) = args.subcommand {
+        if let Some(rl) = &rl {
+            if *rl.db().workspace() == workspace {
+                bail!("Can't encrypt current workspace, switch to a different workspace first")
+            }
+        }
+        encrypt(workspace, &config.database)?;
+    } else if args.delete {
         if args.workspaces.is_empty() {
             bail!("--delete requires workspace");
         }
@@ -96,13 +128,14 @@ fn run(mut args: Args, rl: Option<&mut Shell>) -> Result<()> {
 impl Cmd for Args {
     #[inline]
     fn run(self, rl: &mut Shell) -> Result<()> {
-        run(self, Some(rl))
+        let config = rl.config().clone();
+        run(self, Some(rl), &config)
     }
 }
 
 impl LiteCmd for Args {
     #[inline]
-    fn run(self, _config: &Config) -> Result<()> {
-        run(self, None)
+    fn run(self, config: &Config) -> Result<()> {
+        run(self, None, config)
     }
 }
@@ -0,0 +1,158 @@
+use crate::errors::*;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor, Read};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Bzip2,
+}
+
+impl Compression {
+    pub fn from_magic(buf: &[u8]) -> Option<Compression> {
+        if buf.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if buf.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else if buf.starts_with(b"BZh") {
+            Some(Compression::Bzip2)
+        } else {
+            None
+        }
+    }
+
+    pub fn from_extension(path: &Path) -> Option<Compression> {
+        let ext = path.extension()?.to_str()?;
+        match ext {
+            "gz" | "tgz" => Some(Compression::Gzip),
+            "zst" | "zstd" => Some(Compression::Zstd),
+            "bz2" => Some(Compression::Bzip2),
+            _ => None,
+        }
+    }
+
+    fn wrap<'a, R: BufRead + Send + 'a>(self, reader: R) -> Result<Box<dyn BufRead + Send + 'a>> {
+        let reader: Box<dyn BufRead + Send + 'a> = match self {
+            Compression::Gzip => Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(reader))),
+            Compression::Zstd => Box::new(BufReader::new(zstd::Decoder::with_buffer(reader)?)),
+            Compression::Bzip2 => Box::new(BufReader::new(bzip2::bufread::MultiBzDecoder::new(reader))),
+        };
+        Ok(reader)
+    }
+}
+
+/// Detect compressed data by its magic bytes and decompress it while it's
+/// being read, anything else is passed through
+pub fn decompress<'a, R: Read + Send + 'a>(mut reader: R) -> Result<Box<dyn BufRead + Send + 'a>> {
+    // a single read may return less than we need to detect the format
+    let mut head = Vec::with_capacity(4);
+    while head.len() < 4 {
+        let mut buf = [0; 4];
+        let n = reader.read(&mut buf[..4 - head.len()])?;
+        if n == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+
+    let compression = Compression::from_magic(&head);
+    let reader = BufReader::new(Cursor::new(head).chain(reader));
+    match compression {
+        Some(compression) => compression.wrap(reader),
+        None => Ok(Box::new(reader)),
+    }
+}
+
+/// Open a file that may be compressed, the extension is checked before
+/// falling back to the magic bytes
+pub fn open(path: &Path) -> Result<Box<dyn BufRead + Send>> {
+    let file = File::open(path)
+        .context(format_err!("Failed to open {:?}", path))?;
+    match Compression::from_extension(path) {
+        Some(compression) => compression.wrap(BufReader::new(file)),
+        None => decompress(file),
+    }
+}
+
+/// Read all of stdin, decompressing it if necessary
+pub fn stdin() -> Result<Box<dyn BufRead + Send>> {
+    decompress(io::stdin())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const DATA: &[u8] = b"{\"value\":\"example.com\"}\n{\"value\":\"example.org\"}\n";
+
+    fn read_all<R: BufRead>(mut reader: R) -> Vec<u8> {
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).unwrap();
+        buf
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut w = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        w.write_all(data).unwrap();
+        w.finish().unwrap()
+    }
+
+    #[test]
+    fn test_plain() {
+        let r = decompress(DATA).unwrap();
+        assert_eq!(read_all(r), DATA);
+    }
+
+    #[test]
+    fn test_short() {
+        let r = decompress(&b"B"[..]).unwrap();
+        assert_eq!(read_all(r), b"B");
+        let r = decompress(&b""[..]).unwrap();
+        assert_eq!(read_all(r), b"");
+    }
+
+    #[test]
+    fn test_gzip() {
+        let data = gzip(DATA);
+        let r = decompress(&data[..]).unwrap();
+        assert_eq!(read_all(r), DATA);
+    }
+
+    #[test]
+    fn test_zstd() {
+        let data = zstd::encode_all(DATA, 3).unwrap();
+        let r = decompress(&data[..]).unwrap();
+        assert_eq!(read_all(r), DATA);
+    }
+
+    #[test]
+    fn test_bzip2() {
+        let mut w = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        w.write_all(DATA).unwrap();
+        let data = w.finish().unwrap();
+        let r = decompress(&data[..]).unwrap();
+        assert_eq!(read_all(r), DATA);
+    }
+
+    #[test]
+    fn test_open_by_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("emails.ndjson.gz");
+        std::fs::write(&path, gzip(DATA)).unwrap();
+        assert_eq!(read_all(open(&path).unwrap()), DATA);
+
+        let path = dir.path().join("emails.ndjson");
+        std::fs::write(&path, gzip(DATA)).unwrap();
+        assert_eq!(read_all(open(&path).unwrap()), DATA);
+    }
+
+    #[test]
+    fn test_extension() {
+        assert_eq!(Compression::from_extension(Path::new("a.txt.zst")), Some(Compression::Zstd));
+        assert_eq!(Compression::from_extension(Path::new("a.txt.bz2")), Some(Compression::Bzip2));
+        assert_eq!(Compression::from_extension(Path::new("a.txt")), None);
+    }
+}
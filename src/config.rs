@@ -1,21 +1,41 @@
 use crate::errors::*;
+use crate::db::Filter;
+use crate::fmt::colors::Palette;
 use serde::{Serialize, Deserialize};
 use crate::notify::NotificationConfig;
+use crate::scheduler::SchedulerConfig;
+use crate::term::PromptFormat;
+use std::cmp::Reverse;
 use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use std::net::SocketAddr;
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Config {
+    #[serde(default)]
+    pub activity: ActivityConfig,
+    #[serde(default)]
+    pub colors: Palette,
     #[serde(default)]
     pub core: CoreConfig,
     #[serde(default)]
+    pub database: DatabaseConfig,
+    #[serde(default)]
     pub namespaces: HashMap<String, PathBuf>,
     #[serde(default)]
     pub network: NetworkConfig,
     #[serde(default)]
     pub notifications: HashMap<String, NotificationConfig>,
+    #[serde(default)]
+    pub registries: HashMap<String, RegistryConfig>,
+    #[serde(default)]
+    pub sandbox: SandboxConfig,
+    #[serde(default)]
+    pub scheduler: SchedulerConfig,
+    #[serde(default)]
+    pub serve: ServeConfig,
 }
 
 impl Config {
@@ -41,13 +61,183 @@ impl Config {
     }
 
     pub fn load_from<P: AsRef<Path>>(path: P) -> Result<Config> {
-        let config = fs::read_to_string(&path)
+        let text = fs::read_to_string(&path)
             .context("Failed to read config file")?;
+        Config::parse(&text)
+    }
 
-        let config = toml::from_str(&config)?;
+    fn parse(text: &str) -> Result<Config> {
+        let config: Config = toml::from_str(text)?;
+        if config.registries.contains_key(DEFAULT_REGISTRY) {
+            bail!("The registry name {:?} is reserved, configure it with core.registry instead", DEFAULT_REGISTRY);
+        }
+        for policy in &config.activity.dedup {
+            policy.validate()
+                .context(format_err!("Invalid activity dedup policy for {:?}", policy.topic))?;
+        }
+        for (name, notification) in &config.notifications {
+            notification.validate()
+                .context(format_err!("Invalid notification config {:?}", name))?;
+        }
+        config.scheduler.validate(&config.notifications)?;
+        config.validate_filters(text)?;
 
         Ok(config)
     }
+
+    /// Filters are checked against the real schema, so a typo in a column
+    /// doesn't silently drop every notification
+    fn validate_filters(&self, text: &str) -> Result<()> {
+        for (name, notification) in &self.notifications {
+            let Some(filter) = &notification.filter else { continue };
+            if let Err(err) = NotificationConfig::validate_filter(filter.get_ref()) {
+                let span = filter.span();
+                let offset = Filter::parse_expr(None, filter.get_ref()).ok()
+                    .and_then(|filter| filter.unknown_column())
+                    .and_then(|column| find_word(&text[span.clone()], &column))
+                    .unwrap_or(0);
+                let (line, column) = position(text, span.start + offset);
+                bail!("Invalid filter of notification {:?} at line {}, column {}: {}", name, line, column, err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Every registry, in the order they are asked for a module
+    pub fn registries(&self) -> Vec<Registry> {
+        let mut registries = vec![Registry {
+            name: DEFAULT_REGISTRY.to_string(),
+            url: self.core.registry.clone(),
+            namespaces: Vec::new(),
+            priority: 0,
+        }];
+        registries.extend(self.registries.iter()
+            .map(|(name, registry)| Registry {
+                name: name.clone(),
+                url: registry.url.trim_end_matches('/').to_string(),
+                namespaces: registry.namespaces.clone(),
+                priority: registry.priority,
+            }));
+        // the default registry wins a tie
+        registries.sort_by_key(|x| (Reverse(x.priority), x.name != DEFAULT_REGISTRY, x.name.clone()));
+        registries
+    }
+
+    /// The registries that may serve modules of an author. If a registry
+    /// claims the author as namespace, no other registry is asked.
+    pub fn registries_for(&self, author: &str) -> Vec<Registry> {
+        let registries = self.registries();
+        let claimed = registries.iter()
+            .filter(|x| x.namespaces.iter().any(|x| x == author))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !claimed.is_empty() {
+            claimed
+        } else {
+            registries.into_iter()
+                .filter(|x| x.namespaces.is_empty())
+                .collect()
+        }
+    }
+
+    /// The folders of namespaces that are managed outside of sn0int, relative
+    /// paths are resolved from the home folder
+    pub fn namespace_folders(&self) -> Result<Vec<(&String, PathBuf)>> {
+        let mut folders = Vec::new();
+        for (author, folder) in &self.namespaces {
+            let folder = if folder.is_absolute() {
+                folder.to_owned()
+            } else {
+                let folder = folder.strip_prefix("~/")
+                    .unwrap_or(folder);
+
+                dirs_next::home_dir()
+                    .ok_or_else(|| format_err!("Failed to find home folder"))?
+                    .join(folder)
+            };
+            folders.push((author, folder));
+        }
+        Ok(folders)
+    }
+
+    /// Get a registry by name, `None` is the default registry
+    pub fn registry(&self, name: Option<&str>) -> Result<Registry> {
+        let name = name.unwrap_or(DEFAULT_REGISTRY);
+        self.registries()
+            .into_iter()
+            .find(|x| x.name == name)
+            .ok_or_else(|| format_err!("Registry {:?} isn't configured", name))
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ActivityConfig {
+    #[serde(default)]
+    pub dedup: Vec<DedupPolicy>,
+}
+
+impl ActivityConfig {
+    /// The first policy that matches the topic of an event
+    pub fn dedup_policy(&self, topic: &str) -> Option<&DedupPolicy> {
+        self.dedup.iter()
+            .find(|policy| policy.matches(topic))
+    }
+}
+
+/// Suppress activity events that don't add anything new
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DedupPolicy {
+    /// A glob that matches the topics of the events, like `kpcyrd/ping:*`
+    pub topic: String,
+    /// Events that equal another event of the topic within this window are
+    /// dropped, like `1h`
+    pub window: Option<String>,
+    /// Only log an event if it's different from the previous event of the topic
+    #[serde(default, rename="on-change")]
+    pub on_change: bool,
+    /// The fields of the content that are compared, the whole content is
+    /// compared if this is empty
+    #[serde(default)]
+    pub key: Vec<String>,
+}
+
+impl DedupPolicy {
+    fn validate(&self) -> Result<()> {
+        glob::Pattern::new(&self.topic)?;
+        self.window()?;
+        if self.window.is_none() && !self.on_change {
+            bail!("Either window or on-change needs to be set");
+        }
+        Ok(())
+    }
+
+    pub fn matches(&self, topic: &str) -> bool {
+        glob::Pattern::new(&self.topic)
+            .map(|pattern| pattern.matches(topic))
+            .unwrap_or(false)
+    }
+
+    pub fn window(&self) -> Result<Option<chrono::Duration>> {
+        match &self.window {
+            Some(window) => {
+                let window = Filter::parse_age(window)
+                    .ok_or_else(|| format_err!("Invalid window {:?}, expected something like 90s, 15m, 12h, 30d or 4w", window))?;
+                Ok(Some(window))
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// The part of the content that decides if two events are the same
+    pub fn key(&self, content: &serde_json::Value) -> serde_json::Value {
+        if self.key.is_empty() {
+            return content.clone();
+        }
+        let key = self.key.iter()
+            .map(|field| content.get(field).cloned().unwrap_or(serde_json::Value::Null))
+            .collect();
+        serde_json::Value::Array(key)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -56,6 +246,15 @@ pub struct CoreConfig {
     pub registry: String,
     #[serde(default, rename="no-autoupdate")]
     pub no_autoupdate: bool,
+    #[serde(default="default_batch_size", rename="batch-size")]
+    pub batch_size: usize,
+    #[serde(default, rename="no-pager")]
+    pub no_pager: bool,
+    #[serde(default)]
+    pub prompt: Option<PromptFormat>,
+    /// Lines that are kept in the history of a workspace, 0 disables it
+    #[serde(default="default_history_size", rename="history-size")]
+    pub history_size: usize,
 }
 
 impl Default for CoreConfig {
@@ -63,6 +262,10 @@ impl Default for CoreConfig {
         CoreConfig {
             registry: default_registry(),
             no_autoupdate: false,
+            batch_size: default_batch_size(),
+            no_pager: false,
+            prompt: None,
+            history_size: default_history_size(),
         }
     }
 }
@@ -72,8 +275,441 @@ fn default_registry() -> String {
     String::from("https://sn0int.com")
 }
 
+#[inline]
+fn default_batch_size() -> usize {
+    500
+}
+
+#[inline]
+fn default_history_size() -> usize {
+    1000
+}
+
+/// How sqlite writes the journal of a workspace, `wal` lets `select` run
+/// while a module writes but needs shared memory, which network filesystems
+/// often don't support
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all="lowercase")]
+pub enum JournalMode {
+    Wal,
+    Delete,
+    Truncate,
+}
+
+impl JournalMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JournalMode::Wal => "WAL",
+            JournalMode::Delete => "DELETE",
+            JournalMode::Truncate => "TRUNCATE",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all="lowercase")]
+pub enum Synchronous {
+    Off,
+    Normal,
+    Full,
+}
+
+impl Synchronous {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Synchronous::Off => "OFF",
+            Synchronous::Normal => "NORMAL",
+            Synchronous::Full => "FULL",
+        }
+    }
+}
+
+/// Applied to every connection to the database of a workspace
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseConfig {
+    #[serde(default="default_journal_mode", rename="journal-mode")]
+    pub journal_mode: JournalMode,
+    #[serde(default="default_synchronous")]
+    pub synchronous: Synchronous,
+    /// Milliseconds a connection waits for a lock of another connection
+    #[serde(default="default_busy_timeout", rename="busy-timeout")]
+    pub busy_timeout: u32,
+    /// Prints the passphrase of an encrypted workspace, instead of asking for it
+    #[serde(default, rename="passphrase-command")]
+    pub passphrase_command: Option<String>,
+    /// Whether new entities without a matching autonoscope rule take the
+    /// scope of their parent
+    #[serde(default, rename="inherit-scope")]
+    pub inherit_scope: ScopeInheritance,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> DatabaseConfig {
+        DatabaseConfig {
+            journal_mode: default_journal_mode(),
+            synchronous: default_synchronous(),
+            busy_timeout: default_busy_timeout(),
+            passphrase_command: None,
+            inherit_scope: ScopeInheritance::default(),
+        }
+    }
+}
+
+/// The parent of a subdomain is its domain, urls belong to a subdomain and
+/// ports to an ipaddr. An ipaddr has no parent of its own, but was resolved
+/// from the subdomain a module has been running on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all="lowercase")]
+pub enum ScopeInheritance {
+    #[default]
+    Off,
+    Parent,
+}
+
+#[inline]
+fn default_journal_mode() -> JournalMode {
+    JournalMode::Wal
+}
+
+#[inline]
+fn default_synchronous() -> Synchronous {
+    Synchronous::Normal
+}
+
+#[inline]
+fn default_busy_timeout() -> u32 {
+    10_000
+}
+
+/// The name of the registry in `core.registry`
+pub const DEFAULT_REGISTRY: &str = "default";
+
+/// An additional registry, like an internal mirror
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistryConfig {
+    pub url: String,
+    /// Authors that are only installed from this registry, a registry
+    /// without namespaces is asked for every module
+    #[serde(default)]
+    pub namespaces: Vec<String>,
+    /// Registries with a higher priority are asked first, the default
+    /// registry has a priority of 0
+    #[serde(default)]
+    pub priority: i32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Registry {
+    pub name: String,
+    pub url: String,
+    pub namespaces: Vec<String>,
+    pub priority: i32,
+}
+
+impl Registry {
+    #[inline]
+    pub fn is_default(&self) -> bool {
+        self.name == DEFAULT_REGISTRY
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SandboxConfig {
+    /// Memory a module may allocate in MiB, 0 disables the limit
+    #[serde(default="default_memory_limit", rename="memory-limit")]
+    pub memory_limit: usize,
+    /// Rows a module may insert into the database per run, across all of its
+    /// sources, 0 disables the limit
+    #[serde(default, rename="max-output-rows")]
+    pub max_output_rows: usize,
+    /// Seconds a module may spend on a single source before it's killed, 0
+    /// disables the limit
+    #[serde(default, rename="source-timeout")]
+    pub source_timeout: u64,
+    /// Refuse to run modules that don't declare their network egress
+    #[serde(default, rename="require-egress")]
+    pub require_egress: bool,
+}
+
+impl SandboxConfig {
+    pub fn memory_limit_bytes(&self) -> Option<usize> {
+        match self.memory_limit {
+            0 => None,
+            mib => Some(mib.saturating_mul(1024 * 1024)),
+        }
+    }
+
+    pub fn max_output_rows(&self) -> Option<usize> {
+        match self.max_output_rows {
+            0 => None,
+            rows => Some(rows),
+        }
+    }
+
+    pub fn source_timeout(&self) -> Option<Duration> {
+        match self.source_timeout {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+}
+
+impl Default for SandboxConfig {
+    fn default() -> SandboxConfig {
+        SandboxConfig {
+            memory_limit: default_memory_limit(),
+            max_output_rows: 0,
+            source_timeout: 0,
+            require_egress: false,
+        }
+    }
+}
+
+#[inline]
+fn default_memory_limit() -> usize {
+    1024
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServeConfig {
+    /// Clients of `sn0int serve` need to send this as bearer token
+    pub token: Option<String>,
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct NetworkConfig {
     pub proxy: Option<SocketAddr>,
     pub user_agent: Option<String>,
+    #[serde(default)]
+    pub audit: bool,
+    /// Block all network access of modules
+    #[serde(default)]
+    pub offline: bool,
+    /// Refuse to send module traffic anywhere but this socks5 proxy,
+    /// like `socks5://127.0.0.1:9050`
+    pub require_proxy: Option<String>,
+    /// Network operations that may run at the same time in a run, shared
+    /// by all threads
+    pub max_concurrency: Option<usize>,
+}
+
+impl NetworkConfig {
+    pub fn required_proxy(&self) -> Result<Option<SocketAddr>> {
+        let url = match &self.require_proxy {
+            Some(url) => url,
+            None => return Ok(None),
+        };
+
+        let addr = url.strip_prefix("socks5h://")
+            .or_else(|| url.strip_prefix("socks5://"))
+            .ok_or_else(|| format_err!("network.require_proxy needs to be a socks5:// url: {:?}", url))?;
+        let addr = addr.trim_end_matches('/')
+            .parse()
+            .map_err(|_| format_err!("network.require_proxy needs to be an ip address and port: {:?}", url))?;
+        Ok(Some(addr))
+    }
+
+    /// Use the required proxy for everything, a different proxy is an error
+    pub fn apply_required_proxy(&mut self) -> Result<()> {
+        if let Some(required) = self.required_proxy()? {
+            match self.proxy {
+                Some(proxy) if proxy != required => bail!("network.proxy ({}) conflicts with network.require_proxy ({})", proxy, required),
+                _ => self.proxy = Some(required),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The byte offset of the first occurrence of a column name, ignoring case
+fn find_word(text: &str, word: &str) -> Option<usize> {
+    let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let lower = text.to_ascii_lowercase();
+    lower.match_indices(word)
+        .map(|(idx, _)| idx)
+        .find(|idx| {
+            let before = lower[..*idx].chars().next_back();
+            let after = lower[idx + word.len()..].chars().next();
+            !before.map(is_ident).unwrap_or(false) && !after.map(is_ident).unwrap_or(false)
+        })
+}
+
+/// The 1-based line and column of a byte offset in the config file
+fn position(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network(require_proxy: &str) -> NetworkConfig {
+        NetworkConfig {
+            require_proxy: Some(require_proxy.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn registries() -> Config {
+        let config: Config = toml::from_str(r#"
+        [registries.mirror]
+        url = "https://mirror.example.com/"
+        priority = 10
+
+        [registries.internal]
+        url = "https://sn0int.corp.example.com"
+        namespaces = ["corp"]
+
+        [registries.fallback]
+        url = "https://fallback.example.com"
+        priority = -1
+        "#).unwrap();
+        config
+    }
+
+    #[test]
+    fn test_activity_dedup() {
+        let config: Config = toml::from_str(r#"
+        [[activity.dedup]]
+        topic = "kpcyrd/ping:*"
+        window = "1h"
+        key = ["status"]
+
+        [[activity.dedup]]
+        topic = "*"
+        on-change = true
+        "#).unwrap();
+
+        let policy = config.activity.dedup_policy("kpcyrd/ping:example.com").unwrap();
+        assert_eq!(policy.window().unwrap(), Some(chrono::Duration::hours(1)));
+        assert_eq!(policy.key(&serde_json::json!({"status": "up", "rtt": 12})), serde_json::json!(["up"]));
+        let policy = config.activity.dedup_policy("kpcyrd/other:example.com").unwrap();
+        assert!(policy.on_change);
+        assert_eq!(policy.key(&serde_json::json!("up")), serde_json::json!("up"));
+
+        for policy in &config.activity.dedup {
+            policy.validate().unwrap();
+        }
+        let invalid: Config = toml::from_str(r#"
+        [[activity.dedup]]
+        topic = "kpcyrd/ping:*"
+        "#).unwrap();
+        assert!(invalid.activity.dedup[0].validate().is_err());
+    }
+
+    fn names(registries: Vec<Registry>) -> Vec<String> {
+        registries.into_iter().map(|x| x.name).collect()
+    }
+
+    #[test]
+    fn test_registries_priority() {
+        let config = registries();
+        assert_eq!(names(config.registries()), &["mirror", "default", "internal", "fallback"]);
+        assert_eq!(config.registry(Some("mirror")).unwrap().url, "https://mirror.example.com");
+        assert_eq!(config.registry(None).unwrap().url, "https://sn0int.com");
+        assert!(config.registry(Some("missing")).is_err());
+    }
+
+    #[test]
+    fn test_registries_namespaces() {
+        let config = registries();
+        assert_eq!(names(config.registries_for("corp")), &["internal"]);
+        assert_eq!(names(config.registries_for("kpcyrd")), &["mirror", "default", "fallback"]);
+        assert_eq!(names(Config::default().registries_for("corp")), &["default"]);
+    }
+
+    #[test]
+    fn test_required_proxy() {
+        let addr = network("socks5://127.0.0.1:9050").required_proxy().unwrap();
+        assert_eq!(addr, Some("127.0.0.1:9050".parse().unwrap()));
+        let addr = network("socks5h://[::1]:9050/").required_proxy().unwrap();
+        assert_eq!(addr, Some("[::1]:9050".parse().unwrap()));
+        assert_eq!(NetworkConfig::default().required_proxy().unwrap(), None);
+    }
+
+    #[test]
+    fn test_required_proxy_invalid() {
+        assert!(network("127.0.0.1:9050").required_proxy().is_err());
+        assert!(network("http://127.0.0.1:8080").required_proxy().is_err());
+        assert!(network("socks5://localhost:9050").required_proxy().is_err());
+    }
+
+    #[test]
+    fn test_apply_required_proxy() {
+        let mut config = network("socks5://127.0.0.1:9050");
+        config.apply_required_proxy().unwrap();
+        assert_eq!(config.proxy, Some("127.0.0.1:9050".parse().unwrap()));
+
+        let mut config = network("socks5://127.0.0.1:9050");
+        config.proxy = Some("127.0.0.1:1080".parse().unwrap());
+        assert!(config.apply_required_proxy().is_err());
+    }
+
+    #[test]
+    fn test_notification_filter() {
+        let config = Config::parse(r#"
+[notifications.ssh]
+topics = ["db:port:*"]
+filter = "port = 22 and status = 'open'"
+script = "kpcyrd/notify-desktop"
+"#).unwrap();
+        let filter = config.notifications["ssh"].filter.as_ref().unwrap();
+        assert_eq!(filter.get_ref(), "port = 22 and status = 'open'");
+    }
+
+    #[test]
+    fn test_notification_filter_invalid() {
+        let err = Config::parse(r#"
+[notifications.ssh]
+topics = ["db:port:*"]
+filter = "prot = 22"
+script = "kpcyrd/notify-desktop"
+"#).unwrap_err();
+        let err = err.to_string();
+        assert!(err.starts_with("Invalid filter of notification \"ssh\" at line 4, column 11: "), "{}", err);
+    }
+
+    #[test]
+    fn test_notification_filter_position() {
+        let err = Config::parse(r#"
+[notifications.ssh]
+topics = ["db:port:*"]
+filter = '''
+port = 22 and
+    sport = 22'''
+script = "kpcyrd/notify-desktop"
+"#).unwrap_err();
+        let err = err.to_string();
+        assert!(err.starts_with("Invalid filter of notification \"ssh\" at line 6, column 5: "), "{}", err);
+    }
+
+    #[test]
+    fn test_find_word() {
+        assert_eq!(find_word("\"port = 22 and sport = 1\"", "sport"), Some(15));
+        assert_eq!(find_word("\"sports = 1 and SPORT = 1\"", "sport"), Some(16));
+        assert_eq!(find_word("\"port = 1\"", "sport"), None);
+    }
+
+    #[test]
+    fn test_scheduler() {
+        let config = Config::parse(r#"
+[scheduler.jobs.ctlogs]
+module = "kpcyrd/ctlogs"
+interval = "6h"
+"#).unwrap();
+        assert_eq!(config.scheduler.parallel, 1);
+        assert_eq!(config.scheduler.jobs["ctlogs"].workspace, "default");
+
+        let err = Config::parse(r#"
+[scheduler.jobs.ctlogs]
+module = "kpcyrd/ctlogs"
+interval = "6h"
+notify = "ops"
+"#).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid scheduler job \"ctlogs\"");
+        assert_eq!(err.find_root_cause().to_string(), "Notification config \"ops\" doesn't exist");
+    }
 }
@@ -0,0 +1,162 @@
+use crate::errors::*;
+use diesel::connection::TransactionManager;
+use diesel::prelude::*;
+use std::cmp;
+use std::time::{Duration, Instant};
+use super::{in_transaction, retry_busy, Database};
+
+/// A batch is committed after this long even if it isn't full yet, so a slow
+/// stream of rows doesn't lock out other connections
+const MAX_AGE: Duration = Duration::from_secs(1);
+
+/// Groups the writes of many rows into one transaction, instead of a
+/// transaction for every row. Writes inside of the batch use a savepoint, a
+/// row that fails is rolled back on its own. If the batch can't be committed,
+/// all of its rows are rolled back.
+///
+/// The batch doesn't borrow the database, every batch that has rows needs to
+/// be committed or rolled back before it's dropped.
+#[derive(Debug)]
+pub struct Batch {
+    size: usize,
+    rows: usize,
+    started: Instant,
+}
+
+impl Batch {
+    pub fn new(size: usize) -> Batch {
+        Batch {
+            size: cmp::max(size, 1),
+            rows: 0,
+            started: Instant::now(),
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.rows
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.rows == 0
+    }
+
+    /// Call this before the writes of a row, the first row of a batch starts
+    /// the transaction
+    pub fn row(&mut self, db: &Database) -> Result<()> {
+        if self.rows == 0 {
+            if in_transaction(&db.db) {
+                bail!("Batch can't be started inside of a transaction");
+            }
+            retry_busy(|| {
+                db.db.transaction_manager().begin_transaction_sql(&db.db, "BEGIN IMMEDIATE")?;
+                Ok(())
+            })?;
+            self.started = Instant::now();
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// The batch is full or has been open for too long
+    pub fn is_due(&self) -> bool {
+        self.rows >= self.size || (self.rows > 0 && self.started.elapsed() >= MAX_AGE)
+    }
+
+    pub fn commit(&mut self, db: &Database) -> Result<()> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+        self.rows = 0;
+
+        let manager = db.db.transaction_manager();
+        let result = retry_busy(|| {
+            TransactionManager::<SqliteConnection>::commit_transaction(manager, &db.db)?;
+            Ok(())
+        });
+        if let Err(err) = result {
+            db.clear_lookups();
+            if in_transaction(&db.db) {
+                TransactionManager::<SqliteConnection>::rollback_transaction(manager, &db.db)
+                    .context(format_err!("Failed to roll back batch after: {}", err))?;
+            }
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Discard every row of the batch
+    pub fn rollback(&mut self, db: &Database) -> Result<()> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+        self.rows = 0;
+        db.clear_lookups();
+        TransactionManager::<SqliteConnection>::rollback_transaction(db.db.transaction_manager(), &db.db)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::*;
+
+    fn domain(value: &str) -> Insert {
+        Insert::Domain(NewDomain {
+            value: value.to_string(),
+            unscoped: false,
+        })
+    }
+
+    fn domains(db: &Database) -> Vec<String> {
+        db.list::<Domain>().unwrap()
+            .into_iter()
+            .map(|x| x.value)
+            .collect()
+    }
+
+    #[test]
+    fn test_batch() {
+        let db = Database::memory().unwrap();
+        let mut batch = Batch::new(2);
+
+        batch.row(&db).unwrap();
+        db.insert_generic(domain("example.com")).unwrap();
+        assert!(!batch.is_due());
+        batch.row(&db).unwrap();
+        // a row that fails halfway is rolled back on its own
+        let err = db.write::<(), _>(|| {
+            db.insert_generic_once(domain("example.org"))?;
+            bail!("Failed after insert")
+        });
+        assert!(err.is_err());
+        assert!(batch.is_due());
+        batch.commit(&db).unwrap();
+        assert!(batch.is_empty());
+        assert_eq!(domains(&db), &["example.com"]);
+
+        // writes outside of a batch are committed on their own again
+        db.insert_generic(domain("example.net")).unwrap();
+        assert_eq!(domains(&db), &["example.com", "example.net"]);
+    }
+
+    #[test]
+    fn test_batch_rollback() {
+        let db = Database::memory().unwrap();
+        let mut batch = Batch::new(100);
+        for value in &["example.com", "example.org"] {
+            batch.row(&db).unwrap();
+            db.insert_generic(domain(value)).unwrap();
+        }
+        assert_eq!(batch.len(), 2);
+        batch.rollback(&db).unwrap();
+        assert!(domains(&db).is_empty());
+        // the next batch starts a new transaction
+        batch.row(&db).unwrap();
+        db.insert_generic(domain("example.net")).unwrap();
+        batch.commit(&db).unwrap();
+        assert_eq!(domains(&db), &["example.net"]);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::errors::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use super::Table;
+
+/// The cache is dropped and started over if a run discovers more entities
+/// than this
+const MAX_ENTRIES: usize = 100_000;
+
+/// What's known about an entity that has been looked up or inserted
+#[derive(Debug, Clone, PartialEq)]
+pub enum Lookup {
+    /// Inserts of entities that are out of scope are ignored
+    Unscoped,
+    /// The id of the entity and the last insert that has been applied to it
+    Scoped {
+        id: i32,
+        insert: String,
+    },
+}
+
+/// Remembers the entities a module run has looked up or inserted, so an
+/// entity that is reported again doesn't need to be selected by its value.
+///
+/// The cache is only correct as long as it sees every write of the run, it's
+/// cleared by anything else that changes entities and when a write is rolled
+/// back.
+#[derive(Debug, Default)]
+pub struct LookupCache {
+    entries: HashMap<(Table, String), Lookup>,
+    hits: usize,
+}
+
+impl LookupCache {
+    /// The serialized insert is compared with the one that has been applied
+    /// last, the entity would only need an update if they differ
+    pub fn fingerprint<T: Serialize>(insert: &T) -> Result<String> {
+        let s = serde_json::to_string(insert)?;
+        Ok(s)
+    }
+
+    pub fn get(&mut self, table: Table, value: &str) -> Option<Lookup> {
+        let lookup = self.entries.get(&(table, value.to_string())).cloned();
+        if lookup.is_some() {
+            self.hits += 1;
+        }
+        lookup
+    }
+
+    pub fn insert(&mut self, table: Table, value: String, lookup: Lookup) {
+        if self.entries.len() >= MAX_ENTRIES {
+            debug!("Lookup cache is full, starting over");
+            self.entries.clear();
+        }
+        self.entries.insert((table, value), lookup);
+    }
+
+    pub fn remove(&mut self, table: Table, value: &str) {
+        self.entries.remove(&(table, value.to_string()));
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// How many inserts found their entity in the cache
+    #[inline]
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache() {
+        let mut cache = LookupCache::default();
+        assert_eq!(cache.get(Table::Domains, "example.com"), None);
+        cache.insert(Table::Domains, "example.com".to_string(), Lookup::Scoped {
+            id: 1,
+            insert: "{}".to_string(),
+        });
+        cache.insert(Table::Subdomains, "example.com".to_string(), Lookup::Unscoped);
+        assert_eq!(cache.get(Table::Domains, "example.com"), Some(Lookup::Scoped {
+            id: 1,
+            insert: "{}".to_string(),
+        }));
+        assert_eq!(cache.get(Table::Subdomains, "example.com"), Some(Lookup::Unscoped));
+        assert_eq!(cache.hits(), 2);
+
+        cache.remove(Table::Domains, "example.com");
+        assert_eq!(cache.get(Table::Domains, "example.com"), None);
+        assert_eq!(cache.len(), 1);
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+}
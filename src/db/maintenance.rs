@@ -0,0 +1,375 @@
+use crate::blobs::BlobStorage;
+use crate::errors::*;
+use crate::db::{Count, Database, Table};
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Integer, Text};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::net;
+
+#[derive(QueryableByName)]
+struct ForeignKeyViolation {
+    #[sql_type = "Text"]
+    table: String,
+    #[sql_type = "BigInt"]
+    rowid: i64,
+    #[sql_type = "Text"]
+    parent: String,
+}
+
+#[derive(QueryableByName)]
+struct Value {
+    #[sql_type = "Integer"]
+    id: i32,
+    #[sql_type = "Text"]
+    value: String,
+}
+
+#[derive(QueryableByName)]
+struct Message {
+    #[sql_type = "Text"]
+    message: String,
+}
+
+/// Rows of `table` that point to rows of `parent` that don't exist anymore,
+/// left behind by versions that didn't enforce foreign keys
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Orphans {
+    pub table: String,
+    pub parent: String,
+    pub rows: Vec<i64>,
+}
+
+/// An image whose blob isn't in the blob storage
+#[derive(Debug, PartialEq, Serialize)]
+pub struct MissingBlob {
+    pub id: i32,
+    pub blob: String,
+}
+
+/// A value that would have been inserted differently by the current version
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Denormalized {
+    #[serde(skip)]
+    table: Table,
+    pub family: &'static str,
+    pub id: i32,
+    pub value: String,
+    /// `None` if the value isn't valid at all
+    pub normalized: Option<String>,
+    /// The entity that already uses the normalized value
+    pub duplicate: Option<i32>,
+}
+
+impl Denormalized {
+    #[inline]
+    pub fn is_fixable(&self) -> bool {
+        self.normalized.is_some() && self.duplicate.is_none()
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct FsckReport {
+    pub orphans: Vec<Orphans>,
+    pub missing_blobs: Vec<MissingBlob>,
+    /// Blobs that no entity references, like with `blobs gc` the ones that
+    /// have been written in the last hour are skipped
+    pub dangling_blobs: Vec<String>,
+    pub denormalized: Vec<Denormalized>,
+    /// The problems that could be fixed have been fixed
+    pub fixed: bool,
+}
+
+impl FsckReport {
+    pub fn problems(&self) -> usize {
+        self.orphans.iter().map(|x| x.rows.len()).sum::<usize>()
+            + self.missing_blobs.len()
+            + self.dangling_blobs.len()
+            + self.denormalized.len()
+    }
+
+    pub fn fixable(&self) -> usize {
+        self.orphans.iter().map(|x| x.rows.len()).sum::<usize>()
+            + self.dangling_blobs.len()
+            + self.denormalized.iter().filter(|x| x.is_fixable()).count()
+    }
+}
+
+fn orphans(db: &Database) -> Result<Vec<Orphans>> {
+    let violations = diesel::sql_query("PRAGMA foreign_key_check")
+        .load::<ForeignKeyViolation>(db.db())?;
+
+    let mut orphans = BTreeMap::<_, Vec<_>>::new();
+    for violation in violations {
+        orphans.entry((violation.table, violation.parent))
+            .or_default()
+            .push(violation.rowid);
+    }
+    Ok(orphans.into_iter()
+        .map(|((table, parent), rows)| Orphans { table, parent, rows })
+        .collect())
+}
+
+fn lowercase(value: &str) -> Option<String> {
+    Some(value.to_lowercase())
+}
+
+fn ipaddr(value: &str) -> Option<String> {
+    value.parse::<net::IpAddr>().ok()
+        .map(|ipaddr| ipaddr.to_string())
+}
+
+/// The normalization that is applied by the insert of each table
+const NORMALIZED: &[(Table, fn(&str) -> Option<String>)] = &[
+    (Table::Domains, lowercase),
+    (Table::Subdomains, lowercase),
+    (Table::Ipaddrs, ipaddr),
+    (Table::Emails, lowercase),
+    (Table::Devices, lowercase),
+];
+
+fn denormalized(db: &Database) -> Result<Vec<Denormalized>> {
+    let mut denormalized = Vec::new();
+    for (table, normalize) in NORMALIZED {
+        let values = diesel::sql_query(format!("SELECT id, value FROM {}", table.as_str()))
+            .load::<Value>(db.db())?;
+        let existing = values.iter()
+            .map(|x| (x.value.as_str(), x.id))
+            .collect::<BTreeMap<_, _>>();
+
+        for row in &values {
+            let normalized = normalize(&row.value);
+            if normalized.as_ref() == Some(&row.value) {
+                continue;
+            }
+            let duplicate = normalized.as_ref()
+                .and_then(|x| existing.get(x.as_str()))
+                .copied();
+            denormalized.push(Denormalized {
+                table: *table,
+                family: table.family().as_str(),
+                id: row.id,
+                value: row.value.clone(),
+                normalized,
+                duplicate,
+            });
+        }
+    }
+    Ok(denormalized)
+}
+
+fn blobs(db: &Database, storage: &BlobStorage, report: &mut FsckReport) -> Result<()> {
+    let referenced = diesel::sql_query("SELECT id, value FROM images")
+        .load::<Value>(db.db())?;
+    let stored = storage.list()?
+        .into_iter()
+        .collect::<HashSet<_>>();
+
+    for image in &referenced {
+        if !stored.contains(&image.value) {
+            report.missing_blobs.push(MissingBlob {
+                id: image.id,
+                blob: image.value.clone(),
+            });
+        }
+    }
+
+    report.dangling_blobs = storage.gc(db, false)?
+        .unreferenced
+        .into_iter()
+        .map(|x| x.id)
+        .collect();
+    Ok(())
+}
+
+/// Delete the orphans and dangling blobs and normalize values, unless the
+/// normalized value is already taken by another entity
+fn fix(db: &Database, storage: &BlobStorage, report: &FsckReport) -> Result<()> {
+    db.write(|| {
+        for orphans in &report.orphans {
+            for rows in orphans.rows.chunks(500) {
+                let rows = rows.iter()
+                    .map(|x| x.to_string())
+                    .collect::<Vec<_>>();
+                let query = format!("DELETE FROM {} WHERE rowid IN ({})", orphans.table, rows.join(", "));
+                diesel::sql_query(query).execute(db.db())?;
+            }
+        }
+
+        for denormalized in report.denormalized.iter().filter(|x| x.is_fixable()) {
+            diesel::sql_query(format!("UPDATE {} SET value = ? WHERE id = ?", denormalized.table.as_str()))
+                .bind::<Text, _>(denormalized.normalized.as_ref().unwrap())
+                .bind::<Integer, _>(denormalized.id)
+                .execute(db.db())?;
+        }
+        Ok(())
+    })?;
+
+    // checks the references again, a blob may have been referenced since
+    storage.gc(db, true)?;
+    Ok(())
+}
+
+/// Check the references between rows and to blobs, and the values of the
+/// tables that are normalized on insert
+pub fn fsck(db: &Database, storage: &BlobStorage, apply: bool) -> Result<FsckReport> {
+    let mut report = FsckReport {
+        orphans: orphans(db)?,
+        denormalized: denormalized(db)?,
+        ..Default::default()
+    };
+    blobs(db, storage, &mut report)?;
+
+    if apply && report.fixable() > 0 {
+        fix(db, storage, &report)?;
+        report.fixed = true;
+    }
+    Ok(report)
+}
+
+#[derive(Debug, Serialize)]
+pub struct VacuumReport {
+    /// The problems found by sqlite, empty if the database is fine
+    pub integrity: Vec<String>,
+    /// Size of the database in bytes
+    pub size: i64,
+    /// Bytes of pages that are allocated but unused
+    pub free: i64,
+    /// Size of the database after it has been rebuilt
+    pub vacuumed: Option<i64>,
+}
+
+fn size(db: &Database) -> Result<i64> {
+    let size = diesel::sql_query("SELECT (SELECT page_count FROM pragma_page_count()) * (SELECT page_size FROM pragma_page_size()) AS count")
+        .get_result::<Count>(db.db())?;
+    Ok(size.count)
+}
+
+/// Check the integrity of the database file, and rebuild it along with its
+/// indices if `apply` is set
+pub fn vacuum(db: &Database, apply: bool) -> Result<VacuumReport> {
+    let integrity = diesel::sql_query("SELECT quick_check AS message FROM pragma_quick_check()")
+        .load::<Message>(db.db())?
+        .into_iter()
+        .map(|x| x.message)
+        .filter(|x| x != "ok")
+        .collect::<Vec<_>>();
+    let free = diesel::sql_query("SELECT (SELECT freelist_count FROM pragma_freelist_count()) * (SELECT page_size FROM pragma_page_size()) AS count")
+        .get_result::<Count>(db.db())?;
+
+    let mut report = VacuumReport {
+        integrity,
+        size: size(db)?,
+        free: free.count,
+        vacuumed: None,
+    };
+
+    if apply {
+        if !report.integrity.is_empty() {
+            bail!("Refusing to rebuild a database that failed the integrity check");
+        }
+        db.db().batch_execute("INSERT INTO search_index(search_index) VALUES ('optimize'); REINDEX; VACUUM;")
+            .context("Failed to rebuild database")?;
+        // move everything from the write ahead log into the database file
+        db.db().batch_execute("PRAGMA wal_checkpoint(TRUNCATE);")?;
+        report.vacuumed = Some(size(db)?);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+
+    fn age(dir: &tempfile::TempDir, id: &str) {
+        let two_hours_ago = SystemTime::now() - Duration::from_secs(2 * 60 * 60);
+        File::options().write(true).open(dir.path().join(id)).unwrap()
+            .set_modified(two_hours_ago).unwrap();
+    }
+
+    fn setup() -> (tempfile::TempDir, Database, BlobStorage) {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = BlobStorage::new(dir.path());
+        let db = Database::memory().unwrap();
+        db.db().batch_execute("
+            INSERT INTO domains (id, value, unscoped) VALUES (1, 'example.com', 0), (2, 'Example.com', 0), (3, 'Example.org', 0);
+            INSERT INTO ipaddrs (id, family, value, unscoped) VALUES (1, '6', '2001:DB8::1', 0), (2, '4', 'nope', 0);
+            INSERT INTO images (id, value, unscoped) VALUES (1, 'abc', 0), (2, 'def', 0);
+            PRAGMA foreign_keys = OFF;
+            INSERT INTO subdomains (id, domain_id, value, unscoped) VALUES (1, 1, 'www.example.com', 0), (2, 9, 'www.example.net', 0);
+            INSERT INTO subdomain_ipaddrs (subdomain_id, ip_addr_id) VALUES (1, 1), (1, 9);
+            PRAGMA foreign_keys = ON;
+        ").unwrap();
+        std::fs::write(dir.path().join("abc"), b"x").unwrap();
+        std::fs::write(dir.path().join("xyz"), b"x").unwrap();
+        age(&dir, "xyz");
+        // unreferenced, but written too recently to be dangling
+        std::fs::write(dir.path().join("new"), b"x").unwrap();
+        (dir, db, storage)
+    }
+
+    #[test]
+    fn test_fsck() {
+        let (_dir, db, storage) = setup();
+        let report = fsck(&db, &storage, false).unwrap();
+        assert_eq!(report.orphans, &[
+            Orphans { table: "subdomain_ipaddrs".to_string(), parent: "ipaddrs".to_string(), rows: vec![2] },
+            Orphans { table: "subdomains".to_string(), parent: "domains".to_string(), rows: vec![2] },
+        ]);
+        assert_eq!(report.missing_blobs, &[MissingBlob { id: 2, blob: "def".to_string() }]);
+        assert_eq!(report.dangling_blobs, &["xyz"]);
+        let denormalized = report.denormalized.iter()
+            .map(|x| (x.family, x.value.as_str(), x.normalized.as_deref(), x.duplicate))
+            .collect::<Vec<_>>();
+        assert_eq!(denormalized, &[
+            ("domain", "Example.com", Some("example.com"), Some(1)),
+            ("domain", "Example.org", Some("example.org"), None),
+            ("ipaddr", "2001:DB8::1", Some("2001:db8::1"), None),
+            ("ipaddr", "nope", None, None),
+        ]);
+        assert_eq!(report.problems(), 8);
+        assert_eq!(report.fixable(), 5);
+        assert!(!report.fixed);
+    }
+
+    #[test]
+    fn test_fsck_fix() {
+        let (dir, db, storage) = setup();
+        let report = fsck(&db, &storage, true).unwrap();
+        assert!(report.fixed);
+        assert!(!dir.path().join("xyz").exists());
+        assert!(dir.path().join("new").exists());
+
+        // only the problems that can't be fixed are left
+        let report = fsck(&db, &storage, false).unwrap();
+        assert!(report.orphans.is_empty());
+        assert!(report.dangling_blobs.is_empty());
+        assert_eq!(report.missing_blobs.len(), 1);
+        assert_eq!(report.denormalized.len(), 2);
+        assert_eq!(report.fixable(), 0);
+    }
+
+    #[test]
+    fn test_vacuum() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.sqlite");
+        let db = Database::scratch(path.to_str().unwrap(), "test").unwrap();
+        db.db().batch_execute("
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 2000)
+            INSERT INTO domains (value, unscoped) SELECT 'example' || i || '.com', 0 FROM n;
+            DELETE FROM domains;
+        ").unwrap();
+
+        let report = vacuum(&db, false).unwrap();
+        assert!(report.integrity.is_empty());
+        assert!(report.free > 0);
+        assert_eq!(report.vacuumed, None);
+
+        let report = vacuum(&db, true).unwrap();
+        assert!(report.vacuumed.unwrap() < report.size);
+        assert_eq!(vacuum(&db, false).unwrap().free, 0);
+    }
+}
@@ -1,19 +1,38 @@
 use crate::errors::*;
 use serde::{Serialize, Deserialize};
 
+use diesel::connection::{SimpleConnection, TransactionManager};
 use diesel::expression::SqlLiteral;
 use diesel::expression::sql_literal::sql;
-use diesel::sql_types::Bool;
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::sql_types::{Bool, Integer, Timestamp};
 use diesel::prelude::*;
-use std::fmt::Write;
+use diesel::result::DatabaseErrorKind;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write};
+use std::str::FromStr;
+use std::thread;
+use std::time;
+use lazy_static::lazy_static;
 use strum_macros::{EnumString, IntoStaticStr};
-use crate::autonoscope::{RuleSet, RuleType};
+use crate::autonoscope::{self, RuleEntry, RuleSet, RuleType};
+use crate::config::{DatabaseConfig, DedupPolicy, ScopeInheritance};
+use crate::encryption::{self, WorkspaceKey};
 use crate::models::*;
 use crate::schema::*;
 use crate::migrations;
 use crate::worker;
 use crate::workspaces::Workspace;
-
+use self::lookups::{Lookup, LookupCache};
+use sn0int_common::metadata::Source;
+
+pub mod batch;
+pub mod lookups;
+pub mod maintenance;
+pub mod precondition;
+pub mod search;
+pub mod tree;
 pub mod ttl;
 
 
@@ -30,7 +49,43 @@ impl DbChange {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Why a new entity is in or out of scope
+#[derive(Debug, PartialEq)]
+pub enum ScopeDecision {
+    Rule {
+        rule: String,
+        scoped: bool,
+    },
+    /// No rule matched, the scope is taken from the parent, like
+    /// `domain example.com`
+    Inherited {
+        parent: String,
+        scoped: bool,
+    },
+    Default,
+}
+
+impl ScopeDecision {
+    pub fn scoped(&self) -> bool {
+        match self {
+            ScopeDecision::Rule { scoped, .. } => *scoped,
+            ScopeDecision::Inherited { scoped, .. } => *scoped,
+            ScopeDecision::Default => true,
+        }
+    }
+}
+
+impl fmt::Display for ScopeDecision {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScopeDecision::Rule { rule, .. } => write!(w, "autonoscope {}", rule),
+            ScopeDecision::Inherited { parent, .. } => write!(w, "inherited from {}", parent),
+            ScopeDecision::Default => write!(w, "default"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[derive(EnumString, IntoStaticStr)]
 #[strum(serialize_all = "kebab_case")]
 pub enum Family {
@@ -58,9 +113,52 @@ impl Family {
     pub fn as_str(&self) -> &'static str {
         self.into()
     }
+
+    /// The entities a module with this source runs on
+    pub fn from_source(source: &Source) -> Option<Family> {
+        match source {
+            Source::Domains => Some(Family::Domain),
+            Source::Subdomains => Some(Family::Subdomain),
+            Source::IpAddrs => Some(Family::Ipaddr),
+            Source::Urls => Some(Family::Url),
+            Source::Emails => Some(Family::Email),
+            Source::PhoneNumbers => Some(Family::Phonenumber),
+            Source::Networks => Some(Family::Network),
+            Source::Devices => Some(Family::Device),
+            Source::Accounts(_) => Some(Family::Account),
+            Source::Breaches => Some(Family::Breach),
+            Source::Images => Some(Family::Image),
+            Source::Ports => Some(Family::Port),
+            Source::Netblocks => Some(Family::Netblock),
+            Source::CryptoAddrs(_) => Some(Family::Cryptoaddr),
+            Source::KeyRing(_) | Source::Notifications => None,
+        }
+    }
+
+    pub fn table(&self) -> Table {
+        match self {
+            Family::Domain => Table::Domains,
+            Family::Subdomain => Table::Subdomains,
+            Family::Ipaddr => Table::Ipaddrs,
+            Family::SubdomainIpaddr => Table::SubdomainIpaddrs,
+            Family::Url => Table::Urls,
+            Family::Email => Table::Emails,
+            Family::Phonenumber => Table::Phonenumbers,
+            Family::Device => Table::Devices,
+            Family::Network => Table::Networks,
+            Family::NetworkDevice => Table::NetworkDevices,
+            Family::Account => Table::Accounts,
+            Family::Breach => Table::Breaches,
+            Family::BreachEmail => Table::BreachEmails,
+            Family::Image => Table::Images,
+            Family::Port => Table::Ports,
+            Family::Netblock => Table::Netblocks,
+            Family::Cryptoaddr => Table::Cryptoaddrs,
+        }
+    }
 }
 
-#[derive(EnumString, IntoStaticStr)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EnumString, IntoStaticStr)]
 #[strum(serialize_all = "snake_case")]
 pub enum Table {
     Domains,
@@ -83,56 +181,299 @@ pub enum Table {
 }
 
 impl Table {
+    pub const ALL: &'static [Table] = &[
+        Table::Domains,
+        Table::Subdomains,
+        Table::Ipaddrs,
+        Table::SubdomainIpaddrs,
+        Table::Urls,
+        Table::Emails,
+        Table::Phonenumbers,
+        Table::Devices,
+        Table::Networks,
+        Table::NetworkDevices,
+        Table::Accounts,
+        Table::Breaches,
+        Table::BreachEmails,
+        Table::Images,
+        Table::Ports,
+        Table::Netblocks,
+        Table::Cryptoaddrs,
+    ];
+
     #[inline(always)]
     pub fn as_str(&self) -> &'static str {
         self.into()
     }
+
+    pub fn family(&self) -> Family {
+        match self {
+            Table::Domains => Family::Domain,
+            Table::Subdomains => Family::Subdomain,
+            Table::Ipaddrs => Family::Ipaddr,
+            Table::SubdomainIpaddrs => Family::SubdomainIpaddr,
+            Table::Urls => Family::Url,
+            Table::Emails => Family::Email,
+            Table::Phonenumbers => Family::Phonenumber,
+            Table::Devices => Family::Device,
+            Table::Networks => Family::Network,
+            Table::NetworkDevices => Family::NetworkDevice,
+            Table::Accounts => Family::Account,
+            Table::Breaches => Family::Breach,
+            Table::BreachEmails => Family::BreachEmail,
+            Table::Images => Family::Image,
+            Table::Ports => Family::Port,
+            Table::Netblocks => Family::Netblock,
+            Table::Cryptoaddrs => Family::Cryptoaddr,
+        }
+    }
+}
+
+#[derive(QueryableByName)]
+struct TableColumn {
+    #[sql_type = "diesel::sql_types::Text"]
+    name: String,
+    #[sql_type = "diesel::sql_types::Text"]
+    #[column_name = "type"]
+    sql_type: String,
+}
+
+#[derive(QueryableByName)]
+struct Count {
+    #[sql_type = "diesel::sql_types::BigInt"]
+    count: i64,
+}
+
+#[derive(QueryableByName)]
+struct ForeignKey {
+    #[sql_type = "diesel::sql_types::Text"]
+    #[column_name = "from"]
+    column: String,
 }
 
 pub struct Database {
     workspace: Workspace,
     db: SqliteConnection,
     autonoscope: RuleSet,
+    origin: Option<Origin>,
+    inherit_scope: ScopeInheritance,
+    lookups: RefCell<Option<LookupCache>>,
+    scoped_counts: RefCell<ScopedCounts>,
+}
+
+/// Counts of entities in scope, they're kept until the database changes
+#[derive(Debug, Default)]
+struct ScopedCounts {
+    version: Option<(i64, i64)>,
+    counts: HashMap<Table, i64>,
 }
 
 pub type DatabaseSock = diesel::SqliteConnection;
 
+/// How often a write is attempted if another connection holds the lock
+const BUSY_ATTEMPTS: u32 = 5;
+/// Initial delay between retries, doubled after every attempt
+const BUSY_BACKOFF: time::Duration = time::Duration::from_millis(50);
+
+pub(crate) fn is_busy(err: &Error) -> bool {
+    err.iter_chain().any(|cause| {
+        let msg = cause.to_string();
+        msg.contains("database is locked") || msg.contains("database is busy")
+    })
+}
+
+/// Another connection inserted a row with the same unique value first
+pub(crate) fn is_unique_violation(err: &Error) -> bool {
+    err.iter_chain().any(|cause| matches!(cause.downcast_ref::<diesel::result::Error>(),
+        Some(diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _))))
+}
+
+/// Retry with exponential backoff while the database is locked by another
+/// connection
+fn retry_busy<T, F>(mut f: F) -> Result<T>
+    where F: FnMut() -> Result<T>
+{
+    let mut backoff = BUSY_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Err(err) if is_busy(&err) && attempt < BUSY_ATTEMPTS => {
+                debug!("Database is locked, retrying in {:?} ({}/{})", backoff, attempt, BUSY_ATTEMPTS);
+                thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            },
+            Err(err) if is_busy(&err) => {
+                bail!("Database is still locked after {} attempts, another process is writing to this workspace: {}", BUSY_ATTEMPTS, err)
+            },
+            result => return result,
+        }
+    }
+}
+
+#[inline]
+fn in_transaction(db: &SqliteConnection) -> bool {
+    TransactionManager::<SqliteConnection>::get_transaction_depth(db.transaction_manager()) > 0
+}
+
+/// Run a write in a single transaction
+fn write_with_retry<T, F>(db: &SqliteConnection, f: F) -> Result<T>
+    where F: Fn() -> Result<T>
+{
+    // nested writes are part of the outer transaction, a savepoint discards
+    // what a failed write changed without aborting the outer transaction
+    if in_transaction(db) {
+        return db.transaction::<_, Error, _>(&f);
+    }
+
+    retry_busy(|| {
+        let result = db.immediate_transaction::<_, Error, _>(&f);
+        // without wal the COMMIT itself may find the database locked, this
+        // leaves the transaction open
+        if result.is_err() && in_transaction(db) {
+            TransactionManager::<SqliteConnection>::rollback_transaction(db.transaction_manager(), db)?;
+        }
+        result
+    })
+}
+
+/// The change of an insert into a link table, the link may have been inserted
+/// by another connection since we've looked it up
+fn inserted(result: std::result::Result<usize, diesel::result::Error>) -> Result<DbChange> {
+    match result.map_err(Error::from) {
+        Ok(_) => Ok(DbChange::Insert),
+        Err(err) if is_unique_violation(&err) => Ok(DbChange::None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Run `f` in a transaction that is rolled back afterwards, even if it succeeded
+fn rollback_after<T, F>(db: &SqliteConnection, f: F) -> Result<T>
+    where F: FnOnce() -> Result<T>
+{
+    let manager = db.transaction_manager();
+    TransactionManager::<SqliteConnection>::begin_transaction(manager, db)?;
+    let result = f();
+    TransactionManager::<SqliteConnection>::rollback_transaction(manager, db)?;
+    result
+}
+
 impl Database {
-    pub fn establish(workspace: Workspace) -> Result<Database> {
+    pub fn establish(workspace: Workspace, config: &DatabaseConfig) -> Result<Database> {
         let db = worker::spawn_fn("Connecting to database", || {
-            Database::establish_quiet(workspace)
+            Database::establish_quiet(workspace, config)
         }, false)?;
 
         Ok(db)
     }
 
-    pub fn establish_quiet(workspace: Workspace) -> Result<Database> {
+    pub fn establish_quiet(workspace: Workspace, config: &DatabaseConfig) -> Result<Database> {
+        let db = Database::connect(&workspace, config)?;
+        Database::preflight(&db, &workspace)?;
+
+        let autonoscope = RuleSet::load(&db)?;
+
+        Ok(Database {
+            workspace,
+            db,
+            autonoscope,
+            origin: None,
+            inherit_scope: config.inherit_scope,
+            lookups: RefCell::new(None),
+            scoped_counts: RefCell::default(),
+        })
+    }
+
+    /// A migrated database that only exists in memory
+    #[cfg(test)]
+    pub fn memory() -> Result<Database> {
+        Database::scratch(":memory:", "test")
+    }
+
+    /// A fresh database at a path outside of the workspace directory, for
+    /// throwaway workloads like benchmarks
+    pub fn scratch(path: &str, name: &str) -> Result<Database> {
+        let db = Database::connect_path(path, &DatabaseConfig::default(), None)?;
+        migrations::run(&db)?;
+        let autonoscope = RuleSet::load(&db)?;
+
+        Ok(Database {
+            workspace: Workspace::from_str(name)?,
+            db,
+            autonoscope,
+            origin: None,
+            inherit_scope: ScopeInheritance::default(),
+            lookups: RefCell::new(None),
+            scoped_counts: RefCell::default(),
+        })
+    }
+
+    /// Open the sqlite database of a workspace without touching the schema
+    pub fn connect(workspace: &Workspace, config: &DatabaseConfig) -> Result<SqliteConnection> {
+        let key = encryption::unlock(workspace, config)?;
         let path = workspace.db_path()?;
         let path = path.into_os_string().into_string()
             .map_err(|_| format_err!("Failed to convert db path to utf-8"))?;
+        Database::connect_path(&path, config, key.as_ref())
+    }
 
-        let db = SqliteConnection::establish(&path)
+    fn connect_path(path: &str, config: &DatabaseConfig, key: Option<&WorkspaceKey>) -> Result<SqliteConnection> {
+        let db = SqliteConnection::establish(path)
             .context("Failed to connect to database")?;
+        if let Some(key) = key {
+            Database::decrypt(&db, key)?;
+        }
 
-        db.execute("PRAGMA busy_timeout = 10000")
+        // waits for other connections before a write is retried
+        db.execute(&format!("PRAGMA busy_timeout = {}", config.busy_timeout))
             .context("Failed to set busy_timeout")?;
         db.execute("PRAGMA foreign_keys = ON")
             .context("Failed to enforce foreign keys")?;
-        db.execute("PRAGMA journal_mode = WAL")
-            .context("Failed to enable write ahead log")?;
-        db.execute("PRAGMA synchronous = NORMAL")
-            .context("Failed to enforce foreign keys")?;
+        db.execute(&format!("PRAGMA journal_mode = {}", config.journal_mode.as_str()))
+            .context("Failed to set journal mode")?;
+        db.execute(&format!("PRAGMA synchronous = {}", config.synchronous.as_str()))
+            .context("Failed to set synchronous mode")?;
 
-        migrations::run(&db)
-            .context("Failed to run migrations")?;
+        Ok(db)
+    }
 
-        let autonoscope = RuleSet::load(&db)?;
+    /// The key has to be set before anything is read from the database, it has
+    /// already been verified so a failure means the database is damaged
+    fn decrypt(db: &SqliteConnection, key: &WorkspaceKey) -> Result<()> {
+        db.batch_execute(&key.pragma())
+            .context("Failed to set key")?;
+        if encryption::sqlcipher_version(db)?.is_none() {
+            bail!("Workspace is encrypted, but sn0int has been built without sqlcipher");
+        }
+        db.batch_execute("SELECT count(*) FROM sqlite_master")
+            .context("Failed to decrypt database, it's damaged or hasn't been encrypted")?;
+        Ok(())
+    }
 
-        Ok(Database {
-            workspace,
-            db,
-            autonoscope,
-        })
+    /// Refuse to operate on a schema we don't match instead of failing
+    /// halfway through a query. New workspaces are setup right away.
+    fn preflight(db: &SqliteConnection, workspace: &Workspace) -> Result<()> {
+        match migrations::status(db).context("Failed to check schema version")? {
+            migrations::Status::Fresh => {
+                migrations::run(db)
+                    .context("Failed to run migrations")?;
+            },
+            migrations::Status::UpToDate => (),
+            migrations::Status::Pending(pending) => {
+                let cmd = if workspace.as_str() == "default" {
+                    String::from("sn0int migrate")
+                } else {
+                    format!("sn0int -w {} migrate", workspace.as_str())
+                };
+                bail!("Workspace {:?} needs to be upgraded ({} pending migrations), run: {}",
+                    workspace.as_str(), pending.len(), cmd);
+            },
+            migrations::Status::Unknown(unknown) => {
+                bail!("Workspace {:?} has been migrated by a newer version of sn0int ({} unknown migrations), please upgrade sn0int",
+                    workspace.as_str(), unknown.len());
+            },
+        }
+        Ok(())
     }
 
     #[inline(always)]
@@ -150,11 +491,69 @@ impl Database {
         &self.workspace
     }
 
+    /// Run the writes of `f` in one transaction, nested writes are part of
+    /// it
+    #[inline]
+    pub fn write<T, F: Fn() -> Result<T>>(&self, f: F) -> Result<T> {
+        // whatever a failed write has cached has been rolled back
+        let result = write_with_retry(&self.db, || {
+            let result = f();
+            if result.is_err() {
+                self.clear_lookups();
+            }
+            result
+        });
+        if result.is_err() {
+            self.clear_lookups();
+        }
+        result
+    }
+
+    /// Writes inside `f` are visible to later reads in `f`, but nothing is
+    /// committed to the workspace
+    pub fn dry_run<T, F: FnOnce() -> Result<T>>(&self, f: F) -> Result<T> {
+        let result = rollback_after(&self.db, f);
+        self.clear_lookups();
+        result
+    }
+
+    /// Remember the entities that are looked up or inserted, until this is
+    /// disabled again. This is enabled for the duration of a module run.
+    pub fn cache_lookups(&self, enabled: bool) {
+        let mut lookups = self.lookups.borrow_mut();
+        if let Some(cache) = lookups.take() {
+            debug!("Lookup cache had {} hits for {} entities", cache.hits(), cache.len());
+        }
+        if enabled {
+            *lookups = Some(LookupCache::default());
+        }
+    }
+
+    /// Forget the cached lookups, after entities have been changed outside
+    /// of an insert or a write has been rolled back
+    pub(crate) fn clear_lookups(&self) {
+        if let Some(cache) = self.lookups.borrow_mut().as_mut() {
+            cache.clear();
+        }
+    }
+
+    fn with_lookups<T, F: FnOnce(&mut LookupCache) -> T>(&self, f: F) -> Option<T> {
+        self.lookups.borrow_mut().as_mut().map(f)
+    }
+
     #[inline(always)]
     pub fn autonoscope_add_rule(&mut self, object: &RuleType, value: &str, scoped: bool) -> Result<()> {
         self.autonoscope.add_rule(&self.db, object, value, scoped)
     }
 
+    /// Add several rules that have already been validated, either all of
+    /// them are added or none
+    pub fn autonoscope_add_rules(&mut self, rules: &[RuleEntry]) -> Result<()> {
+        self.write(|| RuleSet::write_rules(&self.db, rules))?;
+        self.autonoscope = RuleSet::load(&self.db)?;
+        Ok(())
+    }
+
     #[inline(always)]
     pub fn autonoscope_delete_rule(&mut self, object: &RuleType, value: &str) -> Result<()> {
         self.autonoscope.delete_rule(&self.db, object, value)
@@ -170,73 +569,369 @@ impl Database {
         &self.autonoscope
     }
 
+    /// Set the module run that is responsible for the following changes
+    #[inline(always)]
+    pub fn set_origin(&mut self, origin: Option<Origin>) {
+        self.origin = origin;
+    }
+
+    #[inline(always)]
+    pub fn set_scope_inheritance(&mut self, inherit_scope: ScopeInheritance) {
+        self.inherit_scope = inherit_scope;
+    }
+
+    pub fn log_provenance(&self, family: &Family, value: &str, action: ProvenanceAction, details: Option<String>) -> Result<()> {
+        let now = Utc::now().naive_utc();
+        NewProvenance::new(family, value, action, self.origin.as_ref(), details, now)
+            .insert(self)
+    }
+
+    /// Updates keep their new values as json too, the details are only meant
+    /// to be read by humans
+    fn log_update(&self, family: &Family, value: &str, update: &Update) -> Result<()> {
+        let now = Utc::now().naive_utc();
+        let mut entry = NewProvenance::new(family, value, ProvenanceAction::Update, self.origin.as_ref(), Some(update.to_plain_str()), now);
+        entry.changes = Some(serde_json::Value::Object(update.to_json()?).to_string());
+        entry.insert(self)
+    }
+
+    pub fn log_scope_change<T: Scopable>(&self, entity: &T, scoped: bool, details: Option<String>) -> Result<()> {
+        let action = if scoped {
+            ProvenanceAction::Scope
+        } else {
+            ProvenanceAction::Noscope
+        };
+        self.log_provenance(&T::table().family(), &entity.to_string(), action, details)
+    }
+
+    /// Change the scope of all entities matching the filter, every entity
+    /// that actually changed is added to its provenance
+    pub fn set_scoped<T: Scopable>(&self, filter: &Filter, scoped: bool) -> Result<usize> {
+        self.clear_lookups();
+        self.write(|| {
+            for entity in self.filter::<T>(filter)? {
+                if entity.scoped() != scoped {
+                    self.log_scope_change(&entity, scoped, None)?;
+                }
+            }
+
+            if scoped {
+                T::scope(self, filter)
+            } else {
+                T::noscope(self, filter)
+            }
+        })
+    }
+
+    /// The number of rows in a table
+    pub fn count_rows(&self, table: Table) -> Result<i64> {
+        let query = format!("SELECT COUNT(*) AS count FROM {}", table.as_str());
+        let count = diesel::sql_query(query)
+            .get_result::<Count>(&self.db)?;
+        Ok(count.count)
+    }
+
+    /// The number of entities in a table that are in scope, the table needs
+    /// an `unscoped` column
+    pub fn count_scoped(&self, table: Table) -> Result<i64> {
+        let query = format!("SELECT COUNT(*) AS count FROM {} WHERE unscoped = 0", table.as_str());
+        let count = diesel::sql_query(query)
+            .get_result::<Count>(&self.db)?;
+        Ok(count.count)
+    }
+
+    /// Like `count_scoped`, but the counts are only read again once this or
+    /// another connection changed the database
+    pub fn count_scoped_cached(&self, table: Table) -> Result<i64> {
+        let version = self.data_version()?;
+        let mut cache = self.scoped_counts.borrow_mut();
+        if cache.version != Some(version) {
+            cache.counts.clear();
+            cache.version = Some(version);
+        }
+        if let Some(count) = cache.counts.get(&table) {
+            return Ok(*count);
+        }
+        let count = self.count_scoped(table)?;
+        cache.counts.insert(table, count);
+        Ok(count)
+    }
+
+    /// Changes with this connection and commits of other connections
+    fn data_version(&self) -> Result<(i64, i64)> {
+        let changes = diesel::sql_query("SELECT total_changes() AS count")
+            .get_result::<Count>(&self.db)?;
+        let version = diesel::sql_query("SELECT data_version AS count FROM pragma_data_version()")
+            .get_result::<Count>(&self.db)?;
+        Ok((changes.count, version.count))
+    }
+
+    /// Entities that have been seen for the first time since `since`
+    pub fn count_added_since(&self, table: Table, since: NaiveDateTime) -> Result<i64> {
+        let query = format!("SELECT COUNT(*) AS count FROM {} WHERE seen_first >= ?", table.as_str());
+        let count = diesel::sql_query(query)
+            .bind::<Timestamp, _>(since)
+            .get_result::<Count>(&self.db)?;
+        Ok(count.count)
+    }
+
+    pub fn delete<T: Scopable>(&self, filter: &Filter) -> Result<usize> {
+        self.clear_lookups();
+        self.write(|| {
+            for entity in self.filter::<T>(filter)? {
+                self.log_provenance(&T::table().family(), &entity.to_string(), ProvenanceAction::Delete, None)?;
+            }
+            T::delete(self, filter)
+        })
+    }
+
+    pub fn insert_network_audit(&self, obj: InsertNetworkAudit) -> Result<()> {
+        self.write(|| {
+            let now = Utc::now().naive_utc();
+            obj.clone().into_new(self.origin.as_ref(), now)?
+                .insert(self)
+        })
+    }
+
+    pub fn insert_run(&self, module: &str, version: &str) -> Result<()> {
+        self.write(|| Run::start(self, module, version))
+    }
+
     /// Returns true if we didn't have this value yet
     pub fn insert_generic(&self, object: Insert) -> Result<Option<(DbChange, i32)>> {
-        let scoped = self.autonoscope.matches(&object)?;
+        self.write(|| self.insert_generic_once(object.clone()))
+    }
+
+    /// Autonoscope rules are evaluated first, if none of them matches and
+    /// scope inheritance is enabled the parent of the entity decides
+    pub fn scope_decision(&self, object: &Insert) -> Result<ScopeDecision> {
+        if let Some((rule, scoped)) = self.autonoscope.matching_rule(object)? {
+            return Ok(ScopeDecision::Rule {
+                rule: autonoscope::rule_label(&rule),
+                scoped,
+            });
+        }
+
+        if self.inherit_scope == ScopeInheritance::Parent {
+            if let Some((parent, scoped)) = self.parent_scope(object)? {
+                return Ok(ScopeDecision::Inherited {
+                    parent,
+                    scoped,
+                });
+            }
+        }
+
+        Ok(ScopeDecision::Default)
+    }
+
+    fn parent_scope(&self, object: &Insert) -> Result<Option<(String, bool)>> {
+        let (family, parent, scoped) = match object {
+            Insert::Subdomain(subdomain) => {
+                let domain = Domain::by_id(self, subdomain.domain_id)?;
+                (Family::Domain, domain.to_string(), domain.scoped())
+            },
+            Insert::Url(url) => {
+                let subdomain = Subdomain::by_id(self, url.subdomain_id)?;
+                (Family::Subdomain, subdomain.to_string(), subdomain.scoped())
+            },
+            Insert::Port(port) => {
+                let ipaddr = IpAddr::by_id(self, port.ip_addr_id)?;
+                (Family::Ipaddr, ipaddr.to_string(), ipaddr.scoped())
+            },
+            // resolved by a module that runs on a subdomain
+            Insert::IpAddr(_) => match &self.origin {
+                Some(Origin { source: Some(Family::Subdomain), target: Some(target), .. }) => {
+                    match Subdomain::get_opt(self, target)? {
+                        Some(subdomain) => (Family::Subdomain, subdomain.to_string(), subdomain.scoped()),
+                        None => return Ok(None),
+                    }
+                },
+                _ => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some((format!("{} {}", family.as_str(), parent), scoped)))
+    }
+
+    fn insert_generic_once(&self, object: Insert) -> Result<Option<(DbChange, i32)>> {
+        let decision = self.scope_decision(&object)?;
+        let scoped = decision.scoped();
+        let reason = match decision {
+            ScopeDecision::Default => None,
+            decision => Some(decision.to_string()),
+        };
+        let reason = reason.as_deref();
         match object {
-            Insert::Domain(object) => self.insert_struct(object, scoped),
-            Insert::Subdomain(object) => self.insert_struct(object, scoped),
-            Insert::IpAddr(object) => self.insert_struct(object, scoped),
+            Insert::Domain(object) => self.insert_struct_by_rule(object, scoped, reason),
+            Insert::Subdomain(object) => self.insert_struct_by_rule(object, scoped, reason),
+            Insert::IpAddr(object) => self.insert_struct_by_rule(object, scoped, reason),
             Insert::SubdomainIpAddr(object) => self.insert_subdomain_ipaddr_struct(&object),
-            Insert::Url(object) => self.insert_struct(object, scoped),
-            Insert::Email(object) => self.insert_struct(object, scoped),
-            Insert::PhoneNumber(object) => self.insert_struct(object, scoped),
-            Insert::Device(object) => self.insert_struct(object, scoped),
-            Insert::Network(object) => self.insert_struct(object, scoped),
+            Insert::Url(object) => self.insert_struct_by_rule(object, scoped, reason),
+            Insert::Email(object) => self.insert_struct_by_rule(object, scoped, reason),
+            Insert::PhoneNumber(object) => self.insert_struct_by_rule(object, scoped, reason),
+            Insert::Device(object) => self.insert_struct_by_rule(object, scoped, reason),
+            Insert::Network(object) => self.insert_struct_by_rule(object, scoped, reason),
             Insert::NetworkDevice(object) => self.insert_network_device_struct(&object),
-            Insert::Account(object) => self.insert_struct(object, scoped),
-            Insert::Breach(object) => self.insert_struct(object, scoped),
+            Insert::Account(object) => self.insert_struct_by_rule(object, scoped, reason),
+            Insert::Breach(object) => self.insert_struct_by_rule(object, scoped, reason),
             Insert::BreachEmail(object) => self.insert_breach_email_struct(object),
-            Insert::Image(object) => self.insert_struct(object, scoped),
-            Insert::Port(object) => self.insert_struct(object, scoped),
-            Insert::Netblock(object) => self.insert_struct(object, scoped),
-            Insert::CryptoAddr(object) => self.insert_struct(object, scoped),
+            Insert::Image(object) => self.insert_struct_by_rule(object, scoped, reason),
+            Insert::Port(object) => self.insert_struct_by_rule(object, scoped, reason),
+            Insert::Netblock(object) => self.insert_struct_by_rule(object, scoped, reason),
+            Insert::CryptoAddr(object) => self.insert_struct_by_rule(object, scoped, reason),
         }
     }
 
     /// Returns true if we didn't have this value yet
-    pub fn insert_struct<T: InsertableStruct<M>, M: Model + Scopable>(&self, mut obj: T, scoped: bool) -> Result<Option<(DbChange, i32)>> {
-        if let Some(existing) = M::get_opt(self, obj.value())? {
-            // entity is out of scope
-            if !existing.scoped() {
-                return Ok(None);
-            }
+    pub fn insert_struct<T, M>(&self, obj: T, scoped: bool) -> Result<Option<(DbChange, i32)>>
+        where T: InsertableStruct<M> + Serialize, M: Model<ID = str> + Scopable
+    {
+        self.insert_struct_by_rule(obj, scoped, None)
+    }
 
-            let update = obj.upsert(&existing);
-            if update.is_dirty() {
-                update.apply(self)?;
-                Ok(Some((DbChange::Update(update.generic()), existing.id())))
-            } else {
-                Ok(Some((DbChange::None, existing.id())))
+    /// The reason for the scope, like the autonoscope rule, is logged if the
+    /// entity is new
+    fn insert_struct_by_rule<T, M>(&self, obj: T, scoped: bool, reason: Option<&str>) -> Result<Option<(DbChange, i32)>>
+        where T: InsertableStruct<M> + Serialize, M: Model<ID = str> + Scopable
+    {
+        let table = M::table();
+        let fingerprint = match self.lookups.borrow().is_some() {
+            true => Some(LookupCache::fingerprint(&obj)?),
+            false => None,
+        };
+
+        if let Some(fingerprint) = &fingerprint {
+            match self.with_lookups(|cache| cache.get(table, obj.value())).flatten() {
+                Some(Lookup::Unscoped) => return Ok(None),
+                Some(Lookup::Scoped { id, insert }) if insert == *fingerprint => {
+                    if self.mark_seen::<M>(id, false)? {
+                        return Ok(Some((DbChange::None, id)));
+                    }
+                    // deleted or moved out of scope by another connection
+                    self.with_lookups(|cache| cache.remove(table, obj.value()));
+                },
+                _ => (),
             }
+        }
+
+        let value = obj.value().to_string();
+        let result = if let Some(existing) = M::get_opt(self, &value)? {
+            self.upsert_existing(obj, existing)?
         } else {
-            obj.set_scoped(scoped);
-            obj.insert(self)?;
-            let id = M::get_id(self, obj.value())?;
-            Ok(Some((DbChange::Insert, id)))
+            self.insert_new(obj, scoped, reason)?
+        };
+
+        if let Some(insert) = fingerprint {
+            let lookup = match &result {
+                Some((DbChange::Insert, _)) if !scoped => Lookup::Unscoped,
+                Some((_, id)) => Lookup::Scoped { id: *id, insert },
+                None => Lookup::Unscoped,
+            };
+            self.with_lookups(|cache| cache.insert(table, value, lookup));
         }
+        Ok(result)
     }
 
-    pub fn insert_activity(&self, obj: NewActivity) -> Result<bool> {
-        if let Some(uniq) = &obj.uniq {
-            if Activity::uniq(self, uniq)?.is_some() {
-                // unique tag set and event already logged
-                return Ok(false);
-            }
+    fn upsert_existing<T: InsertableStruct<M>, M: Model + Scopable>(&self, obj: T, existing: M) -> Result<Option<(DbChange, i32)>> {
+        // entity is out of scope
+        if !existing.scoped() {
+            return Ok(None);
+        }
+
+        self.mark_seen::<M>(existing.id(), false)?;
+
+        let update = obj.upsert(&existing);
+        if update.is_dirty() {
+            update.apply(self)?;
+            let update = update.generic();
+            self.log_update(&M::table().family(), &existing.to_string(), &update)?;
+            Ok(Some((DbChange::Update(update), existing.id())))
+        } else {
+            Ok(Some((DbChange::None, existing.id())))
+        }
+    }
+
+    /// The unique index on `value` decides if the entity is new, if it has
+    /// been inserted since we've looked it up this turns into an update
+    fn insert_new<T: InsertableStruct<M>, M: Model + Scopable>(&self, mut obj: T, scoped: bool, reason: Option<&str>) -> Result<Option<(DbChange, i32)>> {
+        obj.set_scoped(scoped);
+        match obj.insert(self) {
+            Err(err) if is_unique_violation(&err) => {
+                debug!("{} was inserted concurrently, updating instead", M::table().family().as_str());
+                let existing = M::get_opt(self, obj.value())?
+                    .ok_or_else(|| format_err!("Entity vanished after unique constraint violation: {}", err))?;
+                return self.upsert_existing(obj, existing);
+            },
+            res => res?,
+        }
+
+        let id = M::get_id(self, obj.value())?;
+        self.mark_seen::<M>(id, true)?;
+
+        let family = M::table().family();
+        let value = M::by_id(self, id)?.to_string();
+        self.log_provenance(&family, &value, ProvenanceAction::Insert, None)?;
+        if !scoped {
+            let details = reason
+                .map(String::from)
+                .unwrap_or_else(|| String::from("autonoscope"));
+            self.log_provenance(&family, &value, ProvenanceAction::Noscope, Some(details))?;
         }
-        obj.insert(self)?;
-        Ok(true)
+
+        Ok(Some((DbChange::Insert, id)))
+    }
+
+    /// Record that an entity has been observed, either for the first time or
+    /// again. Entities that are seen again need to be in scope, this returns
+    /// false if the entity doesn't exist or isn't in scope.
+    fn mark_seen<M: Scopable>(&self, id: i32, new: bool) -> Result<bool> {
+        let table = M::table();
+        let query = if new {
+            format!("UPDATE {} SET seen_first = ?, seen_last = ? WHERE id = ?", table.as_str())
+        } else {
+            format!("UPDATE {} SET seen_count = seen_count + 1, seen_first = COALESCE(seen_first, ?), seen_last = ? WHERE id = ? AND unscoped = 0", table.as_str())
+        };
+        let now = Utc::now().naive_utc();
+        let rows = diesel::sql_query(query)
+            .bind::<Timestamp, _>(now)
+            .bind::<Timestamp, _>(now)
+            .bind::<Integer, _>(id)
+            .execute(&self.db)?;
+        Ok(rows > 0)
+    }
+
+    pub fn insert_activity(&self, obj: NewActivity, dedup: Option<&DedupPolicy>) -> Result<bool> {
+        self.write(|| {
+            if let Some(uniq) = &obj.uniq {
+                if Activity::uniq(self, uniq)?.is_some() {
+                    // unique tag set and event already logged
+                    return Ok(false);
+                }
+            }
+            if let Some(policy) = dedup {
+                if Activity::is_duplicate(self, &obj, policy)? {
+                    debug!("Suppressing duplicate event for {:?}", obj.topic);
+                    return Ok(false);
+                }
+            }
+            obj.clone().insert(self)?;
+            Ok(true)
+        })
     }
 
     pub fn insert_subdomain_ipaddr_struct(&self, subdomain_ipaddr: &NewSubdomainIpAddr) -> Result<Option<(DbChange, i32)>> {
-        if let Some(subdomain_ipaddr_id) = SubdomainIpAddr::get_id_opt(self, &(subdomain_ipaddr.subdomain_id, subdomain_ipaddr.ip_addr_id))? {
+        let now = Utc::now().naive_utc();
+        let existing = SubdomainIpAddr::get_id_opt(self, &(subdomain_ipaddr.subdomain_id, subdomain_ipaddr.ip_addr_id))?;
+        Resolution::seen(self, subdomain_ipaddr.subdomain_id, subdomain_ipaddr.ip_addr_id, existing.is_some(), now)?;
+
+        if let Some(subdomain_ipaddr_id) = existing {
             Ok(Some((DbChange::None, subdomain_ipaddr_id)))
         } else {
-            diesel::insert_into(subdomain_ipaddrs::table)
+            let change = inserted(diesel::insert_into(subdomain_ipaddrs::table)
                 .values(subdomain_ipaddr)
-                .execute(&self.db)?;
+                .execute(&self.db))?;
             let id = SubdomainIpAddr::get_id(self, &(subdomain_ipaddr.subdomain_id, subdomain_ipaddr.ip_addr_id))?;
-            Ok(Some((DbChange::Insert, id)))
+            Ok(Some((change, id)))
         }
     }
 
@@ -244,11 +939,11 @@ impl Database {
         if let Some(network_device_id) = NetworkDevice::get_id_opt(self, &(network_device.network_id, network_device.device_id))? {
             Ok(Some((DbChange::None, network_device_id)))
         } else {
-            diesel::insert_into(network_devices::table)
+            let change = inserted(diesel::insert_into(network_devices::table)
                 .values(network_device)
-                .execute(&self.db)?;
+                .execute(&self.db))?;
             let id = NetworkDevice::get_id(self, &(network_device.network_id, network_device.device_id))?;
-            Ok(Some((DbChange::Insert, id)))
+            Ok(Some((change, id)))
         }
     }
 
@@ -266,9 +961,13 @@ impl Database {
                 Ok(Some((DbChange::None, id)))
             }
         } else {
-            diesel::insert_into(breach_emails::table)
-                .values(obj)
-                .execute(&self.db)?;
+            let result = diesel::insert_into(breach_emails::table)
+                .values(&obj)
+                .execute(&self.db);
+            if let DbChange::None = inserted(result)? {
+                // inserted concurrently, the password may need an update
+                return self.insert_breach_email_struct(obj);
+            }
             let id = BreachEmail::get_id(self, value)?;
             Ok(Some((DbChange::Insert, id)))
         }
@@ -277,7 +976,12 @@ impl Database {
     //
 
     pub fn update_generic(&self, update: &Update) -> Result<i32> {
-        match update {
+        self.clear_lookups();
+        self.write(|| self.update_generic_once(update))
+    }
+
+    fn update_generic_once(&self, update: &Update) -> Result<i32> {
+        let id = match update {
             Update::Subdomain(update) => self.update_subdomain(update),
             Update::IpAddr(update) => self.update_ipaddr(update),
             Update::Url(update) => self.update_url(update),
@@ -292,7 +996,156 @@ impl Database {
             Update::Port(update) => self.update_port(update),
             Update::Netblock(update) => self.update_netblock(update),
             Update::CryptoAddr(update) => self.update_cryptoaddr(update),
+            Update::Fields(update) => self.update_fields(update),
+        }?;
+
+        let family = update.family()?;
+        if let Some(value) = self.entity_value(&family, id)? {
+            self.log_update(&family, &value, update)?;
+        }
+
+        Ok(id)
+    }
+
+    /// The value of an entity, relations between entities don't have one
+    fn entity_value(&self, family: &Family, id: i32) -> Result<Option<String>> {
+        match family {
+            Family::SubdomainIpaddr | Family::NetworkDevice | Family::BreachEmail => Ok(None),
+            _ => {
+                let (value, _) = self.json_by_id(family, id)?;
+                Ok(Some(value))
+            },
+        }
+    }
+
+    /// The names of the columns of a table
+    pub fn columns(&self, table: &str) -> Result<HashSet<String>> {
+        let columns = diesel::sql_query(format!("PRAGMA table_info({})", table))
+            .load::<TableColumn>(&self.db)?
+            .into_iter()
+            .map(|c| c.name.to_lowercase())
+            .collect::<HashSet<_>>();
+        if columns.is_empty() {
+            return Err(NotFoundError(format!("Table doesn't exist: {:?}", table)).into());
+        }
+        Ok(columns)
+    }
+
+    /// Columns of a table that can be changed with a FieldsUpdate, mapped to their sql type
+    fn updatable_columns(&self, table: &Table) -> Result<HashMap<String, String>> {
+        let foreign_keys = diesel::sql_query(format!("PRAGMA foreign_key_list({})", table.as_str()))
+            .load::<ForeignKey>(&self.db)?
+            .into_iter()
+            .map(|fk| fk.column)
+            .collect::<HashSet<_>>();
+
+        let columns = diesel::sql_query(format!("PRAGMA table_info({})", table.as_str()))
+            .load::<TableColumn>(&self.db)?
+            .into_iter()
+            .filter(|c| !IMMUTABLE_FIELDS.contains(&c.name.as_str()))
+            .filter(|c| !SEEN_FIELDS.contains(&c.name.as_str()))
+            .filter(|c| !foreign_keys.contains(&c.name))
+            .map(|c| (c.name, c.sql_type))
+            .collect();
+
+        Ok(columns)
+    }
+
+    fn json_by_id_typed<T: Model + Serialize>(&self, id: i32) -> Result<(String, serde_json::Value)> {
+        let obj = T::by_id(self, id)?;
+        let value = serde_json::to_value(&obj)?;
+        Ok((obj.to_string(), value))
+    }
+
+    /// An entity serialized like it's exported
+    pub fn entity_json(&self, family: &Family, id: i32) -> Result<serde_json::Value> {
+        let (_, value) = self.json_by_id(family, id)?;
+        Ok(value)
+    }
+
+    fn json_by_id(&self, family: &Family, id: i32) -> Result<(String, serde_json::Value)> {
+        match family {
+            Family::Domain => self.json_by_id_typed::<Domain>(id),
+            Family::Subdomain => self.json_by_id_typed::<Subdomain>(id),
+            Family::Ipaddr => self.json_by_id_typed::<IpAddr>(id),
+            Family::SubdomainIpaddr => self.json_by_id_typed::<SubdomainIpAddr>(id),
+            Family::Url => self.json_by_id_typed::<Url>(id),
+            Family::Email => self.json_by_id_typed::<Email>(id),
+            Family::Phonenumber => self.json_by_id_typed::<PhoneNumber>(id),
+            Family::Device => self.json_by_id_typed::<Device>(id),
+            Family::Network => self.json_by_id_typed::<Network>(id),
+            Family::NetworkDevice => self.json_by_id_typed::<NetworkDevice>(id),
+            Family::Account => self.json_by_id_typed::<Account>(id),
+            Family::Breach => self.json_by_id_typed::<Breach>(id),
+            Family::BreachEmail => self.json_by_id_typed::<BreachEmail>(id),
+            Family::Image => self.json_by_id_typed::<Image>(id),
+            Family::Port => self.json_by_id_typed::<Port>(id),
+            Family::Netblock => self.json_by_id_typed::<Netblock>(id),
+            Family::Cryptoaddr => self.json_by_id_typed::<CryptoAddr>(id),
+        }
+    }
+
+    /// Validate the requested fields and only keep those that differ from the
+    /// current row. Returns the value of the entity for logging.
+    pub fn prepare_fields_update(&self, family: &Family, id: i32, fields: serde_json::Map<String, serde_json::Value>) -> Result<(String, FieldsUpdate)> {
+        let table = family.table();
+        let columns = self.updatable_columns(&table)?;
+        let (value, existing) = self.json_by_id(family, id)?;
+
+        let mut changes = Vec::new();
+        for (name, new) in fields {
+            if IMMUTABLE_FIELDS.contains(&name.as_str()) {
+                bail!("Field can't be updated: {:?}", name);
+            }
+            let sql_type = columns.get(&name)
+                .ok_or_else(|| format_err!("Unknown or immutable field for {}: {:?}", family.as_str(), name))?;
+
+            let new = FieldValue::from_json(sql_type, new)
+                .map_err(|err| format_err!("Invalid value for {:?}: {}", name, err))?;
+
+            let old = existing.get(&name)
+                .cloned()
+                .and_then(|v| FieldValue::from_json(sql_type, v).ok());
+            if old.as_ref() == Some(&new) {
+                continue;
+            }
+
+            changes.push((name, new));
+        }
+
+        Ok((value, FieldsUpdate {
+            table: table.as_str().to_string(),
+            id,
+            fields: changes,
+        }))
+    }
+
+    pub fn update_fields(&self, update: &FieldsUpdate) -> Result<i32> {
+        self.clear_lookups();
+        self.write(|| self.update_fields_once(update))
+    }
+
+    fn update_fields_once(&self, update: &FieldsUpdate) -> Result<i32> {
+        // the update might come from the sandbox, so validate everything again
+        let table = Table::from_str(&update.table)
+            .map_err(|_| format_err!("Unknown table: {:?}", update.table))?;
+        let columns = self.updatable_columns(&table)?;
+
+        let mut query = format!("UPDATE {} SET", table.as_str());
+        for (i, (name, value)) in update.fields.iter().enumerate() {
+            if !columns.contains_key(name) {
+                bail!("Unknown or immutable field for {}: {:?}", table.as_str(), name);
+            }
+            if i > 0 {
+                query.push(',');
+            }
+            write!(query, " {} = {}", name, Filter::sql_value(value))?;
         }
+        write!(query, " WHERE id = {}", update.id)?;
+
+        diesel::sql_query(query)
+            .execute(&self.db)?;
+        Ok(update.id)
     }
 
     pub fn update_subdomain(&self, subdomain_update: &SubdomainUpdate) -> Result<i32> {
@@ -446,31 +1299,192 @@ impl Database {
         T::filter(self, filter)
     }
 
+    pub fn for_each_chunk<T: Model, F>(&self, filter: &Filter, chunk_size: usize, f: F) -> Result<()>
+        where F: FnMut(Vec<T>) -> Result<()>
+    {
+        T::for_each_chunk(self, filter, chunk_size, f)
+    }
+
+    /// The entities of the filter after skipping `offset` of them, ordered by
+    /// id. Only `limit` rows are loaded.
+    pub fn filter_range<T: Model>(&self, filter: &Filter, offset: usize, limit: usize) -> Result<Vec<T>> {
+        T::filter_range(self, filter, offset, limit)
+    }
+
     pub fn filter_with_param<T: Model>(&self, filter: &Filter, param: Option<&String>) -> Result<Vec<T>> {
         match param {
             Some(param) => T::filter_with_param(self, filter, param),
             _ => T::filter(self, filter),
         }
     }
-}
 
-#[derive(Debug, PartialEq)]
-pub struct Filter {
-    query: String,
-}
+    fn query_typed<T: Model + Serialize>(&self, filter: &Filter, query: &Query) -> Result<Vec<serde_json::Value>> {
+        self.filter_range::<T>(filter, query.offset, query.limit)?
+            .into_iter()
+            .map(|x| serde_json::to_value(x).map_err(Error::from))
+            .collect()
+    }
 
-impl Filter {
-    pub fn new<I: Into<String>>(query: I) -> Filter {
-        Filter {
-            query: query.into(),
+    pub fn query(&self, query: &Query) -> Result<Vec<serde_json::Value>> {
+        let filter = Filter::from_conditions(&query.conditions)?;
+        // only return entities in scope, unless the caller explicitly filters on it
+        let filter = if query.conditions.iter().any(|c| c.column == "unscoped") {
+            filter
+        } else {
+            filter.and_scoped()
+        };
+
+        match query.family {
+            Family::Domain => self.query_typed::<Domain>(&filter, query),
+            Family::Subdomain => self.query_typed::<Subdomain>(&filter, query),
+            Family::Ipaddr => self.query_typed::<IpAddr>(&filter, query),
+            Family::SubdomainIpaddr => bail!("Unsupported operation"),
+            Family::Url => self.query_typed::<Url>(&filter, query),
+            Family::Email => self.query_typed::<Email>(&filter, query),
+            Family::Phonenumber => self.query_typed::<PhoneNumber>(&filter, query),
+            Family::Device => self.query_typed::<Device>(&filter, query),
+            Family::Network => self.query_typed::<Network>(&filter, query),
+            Family::NetworkDevice => bail!("Unsupported operation"),
+            Family::Account => self.query_typed::<Account>(&filter, query),
+            Family::Breach => self.query_typed::<Breach>(&filter, query),
+            Family::BreachEmail => bail!("Unsupported operation"),
+            Family::Image => self.query_typed::<Image>(&filter, query),
+            Family::Port => self.query_typed::<Port>(&filter, query),
+            Family::Netblock => self.query_typed::<Netblock>(&filter, query),
+            Family::Cryptoaddr => self.query_typed::<CryptoAddr>(&filter, query),
         }
     }
+}
 
-    #[inline]
-    pub fn any() -> Filter {
+/// A single `column op value` expression of a structured query
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Condition {
+    pub column: String,
+    pub op: String,
+    pub value: serde_json::Value,
+}
+
+/// A structured query that can be sent over ipc, unlike a Filter
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Query {
+    pub family: Family,
+    pub conditions: Vec<Condition>,
+    pub limit: usize,
+    pub offset: usize,
+}
+
+/// The comparisons a filter can use, if a value follows they quote it
+pub const OPERATORS: &[&str] = &["=", "!=", "<", ">", "<=", ">=", "like"];
+
+/// Words in a filter that aren't column names
+const SQL_KEYWORDS: &[&str] = &["and", "or", "not", "like", "glob", "in", "is", "null", "between",
+    "escape", "collate", "nocase", "true", "false", "case", "when", "then", "else", "end", "as"];
+
+lazy_static! {
+    /// The columns of every table, taken from the diesel schema
+    static ref SCHEMA_COLUMNS: HashMap<&'static str, HashSet<&'static str>> = schema_columns(include_str!("../schema.rs"));
+}
+
+/// Read the columns out of the `table!` blocks of a diesel schema
+fn schema_columns(schema: &str) -> HashMap<&str, HashSet<&str>> {
+    let mut tables = HashMap::new();
+    let mut table = None;
+    for line in schema.lines() {
+        let line = line.trim();
+        if let Some((name, _)) = line.strip_suffix('{').and_then(|x| x.split_once(" (")) {
+            table = Some(name);
+        } else if let (Some(table), Some((column, _))) = (table, line.split_once(" -> ")) {
+            tables.entry(table)
+                .or_insert_with(HashSet::new)
+                .insert(column);
+        } else if line == "}" {
+            table = None;
+        }
+    }
+    tables
+}
+
+/// Datetime columns that can be compared against an age like `30d`
+const RELATIVE_COLUMNS: &[&str] = &["seen_first", "seen_last"];
+
+/// A link between two kinds of entities, stored as a pair of ids in `table`
+struct Link {
+    table: Table,
+    left: Table,
+    left_id: &'static str,
+    right: Table,
+    right_id: &'static str,
+}
+
+/// Every link between entities, this is used for `related-to`,
+/// preconditions and the tree of an entity
+const LINKS: &[Link] = &[
+    Link { table: Table::Subdomains, left: Table::Domains, left_id: "domain_id", right: Table::Subdomains, right_id: "id" },
+    Link { table: Table::SubdomainIpaddrs, left: Table::Subdomains, left_id: "subdomain_id", right: Table::Ipaddrs, right_id: "ip_addr_id" },
+    Link { table: Table::Urls, left: Table::Subdomains, left_id: "subdomain_id", right: Table::Urls, right_id: "id" },
+    Link { table: Table::Ports, left: Table::Ipaddrs, left_id: "ip_addr_id", right: Table::Ports, right_id: "id" },
+    Link { table: Table::BreachEmails, left: Table::Emails, left_id: "email_id", right: Table::Breaches, right_id: "breach_id" },
+    Link { table: Table::NetworkDevices, left: Table::Devices, left_id: "device_id", right: Table::Networks, right_id: "network_id" },
+];
+
+/// The table of the link between `from` and `to`, with the column of the
+/// `from` id and the column of the `to` id
+fn link(from: Table, to: Table) -> Option<(Table, &'static str, &'static str)> {
+    LINKS.iter().find_map(|link| {
+        if link.left == from && link.right == to {
+            Some((link.table, link.left_id, link.right_id))
+        } else if link.right == from && link.left == to {
+            Some((link.table, link.right_id, link.left_id))
+        } else {
+            None
+        }
+    })
+}
+
+/// All tables that are linked to `table`
+pub fn linked(table: Table) -> Vec<Table> {
+    LINKS.iter()
+        .filter_map(|link| {
+            if link.left == table {
+                Some(link.right)
+            } else if link.right == table {
+                Some(link.left)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// A query for the ids in `to` that are linked to the ids in `from` that
+/// are selected by `ids`
+pub fn linked_ids(from: Table, to: Table, ids: &str) -> Option<String> {
+    let (table, from_id, to_id) = link(from, to)?;
+    Some(format!("SELECT {} FROM {} WHERE {} IN ({})", to_id, table.as_str(), from_id, ids))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    query: String,
+}
+
+impl Filter {
+    pub fn new<I: Into<String>>(query: I) -> Filter {
+        Filter {
+            query: query.into(),
+        }
+    }
+
+    #[inline]
+    pub fn any() -> Filter {
         Filter::new("1")
     }
 
+    /// `column = value`, the value is escaped
+    pub fn column_eq(column: &str, value: &str) -> Filter {
+        Filter::new(format!("{} = {}", column, Self::escape(value)))
+    }
+
     fn escape(value: &str) -> String {
         let mut out = String::from("'");
         for c in value.chars() {
@@ -483,7 +1497,105 @@ impl Filter {
         out
     }
 
-    pub fn parse(mut args: &[String]) -> Result<Filter> {
+    fn sql_value(value: &FieldValue) -> String {
+        match value {
+            FieldValue::Null => String::from("NULL"),
+            FieldValue::Bool(b) => (*b as u8).to_string(),
+            FieldValue::Integer(n) => n.to_string(),
+            FieldValue::Float(n) => n.to_string(),
+            FieldValue::Text(s) => Self::escape(s),
+            FieldValue::Datetime(dt) => Self::escape(&dt.format("%Y-%m-%d %H:%M:%S%.f").to_string()),
+        }
+    }
+
+    /// Parse an age like `90s`, `15m`, `12h`, `30d` or `4w`
+    pub(crate) fn parse_age(value: &str) -> Option<Duration> {
+        let unit = value.chars().last()?;
+        let n = value[..value.len() - unit.len_utf8()].parse::<i64>().ok()?;
+        match unit {
+            's' => Some(Duration::seconds(n)),
+            'm' => Some(Duration::minutes(n)),
+            'h' => Some(Duration::hours(n)),
+            'd' => Some(Duration::days(n)),
+            'w' => Some(Duration::weeks(n)),
+            _ => None,
+        }
+    }
+
+    /// Escape a value that is compared with `column`, ages are turned into
+    /// the point in time they refer to, so `seen_last < 30d` means "not seen
+    /// in the last 30 days".
+    fn value_for(column: Option<&str>, value: &str) -> String {
+        match column {
+            Some(column) if RELATIVE_COLUMNS.contains(&column) => {
+                if let Some(age) = Self::parse_age(value) {
+                    let dt = Utc::now().naive_utc() - age;
+                    return Self::sql_value(&FieldValue::Datetime(dt));
+                }
+            },
+            _ => (),
+        }
+        Self::escape(value)
+    }
+
+    /// Split `seen_last<30d` into column, operator and value
+    fn split_relative(arg: &str) -> Option<(&str, &str, &str)> {
+        let idx = arg.find(['<', '>'])?;
+        let (column, rest) = arg.split_at(idx);
+        if !RELATIVE_COLUMNS.contains(&column) {
+            return None;
+        }
+        let op_len = if rest[1..].starts_with('=') { 2 } else { 1 };
+        let (op, value) = rest.split_at(op_len);
+        Some((column, op, value))
+    }
+
+    /// Turn `domain:example.com` into a condition on `table`
+    fn related_condition(table: Table, related: &str) -> Result<String> {
+        let (family, value) = related.split_once(':')
+            .ok_or_else(|| format_err!("Expected related entity like domain:example.com, got {:?}", related))?;
+        let family = Family::from_str(family)
+            .map_err(|_| format_err!("Unknown entity type: {:?}", family))?;
+
+        let (link_table, from_id, to_id) = match link(family.table(), table) {
+            Some(link) => link,
+            None => {
+                let known = linked(table).into_iter()
+                    .map(|x| x.family().as_str())
+                    .collect::<Vec<_>>();
+                if known.is_empty() {
+                    bail!("{} can't be related to other entities", table.as_str());
+                }
+                bail!("{} can't be related to {}, expected one of: {}", table.as_str(), family.as_str(), known.join(", "));
+            },
+        };
+
+        let ids = format!("SELECT id FROM {} WHERE value = {}", family.table().as_str(), Self::escape(value));
+        // the link is stored in the table itself, like the domain of a subdomain
+        if link_table == table && to_id == "id" {
+            Ok(format!("{} IN ({})", from_id, ids))
+        } else {
+            Ok(format!("id IN (SELECT {} FROM {} WHERE {} IN ({}))", to_id, link_table.as_str(), from_id, ids))
+        }
+    }
+
+    /// Only match entities that are linked to `related`, like `domain:example.com`
+    pub fn and_related(self, table: Table, related: &str) -> Result<Filter> {
+        let condition = Self::related_condition(table, related)?;
+        let query = format!("({}) AND {}", self.query, condition);
+        Ok(Filter::new(query))
+    }
+
+    pub fn parse(args: &[String]) -> Result<Filter> {
+        Self::parse_with(None, args)
+    }
+
+    /// Like `parse`, but `related-to` clauses are resolved for `table`
+    pub fn parse_for(table: Table, args: &[String]) -> Result<Filter> {
+        Self::parse_with(Some(table), args)
+    }
+
+    fn parse_with(table: Option<Table>, mut args: &[String]) -> Result<Filter> {
         debug!("Parsing query: {:?}", args);
 
         if args.is_empty() {
@@ -499,14 +1611,34 @@ impl Filter {
         let mut query = String::new();
 
         let mut expect_value = false;
+        let mut expect_related = false;
+        let mut column = None;
 
         for arg in args {
-            if ["=", "!=", "<", ">", "<=", ">=", "like"].contains(&arg.to_lowercase().as_str()) {
+            if expect_related {
+                let table = table
+                    .ok_or_else(|| format_err!("related-to can't be used in this filter"))?;
+                write!(query, " {}", Self::related_condition(table, arg)?)?;
+                expect_related = false;
+                continue;
+            }
+
+            if arg.to_lowercase() == "related-to" {
+                expect_related = true;
+                continue;
+            }
+
+            if OPERATORS.contains(&arg.to_lowercase().as_str()) {
                 expect_value = true;
                 write!(query, " {}", arg)?;
                 continue;
             }
 
+            if let Some((key, op, value)) = Self::split_relative(arg) {
+                write!(query, " {} {} {}", key, op, Self::value_for(Some(key), value))?;
+                continue;
+            }
+
             if let Some(idx) = arg.find('=') {
                 if idx != 0 {
                     let (key, value) = arg.split_at(idx);
@@ -517,17 +1649,36 @@ impl Filter {
 
             if expect_value {
                 query.push(' ');
-                query.push_str(&Self::escape(arg));
+                query.push_str(&Self::value_for(column, arg));
                 expect_value = false;
             } else {
                 write!(query, " {}", arg)?;
+                column = Some(arg.as_str());
             }
         }
+        if expect_related {
+            bail!("related-to requires an entity like domain:example.com");
+        }
         debug!("Parsed query: {:?}", query);
 
         Ok(Filter::new(query))
     }
 
+    /// Parse a filter that was passed as a single string, like the value of
+    /// `--exclude-filter`. The leading WHERE may be omitted.
+    pub fn parse_expr(table: Option<Table>, expr: &str) -> Result<Filter> {
+        let mut args = shellwords::split(expr)
+            .map_err(|_| format_err!("Filter has unbalanced quotes: {:?}", expr))?;
+        match args.first() {
+            Some(first) if first.to_lowercase() == "where" => (),
+            _ => args.insert(0, String::from("where")),
+        }
+        if args.len() == 1 {
+            bail!("Filter condition is required");
+        }
+        Self::parse_with(table, &args)
+    }
+
     pub fn parse_optional(args: &[String]) -> Result<Filter> {
         debug!("Parsing optional query: {:?}", args);
 
@@ -539,10 +1690,66 @@ impl Filter {
         Self::parse(args)
     }
 
+    pub fn parse_optional_for(table: Table, args: &[String]) -> Result<Filter> {
+        if args.is_empty() {
+            debug!("Using filter with no condition");
+            return Ok(Filter::any());
+        }
+
+        Self::parse_for(table, args)
+    }
+
+    pub fn from_conditions(conditions: &[Condition]) -> Result<Filter> {
+        if conditions.is_empty() {
+            return Ok(Filter::any());
+        }
+
+        let mut query = String::new();
+
+        for condition in conditions {
+            let column = &condition.column;
+            if column.is_empty() || !column.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                bail!("Invalid column name: {:?}", column);
+            }
+
+            let op = condition.op.to_lowercase();
+            if !OPERATORS.contains(&op.as_str()) {
+                bail!("Invalid operator: {:?}", condition.op);
+            }
+
+            let value = match &condition.value {
+                serde_json::Value::String(s) => Self::value_for(Some(column), s),
+                serde_json::Value::Number(n) => n.to_string(),
+                serde_json::Value::Bool(b) => (*b as u8).to_string(),
+                v => bail!("Unsupported value for {:?}: {:?}", column, v),
+            };
+
+            if !query.is_empty() {
+                query.push_str(" AND");
+            }
+            write!(query, " {} {} {}", column, op, value)?;
+        }
+
+        Ok(Filter::new(query))
+    }
+
     pub fn query(&self) -> &str {
         &self.query
     }
 
+    /// Combine two filters, both of them need to match
+    pub fn and(self, other: &Filter) -> Filter {
+        let query = format!("({}) AND ({})", self.query, other.query);
+        Filter::new(query)
+    }
+
+    /// Leave out everything that matches `other`, a condition that is NULL
+    /// for a row doesn't exclude it
+    pub fn and_not(self, other: &Filter) -> Filter {
+        let query = format!("({}) AND NOT COALESCE(({}), 0)", self.query, other.query);
+        Filter::new(query)
+    }
+
     pub fn and_scoped(&self) -> Filter {
         let query = format!("({}) AND unscoped=0", self.query);
         Filter::new(query)
@@ -551,12 +1758,159 @@ impl Filter {
     pub fn sql(&self) -> SqlLiteral<Bool> {
         sql::<Bool>(&self.query)
     }
+
+    /// The columns the filter refers to, columns that are only used in a
+    /// subquery belong to another table and are left out
+    pub fn columns(&self) -> HashSet<String> {
+        let chars = self.query.chars().collect::<Vec<_>>();
+        let mut columns = HashSet::new();
+        let mut depth = 0;
+        let mut subquery = None;
+        let mut i = 0;
+
+        while i < chars.len() {
+            let c = chars[i];
+            match c {
+                '\'' | '"' => {
+                    let start = i + 1;
+                    i += 1;
+                    while i < chars.len() {
+                        if chars[i] == c {
+                            // quotes are escaped by doubling them
+                            if chars.get(i + 1) == Some(&c) {
+                                i += 1;
+                            } else {
+                                break;
+                            }
+                        }
+                        i += 1;
+                    }
+                    if c == '"' && subquery.is_none() {
+                        let name = chars[start..i.min(chars.len())].iter().collect::<String>();
+                        columns.insert(name.replace("\"\"", "\"").to_lowercase());
+                    }
+                },
+                '(' => depth += 1,
+                ')' => {
+                    depth -= 1;
+                    if subquery.map(|d| depth < d).unwrap_or(false) {
+                        subquery = None;
+                    }
+                },
+                c if c.is_ascii_digit() => {
+                    while chars.get(i + 1).map(|c| c.is_ascii_alphanumeric() || *c == '.').unwrap_or(false) {
+                        i += 1;
+                    }
+                },
+                c if c.is_ascii_alphabetic() || c == '_' => {
+                    let start = i;
+                    while chars.get(i + 1).map(|c| c.is_ascii_alphanumeric() || *c == '_').unwrap_or(false) {
+                        i += 1;
+                    }
+                    let word = chars[start..=i].iter().collect::<String>().to_lowercase();
+                    let is_function = chars[i + 1..].iter()
+                        .find(|c| !c.is_whitespace()) == Some(&'(');
+
+                    if word == "select" {
+                        subquery = subquery.or(Some(depth));
+                    } else if subquery.is_none() && !is_function && !SQL_KEYWORDS.contains(&word.as_str()) {
+                        columns.insert(word);
+                    }
+                },
+                _ => (),
+            }
+            i += 1;
+        }
+
+        columns
+    }
+
+    /// Check if every column of the filter is in `columns`
+    pub fn applies_to(&self, columns: &HashSet<String>) -> bool {
+        self.columns().is_subset(columns)
+    }
+
+    /// Make sure the filter works with the columns of at least one table,
+    /// activity included. This only looks at the schema, nothing is executed.
+    pub fn validate(&self) -> Result<()> {
+        let columns = self.columns();
+        let applies = Table::ALL.iter()
+            .map(Table::as_str)
+            .chain(["activity"])
+            .filter_map(|table| SCHEMA_COLUMNS.get(table))
+            .any(|known| columns.iter().all(|c| known.contains(c.as_str())));
+        if applies {
+            return Ok(());
+        }
+
+        match self.unknown_column() {
+            Some(column) => bail!("No such column: {}", column),
+            None => {
+                let mut columns = columns.into_iter().collect::<Vec<_>>();
+                columns.sort();
+                bail!("No table has all of these columns: {}", columns.join(", "))
+            },
+        }
+    }
+
+    /// The first column of the filter, in alphabetical order, that isn't used
+    /// by any table
+    pub fn unknown_column(&self) -> Option<String> {
+        let mut unknown = self.columns().into_iter()
+            .filter(|c| !SCHEMA_COLUMNS.values().any(|known| known.contains(c.as_str())))
+            .collect::<Vec<_>>();
+        unknown.sort();
+        unknown.into_iter().next()
+    }
+
+    /// Evaluate the filter against an entity that doesn't need to be in the
+    /// database, the fields of the json object are the columns of a single row
+    pub fn matches_json(&self, db: &Database, entity: &serde_json::Value) -> Result<bool> {
+        let fields = entity.as_object()
+            .ok_or_else(|| format_err!("Entity needs to be a json object"))?;
+
+        let mut row = String::from("SELECT 1");
+        for (key, value) in fields {
+            let value = match value {
+                serde_json::Value::Null => FieldValue::Null,
+                serde_json::Value::Bool(b) => FieldValue::Bool(*b),
+                serde_json::Value::Number(n) => match n.as_i64() {
+                    Some(n) => FieldValue::Integer(n),
+                    None => FieldValue::Float(n.as_f64().unwrap_or_default()),
+                },
+                serde_json::Value::String(s) => match s.parse::<NaiveDateTime>() {
+                    Ok(dt) => FieldValue::Datetime(dt),
+                    Err(_) => FieldValue::Text(s.clone()),
+                },
+                value => FieldValue::Text(value.to_string()),
+            };
+            // columns of a subquery have the affinity of their expression,
+            // this makes `unscoped=0` compare like it does on a real table
+            let affinity = match value {
+                FieldValue::Null => None,
+                FieldValue::Bool(_) | FieldValue::Integer(_) => Some("INTEGER"),
+                FieldValue::Float(_) => Some("REAL"),
+                FieldValue::Text(_) | FieldValue::Datetime(_) => Some("TEXT"),
+            };
+            let value = match affinity {
+                Some(affinity) => format!("CAST({} AS {})", Self::sql_value(&value), affinity),
+                None => Self::sql_value(&value),
+            };
+            write!(row, ", {} AS \"{}\"", value, key.replace('"', "\"\""))?;
+        }
+
+        let query = format!("EXISTS(SELECT 1 FROM ({}) WHERE {})", row, self.query);
+        let matches = diesel::select(sql::<Bool>(&query))
+            .get_result::<bool>(db.db())?;
+        Ok(matches)
+    }
 }
 
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::JournalMode;
 
     #[test]
     fn test_filter_simple() {
@@ -626,6 +1980,43 @@ mod tests {
         assert_eq!(filter, Filter::new(" value = '\\'"));
     }
 
+    #[test]
+    fn test_filter_relative() {
+        let filter = Filter::parse(&["where".to_string(),
+                                     "seen_last".to_string(),
+                                     "<".to_string(),
+                                     "30d".to_string(),
+                                    ]).unwrap();
+        assert!(filter.query().starts_with(" seen_last < '20"));
+    }
+
+    #[test]
+    fn test_filter_relative_combined() {
+        let filter = Filter::parse(&["where".to_string(),
+                                     "seen_first>=12h".to_string(),
+                                    ]).unwrap();
+        assert!(filter.query().starts_with(" seen_first >= '20"));
+    }
+
+    #[test]
+    fn test_filter_relative_other_column() {
+        let filter = Filter::parse(&["where".to_string(),
+                                     "value".to_string(),
+                                     "<".to_string(),
+                                     "30d".to_string(),
+                                    ]).unwrap();
+        assert_eq!(filter, Filter::new(" value < '30d'"));
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(Filter::parse_age("90s"), Some(Duration::seconds(90)));
+        assert_eq!(Filter::parse_age("4w"), Some(Duration::weeks(4)));
+        assert_eq!(Filter::parse_age("30"), None);
+        assert_eq!(Filter::parse_age("d"), None);
+        assert_eq!(Filter::parse_age(""), None);
+    }
+
     #[test]
     fn test_filter_quote1() {
         let filter = Filter::parse(&["where".to_string(),
@@ -684,6 +2075,90 @@ mod tests {
         assert_eq!(filter, Filter::new(" value <= '123'"));
     }
 
+    #[test]
+    fn test_filter_related_to() {
+        let filter = Filter::parse_for(Table::Subdomains, &["where".to_string(),
+                                                           "related-to".to_string(),
+                                                           "domain:example.com".to_string(),
+                                                           "and".to_string(),
+                                                           "value".to_string(),
+                                                           "like".to_string(),
+                                                           "www.%".to_string(),
+                                                          ]).unwrap();
+        assert_eq!(filter, Filter::new(" domain_id IN (SELECT id FROM domains WHERE value = 'example.com') and value like 'www.%'"));
+    }
+
+    #[test]
+    fn test_filter_related_to_join_table() {
+        let filter = Filter::any().and_related(Table::Ipaddrs, "subdomain:www.example.com").unwrap();
+        assert_eq!(filter, Filter::new("(1) AND id IN (SELECT ip_addr_id FROM subdomain_ipaddrs WHERE subdomain_id IN (SELECT id FROM subdomains WHERE value = 'www.example.com'))"));
+    }
+
+    #[test]
+    fn test_filter_related_to_invalid() {
+        let err = Filter::any().and_related(Table::Subdomains, "email:root@example.com").unwrap_err();
+        assert_eq!(err.to_string(), "subdomains can't be related to email, expected one of: domain, ipaddr, url");
+        assert!(Filter::any().and_related(Table::Subdomains, "example.com").is_err());
+        assert!(Filter::any().and_related(Table::Subdomains, "foo:example.com").is_err());
+        assert!(Filter::any().and_related(Table::Images, "domain:example.com").is_err());
+    }
+
+    #[test]
+    fn test_filter_related_to_without_table() {
+        assert!(Filter::parse(&["where".to_string(),
+                                "related-to".to_string(),
+                                "domain:example.com".to_string(),
+                               ]).is_err());
+    }
+
+    fn cond(column: &str, op: &str, value: serde_json::Value) -> Condition {
+        Condition {
+            column: column.to_string(),
+            op: op.to_string(),
+            value,
+        }
+    }
+
+    #[test]
+    fn test_filter_conditions_empty() {
+        let filter = Filter::from_conditions(&[]).unwrap();
+        assert_eq!(filter, Filter::any());
+    }
+
+    #[test]
+    fn test_filter_conditions() {
+        let filter = Filter::from_conditions(&[
+            cond("value", "like", "%.example.com".into()),
+            cond("id", ">", 3.into()),
+            cond("resolvable", "=", true.into()),
+        ]).unwrap();
+        assert_eq!(filter, Filter::new(" value like '%.example.com' AND id > 3 AND resolvable = 1"));
+    }
+
+    #[test]
+    fn test_filter_conditions_escape() {
+        let filter = Filter::from_conditions(&[
+            cond("value", "=", "' OR 1=1 --".into()),
+        ]).unwrap();
+        assert_eq!(filter, Filter::new(" value = ''' OR 1=1 --'"));
+    }
+
+    #[test]
+    fn test_filter_conditions_invalid_column() {
+        let r = Filter::from_conditions(&[
+            cond("1=1 OR value", "=", "x".into()),
+        ]);
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn test_filter_conditions_invalid_op() {
+        let r = Filter::from_conditions(&[
+            cond("value", "OR", "x".into()),
+        ]);
+        assert!(r.is_err());
+    }
+
     #[test]
     fn test_family_names() {
         assert_eq!(Family::Domain.as_str(),             "domain");
@@ -724,4 +2199,561 @@ mod tests {
         assert_eq!(Table::Ports.as_str(),               "ports");
         assert_eq!(Table::Netblocks.as_str(),           "netblocks");
     }
+
+    #[test]
+    fn test_filter_and_view() {
+        let view = Filter::parse(&["where".to_string(), "valid=1".to_string()]).unwrap();
+        let filter = Filter::parse(&["where".to_string(), "value".to_string(), "like".to_string(), "%@example.com".to_string()]).unwrap();
+        assert_eq!(filter.and(&view), Filter::new("( value like '%@example.com') AND ( valid = '1')"));
+    }
+
+    #[test]
+    fn test_filter_expr() {
+        let filter = Filter::parse_expr(None, "value like '%@example.com'").unwrap();
+        assert_eq!(filter, Filter::new(" value like '%@example.com'"));
+        let filter = Filter::parse_expr(None, "WHERE unscoped=0").unwrap();
+        assert_eq!(filter, Filter::new(" unscoped = '0'"));
+        assert!(Filter::parse_expr(None, "").is_err());
+        assert!(Filter::parse_expr(None, "where").is_err());
+        assert!(Filter::parse_expr(None, "value = 'foo").is_err());
+    }
+
+    #[test]
+    fn test_filter_and_not() {
+        let exclude = Filter::parse_expr(Some(Table::Domains), "unscoped=0").unwrap();
+        assert_eq!(Filter::any().and_not(&exclude), Filter::new("(1) AND NOT COALESCE(( unscoped = '0'), 0)"));
+    }
+
+    #[test]
+    fn test_filter_and_not_query() {
+        let db = Database::connect_path(":memory:", &DatabaseConfig::default(), None).unwrap();
+        db.execute("CREATE TABLE t (value TEXT, asn INTEGER)").unwrap();
+        db.execute("INSERT INTO t VALUES ('a', 1), ('b', 2), ('c', NULL)").unwrap();
+
+        let exclude = Filter::parse_expr(None, "asn = 1").unwrap();
+        let filter = Filter::any().and_not(&exclude);
+        let query = format!("(SELECT count(*) FROM t WHERE {})", filter.query());
+        let n = diesel::select(sql::<diesel::sql_types::BigInt>(&query))
+            .get_result::<i64>(&db)
+            .unwrap();
+        // the row without an asn isn't excluded
+        assert_eq!(n, 2);
+    }
+
+    fn columns(filter: &Filter) -> Vec<String> {
+        let mut columns = filter.columns().into_iter().collect::<Vec<_>>();
+        columns.sort();
+        columns
+    }
+
+    #[test]
+    fn test_filter_columns() {
+        let filter = Filter::parse_expr(None, "value like '%and%' and (unscoped=0 or NOT valid=1)").unwrap();
+        assert_eq!(columns(&filter), vec!["unscoped", "valid", "value"]);
+        assert_eq!(columns(&Filter::any()), Vec::<String>::new());
+        assert_eq!(columns(&Filter::new("\"Value\" = 'it''s' AND lower(displayname) = 'x'")), vec!["displayname", "value"]);
+    }
+
+    #[test]
+    fn test_filter_columns_subquery() {
+        let filter = Filter::parse_expr(Some(Table::Subdomains), "related-to domain:example.com and resolvable=1").unwrap();
+        assert_eq!(columns(&filter), vec!["domain_id", "resolvable"]);
+        let filter = Filter::parse_expr(Some(Table::Subdomains), "related-to ipaddr:192.0.2.1").unwrap();
+        assert_eq!(columns(&filter), vec!["id"]);
+    }
+
+    #[test]
+    fn test_filter_applies_to() {
+        let db = Database::memory().unwrap();
+        let filter = Filter::parse_expr(None, "displayname=root and unscoped=0").unwrap();
+        assert!(filter.applies_to(&db.columns("accounts").unwrap()));
+        assert!(!filter.applies_to(&db.columns("domains").unwrap()));
+        assert!(db.columns("nope").is_err());
+    }
+
+    #[test]
+    fn test_schema_columns() {
+        let db = Database::memory().unwrap();
+        for table in Table::ALL.iter().map(Table::as_str).chain(["activity"]) {
+            let columns = SCHEMA_COLUMNS[table].iter()
+                .map(|c| c.to_string())
+                .collect::<HashSet<_>>();
+            assert_eq!(columns, db.columns(table).unwrap(), "{}", table);
+        }
+    }
+
+    #[test]
+    fn test_filter_validate() {
+        assert!(Filter::parse_expr(None, "port = 22").unwrap().validate().is_ok());
+        assert!(Filter::parse_expr(None, "topic like 'db:%'").unwrap().validate().is_ok());
+        assert!(Filter::parse_expr(None, "prot = 22").unwrap().validate().is_err());
+        // both columns exist, but not in the same table
+        assert!(Filter::parse_expr(None, "port = 22 and topic like 'db:%'").unwrap().validate().is_err());
+    }
+
+    #[test]
+    fn test_filter_unknown_column() {
+        let filter = Filter::parse_expr(None, "port = 22 and zzz = 1 and prot = 22").unwrap();
+        assert_eq!(filter.unknown_column(), Some("prot".to_string()));
+        let filter = Filter::parse_expr(None, "port = 22 and topic like 'db:%'").unwrap();
+        assert_eq!(filter.unknown_column(), None);
+    }
+
+    #[test]
+    fn test_filter_matches_json() {
+        let db = Database::memory().unwrap();
+        let entity = serde_json::json!({
+            "id": 3,
+            "value": "www.prod.example.com",
+            "unscoped": false,
+            "resolvable": null,
+            "last_seen": "2026-10-14T12:00:00",
+        });
+        let matches = |expr| Filter::parse_expr(None, expr).unwrap().matches_json(&db, &entity).unwrap();
+        assert!(matches("value like '%.prod.example.com'"));
+        assert!(!matches("value like '%.dev.example.com'"));
+        assert!(matches("unscoped=0 and resolvable is null"));
+        assert!(matches("last_seen > 2026-10-01"));
+        assert!(Filter::parse_expr(None, "port = 22").unwrap().matches_json(&db, &entity).is_err());
+
+        let port = serde_json::json!({"value": "192.0.2.1:22", "port": 22, "status": "open"});
+        assert!(Filter::parse_expr(None, "port = 22 and status = 'open'").unwrap().matches_json(&db, &port).unwrap());
+    }
+
+    fn contended_with(journal_mode: JournalMode) -> (tempfile::TempDir, SqliteConnection, SqliteConnection) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.sqlite");
+        let path = path.to_str().unwrap();
+
+        let mut config = DatabaseConfig {
+            journal_mode,
+            ..Default::default()
+        };
+        let a = Database::connect_path(path, &config, None).unwrap();
+        a.execute("CREATE TABLE t (x INTEGER)").unwrap();
+        // fail right away so the retry loop is exercised
+        config.busy_timeout = 0;
+        let b = Database::connect_path(path, &config, None).unwrap();
+        (dir, a, b)
+    }
+
+    fn contended() -> (tempfile::TempDir, SqliteConnection, SqliteConnection) {
+        contended_with(JournalMode::Wal)
+    }
+
+    fn count(db: &SqliteConnection) -> i64 {
+        diesel::select(sql::<diesel::sql_types::BigInt>("(SELECT count(*) FROM t)"))
+            .get_result::<i64>(db)
+            .unwrap()
+    }
+
+    fn insert(db: &SqliteConnection) -> Result<()> {
+        write_with_retry(db, || {
+            db.execute("INSERT INTO t (x) VALUES (1)")?;
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_write_while_reading() {
+        let (_dir, a, b) = contended();
+        // like a select that is still reading its results
+        a.execute("BEGIN").unwrap();
+        assert_eq!(count(&a), 0);
+
+        insert(&b).unwrap();
+        assert_eq!(count(&b), 1);
+        // the reader keeps its snapshot until it's done
+        assert_eq!(count(&a), 0);
+        a.execute("COMMIT").unwrap();
+        assert_eq!(count(&a), 1);
+    }
+
+    #[test]
+    fn test_write_while_reading_without_wal() {
+        let (_dir, a, b) = contended_with(JournalMode::Delete);
+        a.execute("BEGIN").unwrap();
+        assert_eq!(count(&a), 0);
+
+        let err = insert(&b).unwrap_err();
+        assert!(is_busy(&err));
+        a.execute("COMMIT").unwrap();
+        insert(&b).unwrap();
+        assert_eq!(count(&a), 1);
+    }
+
+    #[test]
+    fn test_write_retries_while_locked() {
+        let (_dir, a, b) = contended();
+        a.execute("BEGIN IMMEDIATE").unwrap();
+        let holder = thread::spawn(move || {
+            thread::sleep(time::Duration::from_millis(150));
+            a.execute("COMMIT").unwrap();
+        });
+
+        write_with_retry(&b, || {
+            b.execute("INSERT INTO t (x) VALUES (1)")?;
+            Ok(())
+        }).unwrap();
+        holder.join().unwrap();
+    }
+
+    #[test]
+    fn test_write_gives_up_while_locked() {
+        let (_dir, a, b) = contended();
+        a.execute("BEGIN IMMEDIATE").unwrap();
+
+        let err = write_with_retry(&b, || {
+            b.execute("INSERT INTO t (x) VALUES (1)")?;
+            Ok(())
+        }).unwrap_err();
+        assert!(err.to_string().starts_with("Database is still locked after 5 attempts"));
+        assert!(is_busy(&err));
+    }
+
+    #[test]
+    fn test_retry_busy_attempts() {
+        let mut attempts = 0;
+        let err = retry_busy::<(), _>(|| {
+            attempts += 1;
+            bail!("database is locked")
+        }).unwrap_err();
+        assert_eq!(attempts, BUSY_ATTEMPTS);
+        assert!(is_busy(&err));
+
+        let mut attempts = 0;
+        retry_busy(|| {
+            attempts += 1;
+            if attempts < 3 {
+                bail!("database is locked")
+            }
+            Ok(())
+        }).unwrap();
+        assert_eq!(attempts, 3);
+
+        // other errors are not retried
+        let mut attempts = 0;
+        retry_busy::<(), _>(|| {
+            attempts += 1;
+            bail!("no such table: t")
+        }).unwrap_err();
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    #[cfg(not(feature="sqlcipher"))]
+    fn test_encrypted_without_sqlcipher() {
+        let (_, key) = crate::encryption::tests::create("correct horse battery staple");
+        let err = Database::connect_path(":memory:", &DatabaseConfig::default(), Some(&key)).err().unwrap();
+        assert_eq!(err.to_string(), "Workspace is encrypted, but sn0int has been built without sqlcipher");
+    }
+
+    #[test]
+    fn test_rollback_after() {
+        let db = Database::connect_path(":memory:", &DatabaseConfig::default(), None).unwrap();
+        db.execute("CREATE TABLE t (x INTEGER UNIQUE)").unwrap();
+        let count = |db: &SqliteConnection| diesel::select(sql::<diesel::sql_types::BigInt>("(SELECT count(*) FROM t)"))
+            .get_result::<i64>(db)
+            .unwrap();
+
+        let seen = rollback_after(&db, || {
+            write_with_retry(&db, || {
+                db.execute("INSERT INTO t (x) VALUES (1)")?;
+                Ok(())
+            })?;
+            // a failed nested write only discards its own changes
+            let err = write_with_retry(&db, || {
+                db.execute("INSERT INTO t (x) VALUES (2)")?;
+                db.execute("INSERT INTO t (x) VALUES (1)")?;
+                Ok(())
+            });
+            assert!(err.is_err());
+            Ok(count(&db))
+        }).unwrap();
+        assert_eq!(seen, 1);
+        assert_eq!(count(&db), 0);
+    }
+
+    #[derive(QueryableByName)]
+    struct IndexColumn {
+        #[sql_type = "diesel::sql_types::Text"]
+        column: String,
+        #[sql_type = "diesel::sql_types::Bool"]
+        unique: bool,
+        #[sql_type = "diesel::sql_types::BigInt"]
+        columns: i64,
+    }
+
+    /// The first column of every index on `table`
+    fn indices(db: &Database, table: Table) -> Vec<IndexColumn> {
+        let query = format!("SELECT ii.name AS \"column\", il.\"unique\" AS \"unique\", (SELECT count(*) FROM pragma_index_info(il.name)) AS columns
+            FROM pragma_index_list('{}') il JOIN pragma_index_info(il.name) ii WHERE ii.seqno = 0", table.as_str());
+        diesel::sql_query(query)
+            .load::<IndexColumn>(db.db())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_indices() {
+        let db = Database::memory().unwrap();
+        for table in Table::ALL {
+            if LINKS.iter().any(|link| link.table == *table && link.right_id != "id") {
+                continue;
+            }
+            assert!(indices(&db, *table).iter().any(|i| i.column == "value" && i.unique && i.columns == 1), "{} has no unique index on value", table.as_str());
+        }
+
+        for link in LINKS {
+            for column in &[link.left_id, link.right_id] {
+                if *column != "id" {
+                    assert!(indices(&db, link.table).iter().any(|i| i.column == *column), "{}.{} has no index", link.table.as_str(), column);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_insert_unique_violation() {
+        let db = Database::memory().unwrap();
+        let domain = || NewDomain {
+            value: "example.com".to_string(),
+            unscoped: false,
+        };
+        let (_, id) = db.insert_generic(Insert::Domain(domain())).unwrap().unwrap();
+
+        // like another connection inserted it after it was looked up
+        let (change, existing) = db.insert_new::<_, Domain>(domain(), true, None).unwrap().unwrap();
+        assert!(matches!(change, DbChange::None));
+        assert_eq!(existing, id);
+        assert_eq!(db.list::<Domain>().unwrap()[0].seen_count, 2);
+
+        let change = inserted(diesel::sql_query("INSERT INTO domains (value, unscoped) VALUES ('example.com', 0)").execute(db.db())).unwrap();
+        assert!(matches!(change, DbChange::None));
+        let err = inserted(diesel::sql_query("INSERT INTO domains (value) VALUES (NULL)").execute(db.db()));
+        assert!(err.is_err());
+    }
+
+    fn domains(n: usize) -> Database {
+        let db = Database::memory().unwrap();
+        db.db().execute(&format!("WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < {})
+            INSERT INTO domains (value, unscoped) SELECT 'example' || i || '.com', i % 2 FROM n", n)).unwrap();
+        db
+    }
+
+    #[test]
+    fn test_for_each_chunk() {
+        let db = domains(25);
+        let mut chunks = Vec::new();
+        db.for_each_chunk::<Domain, _>(&Filter::any(), 10, |chunk| {
+            chunks.push(chunk.iter().map(|x| x.id).collect::<Vec<_>>());
+            Ok(())
+        }).unwrap();
+        assert_eq!(chunks.iter().map(Vec::len).collect::<Vec<_>>(), &[10, 10, 5]);
+        assert_eq!(chunks.concat(), (1..=25).collect::<Vec<_>>());
+
+        let mut scoped = 0;
+        db.for_each_chunk::<Domain, _>(&Filter::any().and_scoped(), 5, |chunk| {
+            assert!(chunk.iter().all(|x| !x.unscoped));
+            scoped += chunk.len();
+            Ok(())
+        }).unwrap();
+        assert_eq!(scoped, 12);
+
+        // errors of the callback stop the iteration
+        let mut calls = 0;
+        let err = db.for_each_chunk::<Domain, _>(&Filter::any(), 10, |_| {
+            calls += 1;
+            bail!("Failed to write")
+        });
+        assert!(err.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_filter_range() {
+        let db = domains(2500);
+        let ids = |offset, limit| db.filter_range::<Domain>(&Filter::any(), offset, limit).unwrap()
+            .into_iter()
+            .map(|x| x.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids(0, 3), &[1, 2, 3]);
+        assert_eq!(ids(1998, 4), &[1999, 2000, 2001, 2002]);
+        assert_eq!(ids(2490, 100), (2491..=2500).collect::<Vec<_>>());
+        assert_eq!(ids(0, usize::MAX).len(), 2500);
+        assert!(ids(3000, 10).is_empty());
+        assert!(ids(0, 0).is_empty());
+    }
+    fn ipaddr(country: Option<&str>) -> Insert {
+        Insert::IpAddr(NewIpAddr {
+            family: "4".to_string(),
+            value: "192.0.2.1".to_string(),
+            continent: None,
+            continent_code: None,
+            country: country.map(String::from),
+            country_code: None,
+            city: None,
+            latitude: None,
+            longitude: None,
+            asn: None,
+            as_org: None,
+            description: None,
+            reverse_dns: None,
+            unscoped: false,
+        })
+    }
+
+    fn hits(db: &Database) -> usize {
+        db.lookups.borrow().as_ref().map(|cache| cache.hits()).unwrap_or_default()
+    }
+
+    #[test]
+    fn test_lookup_cache() {
+        let db = Database::memory().unwrap();
+        db.cache_lookups(true);
+        let (change, id) = db.insert_generic(ipaddr(None)).unwrap().unwrap();
+        assert!(matches!(change, DbChange::Insert));
+        for _ in 0..3 {
+            let (change, cached) = db.insert_generic(ipaddr(None)).unwrap().unwrap();
+            assert!(matches!(change, DbChange::None));
+            assert_eq!(cached, id);
+        }
+        assert_eq!(hits(&db), 3);
+        assert_eq!(db.list::<IpAddr>().unwrap()[0].seen_count, 4);
+
+        // a different insert is still applied to the entity
+        let (change, _) = db.insert_generic(ipaddr(Some("Germany"))).unwrap().unwrap();
+        assert!(matches!(change, DbChange::Update(_)));
+        let (change, _) = db.insert_generic(ipaddr(Some("Germany"))).unwrap().unwrap();
+        assert!(matches!(change, DbChange::None));
+        assert_eq!(db.list::<IpAddr>().unwrap()[0].country.as_deref(), Some("Germany"));
+
+        // entities that are out of scope are ignored
+        let filter = Filter::parse_for(Table::Ipaddrs, &["where".to_string(), "value=192.0.2.1".to_string()]).unwrap();
+        db.set_scoped::<IpAddr>(&filter, false).unwrap();
+        assert!(db.insert_generic(ipaddr(None)).unwrap().is_none());
+        assert!(db.insert_generic(ipaddr(None)).unwrap().is_none());
+        assert_eq!(db.list::<IpAddr>().unwrap()[0].seen_count, 6);
+
+        db.cache_lookups(false);
+        assert!(db.lookups.borrow().is_none());
+    }
+
+    #[test]
+    fn test_lookup_cache_invalidation() {
+        let db = Database::memory().unwrap();
+        db.cache_lookups(true);
+        let (_, id) = db.insert_generic(ipaddr(None)).unwrap().unwrap();
+
+        // delete mid-run, the entity is inserted again
+        db.delete::<IpAddr>(&Filter::any()).unwrap();
+        let (change, new_id) = db.insert_generic(ipaddr(None)).unwrap().unwrap();
+        assert!(matches!(change, DbChange::Insert));
+        assert_ne!(id, new_id);
+
+        // deleted by another connection, the cache doesn't know about it
+        db.db().execute("DELETE FROM ipaddrs").unwrap();
+        let (change, _) = db.insert_generic(ipaddr(None)).unwrap().unwrap();
+        assert!(matches!(change, DbChange::Insert));
+        assert_eq!(db.list::<IpAddr>().unwrap().len(), 1);
+
+        // an update changes what the insert would do
+        db.insert_generic(ipaddr(Some("Germany"))).unwrap();
+        db.update_generic(&Update::IpAddr(IpAddrUpdate {
+            id: IpAddr::get(&db, "192.0.2.1").unwrap().id,
+            continent: None,
+            continent_code: None,
+            country: Some("France".to_string()),
+            country_code: None,
+            city: None,
+            latitude: None,
+            longitude: None,
+            asn: None,
+            as_org: None,
+            description: None,
+            reverse_dns: None,
+        })).unwrap();
+        let (change, _) = db.insert_generic(ipaddr(Some("Germany"))).unwrap().unwrap();
+        assert!(matches!(change, DbChange::Update(_)));
+
+        // inserts that are rolled back are forgotten
+        db.dry_run(|| db.insert_generic(Insert::Domain(NewDomain {
+            value: "example.com".to_string(),
+            unscoped: false,
+        }))).unwrap();
+        let (change, _) = db.insert_generic(Insert::Domain(NewDomain {
+            value: "example.com".to_string(),
+            unscoped: false,
+        })).unwrap().unwrap();
+        assert!(matches!(change, DbChange::Insert));
+    }
+
+    #[test]
+    fn test_count_scoped_cached() {
+        let db = Database::memory().unwrap();
+        let domain = |value: &str| Insert::Domain(NewDomain {
+            value: value.to_string(),
+            unscoped: false,
+        });
+        db.insert_generic(domain("example.com")).unwrap();
+        assert_eq!(db.count_scoped_cached(Table::Domains).unwrap(), 1);
+        assert_eq!(db.count_scoped_cached(Table::Domains).unwrap(), 1);
+
+        db.insert_generic(domain("example.org")).unwrap();
+        assert_eq!(db.count_scoped_cached(Table::Domains).unwrap(), 2);
+        db.set_scoped::<Domain>(&Filter::column_eq("value", "example.com"), false).unwrap();
+        assert_eq!(db.count_scoped_cached(Table::Domains).unwrap(), 1);
+    }
+
+    fn subdomain(domain_id: i32, value: &str) -> Insert {
+        Insert::Subdomain(NewSubdomain {
+            domain_id,
+            value: value.to_string(),
+            resolvable: None,
+            unscoped: false,
+        })
+    }
+
+    #[test]
+    fn test_scope_inheritance() {
+        let mut db = Database::memory().unwrap();
+        db.set_scope_inheritance(ScopeInheritance::Parent);
+        let (_, domain_id) = db.insert_generic(Insert::Domain(NewDomain {
+            value: "example.com".to_string(),
+            unscoped: false,
+        })).unwrap().unwrap();
+        db.set_scoped::<Domain>(&Filter::column_eq("value", "example.com"), false).unwrap();
+        db.autonoscope_add_rule(&RuleType::Domain, "api.example.com", true).unwrap();
+
+        let decision = db.scope_decision(&subdomain(domain_id, "www.example.com")).unwrap();
+        assert_eq!(decision, ScopeDecision::Inherited {
+            parent: "domain example.com".to_string(),
+            scoped: false,
+        });
+        assert_eq!(decision.to_string(), "inherited from domain example.com");
+        db.insert_generic(subdomain(domain_id, "www.example.com")).unwrap();
+        let www = Subdomain::get(&db, "www.example.com").unwrap();
+        assert!(!www.scoped());
+        let history = Provenance::history(&db, &Family::Subdomain, "www.example.com").unwrap();
+        assert_eq!(history.last().unwrap().details.as_deref(), Some("inherited from domain example.com"));
+
+        // a matching rule is more important than the parent
+        db.insert_generic(subdomain(domain_id, "api.example.com")).unwrap();
+        assert!(Subdomain::get(&db, "api.example.com").unwrap().scoped());
+
+        // ipaddrs are only inherited from the subdomain a module runs on
+        db.insert_generic(ipaddr(None)).unwrap();
+        assert!(IpAddr::get(&db, "192.0.2.1").unwrap().scoped());
+        db.delete::<IpAddr>(&Filter::any()).unwrap();
+        db.set_origin(Some(Origin {
+            module: "kpcyrd/dns-resolve".to_string(),
+            version: "0.1.0".to_string(),
+            target: Some("www.example.com".to_string()),
+            source: Some(Family::Subdomain),
+        }));
+        db.insert_generic(ipaddr(None)).unwrap();
+        assert!(!IpAddr::get(&db, "192.0.2.1").unwrap().scoped());
+
+        db.set_scope_inheritance(ScopeInheritance::Off);
+        assert_eq!(db.scope_decision(&subdomain(domain_id, "dev.example.com")).unwrap(), ScopeDecision::Default);
+    }
 }
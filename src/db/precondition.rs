@@ -0,0 +1,127 @@
+use crate::errors::*;
+use crate::db::{self, Filter, Table};
+use diesel::expression::sql_literal::sql;
+use diesel::prelude::*;
+use diesel::sql_types::Bool;
+use std::str::FromStr;
+
+/// Only run a module on targets that have (or don't have) related entities
+///
+/// This is written as `[no] <family> [where <filter>]`, for example
+/// `no breaches` or `subdomains where value like dev.%`.
+#[derive(Debug, PartialEq)]
+pub struct Precondition {
+    negate: bool,
+    related: Table,
+    filter: Filter,
+}
+
+impl Precondition {
+    /// Make sure the related entities can be looked up for this kind of target
+    pub fn check(&self, target: Table) -> Result<()> {
+        if db::linked_ids(target, self.related, "").is_none() {
+            bail!("{} are not related to {}", self.related.as_str(), target.as_str());
+        }
+        Ok(())
+    }
+
+    pub fn matches(&self, db: &SqliteConnection, target: Table, id: i32) -> Result<bool> {
+        let ids = db::linked_ids(target, self.related, &id.to_string())
+            .ok_or_else(|| format_err!("{} are not related to {}", self.related.as_str(), target.as_str()))?;
+
+        let query = format!("EXISTS (SELECT 1 FROM {} WHERE id IN ({}) AND ({}))",
+            self.related.as_str(), ids, self.filter.query());
+        let exists = diesel::select(sql::<Bool>(&query))
+            .get_result::<bool>(db)?;
+
+        Ok(exists != self.negate)
+    }
+}
+
+impl FromStr for Precondition {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Precondition> {
+        let mut args = s.split_whitespace();
+
+        let mut related = args.next()
+            .ok_or_else(|| format_err!("Precondition is empty"))?;
+        let negate = related.eq_ignore_ascii_case("no");
+        if negate {
+            related = args.next()
+                .ok_or_else(|| format_err!("Precondition is missing the related entities"))?;
+        }
+
+        let related = Table::from_str(&related.to_lowercase())
+            .map_err(|_| format_err!("Unknown entity type: {:?}", related))?;
+
+        let args = args.map(String::from).collect::<Vec<_>>();
+        let filter = Filter::parse_optional(&args)?;
+
+        Ok(Precondition {
+            negate,
+            related,
+            filter,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+
+    fn setup() -> SqliteConnection {
+        let db = SqliteConnection::establish(":memory:").unwrap();
+        migrations::run(&db).unwrap();
+        db.execute("INSERT INTO emails (id, value, unscoped) VALUES (1, 'a@example.com', 0), (2, 'b@example.com', 0)").unwrap();
+        db.execute("INSERT INTO breaches (id, value, unscoped) VALUES (1, 'leak', 0)").unwrap();
+        db.execute("INSERT INTO breach_emails (breach_id, email_id) VALUES (1, 1)").unwrap();
+        db
+    }
+
+    #[test]
+    fn test_parse() {
+        let p = "no breaches".parse::<Precondition>().unwrap();
+        assert_eq!(p, Precondition {
+            negate: true,
+            related: Table::Breaches,
+            filter: Filter::any(),
+        });
+
+        let p = "subdomains where value like dev.%".parse::<Precondition>().unwrap();
+        assert_eq!(p, Precondition {
+            negate: false,
+            related: Table::Subdomains,
+            filter: Filter::new(" value like 'dev.%'"),
+        });
+
+        assert!("".parse::<Precondition>().is_err());
+        assert!("no".parse::<Precondition>().is_err());
+        assert!("foo".parse::<Precondition>().is_err());
+        assert!("breaches value=1".parse::<Precondition>().is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let p = "no breaches".parse::<Precondition>().unwrap();
+        assert!(p.check(Table::Emails).is_ok());
+        assert!(p.check(Table::Domains).is_err());
+    }
+
+    #[test]
+    fn test_matches() {
+        let db = setup();
+
+        let p = "no breaches".parse::<Precondition>().unwrap();
+        assert!(!p.matches(&db, Table::Emails, 1).unwrap());
+        assert!(p.matches(&db, Table::Emails, 2).unwrap());
+
+        let p = "breaches".parse::<Precondition>().unwrap();
+        assert!(p.matches(&db, Table::Emails, 1).unwrap());
+        assert!(!p.matches(&db, Table::Emails, 2).unwrap());
+
+        let p = "breaches where value = other".parse::<Precondition>().unwrap();
+        assert!(!p.matches(&db, Table::Emails, 1).unwrap());
+    }
+}
@@ -0,0 +1,161 @@
+use crate::errors::*;
+use crate::db::{Database, Family};
+use crate::fmt::{self, Write};
+use crate::fmt::colors::*;
+use diesel::prelude::*;
+use diesel::sql_types::{Bool, Integer, Text};
+
+/// Marks the start and end of a match in a snippet
+const MATCH_START: char = '\x02';
+const MATCH_END: char = '\x03';
+/// Tokens of text around the match that are shown in a snippet
+const SNIPPET_TOKENS: usize = 12;
+
+/// An entity that contains the search term in its value or in one of its
+/// text columns, like the title of a url or the banner of a port
+#[derive(Debug, QueryableByName)]
+pub struct Hit {
+    #[sql_type = "Text"]
+    pub family: String,
+    #[sql_type = "Integer"]
+    pub id: i32,
+    #[sql_type = "Text"]
+    pub value: String,
+    #[sql_type = "Bool"]
+    unscoped: bool,
+    #[sql_type = "Text"]
+    snippet: String,
+}
+
+impl Hit {
+    #[inline]
+    pub fn scoped(&self) -> bool {
+        !self.unscoped
+    }
+
+    /// The text around the match, if the term wasn't only found in the value
+    pub fn snippet(&self) -> Option<&str> {
+        if self.snippet.contains(MATCH_START) {
+            Some(&self.snippet)
+        } else {
+            None
+        }
+    }
+}
+
+/// Every word of the term has to show up at the start of a word of the
+/// entity. The words are quoted so things like dots in domains aren't parsed
+/// as query syntax.
+fn match_expr(term: &str) -> Result<String> {
+    let words = term.split_whitespace()
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>();
+    if words.is_empty() {
+        bail!("Search term is empty");
+    }
+    Ok(words.join(" "))
+}
+
+/// The best matches first
+pub fn search(db: &Database, term: &str, family: Option<&Family>, limit: usize) -> Result<Vec<Hit>> {
+    let mut query = format!("SELECT family, entity_id AS id, value, unscoped, snippet(search_index, 4, char({}), char({}), '…', {}) AS snippet
+        FROM search_index WHERE search_index MATCH ?", MATCH_START as u32, MATCH_END as u32, SNIPPET_TOKENS);
+    if let Some(family) = family {
+        write!(query, " AND family = '{}'", family.as_str())?;
+    }
+    write!(query, " ORDER BY rank LIMIT {}", limit)?;
+
+    let hits = diesel::sql_query(query)
+        .bind::<Text, _>(match_expr(term)?)
+        .load::<Hit>(db.db())
+        .context("Failed to search")?;
+    Ok(hits)
+}
+
+/// Print a snippet on a single line with the matches highlighted
+fn highlight(w: &mut fmt::DetailFormatter, snippet: &str) -> fmt::Result {
+    let snippet = snippet.replace(['\r', '\n', '\t'], " ");
+    for (i, part) in snippet.split([MATCH_START, MATCH_END]).enumerate() {
+        if i % 2 == 1 {
+            w.direct_display::<Red, _>(part)?;
+        } else {
+            write!(w, "{}", part)?;
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for Hit {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        let mut w = fmt::DetailFormatter::new(w, self.scoped());
+        w.start()?;
+        write!(w, "{} ", self.family)?;
+        w.id(self.id)?;
+        w.debug::<Green, _>(&self.value)?;
+        if let Some(snippet) = self.snippet() {
+            write!(w, "\n\t")?;
+            highlight(&mut w, snippet)?;
+        }
+        w.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Filter, Table};
+    use crate::models::*;
+
+    fn setup() -> Database {
+        let db = Database::memory().unwrap();
+        db.db().execute("INSERT INTO domains (id, value, unscoped) VALUES (1, 'example.com', 0)").unwrap();
+        db.db().execute("INSERT INTO subdomains (id, domain_id, value, unscoped) VALUES (1, 1, 'jenkins.example.com', 0), (2, 1, 'www.example.com', 0)").unwrap();
+        db.db().execute("INSERT INTO urls (id, subdomain_id, value, path, title, unscoped) VALUES (1, 2, 'https://www.example.com/', '/', 'Dashboard [Jenkins]', 0)").unwrap();
+        db
+    }
+
+    fn values(hits: &[Hit]) -> Vec<(&str, &str)> {
+        hits.iter()
+            .map(|x| (x.family.as_str(), x.value.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_match_expr() {
+        assert_eq!(match_expr("jenkins").unwrap(), "\"jenkins\"*");
+        assert_eq!(match_expr(" a.example.com  \"x ").unwrap(), "\"a.example.com\"* \"\"\"x\"*");
+        assert!(match_expr("  ").is_err());
+    }
+
+    #[test]
+    fn test_search() {
+        let db = setup();
+        let mut hits = search(&db, "jenk", None, 10).unwrap();
+        hits.sort_by(|a, b| a.family.cmp(&b.family));
+        assert_eq!(values(&hits), &[("subdomain", "jenkins.example.com"), ("url", "https://www.example.com/")]);
+        assert_eq!(hits[0].snippet(), None);
+        assert_eq!(hits[1].snippet(), Some("Dashboard [\x02Jenkins\x03]"));
+
+        let hits = search(&db, "jenkins", Some(&Family::Url), 10).unwrap();
+        assert_eq!(values(&hits), &[("url", "https://www.example.com/")]);
+        assert!(search(&db, "www.example.com dashboard", None, 10).unwrap().len() == 1);
+        assert!(search(&db, "gitlab", None, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_search_follows_changes() {
+        let db = setup();
+        db.db().execute("UPDATE urls SET title = 'GitLab' WHERE id = 1").unwrap();
+        assert_eq!(search(&db, "jenkins", None, 10).unwrap().len(), 1);
+        assert_eq!(search(&db, "gitlab", None, 10).unwrap().len(), 1);
+
+        let filter = Filter::parse_for(Table::Subdomains, &["where".to_string(), "value=jenkins.example.com".to_string()]).unwrap();
+        db.set_scoped::<Subdomain>(&filter, false).unwrap();
+        let hits = search(&db, "jenkins", None, 10).unwrap();
+        assert!(!hits[0].scoped());
+
+        // deleting the domain cascades to its subdomains and urls
+        db.db().execute("DELETE FROM domains").unwrap();
+        assert!(search(&db, "example", None, 10).unwrap().is_empty());
+    }
+}
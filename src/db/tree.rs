@@ -0,0 +1,229 @@
+use crate::errors::*;
+use crate::db::{self, Database, Table};
+use crate::fmt::{self, Write};
+use crate::fmt::colors::*;
+use diesel::prelude::*;
+use diesel::sql_types::{Bool, Integer, Text};
+use std::collections::{HashMap, HashSet};
+
+/// Ids are inlined into the query, this keeps the statements reasonably short
+const CHUNK_SIZE: usize = 500;
+
+#[derive(QueryableByName)]
+struct Row {
+    #[sql_type = "Integer"]
+    id: i32,
+    #[sql_type = "Text"]
+    value: String,
+    #[sql_type = "Bool"]
+    unscoped: bool,
+}
+
+#[derive(QueryableByName)]
+struct Edge {
+    #[sql_type = "Integer"]
+    from: i32,
+    #[sql_type = "Integer"]
+    to: i32,
+}
+
+/// An entity with everything that was first reached through it
+#[derive(Debug, PartialEq)]
+pub struct Node {
+    pub table: Table,
+    pub id: i32,
+    pub value: String,
+    pub scoped: bool,
+    pub children: Vec<Node>,
+}
+
+fn join(ids: &[i32]) -> String {
+    ids.iter()
+        .map(|id| id.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn rows(db: &Database, table: Table, ids: &[i32]) -> Result<Vec<Row>> {
+    let mut rows = Vec::new();
+    for chunk in ids.chunks(CHUNK_SIZE) {
+        let query = format!("SELECT id, value, unscoped FROM {} WHERE id IN ({})", table.as_str(), join(chunk));
+        rows.extend(diesel::sql_query(query).load::<Row>(db.db())?);
+    }
+    Ok(rows)
+}
+
+/// The links from the ids in `from` to entities in `to`, with one query per chunk
+fn edges(db: &Database, from: Table, to: Table, ids: &[i32]) -> Result<Vec<Edge>> {
+    let (table, from_id, to_id) = db::link(from, to)
+        .ok_or_else(|| format_err!("{} are not related to {}", to.as_str(), from.as_str()))?;
+
+    let mut edges = Vec::new();
+    for chunk in ids.chunks(CHUNK_SIZE) {
+        let query = format!("SELECT {} AS \"from\", {} AS \"to\" FROM {} WHERE {} IN ({})",
+            from_id, to_id, table.as_str(), from_id, join(chunk));
+        edges.extend(diesel::sql_query(query).load::<Edge>(db.db())?);
+    }
+    Ok(edges)
+}
+
+/// Group entities by table, keeping the order the tables showed up in
+fn by_table(entities: &[(Table, i32)]) -> Vec<(Table, Vec<i32>)> {
+    let mut groups: Vec<(Table, Vec<i32>)> = Vec::new();
+    for (table, id) in entities {
+        match groups.iter_mut().find(|(x, _)| x == table) {
+            Some((_, ids)) => ids.push(*id),
+            None => groups.push((*table, vec![*id])),
+        }
+    }
+    groups
+}
+
+impl Node {
+    /// Follow all links of an entity, level by level. Every entity shows up
+    /// once, below the first entity it was reached from, so cycles end there.
+    pub fn build(db: &Database, table: Table, value: &str, depth: usize) -> Result<Node> {
+        let query = format!("SELECT id, value, unscoped FROM {} WHERE value = ?", table.as_str());
+        let root = diesel::sql_query(query)
+            .bind::<Text, _>(value)
+            .get_result::<Row>(db.db())
+            .optional()?
+            .ok_or_else(|| NotFoundError(format!("{} {:?} doesn't exist", table.family().as_str(), value)))?;
+
+        let root_key = (table, root.id);
+        let mut visited = HashSet::new();
+        visited.insert(root_key);
+        let mut entities = HashMap::new();
+        entities.insert(root_key, root);
+        let mut children = HashMap::<_, Vec<_>>::new();
+
+        let mut level = vec![root_key];
+        for _ in 0..depth {
+            let mut next = Vec::new();
+            for (from, ids) in by_table(&level) {
+                for to in db::linked(from) {
+                    for edge in edges(db, from, to, &ids)? {
+                        if visited.insert((to, edge.to)) {
+                            children.entry((from, edge.from)).or_default().push((to, edge.to));
+                            next.push((to, edge.to));
+                        }
+                    }
+                }
+            }
+
+            for (table, ids) in by_table(&next) {
+                for row in rows(db, table, &ids)? {
+                    entities.insert((table, row.id), row);
+                }
+            }
+
+            if next.is_empty() {
+                break;
+            }
+            level = next;
+        }
+
+        Ok(Node::assemble(root_key, &mut entities, &mut children))
+    }
+
+    fn assemble(key: (Table, i32), entities: &mut HashMap<(Table, i32), Row>, children: &mut HashMap<(Table, i32), Vec<(Table, i32)>>) -> Node {
+        let row = entities.remove(&key)
+            .expect("Entity of the tree wasn't loaded");
+        let mut nodes = Vec::new();
+        for child in children.remove(&key).unwrap_or_default() {
+            // the link may point to an entity that doesn't exist anymore
+            if entities.contains_key(&child) {
+                nodes.push(Node::assemble(child, entities, children));
+            }
+        }
+        nodes.sort_by(|a, b| (a.table.as_str(), &a.value).cmp(&(b.table.as_str(), &b.value)));
+
+        Node {
+            table: key.0,
+            id: row.id,
+            value: row.value,
+            scoped: !row.unscoped,
+            children: nodes,
+        }
+    }
+
+    fn line(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        let mut w = fmt::DetailFormatter::new(w, self.scoped);
+        w.start()?;
+        write!(w, "{} ", self.table.family().as_str())?;
+        w.id(self.id)?;
+        w.debug::<Green, _>(&self.value)?;
+        w.end()
+    }
+
+    fn children(&self, w: &mut fmt::Formatter, indent: &str) -> fmt::Result {
+        for (i, child) in self.children.iter().enumerate() {
+            let last = i + 1 == self.children.len();
+            write!(w, "\n{}{}", indent, if last { "└── " } else { "├── " })?;
+            child.line(w)?;
+            let indent = format!("{}{}", indent, if last { "    " } else { "│   " });
+            child.children(w, &indent)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        self.line(w)?;
+        self.children(w, "")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup() -> Database {
+        let db = Database::memory().unwrap();
+        db.db().execute("INSERT INTO domains (id, value, unscoped) VALUES (1, 'example.com', 0)").unwrap();
+        db.db().execute("INSERT INTO subdomains (id, domain_id, value, unscoped) VALUES (1, 1, 'www.example.com', 0), (2, 1, 'old.example.com', 1)").unwrap();
+        db.db().execute("INSERT INTO ipaddrs (id, family, value, unscoped) VALUES (1, '4', '192.0.2.1', 0)").unwrap();
+        // both subdomains point to the same ip address
+        db.db().execute("INSERT INTO subdomain_ipaddrs (subdomain_id, ip_addr_id) VALUES (1, 1), (2, 1)").unwrap();
+        db.db().execute("INSERT INTO ports (id, ip_addr_id, value, ip_addr, port, protocol, status, unscoped) VALUES (1, 1, 'tcp/192.0.2.1:443', '192.0.2.1', 443, 'tcp', 'open', 0)").unwrap();
+        db
+    }
+
+    fn values(node: &Node) -> Vec<&str> {
+        node.children.iter().map(|x| x.value.as_str()).collect()
+    }
+
+    #[test]
+    fn test_tree() {
+        let db = setup();
+        let tree = Node::build(&db, Table::Domains, "example.com", 5).unwrap();
+        assert_eq!(values(&tree), &["old.example.com", "www.example.com"]);
+        assert!(!tree.children[0].scoped);
+
+        // the ip address is only shown below the first subdomain
+        let ipaddrs = tree.children.iter()
+            .map(|x| x.children.len())
+            .sum::<usize>();
+        assert_eq!(ipaddrs, 1);
+        let ipaddr = tree.children.iter()
+            .flat_map(|x| &x.children)
+            .next()
+            .unwrap();
+        assert_eq!(values(ipaddr), &["tcp/192.0.2.1:443"]);
+    }
+
+    #[test]
+    fn test_tree_depth() {
+        let db = setup();
+        let tree = Node::build(&db, Table::Domains, "example.com", 1).unwrap();
+        assert_eq!(values(&tree), &["old.example.com", "www.example.com"]);
+        assert!(tree.children.iter().all(|x| x.children.is_empty()));
+
+        let tree = Node::build(&db, Table::Ports, "tcp/192.0.2.1:443", 2).unwrap();
+        configure(ColorChoice::Never);
+        assert_eq!(format!("{:#}", tree), "port \"tcp/192.0.2.1:443\"\n└── ipaddr \"192.0.2.1\"\n    ├── subdomain \"old.example.com\"\n    └── subdomain \"www.example.com\"");
+
+        assert!(Node::build(&db, Table::Domains, "example.org", 1).is_err());
+    }
+}
@@ -138,11 +138,13 @@ pub fn reap_expired(rl: &mut Shell) -> Result<()> {
         if let Err(err) = notify::trigger_notify_event(rl, &mut Term, &mut ratelimit, topic, &Notification {
             subject,
             body: None,
+            entity: None,
         }) {
             term::error(&format!("Failed to send notifications: {}", err));
         }
     }
 
+    notify::finish_run(rl, &mut Term);
     debug!("Finished reaping expired entities");
     Ok(())
 }
@@ -0,0 +1,558 @@
+//! Workspaces can be encrypted at rest, the database with sqlcipher and the
+//! blobs with the secretbox of libsodium. Both keys are derived from one
+//! passphrase, the parameters of the derivation are stored next to the
+//! database of the workspace.
+use crate::blobs::BlobStorage;
+use crate::config::DatabaseConfig;
+use crate::errors::*;
+use crate::paths;
+use crate::utils;
+use crate::workspaces::Workspace;
+use data_encoding::{BASE64, HEXLOWER};
+use diesel::connection::SimpleConnection;
+use diesel::dsl::sql;
+use diesel::prelude::*;
+use diesel::sql_types::{Integer, Text};
+use lazy_static::lazy_static;
+use serde::{Serialize, Deserialize};
+use sodiumoxide::crypto::pwhash::argon2id13;
+use sodiumoxide::crypto::secretbox;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+/// Used instead of asking for a passphrase, useful for scripting
+pub const PASSPHRASE_ENV: &str = "SN0INT_WORKSPACE_PASSPHRASE";
+
+// The encrypted files are prepared next to the plaintext files of the
+// workspace, the marker is written once everything else is complete
+const DB_ENCRYPTING: &str = "db.sqlite.encrypting";
+const BLOBS_ENCRYPTING: &str = "blobs.encrypting";
+const BLOBS_PLAINTEXT: &str = "blobs.plaintext";
+const MARKER_ENCRYPTING: &str = "encryption.json.encrypting";
+
+/// Sealed with the blob key, tells a wrong passphrase apart from a damaged
+/// database
+const CHECK: &[u8] = b"sn0int workspace";
+
+lazy_static! {
+    /// The workspaces that have been unlocked by this process, the passphrase
+    /// is only asked for once
+    static ref UNLOCKED: Mutex<HashMap<String, WorkspaceKey>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Clone, PartialEq)]
+pub struct WorkspaceKey {
+    db: [u8; 32],
+    blobs: secretbox::Key,
+}
+
+impl fmt::Debug for WorkspaceKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("WorkspaceKey { .. }")
+    }
+}
+
+impl WorkspaceKey {
+    /// The raw key of the database, in the syntax of `PRAGMA key` and `ATTACH`
+    fn sql(&self) -> String {
+        format!("\"x'{}'\"", HEXLOWER.encode(&self.db))
+    }
+
+    /// Has to be the first statement on a connection
+    pub fn pragma(&self) -> String {
+        format!("PRAGMA key = {};", self.sql())
+    }
+
+    #[inline(always)]
+    pub fn blobs(&self) -> &secretbox::Key {
+        &self.blobs
+    }
+}
+
+/// How the key of an encrypted workspace is derived, stored as
+/// `encryption.json` in the workspace directory
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Encryption {
+    /// The key derivation, only `argon2id13` is supported
+    kdf: String,
+    salt: String,
+    opslimit: usize,
+    memlimit: usize,
+    nonce: String,
+    check: String,
+}
+
+impl Encryption {
+    pub fn path(workspace: &Workspace) -> Result<PathBuf> {
+        let path = paths::workspace_dir(workspace)?
+            .join("encryption.json");
+        Ok(path)
+    }
+
+    /// None if the workspace isn't encrypted
+    pub fn load(workspace: &Workspace) -> Result<Option<Encryption>> {
+        let path = Encryption::path(workspace)?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        let buf = fs::read(&path)
+            .context(format!("Failed to read {:?}", path))?;
+        let encryption = serde_json::from_slice(&buf)
+            .context(format!("Failed to parse {:?}", path))?;
+        Ok(Some(encryption))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+            .context(format!("Failed to write {:?}", path))?;
+        Ok(())
+    }
+
+    pub fn create(passphrase: &str) -> Result<(Encryption, WorkspaceKey)> {
+        let argon2id13::OpsLimit(opslimit) = argon2id13::OPSLIMIT_INTERACTIVE;
+        let argon2id13::MemLimit(memlimit) = argon2id13::MEMLIMIT_INTERACTIVE;
+        Encryption::create_with(passphrase, opslimit, memlimit)
+    }
+
+    fn create_with(passphrase: &str, opslimit: usize, memlimit: usize) -> Result<(Encryption, WorkspaceKey)> {
+        sodiumoxide::init()
+            .map_err(|_| format_err!("Failed to initialize libsodium"))?;
+
+        let salt = argon2id13::gen_salt();
+        let key = derive_key(passphrase, &salt, opslimit, memlimit)?;
+        let nonce = secretbox::gen_nonce();
+        let check = secretbox::seal(CHECK, &nonce, key.blobs());
+
+        let encryption = Encryption {
+            kdf: String::from("argon2id13"),
+            salt: BASE64.encode(salt.as_ref()),
+            opslimit,
+            memlimit,
+            nonce: BASE64.encode(nonce.as_ref()),
+            check: BASE64.encode(&check),
+        };
+        Ok((encryption, key))
+    }
+
+    /// Derive the key from the passphrase, this fails if the passphrase is wrong
+    pub fn unlock(&self, passphrase: &str) -> Result<WorkspaceKey> {
+        if self.kdf != "argon2id13" {
+            bail!("Unsupported key derivation: {:?}", self.kdf);
+        }
+        sodiumoxide::init()
+            .map_err(|_| format_err!("Failed to initialize libsodium"))?;
+
+        let salt = BASE64.decode(self.salt.as_bytes())
+            .context("Failed to decode salt")?;
+        let salt = argon2id13::Salt::from_slice(&salt)
+            .ok_or_else(|| format_err!("Salt has wrong length"))?;
+        let nonce = BASE64.decode(self.nonce.as_bytes())
+            .context("Failed to decode nonce")?;
+        let nonce = secretbox::Nonce::from_slice(&nonce)
+            .ok_or_else(|| format_err!("Nonce has wrong length"))?;
+        let check = BASE64.decode(self.check.as_bytes())
+            .context("Failed to decode check")?;
+
+        ensure_kdf_limits(self.opslimit, self.memlimit)?;
+        let key = derive_key(passphrase, &salt, self.opslimit, self.memlimit)?;
+        match secretbox::open(&check, &nonce, key.blobs()) {
+            Ok(check) if check == CHECK => Ok(key),
+            _ => bail!("Wrong passphrase"),
+        }
+    }
+}
+
+/// The parameters of the key derivation are read from files that may come
+/// from somebody else, refuse anything that is more expensive than the
+/// strongest preset of libsodium
+pub fn ensure_kdf_limits(opslimit: usize, memlimit: usize) -> Result<()> {
+    let argon2id13::OpsLimit(max_opslimit) = argon2id13::OPSLIMIT_SENSITIVE;
+    let argon2id13::MemLimit(max_memlimit) = argon2id13::MEMLIMIT_SENSITIVE;
+    if opslimit > max_opslimit {
+        bail!("Key derivation opslimit is too high: {} (max {})", opslimit, max_opslimit);
+    }
+    if memlimit > max_memlimit {
+        bail!("Key derivation memlimit is too high: {} (max {})", memlimit, max_memlimit);
+    }
+    Ok(())
+}
+
+fn derive_key(passphrase: &str, salt: &argon2id13::Salt, opslimit: usize, memlimit: usize) -> Result<WorkspaceKey> {
+    let mut key = [0; 64];
+    argon2id13::derive_key(&mut key, passphrase.as_bytes(), salt,
+                           argon2id13::OpsLimit(opslimit),
+                           argon2id13::MemLimit(memlimit))
+        .map_err(|_| format_err!("Failed to derive key from passphrase"))?;
+
+    let mut db = [0; 32];
+    db.copy_from_slice(&key[..32]);
+    let blobs = secretbox::Key::from_slice(&key[32..])
+        .ok_or_else(|| format_err!("Derived key has wrong length"))?;
+    Ok(WorkspaceKey {
+        db,
+        blobs,
+    })
+}
+
+/// Run the command that prints the passphrase, like a lookup in the keychain
+/// of the operating system
+fn passphrase_command(cmd: &str, workspace: &Workspace) -> Result<String> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .env("SN0INT_WORKSPACE", workspace.as_str())
+        .stderr(Stdio::inherit())
+        .output()
+        .context("Failed to run passphrase-command")?;
+    if !output.status.success() {
+        bail!("passphrase-command failed: {}", output.status);
+    }
+    let passphrase = String::from_utf8(output.stdout)
+        .context("passphrase-command printed invalid utf-8")?;
+    Ok(passphrase.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// The passphrase of a workspace, taken from the environment, the
+/// passphrase-command or a prompt, in that order
+pub fn passphrase(workspace: &Workspace, config: &DatabaseConfig, confirm: bool) -> Result<String> {
+    let passphrase = if let Ok(passphrase) = env::var(PASSPHRASE_ENV) {
+        passphrase
+    } else if let Some(cmd) = &config.passphrase_command {
+        passphrase_command(cmd, workspace)?
+    } else {
+        let passphrase = utils::question_password(&format!("Passphrase for workspace {:?}", workspace.as_str()))?;
+        if confirm && !passphrase.is_empty() && utils::question_password("Repeat passphrase")? != passphrase {
+            bail!("Passphrases don't match");
+        }
+        passphrase
+    };
+
+    if passphrase.is_empty() {
+        bail!("Passphrase can not be empty");
+    }
+    Ok(passphrase)
+}
+
+/// The key of a workspace, or none if it isn't encrypted
+pub fn unlock(workspace: &Workspace, config: &DatabaseConfig) -> Result<Option<WorkspaceKey>> {
+    recover(&paths::workspace_dir(workspace)?)?;
+    let encryption = match Encryption::load(workspace)? {
+        Some(encryption) => encryption,
+        None => return Ok(None),
+    };
+    if let Some(key) = UNLOCKED.lock().unwrap().get(workspace.as_str()) {
+        return Ok(Some(key.clone()));
+    }
+
+    let passphrase = passphrase(workspace, config, false)?;
+    let key = encryption.unlock(&passphrase)
+        .context(format!("Failed to unlock workspace {:?}", workspace.as_str()))?;
+    UNLOCKED.lock().unwrap().insert(workspace.to_string(), key.clone());
+    Ok(Some(key))
+}
+
+#[derive(QueryableByName)]
+struct CipherVersion {
+    #[sql_type = "Text"]
+    cipher_version: String,
+}
+
+#[derive(QueryableByName)]
+struct UserVersion {
+    #[sql_type = "Integer"]
+    user_version: i32,
+}
+
+/// The version of sqlcipher, none if sqlite doesn't support encryption
+pub fn sqlcipher_version(db: &SqliteConnection) -> Result<Option<String>> {
+    let version = diesel::sql_query("PRAGMA cipher_version")
+        .load::<CipherVersion>(db)?
+        .pop()
+        .map(|x| x.cipher_version);
+    Ok(version)
+}
+
+/// Open a plaintext database with an exclusive lock that is held until the
+/// connection is dropped. Leaving wal mode only works if no other connection
+/// has the database open, so this fails if it's used by another process.
+fn lock_db(path: &Path) -> Result<SqliteConnection> {
+    let path = path.to_str()
+        .ok_or_else(|| format_err!("Failed to convert db path to utf-8"))?;
+    let db = SqliteConnection::establish(path)
+        .context("Failed to connect to database")?;
+
+    let locked = db.batch_execute("PRAGMA journal_mode = DELETE").is_ok()
+        && diesel::select(sql::<Text>("(SELECT journal_mode FROM pragma_journal_mode)"))
+            .get_result::<String>(&db)
+            .map(|mode| mode.eq_ignore_ascii_case("delete"))
+            .unwrap_or(false)
+        && db.batch_execute("PRAGMA locking_mode = EXCLUSIVE; BEGIN EXCLUSIVE; COMMIT;").is_ok();
+    if !locked {
+        bail!("Database is used by another process, close it there first");
+    }
+    Ok(db)
+}
+
+/// Write an encrypted copy of a plaintext database
+fn export_encrypted(db: &SqliteConnection, dest: &Path, key: &WorkspaceKey) -> Result<()> {
+    let dest = dest.to_str()
+        .ok_or_else(|| format_err!("Failed to convert db path to utf-8"))?;
+
+    if sqlcipher_version(db)?.is_none() {
+        bail!("sn0int has been built without sqlcipher, workspaces can't be encrypted");
+    }
+
+    let user_version = diesel::sql_query("PRAGMA user_version")
+        .get_result::<UserVersion>(db)?
+        .user_version;
+    db.batch_execute(&format!("ATTACH DATABASE '{}' AS encrypted KEY {};
+        SELECT sqlcipher_export('encrypted');
+        PRAGMA encrypted.user_version = {};
+        DETACH DATABASE encrypted;", dest.replace('\'', "''"), key.sql(), user_version))
+        .context("Failed to write encrypted database")?;
+    Ok(())
+}
+
+fn remove_all(path: &Path) -> Result<()> {
+    let result = if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+    match result {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => {
+            Err(err).context(format!("Failed to delete {:?}", path))?
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Replace the plaintext files of a workspace with the encrypted files that
+/// have been prepared next to them. Every step can be repeated, so an
+/// interrupted swap is completed by running it again.
+fn swap(dir: &Path) -> Result<()> {
+    let blobs = dir.join("blobs");
+    let plaintext_blobs = dir.join(BLOBS_PLAINTEXT);
+    let encrypted_blobs = dir.join(BLOBS_ENCRYPTING);
+    if encrypted_blobs.exists() {
+        if blobs.exists() {
+            fs::rename(&blobs, &plaintext_blobs)
+                .context("Failed to replace blobs")?;
+        }
+        fs::rename(&encrypted_blobs, &blobs)
+            .context("Failed to replace blobs")?;
+    }
+
+    let encrypted_db = dir.join(DB_ENCRYPTING);
+    if encrypted_db.exists() {
+        for suffix in ["-wal", "-shm"] {
+            remove_all(&dir.join(format!("db.sqlite{}", suffix)))?;
+        }
+        fs::rename(&encrypted_db, dir.join("db.sqlite"))
+            .context("Failed to replace database")?;
+    }
+
+    fs::rename(dir.join(MARKER_ENCRYPTING), dir.join("encryption.json"))
+        .context("Failed to mark workspace as encrypted")?;
+    remove_all(&plaintext_blobs)?;
+    Ok(())
+}
+
+/// Complete the encryption of a workspace that has been interrupted after
+/// the encrypted files were written
+pub fn recover(dir: &Path) -> Result<()> {
+    if dir.join(MARKER_ENCRYPTING).exists() {
+        info!("Completing the interrupted encryption of {:?}", dir);
+        swap(dir)?;
+    } else if dir.join("encryption.json").exists() {
+        remove_all(&dir.join(BLOBS_PLAINTEXT))?;
+    }
+    Ok(())
+}
+
+/// Encrypt the database and the blobs of a workspace with a new passphrase.
+/// Everything is written next to the plaintext files first, which are
+/// replaced afterwards. The database is locked the whole time.
+pub fn encrypt_workspace(workspace: &Workspace, passphrase: &str) -> Result<()> {
+    let dir = paths::workspace_dir(workspace)?;
+    recover(&dir)?;
+    if Encryption::load(workspace)?.is_some() {
+        bail!("Workspace {:?} is already encrypted", workspace.as_str());
+    }
+
+    let db = lock_db(&workspace.db_path()?)
+        .context(format!("Failed to lock workspace {:?}", workspace.as_str()))?;
+    // left behind by an encryption that failed before it was complete
+    for name in [DB_ENCRYPTING, BLOBS_ENCRYPTING] {
+        remove_all(&dir.join(name))?;
+    }
+
+    let (encryption, key) = Encryption::create(passphrase)?;
+    export_encrypted(&db, &dir.join(DB_ENCRYPTING), &key)?;
+
+    let blobs = BlobStorage::workspace(workspace)?;
+    let encrypted_blobs = dir.join(BLOBS_ENCRYPTING);
+    fs::create_dir(&encrypted_blobs)?;
+    let encrypted = BlobStorage::new(&encrypted_blobs)
+        .with_key(Some(&key));
+    for id in blobs.list()? {
+        encrypted.save(&blobs.load(&id)?)?;
+    }
+
+    // from here on an interruption is completed by recover
+    encryption.save(&dir.join(MARKER_ENCRYPTING))?;
+    swap(&dir)?;
+    drop(db);
+    // the shell doesn't keep a history for encrypted workspaces
+    remove_all(&paths::history_path(workspace)?)?;
+
+    UNLOCKED.lock().unwrap().insert(workspace.to_string(), key);
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub fn create(passphrase: &str) -> (Encryption, WorkspaceKey) {
+        let argon2id13::OpsLimit(opslimit) = argon2id13::OPSLIMIT_INTERACTIVE;
+        Encryption::create_with(passphrase, opslimit, 8192 * 1024).unwrap()
+    }
+
+    #[test]
+    fn test_unlock() {
+        let (encryption, key) = create("correct horse");
+        assert_eq!(encryption.unlock("correct horse").unwrap(), key);
+        let err = encryption.unlock("battery staple").unwrap_err();
+        assert_eq!(err.to_string(), "Wrong passphrase");
+
+        let json = serde_json::to_string(&encryption).unwrap();
+        let encryption = serde_json::from_str::<Encryption>(&json).unwrap();
+        assert_eq!(encryption.unlock("correct horse").unwrap(), key);
+    }
+
+    #[test]
+    fn test_unlock_limits() {
+        let (mut encryption, _) = create("correct horse");
+        let argon2id13::OpsLimit(opslimit) = argon2id13::OPSLIMIT_SENSITIVE;
+        encryption.opslimit = opslimit + 1;
+        let err = encryption.unlock("correct horse").unwrap_err();
+        assert!(err.to_string().starts_with("Key derivation opslimit is too high"));
+
+        let (mut encryption, _) = create("correct horse");
+        let argon2id13::MemLimit(memlimit) = argon2id13::MEMLIMIT_SENSITIVE;
+        encryption.memlimit = memlimit + 1;
+        let err = encryption.unlock("correct horse").unwrap_err();
+        assert!(err.to_string().starts_with("Key derivation memlimit is too high"));
+    }
+
+    #[test]
+    fn test_keys_are_independent() {
+        let (_, key) = create("correct horse");
+        assert_ne!(&key.db[..], &key.blobs().0[..]);
+        let (_, other) = create("correct horse");
+        assert_ne!(key, other);
+        assert!(key.pragma().starts_with("PRAGMA key = \"x'"));
+        assert_eq!(key.pragma().len(), "PRAGMA key = \"x''\";".len() + 64);
+        assert_eq!(format!("{:?}", key), "WorkspaceKey { .. }");
+    }
+
+    #[test]
+    fn test_lock_db() {
+        use crate::db::Database;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.sqlite");
+        let db = Database::scratch(path.to_str().unwrap(), "test").unwrap();
+        let err = lock_db(&path).err().unwrap();
+        assert_eq!(err.to_string(), "Database is used by another process, close it there first");
+        drop(db);
+
+        let locked = lock_db(&path).unwrap();
+        let other = SqliteConnection::establish(path.to_str().unwrap()).unwrap();
+        assert!(other.batch_execute("SELECT * FROM domains").is_err());
+        drop(locked);
+        other.batch_execute("SELECT * FROM domains").unwrap();
+    }
+
+    fn write(dir: &Path, name: &str, content: &str) {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    fn read(dir: &Path, name: &str) -> String {
+        fs::read_to_string(dir.join(name)).unwrap()
+    }
+
+    #[test]
+    fn test_recover_interrupted_swap() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        // interrupted after the blobs have been swapped
+        write(dir, "blobs.plaintext/abc", "plain");
+        write(dir, "blobs/abc", "encrypted");
+        write(dir, "db.sqlite", "plain");
+        write(dir, "db.sqlite-wal", "plain");
+        write(dir, DB_ENCRYPTING, "encrypted");
+        write(dir, MARKER_ENCRYPTING, "{}");
+
+        recover(dir).unwrap();
+        assert_eq!(read(dir, "blobs/abc"), "encrypted");
+        assert_eq!(read(dir, "db.sqlite"), "encrypted");
+        assert_eq!(read(dir, "encryption.json"), "{}");
+        for name in [DB_ENCRYPTING, MARKER_ENCRYPTING, BLOBS_PLAINTEXT, "db.sqlite-wal"] {
+            assert!(!dir.join(name).exists(), "{} still exists", name);
+        }
+    }
+
+    #[test]
+    fn test_recover_incomplete() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        // the encrypted files haven't been completed, nothing is replaced
+        write(dir, "blobs/abc", "plain");
+        write(dir, "blobs.encrypting/abc", "encrypted");
+        write(dir, "db.sqlite", "plain");
+        write(dir, DB_ENCRYPTING, "encrypted");
+
+        recover(dir).unwrap();
+        assert_eq!(read(dir, "blobs/abc"), "plain");
+        assert_eq!(read(dir, "db.sqlite"), "plain");
+        assert!(!dir.join("encryption.json").exists());
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    #[test]
+    fn test_export_encrypted() {
+        use crate::db::Database;
+        use crate::models::*;
+
+        let dir = tempfile::tempdir().unwrap();
+        let plain = dir.path().join("plain.sqlite");
+        let encrypted = dir.path().join("encrypted.sqlite");
+        let db = Database::scratch(plain.to_str().unwrap(), "test").unwrap();
+        db.insert_generic(Insert::Domain(NewDomain {
+            value: "example.com".to_string(),
+            unscoped: false,
+        })).unwrap();
+        drop(db);
+
+        let (_, key) = create("correct horse");
+        export_encrypted(&lock_db(&plain).unwrap(), &encrypted, &key).unwrap();
+        let db = SqliteConnection::establish(encrypted.to_str().unwrap()).unwrap();
+        db.batch_execute(&key.pragma()).unwrap();
+        db.batch_execute("SELECT * FROM domains").unwrap();
+
+        let (_, other) = create("correct horse");
+        let db = SqliteConnection::establish(encrypted.to_str().unwrap()).unwrap();
+        db.batch_execute(&other.pragma()).unwrap();
+        assert!(db.batch_execute("SELECT * FROM domains").is_err());
+    }
+}
@@ -1,37 +1,52 @@
 use crate::errors::*;
 use serde::{Serialize, Deserialize};
 
-use crate::db::Family;
-use crate::engine::{Environment, IpcChild};
+use crate::db::{Family, Query};
+use crate::engine::{Entrypoint, Environment, IpcChild, ModuleInfo};
+use crate::engine::memory::MemoryLimit;
+use crate::error_info::{CapabilityError, ErrorInfo};
 use crate::geoip::{MaxmindReader, GeoIP, AsnDB};
 use crate::hlua::{self, AnyLuaValue};
-use crate::keyring::KeyRingEntry;
+use crate::keyring::{KeyName, KeyRingEntry};
 use crate::lazy::Lazy;
 use crate::models::*;
 use crate::psl::{Psl, PslReader};
 use crate::ratelimits::RatelimitResponse;
 use crate::runtime;
-use crate::sockets::{Socket, SocketOptions, TlsData};
+use crate::sockets::{ConnectRequest, Connection, Connector, Datagram, Socket, SocketOptions, TlsData};
 use crate::utils;
 use crate::web::{HttpSession, HttpRequest, RequestOptions};
 use crate::websockets::{WebSocket, WebSocketOptions};
-use crate::worker::{Event, LogEvent, DatabaseEvent, DatabaseResponse, StdioEvent, RatelimitEvent};
-use chrootable_https::{self, Resolver};
+use crate::ipc::concurrency::ConcurrencyEvent;
+use crate::ipc::files::{FileEvent, FileResponse};
+#[cfg(test)]
+use crate::ipc::network::NetworkPolicy;
+use crate::ipc::network::ResolveRequest;
+use crate::worker::{Event, LogEvent, DatabaseEvent, DatabaseResponse, StdioEvent, RatelimitEvent, KeyringEvent, PromptEvent, PromptResponse, NotifyEvent};
+use chrono::NaiveDateTime;
+#[cfg(test)]
+use chrootable_https::Resolver;
+use chrootable_https::dns::DnsReply;
+use percent_encoding::{percent_encode, NON_ALPHANUMERIC};
+use sn0int_common::metadata::Capability;
 use sn0int_std::blobs::{Blob, BlobState};
+use sn0int_std::concurrency::{ConcurrencyState, Slot};
 use sn0int_std::mqtt::{MqttClient, MqttOptions};
-use sn0int_std::web::WebState;
+use sn0int_std::web::{HttpAudit, WebState};
 use std::collections::HashMap;
 use std::result;
-use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Instant;
 
 
-pub trait State {
+pub trait State: ConcurrencyState {
     fn clear_error(&self);
 
     fn last_error(&self) -> Option<String>;
 
+    fn last_error_info(&self) -> Option<ErrorInfo>;
+
     fn set_error(&self, err: Error) -> Error;
 
     fn send(&self, msg: &Event);
@@ -113,6 +128,17 @@ pub trait State {
             .map_err(Error::from)
     }
 
+    fn db_query(&self, query: Query) -> Result<Vec<serde_json::Value>> {
+        self.send(&Event::Database(Box::new(DatabaseEvent::Query(query))));
+        let r = self.db_recv()
+            .context("Failed to query database")?;
+
+        match r {
+            DatabaseResponse::Rows(rows) => Ok(rows),
+            _ => bail!("Unexpected database response for db_query: {:?}", r),
+        }
+    }
+
     fn db_update(&self, family: Family, value: String, update: Update) -> Result<DatabaseResponse> {
         self.send(&Event::Database(Box::new(DatabaseEvent::Update((family, value, update)))));
         self.db_recv()
@@ -120,7 +146,22 @@ pub trait State {
             .map_err(Error::from)
     }
 
+    fn db_update_fields(&self, family: Family, id: i32, fields: serde_json::Map<String, serde_json::Value>) -> Result<DatabaseResponse> {
+        self.send(&Event::Database(Box::new(DatabaseEvent::UpdateFields((family, id, fields)))));
+        self.db_recv()
+            .context("Failed to update database")
+            .map_err(Error::from)
+    }
+
+    fn db_network_audit(&self, object: InsertNetworkAudit) -> Result<()> {
+        self.send(&Event::Database(Box::new(DatabaseEvent::NetworkAudit(object))));
+        self.db_recv()
+            .context("Failed to write network audit log")?;
+        Ok(())
+    }
+
     fn stdin_read_line(&self) -> Result<Option<String>> {
+        self.check_capability(Capability::Stdin)?;
         self.send(&Event::Stdio(StdioEvent::Readline));
         let reply = self.recv()?;
         let reply: result::Result<Option<String>, String> = serde_json::from_value(reply)?;
@@ -128,12 +169,50 @@ pub trait State {
     }
 
     fn stdin_read_to_end(&self) -> Result<Option<String>> {
+        self.check_capability(Capability::Stdin)?;
         self.send(&Event::Stdio(StdioEvent::ToEnd));
         let reply = self.recv()?;
         let reply: result::Result<Option<String>, String> = serde_json::from_value(reply)?;
         reply.map_err(|err| format_err!("Failed to read stdin: {:?}", err))
     }
 
+    fn read_file(&self, path: &str) -> Result<String> {
+        self.send(&Event::File(FileEvent::Read(path.to_string())));
+        let reply = self.recv()?;
+        let reply: result::Result<FileResponse, String> = serde_json::from_value(reply)?;
+        match reply {
+            Ok(FileResponse::File(content)) => Ok(content),
+            Ok(_) => bail!("Unexpected reply from parent"),
+            Err(err) => bail!("Failed to read file: {}", err),
+        }
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<String>> {
+        self.send(&Event::File(FileEvent::ReadDir(path.to_string())));
+        let reply = self.recv()?;
+        let reply: result::Result<FileResponse, String> = serde_json::from_value(reply)?;
+        match reply {
+            Ok(FileResponse::Dir(entries)) => Ok(entries),
+            Ok(_) => bail!("Unexpected reply from parent"),
+            Err(err) => bail!("Failed to read directory: {}", err),
+        }
+    }
+
+    fn prompt(&self, prompt: PromptEvent) -> Result<PromptResponse> {
+        self.send(&Event::Prompt(prompt));
+        let reply = self.recv()?;
+        let reply: result::Result<PromptResponse, String> = serde_json::from_value(reply)?;
+        reply.map_err(|err| format_err!("Failed to prompt: {}", err))
+    }
+
+    fn notify(&self, notify: NotifyEvent) -> Result<bool> {
+        self.check_capability(Capability::Notify)?;
+        self.send(&Event::Notify(notify));
+        let reply = self.recv()?;
+        let reply: result::Result<bool, String> = serde_json::from_value(reply)?;
+        reply.map_err(|err| format_err!("Failed to send notification: {}", err))
+    }
+
     fn ratelimit(&self, key: String, passes: u32, time: u32) -> Result<()> {
         let ratelimit = Event::Ratelimit(RatelimitEvent::new(key, passes, time));
         loop {
@@ -149,6 +228,29 @@ pub trait State {
         Ok(())
     }
 
+    fn keyring_rotate(&self, namespace: &str) -> Result<Option<KeyRingEntry>> {
+        self.send(&Event::Keyring(KeyringEvent::Rotate(namespace.to_string())));
+        let reply = self.recv()?;
+        let reply: result::Result<Option<KeyRingEntry>, String> = serde_json::from_value(reply)?;
+        reply.map_err(|err| format_err!("Failed to rotate keys: {}", err))
+    }
+
+    fn keyring_exhausted(&self, key: KeyName, until: NaiveDateTime) -> Result<()> {
+        self.send(&Event::Keyring(KeyringEvent::Exhausted((key, until))));
+        let reply = self.recv()?;
+        let reply: result::Result<Option<KeyRingEntry>, String> = serde_json::from_value(reply)?;
+        reply.map(|_| ())
+            .map_err(|err| format_err!("Failed to mark key as exhausted: {}", err))
+    }
+
+    fn keyring_used(&self, keys: Vec<KeyName>) -> Result<()> {
+        self.send(&Event::Keyring(KeyringEvent::Used(keys)));
+        let reply = self.recv()?;
+        let reply: result::Result<Option<KeyRingEntry>, String> = serde_json::from_value(reply)?;
+        reply.map(|_| ())
+            .map_err(|err| format_err!("Failed to record keyring usage: {}", err))
+    }
+
     #[inline]
     fn random_id(&self) -> String {
         utils::random_string(16)
@@ -156,12 +258,19 @@ pub trait State {
 
     fn keyring(&self, namespace: &str) -> Vec<&KeyRingEntry>;
 
-    fn dns_config(&self) -> &Resolver;
+    /// Send a dns query through the parent
+    fn dns(&self, req: &ResolveRequest) -> Result<DnsReply>;
+
+    /// Fail if the module didn't declare or the user didn't accept this capability
+    fn check_capability(&self, capability: Capability) -> Result<()>;
 
-    fn proxy(&self) -> Option<&SocketAddr>;
+    /// The point in time the script has to be done with its source by
+    fn deadline(&self) -> Option<Instant>;
 
     fn getopt(&self, key: &str) -> Option<&String>;
 
+    fn module_info(&self) -> Option<&ModuleInfo>;
+
     fn psl(&self) -> Result<Arc<Psl>>;
 
     fn geoip(&self) -> Result<Arc<GeoIP>>;
@@ -206,17 +315,20 @@ pub struct LuaState {
     mqtt_sessions: Mutex<HashMap<String, Arc<Mutex<MqttClient>>>>,
     blobs: Mutex<HashMap<String, Arc<Blob>>>,
     http_sessions: Mutex<HashMap<String, HttpSession>>,
-    http_clients: Mutex<HashMap<String, Arc<chrootable_https::Client<Resolver>>>>,
 
     verbose: u8,
     keyring: Vec<KeyRingEntry>, // TODO: maybe hashmap
-    dns_config: Resolver,
     psl: Mutex<Lazy<PslReader, Arc<Psl>>>,
     geoip: Option<Mutex<Lazy<MaxmindReader, Arc<GeoIP>>>>,
     asn: Option<Mutex<Lazy<MaxmindReader, Arc<AsnDB>>>>,
-    proxy: Option<SocketAddr>,
     user_agent: Option<String>,
     options: HashMap<String, String>,
+    memory: Option<&'static MemoryLimit>,
+    deadline: Option<Instant>,
+    capabilities: Option<Vec<Capability>>,
+    audit: bool,
+    concurrency: bool,
+    module: Option<ModuleInfo>,
 }
 
 impl State for LuaState {
@@ -230,6 +342,11 @@ impl State for LuaState {
         lock.as_ref().map(|err| err.to_string())
     }
 
+    fn last_error_info(&self) -> Option<ErrorInfo> {
+        let lock = self.error.lock().unwrap();
+        lock.as_ref().map(ErrorInfo::new)
+    }
+
     fn set_error(&self, err: Error) -> Error {
         let mut mtx = self.error.lock().unwrap();
         let cp = format_err!("{:?}", err);
@@ -257,18 +374,32 @@ impl State for LuaState {
             .collect()
     }
 
-    fn dns_config(&self) -> &Resolver {
-        &self.dns_config
+    fn dns(&self, req: &ResolveRequest) -> Result<DnsReply> {
+        let mut tx = self.logger.lock().unwrap();
+        tx.resolve(req)
     }
 
-    fn proxy(&self) -> Option<&SocketAddr> {
-        self.proxy.as_ref()
+    fn check_capability(&self, capability: Capability) -> Result<()> {
+        match &self.capabilities {
+            Some(granted) if !granted.contains(&capability) => {
+                Err(CapabilityError { capability: capability.as_str() }.into())
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.deadline
     }
 
     fn getopt(&self, key: &str) -> Option<&String> {
         self.options.get(key)
     }
 
+    fn module_info(&self) -> Option<&ModuleInfo> {
+        self.module.as_ref()
+    }
+
     fn psl(&self) -> Result<Arc<Psl>> {
         let mut psl = self.psl.lock().unwrap();
         let psl = psl.get()?;
@@ -296,14 +427,14 @@ impl State for LuaState {
     }
 
     fn sock_connect(&self, host: &str, port: u16, options: &SocketOptions) -> Result<String> {
+        self.check_capability(Capability::Socket)?;
         let mut mtx = self.socket_sessions.lock().unwrap();
         let id = self.random_id();
 
-        let sock = if let Some(proxy) = self.resolve_proxy_options(&options.proxy)? {
-            Socket::connect_socks5(*proxy, host, port, options)?
-        } else {
-            Socket::connect(&self.dns_config, host, port, options)?
-        };
+        let slot = Slot::acquire(self)?;
+        let sock = Socket::connect(self, host, port, options);
+        drop(slot);
+        let sock = sock?;
 
         mtx.insert(id.clone(), Arc::new(Mutex::new(sock)));
 
@@ -331,10 +462,14 @@ impl State for LuaState {
     }
 
     fn ws_connect(&self, url: url::Url, options: &WebSocketOptions) -> Result<String> {
+        self.check_capability(Capability::Websocket)?;
         let mut mtx = self.ws_sessions.lock().unwrap();
         let id = self.random_id();
 
-        let sock = WebSocket::connect(&self.dns_config, url, options)?;
+        let slot = Slot::acquire(self)?;
+        let sock = WebSocket::connect(self, url, options);
+        drop(slot);
+        let sock = sock?;
         mtx.insert(id.clone(), Arc::new(Mutex::new(sock)));
 
         Ok(id)
@@ -347,10 +482,14 @@ impl State for LuaState {
     }
 
     fn mqtt_connect(&self, url: url::Url, options: &MqttOptions) -> Result<String> {
+        self.check_capability(Capability::Mqtt)?;
         let mut mtx = self.mqtt_sessions.lock().unwrap();
         let id = self.random_id();
 
-        let sock = MqttClient::connect(&self.dns_config, url, options)?;
+        let slot = Slot::acquire(self)?;
+        let sock = MqttClient::connect(self, url, options);
+        drop(slot);
+        let sock = sock?;
         mtx.insert(id.clone(), Arc::new(Mutex::new(sock)));
 
         Ok(id)
@@ -392,39 +531,55 @@ impl State for LuaState {
     }
 }
 
-impl WebState for LuaState {
-    fn http(&self, proxy: &Option<SocketAddr>) -> Result<Arc<chrootable_https::Client<Resolver>>> {
-        let proxy = self.resolve_proxy_options(proxy)?;
-
-        let proxy_str = if let Some(proxy) = &proxy {
-            proxy.to_string()
-        } else {
-            String::new()
-        };
-
-        let mut clients = self.http_clients.lock().unwrap();
+impl Connector for LuaState {
+    fn connect(&self, req: &ConnectRequest) -> Result<Connection> {
+        let mut tx = self.logger.lock().unwrap();
+        tx.connect(req)
+    }
 
-        if let Some(client) = clients.get(&proxy_str) {
-            Ok(client.clone())
-        } else {
-            let client = if let Some(proxy) = proxy {
-                chrootable_https::Client::with_socks5(*proxy)
-            } else {
-                let resolver = self.dns_config.clone();
-                chrootable_https::Client::new(resolver)
-            };
-            let client = Arc::new(client);
-            clients.insert(proxy_str, client.clone());
-            Ok(client)
-        }
+    fn connect_udp(&self, req: &ConnectRequest) -> Result<Datagram> {
+        let mut tx = self.logger.lock().unwrap();
+        tx.connect_udp(req)
     }
+}
 
+impl WebState for LuaState {
     fn register_in_jar(&self, session: &str, key: String, value: String) {
         let mut mtx = self.http_sessions.lock().unwrap();
         if let Some(session) = mtx.get_mut(session) {
             session.cookies.register_in_jar(key, value);
         }
     }
+
+    fn audit_http(&self, audit: HttpAudit) -> Result<()> {
+        if !self.audit {
+            return Ok(());
+        }
+
+        let url = url::Url::parse(&audit.url)?;
+        let headers = audit.headers.into_iter()
+            .map(|(key, value)| {
+                let value = match key.to_lowercase().as_str() {
+                    "authorization" | "proxy-authorization" | "cookie" => REDACTED.to_string(),
+                    _ => self.redact(&value),
+                };
+                (key, value)
+            })
+            .collect();
+
+        self.db_network_audit(InsertNetworkAudit {
+            kind: String::from("http"),
+            host: url.host_str().unwrap_or_default().to_string(),
+            port: url.port_or_known_default(),
+            method: Some(audit.method),
+            url: Some(self.redact(&audit.url)),
+            headers: Some(headers),
+            status: audit.status,
+            bytes: audit.bytes.map(|b| b as u64),
+            error: audit.error.map(|e| self.redact(&e)),
+            ipaddr: audit.ipaddr,
+        })
+    }
 }
 
 impl BlobState for LuaState {
@@ -437,17 +592,51 @@ impl BlobState for LuaState {
 
         id
     }
+
+    fn check_blob(&self) -> Result<()> {
+        self.check_capability(Capability::Blob)
+    }
 }
 
-impl LuaState {
-    fn resolve_proxy_options<'a>(&'a self, options: &'a Option<SocketAddr>) -> Result<Option<&'a SocketAddr>> {
-        match (&self.proxy, options) {
-            (Some(system),  Some(options))  if system == options => Ok(Some(system)),
-            (Some(_),       Some(_))        => bail!("Overriding the system proxy isn't allowed"),
-            (Some(system),  None)           => Ok(Some(system)),
-            (None,          Some(options))  => Ok(Some(options)),
-            (None,          None)           => Ok(None),
+impl ConcurrencyState for LuaState {
+    fn acquire_slot(&self) -> Result<()> {
+        if !self.concurrency {
+            return Ok(());
         }
+
+        self.send(&Event::Concurrency(ConcurrencyEvent::Acquire));
+        let reply = self.recv()?;
+        let reply: result::Result<(), String> = serde_json::from_value(reply)?;
+        reply.map_err(|err| format_err!("Failed to wait for a free slot: {}", err))
+    }
+
+    fn release_slot(&self) {
+        if self.concurrency {
+            self.send(&Event::Concurrency(ConcurrencyEvent::Release));
+        }
+    }
+}
+
+const REDACTED: &str = "[REDACTED]";
+
+fn redact_secrets(s: &str, keyring: &[KeyRingEntry]) -> String {
+    let mut s = s.to_string();
+    let secrets = keyring.iter()
+        .flat_map(|key| Some(&key.access_key).into_iter().chain(key.secret_key.as_ref()))
+        .filter(|secret| !secret.is_empty());
+
+    for secret in secrets {
+        let encoded = percent_encode(secret.as_bytes(), NON_ALPHANUMERIC).to_string();
+        s = s.replace(secret.as_str(), REDACTED)
+            .replace(&encoded, REDACTED);
+    }
+    s
+}
+
+impl LuaState {
+    /// Remove keyring secrets before anything is written to the audit log
+    fn redact(&self, s: &str) -> String {
+        redact_secrets(s, &self.keyring)
     }
 }
 
@@ -459,6 +648,8 @@ pub struct Script {
 pub fn ctx<'a>(env: Environment, logger: Arc<Mutex<Box<dyn IpcChild>>>) -> (hlua::Lua<'a>, Arc<LuaState>) {
     debug!("Creating lua context");
     let mut lua = hlua::Lua::new();
+    let memory = env.memory_limit
+        .map(|limit| MemoryLimit::install(&mut lua, limit));
     lua.open_string();
 
     let geoip = env.geoip.map(|db| Mutex::new(Lazy::from(db)));
@@ -472,17 +663,20 @@ pub fn ctx<'a>(env: Environment, logger: Arc<Mutex<Box<dyn IpcChild>>>) -> (hlua
         mqtt_sessions: Mutex::new(HashMap::new()),
         blobs: Mutex::new(HashMap::new()),
         http_sessions: Mutex::new(HashMap::new()),
-        http_clients: Mutex::new(HashMap::new()),
 
         verbose: env.verbose,
         keyring: env.keyring,
-        dns_config: env.dns_config,
         psl: Mutex::new(Lazy::from(env.psl)),
         geoip,
         asn,
-        proxy: env.proxy,
         user_agent: env.user_agent,
         options: env.options,
+        memory,
+        deadline: env.timeout.map(|timeout| Instant::now() + timeout),
+        capabilities: env.capabilities,
+        audit: env.audit,
+        concurrency: env.concurrency,
+        module: env.module,
     });
 
     debug!("Adding all blobs from StartCommand");
@@ -505,6 +699,7 @@ pub fn ctx<'a>(env: Environment, logger: Arc<Mutex<Box<dyn IpcChild>>>) -> (hlua
     runtime::db_activity(&mut lua, state.clone());
     runtime::db_select(&mut lua, state.clone());
     runtime::db_update(&mut lua, state.clone());
+    runtime::db_update_fields(&mut lua, state.clone());
     runtime::debug(&mut lua, state.clone());
     runtime::dns(&mut lua, state.clone());
     runtime::error(&mut lua, state.clone());
@@ -538,17 +733,28 @@ pub fn ctx<'a>(env: Environment, logger: Arc<Mutex<Box<dyn IpcChild>>>) -> (hlua
     runtime::json_encode(&mut lua, state.clone());
     runtime::key_trunc_pad(&mut lua, state.clone());
     runtime::keyring(&mut lua, state.clone());
+    runtime::keyring_exhausted(&mut lua, state.clone());
+    runtime::keyring_rotate(&mut lua, state.clone());
     runtime::last_err(&mut lua, state.clone());
+    runtime::last_err_info(&mut lua, state.clone());
     runtime::md5(&mut lua, state.clone());
+    runtime::module_info(&mut lua, state.clone());
     runtime::mqtt_connect(&mut lua, state.clone());
     runtime::mqtt_subscribe(&mut lua, state.clone());
     runtime::mqtt_recv(&mut lua, state.clone());
     runtime::mqtt_ping(&mut lua, state.clone());
+    runtime::notify(&mut lua, state.clone());
     runtime::pgp_pubkey(&mut lua, state.clone());
     runtime::pgp_pubkey_armored(&mut lua, state.clone());
     runtime::print(&mut lua, state.clone());
+    runtime::prompt(&mut lua, state.clone());
+    runtime::prompt_confirm(&mut lua, state.clone());
+    runtime::prompt_password(&mut lua, state.clone());
+    runtime::prompt_select(&mut lua, state.clone());
     runtime::psl_domain_from_dns_name(&mut lua, state.clone());
     runtime::ratelimit_throttle(&mut lua, state.clone());
+    runtime::read_dir(&mut lua, state.clone());
+    runtime::read_file(&mut lua, state.clone());
     runtime::regex_find(&mut lua, state.clone());
     runtime::regex_find_all(&mut lua, state.clone());
     runtime::semver_match(&mut lua, state.clone());
@@ -559,11 +765,14 @@ pub fn ctx<'a>(env: Environment, logger: Arc<Mutex<Box<dyn IpcChild>>>) -> (hlua
     runtime::sha3_256(&mut lua, state.clone());
     runtime::sha3_512(&mut lua, state.clone());
     runtime::sleep(&mut lua, state.clone());
+    runtime::sleep_jitter(&mut lua, state.clone());
     runtime::sn0int_time(&mut lua, state.clone());
     runtime::sn0int_time_from(&mut lua, state.clone());
     runtime::sn0int_version(&mut lua, state.clone());
     runtime::sock_connect(&mut lua, state.clone());
     runtime::sock_upgrade_tls(&mut lua, state.clone());
+    runtime::sock_ja3(&mut lua, state.clone());
+    runtime::sock_ja4(&mut lua, state.clone());
     runtime::sock_options(&mut lua, state.clone());
     runtime::sock_send(&mut lua, state.clone());
     runtime::sock_recv(&mut lua, state.clone());
@@ -585,12 +794,16 @@ pub fn ctx<'a>(env: Environment, logger: Arc<Mutex<Box<dyn IpcChild>>>) -> (hlua
     runtime::strval(&mut lua, state.clone());
     runtime::strftime(&mut lua, state.clone());
     runtime::strptime(&mut lua, state.clone());
+    runtime::time_monotonic(&mut lua, state.clone());
     runtime::time_unix(&mut lua, state.clone());
     runtime::url_decode(&mut lua, state.clone());
     runtime::url_encode(&mut lua, state.clone());
     runtime::url_escape(&mut lua, state.clone());
     runtime::url_join(&mut lua, state.clone());
+    runtime::url_normalize(&mut lua, state.clone());
     runtime::url_parse(&mut lua, state.clone());
+    runtime::url_query(&mut lua, state.clone());
+    runtime::url_set_query(&mut lua, state.clone());
     runtime::url_unescape(&mut lua, state.clone());
     runtime::utf8_decode(&mut lua, state.clone());
     runtime::warn(&mut lua, state.clone());
@@ -637,22 +850,43 @@ impl Script {
         &self.code
     }
 
+    /// Check if the script that has been loaded into `lua` defines a global
+    /// function with this name
+    fn defines_function(lua: &mut hlua::Lua, name: &str) -> bool {
+        let function: Option<hlua::LuaFunction<_>> = lua.get(name);
+        function.is_some()
+    }
+
     pub fn run(&self, env: Environment,
                       tx: Arc<Mutex<Box<dyn IpcChild>>>,
+                      entrypoint: Entrypoint,
                       arg: AnyLuaValue,
     ) -> Result<()> {
         let (mut lua, state) = ctx(env, tx);
 
-        debug!("Initializing lua module");
-        lua.execute::<()>(&self.code)?;
+        let exec = || {
+            debug!("Initializing lua module");
+            lua.execute::<()>(&self.code)?;
 
-        let run: Result<_> = lua.get("run")
-            .ok_or_else(|| format_err!( "run undefined"));
-        let mut run: hlua::LuaFunction<_> = run?;
+            // the parent only schedules on_finish if a script reported it
+            if entrypoint == Entrypoint::Run && Self::defines_function(&mut lua, Entrypoint::Finish.function()) {
+                state.send(&Event::OnFinish);
+            }
 
-        debug!("Starting lua script");
-        let result: hlua::AnyLuaValue = run.call_with_args(arg)
-            .map_err(|err| format_err!("execution failed: {:?}", err))?;
+            let function = entrypoint.function();
+            let run: Result<_> = lua.get(function)
+                .ok_or_else(|| format_err!("{} undefined", function));
+            let mut run: hlua::LuaFunction<_> = run?;
+
+            debug!("Starting lua script");
+            let result: hlua::AnyLuaValue = run.call_with_args(arg)
+                .map_err(|err| format_err!("execution failed: {:?}", err))?;
+            Ok(result)
+        };
+        let result = match state.memory {
+            Some(memory) => memory.catch(exec)?,
+            None => exec()?,
+        };
 
         debug!("Lua script terminated");
 
@@ -669,12 +903,29 @@ impl Script {
 
     #[cfg(test)]
     pub fn test(&self) -> Result<()> {
+        self.test_with(Script::test_env()?)
+    }
+
+    #[cfg(test)]
+    pub fn test_with(&self, env: Environment) -> Result<()> {
+        self.test_with_policy(env, NetworkPolicy::default())
+    }
+
+    /// Run the script with connections restricted by the parent's policy
+    #[cfg(test)]
+    pub fn test_with_policy(&self, env: Environment, policy: NetworkPolicy) -> Result<()> {
         use crate::ipc::child::DummyIpcChild;
+        use crate::ipc::network::Broker;
+        let broker = Broker::new(policy, Resolver::from_system_v4()?);
+        self.run(env, DummyIpcChild::create(broker), Entrypoint::Run, AnyLuaValue::LuaNil)
+    }
+
+    /// The environment used by `test`, tests may adjust it for `test_with`
+    #[cfg(test)]
+    pub fn test_env() -> Result<Environment> {
         use crate::geoip::Maxmind;
         use crate::paths;
         let keyring = Vec::new();
-        let dns_config = Resolver::from_system_v4()?;
-        let proxy = None;
         let user_agent = None;
         let psl = PslReader::String(r#"
 // ===BEGIN ICANN DOMAINS===
@@ -688,18 +939,90 @@ a.prod.fastly.net
         let geoip = GeoIP::try_open_reader(&cache_dir)?;
         let asn = AsnDB::try_open_reader(&cache_dir)?;
 
-        let env = Environment {
+        Ok(Environment {
             verbose: 0,
             keyring,
-            dns_config,
-            proxy,
             user_agent,
             options: HashMap::new(),
             blobs: Vec::new(),
+            memory_limit: None,
+            timeout: None,
+            capabilities: None,
+            audit: false,
+            concurrency: false,
+            module: None,
             psl,
             geoip,
             asn,
-        };
-        self.run(env, DummyIpcChild::create(), AnyLuaValue::LuaNil)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_secrets() {
+        let keyring = vec![KeyRingEntry {
+            namespace: "shodan".to_string(),
+            access_key: "a13f9e".to_string(),
+            secret_key: Some("s3cr3t/+key".to_string()),
+            workspace: None,
+        }];
+        assert_eq!(redact_secrets("https://api.example.com/?id=a13f9e&key=s3cr3t/+key", &keyring),
+            "https://api.example.com/?id=[REDACTED]&key=[REDACTED]");
+        assert_eq!(redact_secrets("https://api.example.com/?key=s3cr3t%2F%2Bkey", &keyring),
+            "https://api.example.com/?key=[REDACTED]");
+        assert_eq!(redact_secrets("https://example.com/", &keyring), "https://example.com/");
+    }
+
+    fn defines_on_finish(code: &str) -> bool {
+        let mut lua = hlua::Lua::new();
+        lua.execute::<()>(code).unwrap();
+        Script::defines_function(&mut lua, "on_finish")
+    }
+
+    #[test]
+    fn test_defines_on_finish() {
+        assert!(defines_on_finish(r#"
+        function run()
+        end
+
+        function on_finish(result)
+        end
+        "#));
+        assert!(defines_on_finish(r#"
+        on_finish = function(result)
+        end
+        "#));
+        assert!(!defines_on_finish(r#"
+        function run()
+            -- function on_finish() is not defined
+            local x = "function on_finish()"
+        end
+        "#));
+        assert!(!defines_on_finish("on_finish = 1"));
+    }
+
+    #[test]
+    fn test_run_on_finish() {
+        use crate::ipc::child::DummyIpcChild;
+        use crate::ipc::network::Broker;
+        let script = Script::load_unchecked(r#"
+        function run()
+            return 'run should not be called'
+        end
+
+        function on_finish(result)
+            if not result['failed'] then
+                return 'expected a failed run'
+            end
+        end
+        "#).unwrap();
+        let mut arg = crate::engine::structs::LuaMap::new();
+        arg.insert("failed", AnyLuaValue::LuaBoolean(true));
+        let broker = Broker::new(NetworkPolicy::default(), Resolver::empty());
+        script.run(Script::test_env().unwrap(), DummyIpcChild::create(broker), Entrypoint::Finish, arg.into()).unwrap();
     }
 }
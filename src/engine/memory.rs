@@ -0,0 +1,152 @@
+use crate::errors::*;
+use crate::hlua::{self, AsMutLua};
+use libc::c_void;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+
+/// Accounting for the memory that is allocated by a lua state
+#[derive(Debug)]
+pub struct MemoryLimit {
+    limit: usize,
+    used: AtomicUsize,
+    exceeded: AtomicBool,
+    catching: AtomicBool,
+}
+
+impl MemoryLimit {
+    /// Replace the allocator of the lua state with one that refuses to grow
+    /// beyond `limit` bytes, use `catch` to run code in that lua state.
+    ///
+    /// The accounting struct needs to outlive the lua state, which may be
+    /// destroyed after every other reference is gone, so it's leaked on
+    /// purpose. This is only done once per sandbox process.
+    pub fn install(lua: &mut hlua::Lua, limit: usize) -> &'static MemoryLimit {
+        let memory: &'static MemoryLimit = Box::leak(Box::new(MemoryLimit {
+            limit,
+            used: AtomicUsize::new(0),
+            exceeded: AtomicBool::new(false),
+            catching: AtomicBool::new(false),
+        }));
+
+        let ud = memory as *const MemoryLimit as *mut c_void;
+        unsafe {
+            hlua::ffi::lua_setallocf(lua.as_mut_lua().state_ptr(), alloc, ud);
+        }
+
+        // hlua panics on out-of-memory errors, these are reported by `catch`
+        let default = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !(memory.exceeded() && memory.catching.load(Ordering::SeqCst)) {
+                default(info);
+            }
+        }));
+
+        memory
+    }
+
+    /// Turn out-of-memory conditions while running `f` into a regular error
+    pub fn catch<T, F: FnOnce() -> Result<T>>(&self, f: F) -> Result<T> {
+        self.catching.store(true, Ordering::SeqCst);
+        let result = panic::catch_unwind(AssertUnwindSafe(f));
+        self.catching.store(false, Ordering::SeqCst);
+
+        match result {
+            Ok(Err(_)) | Err(_) if self.exceeded() => {
+                bail!("Module exceeded the memory limit of {} MiB", self.limit / 1024 / 1024)
+            },
+            Ok(result) => result,
+            Err(err) => panic::resume_unwind(err),
+        }
+    }
+
+    #[inline]
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    #[inline]
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::SeqCst)
+    }
+
+    #[inline]
+    pub fn exceeded(&self) -> bool {
+        self.exceeded.load(Ordering::SeqCst)
+    }
+
+    fn reserve(&self, size: usize) -> bool {
+        let result = self.used.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+            used.checked_add(size)
+                .filter(|total| *total <= self.limit)
+        });
+        if result.is_err() {
+            self.exceeded.store(true, Ordering::SeqCst);
+        }
+        result.is_ok()
+    }
+
+    fn release(&self, size: usize) {
+        // memory that was allocated before the limit was installed isn't tracked
+        let _ = self.used.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+            Some(used.saturating_sub(size))
+        });
+    }
+}
+
+extern "C" fn alloc(ud: *mut c_void, ptr: *mut c_void, osize: usize, nsize: usize) -> *mut c_void {
+    let memory = unsafe { &*(ud as *const MemoryLimit) };
+    // if ptr is null, osize encodes the type of the object instead of a size
+    let osize = if ptr.is_null() { 0 } else { osize };
+
+    if nsize == 0 {
+        unsafe { libc::free(ptr) };
+        memory.release(osize);
+        return ptr::null_mut();
+    }
+
+    // lua assumes shrinking never fails, so only growth is checked
+    if nsize > osize && !memory.reserve(nsize - osize) {
+        return ptr::null_mut();
+    }
+
+    let new = unsafe { libc::realloc(ptr, nsize) };
+    if new.is_null() {
+        if nsize > osize {
+            memory.release(nsize - osize);
+        }
+    } else if nsize < osize {
+        memory.release(osize - nsize);
+    }
+    new
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_limit_exceeded() {
+        let mut lua = hlua::Lua::new();
+        let memory = MemoryLimit::install(&mut lua, 1024 * 1024);
+        let r = memory.catch(|| {
+            lua.execute::<()>("local t = {} for i=1,10000000 do t[i] = i end")?;
+            Ok(())
+        });
+        assert_eq!(r.unwrap_err().to_string(), "Module exceeded the memory limit of 1 MiB");
+        assert!(memory.exceeded());
+        assert!(memory.used() <= memory.limit());
+    }
+
+    #[test]
+    fn test_memory_limit_ok() {
+        let mut lua = hlua::Lua::new();
+        let memory = MemoryLimit::install(&mut lua, 16 * 1024 * 1024);
+        memory.catch(|| {
+            lua.execute::<()>("local t = {} for i=1,1000 do t[i] = i end")?;
+            Ok(())
+        }).unwrap();
+        assert!(!memory.exceeded());
+    }
+}
@@ -1,42 +1,71 @@
 use crate::errors::*;
 use serde::{Serialize, Deserialize};
 
+use crate::capabilities;
 use crate::blobs::Blob;
 use crate::config::Config;
 use crate::geoip::MaxmindReader;
 use crate::json::LuaJsonValue;
 use crate::keyring::KeyRingEntry;
+use crate::links::Links;
 use std::fs;
 use std::fmt::Debug;
 use std::path::PathBuf;
 use std::collections::HashMap;
-use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
 use crate::engine::ctx::Script;
 use crate::ipc::child::IpcChild;
 use sn0int_common::ModuleID;
-use sn0int_common::metadata::{Metadata, Source, Stealth};
-use chrootable_https::dns::Resolver;
+use sn0int_common::metadata::{Capability, EgressRule, Metadata, Source, Stealth};
 use crate::psl::PslReader;
 use crate::paths;
 use std::cmp::Ordering;
 use std::path::Path;
+use std::time::Duration;
 use crate::term;
 
 pub mod ctx;
+pub mod memory;
 pub use sn0int_std::engine::structs;
 
 
+/// The lua function that is called by the sandbox child
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Entrypoint {
+    /// Once for every target
+    #[default]
+    Run,
+    /// Once after all targets have been processed
+    Finish,
+}
+
+impl Entrypoint {
+    pub fn function(&self) -> &'static str {
+        match self {
+            Entrypoint::Run => "run",
+            Entrypoint::Finish => "on_finish",
+        }
+    }
+}
+
 /// Data that is passed to every script
 #[derive(Debug)]
 pub struct Environment {
     pub verbose: u8,
     pub keyring: Vec<KeyRingEntry>,
-    pub dns_config: Resolver,
-    pub proxy: Option<SocketAddr>,
     pub user_agent: Option<String>,
     pub options: HashMap<String, String>,
     pub blobs: Vec<Blob>,
+    pub memory_limit: Option<usize>,
+    /// The time the script may spend on its source, sleeping past it fails
+    pub timeout: Option<Duration>,
+    /// `None` if the module may use everything
+    pub capabilities: Option<Vec<Capability>>,
+    pub audit: bool,
+    /// Network operations need a slot from the concurrency budget of the run
+    pub concurrency: bool,
+    /// The header of the module that is executed
+    pub module: Option<ModuleInfo>,
     pub psl: PslReader,
     pub geoip: Option<MaxmindReader>,
     pub asn: Option<MaxmindReader>,
@@ -116,19 +145,36 @@ impl<'a> Library<'a> {
             self.load_module_folder(&path, &author_name, private_modules)?;
         }
 
-        for (author, folder) in &self.config.namespaces {
-            let folder = if folder.is_absolute() {
-                folder.to_owned()
-            } else {
-                let folder = folder.strip_prefix("~/")
-                    .unwrap_or(folder);
+        for (author, folder) in self.config.namespace_folders()? {
+            self.load_module_folder(&folder, author, true)?;
+        }
 
-                dirs_next::home_dir()
-                    .ok_or_else(|| format_err!("Failed to find home folder"))?
-                    .join(folder)
-            };
+        for (canonical, path) in Links::load()?.iter() {
+            if let Err(err) = self.load_linked_module(path, canonical) {
+                let root = err.find_root_cause();
+                term::warn(&format!("Failed to load linked module {}: {}", canonical, root));
+            }
+        }
 
-            self.load_module_folder(&folder, author, true)?;
+        Ok(())
+    }
+
+    /// A linked module replaces a module with the same name from the module
+    /// directory
+    fn load_linked_module(&mut self, path: &Path, canonical: &str) -> Result<()> {
+        let id = canonical.parse::<ModuleID>()
+            .map_err(|err| format_err!("{}", err))?;
+        let module = Module::load_linked(path, &id.author, &id.name)?;
+
+        self.modules.remove(canonical);
+        if let Some(vec) = self.modules.get_mut(&id.name) {
+            vec.retain(|m| m.canonical() != canonical);
+        }
+
+        for key in &[id.name.clone(), canonical.to_string()] {
+            self.modules.entry(key.to_string())
+                .or_default()
+                .push(module.clone());
         }
 
         Ok(())
@@ -187,7 +233,7 @@ impl<'a> Library<'a> {
         if let Some(module) = self.get_opt(name)? {
             Ok(module)
         } else {
-            bail!("Module not found")
+            Err(NotFoundError(String::from("Module not found")).into())
         }
     }
 
@@ -228,12 +274,34 @@ pub struct Module {
     version: String,
     source: Option<Source>,
     keyring_access: Vec<String>,
+    keyring_optional: Vec<String>,
+    egress: Option<Vec<EgressRule>>,
+    capabilities: Option<Vec<Capability>>,
+    emits: Vec<String>,
+    dns: bool,
     stealth: Stealth,
 
     private_module: bool,
+    /// The file of a module that has been added with `pkg link`
+    linked: Option<PathBuf>,
     script: Script,
 }
 
+/// The parts of the module header that are returned by `module_info`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModuleInfo {
+    pub author: String,
+    pub name: String,
+    pub canonical: String,
+    pub version: String,
+    pub description: String,
+    /// The argument the module is executed with
+    pub source: Option<String>,
+    pub emits: Vec<String>,
+    pub keyring_access: Vec<String>,
+    pub stealth: String,
+}
+
 impl Module {
     pub fn load(path: &Path, author: &str, name: &str, private_module: bool) -> Result<Module> {
         debug!("Loading lua module {}/{} from {:?}", author, name, path);
@@ -252,13 +320,35 @@ impl Module {
             version: metadata.version,
             source: metadata.source,
             keyring_access: metadata.keyring_access,
+            keyring_optional: metadata.keyring_optional,
+            egress: metadata.egress,
+            capabilities: metadata.capabilities,
+            emits: metadata.emits,
+            dns: metadata.dns,
             stealth: metadata.stealth,
 
             private_module,
+            linked: None,
             script,
         })
     }
 
+    /// Linked modules are never updated from the registry
+    pub fn load_linked(path: &Path, author: &str, name: &str) -> Result<Module> {
+        let mut module = Module::load(path, author, name, true)
+            .context(format!("Failed to parse {:?}", path))?;
+        module.linked = Some(path.to_path_buf());
+        Ok(module)
+    }
+
+    /// Read a linked module again to pick up changes since it was loaded
+    pub fn reload_linked(&self) -> Result<Module> {
+        match &self.linked {
+            Some(path) => Module::load_linked(path, &self.author, &self.name),
+            None => Ok(self.clone()),
+        }
+    }
+
     #[inline]
     pub fn author(&self) -> &str {
         &self.author
@@ -301,6 +391,50 @@ impl Module {
         &self.keyring_access
     }
 
+    /// The module also works if there are no keys for this namespace
+    #[inline]
+    pub fn is_keyring_optional(&self, namespace: &str) -> bool {
+        self.keyring_optional.iter().any(|x| x == namespace)
+    }
+
+    #[inline]
+    pub fn egress(&self) -> Option<&[EgressRule]> {
+        self.egress.as_deref()
+    }
+
+    #[inline]
+    pub fn declared_capabilities(&self) -> Option<&[Capability]> {
+        self.capabilities.as_deref()
+    }
+
+    #[inline]
+    pub fn emits(&self) -> &[String] {
+        &self.emits
+    }
+
+    #[inline]
+    pub fn dns(&self) -> bool {
+        self.dns
+    }
+
+    /// A summary of the optional features this module has access to
+    pub fn capabilities(&self) -> String {
+        let mut capabilities = Vec::new();
+        if self.dns {
+            capabilities.push(String::from("dns"));
+        }
+        match &self.capabilities {
+            Some(declared) => capabilities.extend(declared.iter().map(|c| c.as_str().to_string())),
+            None => capabilities.push(capabilities::format_list(None)),
+        }
+
+        if capabilities.is_empty() {
+            String::from("none")
+        } else {
+            capabilities.join(", ")
+        }
+    }
+
     #[inline]
     pub fn stealth(&self) -> &Stealth {
         &self.stealth
@@ -311,14 +445,33 @@ impl Module {
         self.private_module
     }
 
+    pub fn info(&self) -> ModuleInfo {
+        ModuleInfo {
+            author: self.author.clone(),
+            name: self.name.clone(),
+            canonical: self.canonical(),
+            version: self.version.clone(),
+            description: self.description.clone(),
+            source: self.source.as_ref().map(Source::to_string),
+            emits: self.emits.clone(),
+            keyring_access: self.keyring_access.clone(),
+            stealth: format!("{:?}", self.stealth).to_lowercase(),
+        }
+    }
+
+    #[inline]
+    pub fn linked(&self) -> Option<&PathBuf> {
+        self.linked.as_ref()
+    }
+
     #[inline]
     pub fn code(&self) -> &str {
         self.script.code()
     }
 
-    pub fn run(&self, env: Environment, ipc_child: Arc<Mutex<Box<dyn IpcChild>>>, arg: LuaJsonValue) -> Result<()> {
+    pub fn run(&self, env: Environment, ipc_child: Arc<Mutex<Box<dyn IpcChild>>>, entrypoint: Entrypoint, arg: LuaJsonValue) -> Result<()> {
         debug!("Executing lua script {}", self.canonical());
-        self.script.run(env, ipc_child, arg.into())
+        self.script.run(env, ipc_child, entrypoint, arg.into())
     }
 
     #[inline]
@@ -1,3 +1,216 @@
 pub use log::{trace, debug, info, warn, error};
 pub use failure::{Error, ResultExt, format_err, bail};
 pub type Result<T> = ::std::result::Result<T, Error>;
+
+use diesel::result::DatabaseErrorKind;
+use serde::Serialize;
+use sn0int_std::error_info::{CapabilityError, EgressError, ErrorInfo, ErrorKind};
+use std::error;
+use std::fmt;
+use std::io;
+
+/// Something that has been looked up by name or id doesn't exist
+#[derive(Debug)]
+pub struct NotFoundError(pub String);
+
+impl fmt::Display for NotFoundError {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(w, "{}", self.0)
+    }
+}
+
+impl error::Error for NotFoundError {}
+
+/// The sandbox refused to run a module or lost its child process
+#[derive(Debug)]
+pub struct SandboxError(pub String);
+
+impl fmt::Display for SandboxError {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(w, "{}", self.0)
+    }
+}
+
+impl error::Error for SandboxError {}
+
+/// Stable error codes for scripts that call sn0int, these are also used as
+/// exit codes and must not be renumbered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorCode {
+    Other,
+    NotFound,
+    Conflict,
+    SandboxViolation,
+    Network,
+    DbLocked,
+    PermissionDenied,
+}
+
+impl ErrorCode {
+    pub fn of(err: &Error) -> ErrorCode {
+        if crate::db::is_busy(err) {
+            return ErrorCode::DbLocked;
+        }
+
+        for cause in err.iter_chain() {
+            if let Some(code) = Self::from_fail(cause) {
+                return code;
+            }
+        }
+
+        match ErrorInfo::new(err).kind {
+            ErrorKind::Dns
+            | ErrorKind::Timeout
+            | ErrorKind::Tls
+            | ErrorKind::Connection
+            | ErrorKind::HttpStatus
+            | ErrorKind::Offline => ErrorCode::Network,
+            ErrorKind::Capability => ErrorCode::SandboxViolation,
+            ErrorKind::Io | ErrorKind::Other => ErrorCode::Other,
+        }
+    }
+
+    fn from_fail(err: &dyn failure::Fail) -> Option<ErrorCode> {
+        if let Some(err) = err.downcast_ref::<diesel::result::Error>() {
+            match err {
+                diesel::result::Error::NotFound => Some(ErrorCode::NotFound),
+                diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => Some(ErrorCode::Conflict),
+                _ => None,
+            }
+        } else if let Some(err) = err.downcast_ref::<io::Error>() {
+            match err.kind() {
+                io::ErrorKind::NotFound => Some(ErrorCode::NotFound),
+                io::ErrorKind::AlreadyExists => Some(ErrorCode::Conflict),
+                io::ErrorKind::PermissionDenied => Some(ErrorCode::PermissionDenied),
+                _ => None,
+            }
+        } else if err.downcast_ref::<NotFoundError>().is_some() {
+            Some(ErrorCode::NotFound)
+        } else if err.downcast_ref::<CapabilityError>().is_some()
+            || err.downcast_ref::<EgressError>().is_some()
+            || err.downcast_ref::<SandboxError>().is_some() {
+            Some(ErrorCode::SandboxViolation)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::Other => "other",
+            ErrorCode::NotFound => "not-found",
+            ErrorCode::Conflict => "conflict",
+            ErrorCode::SandboxViolation => "sandbox-violation",
+            ErrorCode::Network => "network",
+            ErrorCode::DbLocked => "db-locked",
+            ErrorCode::PermissionDenied => "permission-denied",
+        }
+    }
+
+    /// 2 is skipped because clap uses it for usage errors
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ErrorCode::Other => 1,
+            ErrorCode::NotFound => 3,
+            ErrorCode::Conflict => 4,
+            ErrorCode::SandboxViolation => 5,
+            ErrorCode::Network => 6,
+            ErrorCode::DbLocked => 7,
+            ErrorCode::PermissionDenied => 8,
+        }
+    }
+}
+
+/// An error as it's printed for commands that run with `--json`
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ErrorReport {
+    pub code: ErrorCode,
+    pub exit_code: i32,
+    pub message: String,
+    pub causes: Vec<String>,
+}
+
+impl ErrorReport {
+    pub fn new(err: &Error) -> ErrorReport {
+        let code = ErrorCode::of(err);
+        ErrorReport {
+            code,
+            exit_code: code.exit_code(),
+            message: err.to_string(),
+            causes: err.iter_chain()
+                .skip(1)
+                .map(|cause| cause.to_string())
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sn0int_std::error_info::OfflineError;
+
+    fn code(err: Error) -> ErrorCode {
+        ErrorCode::of(&err)
+    }
+
+    #[test]
+    fn test_diesel() {
+        assert_eq!(code(diesel::result::Error::NotFound.into()), ErrorCode::NotFound);
+        let err = diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, Box::new(String::from("UNIQUE constraint failed")));
+        assert_eq!(code(err.into()), ErrorCode::Conflict);
+    }
+
+    #[test]
+    fn test_db_locked() {
+        assert_eq!(code(format_err!("database is locked")), ErrorCode::DbLocked);
+    }
+
+    #[test]
+    fn test_network() {
+        let err = Error::from(OfflineError).context("Failed to connect");
+        assert_eq!(code(err.into()), ErrorCode::Network);
+        let err = io::Error::from(io::ErrorKind::ConnectionRefused);
+        assert_eq!(code(err.into()), ErrorCode::Network);
+    }
+
+    #[test]
+    fn test_sandbox() {
+        assert_eq!(code(CapabilityError { capability: "dns" }.into()), ErrorCode::SandboxViolation);
+        assert_eq!(code(EgressError { host: "example.com".to_string() }.into()), ErrorCode::SandboxViolation);
+        let err = Error::from(SandboxError("Sandbox child has crashed".to_string())).context("Failed to run module");
+        assert_eq!(code(err.into()), ErrorCode::SandboxViolation);
+    }
+
+    #[test]
+    fn test_remote() {
+        let info = ErrorInfo::new(&CapabilityError { capability: "dns" }.into());
+        assert_eq!(code(info.into_error()), ErrorCode::SandboxViolation);
+        let info = ErrorInfo::new(&io::Error::from(io::ErrorKind::TimedOut).into());
+        assert_eq!(code(info.into_error()), ErrorCode::Network);
+    }
+
+    #[test]
+    fn test_not_found() {
+        assert_eq!(code(NotFoundError("View doesn't exist: \"foo\"".to_string()).into()), ErrorCode::NotFound);
+    }
+
+    #[test]
+    fn test_messages_are_not_classified() {
+        assert_eq!(code(format_err!("View doesn't exist: \"foo\"")), ErrorCode::Other);
+        assert_eq!(code(format_err!("Sandbox child has crashed")), ErrorCode::Other);
+        assert_eq!(code(format_err!("something went wrong")), ErrorCode::Other);
+    }
+
+    #[test]
+    fn test_report_keeps_messages() {
+        let err = Error::from(io::Error::from(io::ErrorKind::NotFound));
+        let err = Error::from(err.context("Failed to read keyring file"));
+        let report = ErrorReport::new(&err);
+        assert_eq!(report.code, ErrorCode::NotFound);
+        assert_eq!(report.exit_code, 3);
+        assert_eq!(report.message, "Failed to read keyring file");
+        assert_eq!(report.causes, vec!["entity not found"]);
+    }
+}
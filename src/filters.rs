@@ -1,5 +1,6 @@
 use crate::errors::*;
-use crate::db;
+use crate::db::{self, Database, Family, Table};
+use crate::models::View;
 use clap::Parser;
 
 #[derive(Debug, Parser)]
@@ -49,6 +50,67 @@ pub enum Target {
 }
 
 impl Target {
+    pub fn table(&self) -> Table {
+        match self {
+            Target::Domains(_) => Table::Domains,
+            Target::Subdomains(_) => Table::Subdomains,
+            Target::IpAddrs(_) => Table::Ipaddrs,
+            Target::Urls(_) => Table::Urls,
+            Target::Emails(_) => Table::Emails,
+            Target::PhoneNumbers(_) => Table::Phonenumbers,
+            Target::Devices(_) => Table::Devices,
+            Target::Networks(_) => Table::Networks,
+            Target::Accounts(_) => Table::Accounts,
+            Target::Breaches(_) => Table::Breaches,
+            Target::Images(_) => Table::Images,
+            Target::Ports(_) => Table::Ports,
+            Target::Netblocks(_) => Table::Netblocks,
+            Target::CryptoAddrs(_) => Table::Cryptoaddrs,
+        }
+    }
+
+    /// Every entity of a type that autonoscope rules apply to
+    pub fn any_of(family: &Family) -> Option<Target> {
+        let filter = Filter::default();
+        match family {
+            Family::Domain => Some(Target::Domains(filter)),
+            Family::Subdomain => Some(Target::Subdomains(filter)),
+            Family::Ipaddr => Some(Target::IpAddrs(filter)),
+            Family::Url => Some(Target::Urls(filter)),
+            Family::Email => Some(Target::Emails(filter)),
+            Family::Port => Some(Target::Ports(filter)),
+            Family::Netblock => Some(Target::Netblocks(filter)),
+            _ => None,
+        }
+    }
+
+    pub fn filter(&self) -> &Filter {
+        match self {
+            Target::Domains(f) |
+            Target::Subdomains(f) |
+            Target::IpAddrs(f) |
+            Target::Urls(f) |
+            Target::Emails(f) |
+            Target::PhoneNumbers(f) |
+            Target::Devices(f) |
+            Target::Networks(f) |
+            Target::Accounts(f) |
+            Target::Breaches(f) |
+            Target::Images(f) |
+            Target::Ports(f) |
+            Target::Netblocks(f) |
+            Target::CryptoAddrs(f) => f,
+        }
+    }
+
+    pub fn parse_optional(&self, db: &Database) -> Result<db::Filter> {
+        self.filter().parse_optional(db, self.table())
+    }
+
+    pub fn parse(&self, db: &Database) -> Result<db::Filter> {
+        self.filter().parse(db, self.table())
+    }
+
     pub fn domains(&self) -> Option<&Filter> {
         if let Target::Domains(f) = self {
             Some(f)
@@ -81,6 +143,14 @@ impl Target {
         }
     }
 
+    pub fn emails(&self) -> Option<&Filter> {
+        if let Target::Emails(f) = self {
+            Some(f)
+        } else {
+            None
+        }
+    }
+
     pub fn ports(&self) -> Option<&Filter> {
         if let Target::Ports(f) = self {
             Some(f)
@@ -98,8 +168,17 @@ impl Target {
     }
 }
 
-#[derive(Debug, Parser)]
+#[derive(Debug, Default, Parser)]
 pub struct Filter {
+    /// Only match entities linked to this one, like domain:example.com
+    #[arg(long="related-to")]
+    related_to: Vec<String>,
+    /// Apply a saved view, additional conditions are combined with AND
+    #[arg(long="view")]
+    view: Option<String>,
+    /// Leave out entities matching this filter, like "where unscoped=0"
+    #[arg(long="exclude-filter")]
+    exclude_filter: Vec<String>,
     args: Vec<String>,
 }
 
@@ -109,11 +188,30 @@ impl Filter {
         db::Filter::any()
     }
 
-    pub fn parse_optional(&self) -> Result<db::Filter> {
-        db::Filter::parse_optional(&self.args)
+    fn and_extra(&self, db: &Database, table: Table, mut filter: db::Filter) -> Result<db::Filter> {
+        for related in &self.related_to {
+            filter = filter.and_related(table, related)?;
+        }
+        if let Some(view) = &self.view {
+            filter = filter.and(&View::get(db, view)?.filter());
+        }
+        for exclude in &self.exclude_filter {
+            filter = filter.and_not(&db::Filter::parse_expr(Some(table), exclude)?);
+        }
+        Ok(filter)
     }
 
-    pub fn parse(&self) -> Result<db::Filter> {
-        db::Filter::parse(&self.args)
+    pub fn parse_optional(&self, db: &Database, table: Table) -> Result<db::Filter> {
+        let filter = db::Filter::parse_optional_for(table, &self.args)?;
+        self.and_extra(db, table, filter)
+    }
+
+    pub fn parse(&self, db: &Database, table: Table) -> Result<db::Filter> {
+        // --related-to, --view and --exclude-filter are conditions on their own
+        if self.args.is_empty() && (!self.related_to.is_empty() || self.view.is_some() || !self.exclude_filter.is_empty()) {
+            return self.and_extra(db, table, db::Filter::any());
+        }
+        let filter = db::Filter::parse_for(table, &self.args)?;
+        self.and_extra(db, table, filter)
     }
 }
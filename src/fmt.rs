@@ -4,70 +4,264 @@ pub use std::fmt::{Result, Formatter, Display, Debug};
 
 
 pub mod colors {
+    use atty::{self, Stream};
+    use clap::ValueEnum;
+    use crate::errors::*;
+    use lazy_static::lazy_static;
+    use serde::{de, Serialize, Serializer, Deserialize, Deserializer};
+    use std::env;
     use std::fmt::{self, Write};
+    use std::result;
+    use std::str::FromStr;
+    use std::sync::RwLock;
+
+    /// When to write escape codes
+    #[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+    pub enum ColorChoice {
+        Always,
+        /// Only if stdout is a terminal and `NO_COLOR` isn't set
+        #[default]
+        Auto,
+        Never,
+    }
 
-    pub trait Color {
-        fn color<W: Write>(w: &mut W) -> fmt::Result;
-        fn display<W: Write, D: fmt::Display>(w: &mut W, v: D) -> fmt::Result;
-        fn debug<W: Write, D: fmt::Debug>(w: &mut W, v: D) -> fmt::Result;
+    impl ColorChoice {
+        fn enabled(self) -> bool {
+            match self {
+                ColorChoice::Always => true,
+                ColorChoice::Auto => {
+                    let no_color = env::var_os("NO_COLOR")
+                        .map(|x| !x.is_empty())
+                        .unwrap_or(false);
+                    !no_color && atty::is(Stream::Stdout)
+                },
+                ColorChoice::Never => false,
+            }
+        }
     }
 
-    pub struct Red;
+    /// The styles sn0int uses
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Style {
+        Red,
+        Green,
+        Yellow,
+        Blue,
+        Grey,
+        Bold,
+    }
 
-    impl Color for Red {
-        #[inline]
-        fn color<W: Write>(w: &mut W) -> fmt::Result {
-            write!(w, "\x1b[31m")
+    const NAMES: &[(&str, u8)] = &[
+        ("black", 30),
+        ("red", 31),
+        ("green", 32),
+        ("yellow", 33),
+        ("blue", 34),
+        ("magenta", 35),
+        ("cyan", 36),
+        ("white", 37),
+        ("grey", 90),
+        ("gray", 90),
+        ("bright-red", 91),
+        ("bright-green", 92),
+        ("bright-yellow", 93),
+        ("bright-blue", 94),
+        ("bright-magenta", 95),
+        ("bright-cyan", 96),
+        ("bright-white", 97),
+    ];
+
+    /// A color of the terminal, either by name or a number of the 256 color palette
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum ColorCode {
+        Ansi(u8),
+        Fixed(u8),
+    }
+
+    impl fmt::Display for ColorCode {
+        /// The parameters of the escape code
+        fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                ColorCode::Ansi(code) => write!(w, "{}", code),
+                ColorCode::Fixed(code) => write!(w, "38;5;{}", code),
+            }
         }
+    }
 
-        #[inline]
-        fn display<W: Write, D: fmt::Display>(w: &mut W, v: D) -> fmt::Result {
-            write!(w, "\x1b[31m{}\x1b[0m", v)
+    impl FromStr for ColorCode {
+        type Err = Error;
+
+        fn from_str(s: &str) -> Result<ColorCode> {
+            if let Some((_, code)) = NAMES.iter().find(|(name, _)| *name == s) {
+                Ok(ColorCode::Ansi(*code))
+            } else if let Ok(code) = s.parse::<u8>() {
+                Ok(ColorCode::Fixed(code))
+            } else {
+                bail!("Unknown color {:?}, expected a name like cyan or bright-red, or a number from 0 to 255", s)
+            }
         }
+    }
 
-        #[inline]
-        fn debug<W: Write, D: fmt::Debug>(w: &mut W, v: D) -> fmt::Result {
-            write!(w, "\x1b[31m{:?}\x1b[0m", v)
+    impl Serialize for ColorCode {
+        fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+            where S: Serializer
+        {
+            match self {
+                ColorCode::Ansi(code) => {
+                    let (name, _) = NAMES.iter()
+                        .find(|(_, x)| x == code)
+                        .expect("Unnamed ansi color");
+                    serializer.serialize_str(name)
+                },
+                ColorCode::Fixed(code) => serializer.serialize_str(&code.to_string()),
+            }
         }
     }
 
-    pub struct Green;
+    impl<'de> Deserialize<'de> for ColorCode {
+        fn deserialize<D>(deserializer: D) -> result::Result<Self, D::Error>
+            where D: Deserializer<'de>
+        {
+            let s = String::deserialize(deserializer)?;
+            FromStr::from_str(&s).map_err(de::Error::custom)
+        }
+    }
 
-    impl Color for Green {
-        #[inline]
-        fn color<W: Write>(w: &mut W) -> fmt::Result {
-            write!(w, "\x1b[32m")
+    /// Colors from the config that replace the defaults
+    #[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Palette {
+        pub red: Option<ColorCode>,
+        pub green: Option<ColorCode>,
+        pub yellow: Option<ColorCode>,
+        pub blue: Option<ColorCode>,
+        pub grey: Option<ColorCode>,
+    }
+
+    impl Palette {
+        pub(crate) fn code(&self, style: Style) -> ColorCode {
+            let (custom, default) = match style {
+                Style::Red => (self.red, 31),
+                Style::Green => (self.green, 32),
+                Style::Yellow => (self.yellow, 33),
+                Style::Blue => (self.blue, 34),
+                Style::Grey => (self.grey, 90),
+                Style::Bold => (None, 1),
+            };
+            custom.unwrap_or(ColorCode::Ansi(default))
         }
+    }
 
-        #[inline]
-        fn display<W: Write, D: fmt::Display>(w: &mut W, v: D) -> fmt::Result {
-            write!(w, "\x1b[32m{}\x1b[0m", v)
+    #[derive(Debug)]
+    struct Settings {
+        enabled: bool,
+        palette: Palette,
+    }
+
+    lazy_static! {
+        static ref SETTINGS: RwLock<Settings> = RwLock::new(Settings {
+            enabled: ColorChoice::Auto.enabled(),
+            palette: Palette::default(),
+        });
+    }
+
+    /// Apply the `--color` flag, this also covers everything that's printed
+    /// with the colored crate
+    pub fn configure(choice: ColorChoice) {
+        let enabled = choice.enabled();
+        colored::control::set_override(enabled);
+        SETTINGS.write().unwrap().enabled = enabled;
+    }
+
+    pub fn set_palette(palette: &Palette) {
+        SETTINGS.write().unwrap().palette = palette.clone();
+    }
+
+    #[inline]
+    pub fn enabled() -> bool {
+        SETTINGS.read().unwrap().enabled
+    }
+
+    /// The escape code parameters of a style, `None` if colors are disabled
+    pub fn code(style: Style) -> Option<ColorCode> {
+        let settings = SETTINGS.read().unwrap();
+        if settings.enabled {
+            Some(settings.palette.code(style))
+        } else {
+            None
         }
+    }
 
-        #[inline]
-        fn debug<W: Write, D: fmt::Debug>(w: &mut W, v: D) -> fmt::Result {
-            write!(w, "\x1b[32m{:?}\x1b[0m", v)
+    #[inline]
+    pub fn reset<W: Write>(w: &mut W) -> fmt::Result {
+        if enabled() {
+            write!(w, "\x1b[0m")
+        } else {
+            Ok(())
         }
     }
 
-    pub struct Yellow;
+    /// A value that is displayed in a style
+    pub struct Painted<D>(Style, D);
+
+    impl<D: fmt::Display> fmt::Display for Painted<D> {
+        fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+            match code(self.0) {
+                Some(code) => write!(w, "\x1b[{}m{}\x1b[0m", code, self.1),
+                None => write!(w, "{}", self.1),
+            }
+        }
+    }
+
+    #[inline]
+    pub fn paint<D: fmt::Display>(style: Style, v: D) -> Painted<D> {
+        Painted(style, v)
+    }
+
+    pub trait Color {
+        const STYLE: Style;
 
-    impl Color for Yellow {
-        #[inline]
         fn color<W: Write>(w: &mut W) -> fmt::Result {
-            write!(w, "\x1b[33m")
+            match code(Self::STYLE) {
+                Some(code) => write!(w, "\x1b[{}m", code),
+                None => Ok(()),
+            }
         }
 
-        #[inline]
         fn display<W: Write, D: fmt::Display>(w: &mut W, v: D) -> fmt::Result {
-            write!(w, "\x1b[33m{}\x1b[0m", v)
+            write!(w, "{}", paint(Self::STYLE, v))
         }
 
-        #[inline]
         fn debug<W: Write, D: fmt::Debug>(w: &mut W, v: D) -> fmt::Result {
-            write!(w, "\x1b[33m{:?}\x1b[0m", v)
+            match code(Self::STYLE) {
+                Some(code) => write!(w, "\x1b[{}m{:?}\x1b[0m", code, v),
+                None => write!(w, "{:?}", v),
+            }
         }
     }
+
+    pub struct Red;
+
+    impl Color for Red {
+        const STYLE: Style = Style::Red;
+    }
+
+    pub struct Green;
+
+    impl Color for Green {
+        const STYLE: Style = Style::Green;
+    }
+
+    pub struct Yellow;
+
+    impl Color for Yellow {
+        const STYLE: Style = Style::Yellow;
+    }
+
+    pub struct Grey;
+
+    impl Color for Grey {
+        const STYLE: Style = Style::Grey;
+    }
 }
 use self::colors::*;
 
@@ -75,15 +269,19 @@ use self::colors::*;
 pub struct DetailFormatter<'a, 'b> {
     w: &'a mut fmt::Formatter<'b>,
     scoped: bool,
+    /// Set by formatting with `{:#}`, hides the database ids
+    no_ids: bool,
     in_group: bool,
     fresh_group: bool,
 }
 
 impl<'a, 'b> DetailFormatter<'a, 'b> {
     pub fn new(w: &'a mut fmt::Formatter<'b>, scoped: bool) -> DetailFormatter<'a, 'b> {
+        let no_ids = w.alternate();
         DetailFormatter {
             w,
             scoped,
+            no_ids,
             in_group: false,
             fresh_group: true,
         }
@@ -107,7 +305,7 @@ impl<'a, 'b> DetailFormatter<'a, 'b> {
     #[inline]
     pub fn start(&mut self) -> fmt::Result {
         if !self.scoped {
-            write!(self, "\x1b[90m")
+            Grey::color(self)
         } else {
             Ok(())
         }
@@ -116,7 +314,7 @@ impl<'a, 'b> DetailFormatter<'a, 'b> {
     #[inline]
     pub fn end(&mut self) -> fmt::Result {
         if !self.scoped {
-            write!(self, "\x1b[0m")
+            colors::reset(self)
         } else {
             Ok(())
         }
@@ -124,8 +322,10 @@ impl<'a, 'b> DetailFormatter<'a, 'b> {
 
     #[inline]
     pub fn id<D: fmt::Display>(&mut self, v: D) -> fmt::Result {
-        if self.scoped {
-            write!(self, "\x1b[32m#{}\x1b[0m, ", v)
+        if self.no_ids {
+            Ok(())
+        } else if self.scoped {
+            write!(self, "{}, ", paint(Style::Green, format_args!("#{}", v)))
         } else {
             write!(self, "#{}, ", v)
         }
@@ -195,7 +395,7 @@ impl<'a, 'b> DetailFormatter<'a, 'b> {
     #[inline]
     pub fn clear(&mut self) -> fmt::Result {
         if self.scoped {
-            write!(self, "\x1b[0m")
+            colors::reset(self)
         } else {
             Ok(())
         }
@@ -217,9 +417,9 @@ impl<'a, 'b> DetailFormatter<'a, 'b> {
     pub fn child<D: fmt::Display>(&mut self, c: D) -> fmt::Result {
         if self.scoped {
             // if child is unscoped, draw as grey as well
-            write!(self, "\n\t\x1b[33m{}\x1b[0m", c)
+            write!(self, "\n\t{}", paint(Style::Yellow, c))
         } else {
-            write!(self, "\n\t\x1b[90m{}\x1b[0m", c)
+            write!(self, "\n\t{}", paint(Style::Grey, c))
         }
     }
 }
@@ -229,3 +429,47 @@ impl<'a, 'b> fmt::Write for DetailFormatter<'a, 'b> {
         self.w.write_str(s)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Row;
+
+    impl fmt::Display for Row {
+        fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+            let mut w = DetailFormatter::new(w, false);
+            w.id(1337)?;
+            w.debug::<Green, _>("example.com")
+        }
+    }
+
+    #[test]
+    fn test_show_ids() {
+        assert_eq!(format!("{}", Row), "#1337, \"example.com\"");
+    }
+
+    #[test]
+    fn test_hide_ids() {
+        assert_eq!(format!("{:#}", Row), "\"example.com\"");
+    }
+
+    #[test]
+    fn test_color_code() {
+        assert_eq!("bright-blue".parse::<ColorCode>().unwrap(), ColorCode::Ansi(94));
+        assert_eq!("208".parse::<ColorCode>().unwrap().to_string(), "38;5;208");
+        assert!("purple".parse::<ColorCode>().is_err());
+        assert!("256".parse::<ColorCode>().is_err());
+    }
+
+    #[test]
+    fn test_palette() {
+        let palette: Palette = toml::from_str(r#"
+            green = "blue"
+            red = "214"
+        "#).unwrap();
+        assert_eq!(palette.code(Style::Green), ColorCode::Ansi(34));
+        assert_eq!(palette.code(Style::Red), ColorCode::Fixed(214));
+        assert_eq!(palette.code(Style::Yellow), ColorCode::Ansi(33));
+    }
+}
@@ -0,0 +1,432 @@
+use crate::errors::*;
+use crate::paths;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+/// Prefix of the authors that modules from git are installed with
+pub const GIT_NAMESPACE: &str = "git-";
+
+/// A repository to install modules from, written as
+/// `git+https://github.com/org/repo[#ref][:path]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct GitSource {
+    pub url: String,
+    pub reference: Option<String>,
+    pub path: Option<String>,
+}
+
+impl FromStr for GitSource {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<GitSource> {
+        let s = s.strip_prefix("git+")
+            .ok_or_else(|| format_err!("Git sources need to start with git+"))?;
+
+        let (url, fragment) = match s.split_once('#') {
+            Some((url, fragment)) => (url, Some(fragment)),
+            None => (s, None),
+        };
+
+        let (url, reference, path) = match fragment {
+            Some(fragment) => {
+                let (reference, path) = match fragment.split_once(':') {
+                    Some((reference, path)) => (reference, Some(path)),
+                    None => (fragment, None),
+                };
+                (url, Some(reference), path)
+            },
+            None => {
+                // a colon after the host separates the path, a colon before
+                // it is part of the url, like the port or the scheme
+                let (scheme, rest) = url.split_once("://")
+                    .ok_or_else(|| format_err!("Git url is missing a scheme: {:?}", url))?;
+                let host_end = rest.find('/').unwrap_or(rest.len());
+                match rest.rfind(':') {
+                    Some(idx) if idx > host_end => {
+                        let split = scheme.len() + 3 + idx;
+                        (&url[..split], None, Some(&url[split + 1..]))
+                    },
+                    _ => (url, None, None),
+                }
+            },
+        };
+
+        if !url.contains("://") {
+            bail!("Git url is missing a scheme: {:?}", url);
+        }
+
+        let reference = reference.filter(|x| !x.is_empty()).map(String::from);
+        let path = path.filter(|x| !x.is_empty()).map(String::from);
+
+        if let Some(path) = &path {
+            if Path::new(path).components().any(|c| !matches!(c, std::path::Component::Normal(_))) {
+                bail!("Path in git repository must be relative: {:?}", path);
+            }
+        }
+
+        Ok(GitSource {
+            url: url.to_string(),
+            reference,
+            path,
+        })
+    }
+}
+
+impl fmt::Display for GitSource {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(w, "git+{}", self.url)?;
+        if let Some(reference) = &self.reference {
+            write!(w, "#{}", reference)?;
+        }
+        if let Some(path) = &self.path {
+            if self.reference.is_none() {
+                write!(w, "#")?;
+            }
+            write!(w, ":{}", path)?;
+        }
+        Ok(())
+    }
+}
+
+/// A module file that has been read from a checkout
+#[derive(Debug)]
+pub struct GitModule {
+    pub name: String,
+    pub file: String,
+    pub code: String,
+}
+
+impl GitSource {
+    /// The namespace the modules are installed into, this is derived from
+    /// the host and the owner of the repository, like `git-github-com-org`.
+    /// Registry modules can't use the prefix, so a repository can't shadow
+    /// them
+    pub fn author(&self) -> Result<String> {
+        let url = self.url.trim_end_matches('/');
+        let (_, rest) = url.split_once("://")
+            .ok_or_else(|| format_err!("Git url is missing a scheme: {:?}", self.url))?;
+        let authority = rest.split('/').next().unwrap_or("");
+        let host = authority.rsplit('@').next().unwrap_or("");
+
+        let mut segments = url.rsplit(['/', ':']);
+        segments.next();
+        let owner = segments.next()
+            .map(|x| x.trim_start_matches('~'))
+            .filter(|x| *x != authority)
+            .ok_or_else(|| format_err!("Failed to detect owner of repository: {:?}", self.url))?;
+        if !valid_name(owner) {
+            bail!("Owner of repository is not a valid author name: {:?}", owner);
+        }
+
+        let host = host.chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+            .collect::<String>();
+        Ok(format!("{}{}-{}", GIT_NAMESPACE, host, owner))
+    }
+
+    fn checkout_dir(&self) -> Result<PathBuf> {
+        let name = self.url.chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect::<String>();
+        Ok(paths::cache_dir()?.join("git").join(name))
+    }
+
+    /// Fetch the reference into the cache and check it out, returns the
+    /// directory and the commit
+    pub fn checkout(&self) -> Result<(PathBuf, String)> {
+        let dir = self.checkout_dir()?;
+        let commit = self.checkout_into(&dir)?;
+        Ok((dir, commit))
+    }
+
+    fn checkout_into(&self, dir: &Path) -> Result<String> {
+        if !dir.join(".git").exists() {
+            fs::create_dir_all(dir)
+                .context("Failed to create checkout directory")?;
+            git(dir, &["init", "-q"])?;
+            git(dir, &["remote", "add", "--", "origin", &self.url])?;
+        }
+
+        let reference = self.reference.as_deref().unwrap_or("HEAD");
+        if reference.starts_with('-') {
+            bail!("Invalid git reference: {:?}", reference);
+        }
+        git(dir, &["fetch", "-q", "--depth", "1", "--", "origin", reference])
+            .context(format_err!("Failed to fetch {:?} from {:?}", reference, self.url))?;
+        git(dir, &["checkout", "-q", "-f", "--detach", "FETCH_HEAD", "--"])?;
+        git(dir, &["rev-parse", "HEAD"])
+    }
+
+    /// Read the module at the path, or every module in the folder if the
+    /// path isn't a lua file
+    pub fn modules(&self, dir: &Path) -> Result<Vec<GitModule>> {
+        let path = self.path.as_deref().unwrap_or("");
+
+        let files = if path.ends_with(".lua") {
+            vec![path.to_string()]
+        } else {
+            let mut files = Vec::new();
+            let folder = dir.join(path);
+            for entry in fs::read_dir(&folder)
+                .context(format_err!("Failed to read folder in repository: {:?}", path))?
+            {
+                let entry = entry?;
+                let name = entry.file_name();
+                let name = name.to_str()
+                    .ok_or_else(|| format_err!("Filename is invalid utf8"))?;
+                if name.ends_with(".lua") && entry.file_type()?.is_file() {
+                    files.push(Path::new(path).join(name).to_string_lossy().into_owned());
+                }
+            }
+            files.sort();
+            files
+        };
+
+        if files.is_empty() {
+            bail!("No modules found in {}", self);
+        }
+
+        files.into_iter()
+            .map(|file| read_module(dir, file))
+            .collect()
+    }
+
+    /// Read a single module from the checkout, used by updates
+    pub fn module(&self, dir: &Path, file: &str) -> Result<GitModule> {
+        read_module(dir, file.to_string())
+    }
+}
+
+/// Same characters as in a module id, the repository controls these names
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+fn read_module(dir: &Path, file: String) -> Result<GitModule> {
+    let name = Path::new(&file).file_stem()
+        .and_then(|x| x.to_str())
+        .ok_or_else(|| format_err!("Invalid module path: {:?}", file))?
+        .to_string();
+    if !valid_name(&name) {
+        bail!("Module has an invalid name: {:?}", file);
+    }
+
+    let code = fs::read_to_string(dir.join(&file))
+        .context(format_err!("Failed to read module {:?}", file))?;
+
+    Ok(GitModule {
+        name,
+        file,
+        code,
+    })
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    debug!("Running git {:?} in {:?}", args, dir);
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .context("Failed to execute git")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("git {} failed: {}", args[0], stderr.trim());
+    }
+
+    let stdout = String::from_utf8(output.stdout)
+        .context("git returned invalid utf8")?;
+    Ok(stdout.trim().to_string())
+}
+
+/// Where a module has been installed from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GitOrigin {
+    pub url: String,
+    pub reference: Option<String>,
+    /// The path of the module inside the repository
+    pub file: String,
+    pub commit: String,
+}
+
+impl GitOrigin {
+    pub fn source(&self) -> GitSource {
+        GitSource {
+            url: self.url.clone(),
+            reference: self.reference.clone(),
+            path: Some(self.file.clone()),
+        }
+    }
+
+    pub fn short_commit(&self) -> &str {
+        self.commit.get(..7).unwrap_or(&self.commit)
+    }
+}
+
+impl fmt::Display for GitOrigin {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(w, "{} ({})", self.source(), self.short_commit())
+    }
+}
+
+/// Modules that have been installed from git, by canonical name
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct GitOrigins {
+    #[serde(default)]
+    modules: HashMap<String, GitOrigin>,
+}
+
+impl GitOrigins {
+    fn path() -> Result<PathBuf> {
+        let path = paths::sn0int_dir()?;
+        Ok(path.join("git-modules.json"))
+    }
+
+    pub fn load() -> Result<GitOrigins> {
+        let path = GitOrigins::path()?;
+        if !path.exists() {
+            return Ok(GitOrigins::default());
+        }
+
+        let data = fs::read(&path)
+            .context("Failed to read git module origins")?;
+        let origins = serde_json::from_slice(&data)
+            .context("Failed to parse git module origins")?;
+        Ok(origins)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let data = serde_json::to_string(&self)?;
+        fs::write(GitOrigins::path()?, data)
+            .context("Failed to write git module origins")?;
+        Ok(())
+    }
+
+    pub fn get(&self, canonical: &str) -> Option<&GitOrigin> {
+        self.modules.get(canonical)
+    }
+
+    pub fn insert(&mut self, canonical: String, origin: GitOrigin) {
+        self.modules.insert(canonical, origin);
+    }
+
+    pub fn remove(&mut self, canonical: &str) {
+        self.modules.remove(canonical);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(url: &str, reference: Option<&str>, path: Option<&str>) -> GitSource {
+        GitSource {
+            url: url.to_string(),
+            reference: reference.map(String::from),
+            path: path.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let x = "git+https://github.com/org/repo".parse::<GitSource>().unwrap();
+        assert_eq!(x, source("https://github.com/org/repo", None, None));
+        let x = "git+https://github.com/org/repo#v1.0".parse::<GitSource>().unwrap();
+        assert_eq!(x, source("https://github.com/org/repo", Some("v1.0"), None));
+        let x = "git+https://github.com/org/repo#main:modules/foo.lua".parse::<GitSource>().unwrap();
+        assert_eq!(x, source("https://github.com/org/repo", Some("main"), Some("modules/foo.lua")));
+        let x = "git+https://github.com/org/repo:modules".parse::<GitSource>().unwrap();
+        assert_eq!(x, source("https://github.com/org/repo", None, Some("modules")));
+        let x = "git+https://git.example.com:8443/org/repo".parse::<GitSource>().unwrap();
+        assert_eq!(x, source("https://git.example.com:8443/org/repo", None, None));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!("https://github.com/org/repo".parse::<GitSource>().is_err());
+        assert!("git+github.com/org/repo".parse::<GitSource>().is_err());
+        assert!("git+https://github.com/org/repo#main:../foo.lua".parse::<GitSource>().is_err());
+        assert!("git+https://github.com/org/repo#main:/etc/foo.lua".parse::<GitSource>().is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        for x in &[
+            "git+https://github.com/org/repo",
+            "git+https://github.com/org/repo#v1.0",
+            "git+https://github.com/org/repo#main:modules/foo.lua",
+        ] {
+            assert_eq!(x.parse::<GitSource>().unwrap().to_string(), *x);
+        }
+        let x = "git+https://github.com/org/repo:modules".parse::<GitSource>().unwrap();
+        assert_eq!(x.to_string(), "git+https://github.com/org/repo#:modules");
+    }
+
+    #[test]
+    fn test_author() {
+        assert_eq!(source("https://github.com/org/repo", None, None).author().unwrap(), "git-github-com-org");
+        assert_eq!(source("https://github.com/org/repo.git/", None, None).author().unwrap(), "git-github-com-org");
+        assert_eq!(source("ssh://git@example.com/~kpcyrd/modules", None, None).author().unwrap(), "git-example-com-kpcyrd");
+        assert_eq!(source("https://Git.example.com:8443/org/repo", None, None).author().unwrap(), "git-git-example-com-8443-org");
+        assert!(source("https://example.com/o.r.g/repo", None, None).author().is_err());
+        assert!(source("https://example.com/repo", None, None).author().is_err());
+    }
+
+    fn commit(repo: &Path, file: &str, code: &str) -> String {
+        fs::write(repo.join(file), code).unwrap();
+        git(repo, &["add", "--", file]).unwrap();
+        git(repo, &["-c", "user.name=sn0int", "-c", "user.email=sn0int@example.com",
+                    "commit", "-q", "-m", file]).unwrap();
+        git(repo, &["rev-parse", "HEAD"]).unwrap()
+    }
+
+    fn repo() -> (tempfile::TempDir, String) {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path().join("repo");
+        fs::create_dir(&repo).unwrap();
+        git(&repo, &["init", "-q"]).unwrap();
+        let url = format!("file://{}", repo.display());
+        (dir, url)
+    }
+
+    #[test]
+    fn test_checkout_and_fetch() {
+        let (dir, url) = repo();
+        let repo = dir.path().join("repo");
+        let first = commit(&repo, "foo.lua", "-- foo 1");
+        git(&repo, &["tag", "v1"]).unwrap();
+        let second = commit(&repo, "foo.lua", "-- foo 2");
+
+        let checkout = dir.path().join("checkout");
+        let commit = source(&url, None, None).checkout_into(&checkout).unwrap();
+        assert_eq!(commit, second);
+        assert_eq!(fs::read_to_string(checkout.join("foo.lua")).unwrap(), "-- foo 2");
+
+        // fetching again reuses the checkout and moves to the reference
+        let src = source(&url, Some("v1"), Some("foo.lua"));
+        let commit = src.checkout_into(&checkout).unwrap();
+        assert_eq!(commit, first);
+        let module = src.module(&checkout, "foo.lua").unwrap();
+        assert_eq!(module.name, "foo");
+        assert_eq!(module.code, "-- foo 1");
+    }
+
+    #[test]
+    fn test_checkout_rejects_options() {
+        let (dir, url) = repo();
+        commit(&dir.path().join("repo"), "foo.lua", "-- foo");
+        let marker = dir.path().join("pwned");
+        let reference = format!("--upload-pack=touch {}", marker.display());
+
+        let checkout = dir.path().join("checkout");
+        let err = source(&url, Some(&reference), None).checkout_into(&checkout).unwrap_err();
+        assert!(err.to_string().contains("Invalid git reference"));
+        assert!(!marker.exists());
+    }
+}
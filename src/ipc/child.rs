@@ -1,34 +1,55 @@
 use crate::errors::*;
 use crate::ipc::common::*;
 use crate::engine::Environment;
+use crate::error_info::ErrorInfo;
 use crate::geoip::MaxmindReader;
+use crate::ipc::network::{self, Broker, Connected, NetworkEvent, ResolveRequest, BROKER_FD};
 use crate::psl::PslReader;
+use crate::sockets::{ConnectRequest, Connection, Datagram};
 use crate::worker::Event;
+use chrootable_https::dns::DnsReply;
 use std::fmt::Debug;
 use std::io::prelude::*;
 use std::io::{self, Stdin, Stdout};
+use std::net::{TcpStream, UdpSocket};
+use std::os::unix::io::FromRawFd;
+use std::os::unix::net::UnixStream;
+use std::result;
 use std::sync::{Arc, Mutex};
 
 pub trait IpcChild: Debug {
     fn send(&mut self, event: &Event) -> Result<()>;
 
     fn recv(&mut self) -> Result<serde_json::Value>;
+
+    /// Ask the parent to open a connection
+    fn connect(&mut self, req: &ConnectRequest) -> Result<Connection>;
+
+    /// Ask the parent to open a udp socket
+    fn connect_udp(&mut self, req: &ConnectRequest) -> Result<Datagram>;
+
+    /// Ask the parent to send a dns query
+    fn resolve(&mut self, req: &ResolveRequest) -> Result<DnsReply>;
 }
 
 #[derive(Debug)]
 pub struct StdioIpcChild {
     stdin: Stdin,
     stdout: Stdout,
+    broker: UnixStream,
 }
 
 impl StdioIpcChild {
     pub fn setup() -> StdioIpcChild {
         let stdin = io::stdin();
         let stdout = io::stdout();
+        // inherited from the parent, see IpcParent::setup
+        let broker = unsafe { UnixStream::from_raw_fd(BROKER_FD) };
 
         StdioIpcChild {
             stdin,
             stdout,
+            broker,
         }
     }
 
@@ -56,14 +77,56 @@ impl IpcChild for StdioIpcChild {
         debug!("IpcChild received: {:?}", event);
         Ok(event)
     }
+
+    fn connect(&mut self, req: &ConnectRequest) -> Result<Connection> {
+        self.send(&Event::Network(NetworkEvent::Connect(req.clone())))?;
+        let reply = self.recv()?;
+        let reply: result::Result<Connected, ErrorInfo> = serde_json::from_value(reply)?;
+        let connected = reply.map_err(ErrorInfo::into_error)?;
+
+        let fd = network::recv_fd(&self.broker)?;
+        let stream = unsafe { TcpStream::from_raw_fd(fd) };
+        Ok(Connection {
+            stream,
+            addr: connected.addr,
+        })
+    }
+
+    fn connect_udp(&mut self, req: &ConnectRequest) -> Result<Datagram> {
+        self.send(&Event::Network(NetworkEvent::ConnectUdp(req.clone())))?;
+        let reply = self.recv()?;
+        let reply: result::Result<Connected, ErrorInfo> = serde_json::from_value(reply)?;
+        let connected = reply.map_err(ErrorInfo::into_error)?;
+
+        let fd = network::recv_fd(&self.broker)?;
+        let socket = unsafe { UdpSocket::from_raw_fd(fd) };
+        let addr = connected.addr
+            .ok_or_else(|| format_err!("Parent didn't send the address of the udp socket"))?;
+        Ok(Datagram {
+            socket,
+            addr,
+        })
+    }
+
+    fn resolve(&mut self, req: &ResolveRequest) -> Result<DnsReply> {
+        self.send(&Event::Network(NetworkEvent::Resolve(req.clone())))?;
+        let reply = self.recv()?;
+        let reply: result::Result<DnsReply, ErrorInfo> = serde_json::from_value(reply)?;
+        reply.map_err(ErrorInfo::into_error)
+    }
 }
 
+/// Used if there's no sandbox child, connections are opened in-process
 #[derive(Debug)]
-pub struct DummyIpcChild;
+pub struct DummyIpcChild {
+    broker: Broker,
+}
 
 impl DummyIpcChild {
-    pub fn create() -> Arc<Mutex<Box<dyn IpcChild>>> {
-        Arc::new(Mutex::new(Box::new(DummyIpcChild)))
+    pub fn create(broker: Broker) -> Arc<Mutex<Box<dyn IpcChild>>> {
+        Arc::new(Mutex::new(Box::new(DummyIpcChild {
+            broker,
+        })))
     }
 }
 
@@ -75,6 +138,18 @@ impl IpcChild for DummyIpcChild {
     fn recv(&mut self) -> Result<serde_json::Value> {
         unimplemented!("DummyIpcChild::recv doesn't exist")
     }
+
+    fn connect(&mut self, req: &ConnectRequest) -> Result<Connection> {
+        self.broker.connect(req)
+    }
+
+    fn connect_udp(&mut self, req: &ConnectRequest) -> Result<Datagram> {
+        self.broker.connect_udp(req)
+    }
+
+    fn resolve(&mut self, req: &ResolveRequest) -> Result<DnsReply> {
+        self.broker.resolve(req)
+    }
 }
 
 pub fn run(geoip: Option<MaxmindReader>, asn: Option<MaxmindReader>, psl: PslReader) -> Result<()> {
@@ -84,11 +159,15 @@ pub fn run(geoip: Option<MaxmindReader>, asn: Option<MaxmindReader>, psl: PslRea
     let environment = Environment {
         verbose: start.verbose,
         keyring: start.keyring,
-        dns_config: start.dns_config,
-        proxy: start.proxy,
         user_agent: start.user_agent,
         options: start.options,
         blobs: start.blobs,
+        memory_limit: start.memory_limit,
+        timeout: start.timeout,
+        capabilities: start.capabilities,
+        audit: start.audit,
+        concurrency: start.concurrency,
+        module: Some(start.module.info()),
         psl,
         geoip,
         asn,
@@ -97,6 +176,7 @@ pub fn run(geoip: Option<MaxmindReader>, asn: Option<MaxmindReader>, psl: PslRea
     let mtx: Arc<Mutex<Box<dyn IpcChild>>> = Arc::new(Mutex::new(Box::new(ipc_child)));
     let result = start.module.run(environment,
                                   mtx.clone(),
+                                  start.entrypoint,
                                   start.arg.into());
     let mut ipc_child = Arc::try_unwrap(mtx).expect("Failed to consume Arc")
                         .into_inner().expect("Failed to consume Mutex");
@@ -1,45 +1,62 @@
-use chrootable_https::dns::Resolver;
 use crate::blobs::Blob;
-use crate::engine::Module;
+use crate::engine::{Entrypoint, Module};
 use crate::keyring::KeyRingEntry;
+use sn0int_common::metadata::Capability;
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::time::Duration;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct StartCommand {
     pub verbose: u8,
     pub keyring: Vec<KeyRingEntry>,
-    pub dns_config: Resolver,
-    pub proxy: Option<SocketAddr>,
     pub user_agent: Option<String>,
     pub options: HashMap<String, String>,
     pub module: Module,
+    #[serde(default)]
+    pub entrypoint: Entrypoint,
     pub arg: serde_json::Value,
     pub blobs: Vec<Blob>,
+    pub memory_limit: Option<usize>,
+    /// The time the module may spend on this source
+    #[serde(default)]
+    pub timeout: Option<Duration>,
+    pub capabilities: Option<Vec<Capability>>,
+    pub audit: bool,
+    /// Ask the parent for a slot before network operations
+    #[serde(default)]
+    pub concurrency: bool,
 }
 
 impl StartCommand {
     pub fn new(verbose: u8,
                keyring: Vec<KeyRingEntry>,
-               dns_config: Resolver,
-               proxy: Option<SocketAddr>,
                user_agent: Option<String>,
                options: HashMap<String, String>,
                module: Module,
+               entrypoint: Entrypoint,
                arg: serde_json::Value,
                blobs: Vec<Blob>,
+               memory_limit: Option<usize>,
+               timeout: Option<Duration>,
+               capabilities: Option<Vec<Capability>>,
+               audit: bool,
+               concurrency: bool,
     ) -> StartCommand {
         StartCommand {
             verbose,
             keyring,
-            dns_config,
-            proxy,
             user_agent,
             options,
             module,
+            entrypoint,
             arg,
             blobs,
+            memory_limit,
+            timeout,
+            capabilities,
+            audit,
+            concurrency,
         }
     }
 }
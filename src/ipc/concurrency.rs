@@ -0,0 +1,157 @@
+use crate::ipc::parent::IpcParent;
+use crate::worker::EventSender;
+use serde::{Serialize, Deserialize};
+use std::sync::{Condvar, Mutex};
+
+/// The concurrency budget of a run
+///
+/// Every dns lookup, http request and connection of every child takes a slot
+/// and waits until one is returned if the budget is used up.
+#[derive(Debug)]
+pub struct ConcurrencyLimit {
+    limit: usize,
+    used: Mutex<usize>,
+    free: Condvar,
+}
+
+impl ConcurrencyLimit {
+    pub fn new(limit: usize) -> ConcurrencyLimit {
+        ConcurrencyLimit {
+            limit: limit.max(1),
+            used: Mutex::new(0),
+            free: Condvar::new(),
+        }
+    }
+
+    pub fn acquire(&self) {
+        let mut used = self.used.lock().unwrap();
+        while *used >= self.limit {
+            used = self.free.wait(used).unwrap();
+        }
+        *used += 1;
+    }
+
+    pub fn release(&self) {
+        let mut used = self.used.lock().unwrap();
+        *used = used.saturating_sub(1);
+        self.free.notify_one();
+    }
+
+    #[cfg(test)]
+    fn used(&self) -> usize {
+        *self.used.lock().unwrap()
+    }
+}
+
+/// The slots that are held by a single child, they're returned if the child
+/// exits before releasing them
+pub struct HeldSlots<'a> {
+    limit: &'a ConcurrencyLimit,
+    held: usize,
+}
+
+impl<'a> HeldSlots<'a> {
+    pub fn new(limit: &'a ConcurrencyLimit) -> HeldSlots<'a> {
+        HeldSlots {
+            limit,
+            held: 0,
+        }
+    }
+
+    fn acquire(&mut self) {
+        self.limit.acquire();
+        self.held += 1;
+    }
+
+    fn release(&mut self) {
+        if self.held > 0 {
+            self.held -= 1;
+            self.limit.release();
+        }
+    }
+}
+
+impl Drop for HeldSlots<'_> {
+    fn drop(&mut self) {
+        for _ in 0..self.held {
+            self.limit.release();
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ConcurrencyEvent {
+    Acquire,
+    Release,
+}
+
+impl ConcurrencyEvent {
+    pub fn apply(self, ipc_parent: &mut IpcParent, tx: &EventSender, held: Option<&mut HeldSlots>) {
+        match self {
+            ConcurrencyEvent::Acquire => {
+                if let Some(held) = held {
+                    held.acquire();
+                }
+                ipc_parent.send_struct(Ok::<(), String>(()), tx);
+            },
+            // the child doesn't wait for a reply
+            ConcurrencyEvent::Release => if let Some(held) = held {
+                held.release();
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_acquire_waits_for_release() {
+        let limit = Arc::new(ConcurrencyLimit::new(1));
+        limit.acquire();
+
+        let acquired = Arc::new(AtomicBool::new(false));
+        let t = {
+            let limit = limit.clone();
+            let acquired = acquired.clone();
+            thread::spawn(move || {
+                limit.acquire();
+                acquired.store(true, Ordering::SeqCst);
+            })
+        };
+
+        thread::sleep(Duration::from_millis(100));
+        assert!(!acquired.load(Ordering::SeqCst));
+
+        limit.release();
+        t.join().unwrap();
+        assert!(acquired.load(Ordering::SeqCst));
+        assert_eq!(limit.used(), 1);
+    }
+
+    #[test]
+    fn test_held_slots_returned_on_drop() {
+        let limit = ConcurrencyLimit::new(4);
+        {
+            let mut held = HeldSlots::new(&limit);
+            held.acquire();
+            held.acquire();
+            held.acquire();
+            held.release();
+            assert_eq!(limit.used(), 2);
+        }
+        assert_eq!(limit.used(), 0);
+
+        // releasing more than was acquired doesn't free slots of other children
+        let mut other = HeldSlots::new(&limit);
+        other.acquire();
+        let mut held = HeldSlots::new(&limit);
+        held.release();
+        assert_eq!(limit.used(), 1);
+    }
+}
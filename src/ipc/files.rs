@@ -0,0 +1,216 @@
+use crate::compression;
+use crate::errors::*;
+use crate::ipc::parent::IpcParent;
+use crate::worker::EventSender;
+use serde::{Serialize, Deserialize};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// The largest file, after decompression, that is passed to a module
+const MAX_READ_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Files and folders the user allowed a module to read with --allow-read
+///
+/// The child never touches the filesystem itself, the parent resolves the
+/// requested path and only returns its content if it's covered by a grant.
+#[derive(Debug, Clone, Default)]
+pub struct ReadGrants {
+    paths: Vec<PathBuf>,
+}
+
+fn expand_home(path: &Path) -> Result<PathBuf> {
+    if let Ok(path) = path.strip_prefix("~") {
+        let home = dirs_next::home_dir()
+            .ok_or_else(|| format_err!("Failed to find home folder"))?;
+        Ok(home.join(path))
+    } else {
+        Ok(path.to_owned())
+    }
+}
+
+impl ReadGrants {
+    pub fn new(paths: &[PathBuf]) -> Result<ReadGrants> {
+        let paths = paths.iter()
+            .map(|path| {
+                let path = expand_home(path)?;
+                let path = fs::canonicalize(&path)
+                    .context(format!("Failed to resolve granted path {:?}", path))?;
+                Ok(path)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ReadGrants {
+            paths,
+        })
+    }
+
+    /// Resolve symlinks and `..` before checking the path against the
+    /// grants, so a link inside a granted folder can't point outside of it
+    fn resolve(&self, path: &str) -> Result<PathBuf> {
+        let resolved = expand_home(Path::new(path))?;
+        let resolved = match fs::canonicalize(&resolved) {
+            Ok(resolved) => resolved,
+            Err(_) => return Err(NotFoundError(format!("Path {:?} doesn't exist or wasn't granted to this module", path)).into()),
+        };
+
+        let granted = self.paths.iter()
+            .any(|grant| resolved == *grant || (grant.is_dir() && resolved.starts_with(grant)));
+
+        if granted {
+            Ok(resolved)
+        } else {
+            Err(NotFoundError(format!("Path {:?} doesn't exist or wasn't granted to this module", path)).into())
+        }
+    }
+
+    pub fn read_file(&self, path: &str) -> Result<String> {
+        self.read_file_limited(path, MAX_READ_SIZE)
+    }
+
+    fn read_file_limited(&self, path: &str, limit: u64) -> Result<String> {
+        let resolved = self.resolve(path)?;
+        if resolved.is_dir() {
+            bail!("Path {:?} is a directory", path);
+        }
+
+        // read one byte past the limit to notice files that exceed it
+        let mut content = Vec::new();
+        compression::open(&resolved)?
+            .take(limit + 1)
+            .read_to_end(&mut content)
+            .context(format!("Failed to read {:?}", path))?;
+        if content.len() as u64 > limit {
+            bail!("File {:?} is larger than the limit of {} bytes", path, limit);
+        }
+
+        String::from_utf8(content)
+            .map_err(|_| format_err!("File {:?} is not valid utf-8, binary files can't be read", path))
+    }
+
+    pub fn read_dir(&self, path: &str) -> Result<Vec<String>> {
+        let resolved = self.resolve(path)?;
+        if !resolved.is_dir() {
+            bail!("Path {:?} is not a directory", path);
+        }
+
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&resolved)? {
+            let entry = entry?;
+            let name = entry.file_name()
+                .into_string()
+                .map_err(|_| format_err!("Filename is not valid utf8"))?;
+            entries.push(name);
+        }
+        entries.sort();
+
+        Ok(entries)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum FileEvent {
+    Read(String),
+    ReadDir(String),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum FileResponse {
+    File(String),
+    Dir(Vec<String>),
+}
+
+impl FileEvent {
+    pub fn apply(self, ipc_parent: &mut IpcParent, tx: &EventSender, grants: &ReadGrants) {
+        let reply = match self {
+            FileEvent::Read(path) => grants.read_file(&path).map(FileResponse::File),
+            FileEvent::ReadDir(path) => grants.read_dir(&path).map(FileResponse::Dir),
+        };
+        let reply = reply.map_err(|e| e.to_string());
+        ipc_parent.send_struct(reply, tx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    fn setup() -> (tempfile::TempDir, ReadGrants) {
+        let dir = tempfile::tempdir().unwrap();
+        let granted = dir.path().join("granted");
+        fs::create_dir(&granted).unwrap();
+        fs::write(granted.join("words.txt"), "foo\nbar\n").unwrap();
+        fs::write(dir.path().join("secret.txt"), "hunter2").unwrap();
+        fs::write(dir.path().join("single.txt"), "single").unwrap();
+        symlink(dir.path().join("secret.txt"), granted.join("escape.txt")).unwrap();
+
+        let grants = ReadGrants::new(&[granted, dir.path().join("single.txt")]).unwrap();
+        (dir, grants)
+    }
+
+    fn path(dir: &tempfile::TempDir, path: &str) -> String {
+        dir.path().join(path).to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_read_granted() {
+        let (dir, grants) = setup();
+        assert_eq!(grants.read_file(&path(&dir, "granted/words.txt")).unwrap(), "foo\nbar\n");
+        assert_eq!(grants.read_file(&path(&dir, "single.txt")).unwrap(), "single");
+    }
+
+    #[test]
+    fn test_read_compressed() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("words.txt.zst");
+        fs::write(&file, zstd::encode_all(&b"foo\nbar\n"[..], 3).unwrap()).unwrap();
+
+        let grants = ReadGrants::new(std::slice::from_ref(&file)).unwrap();
+        assert_eq!(grants.read_file(file.to_str().unwrap()).unwrap(), "foo\nbar\n");
+    }
+
+    #[test]
+    fn test_read_limit() {
+        let (dir, grants) = setup();
+        let file = path(&dir, "granted/words.txt");
+        assert_eq!(grants.read_file_limited(&file, 8).unwrap(), "foo\nbar\n");
+        let err = grants.read_file_limited(&file, 7).unwrap_err();
+        assert!(err.to_string().contains("larger than the limit"), "{}", err);
+    }
+
+    #[test]
+    fn test_read_binary() {
+        let (dir, grants) = setup();
+        fs::write(dir.path().join("granted/blob.bin"), b"\xff\xfe\x00").unwrap();
+        let err = grants.read_file(&path(&dir, "granted/blob.bin")).unwrap_err();
+        assert!(err.to_string().contains("not valid utf-8"), "{}", err);
+    }
+
+    #[test]
+    fn test_read_not_granted() {
+        let (dir, grants) = setup();
+        assert!(grants.read_file(&path(&dir, "secret.txt")).is_err());
+        assert!(grants.read_file(&path(&dir, "granted/../secret.txt")).is_err());
+        assert!(grants.read_file(&path(&dir, "granted/missing.txt")).is_err());
+        assert!(grants.read_dir(&path(&dir, "")).is_err());
+    }
+
+    #[test]
+    fn test_symlink_escape() {
+        let (dir, grants) = setup();
+        assert!(grants.read_file(&path(&dir, "granted/escape.txt")).is_err());
+    }
+
+    #[test]
+    fn test_read_dir() {
+        let (dir, grants) = setup();
+        assert_eq!(grants.read_dir(&path(&dir, "granted")).unwrap(), vec!["escape.txt", "words.txt"]);
+        assert!(grants.read_dir(&path(&dir, "single.txt")).is_err());
+        assert!(grants.read_file(&path(&dir, "granted")).is_err());
+    }
+
+    #[test]
+    fn test_missing_grant() {
+        assert!(ReadGrants::new(&[PathBuf::from("/does/not/exist")]).is_err());
+    }
+}
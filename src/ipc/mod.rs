@@ -1,3 +1,6 @@
 pub mod child;
 pub mod common;
+pub mod concurrency;
+pub mod files;
+pub mod network;
 pub mod parent;
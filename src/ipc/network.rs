@@ -0,0 +1,760 @@
+use crate::errors::*;
+use crate::config::NetworkConfig;
+use crate::engine::Module;
+use crate::error_info::{CapabilityError, EgressError, ErrorInfo, OfflineError};
+use crate::ipc::parent::IpcParent;
+use crate::sockets::{self, ConnectRequest, Connection, Datagram};
+use crate::models::InsertNetworkAudit;
+use crate::worker::{DatabaseEvent, EventSender, EventWithCallback, LogEvent};
+use chrootable_https::dns::{DnsError, DnsReply, DnsResolver, RData, RecordType, Resolver};
+use serde::{Serialize, Deserialize};
+use sn0int_common::metadata::EgressRule;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::ptr;
+use std::result;
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// The file descriptor the child receives its connections on
+pub const BROKER_FD: RawFd = 3;
+
+/// Where a module is allowed to connect to
+#[derive(Debug, Clone)]
+pub struct NetworkPolicy {
+    pub egress: Option<Vec<EgressRule>>,
+    pub dns: bool,
+    pub offline: bool,
+    pub proxy: Option<SocketAddr>,
+    /// Everything has to go through this proxy (network.require_proxy)
+    pub required_proxy: Option<SocketAddr>,
+}
+
+impl Default for NetworkPolicy {
+    fn default() -> NetworkPolicy {
+        NetworkPolicy {
+            egress: None,
+            dns: true,
+            offline: false,
+            proxy: None,
+            required_proxy: None,
+        }
+    }
+}
+
+impl NetworkPolicy {
+    pub fn new(module: &Module, proxy: Option<SocketAddr>, network: &NetworkConfig) -> Result<NetworkPolicy> {
+        Ok(NetworkPolicy {
+            egress: module.egress().map(|rules| rules.to_vec()),
+            dns: module.dns(),
+            offline: network.offline,
+            proxy,
+            required_proxy: network.required_proxy()?,
+        })
+    }
+
+    /// Modules may only set a proxy if there's no system proxy
+    fn proxy_for(&self, requested: Option<SocketAddr>) -> Result<Option<SocketAddr>> {
+        match (self.proxy, requested) {
+            (Some(system), Some(requested)) if system == requested => Ok(Some(system)),
+            (Some(_), Some(_)) => bail!("Overriding the system proxy isn't allowed"),
+            (Some(system), None) => Ok(Some(system)),
+            (None, requested) => Ok(requested),
+        }
+    }
+
+    /// A name that's covered by a host rule may resolve to anything
+    fn allows_host(&self, host: &str) -> bool {
+        match &self.egress {
+            Some(rules) => rules.iter().any(|rule| rule.matches_host(host)),
+            None => true,
+        }
+    }
+
+    /// Every address a name resolves to has to be covered, otherwise the
+    /// connection could end up anywhere
+    fn check_addrs(&self, host: &str, addrs: &[IpAddr]) -> Result<()> {
+        let rules = match &self.egress {
+            Some(rules) => rules,
+            None => return Ok(()),
+        };
+
+        if rules.iter().any(|rule| rule.matches_host(host)) {
+            return Ok(());
+        }
+
+        if !addrs.is_empty() && addrs.iter().all(|addr| rules.iter().any(|rule| rule.matches_addr(addr))) {
+            Ok(())
+        } else {
+            Err(EgressError { host: host.to_string() }.into())
+        }
+    }
+}
+
+/// Opens connections on behalf of the child
+///
+/// The child can't be trusted to enforce its own egress policy, so it asks
+/// the parent instead. The parent resolves the name once, checks every
+/// address and passes the connected socket to the child, so the address that
+/// has been checked is the one that's used.
+#[derive(Debug)]
+pub struct Broker {
+    policy: NetworkPolicy,
+    resolver: Resolver,
+    audit: bool,
+}
+
+impl Broker {
+    pub fn new(policy: NetworkPolicy, resolver: Resolver) -> Broker {
+        Broker {
+            policy,
+            resolver,
+            audit: false,
+        }
+    }
+
+    /// Record every connection and lookup in the network audit log
+    pub fn with_audit(mut self, audit: bool) -> Broker {
+        self.audit = audit;
+        self
+    }
+
+    pub fn connect(&self, req: &ConnectRequest) -> Result<Connection> {
+        if self.policy.offline {
+            return Err(OfflineError.into());
+        }
+
+        let host = req.host();
+        let proxy = self.policy.proxy_for(req.proxy)?;
+
+        if let Some(required) = self.policy.required_proxy {
+            if proxy != Some(required) {
+                bail!("Connection to {:?} has to go through {} (network.require_proxy is enabled)", host, required);
+            }
+        }
+
+        // with a proxy the name is resolved remotely, only host rules apply
+        if let Some(proxy) = proxy {
+            if !self.policy.allows_host(host) {
+                return Err(EgressError { host: host.to_string() }.into());
+            }
+            let stream = sockets::connect_socks5(proxy, host, req.port)?;
+            return Ok(Connection { stream, addr: None });
+        }
+
+        if !self.policy.dns && host.parse::<IpAddr>().is_err() {
+            return Err(CapabilityError { capability: "dns" }.into());
+        }
+
+        let addrs = sockets::resolve(&self.resolver, host)?;
+        self.policy.check_addrs(host, &addrs)?;
+
+        sockets::connect_addrs(&addrs, req.port, req.connect_timeout)
+    }
+
+    /// Udp is only used for quic, which can't be sent through a socks5 proxy
+    pub fn connect_udp(&self, req: &ConnectRequest) -> Result<Datagram> {
+        if self.policy.offline {
+            return Err(OfflineError.into());
+        }
+
+        let host = req.host();
+        if let Some(required) = self.policy.required_proxy {
+            bail!("Connection to {:?} has to go through {} (network.require_proxy is enabled)", host, required);
+        }
+
+        if self.policy.proxy_for(req.proxy)?.is_some() {
+            bail!("udp can't be sent through a socks5 proxy");
+        }
+
+        if !self.policy.dns && host.parse::<IpAddr>().is_err() {
+            return Err(CapabilityError { capability: "dns" }.into());
+        }
+
+        let addrs = sockets::resolve(&self.resolver, host)?;
+        self.policy.check_addrs(host, &addrs)?;
+
+        sockets::connect_udp_addrs(&addrs, req.port)
+    }
+
+    pub fn resolve(&self, req: &ResolveRequest) -> Result<DnsReply> {
+        if self.policy.offline {
+            return Err(OfflineError.into());
+        }
+
+        if !self.policy.dns {
+            return Err(CapabilityError { capability: "dns" }.into());
+        }
+
+        // dns lookups are sent over udp, which can't be proxied, tor resolves
+        // the name instead
+        if let Some(required) = self.policy.required_proxy {
+            return self.resolve_socks5(required, req);
+        }
+
+        if self.policy.proxy.is_some() {
+            bail!("dns is disabled if a proxy is active");
+        }
+
+        // a custom nameserver receives packets of our choosing, so it has to
+        // be covered by the egress policy like any other connection
+        if let Some(ns) = req.nameserver {
+            self.policy.check_addrs(&ns.ip().to_string(), &[ns.ip()])?;
+        }
+
+        let record = req.record.parse::<RecordType>()?;
+        let resolver = Resolver {
+            ns: self.nameservers(req),
+            tcp: req.tcp,
+            timeout: req.timeout,
+        };
+
+        resolver.resolve(&req.name, record)
+            .wait_for_response()
+    }
+
+    /// Tor only resolves a name to an address, everything else is refused
+    fn resolve_socks5(&self, proxy: SocketAddr, req: &ResolveRequest) -> Result<DnsReply> {
+        if req.nameserver.is_some() {
+            bail!("Custom nameservers can't be used through {} (network.require_proxy is enabled)", proxy);
+        }
+
+        let record = req.record.parse::<RecordType>()?;
+        if record != RecordType::A && record != RecordType::AAAA {
+            bail!("Only A and AAAA records can be resolved through {} (network.require_proxy is enabled)", proxy);
+        }
+
+        let answers = match sockets::resolve_socks5(proxy, &req.name, req.timeout)? {
+            Some(IpAddr::V4(ip)) if record == RecordType::A => vec![(req.name.clone(), RData::A(ip), 0)],
+            Some(IpAddr::V6(ip)) if record == RecordType::AAAA => vec![(req.name.clone(), RData::AAAA(ip), 0)],
+            Some(_) => Vec::new(),
+            None => return Ok(DnsReply {
+                answers: Vec::new(),
+                error: Some(DnsError::NXDomain),
+            }),
+        };
+
+        Ok(DnsReply {
+            answers,
+            error: None,
+        })
+    }
+
+    fn nameservers(&self, req: &ResolveRequest) -> Vec<SocketAddr> {
+        match req.nameserver {
+            Some(ns) => vec![ns],
+            None => self.resolver.ns.clone(),
+        }
+    }
+
+    fn audit_connect(&self, req: &ConnectRequest, result: &Result<Connection>) -> InsertNetworkAudit {
+        let addr = result.as_ref().map(|conn| conn.addr);
+        self.audit_socket("tcp", req, addr)
+    }
+
+    fn audit_connect_udp(&self, req: &ConnectRequest, result: &Result<Datagram>) -> InsertNetworkAudit {
+        let addr = result.as_ref().map(|datagram| Some(datagram.addr));
+        self.audit_socket("udp", req, addr)
+    }
+
+    fn audit_socket(&self, kind: &str, req: &ConnectRequest, addr: result::Result<Option<SocketAddr>, &Error>) -> InsertNetworkAudit {
+        let (ipaddr, error) = match addr {
+            Ok(addr) => (addr.map(|addr| addr.ip()), None),
+            Err(err) => (None, Some(err.to_string())),
+        };
+
+        InsertNetworkAudit {
+            kind: kind.to_string(),
+            host: req.host().to_string(),
+            port: Some(req.port),
+            method: None,
+            url: None,
+            headers: None,
+            status: None,
+            bytes: None,
+            error,
+            ipaddr,
+        }
+    }
+
+    fn audit_resolve(&self, req: &ResolveRequest, result: &Result<DnsReply>) -> InsertNetworkAudit {
+        // the resolver starts with the first nameserver
+        let ns = self.nameservers(req).into_iter().next();
+        InsertNetworkAudit {
+            kind: String::from("dns"),
+            host: req.name.clone(),
+            port: ns.map(|ns| ns.port()),
+            method: Some(req.record.clone()),
+            url: None,
+            headers: None,
+            status: None,
+            bytes: None,
+            error: result.as_ref().err().map(|err| err.to_string()),
+            ipaddr: ns.map(|ns| ns.ip()),
+        }
+    }
+}
+
+fn write_audit(tx: &EventSender, audit: InsertNetworkAudit) {
+    let (tx2, rx2) = mpsc::channel();
+    tx.send(DatabaseEvent::NetworkAudit(audit).with_callback(tx2));
+    if let Err(err) = rx2.recv().unwrap() {
+        tx.log(LogEvent::Error(format!("Failed to write network audit log: {}", err)));
+    }
+}
+
+/// A lookup with the `dns` function
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResolveRequest {
+    pub name: String,
+    pub record: String,
+    pub nameserver: Option<SocketAddr>,
+    pub tcp: bool,
+    pub timeout: Option<Duration>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum NetworkEvent {
+    Connect(ConnectRequest),
+    ConnectUdp(ConnectRequest),
+    Resolve(ResolveRequest),
+}
+
+/// Sent after the socket has been passed to the child
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Connected {
+    pub addr: Option<SocketAddr>,
+}
+
+impl NetworkEvent {
+    pub fn apply(self, ipc_parent: &mut IpcParent, tx: &EventSender, broker: &Broker) {
+        match self {
+            NetworkEvent::Connect(req) => {
+                let conn = broker.connect(&req);
+                if broker.audit {
+                    write_audit(tx, broker.audit_connect(&req, &conn));
+                }
+                let reply = conn
+                    .and_then(|conn| {
+                        ipc_parent.send_fd(conn.stream.as_raw_fd())?;
+                        Ok(Connected { addr: conn.addr })
+                    })
+                    .map_err(|err| ErrorInfo::new(&err));
+                ipc_parent.send_struct(reply, tx);
+            },
+            NetworkEvent::ConnectUdp(req) => {
+                let datagram = broker.connect_udp(&req);
+                if broker.audit {
+                    write_audit(tx, broker.audit_connect_udp(&req, &datagram));
+                }
+                let reply = datagram
+                    .and_then(|datagram| {
+                        ipc_parent.send_fd(datagram.socket.as_raw_fd())?;
+                        Ok(Connected { addr: Some(datagram.addr) })
+                    })
+                    .map_err(|err| ErrorInfo::new(&err));
+                ipc_parent.send_struct(reply, tx);
+            },
+            NetworkEvent::Resolve(req) => {
+                let reply = broker.resolve(&req);
+                if broker.audit {
+                    write_audit(tx, broker.audit_resolve(&req, &reply));
+                }
+                let reply = reply
+                    .map_err(|err| ErrorInfo::new(&err));
+                ipc_parent.send_struct(reply, tx);
+            },
+        }
+    }
+}
+
+/// Pass a file descriptor over a unix socket
+pub fn send_fd(sock: &UnixStream, fd: RawFd) -> Result<()> {
+    let mut data = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+
+    unsafe {
+        let space = libc::CMSG_SPACE(mem::size_of::<RawFd>() as u32) as usize;
+        let mut control = vec![0u8; space];
+
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = space as _;
+
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<RawFd>() as u32) as _;
+        ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut RawFd, fd);
+
+        if libc::sendmsg(sock.as_raw_fd(), &msg, 0) < 0 {
+            return Err(Error::from(io::Error::last_os_error()).context("Failed to pass socket to child").into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Receive a file descriptor that has been sent with [send_fd]
+pub fn recv_fd(sock: &UnixStream) -> Result<RawFd> {
+    let mut data = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+
+    unsafe {
+        let space = libc::CMSG_SPACE(mem::size_of::<RawFd>() as u32) as usize;
+        let mut control = vec![0u8; space];
+
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = space as _;
+
+        let n = libc::recvmsg(sock.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC);
+        if n < 0 {
+            return Err(Error::from(io::Error::last_os_error()).context("Failed to receive socket").into());
+        } else if n == 0 {
+            bail!("Connection to parent has been closed");
+        }
+
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        if cmsg.is_null() || (*cmsg).cmsg_level != libc::SOL_SOCKET || (*cmsg).cmsg_type != libc::SCM_RIGHTS {
+            bail!("Parent didn't send a socket");
+        }
+        Ok(ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const RawFd))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::prelude::*;
+    use std::net::{TcpListener, TcpStream};
+    use std::os::unix::io::FromRawFd;
+    use std::thread;
+
+    fn policy(egress: &str) -> NetworkPolicy {
+        NetworkPolicy {
+            egress: Some(EgressRule::parse_list(egress).unwrap()),
+            ..Default::default()
+        }
+    }
+
+    fn addrs(list: &[&str]) -> Vec<IpAddr> {
+        list.iter().map(|x| x.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_all_addrs_checked() {
+        let policy = policy("10.0.0.0/8, 2001:db8::/32");
+        assert!(policy.check_addrs("example.com", &addrs(&["10.1.2.3", "2001:db8::1"])).is_ok());
+        // a single address outside of the policy blocks the connection
+        assert!(policy.check_addrs("example.com", &addrs(&["10.1.2.3", "2001:db9::1"])).is_err());
+        assert!(policy.check_addrs("example.com", &addrs(&["203.0.113.1", "2001:db8::1"])).is_err());
+        assert!(policy.check_addrs("example.com", &[]).is_err());
+    }
+
+    #[test]
+    fn test_host_rule() {
+        let policy = policy("example.com");
+        assert!(policy.check_addrs("example.com", &addrs(&["203.0.113.1", "::1"])).is_ok());
+        assert!(policy.check_addrs("example.org", &addrs(&["203.0.113.1"])).is_err());
+        assert!(NetworkPolicy::default().check_addrs("example.org", &addrs(&["203.0.113.1"])).is_ok());
+    }
+
+    #[test]
+    fn test_proxy_override() {
+        let system = "127.0.0.1:9050".parse().unwrap();
+        let other = "127.0.0.1:1080".parse().unwrap();
+        let policy = NetworkPolicy {
+            proxy: Some(system),
+            ..Default::default()
+        };
+        assert_eq!(policy.proxy_for(None).unwrap(), Some(system));
+        assert_eq!(policy.proxy_for(Some(system)).unwrap(), Some(system));
+        assert!(policy.proxy_for(Some(other)).is_err());
+        assert_eq!(NetworkPolicy::default().proxy_for(Some(other)).unwrap(), Some(other));
+    }
+
+    #[test]
+    fn test_connect_blocked() {
+        let broker = Broker::new(policy("10.0.0.0/8"), Resolver::empty());
+        let err = broker.connect(&ConnectRequest::new("127.0.0.1", 80)).unwrap_err();
+        assert_eq!(err.to_string(), "Connection to \"127.0.0.1\" is blocked by the egress policy of this module");
+    }
+
+    #[test]
+    fn test_connect_offline() {
+        let broker = Broker::new(NetworkPolicy {
+            offline: true,
+            ..Default::default()
+        }, Resolver::empty());
+        let err = broker.connect(&ConnectRequest::new("127.0.0.1", 80)).unwrap_err();
+        assert!(err.downcast_ref::<OfflineError>().is_some());
+    }
+
+    #[test]
+    fn test_connect_no_dns() {
+        let broker = Broker::new(NetworkPolicy {
+            dns: false,
+            ..Default::default()
+        }, Resolver::empty());
+        let err = broker.connect(&ConnectRequest::new("example.com", 80)).unwrap_err();
+        assert!(err.downcast_ref::<CapabilityError>().is_some());
+    }
+
+    #[test]
+    fn test_required_proxy() {
+        let required = "127.0.0.1:9050".parse().unwrap();
+        let broker = Broker::new(NetworkPolicy {
+            required_proxy: Some(required),
+            ..Default::default()
+        }, Resolver::empty());
+
+        let err = broker.connect(&ConnectRequest::new("127.0.0.1", 80)).unwrap_err();
+        assert_eq!(err.to_string(), "Connection to \"127.0.0.1\" has to go through 127.0.0.1:9050 (network.require_proxy is enabled)");
+
+        let req = ResolveRequest {
+            name: String::from("example.com"),
+            record: String::from("A"),
+            nameserver: None,
+            tcp: false,
+            timeout: None,
+        };
+        let err = broker.resolve(&req).unwrap_err();
+        assert_eq!(err.to_string(), "dns can't be sent through 127.0.0.1:9050 (network.require_proxy is enabled)");
+    }
+
+    #[test]
+    fn test_nameserver_egress() {
+        let broker = Broker::new(policy("api.github.com"), Resolver::empty());
+        let req = ResolveRequest {
+            name: String::from("api.github.com"),
+            record: String::from("A"),
+            nameserver: Some("203.0.113.1:4444".parse().unwrap()),
+            tcp: true,
+            timeout: None,
+        };
+        let err = broker.resolve(&req).unwrap_err();
+        assert_eq!(err.to_string(), "Connection to \"203.0.113.1\" is blocked by the egress policy of this module");
+
+        let broker = Broker::new(policy("api.github.com, 203.0.113.0/24"), Resolver::empty());
+        let err = broker.resolve(&ResolveRequest {
+            record: String::from("invalid"),
+            ..req
+        }).unwrap_err();
+        assert!(err.downcast_ref::<EgressError>().is_none());
+    }
+
+    fn fake_tor(reply: &'static [u8]) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut sock, _) = listener.accept().unwrap();
+            let mut buf = [0; 3];
+            sock.read_exact(&mut buf).unwrap();
+            sock.write_all(&[0x05, 0x00]).unwrap();
+            let mut buf = [0; 5];
+            sock.read_exact(&mut buf).unwrap();
+            let mut name = vec![0; buf[4] as usize + 2];
+            sock.read_exact(&mut name).unwrap();
+            sock.write_all(reply).unwrap();
+        });
+        addr
+    }
+
+    #[test]
+    fn test_resolve_required_proxy() {
+        let proxy = fake_tor(&[0x05, 0x00, 0x00, 0x01, 93, 184, 216, 34, 0x00, 0x00]);
+        let broker = Broker::new(NetworkPolicy {
+            required_proxy: Some(proxy),
+            proxy: Some(proxy),
+            ..Default::default()
+        }, Resolver::empty());
+        let req = ResolveRequest {
+            name: String::from("example.com"),
+            record: String::from("A"),
+            nameserver: None,
+            tcp: false,
+            timeout: None,
+        };
+        let reply = broker.resolve(&req).unwrap();
+        assert_eq!(reply.success().unwrap(), addrs(&["93.184.216.34"]));
+
+        // these can't be sent through the proxy
+        assert!(broker.resolve(&ResolveRequest {
+            record: String::from("MX"),
+            ..req.clone()
+        }).is_err());
+        assert!(broker.resolve(&ResolveRequest {
+            nameserver: Some("1.1.1.1:53".parse().unwrap()),
+            ..req.clone()
+        }).is_err());
+
+        let broker = Broker::new(NetworkPolicy {
+            dns: false,
+            required_proxy: Some(proxy),
+            ..Default::default()
+        }, Resolver::empty());
+        let err = broker.resolve(&req).unwrap_err();
+        assert!(err.downcast_ref::<CapabilityError>().is_some());
+    }
+
+    #[test]
+    fn test_resolve_required_proxy_nxdomain() {
+        let proxy = fake_tor(&[0x05, 0x04, 0x00, 0x01, 0, 0, 0, 0, 0x00, 0x00]);
+        let broker = Broker::new(NetworkPolicy {
+            required_proxy: Some(proxy),
+            ..Default::default()
+        }, Resolver::empty());
+        let reply = broker.resolve(&ResolveRequest {
+            name: String::from("doesntexist.example.com"),
+            record: String::from("A"),
+            nameserver: None,
+            tcp: false,
+            timeout: None,
+        }).unwrap();
+        assert_eq!(reply.error, Some(DnsError::NXDomain));
+    }
+
+    #[test]
+    fn test_audit_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let broker = Broker::new(NetworkPolicy::default(), Resolver::empty());
+        let req = ConnectRequest::new("127.0.0.1", port);
+        let conn = broker.connect(&req);
+        let audit = broker.audit_connect(&req, &conn);
+        assert_eq!(audit.kind, "tcp");
+        assert_eq!(audit.port, Some(port));
+        assert_eq!(audit.ipaddr, Some("127.0.0.1".parse().unwrap()));
+        assert_eq!(audit.error, None);
+
+        let broker = Broker::new(policy("10.0.0.0/8"), Resolver::empty());
+        let conn = broker.connect(&req);
+        let audit = broker.audit_connect(&req, &conn);
+        assert_eq!(audit.ipaddr, None);
+        assert_eq!(audit.error.as_deref(), Some("Connection to \"127.0.0.1\" is blocked by the egress policy of this module"));
+    }
+
+    #[test]
+    fn test_connect_udp() {
+        let server = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        let req = ConnectRequest::new("127.0.0.1", port);
+
+        let broker = Broker::new(policy("127.0.0.1"), Resolver::empty());
+        let datagram = broker.connect_udp(&req);
+        let audit = broker.audit_connect_udp(&req, &datagram);
+        assert_eq!(datagram.unwrap().addr, server.local_addr().unwrap());
+        assert_eq!(audit.kind, "udp");
+        assert_eq!(audit.ipaddr, Some("127.0.0.1".parse().unwrap()));
+
+        let broker = Broker::new(policy("10.0.0.0/8"), Resolver::empty());
+        let err = broker.connect_udp(&req).unwrap_err();
+        assert!(err.downcast_ref::<EgressError>().is_some());
+
+        let broker = Broker::new(NetworkPolicy {
+            proxy: Some("127.0.0.1:9050".parse().unwrap()),
+            ..Default::default()
+        }, Resolver::empty());
+        assert!(broker.connect_udp(&req).is_err());
+    }
+
+    #[test]
+    fn test_audit_resolve() {
+        let resolver = Resolver {
+            ns: vec!["127.0.0.53:53".parse().unwrap()],
+            tcp: false,
+            timeout: None,
+        };
+        let broker = Broker::new(NetworkPolicy {
+            dns: false,
+            ..Default::default()
+        }, resolver);
+        let mut req = ResolveRequest {
+            name: String::from("example.com"),
+            record: String::from("AAAA"),
+            nameserver: None,
+            tcp: false,
+            timeout: None,
+        };
+
+        let reply = broker.resolve(&req);
+        let audit = broker.audit_resolve(&req, &reply);
+        assert_eq!(audit.kind, "dns");
+        assert_eq!(audit.host, "example.com");
+        assert_eq!(audit.method.as_deref(), Some("AAAA"));
+        assert_eq!(audit.ipaddr, Some("127.0.0.53".parse().unwrap()));
+        assert_eq!(audit.port, Some(53));
+        assert!(audit.error.is_some());
+
+        req.nameserver = Some("1.1.1.1:5353".parse().unwrap());
+        let audit = broker.audit_resolve(&req, &reply);
+        assert_eq!(audit.ipaddr, Some("1.1.1.1".parse().unwrap()));
+        assert_eq!(audit.port, Some(5353));
+    }
+
+    #[test]
+    fn test_resolve_policy() {
+        let req = ResolveRequest {
+            name: String::from("example.com"),
+            record: String::from("A"),
+            nameserver: None,
+            tcp: false,
+            timeout: None,
+        };
+
+        let broker = Broker::new(NetworkPolicy {
+            dns: false,
+            ..Default::default()
+        }, Resolver::empty());
+        let err = broker.resolve(&req).unwrap_err();
+        assert!(err.downcast_ref::<CapabilityError>().is_some());
+
+        let broker = Broker::new(NetworkPolicy {
+            offline: true,
+            ..Default::default()
+        }, Resolver::empty());
+        let err = broker.resolve(&req).unwrap_err();
+        assert!(err.downcast_ref::<OfflineError>().is_some());
+
+        let broker = Broker::new(NetworkPolicy {
+            proxy: Some("127.0.0.1:9050".parse().unwrap()),
+            ..Default::default()
+        }, Resolver::empty());
+        assert!(broker.resolve(&req).is_err());
+    }
+
+    #[test]
+    fn test_pass_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let broker = Broker::new(policy("127.0.0.1"), Resolver::empty());
+        let conn = broker.connect(&ConnectRequest::new("127.0.0.1", addr.port())).unwrap();
+        assert_eq!(conn.addr, Some(addr));
+
+        let (parent, child) = UnixStream::pair().unwrap();
+        send_fd(&parent, conn.stream.as_raw_fd()).unwrap();
+        drop(conn);
+        let mut stream = unsafe { TcpStream::from_raw_fd(recv_fd(&child).unwrap()) };
+
+        let (mut server, _) = listener.accept().unwrap();
+        stream.write_all(b"ohai").unwrap();
+        let mut buf = [0; 4];
+        server.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ohai");
+    }
+}
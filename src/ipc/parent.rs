@@ -2,38 +2,79 @@ use crate::errors::*;
 use crate::ipc::common::*;
 use chrootable_https::dns::Resolver;
 use crate::blobs::Blob;
-use crate::engine::Module;
+use crate::capabilities::AcceptedCapabilities;
+use crate::config::{NetworkConfig, SandboxConfig};
+use crate::engine::{Entrypoint, Module};
+use crate::ipc::concurrency::{ConcurrencyLimit, HeldSlots};
+use crate::ipc::files::ReadGrants;
+use crate::ipc::network::{self, Broker, NetworkPolicy, BROKER_FD};
 use crate::keyring::KeyRingEntry;
-use crate::worker::{Event, Event2, LogEvent, ExitEvent, EventSender, EventWithCallback};
+use crate::sandbox::Profile;
+use crate::worker::{Event, Event2, LogEvent, ExitEvent, EventSender, EventWithCallback, DatabaseEvent, SharedStdin};
 use std::collections::HashMap;
 use std::env;
 use std::ffi::OsString;
 use std::io::prelude::*;
-use std::io::{BufReader, BufRead, stdin};
-use std::net::SocketAddr;
+use std::io::{self, BufReader, BufRead};
+use std::net::{SocketAddr, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
 use std::process::{Command, Child, Stdio, ChildStdin, ChildStdout};
 
 pub struct IpcParent {
     child: Child,
     stdin: ChildStdin,
     stdout: BufReader<ChildStdout>,
+    broker: UnixStream,
+}
+
+/// Make the child end of the broker socket available as `BROKER_FD`
+fn setup_broker_fd(cmd: &mut Command, fd: RawFd) {
+    unsafe {
+        cmd.pre_exec(move || {
+            // dup2 is a no-op if the fd is already in place, but it would be
+            // closed on exec
+            let ret = if fd == BROKER_FD {
+                libc::fcntl(fd, libc::F_SETFD, 0)
+            } else {
+                libc::dup2(fd, BROKER_FD)
+            };
+            if ret < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        });
+    }
 }
 
 impl IpcParent {
-    pub fn setup(module: &Module) -> Result<IpcParent> {
+    pub fn setup(module: &Module, profile: Profile) -> Result<IpcParent> {
         let exe = match env::current_exe() {
             Ok(exe) => exe.into_os_string(),
             _ => OsString::from("sn0int"),
         };
 
-        let mut child = Command::new(exe)
-            .arg("sandbox")
+        let (broker, child_broker) = UnixStream::pair()
+            .context("Failed to create broker socket")?;
+
+        let mut cmd = Command::new(exe);
+        cmd.arg("sandbox")
             .arg(&module.canonical())
+            .arg("--profile")
+            .arg(profile.as_str())
             .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()
+            .stdout(Stdio::piped());
+        setup_broker_fd(&mut cmd, child_broker.as_raw_fd());
+        let mut child = cmd.spawn()
             .context("Failed to spawn child process")?;
+        drop(child_broker);
 
         let stdin = child.stdin.take().expect("Failed to take child stdin");
         let stdout = child.stdout.take().expect("Failed to take child stdout");
@@ -43,9 +84,15 @@ impl IpcParent {
             child,
             stdin,
             stdout,
+            broker,
         })
     }
 
+    /// Pass a connection to the child
+    pub fn send_fd(&mut self, fd: RawFd) -> Result<()> {
+        network::send_fd(&self.broker, fd)
+    }
+
     pub fn send_start(&mut self, start: &StartCommand) -> Result<()> {
         let start = serde_json::to_value(start)?;
         self.send(&start)?;
@@ -72,7 +119,7 @@ impl IpcParent {
         let len = self.stdout.read_line(&mut line)?;
 
         if len == 0 {
-            bail!("Sandbox child has crashed");
+            return Err(SandboxError(String::from("Sandbox child has crashed")).into());
         }
 
         let event = serde_json::from_str(&line[..len])?;
@@ -80,6 +127,16 @@ impl IpcParent {
         Ok(event)
     }
 
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    pub fn kill(&mut self) -> Result<()> {
+        self.child.kill()
+            .context("Failed to kill child")?;
+        Ok(())
+    }
+
     pub fn wait(&mut self) -> Result<()> {
         let exit = self.child.wait()
             .context("Failed to wait for child")?;
@@ -102,37 +159,166 @@ impl IpcParent {
     }
 }
 
+/// Kills the child once the source timeout expired, unless it's dropped first
+///
+/// The child only knows about the deadline when it's sleeping, a module that
+/// is busy or stuck in a request has to be stopped from the outside.
+struct Watchdog {
+    _cancel: mpsc::Sender<()>,
+    fired: Arc<AtomicBool>,
+}
+
+impl Watchdog {
+    fn start(pid: u32, timeout: Duration) -> Watchdog {
+        let (cancel, rx) = mpsc::channel::<()>();
+        let fired = Arc::new(AtomicBool::new(false));
+        let flag = fired.clone();
+        thread::spawn(move || {
+            // the sender is dropped if the child finished in time
+            if let Err(mpsc::RecvTimeoutError::Timeout) = rx.recv_timeout(timeout) {
+                flag.store(true, Ordering::SeqCst);
+                unsafe {
+                    libc::kill(pid as libc::pid_t, libc::SIGKILL);
+                }
+            }
+        });
+        Watchdog {
+            _cancel: cancel,
+            fired,
+        }
+    }
+
+    fn fired(&self) -> bool {
+        self.fired.load(Ordering::SeqCst)
+    }
+}
+
+pub fn ensure_egress_declared(module: &Module, sandbox: &SandboxConfig) -> Result<()> {
+    if sandbox.require_egress && module.egress().is_none() {
+        return Err(SandboxError(format!("Module {} doesn't declare its network egress, refusing to run it (sandbox.require-egress is enabled)",
+            module.canonical())).into());
+    }
+    Ok(())
+}
+
+/// Set after the required proxy has been verified once in this process
+static PROXY_VERIFIED: AtomicBool = AtomicBool::new(false);
+
+/// Tor answers plain http requests on its socks port with this status
+const TOR_HTTP_REPLY: &str = "Tor is not an HTTP Proxy";
+
+fn verify_tor_proxy(proxy: SocketAddr) -> Result<()> {
+    let timeout = Duration::from_secs(5);
+    let mut sock = TcpStream::connect_timeout(&proxy, timeout)
+        .context(format!("Required proxy {} is unreachable", proxy))?;
+    sock.set_read_timeout(Some(timeout))?;
+    sock.write_all(b"GET / HTTP/1.0\r\n\r\n")?;
+
+    let mut reply = Vec::new();
+    // tor closes the connection after the reply, a timeout means it's something else
+    let _ = sock.take(4096).read_to_end(&mut reply);
+    if !String::from_utf8_lossy(&reply).contains(TOR_HTTP_REPLY) {
+        bail!("Required proxy {} doesn't look like tor", proxy);
+    }
+    Ok(())
+}
+
+/// Make sure the required proxy is used and reachable, the broker then refuses
+/// everything that can't be sent through it
+pub fn ensure_required_proxy(proxy: Option<SocketAddr>, network: &NetworkConfig) -> Result<()> {
+    let required = match network.required_proxy()? {
+        Some(required) => required,
+        None => return Ok(()),
+    };
+
+    if proxy != Some(required) {
+        bail!("Module traffic has to go through {} (network.require_proxy is enabled)", required);
+    }
+
+    if !PROXY_VERIFIED.load(Ordering::SeqCst) {
+        verify_tor_proxy(required)?;
+        PROXY_VERIFIED.store(true, Ordering::SeqCst);
+    }
+
+    Ok(())
+}
+
 pub fn run(module: Module,
            tx: &EventSender,
+           entrypoint: Entrypoint,
            arg: serde_json::Value,
            keyring: Vec<KeyRingEntry>,
            verbose: u8,
-           has_stdin: bool,
+           stdin: Option<&SharedStdin>,
            proxy: Option<SocketAddr>,
            user_agent: Option<String>,
            options: HashMap<String, String>,
            blobs: Vec<Blob>,
+           read_grants: &ReadGrants,
+           network: &NetworkConfig,
+           profile: Profile,
+           sandbox: &SandboxConfig,
+           concurrency: Option<&ConcurrencyLimit>,
+           rows: &AtomicUsize,
 ) -> Result<ExitEvent> {
+    ensure_egress_declared(&module, sandbox)?;
+    ensure_required_proxy(proxy, network)?;
     let dns_config = Resolver::from_system_v4()?;
 
-    let mut reader = if has_stdin {
-        Some(BufReader::new(stdin()))
-    } else {
-        None
-    };
+    let broker = Broker::new(NetworkPolicy::new(&module, proxy, network)?, dns_config)
+        .with_audit(network.audit);
 
-    let mut ipc_parent = IpcParent::setup(&module)?;
-    ipc_parent.send_start(&StartCommand::new(verbose, keyring, dns_config, proxy, user_agent, options, module, arg, blobs))?;
+    let mut ipc_parent = IpcParent::setup(&module, profile)?;
+    let memory_limit = sandbox.memory_limit_bytes();
+    let timeout = sandbox.source_timeout();
+    let max_rows = sandbox.max_output_rows();
+    let capabilities = Some(AcceptedCapabilities::load()?.effective(&module));
+    ipc_parent.send_start(&StartCommand::new(verbose, keyring, user_agent, options, module, entrypoint, arg, blobs, memory_limit, timeout, capabilities, network.audit, concurrency.is_some()))?;
 
+    // the child is only reaped after the watchdog is gone, so it can't kill a
+    // process that reused the pid
+    let watchdog = timeout.map(|timeout| Watchdog::start(ipc_parent.id(), timeout));
+    let mut held = concurrency.map(HeldSlots::new);
+    let mut killed = false;
     let exit = loop {
-        match ipc_parent.recv()? {
+        let event = match ipc_parent.recv() {
+            Ok(event) => event,
+            Err(_) if watchdog.as_ref().map(Watchdog::fired).unwrap_or(false) => {
+                let err = format!("Module exceeded the source timeout of {} seconds", sandbox.source_timeout);
+                tx.send(Event2::Log(LogEvent::Error(err.clone())));
+                break ExitEvent::Err(err);
+            },
+            Err(err) => return Err(err),
+        };
+
+        match event {
             Event::Log(event) => tx.send(Event2::Log(event)),
-            Event::Database(object) => ipc_parent.send_event_callback(*object, tx),
-            Event::Stdio(object) => object.apply(&mut ipc_parent, tx, &mut reader),
+            Event::Database(object) => {
+                // the limit is shared by every source of the run
+                if let DatabaseEvent::Insert(_) | DatabaseEvent::InsertTtl(_) = *object {
+                    let count = rows.fetch_add(1, Ordering::SeqCst) + 1;
+                    if let Some(max_rows) = max_rows.filter(|max_rows| count > *max_rows) {
+                        ipc_parent.kill()?;
+                        killed = true;
+                        let err = format!("Module exceeded the limit of {} output rows", max_rows);
+                        tx.send(Event2::Log(LogEvent::Error(err.clone())));
+                        break ExitEvent::Err(err);
+                    }
+                }
+                ipc_parent.send_event_callback(*object, tx);
+            },
+            Event::Stdio(object) => object.apply(&mut ipc_parent, tx, stdin),
+            Event::File(object) => object.apply(&mut ipc_parent, tx, read_grants),
             Event::Ratelimit(req) => ipc_parent.send_event_callback(req, tx),
+            Event::Concurrency(event) => event.apply(&mut ipc_parent, tx, held.as_mut()),
+            Event::Network(event) => event.apply(&mut ipc_parent, tx, &broker),
+            Event::Keyring(req) => ipc_parent.send_event_callback(req, tx),
             Event::Blob(blob) => ipc_parent.send_event_callback(blob, tx),
+            Event::Prompt(prompt) => ipc_parent.send_event_callback(prompt, tx),
+            Event::Notify(notify) => ipc_parent.send_event_callback(notify, tx),
+            Event::OnFinish => tx.send(Event2::OnFinish),
             Event::Exit(event) => {
-                if let ExitEvent::Err(err) = &event {
+                if let ExitEvent::Err(err) | ExitEvent::Offline(err) = &event {
                     tx.send(Event2::Log(LogEvent::Error(err.clone())));
                 }
                 break event;
@@ -140,7 +326,92 @@ pub fn run(module: Module,
         }
     };
 
-    ipc_parent.wait()?;
+    if killed || watchdog.map(|watchdog| watchdog.fired()).unwrap_or(false) {
+        // the child has been killed, there's no exit status worth checking
+        let _ = ipc_parent.wait();
+    } else {
+        ipc_parent.wait()?;
+    }
 
     Ok(exit)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn fake_proxy(reply: &'static [u8]) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut sock, _) = listener.accept().unwrap();
+            let mut buf = [0; 1024];
+            let _ = sock.read(&mut buf);
+            sock.write_all(reply).unwrap();
+        });
+        addr
+    }
+
+    #[test]
+    fn test_verify_tor_proxy() {
+        let addr = fake_proxy(b"HTTP/1.0 501 Tor is not an HTTP Proxy\r\nContent-Type: text/html\r\n\r\n");
+        verify_tor_proxy(addr).unwrap();
+    }
+
+    fn module(header: &str) -> Module {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.lua");
+        let code = format!("-- Description: test\n-- Version: 0.1.0\n{}-- License: GPL-3.0\n\nfunction run() end\n", header);
+        std::fs::write(&path, code).unwrap();
+        Module::load(&path, "anonymous", "test", false).unwrap()
+    }
+
+    #[test]
+    fn test_required_proxy() {
+        let required = fake_proxy(b"HTTP/1.0 501 Tor is not an HTTP Proxy\r\n\r\n");
+        let network = NetworkConfig {
+            require_proxy: Some(format!("socks5://{}", required)),
+            ..Default::default()
+        };
+        ensure_required_proxy(Some(required), &network).unwrap();
+
+        let other = "127.0.0.1:9150".parse().unwrap();
+        assert!(ensure_required_proxy(Some(other), &network).is_err());
+        assert!(ensure_required_proxy(None, &network).is_err());
+        assert!(ensure_required_proxy(None, &NetworkConfig::default()).is_ok());
+
+        // dns is resolved by the proxy, modules don't have to opt out of it
+        let policy = NetworkPolicy::new(&module(""), Some(required), &network).unwrap();
+        assert!(policy.dns);
+        assert_eq!(policy.required_proxy, Some(required));
+        // a module without dns runs the same way, the broker refuses its lookups
+        let policy = NetworkPolicy::new(&module("-- DNS: false\n"), Some(required), &network).unwrap();
+        assert!(!policy.dns);
+        assert_eq!(policy.required_proxy, Some(required));
+    }
+
+    #[test]
+    fn test_watchdog_kills() {
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        let watchdog = Watchdog::start(child.id(), Duration::from_millis(50));
+        let status = child.wait().unwrap();
+        assert!(!status.success());
+        assert!(watchdog.fired());
+    }
+
+    #[test]
+    fn test_watchdog_cancel() {
+        let mut child = Command::new("sleep").arg("0.2").spawn().unwrap();
+        let watchdog = Watchdog::start(child.id(), Duration::from_millis(100));
+        drop(watchdog);
+        assert!(child.wait().unwrap().success());
+    }
+
+    #[test]
+    fn test_verify_not_tor() {
+        let addr = fake_proxy(b"HTTP/1.1 400 Bad Request\r\n\r\n");
+        assert!(verify_tor_proxy(addr).is_err());
+    }
+}
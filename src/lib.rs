@@ -14,16 +14,23 @@ pub mod api;
 pub mod args;
 pub mod auth;
 pub mod autonoscope;
+pub mod bench;
 pub mod blobs;
 pub mod cal;
+pub mod capabilities;
+pub mod changelog;
 pub mod cmd;
+pub mod compression;
 pub mod config;
 use sn0int_std::crt;
 pub mod db;
+pub mod encryption;
 pub mod errors;
 pub mod engine;
+use sn0int_std::error_info;
 pub mod filters;
 pub mod fmt;
+pub mod git;
 use sn0int_std::geo;
 pub use sn0int_std::geoip;
 use sn0int_std::gfx;
@@ -31,6 +38,10 @@ use sn0int_std::html;
 use sn0int_std::json;
 pub mod ipc;
 pub mod keyring;
+pub mod links;
+pub mod lockfile;
+pub mod logging;
+pub mod manifest;
 use sn0int_std::lazy;
 pub mod migrations;
 pub mod models;
@@ -39,14 +50,18 @@ pub mod notify;
 pub mod paths;
 pub use sn0int_std::psl;
 pub mod options;
+pub mod output;
 use sn0int_std::ratelimits;
 pub mod registry;
 pub mod repl;
 pub mod runtime;
 pub mod sandbox;
+pub mod scheduler;
 pub mod schema;
 pub mod ser;
+pub mod serve;
 pub mod shell;
+pub mod signing;
 use sn0int_std::sockets;
 pub mod term;
 pub mod update;
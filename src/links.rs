@@ -0,0 +1,90 @@
+use crate::errors::*;
+use crate::paths;
+use serde::{Serialize, Deserialize};
+use sn0int_common::ModuleID;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Modules that are loaded from a file outside of the module directory, by
+/// canonical name. They're read again every time they're executed.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Links {
+    #[serde(default)]
+    modules: BTreeMap<String, PathBuf>,
+}
+
+impl Links {
+    fn path() -> Result<PathBuf> {
+        let path = paths::sn0int_dir()?;
+        Ok(path.join("linked-modules.json"))
+    }
+
+    pub fn load() -> Result<Links> {
+        let path = Links::path()?;
+        if !path.exists() {
+            return Ok(Links::default());
+        }
+
+        let data = fs::read(&path)
+            .context("Failed to read linked modules")?;
+        let links = serde_json::from_slice(&data)
+            .context("Failed to parse linked modules")?;
+        Ok(links)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let data = serde_json::to_string_pretty(&self)?;
+        fs::write(Links::path()?, data + "\n")
+            .context("Failed to write linked modules")?;
+        Ok(())
+    }
+
+    pub fn get(&self, canonical: &str) -> Option<&PathBuf> {
+        self.modules.get(canonical)
+    }
+
+    pub fn insert(&mut self, canonical: String, path: PathBuf) {
+        self.modules.insert(canonical, path);
+    }
+
+    pub fn remove(&mut self, canonical: &str) -> Option<PathBuf> {
+        self.modules.remove(canonical)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=(&String, &PathBuf)> {
+        self.modules.iter()
+    }
+}
+
+/// Guess the module id from a path that follows the layout of the module
+/// directory, `<author>/<name>.lua`
+pub fn module_id_from_path(path: &Path) -> Result<ModuleID> {
+    if path.extension().and_then(|x| x.to_str()) != Some("lua") {
+        bail!("File extension has to be .lua");
+    }
+
+    let name = path.file_stem()
+        .and_then(|x| x.to_str())
+        .ok_or_else(|| format_err!("Couldn't get file name"))?;
+    let author = path.parent()
+        .and_then(|x| x.file_name())
+        .and_then(|x| x.to_str())
+        .ok_or_else(|| format_err!("Couldn't get author from {:?}, the module id needs to be given explicitly", path))?;
+
+    format!("{}/{}", author, name).parse::<ModuleID>()
+        .map_err(|err| format_err!("Couldn't derive module id from {:?}, the module id needs to be given explicitly: {}", path, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_module_id_from_path() {
+        let id = module_id_from_path(Path::new("/home/user/repos/modules/kpcyrd/ctlogs.lua")).unwrap();
+        assert_eq!(id.to_string(), "kpcyrd/ctlogs");
+        assert!(module_id_from_path(Path::new("/home/user/kpcyrd/ctlogs.txt")).is_err());
+        assert!(module_id_from_path(Path::new("ctlogs.lua")).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use crate::errors::*;
+use crate::engine::Library;
+use crate::git::{GitOrigin, GitOrigins};
+use crate::paths;
+use crate::signing::Verification;
+use data_encoding::HEXLOWER;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub fn hash(code: &str) -> String {
+    HEXLOWER.encode(&Sha256::digest(code.as_bytes()))
+}
+
+/// The exact version of an installed module
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LockedModule {
+    pub version: String,
+    pub sha256: String,
+    /// Pinned modules are skipped by `pkg update`
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git: Option<GitOrigin>,
+    #[serde(default)]
+    pub verification: Verification,
+    /// The registry the module has been installed from, this is missing for
+    /// git modules and modules installed before multiple registries existed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registry: Option<String>,
+}
+
+/// Every module that has been installed, this can be copied to another
+/// machine and installed with `pkg install --locked`
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Lockfile {
+    #[serde(default)]
+    modules: BTreeMap<String, LockedModule>,
+}
+
+impl Lockfile {
+    pub fn path() -> Result<PathBuf> {
+        let path = paths::sn0int_dir()?;
+        Ok(path.join("modules.lock"))
+    }
+
+    pub fn load() -> Result<Lockfile> {
+        let path = Lockfile::path()?;
+        if !path.exists() {
+            return Ok(Lockfile::default());
+        }
+        Lockfile::load_from(&path)
+    }
+
+    pub fn load_from(path: &Path) -> Result<Lockfile> {
+        let data = fs::read(path)
+            .context(format_err!("Failed to read lockfile {:?}", path))?;
+        let lockfile = serde_json::from_slice(&data)
+            .context(format_err!("Failed to parse lockfile {:?}", path))?;
+        Ok(lockfile)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let data = serde_json::to_string_pretty(&self)?;
+        fs::write(Lockfile::path()?, data + "\n")
+            .context("Failed to write lockfile")?;
+        Ok(())
+    }
+
+    pub fn get(&self, canonical: &str) -> Option<&LockedModule> {
+        self.modules.get(canonical)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=(&String, &LockedModule)> {
+        self.modules.iter()
+    }
+
+    #[inline]
+    pub fn is_pinned(&self, canonical: &str) -> bool {
+        self.get(canonical).map(|x| x.pinned).unwrap_or(false)
+    }
+
+    /// Record a module that has been written to disk, a pin is kept
+    pub fn installed(&mut self, canonical: String, version: String, code: &str, git: Option<GitOrigin>, verification: Verification, registry: Option<String>) {
+        let pinned = self.is_pinned(&canonical);
+        self.modules.insert(canonical, LockedModule {
+            version,
+            sha256: hash(code),
+            pinned,
+            git,
+            verification,
+            registry,
+        });
+    }
+
+    pub fn remove(&mut self, canonical: &str) -> bool {
+        self.modules.remove(canonical).is_some()
+    }
+
+    /// Returns false if the module isn't in the lockfile
+    pub fn set_pinned(&mut self, canonical: &str, pinned: bool) -> bool {
+        if let Some(module) = self.modules.get_mut(canonical) {
+            module.pinned = pinned;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Add modules that have been installed before the lockfile existed and
+    /// remove modules that have been deleted, returns true if anything changed
+    pub fn sync(&mut self, library: &Library, origins: &GitOrigins) -> bool {
+        let mut changed = false;
+
+        let installed = library.list()
+            .into_iter()
+            .filter(|module| !module.is_private())
+            .map(|module| (module.canonical(), module))
+            .collect::<BTreeMap<_, _>>();
+
+        let before = self.modules.len();
+        self.modules.retain(|canonical, _| installed.contains_key(canonical));
+        changed |= before != self.modules.len();
+
+        for (canonical, module) in installed {
+            let locked = self.get(&canonical);
+            if locked.map(|x| x.sha256 != hash(module.code())).unwrap_or(true) {
+                let git = origins.get(&canonical).cloned();
+                self.installed(canonical, module.version().to_string(), module.code(), git, Verification::Unsigned, None);
+                changed = true;
+            }
+        }
+
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_installed_keeps_pin() {
+        let mut lockfile = Lockfile::default();
+        lockfile.installed("kpcyrd/ctlogs".to_string(), "0.1.0".to_string(), "-- a", None, Verification::Unsigned, None);
+        assert!(!lockfile.is_pinned("kpcyrd/ctlogs"));
+        assert!(lockfile.set_pinned("kpcyrd/ctlogs", true));
+        lockfile.installed("kpcyrd/ctlogs".to_string(), "0.2.0".to_string(), "-- b", None, Verification::Verified("key".to_string()), Some("mirror".to_string()));
+        assert_eq!(lockfile.get("kpcyrd/ctlogs"), Some(&LockedModule {
+            version: "0.2.0".to_string(),
+            sha256: hash("-- b"),
+            pinned: true,
+            git: None,
+            verification: Verification::Verified("key".to_string()),
+            registry: Some("mirror".to_string()),
+        }));
+        assert!(!lockfile.set_pinned("kpcyrd/asn", true));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut lockfile = Lockfile::default();
+        lockfile.installed("kpcyrd/ctlogs".to_string(), "0.1.0".to_string(), "-- a", None, Verification::Verified("key".to_string()), Some("default".to_string()));
+        lockfile.installed("org/foo".to_string(), "0.1.0".to_string(), "-- b", Some(GitOrigin {
+            url: "https://github.com/org/repo".to_string(),
+            reference: None,
+            file: "foo.lua".to_string(),
+            commit: "2874f2d297fd80d5d13b09ae02ed617ef98938a8".to_string(),
+        }), Verification::Unsigned, None);
+        let json = serde_json::to_string(&lockfile).unwrap();
+        let parsed = serde_json::from_str::<Lockfile>(&json).unwrap();
+        assert_eq!(parsed, lockfile);
+    }
+}
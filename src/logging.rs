@@ -0,0 +1,71 @@
+use clap::ValueEnum;
+use env_logger::Env;
+use std::env;
+
+/// Parts of sn0int whose logs can be enabled on their own with `--debug`
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Subsystem {
+    Http,
+    Dns,
+    Db,
+    Sandbox,
+    Worker,
+}
+
+impl Subsystem {
+    /// The log targets that belong to the subsystem
+    fn targets(self) -> &'static [&'static str] {
+        match self {
+            Subsystem::Http => &["sn0int::runtime::http", "sn0int_std::web", "sn0int_std::websockets", "chrootable_https"],
+            Subsystem::Dns => &["sn0int::runtime::dns", "chrootable_https::dns", "trust_dns", "trust_dns_proto"],
+            Subsystem::Db => &["sn0int::db", "sn0int::models", "sn0int::migrations"],
+            Subsystem::Sandbox => &["sn0int::sandbox", "sn0int::ipc", "boxxy", "caps", "syscallz"],
+            Subsystem::Worker => &["sn0int::worker", "sn0int::engine", "sn0int::notify"],
+        }
+    }
+}
+
+/// The level of everything that isn't selected with `--debug`
+fn level(verbose: u8, quiet: bool) -> &'static str {
+    match (verbose, quiet) {
+        (_, true) | (0, _) => "off",
+        (1, _) => "warn",
+        (2, _) => "info",
+        (3, _) => "debug",
+        _ => "trace",
+    }
+}
+
+fn filter(verbose: u8, quiet: bool, debug: &[Subsystem]) -> String {
+    let mut filter = level(verbose, quiet).to_string();
+    for subsystem in debug {
+        for target in subsystem.targets() {
+            filter.push(',');
+            filter.push_str(target);
+            filter.push_str("=debug");
+        }
+    }
+    filter
+}
+
+/// Setup logging, `RUST_LOG` overrides the flags. The filter is passed on in
+/// the environment so the sandbox child logs the same way.
+pub fn init(verbose: u8, quiet: bool, debug: &[Subsystem]) {
+    if env::var_os("RUST_LOG").is_none() {
+        env::set_var("RUST_LOG", filter(verbose, quiet, debug));
+    }
+    env_logger::init_from_env(Env::default());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        assert_eq!(filter(0, false, &[]), "off");
+        assert_eq!(filter(2, true, &[]), "off");
+        assert_eq!(filter(2, false, &[]), "info");
+        assert_eq!(filter(0, false, &[Subsystem::Db]), "off,sn0int::db=debug,sn0int::models=debug,sn0int::migrations=debug");
+    }
+}
@@ -1,4 +1,3 @@
-use env_logger::Env;
 use sn0int::args::{self, Args, SubCommand};
 use sn0int::auth;
 use clap::Parser;
@@ -7,21 +6,29 @@ use sn0int::cmd::run_cmd::Params;
 use sn0int::config::Config;
 use sn0int::db;
 use sn0int::errors::*;
+use sn0int::fmt::colors::{self, paint, Style};
 use sn0int::engine::Module;
 use sn0int::geoip::{GeoIP, AsnDB, Maxmind};
 use sn0int::ipc;
+use sn0int::logging;
+use sn0int::notify;
 use sn0int::options::Opt;
+use sn0int::output::OutputFormat;
 use sn0int::paths;
 use sn0int::psl::PslReader;
 use sn0int::registry;
 use sn0int::repl;
 use sn0int::sandbox;
 use sn0int::shell;
-use std::fs::OpenOptions;
-use std::io::Write;
+use sn0int::term;
+use std::cmp;
 use std::path::Path;
 
 fn run_run(gargs: &Args, args: &args::Run, config: &Config) -> Result<()> {
+    // stdout is only used for events
+    if args.run.output == OutputFormat::Json {
+        term::diagnostics_to_stderr();
+    }
     let mut rl = shell::init(gargs, config, false)?;
 
     let module = args.run.module.as_ref()
@@ -54,6 +61,8 @@ fn run_run(gargs: &Args, args: &args::Run, config: &Config) -> Result<()> {
     let mut params = Params::from(args);
     // The module was already set and loaded
     params.module = None;
+    params.verbose = cmp::max(params.verbose, gargs.verbose);
+    params.quiet |= gargs.quiet;
 
     if args.dump_sandbox_init_msg {
         cmd::run_cmd::dump_sandbox_init_msg(&mut rl, params, Opt::collect(&args.options))
@@ -62,84 +71,87 @@ fn run_run(gargs: &Args, args: &args::Run, config: &Config) -> Result<()> {
     }
 }
 
-fn run_sandbox() -> Result<()> {
+fn run_sandbox(args: &args::Sandbox) -> Result<()> {
     let cache_dir = paths::cache_dir()?;
     let geoip = GeoIP::try_open_reader(&cache_dir)?;
     let asn = AsnDB::try_open_reader(&cache_dir)?;
     let psl = PslReader::open(&cache_dir)?;
 
-    sandbox::init()
+    sandbox::init(args.profile)
         .context("Failed to init sandbox")?;
     ipc::child::run(geoip, asn, psl)
 }
 
 fn run_cmd<T: cmd::Cmd>(gargs: &Args, args: T, config: &Config) -> Result<()> {
     let mut rl = shell::init(gargs, config, false)?;
-    args.run(&mut rl)
-}
-
-fn run_new(_gargs: &Args, args: &args::New) -> Result<()> {
-    // TODO: Add `-- Author: anonymous`
-    let boilerplate = b"-- Description: TODO your description here
--- Version: 0.1.0
--- License: GPL-3.0
-
-function run()
-    -- TODO your code here
-end
-";
-
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create_new(true)
-        .open(&args.path)?;
-    file.write_all(boilerplate)?;
-
-    Ok(())
+    let result = args.run(&mut rl);
+    // nothing that's still queued should be lost when we exit
+    notify::finish_run(&mut rl, &mut term::Term);
+    result
 }
 
-fn run() -> Result<()> {
-    let mut args = Args::parse();
-
+fn run(mut args: Args) -> Result<()> {
     if !args.is_sandbox() {
         sandbox::fasten_seatbelt()?;
     }
 
-    let config = Config::load_or_default()
+    colors::configure(args.color);
+    let mut config = Config::load_or_default()
         .context("Failed to load config")?;
+    colors::set_palette(&config.colors);
+    if args.offline {
+        config.network.offline = true;
+    }
+    config.network.apply_required_proxy()?;
 
     debug!("Loaded config: {:?}", config);
 
     match args.subcommand.take() {
         Some(SubCommand::Run(run)) => run_run(&args, &run, &config),
-        Some(SubCommand::Sandbox(_)) => run_sandbox(),
-        Some(SubCommand::Login(_)) => auth::run_login(&config),
-        Some(SubCommand::New(new)) => run_new(&args, &new),
+        Some(SubCommand::Sandbox(sandbox)) => run_sandbox(&sandbox),
+        Some(SubCommand::Login(login)) => auth::run_login(&config, login.registry.as_deref()),
+        Some(SubCommand::New(new)) => new.run(&config),
         Some(SubCommand::Publish(publish)) => registry::run_publish(&args, &publish, &config),
         // TODO: deprecate
         Some(SubCommand::Install(install)) => cmd::pkg_cmd::Args {
             subcommand: cmd::pkg_cmd::SubCommand::Install(install),
         }.run(&config),
-        // TODO: deprecate
-        Some(SubCommand::Search(search)) => cmd::pkg_cmd::Args {
-            subcommand: cmd::pkg_cmd::SubCommand::Search(search),
-        }.run(&config),
+        Some(SubCommand::Info(info)) => info.run(&config),
         Some(SubCommand::Pkg(pkg)) => pkg.run(&config),
         Some(SubCommand::Add(add)) => run_cmd(&args, add, &config),
         Some(SubCommand::Select(select)) => run_cmd(&args, select, &config),
         Some(SubCommand::Delete(delete)) => run_cmd(&args, delete, &config),
         Some(SubCommand::Activity(activity)) => run_cmd(&args, activity, &config),
+        Some(SubCommand::Audit(audit)) => run_cmd(&args, audit, &config),
+        Some(SubCommand::Provenance(provenance)) => run_cmd(&args, provenance, &config),
+        Some(SubCommand::Tree(tree)) => run_cmd(&args, tree, &config),
+        Some(SubCommand::Search(search)) => {
+            term::warn(&format!("The {} command searches the entities of the workspace now, use {} to search the registry", paint(Style::Bold, "search"), paint(Style::Bold, "pkg search")));
+            run_cmd(&args, search, &config)
+        },
+        Some(SubCommand::Db(db)) => run_cmd(&args, db, &config),
+        Some(SubCommand::Blobs(blobs)) => run_cmd(&args, blobs, &config),
         Some(SubCommand::Scope(scope)) => run_cmd(&args, scope, &config),
         Some(SubCommand::Noscope(noscope)) => run_cmd(&args, noscope, &config),
         Some(SubCommand::Autoscope(autoscope)) => run_cmd(&args, autoscope, &config),
         Some(SubCommand::Autonoscope(autonoscope)) => run_cmd(&args, autonoscope, &config),
         Some(SubCommand::Rescope(rescope)) => run_cmd(&args, rescope, &config),
         Some(SubCommand::Workspace(workspace)) => workspace.run(&config),
+        Some(SubCommand::Migrate(migrate)) => migrate.run(args.workspace(), &config),
+        Some(SubCommand::Bench(bench)) => bench.run(&config),
         Some(SubCommand::Fsck(fsck)) => run_cmd(&args, fsck, &config),
         Some(SubCommand::Export(export)) => run_cmd(&args, export, &config),
+        Some(SubCommand::Import(import)) => run_cmd(&args, import, &config),
+        Some(SubCommand::Keyring(keyring)) => run_cmd(&args, keyring, &config),
+        Some(SubCommand::View(view)) => run_cmd(&args, view, &config),
+        Some(SubCommand::Verify(verify)) => verify.run(&config),
         Some(SubCommand::Cal(cal)) => run_cmd(&args, cal, &config),
         Some(SubCommand::Notify(notify)) => run_cmd(&args, notify, &config),
         Some(SubCommand::Stats(stats)) => run_cmd(&args, stats, &config),
+        Some(SubCommand::Serve(serve)) => run_cmd(&args, serve, &config),
+        Some(SubCommand::Timeline(timeline)) => run_cmd(&args, timeline, &config),
+        Some(SubCommand::Diff(diff)) => run_cmd(&args, diff, &config),
+        Some(SubCommand::Scheduler(scheduler)) => scheduler.run(&config),
         Some(SubCommand::Repl) => repl::run(&config),
         Some(SubCommand::Paths) => paths::run(&config),
         Some(SubCommand::Completions(completions)) => completions.generate(),
@@ -148,14 +160,22 @@ fn run() -> Result<()> {
 }
 
 fn main() {
-    env_logger::init_from_env(Env::default()
-        .default_filter_or("off"));
-
-    if let Err(err) = run() {
-        eprintln!("Error: {}", err);
-        for cause in err.iter_chain().skip(1) {
-            eprintln!("Because: {}", cause);
+    let args = Args::parse();
+    logging::init(args.verbose, args.quiet, &args.debug);
+    let json = args.is_json();
+
+    if let Err(err) = run(args) {
+        let report = ErrorReport::new(&err);
+        if json {
+            let report = serde_json::to_string(&report)
+                .expect("Failed to serialize error");
+            eprintln!("{}", report);
+        } else {
+            eprintln!("Error: {}", report.message);
+            for cause in &report.causes {
+                eprintln!("Because: {}", cause);
+            }
         }
-        std::process::exit(1);
+        std::process::exit(report.exit_code);
     }
 }
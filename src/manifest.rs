@@ -0,0 +1,308 @@
+use crate::errors::*;
+use crate::paths;
+use chrono::NaiveDateTime;
+use data_encoding::{BASE64, HEXLOWER};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use sodiumoxide::crypto::sign::{self, PublicKey, SecretKey, Signature};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Describes the files of an export so the recipient can verify them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub workspace: String,
+    pub schema_version: Option<String>,
+    pub exported_at: NaiveDateTime,
+    pub format: String,
+    pub files: Vec<ManifestFile>,
+    pub counts: BTreeMap<String, usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<ManifestSignature>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestFile {
+    /// Relative to the folder of the manifest
+    pub path: String,
+    pub sha256: String,
+    pub bytes: u64,
+}
+
+impl ManifestFile {
+    pub fn new(path: String, content: &[u8]) -> ManifestFile {
+        ManifestFile {
+            path,
+            sha256: HEXLOWER.encode(&Sha256::digest(content)),
+            bytes: content.len() as u64,
+        }
+    }
+}
+
+/// Hashes a file while it's written, for exports that are too large to keep
+/// in memory
+pub struct HashWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl<W: Write> HashWriter<W> {
+    pub fn new(inner: W) -> HashWriter<W> {
+        HashWriter {
+            inner,
+            hasher: Sha256::new(),
+            bytes: 0,
+        }
+    }
+
+    pub fn finish(mut self, path: String) -> Result<ManifestFile> {
+        self.inner.flush()?;
+        Ok(ManifestFile {
+            path,
+            sha256: HEXLOWER.encode(&self.hasher.finalize()),
+            bytes: self.bytes,
+        })
+    }
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.bytes += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestSignature {
+    pub public_key: String,
+    pub signature: String,
+}
+
+impl Manifest {
+    /// The bytes that are signed, this is the manifest without its signature
+    fn signed_data(&self) -> Result<Vec<u8>> {
+        let mut unsigned = self.clone();
+        unsigned.signature = None;
+        let data = serde_json::to_vec(&unsigned)?;
+        Ok(data)
+    }
+
+    pub fn sign(&mut self, key: &SigningKey) -> Result<()> {
+        let data = self.signed_data()?;
+        self.signature = Some(ManifestSignature {
+            public_key: key.public_key(),
+            signature: key.sign(&data),
+        });
+        Ok(())
+    }
+
+    /// Returns the public key of the signer if the manifest is signed
+    pub fn verify_signature(&self) -> Result<Option<String>> {
+        let sig = match &self.signature {
+            Some(sig) => sig,
+            None => return Ok(None),
+        };
+
+        let public_key = BASE64.decode(sig.public_key.as_bytes())
+            .context("Failed to decode public key")?;
+        let public_key = PublicKey::from_slice(&public_key)
+            .ok_or_else(|| format_err!("Public key has wrong length"))?;
+        let signature = BASE64.decode(sig.signature.as_bytes())
+            .context("Failed to decode signature")?;
+        let signature = Signature::from_bytes(&signature)
+            .map_err(|_| format_err!("Signature has wrong length"))?;
+
+        let data = self.signed_data()?;
+        if !sign::verify_detached(&signature, &data, &public_key) {
+            bail!("Signature is invalid");
+        }
+        Ok(Some(sig.public_key.clone()))
+    }
+
+    /// Check every file against its hash, paths are relative to `base`
+    pub fn verify_files(&self, base: &Path) -> Result<()> {
+        for file in &self.files {
+            let path = Path::new(&file.path);
+            if path.is_absolute() || path.components().any(|c| c.as_os_str() == "..") {
+                bail!("Refusing to verify file outside of the manifest folder: {:?}", file.path);
+            }
+
+            let content = fs::read(base.join(path))
+                .context(format!("Failed to read {:?}", file.path))?;
+            let actual = ManifestFile::new(file.path.clone(), &content);
+            if actual != *file {
+                bail!("File has been modified: {:?}", file.path);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Manifest> {
+        let data = fs::read(path)
+            .context(format!("Failed to read {:?}", path))?;
+        let manifest = serde_json::from_slice(&data)
+            .context("Failed to parse manifest")?;
+        Ok(manifest)
+    }
+}
+
+/// The ed25519 key that is used to sign export manifests and published modules
+pub struct SigningKey {
+    public: PublicKey,
+    secret: SecretKey,
+}
+
+impl SigningKey {
+    fn path() -> Result<PathBuf> {
+        let path = paths::sn0int_dir()?;
+        Ok(path.join("signing.key"))
+    }
+
+    pub fn load_or_create() -> Result<SigningKey> {
+        let path = SigningKey::path()?;
+        if path.exists() {
+            let data = fs::read_to_string(&path)
+                .context("Failed to read signing key")?;
+            let secret = BASE64.decode(data.trim().as_bytes())
+                .context("Failed to decode signing key")?;
+            let secret = SecretKey::from_slice(&secret)
+                .ok_or_else(|| format_err!("Signing key has wrong length"))?;
+            Ok(SigningKey {
+                public: secret.public_key(),
+                secret,
+            })
+        } else {
+            let key = SigningKey::generate()?;
+            write_secret(&path, &BASE64.encode(key.secret.as_ref()))?;
+            Ok(key)
+        }
+    }
+
+    pub fn generate() -> Result<SigningKey> {
+        sodiumoxide::init()
+            .map_err(|_| format_err!("Failed to initialize libsodium"))?;
+        let (public, secret) = sign::gen_keypair();
+        Ok(SigningKey {
+            public,
+            secret,
+        })
+    }
+
+    pub fn public_key(&self) -> String {
+        BASE64.encode(self.public.as_ref())
+    }
+
+    /// Create a detached signature, encoded as base64
+    pub fn sign(&self, data: &[u8]) -> String {
+        let signature = sign::sign_detached(data, &self.secret);
+        BASE64.encode(signature.as_ref())
+    }
+}
+
+#[cfg(unix)]
+fn write_secret(path: &Path, data: &str) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .context("Failed to create signing key")?;
+    file.write_all(data.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn write_secret(path: &Path, data: &str) -> Result<()> {
+    fs::write(path, data)
+        .context("Failed to create signing key")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn manifest() -> Manifest {
+        let mut counts = BTreeMap::new();
+        counts.insert("domains".to_string(), 1);
+        Manifest {
+            workspace: "demo".to_string(),
+            schema_version: Some("20261014160000".to_string()),
+            exported_at: NaiveDateTime::from_str("2020-03-14T16:20:23").unwrap(),
+            format: "json".to_string(),
+            files: vec![ManifestFile::new("export.json".to_string(), b"{}\n")],
+            counts,
+            signature: None,
+        }
+    }
+
+    #[test]
+    fn test_file_hash() {
+        let file = ManifestFile::new("export.json".to_string(), b"{}\n");
+        assert_eq!(file.sha256, "ca3d163bab055381827226140568f3bef7eaac187cebd76878e0b63e9e442356");
+        assert_eq!(file.bytes, 3);
+    }
+
+    #[test]
+    fn test_hash_writer() {
+        let mut buf = Vec::new();
+        let mut w = HashWriter::new(&mut buf);
+        w.write_all(b"{").unwrap();
+        w.write_all(b"}\n").unwrap();
+        let file = w.finish("export.json".to_string()).unwrap();
+        assert_eq!(file, ManifestFile::new("export.json".to_string(), b"{}\n"));
+        assert_eq!(buf, b"{}\n");
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let key = SigningKey::generate().unwrap();
+        let mut manifest = manifest();
+        manifest.sign(&key).unwrap();
+        assert_eq!(manifest.verify_signature().unwrap(), Some(key.public_key()));
+    }
+
+    #[test]
+    fn test_unsigned() {
+        assert_eq!(manifest().verify_signature().unwrap(), None);
+    }
+
+    #[test]
+    fn test_tampered() {
+        let mut manifest = manifest();
+        manifest.sign(&SigningKey::generate().unwrap()).unwrap();
+        manifest.counts.insert("domains".to_string(), 2);
+        assert!(manifest.verify_signature().is_err());
+    }
+
+    #[test]
+    fn test_verify_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = manifest();
+        fs::write(dir.path().join("export.json"), b"{}\n").unwrap();
+        manifest.verify_files(dir.path()).unwrap();
+
+        fs::write(dir.path().join("export.json"), b"[]\n").unwrap();
+        assert!(manifest.verify_files(dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_verify_files_outside() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manifest = manifest();
+        manifest.files[0].path = "../export.json".to_string();
+        assert!(manifest.verify_files(dir.path()).is_err());
+    }
+}
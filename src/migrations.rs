@@ -1,11 +1,187 @@
 #![allow(unused_imports)]
 use crate::errors::*;
 
+use diesel::prelude::*;
 use diesel::sqlite::*;
+use diesel_migrations::MigrationConnection;
+use lazy_static::lazy_static;
+use std::collections::HashSet;
+use std::result;
 
 embed_migrations!();
 
+#[derive(Debug, PartialEq)]
+pub enum Status {
+    /// The database has never been migrated
+    Fresh,
+    UpToDate,
+    /// Migrations this binary would need to apply
+    Pending(Vec<String>),
+    /// Migrations that have been applied by a newer version of sn0int
+    Unknown(Vec<String>),
+}
+
 pub fn run(conn: &SqliteConnection) -> Result<()> {
     embedded_migrations::run(conn)?;
+    backfill(conn)?;
+    Ok(())
+}
+
+/// Apply all pending migrations and return the versions that have been applied
+pub fn run_verbose(conn: &SqliteConnection) -> Result<Vec<String>> {
+    let mut buf = Vec::new();
+    embedded_migrations::run_with_output(conn, &mut buf)?;
+    backfill(conn)?;
+    Ok(parse_output(&buf))
+}
+
+/// Fill in columns of existing rows that can't be computed in sql, new rows
+/// always have them set
+fn backfill(conn: &SqliteConnection) -> Result<()> {
+    use crate::models::is_syntax_valid;
+    use crate::schema::emails::dsl::*;
+
+    let unchecked = emails.filter(syntax_valid.is_null())
+        .select((id, value))
+        .load::<(i32, String)>(conn)?;
+    if unchecked.is_empty() {
+        return Ok(());
+    }
+
+    debug!("Checking syntax of {} existing emails", unchecked.len());
+    conn.transaction::<_, Error, _>(|| {
+        for (row, email) in unchecked {
+            diesel::update(emails.filter(id.eq(row)))
+                .set(syntax_valid.eq(is_syntax_valid(&email)))
+                .execute(conn)?;
+        }
+        Ok(())
+    }).context("Failed to check syntax of existing emails")?;
     Ok(())
 }
+
+fn parse_output(buf: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(buf)
+        .lines()
+        .filter_map(|line| line.strip_prefix("Running migration "))
+        .map(String::from)
+        .collect()
+}
+
+lazy_static! {
+    static ref EXPECTED_VERSIONS: result::Result<Vec<String>, String> = migrate_memory()
+        .map_err(|err| err.to_string());
+}
+
+/// The embedded migrations aren't exposed by diesel, apply them to an empty
+/// in-memory database to find out which versions we ship.
+fn migrate_memory() -> Result<Vec<String>> {
+    let conn = SqliteConnection::establish(":memory:")
+        .context("Failed to create in-memory database")?;
+    let mut versions = run_verbose(&conn)?;
+    versions.sort();
+    Ok(versions)
+}
+
+/// The versions we ship, this is only computed once per process
+pub fn expected_versions() -> Result<Vec<String>> {
+    EXPECTED_VERSIONS.clone()
+        .map_err(|err| format_err!("{}", err))
+}
+
+pub fn applied_versions(conn: &SqliteConnection) -> Result<Vec<String>> {
+    diesel_migrations::setup_database(conn)?;
+    let mut versions = conn.previously_run_migration_versions()?
+        .into_iter()
+        .collect::<Vec<_>>();
+    versions.sort();
+    Ok(versions)
+}
+
+pub fn status(conn: &SqliteConnection) -> Result<Status> {
+    let expected = expected_versions()?;
+    let applied = applied_versions(conn)?;
+
+    if applied.is_empty() {
+        return Ok(Status::Fresh);
+    }
+
+    let known = expected.iter().collect::<HashSet<_>>();
+    let unknown = applied.iter()
+        .filter(|v| !known.contains(v))
+        .cloned()
+        .collect::<Vec<_>>();
+    if !unknown.is_empty() {
+        return Ok(Status::Unknown(unknown));
+    }
+
+    let applied = applied.iter().collect::<HashSet<_>>();
+    let pending = expected.iter()
+        .filter(|v| !applied.contains(v))
+        .cloned()
+        .collect::<Vec<_>>();
+    if !pending.is_empty() {
+        return Ok(Status::Pending(pending));
+    }
+
+    Ok(Status::UpToDate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory() -> SqliteConnection {
+        SqliteConnection::establish(":memory:").unwrap()
+    }
+
+    #[test]
+    fn test_expected_versions() {
+        let versions = expected_versions().unwrap();
+        assert!(!versions.is_empty());
+        assert!(versions.iter().all(|v| v.chars().all(|c| c.is_ascii_digit())));
+    }
+
+    #[test]
+    fn test_status_fresh_and_up_to_date() {
+        let conn = memory();
+        assert_eq!(status(&conn).unwrap(), Status::Fresh);
+        run(&conn).unwrap();
+        assert_eq!(status(&conn).unwrap(), Status::UpToDate);
+    }
+
+    #[test]
+    fn test_status_pending() {
+        let conn = memory();
+        run(&conn).unwrap();
+        let last = expected_versions().unwrap().pop().unwrap();
+        conn.execute(&format!("DELETE FROM __diesel_schema_migrations WHERE version = '{}'", last)).unwrap();
+        assert_eq!(status(&conn).unwrap(), Status::Pending(vec![last]));
+    }
+
+    #[test]
+    fn test_backfill_syntax_valid() {
+        let conn = memory();
+        run(&conn).unwrap();
+        conn.execute("INSERT INTO emails (value, unscoped) VALUES ('foo@example.com', 0), ('foo', 0)").unwrap();
+        run(&conn).unwrap();
+
+        use crate::schema::emails::dsl::*;
+        let rows = emails.select((value, syntax_valid))
+            .order_by(id)
+            .load::<(String, Option<bool>)>(&conn)
+            .unwrap();
+        assert_eq!(rows, vec![
+            ("foo@example.com".to_string(), Some(true)),
+            ("foo".to_string(), Some(false)),
+        ]);
+    }
+
+    #[test]
+    fn test_status_unknown() {
+        let conn = memory();
+        run(&conn).unwrap();
+        conn.execute("INSERT INTO __diesel_schema_migrations (version) VALUES ('99991231000000')").unwrap();
+        assert_eq!(status(&conn).unwrap(), Status::Unknown(vec![String::from("99991231000000")]));
+    }
+}
@@ -20,6 +20,10 @@ pub struct Account {
     pub phonenumber: Option<String>,
     pub profile_pic: Option<String>,
     pub birthday: Option<String>,
+
+    pub seen_count: i32,
+    pub seen_first: Option<NaiveDateTime>,
+    pub seen_last: Option<NaiveDateTime>,
 }
 
 impl Model for Account {
@@ -46,6 +50,30 @@ impl Model for Account {
         Ok(results)
     }
 
+    fn filter_page(db: &Database, filter: &Filter, after: i32, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::accounts::dsl::*;
+
+        let query = accounts.filter(filter.sql())
+            .filter(id.gt(after))
+            .order(id)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
+    fn filter_range(db: &Database, filter: &Filter, offset: usize, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::accounts::dsl::*;
+
+        let query = accounts.filter(filter.sql())
+            .order(id)
+            .offset(offset as i64)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
     fn filter_with_param(db: &Database, filter: &Filter, param: &str) -> Result<Vec<Self>> {
         use crate::schema::accounts::dsl::*;
 
@@ -111,6 +139,10 @@ impl Model for Account {
 }
 
 impl Scopable for Account {
+    fn table() -> crate::db::Table {
+        crate::db::Table::Accounts
+    }
+
     fn scoped(&self) -> bool {
         !self.unscoped
     }
@@ -1,4 +1,5 @@
 use crate::errors::*;
+use crate::config::DedupPolicy;
 use serde::{Serialize, Deserialize};
 use crate::schema::activity;
 use diesel::prelude::*;
@@ -84,6 +85,46 @@ impl Activity {
             .map_err(Error::from)
     }
 
+    /// Check if an event doesn't add anything new according to the dedup
+    /// policy of its topic
+    pub fn is_duplicate(db: &Database, event: &NewActivity, policy: &DedupPolicy) -> Result<bool> {
+        use crate::schema::activity::dsl::*;
+
+        let content_json = serde_json::from_str::<serde_json::Value>(&event.content)?;
+        let key = policy.key(&content_json);
+        let same = |other: &Activity| -> Result<bool> {
+            let other = serde_json::from_str::<serde_json::Value>(&other.content)?;
+            Ok(policy.key(&other) == key)
+        };
+
+        if policy.on_change {
+            let previous = activity.filter(topic.eq(&event.topic))
+                .filter(time.le(event.time))
+                .order_by((time.desc(), id.desc()))
+                .first::<Self>(db.db())
+                .optional()?;
+            if let Some(previous) = previous {
+                if same(&previous)? {
+                    return Ok(true);
+                }
+            }
+        }
+
+        if let Some(window) = policy.window()? {
+            let nearby = activity.filter(topic.eq(&event.topic))
+                .filter(time.ge(event.time - window))
+                .filter(time.le(event.time + window))
+                .load::<Self>(db.db())?;
+            for other in &nearby {
+                if same(other)? {
+                    return Ok(true);
+                }
+            }
+        }
+
+        Ok(false)
+    }
+
     pub fn count(db: &Database) -> Result<usize> {
         use crate::schema::activity::dsl::*;
         activity.count()
@@ -194,3 +235,58 @@ impl InsertToNew for InsertActivity {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+
+    fn event(time: NaiveDateTime, content: serde_json::Value) -> NewActivity {
+        NewActivity {
+            topic: "kpcyrd/ping:example.com".to_string(),
+            time,
+            uniq: None,
+            latitude: None,
+            longitude: None,
+            radius: None,
+            content: content.to_string(),
+        }
+    }
+
+    fn policy(window: Option<&str>, on_change: bool) -> DedupPolicy {
+        DedupPolicy {
+            topic: "kpcyrd/ping:*".to_string(),
+            window: window.map(String::from),
+            on_change,
+            key: vec!["status".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_dedup_window() {
+        let db = Database::memory().unwrap();
+        let policy = policy(Some("1h"), false);
+        let now = Utc::now().naive_utc();
+
+        assert!(db.insert_activity(event(now, serde_json::json!({"status": "up", "rtt": 12})), Some(&policy)).unwrap());
+        // only the key is compared
+        assert!(!db.insert_activity(event(now + Duration::minutes(30), serde_json::json!({"status": "up", "rtt": 15})), Some(&policy)).unwrap());
+        assert!(db.insert_activity(event(now + Duration::minutes(30), serde_json::json!({"status": "down"})), Some(&policy)).unwrap());
+        assert!(db.insert_activity(event(now + Duration::hours(2), serde_json::json!({"status": "up"})), Some(&policy)).unwrap());
+        assert_eq!(Activity::count(&db).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_dedup_on_change() {
+        let db = Database::memory().unwrap();
+        let policy = policy(None, true);
+        let now = Utc::now().naive_utc();
+
+        for (minutes, status, inserted) in &[(0, "up", true), (10, "up", false), (20, "down", true), (30, "up", true), (40, "up", false)] {
+            let event = event(now + Duration::minutes(*minutes), serde_json::json!({"status": status}));
+            assert_eq!(db.insert_activity(event, Some(&policy)).unwrap(), *inserted);
+        }
+        // without a policy every event is logged
+        assert!(db.insert_activity(event(now, serde_json::json!({"status": "up"})), None).unwrap());
+    }
+}
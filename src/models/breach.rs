@@ -1,4 +1,5 @@
 use crate::errors::*;
+use chrono::NaiveDateTime;
 use serde::{Serialize, Deserialize};
 use crate::fmt::colors::*;
 use diesel::prelude::*;
@@ -10,6 +11,10 @@ pub struct Breach {
     pub id: i32,
     pub value: String,
     pub unscoped: bool,
+
+    pub seen_count: i32,
+    pub seen_first: Option<NaiveDateTime>,
+    pub seen_last: Option<NaiveDateTime>,
 }
 
 impl Model for Breach {
@@ -36,6 +41,30 @@ impl Model for Breach {
         Ok(results)
     }
 
+    fn filter_page(db: &Database, filter: &Filter, after: i32, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::breaches::dsl::*;
+
+        let query = breaches.filter(filter.sql())
+            .filter(id.gt(after))
+            .order(id)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
+    fn filter_range(db: &Database, filter: &Filter, offset: usize, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::breaches::dsl::*;
+
+        let query = breaches.filter(filter.sql())
+            .order(id)
+            .offset(offset as i64)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
     fn delete(db: &Database, filter: &Filter) -> Result<usize> {
         use crate::schema::breaches::dsl::*;
 
@@ -90,6 +119,10 @@ impl Model for Breach {
 }
 
 impl Scopable for Breach {
+    fn table() -> crate::db::Table {
+        crate::db::Table::Breaches
+    }
+
     fn scoped(&self) -> bool {
         !self.unscoped
     }
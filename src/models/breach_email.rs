@@ -38,6 +38,30 @@ impl Model for BreachEmail {
         Ok(results)
     }
 
+    fn filter_page(db: &Database, filter: &Filter, after: i32, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::breach_emails::dsl::*;
+
+        let query = breach_emails.filter(filter.sql())
+            .filter(id.gt(after))
+            .order(id)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
+    fn filter_range(db: &Database, filter: &Filter, offset: usize, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::breach_emails::dsl::*;
+
+        let query = breach_emails.filter(filter.sql())
+            .order(id)
+            .offset(offset as i64)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
     fn delete(db: &Database, filter: &Filter) -> Result<usize> {
         use crate::schema::breach_emails::dsl::*;
 
@@ -18,6 +18,10 @@ pub struct CryptoAddr {
     pub last_withdrawal: Option<NaiveDateTime>,
     pub unscoped: bool,
     pub description: Option<String>,
+
+    pub seen_count: i32,
+    pub seen_first: Option<NaiveDateTime>,
+    pub seen_last: Option<NaiveDateTime>,
 }
 
 impl Model for CryptoAddr {
@@ -44,6 +48,30 @@ impl Model for CryptoAddr {
         Ok(results)
     }
 
+    fn filter_page(db: &Database, filter: &Filter, after: i32, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::cryptoaddrs::dsl::*;
+
+        let query = cryptoaddrs.filter(filter.sql())
+            .filter(id.gt(after))
+            .order(id)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
+    fn filter_range(db: &Database, filter: &Filter, offset: usize, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::cryptoaddrs::dsl::*;
+
+        let query = cryptoaddrs.filter(filter.sql())
+            .order(id)
+            .offset(offset as i64)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
     fn filter_with_param(db: &Database, filter: &Filter, param: &str) -> Result<Vec<Self>> {
         use crate::schema::cryptoaddrs::dsl::*;
 
@@ -109,6 +137,10 @@ impl Model for CryptoAddr {
 }
 
 impl Scopable for CryptoAddr {
+    fn table() -> crate::db::Table {
+        crate::db::Table::Cryptoaddrs
+    }
+
     fn scoped(&self) -> bool {
         !self.unscoped
     }
@@ -15,6 +15,10 @@ pub struct Device {
     pub vendor: Option<String>,
     pub unscoped: bool,
     pub last_seen: Option<NaiveDateTime>,
+
+    pub seen_count: i32,
+    pub seen_first: Option<NaiveDateTime>,
+    pub seen_last: Option<NaiveDateTime>,
 }
 
 impl Model for Device {
@@ -41,6 +45,30 @@ impl Model for Device {
         Ok(results)
     }
 
+    fn filter_page(db: &Database, filter: &Filter, after: i32, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::devices::dsl::*;
+
+        let query = devices.filter(filter.sql())
+            .filter(id.gt(after))
+            .order(id)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
+    fn filter_range(db: &Database, filter: &Filter, offset: usize, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::devices::dsl::*;
+
+        let query = devices.filter(filter.sql())
+            .order(id)
+            .offset(offset as i64)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
     fn delete(db: &Database, filter: &Filter) -> Result<usize> {
         use crate::schema::devices::dsl::*;
 
@@ -95,6 +123,10 @@ impl Model for Device {
 }
 
 impl Scopable for Device {
+    fn table() -> crate::db::Table {
+        crate::db::Table::Devices
+    }
+
     fn scoped(&self) -> bool {
         !self.unscoped
     }
@@ -1,8 +1,10 @@
 use crate::errors::*;
+use chrono::NaiveDateTime;
 use serde::{Serialize, Deserialize};
 use crate::fmt::colors::*;
 use diesel::prelude::*;
 use crate::models::*;
+use std::net::{Ipv4Addr, Ipv6Addr};
 
 #[derive(Identifiable, Queryable, Serialize, Deserialize, PartialEq, Debug)]
 #[table_name="emails"]
@@ -12,6 +14,11 @@ pub struct Email {
     pub unscoped: bool,
     pub valid: Option<bool>,
     pub displayname: Option<String>,
+
+    pub seen_count: i32,
+    pub seen_first: Option<NaiveDateTime>,
+    pub seen_last: Option<NaiveDateTime>,
+    pub syntax_valid: Option<bool>,
 }
 
 impl Model for Email {
@@ -38,6 +45,30 @@ impl Model for Email {
         Ok(results)
     }
 
+    fn filter_page(db: &Database, filter: &Filter, after: i32, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::emails::dsl::*;
+
+        let query = emails.filter(filter.sql())
+            .filter(id.gt(after))
+            .order(id)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
+    fn filter_range(db: &Database, filter: &Filter, offset: usize, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::emails::dsl::*;
+
+        let query = emails.filter(filter.sql())
+            .order(id)
+            .offset(offset as i64)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
     fn delete(db: &Database, filter: &Filter) -> Result<usize> {
         use crate::schema::emails::dsl::*;
 
@@ -92,6 +123,10 @@ impl Model for Email {
 }
 
 impl Scopable for Email {
+    fn table() -> crate::db::Table {
+        crate::db::Table::Emails
+    }
+
     fn scoped(&self) -> bool {
         !self.unscoped
     }
@@ -183,6 +218,7 @@ pub struct DetailedEmail {
     breaches: Vec<BreachWithPassword>,
     unscoped: bool,
     valid: Option<bool>,
+    syntax_valid: Option<bool>,
 }
 
 impl DisplayableDetailed for DetailedEmail {
@@ -199,6 +235,10 @@ impl DisplayableDetailed for DetailedEmail {
         w.start_group();
         w.opt_debug::<Yellow, _>(&self.displayname)?;
 
+        if self.syntax_valid == Some(false) {
+            w.display::<Red, _>("invalid syntax")?;
+        }
+
         if let Some(valid) = self.valid {
             if valid {
                 w.display::<Green, _>("valid")?;
@@ -241,6 +281,7 @@ impl Detailed for Email {
             breaches,
             unscoped: self.unscoped,
             valid: self.valid,
+            syntax_valid: self.syntax_valid,
         })
     }
 }
@@ -252,6 +293,7 @@ pub struct NewEmail {
     pub displayname: Option<String>,
     pub valid: Option<bool>,
     pub unscoped: bool,
+    pub syntax_valid: Option<bool>,
 }
 
 impl InsertableStruct<Email> for NewEmail {
@@ -279,6 +321,7 @@ impl Upsertable<Email> for NewEmail {
             id: existing.id,
             displayname: Self::upsert_opt(self.displayname, &existing.displayname),
             valid: Self::upsert_opt(self.valid, &existing.valid),
+            syntax_valid: Self::upsert_opt(self.syntax_valid, &existing.syntax_valid),
         }
     }
 }
@@ -303,12 +346,14 @@ impl InsertToNew for InsertEmail {
 
     fn try_into_new(self) -> Result<NewEmail> {
         let value = self.value.to_lowercase();
+        let syntax_valid = Some(is_syntax_valid(&value));
         Ok(NewEmail {
             value,
             displayname: self.displayname,
             valid: self.valid,
 
             unscoped: false,
+            syntax_valid,
         })
     }
 }
@@ -319,12 +364,14 @@ pub struct EmailUpdate {
     pub id: i32,
     pub displayname: Option<String>,
     pub valid: Option<bool>,
+    pub syntax_valid: Option<bool>,
 }
 
 impl Upsert for EmailUpdate {
     fn is_dirty(&self) -> bool {
         self.displayname.is_some() ||
-            self.valid.is_some()
+            self.valid.is_some() ||
+            self.syntax_valid.is_some()
     }
 
     fn generic(self) -> Update {
@@ -340,11 +387,64 @@ impl Updateable<Email> for EmailUpdate {
     fn changeset(&mut self, existing: &Email) {
         Self::clear_if_equal(&mut self.displayname, &existing.displayname);
         Self::clear_if_equal(&mut self.valid, &existing.valid);
+        Self::clear_if_equal(&mut self.syntax_valid, &existing.syntax_valid);
     }
 
     fn fmt(&self, updates: &mut Vec<String>, colors: bool) {
         Self::push_value(updates, "displayname", &self.displayname, colors);
         Self::push_value(updates, "valid", &self.valid, colors);
+        Self::push_value(updates, "syntax_valid", &self.syntax_valid, colors);
+    }
+}
+
+fn is_atext(c: char) -> bool {
+    c.is_ascii_alphanumeric() || !c.is_ascii() || "!#$%&'*+-/=?^_`{|}~".contains(c)
+}
+
+fn is_local_part_valid(local: &str) -> bool {
+    if local.len() > 64 {
+        false
+    } else if let Some(quoted) = local.strip_prefix('"').and_then(|x| x.strip_suffix('"')) {
+        let mut chars = quoted.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if chars.next().is_none() => return false,
+                '"' => return false,
+                c if c.is_control() => return false,
+                _ => (),
+            }
+        }
+        true
+    } else {
+        local.split('.')
+            .all(|atom| !atom.is_empty() && atom.chars().all(is_atext))
+    }
+}
+
+fn is_domain_valid(domain: &str) -> bool {
+    if let Some(literal) = domain.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+        match literal.strip_prefix("IPv6:") {
+            Some(ip) => ip.parse::<Ipv6Addr>().is_ok(),
+            None => literal.parse::<Ipv4Addr>().is_ok(),
+        }
+    } else {
+        domain.len() <= 253 &&
+            domain.contains('.') &&
+            domain.split('.').all(|label| {
+                !label.is_empty() &&
+                    label.len() <= 63 &&
+                    !label.starts_with('-') &&
+                    !label.ends_with('-') &&
+                    label.chars().all(|c| c.is_alphanumeric() || c == '-')
+            })
+    }
+}
+
+/// Check the syntax of an address, this doesn't tell if the mailbox exists
+pub fn is_syntax_valid(email: &str) -> bool {
+    match email.rsplit_once('@') {
+        Some((local, domain)) => is_local_part_valid(local) && is_domain_valid(domain),
+        None => false,
     }
 }
 
@@ -364,6 +464,43 @@ mod tests {
             displayname: None,
             valid: None,
             unscoped: false,
+            syntax_valid: Some(true),
         });
     }
+
+    #[test]
+    fn test_syntax_valid() {
+        for email in &[
+            "foo@example.com",
+            "foo.bar+tag@sub.example.co.uk",
+            "o'hara@example.com",
+            "\"foo bar\"@example.com",
+            "root@[127.0.0.1]",
+            "root@[IPv6:::1]",
+            "jörg@bücher.example",
+        ] {
+            assert!(is_syntax_valid(email), "{:?}", email);
+        }
+    }
+
+    #[test]
+    fn test_syntax_invalid() {
+        for email in &[
+            "",
+            "foo",
+            "@example.com",
+            "foo@",
+            "foo@localhost",
+            "foo..bar@example.com",
+            ".foo@example.com",
+            "foo bar@example.com",
+            "foo@exa mple.com",
+            "foo@-example.com",
+            "foo@example..com",
+            "foo@[999.0.0.1]",
+            "foo@bar@example.com",
+        ] {
+            assert!(!is_syntax_valid(email), "{:?}", email);
+        }
+    }
 }
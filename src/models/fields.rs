@@ -0,0 +1,183 @@
+use crate::errors::*;
+use chrono::NaiveDateTime;
+use serde::{Serialize, Deserialize};
+use crate::db::Database;
+use crate::fmt::colors::{paint, Style};
+use crate::models::Upsert;
+use super::Update;
+
+
+/// Columns that identify an entity and must never change
+pub const IMMUTABLE_FIELDS: &[&str] = &["id", "value", "unscoped"];
+
+/// Columns that track how often an entity has been observed, these are
+/// maintained by the database layer and can't be set from a module
+pub const SEEN_FIELDS: &[&str] = &["seen_count", "seen_first", "seen_last"];
+
+/// An update of arbitrary columns, checked against the schema at runtime
+/// instead of being wired through a typed changeset.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldsUpdate {
+    pub table: String,
+    pub id: i32,
+    pub fields: Vec<(String, FieldValue)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FieldValue {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    Text(String),
+    Datetime(NaiveDateTime),
+}
+
+impl FieldValue {
+    /// Convert a json value into the type the column has been declared with
+    pub fn from_json(sql_type: &str, value: serde_json::Value) -> Result<FieldValue> {
+        use serde_json::Value;
+
+        let sql_type = sql_type.split('(').next().unwrap_or("").trim().to_uppercase();
+        let value = match value {
+            Value::Null => FieldValue::Null,
+            Value::Bool(b) => FieldValue::Bool(b),
+            Value::Number(n) => {
+                if let Some(n) = n.as_i64() {
+                    FieldValue::Integer(n)
+                } else if let Some(n) = n.as_f64() {
+                    FieldValue::Float(n)
+                } else {
+                    bail!("Number is out of range: {}", n)
+                }
+            },
+            Value::String(s) if sql_type == "DATETIME" => {
+                let s = s.replace(' ', "T");
+                let dt = s.parse::<NaiveDateTime>()
+                    .context("Failed to parse datetime")?;
+                FieldValue::Datetime(dt)
+            },
+            Value::String(s) => FieldValue::Text(s),
+            v => bail!("Unsupported value: {:?}", v),
+        };
+
+        match (sql_type.as_str(), &value) {
+            (_, FieldValue::Null) => (),
+            ("BOOLEAN", FieldValue::Bool(_)) => (),
+            ("BOOLEAN", _) => bail!("Expected boolean"),
+            ("INTEGER", FieldValue::Integer(_)) | ("INT", FieldValue::Integer(_)) | ("BIGINT", FieldValue::Integer(_)) => (),
+            ("INTEGER", _) | ("INT", _) | ("BIGINT", _) => bail!("Expected integer"),
+            ("FLOAT", FieldValue::Integer(_)) | ("FLOAT", FieldValue::Float(_)) => (),
+            ("FLOAT", _) => bail!("Expected number"),
+            ("VARCHAR", FieldValue::Text(_)) | ("TEXT", FieldValue::Text(_)) => (),
+            ("VARCHAR", _) | ("TEXT", _) => bail!("Expected string"),
+            _ => (),
+        }
+
+        Ok(value)
+    }
+
+    /// The value like it's serialized as part of an entity
+    pub fn to_json(&self) -> serde_json::Value {
+        use serde_json::Value;
+
+        match self {
+            FieldValue::Null => Value::Null,
+            FieldValue::Bool(b) => Value::Bool(*b),
+            FieldValue::Integer(n) => Value::from(*n),
+            FieldValue::Float(n) => Value::from(*n),
+            FieldValue::Text(s) => Value::String(s.clone()),
+            FieldValue::Datetime(dt) => serde_json::to_value(dt).unwrap_or_default(),
+        }
+    }
+
+    fn display(&self) -> String {
+        match self {
+            FieldValue::Null => "None".to_string(),
+            FieldValue::Bool(b) => b.to_string(),
+            FieldValue::Integer(n) => n.to_string(),
+            FieldValue::Float(n) => n.to_string(),
+            FieldValue::Text(s) => format!("{:?}", s),
+            FieldValue::Datetime(dt) => format!("{:?}", dt),
+        }
+    }
+}
+
+impl FieldsUpdate {
+    fn fmt(&self, colors: bool) -> String {
+        self.fields.iter()
+            .map(|(name, value)| if colors {
+                format!("{} => {}", name, paint(Style::Yellow, value.display()))
+            } else {
+                format!("{} => {}", name, value.display())
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    pub fn to_plain_str(&self) -> String {
+        self.fmt(false)
+    }
+
+    pub fn to_json(&self) -> serde_json::Map<String, serde_json::Value> {
+        self.fields.iter()
+            .map(|(name, value)| (name.clone(), value.to_json()))
+            .collect()
+    }
+
+    pub fn to_term_str(&self) -> String {
+        self.fmt(true)
+    }
+}
+
+impl Upsert for FieldsUpdate {
+    fn is_dirty(&self) -> bool {
+        !self.fields.is_empty()
+    }
+
+    fn generic(self) -> Update {
+        Update::Fields(self)
+    }
+
+    fn apply(&self, db: &Database) -> Result<i32> {
+        db.update_fields(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_field_value_datetime() {
+        let v = FieldValue::from_json("DATETIME", "2020-01-02 03:04:05".into()).unwrap();
+        let dt = "2020-01-02T03:04:05".parse::<NaiveDateTime>().unwrap();
+        assert_eq!(v, FieldValue::Datetime(dt));
+    }
+
+    #[test]
+    fn test_field_value_type_mismatch() {
+        assert!(FieldValue::from_json("BOOLEAN", "yes".into()).is_err());
+        assert!(FieldValue::from_json("INTEGER", 1.5.into()).is_err());
+        assert!(FieldValue::from_json("TEXT", 1.into()).is_err());
+    }
+
+    #[test]
+    fn test_field_value_null() {
+        let v = FieldValue::from_json("INTEGER", serde_json::Value::Null).unwrap();
+        assert_eq!(v, FieldValue::Null);
+    }
+
+    #[test]
+    fn test_fields_update_fmt() {
+        let update = FieldsUpdate {
+            table: "subdomains".to_string(),
+            id: 1,
+            fields: vec![
+                ("resolvable".to_string(), FieldValue::Bool(true)),
+                ("asn".to_string(), FieldValue::Integer(13335)),
+            ],
+        };
+        assert_eq!(update.to_plain_str(), "resolvable => true, asn => 13335");
+    }
+}
@@ -28,6 +28,10 @@ pub struct Image {
     pub phash: Option<String>,
 
     pub unscoped: bool,
+
+    pub seen_count: i32,
+    pub seen_first: Option<NaiveDateTime>,
+    pub seen_last: Option<NaiveDateTime>,
 }
 
 impl Model for Image {
@@ -54,6 +58,30 @@ impl Model for Image {
         Ok(results)
     }
 
+    fn filter_page(db: &Database, filter: &Filter, after: i32, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::images::dsl::*;
+
+        let query = images.filter(filter.sql())
+            .filter(id.gt(after))
+            .order(id)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
+    fn filter_range(db: &Database, filter: &Filter, offset: usize, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::images::dsl::*;
+
+        let query = images.filter(filter.sql())
+            .order(id)
+            .offset(offset as i64)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
     fn delete(db: &Database, filter: &Filter) -> Result<usize> {
         use crate::schema::images::dsl::*;
 
@@ -112,6 +140,10 @@ impl Model for Image {
 }
 
 impl Scopable for Image {
+    fn table() -> crate::db::Table {
+        crate::db::Table::Images
+    }
+
     fn scoped(&self) -> bool {
         !self.unscoped
     }
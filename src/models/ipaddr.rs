@@ -1,4 +1,5 @@
 use crate::errors::*;
+use chrono::NaiveDateTime;
 use serde::{Serialize, Deserialize};
 use crate::fmt::Write;
 use crate::fmt::colors::*;
@@ -25,6 +26,10 @@ pub struct IpAddr {
     pub as_org: Option<String>,
     pub description: Option<String>,
     pub reverse_dns: Option<String>,
+
+    pub seen_count: i32,
+    pub seen_first: Option<NaiveDateTime>,
+    pub seen_last: Option<NaiveDateTime>,
 }
 
 impl Model for IpAddr {
@@ -51,6 +56,30 @@ impl Model for IpAddr {
         Ok(results)
     }
 
+    fn filter_page(db: &Database, filter: &Filter, after: i32, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::ipaddrs::dsl::*;
+
+        let query = ipaddrs.filter(filter.sql())
+            .filter(id.gt(after))
+            .order(id)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
+    fn filter_range(db: &Database, filter: &Filter, offset: usize, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::ipaddrs::dsl::*;
+
+        let query = ipaddrs.filter(filter.sql())
+            .order(id)
+            .offset(offset as i64)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
     fn delete(db: &Database, filter: &Filter) -> Result<usize> {
         use crate::schema::ipaddrs::dsl::*;
 
@@ -105,6 +134,10 @@ impl Model for IpAddr {
 }
 
 impl Scopable for IpAddr {
+    fn table() -> crate::db::Table {
+        crate::db::Table::Ipaddrs
+    }
+
     fn scoped(&self) -> bool {
         !self.unscoped
     }
@@ -3,8 +3,11 @@ use serde::{Serialize, Deserialize};
 use crate::engine::ctx::State;
 use crate::errors::*;
 use crate::fmt;
+use crate::fmt::colors::{paint, Style};
 use crate::schema::*;
 use std::borrow::Cow;
+use std::cmp;
+use std::str::FromStr;
 use std::sync::Arc;
 
 
@@ -134,6 +137,7 @@ pub enum Update {
     Port(PortUpdate),
     Netblock(NetblockUpdate),
     CryptoAddr(CryptoAddrUpdate),
+    Fields(FieldsUpdate),
 }
 
 impl Update {
@@ -153,9 +157,62 @@ impl Update {
             Update::Port(update)          => update.is_dirty(),
             Update::Netblock(update)      => update.is_dirty(),
             Update::CryptoAddr(update)    => update.is_dirty(),
+            Update::Fields(update)        => update.is_dirty(),
         }
     }
 
+    /// The new values of the fields that are changed, fields that are left
+    /// alone aren't part of it
+    pub fn to_json(&self) -> Result<serde_json::Map<String, serde_json::Value>> {
+        let value = match self {
+            Update::Subdomain(update)       => serde_json::to_value(update),
+            Update::IpAddr(update)          => serde_json::to_value(update),
+            Update::Url(update)             => serde_json::to_value(update),
+            Update::Email(update)           => serde_json::to_value(update),
+            Update::PhoneNumber(update)     => serde_json::to_value(update),
+            Update::Device(update)          => serde_json::to_value(update),
+            Update::Network(update)         => serde_json::to_value(update),
+            Update::NetworkDevice(update)   => serde_json::to_value(update),
+            Update::Account(update)         => serde_json::to_value(update),
+            Update::BreachEmail(update)     => serde_json::to_value(update),
+            Update::Image(update)           => serde_json::to_value(update),
+            Update::Port(update)            => serde_json::to_value(update),
+            Update::Netblock(update)        => serde_json::to_value(update),
+            Update::CryptoAddr(update)      => serde_json::to_value(update),
+            // a field may be set to null, so they can't be skipped
+            Update::Fields(update)          => return Ok(update.to_json()),
+        }?;
+        let mut fields = match value {
+            serde_json::Value::Object(fields) => fields,
+            _ => bail!("Expected update to be an object"),
+        };
+        fields.retain(|name, value| name != "id" && !value.is_null());
+        Ok(fields)
+    }
+
+    pub fn family(&self) -> Result<Family> {
+        let family = match self {
+            Update::Subdomain(_)     => Family::Subdomain,
+            Update::IpAddr(_)        => Family::Ipaddr,
+            Update::Url(_)           => Family::Url,
+            Update::Email(_)         => Family::Email,
+            Update::PhoneNumber(_)   => Family::Phonenumber,
+            Update::Device(_)        => Family::Device,
+            Update::Network(_)       => Family::Network,
+            Update::NetworkDevice(_) => Family::NetworkDevice,
+            Update::Account(_)       => Family::Account,
+            Update::BreachEmail(_)   => Family::BreachEmail,
+            Update::Image(_)         => Family::Image,
+            Update::Port(_)          => Family::Port,
+            Update::Netblock(_)      => Family::Netblock,
+            Update::CryptoAddr(_)    => Family::Cryptoaddr,
+            Update::Fields(update)   => Table::from_str(&update.table)
+                .map_err(|_| format_err!("Unknown table: {:?}", update.table))?
+                .family(),
+        };
+        Ok(family)
+    }
+
     pub fn to_plain_str(&self) -> String {
         match self {
             Update::Subdomain(update)       => update.to_plain_str(),
@@ -172,6 +229,7 @@ impl Update {
             Update::Port(update)            => update.to_plain_str(),
             Update::Netblock(update)        => update.to_plain_str(),
             Update::CryptoAddr(update)      => update.to_plain_str(),
+            Update::Fields(update)          => update.to_plain_str(),
         }
     }
 
@@ -191,10 +249,14 @@ impl Update {
             Update::Port(update)            => update.to_term_str(),
             Update::Netblock(update)        => update.to_term_str(),
             Update::CryptoAddr(update)      => update.to_term_str(),
+            Update::Fields(update)          => update.to_term_str(),
         }
     }
 }
 
+/// How many entities are loaded at once when a filter is iterated in chunks
+pub const CHUNK_SIZE: usize = 1_000;
+
 pub trait Model: Sized {
     type ID: ?Sized;
 
@@ -204,6 +266,36 @@ pub trait Model: Sized {
 
     fn filter(db: &Database, filter: &Filter) -> Result<Vec<Self>>;
 
+    /// At most `limit` entities of the filter with an id after `after`,
+    /// ordered by id
+    fn filter_page(db: &Database, filter: &Filter, after: i32, limit: usize) -> Result<Vec<Self>>;
+
+    /// At most `limit` entities of the filter after skipping `offset` of
+    /// them, ordered by id
+    fn filter_range(db: &Database, filter: &Filter, offset: usize, limit: usize) -> Result<Vec<Self>>;
+
+    /// Like `filter`, but the entities are passed to `f` in chunks of
+    /// `chunk_size` instead of loading all of them at once
+    fn for_each_chunk<F>(db: &Database, filter: &Filter, chunk_size: usize, mut f: F) -> Result<()>
+        where F: FnMut(Vec<Self>) -> Result<()>
+    {
+        let chunk_size = cmp::max(chunk_size, 1);
+        let mut after = 0;
+        loop {
+            let chunk = Self::filter_page(db, filter, after, chunk_size)?;
+            let last = match chunk.last() {
+                Some(last) => last.id(),
+                None => return Ok(()),
+            };
+            let done = chunk.len() < chunk_size;
+            f(chunk)?;
+            if done {
+                return Ok(());
+            }
+            after = last;
+        }
+    }
+
     fn filter_with_param(_db: &Database, _filter: &Filter, _param: &str) -> Result<Vec<Self>> {
         unimplemented!("This model doesn't support filtering with an additional parameter")
     }
@@ -241,6 +333,9 @@ pub trait Model: Sized {
 }
 
 pub trait Scopable: Model {
+    /// The table the entity is stored in
+    fn table() -> Table;
+
     fn scoped(&self) -> bool;
 
     fn set_scoped(&self, _db: &Database, _value: bool) -> Result<()>;
@@ -335,7 +430,7 @@ pub trait Updateable<M> {
     fn push_value<D: fmt::Debug>(updates: &mut Vec<String>, name: &str, value: &Option<D>, colors: bool) {
         if let Some(v) = value {
             if colors {
-                updates.push(format!("{} => \x1b[33m{:?}\x1b[0m", name, v));
+                updates.push(format!("{} => {}", name, paint(Style::Yellow, format!("{:?}", v))));
             } else {
                 updates.push(format!("{} => {:?}", name, v));
             }
@@ -346,7 +441,7 @@ pub trait Updateable<M> {
     fn push_raw<T: AsRef<str>>(updates: &mut Vec<String>, name: &str, value: Option<T>, colors: bool) {
         if let Some(v) = value {
             if colors {
-                updates.push(format!("{} => \x1b[33m{}\x1b[0m", name, v.as_ref()));
+                updates.push(format!("{} => {}", name, paint(Style::Yellow, v.as_ref())));
             } else {
                 updates.push(format!("{} => {}", name, v.as_ref()));
             }
@@ -365,6 +460,12 @@ pub trait Detailed: Scopable {
     type T: fmt::Display;
 
     fn detailed(&self, db: &Database) -> Result<Self::T>;
+
+    /// Like `detailed`, but also expand history that's collapsed by default
+    #[inline]
+    fn detailed_history(&self, db: &Database) -> Result<Self::T> {
+        self.detailed(db)
+    }
 }
 
 pub trait DisplayableDetailed {
@@ -468,3 +569,21 @@ pub use self::cryptoaddr::*;
 
 mod activity;
 pub use self::activity::*;
+
+mod provenance;
+pub use self::provenance::*;
+
+mod network_audit;
+pub use self::network_audit::*;
+
+mod resolution;
+pub use self::resolution::*;
+
+mod fields;
+pub use self::fields::*;
+
+mod view;
+pub use self::view::*;
+
+mod run;
+pub use self::run::*;
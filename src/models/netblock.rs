@@ -1,4 +1,5 @@
 use crate::errors::*;
+use chrono::NaiveDateTime;
 use serde::{Serialize, Deserialize};
 use crate::fmt::colors::*;
 use diesel::prelude::*;
@@ -14,6 +15,10 @@ pub struct Netblock {
     pub asn: Option<i32>,
     pub as_org: Option<String>,
     pub description: Option<String>,
+
+    pub seen_count: i32,
+    pub seen_first: Option<NaiveDateTime>,
+    pub seen_last: Option<NaiveDateTime>,
 }
 
 impl Model for Netblock {
@@ -40,6 +45,30 @@ impl Model for Netblock {
         Ok(results)
     }
 
+    fn filter_page(db: &Database, filter: &Filter, after: i32, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::netblocks::dsl::*;
+
+        let query = netblocks.filter(filter.sql())
+            .filter(id.gt(after))
+            .order(id)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
+    fn filter_range(db: &Database, filter: &Filter, offset: usize, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::netblocks::dsl::*;
+
+        let query = netblocks.filter(filter.sql())
+            .order(id)
+            .offset(offset as i64)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
     fn delete(db: &Database, filter: &Filter) -> Result<usize> {
         use crate::schema::netblocks::dsl::*;
 
@@ -94,6 +123,10 @@ impl Model for Netblock {
 }
 
 impl Scopable for Netblock {
+    fn table() -> crate::db::Table {
+        crate::db::Table::Netblocks
+    }
+
     fn scoped(&self) -> bool {
         !self.unscoped
     }
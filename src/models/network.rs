@@ -1,4 +1,5 @@
 use crate::errors::*;
+use chrono::NaiveDateTime;
 use serde::{Serialize, Deserialize};
 use crate::fmt::colors::*;
 use diesel::prelude::*;
@@ -13,6 +14,10 @@ pub struct Network {
     pub latitude: Option<f32>,
     pub longitude: Option<f32>,
     pub description: Option<String>,
+
+    pub seen_count: i32,
+    pub seen_first: Option<NaiveDateTime>,
+    pub seen_last: Option<NaiveDateTime>,
 }
 
 impl Model for Network {
@@ -39,6 +44,30 @@ impl Model for Network {
         Ok(results)
     }
 
+    fn filter_page(db: &Database, filter: &Filter, after: i32, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::networks::dsl::*;
+
+        let query = networks.filter(filter.sql())
+            .filter(id.gt(after))
+            .order(id)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
+    fn filter_range(db: &Database, filter: &Filter, offset: usize, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::networks::dsl::*;
+
+        let query = networks.filter(filter.sql())
+            .order(id)
+            .offset(offset as i64)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
     fn delete(db: &Database, filter: &Filter) -> Result<usize> {
         use crate::schema::networks::dsl::*;
 
@@ -93,6 +122,10 @@ impl Model for Network {
 }
 
 impl Scopable for Network {
+    fn table() -> crate::db::Table {
+        crate::db::Table::Networks
+    }
+
     fn scoped(&self) -> bool {
         !self.unscoped
     }
@@ -0,0 +1,297 @@
+use crate::errors::*;
+use serde::{Serialize, Deserialize};
+use crate::schema::network_audit;
+use diesel::prelude::*;
+use crate::models::*;
+use chrono::NaiveDateTime;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::Write;
+use std::net::IpAddr;
+
+#[derive(Identifiable, Queryable, PartialEq, Debug)]
+#[table_name="network_audit"]
+pub struct NetworkAudit {
+    pub id: i32,
+    pub time: NaiveDateTime,
+    pub module: Option<String>,
+    pub target: Option<String>,
+    pub kind: String,
+    pub host: String,
+    pub port: Option<i32>,
+    pub method: Option<String>,
+    pub url: Option<String>,
+    pub headers: Option<String>,
+    pub status: Option<i32>,
+    pub bytes: Option<i64>,
+    pub error: Option<String>,
+    pub ipaddr: Option<String>,
+}
+
+impl NetworkAudit {
+    pub fn query(db: &Database, filter: &NetworkAuditFilter) -> Result<Vec<Self>> {
+        use crate::schema::network_audit::dsl::*;
+
+        let mut query = network_audit.into_boxed();
+
+        if let Some(my_module) = &filter.module {
+            query = query.filter(module.eq(my_module));
+        }
+
+        if let Some(since) = &filter.since {
+            query = query.filter(time.ge(since));
+        }
+
+        if let Some(until) = &filter.until {
+            query = query.filter(time.le(until));
+        }
+
+        query
+            .order_by((time.asc(), id.asc()))
+            .load::<Self>(db.db())
+            .map_err(Error::from)
+    }
+}
+
+impl fmt::Display for NetworkAudit {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(w, "{} {}", self.time.format("%Y-%m-%d %H:%M:%S"), self.module.as_deref().unwrap_or("-"))?;
+        if let Some(target) = &self.target {
+            write!(w, " on {:?}", target)?;
+        }
+
+        match (&self.method, &self.url) {
+            (Some(method), Some(url)) => write!(w, ": {} {}", method, url)?,
+            (None, Some(url)) => write!(w, ": {} {}", self.kind, url)?,
+            (Some(record), None) => write!(w, ": {} {} {}", self.kind, record, self.host)?,
+            _ => {
+                write!(w, ": {} {}", self.kind, self.host)?;
+                if let Some(port) = self.port {
+                    write!(w, ":{}", port)?;
+                }
+            },
+        }
+
+        if let Some(ipaddr) = &self.ipaddr {
+            write!(w, " [{}]", ipaddr)?;
+        }
+
+        if let Some(status) = self.status {
+            write!(w, " ({}", status)?;
+            if let Some(bytes) = self.bytes {
+                write!(w, ", {} bytes", bytes)?;
+            }
+            write!(w, ")")?;
+        }
+
+        if let Some(error) = &self.error {
+            write!(w, " failed: {}", error)?;
+        }
+
+        Ok(())
+    }
+}
+
+pub struct NetworkAuditFilter {
+    pub module: Option<String>,
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JsonNetworkAudit {
+    pub time: NaiveDateTime,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub module: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    pub kind: String,
+    pub host: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub headers: Option<BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ipaddr: Option<String>,
+}
+
+impl JsonNetworkAudit {
+    pub fn write_to<W: Write>(self, mut w: W) -> Result<()> {
+        let s = serde_json::to_string(&self)?;
+        writeln!(w, "{}", s)?;
+        Ok(())
+    }
+}
+
+impl TryFrom<NetworkAudit> for JsonNetworkAudit {
+    type Error = Error;
+
+    fn try_from(a: NetworkAudit) -> Result<Self> {
+        let headers = match a.headers {
+            Some(headers) => Some(serde_json::from_str(&headers)?),
+            None => None,
+        };
+
+        Ok(JsonNetworkAudit {
+            time: a.time,
+            module: a.module,
+            target: a.target,
+            kind: a.kind,
+            host: a.host,
+            port: a.port,
+            method: a.method,
+            url: a.url,
+            headers,
+            status: a.status,
+            bytes: a.bytes,
+            error: a.error,
+            ipaddr: a.ipaddr,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[table_name="network_audit"]
+pub struct NewNetworkAudit {
+    pub time: NaiveDateTime,
+    pub module: Option<String>,
+    pub target: Option<String>,
+    pub kind: String,
+    pub host: String,
+    pub port: Option<i32>,
+    pub method: Option<String>,
+    pub url: Option<String>,
+    pub headers: Option<String>,
+    pub status: Option<i32>,
+    pub bytes: Option<i64>,
+    pub error: Option<String>,
+    pub ipaddr: Option<String>,
+}
+
+impl NewNetworkAudit {
+    pub fn insert(&self, db: &Database) -> Result<()> {
+        diesel::insert_into(network_audit::table)
+            .values(self)
+            .execute(db.db())?;
+        Ok(())
+    }
+}
+
+/// A connection or lookup, the time and module are added when it's written
+///
+/// Connections and dns lookups are recorded by the parent process, which
+/// opens them. Http requests are reported by the sandbox since the parent
+/// can't see into tls connections.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InsertNetworkAudit {
+    pub kind: String,
+    pub host: String,
+    pub port: Option<u16>,
+    pub method: Option<String>,
+    pub url: Option<String>,
+    pub headers: Option<BTreeMap<String, String>>,
+    pub status: Option<u16>,
+    pub bytes: Option<u64>,
+    pub error: Option<String>,
+    /// The address that has actually been connected to
+    pub ipaddr: Option<IpAddr>,
+}
+
+impl InsertNetworkAudit {
+    pub fn into_new(self, origin: Option<&Origin>, time: NaiveDateTime) -> Result<NewNetworkAudit> {
+        let headers = match self.headers {
+            Some(headers) => Some(serde_json::to_string(&headers)?),
+            None => None,
+        };
+
+        Ok(NewNetworkAudit {
+            time,
+            module: origin.map(|o| o.module.clone()),
+            target: origin.and_then(|o| o.target.clone()),
+            kind: self.kind,
+            host: self.host,
+            port: self.port.map(i32::from),
+            method: self.method,
+            url: self.url,
+            headers,
+            status: self.status.map(i32::from),
+            bytes: self.bytes.map(|b| i64::try_from(b).unwrap_or(i64::MAX)),
+            error: self.error,
+            ipaddr: self.ipaddr.map(|ipaddr| ipaddr.to_string()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn entry() -> NetworkAudit {
+        NetworkAudit {
+            id: 1,
+            time: NaiveDateTime::from_str("2020-03-14T16:20:23").unwrap(),
+            module: Some("kpcyrd/url-scan".to_string()),
+            target: Some("example.com".to_string()),
+            kind: "http".to_string(),
+            host: "example.com".to_string(),
+            port: Some(443),
+            method: Some("GET".to_string()),
+            url: Some("https://example.com/".to_string()),
+            headers: Some(r#"{"user-agent":"sn0int"}"#.to_string()),
+            status: Some(200),
+            bytes: Some(1256),
+            error: None,
+            ipaddr: Some("93.184.216.34".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_display_http() {
+        assert_eq!(entry().to_string(), "2020-03-14 16:20:23 kpcyrd/url-scan on \"example.com\": GET https://example.com/ [93.184.216.34] (200, 1256 bytes)");
+    }
+
+    #[test]
+    fn test_display_tcp_failed() {
+        let mut a = entry();
+        a.kind = "tcp".to_string();
+        a.method = None;
+        a.url = None;
+        a.status = None;
+        a.bytes = None;
+        a.error = Some("Connection refused".to_string());
+        assert_eq!(a.to_string(), "2020-03-14 16:20:23 kpcyrd/url-scan on \"example.com\": tcp example.com:443 [93.184.216.34] failed: Connection refused");
+    }
+
+    #[test]
+    fn test_display_dns() {
+        let mut a = entry();
+        a.kind = "dns".to_string();
+        a.port = Some(53);
+        a.method = Some("AAAA".to_string());
+        a.url = None;
+        a.headers = None;
+        a.status = None;
+        a.bytes = None;
+        a.ipaddr = Some("127.0.0.53".to_string());
+        assert_eq!(a.to_string(), "2020-03-14 16:20:23 kpcyrd/url-scan on \"example.com\": dns AAAA example.com [127.0.0.53]");
+    }
+
+    #[test]
+    fn test_json_headers() {
+        let a = JsonNetworkAudit::try_from(entry()).unwrap();
+        let json = serde_json::to_string(&a).unwrap();
+        assert_eq!(json, r#"{"time":"2020-03-14T16:20:23","module":"kpcyrd/url-scan","target":"example.com","kind":"http","host":"example.com","port":443,"method":"GET","url":"https://example.com/","headers":{"user-agent":"sn0int"},"status":200,"bytes":1256,"ipaddr":"93.184.216.34"}"#);
+    }
+}
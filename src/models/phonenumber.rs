@@ -21,6 +21,10 @@ pub struct PhoneNumber {
     pub last_ported: Option<NaiveDateTime>,
     pub caller_name: Option<String>,
     pub caller_type: Option<String>,
+
+    pub seen_count: i32,
+    pub seen_first: Option<NaiveDateTime>,
+    pub seen_last: Option<NaiveDateTime>,
 }
 
 impl Model for PhoneNumber {
@@ -47,6 +51,30 @@ impl Model for PhoneNumber {
         Ok(results)
     }
 
+    fn filter_page(db: &Database, filter: &Filter, after: i32, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::phonenumbers::dsl::*;
+
+        let query = phonenumbers.filter(filter.sql())
+            .filter(id.gt(after))
+            .order(id)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
+    fn filter_range(db: &Database, filter: &Filter, offset: usize, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::phonenumbers::dsl::*;
+
+        let query = phonenumbers.filter(filter.sql())
+            .order(id)
+            .offset(offset as i64)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
     fn delete(db: &Database, filter: &Filter) -> Result<usize> {
         use crate::schema::phonenumbers::dsl::*;
 
@@ -101,6 +129,10 @@ impl Model for PhoneNumber {
 }
 
 impl Scopable for PhoneNumber {
+    fn table() -> crate::db::Table {
+        crate::db::Table::Phonenumbers
+    }
+
     fn scoped(&self) -> bool {
         !self.unscoped
     }
@@ -1,4 +1,5 @@
 use crate::errors::*;
+use chrono::NaiveDateTime;
 use serde::{Serialize, Deserialize};
 use crate::fmt::Write;
 use crate::fmt::colors::*;
@@ -22,6 +23,13 @@ pub struct Port {
     pub banner: Option<String>,
     pub service: Option<String>,
     pub version: Option<String>,
+
+    pub seen_count: i32,
+    pub seen_first: Option<NaiveDateTime>,
+    pub seen_last: Option<NaiveDateTime>,
+
+    pub ja3s: Option<String>,
+    pub ja4s: Option<String>,
 }
 
 impl Model for Port {
@@ -48,6 +56,30 @@ impl Model for Port {
         Ok(results)
     }
 
+    fn filter_page(db: &Database, filter: &Filter, after: i32, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::ports::dsl::*;
+
+        let query = ports.filter(filter.sql())
+            .filter(id.gt(after))
+            .order(id)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
+    fn filter_range(db: &Database, filter: &Filter, offset: usize, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::ports::dsl::*;
+
+        let query = ports.filter(filter.sql())
+            .order(id)
+            .offset(offset as i64)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
     fn delete(db: &Database, filter: &Filter) -> Result<usize> {
         use crate::schema::ports::dsl::*;
 
@@ -102,6 +134,10 @@ impl Model for Port {
 }
 
 impl Scopable for Port {
+    fn table() -> crate::db::Table {
+        crate::db::Table::Ports
+    }
+
     fn scoped(&self) -> bool {
         !self.unscoped
     }
@@ -160,6 +196,8 @@ pub struct DetailedPort {
     banner: Option<String>,
     service: Option<String>,
     version: Option<String>,
+    ja3s: Option<String>,
+    ja4s: Option<String>,
 }
 
 impl DisplayableDetailed for DetailedPort {
@@ -179,6 +217,8 @@ impl DisplayableDetailed for DetailedPort {
         w.opt_debug::<Yellow, _>(&self.banner)?;
         w.opt_debug::<Yellow, _>(&self.service)?;
         w.opt_debug::<Yellow, _>(&self.version)?;
+        w.opt_debug::<Yellow, _>(&self.ja3s)?;
+        w.opt_debug::<Yellow, _>(&self.ja4s)?;
         w.end_group()?;
 
         Ok(())
@@ -205,6 +245,8 @@ impl Detailed for Port {
             banner: self.banner.clone(),
             service: self.service.clone(),
             version: self.version.clone(),
+            ja3s: self.ja3s.clone(),
+            ja4s: self.ja4s.clone(),
         })
     }
 }
@@ -222,6 +264,8 @@ pub struct NewPort {
     pub banner: Option<String>,
     pub service: Option<String>,
     pub version: Option<String>,
+    pub ja3s: Option<String>,
+    pub ja4s: Option<String>,
 
     pub unscoped: bool,
 }
@@ -253,6 +297,8 @@ impl Upsertable<Port> for NewPort {
             banner: Self::upsert_opt(self.banner, &existing.banner),
             service: Self::upsert_opt(self.service, &existing.service),
             version: Self::upsert_opt(self.version, &existing.version),
+            ja3s: Self::upsert_opt(self.ja3s, &existing.ja3s),
+            ja4s: Self::upsert_opt(self.ja4s, &existing.ja4s),
         }
     }
 }
@@ -276,6 +322,8 @@ pub struct InsertPort {
     pub banner: Option<String>,
     pub service: Option<String>,
     pub version: Option<String>,
+    pub ja3s: Option<String>,
+    pub ja4s: Option<String>,
 }
 
 impl InsertToNew for InsertPort {
@@ -303,6 +351,8 @@ impl InsertToNew for InsertPort {
             banner: self.banner,
             service: self.service,
             version: self.version,
+            ja3s: self.ja3s,
+            ja4s: self.ja4s,
 
             unscoped: false,
         })
@@ -317,6 +367,8 @@ pub struct PortUpdate {
     pub banner: Option<String>,
     pub service: Option<String>,
     pub version: Option<String>,
+    pub ja3s: Option<String>,
+    pub ja4s: Option<String>,
 }
 
 impl Upsert for PortUpdate {
@@ -324,7 +376,9 @@ impl Upsert for PortUpdate {
         self.status.is_some() ||
         self.banner.is_some() ||
         self.service.is_some() ||
-        self.version.is_some()
+        self.version.is_some() ||
+        self.ja3s.is_some() ||
+        self.ja4s.is_some()
     }
 
     fn generic(self) -> Update {
@@ -342,6 +396,8 @@ impl Updateable<Port> for PortUpdate {
         Self::clear_if_equal(&mut self.banner, &existing.banner);
         Self::clear_if_equal(&mut self.service, &existing.service);
         Self::clear_if_equal(&mut self.version, &existing.version);
+        Self::clear_if_equal(&mut self.ja3s, &existing.ja3s);
+        Self::clear_if_equal(&mut self.ja4s, &existing.ja4s);
     }
 
     fn fmt(&self, updates: &mut Vec<String>, colors: bool) {
@@ -349,5 +405,7 @@ impl Updateable<Port> for PortUpdate {
         Self::push_value(updates, "banner", &self.banner, colors);
         Self::push_value(updates, "service", &self.service, colors);
         Self::push_value(updates, "version", &self.version, colors);
+        Self::push_value(updates, "ja3s", &self.ja3s, colors);
+        Self::push_value(updates, "ja4s", &self.ja4s, colors);
     }
 }
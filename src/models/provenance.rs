@@ -0,0 +1,432 @@
+use crate::errors::*;
+use serde::{Serialize, Deserialize};
+use crate::schema::provenance;
+use diesel::prelude::*;
+use crate::models::*;
+use chrono::NaiveDateTime;
+use diesel::dsl::sql;
+use diesel::sql_types::{BigInt, Bool, Text, Timestamp};
+use std::collections::HashSet;
+use std::fmt;
+use strum_macros::IntoStaticStr;
+
+/// A change to an entity, kept even after the entity has been deleted
+#[derive(Identifiable, Queryable, Serialize, Deserialize, PartialEq, Debug)]
+#[table_name="provenance"]
+pub struct Provenance {
+    #[serde(skip)]
+    pub id: i32,
+    pub family: String,
+    pub value: String,
+    pub time: NaiveDateTime,
+    pub action: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub module: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+    /// The new values of an update as json object
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changes: Option<String>,
+}
+
+impl Provenance {
+    pub fn history(db: &Database, my_family: &Family, my_value: &str) -> Result<Vec<Self>> {
+        use crate::schema::provenance::dsl::*;
+        provenance
+            .filter(family.eq(my_family.as_str()))
+            .filter(value.eq(my_value))
+            .order_by((time.asc(), id.asc()))
+            .load::<Self>(db.db())
+            .map_err(Error::from)
+    }
+
+    /// Entities that entered or left scope since the given time
+    pub fn scope_changes(db: &Database, since: NaiveDateTime) -> Result<Vec<Self>> {
+        use crate::schema::provenance::dsl::*;
+        let actions: [&str; 2] = [ProvenanceAction::Scope.into(), ProvenanceAction::Noscope.into()];
+        provenance
+            .filter(action.eq_any(&actions))
+            .filter(time.ge(since))
+            .order_by((time.asc(), id.asc()))
+            .load::<Self>(db.db())
+            .map_err(Error::from)
+    }
+
+    /// Every change since the given time, in the order they happened
+    pub fn since(db: &Database, since: NaiveDateTime) -> Result<Vec<Self>> {
+        use crate::schema::provenance::dsl::*;
+        provenance
+            .filter(time.ge(since))
+            .order_by((time.asc(), id.asc()))
+            .load::<Self>(db.db())
+            .map_err(Error::from)
+    }
+
+    /// The family and value of every entity whose scope has last been changed
+    /// by the scope or noscope command
+    pub fn manual_scope(db: &Database) -> Result<HashSet<(String, String)>> {
+        use crate::schema::provenance::dsl::*;
+        let actions: [&str; 2] = [ProvenanceAction::Scope.into(), ProvenanceAction::Noscope.into()];
+        let entries = provenance
+            .filter(action.eq_any(&actions))
+            .order_by((time.asc(), id.asc()))
+            .load::<Self>(db.db())?;
+
+        let mut manual = HashSet::new();
+        for change in entries {
+            let key = (change.family.clone(), change.value.clone());
+            if change.is_manual() {
+                manual.insert(key);
+            } else {
+                manual.remove(&key);
+            }
+        }
+        Ok(manual)
+    }
+
+    /// The modules that inserted the most entities, optionally only
+    /// counting the inserts since the given time
+    pub fn top_modules(db: &Database, since: Option<NaiveDateTime>, limit: i64) -> Result<Vec<(String, i64)>> {
+        let insert: &str = ProvenanceAction::Insert.into();
+        let query = "SELECT module, COUNT(*) AS count FROM provenance
+            WHERE action = ? AND module IS NOT NULL AND time >= ?
+            GROUP BY module ORDER BY count DESC, module LIMIT ?";
+        // every insert since the epoch without a window
+        let since = since.unwrap_or_default();
+        let modules = diesel::sql_query(query)
+            .bind::<Text, _>(insert)
+            .bind::<Timestamp, _>(since)
+            .bind::<BigInt, _>(limit)
+            .load::<ModuleCount>(db.db())?;
+        Ok(modules.into_iter()
+            .map(|m| (m.module, m.count))
+            .collect())
+    }
+
+    /// When the entities of a family or the entities added by a module have
+    /// been inserted, sorted by time. A module without a namespace matches
+    /// the module of that name in any namespace.
+    pub fn insert_times(db: &Database, my_module: Option<&str>, my_family: Option<&Family>, since: NaiveDateTime, until: NaiveDateTime) -> Result<Vec<NaiveDateTime>> {
+        use crate::schema::provenance::dsl::*;
+        let insert: &str = ProvenanceAction::Insert.into();
+        let mut query = provenance
+            .select(time)
+            .filter(action.eq(insert))
+            .filter(time.ge(since))
+            .filter(time.le(until))
+            .into_boxed();
+
+        if let Some(my_module) = my_module {
+            if my_module.contains('/') {
+                query = query.filter(module.eq(my_module.to_string()));
+            } else {
+                // compare the name after the namespace, a LIKE pattern
+                // would treat `_` and `%` in the name as wildcards
+                query = query.filter(sql::<Bool>("substr(module, instr(module, '/') + 1) = ")
+                    .bind::<Text, _>(my_module.to_string()));
+            }
+        }
+
+        if let Some(my_family) = my_family {
+            query = query.filter(family.eq(my_family.as_str()));
+        }
+
+        query
+            .order_by((time.asc(), id.asc()))
+            .load::<NaiveDateTime>(db.db())
+            .map_err(Error::from)
+    }
+
+    fn build_timeline_query<'a>(filter: &'a TimelineFilter) -> provenance::BoxedQuery<'a, diesel::sqlite::Sqlite> {
+        use crate::schema::provenance::dsl::*;
+        let insert: &str = ProvenanceAction::Insert.into();
+        let mut query = provenance
+            .filter(action.eq(insert))
+            .into_boxed();
+
+        if let Some(my_family) = &filter.family {
+            query = query.filter(family.eq(my_family.as_str()));
+        }
+        if let Some(entities) = &filter.entities {
+            query = query.filter(entities.sql());
+        }
+        if let Some(since) = filter.since {
+            query = query.filter(time.ge(since));
+        }
+        if let Some(until) = filter.until {
+            query = query.filter(time.le(until));
+        }
+
+        query
+    }
+
+    /// Every insert that matches the filter in chronological order, the
+    /// offset and limit select a page
+    pub fn timeline(db: &Database, filter: &TimelineFilter, offset: i64, limit: Option<i64>) -> Result<Vec<Self>> {
+        use crate::schema::provenance::dsl::*;
+        // sqlite doesn't accept an offset without a limit, -1 is unlimited
+        Self::build_timeline_query(filter)
+            .order_by((time.asc(), id.asc()))
+            .limit(limit.unwrap_or(-1))
+            .offset(offset)
+            .load::<Self>(db.db())
+            .map_err(Error::from)
+    }
+
+    /// The number of inserts that match the filter
+    pub fn count_timeline(db: &Database, filter: &TimelineFilter) -> Result<i64> {
+        Self::build_timeline_query(filter)
+            .count()
+            .get_result::<i64>(db.db())
+            .map_err(Error::from)
+    }
+
+    /// The autonoscope rule that caused a scope change, like `domain:example.com`
+    pub fn rule(&self) -> Option<&str> {
+        let details = self.details.as_deref()?;
+        let (reason, rule) = details.split_once(' ')?;
+        match reason {
+            "autonoscope" | "rescope" | "apply" => Some(rule),
+            _ => None,
+        }
+    }
+
+    /// Changes without a module and without details have been done by hand
+    #[inline]
+    pub fn is_manual(&self) -> bool {
+        self.module.is_none() && self.details.is_none()
+    }
+
+    #[inline]
+    pub fn is_scoped(&self) -> bool {
+        let scope: &str = ProvenanceAction::Scope.into();
+        self.action == scope
+    }
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(w, "{} {:<8}", self.time.format("%Y-%m-%d %H:%M:%S"), self.action)?;
+
+        match (&self.module, &self.version) {
+            (Some(module), Some(version)) => write!(w, " {} v{}", module, version)?,
+            (Some(module), None) => write!(w, " {}", module)?,
+            _ => write!(w, " (manual)")?,
+        }
+        if let Some(target) = &self.target {
+            write!(w, " on {:?}", target)?;
+        }
+        if let Some(details) = &self.details {
+            write!(w, ": {}", details)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Selects the inserts that are shown in a timeline
+#[derive(Debug, Default)]
+pub struct TimelineFilter {
+    pub family: Option<Family>,
+    /// Only entities that exist and match this filter, on the columns of the
+    /// table of `family`
+    pub entities: Option<Filter>,
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+}
+
+impl TimelineFilter {
+    pub fn with_entities(&mut self, table: crate::db::Table, filter: &Filter) {
+        self.family = Some(table.family());
+        self.entities = Some(Filter::new(format!("value IN (SELECT value FROM {} WHERE {})", table.as_str(), filter.query())));
+    }
+}
+
+#[derive(QueryableByName)]
+struct ModuleCount {
+    #[sql_type = "Text"]
+    module: String,
+    #[sql_type = "BigInt"]
+    count: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum ProvenanceAction {
+    Insert,
+    Update,
+    Scope,
+    Noscope,
+    Delete,
+}
+
+/// The module run that is responsible for the database changes that are
+/// currently applied, changes without an origin have been done by the user
+#[derive(Debug, Clone, PartialEq)]
+pub struct Origin {
+    pub module: String,
+    pub version: String,
+    pub target: Option<String>,
+    /// The family of the target, if the module runs on entities
+    pub source: Option<Family>,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[table_name="provenance"]
+pub struct NewProvenance {
+    pub family: String,
+    pub value: String,
+    pub time: NaiveDateTime,
+    pub action: String,
+    pub module: Option<String>,
+    pub version: Option<String>,
+    pub target: Option<String>,
+    pub details: Option<String>,
+    pub changes: Option<String>,
+}
+
+impl NewProvenance {
+    pub fn new(family: &Family, value: &str, action: ProvenanceAction, origin: Option<&Origin>, details: Option<String>, time: NaiveDateTime) -> NewProvenance {
+        let action: &str = action.into();
+        NewProvenance {
+            family: family.as_str().to_string(),
+            value: value.to_string(),
+            time,
+            action: action.to_string(),
+            module: origin.map(|o| o.module.clone()),
+            version: origin.map(|o| o.version.clone()),
+            target: origin.and_then(|o| o.target.clone()),
+            details,
+            changes: None,
+        }
+    }
+
+    pub fn insert(&self, db: &Database) -> Result<()> {
+        diesel::insert_into(provenance::table)
+            .values(self)
+            .execute(db.db())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use std::str::FromStr;
+
+    fn entry(module: Option<&str>, target: Option<&str>, details: Option<&str>) -> Provenance {
+        Provenance {
+            id: 1,
+            family: "email".to_string(),
+            value: "a@example.com".to_string(),
+            time: NaiveDateTime::from_str("2020-03-14T16:20:23").unwrap(),
+            action: "update".to_string(),
+            module: module.map(String::from),
+            version: module.map(|_| "0.1.0".to_string()),
+            target: target.map(String::from),
+            details: details.map(String::from),
+            changes: None,
+        }
+    }
+
+    #[test]
+    fn test_display_module() {
+        let p = entry(Some("kpcyrd/pgp-keyserver"), Some("example.com"), Some("valid => true"));
+        assert_eq!(p.to_string(), "2020-03-14 16:20:23 update   kpcyrd/pgp-keyserver v0.1.0 on \"example.com\": valid => true");
+    }
+
+    #[test]
+    fn test_display_manual() {
+        let p = entry(None, None, None);
+        assert_eq!(p.to_string(), "2020-03-14 16:20:23 update   (manual)");
+    }
+
+    #[test]
+    fn test_rule() {
+        assert_eq!(entry(None, None, Some("autonoscope domain:example.com")).rule(), Some("domain:example.com"));
+        assert_eq!(entry(None, None, Some("rescope url:https://example.com/")).rule(), Some("url:https://example.com/"));
+        assert_eq!(entry(None, None, Some("apply ip:10.0.0.0/8")).rule(), Some("ip:10.0.0.0/8"));
+        assert_eq!(entry(None, None, Some("autonoscope")).rule(), None);
+        assert_eq!(entry(None, None, Some("valid => true")).rule(), None);
+        assert_eq!(entry(None, None, None).rule(), None);
+    }
+
+    #[test]
+    fn test_insert_times() {
+        let mut db = Database::memory().unwrap();
+        let insert = |db: &mut Database, module: Option<&str>, value: &str| {
+            db.set_origin(module.map(|module| Origin {
+                module: module.to_string(),
+                version: "0.1.0".to_string(),
+                target: None,
+                source: None,
+            }));
+            db.insert_generic(Insert::Domain(NewDomain {
+                value: value.to_string(),
+                unscoped: false,
+            })).unwrap();
+        };
+        insert(&mut db, Some("kpcyrd/ctlogs"), "a.com");
+        insert(&mut db, Some("kpcyrd/ctlogs-ng"), "b.com");
+        insert(&mut db, None, "c.com");
+        insert(&mut db, Some("kpcyrd/ctlogsXng"), "d.com");
+        insert(&mut db, Some("kpcyrd/CTLOGS_ng"), "e.com");
+
+        let since = NaiveDateTime::from_str("2020-03-14T16:20:23").unwrap();
+        let until = Utc::now().naive_utc();
+        let times = |module, family| Provenance::insert_times(&db, module, family, since, until).unwrap().len();
+        assert_eq!(times(None, None), 5);
+        assert_eq!(times(Some("ctlogs"), None), 1);
+        // `_` isn't a wildcard and the name is case sensitive
+        assert_eq!(times(Some("ctlogs_ng"), None), 0);
+        assert_eq!(times(Some("CTLOGS_ng"), None), 1);
+        assert_eq!(times(Some("kpcyrd/ctlogs"), None), 1);
+        assert_eq!(times(Some("kpcyrd/ctlogs"), Some(&Family::Subdomain)), 0);
+        assert_eq!(times(None, Some(&Family::Domain)), 5);
+        assert!(Provenance::insert_times(&db, None, None, until, until).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_timeline() {
+        let db = Database::memory().unwrap();
+        for value in ["c.com", "a.com", "b.com"] {
+            db.insert_generic(Insert::Domain(NewDomain {
+                value: value.to_string(),
+                unscoped: false,
+            })).unwrap();
+        }
+        db.set_scoped::<Domain>(&Filter::column_eq("value", "a.com"), false).unwrap();
+
+        let filter = TimelineFilter::default();
+        let values = |filter: &TimelineFilter, offset, limit| Provenance::timeline(&db, filter, offset, limit).unwrap()
+            .into_iter()
+            .map(|entry| entry.value)
+            .collect::<Vec<_>>();
+        assert_eq!(values(&filter, 0, None), vec!["c.com", "a.com", "b.com"]);
+        assert_eq!(values(&filter, 1, Some(1)), vec!["a.com"]);
+        assert_eq!(Provenance::count_timeline(&db, &filter).unwrap(), 3);
+
+        let mut filter = TimelineFilter::default();
+        filter.with_entities(crate::db::Table::Domains, &Filter::new("unscoped=0"));
+        assert_eq!(values(&filter, 0, None), vec!["c.com", "b.com"]);
+        assert_eq!(Provenance::count_timeline(&db, &filter).unwrap(), 2);
+
+        let filter = TimelineFilter {
+            family: Some(Family::Subdomain),
+            ..Default::default()
+        };
+        assert!(values(&filter, 0, None).is_empty());
+    }
+
+    #[test]
+    fn test_json() {
+        let p = entry(None, None, None);
+        let json = serde_json::to_string(&p).unwrap();
+        assert_eq!(json, r#"{"family":"email","value":"a@example.com","time":"2020-03-14T16:20:23","action":"update"}"#);
+    }
+}
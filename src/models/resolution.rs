@@ -0,0 +1,174 @@
+use crate::errors::*;
+use crate::schema::resolutions;
+use diesel::prelude::*;
+use crate::models::*;
+use chrono::NaiveDateTime;
+use std::net;
+
+/// Every ip address a subdomain ever resolved to, the current mapping is
+/// kept in `subdomain_ipaddrs`. Each row is one period, an address that comes
+/// back after the subdomain resolved to something else gets a new row.
+#[derive(Identifiable, Queryable, Associations, PartialEq, Debug)]
+#[belongs_to(Subdomain)]
+#[belongs_to(IpAddr)]
+#[table_name="resolutions"]
+pub struct Resolution {
+    pub id: i32,
+    pub subdomain_id: i32,
+    pub ip_addr_id: i32,
+    pub first_seen: NaiveDateTime,
+    pub last_seen: NaiveDateTime,
+}
+
+impl Resolution {
+    /// Extend the latest period of this ip address if the subdomain is still
+    /// linked to it, otherwise a new period is started
+    pub fn seen(db: &Database, my_subdomain_id: i32, my_ip_addr_id: i32, linked: bool, now: NaiveDateTime) -> Result<()> {
+        use crate::schema::resolutions::dsl::*;
+
+        let latest = resolutions.filter(subdomain_id.eq(my_subdomain_id))
+                                .filter(ip_addr_id.eq(my_ip_addr_id))
+                                .order_by(id.desc())
+                                .select(id)
+                                .first::<i32>(db.db())
+                                .optional()?;
+
+        if let (Some(existing), true) = (latest, linked) {
+            diesel::update(resolutions.filter(id.eq(existing)))
+                .set(last_seen.eq(now))
+                .execute(db.db())?;
+        } else {
+            diesel::insert_into(resolutions)
+                .values(&NewResolution {
+                    subdomain_id: my_subdomain_id,
+                    ip_addr_id: my_ip_addr_id,
+                    first_seen: now,
+                    last_seen: now,
+                })
+                .execute(db.db())?;
+        }
+
+        Ok(())
+    }
+
+    /// All recorded resolutions of a subdomain, most recently seen first
+    pub fn history(db: &Database, subdomain: &Subdomain) -> Result<Vec<Self>> {
+        Resolution::belonging_to(subdomain)
+            .order_by((resolutions::last_seen.desc(), resolutions::id.desc()))
+            .load::<Self>(db.db())
+            .map_err(Error::from)
+    }
+}
+
+pub struct PrintableResolution {
+    ipaddr: net::IpAddr,
+    first_seen: NaiveDateTime,
+    last_seen: NaiveDateTime,
+}
+
+impl fmt::Display for PrintableResolution {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(w, "{} (first seen {}, last seen {})",
+            self.ipaddr,
+            self.first_seen.format("%Y-%m-%d %H:%M:%S"),
+            self.last_seen.format("%Y-%m-%d %H:%M:%S"))
+    }
+}
+
+impl Printable<PrintableResolution> for Resolution {
+    fn printable(&self, db: &Database) -> Result<PrintableResolution> {
+        let ipaddr = IpAddr::by_id(db, self.ip_addr_id)?;
+        Ok(PrintableResolution {
+            ipaddr: ipaddr.value.parse()?,
+            first_seen: self.first_seen,
+            last_seen: self.last_seen,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[table_name="resolutions"]
+pub struct NewResolution {
+    pub subdomain_id: i32,
+    pub ip_addr_id: i32,
+    pub first_seen: NaiveDateTime,
+    pub last_seen: NaiveDateTime,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn link(db: &Database, subdomain_id: i32, ip_addr_id: i32) {
+        db.insert_generic(Insert::SubdomainIpAddr(NewSubdomainIpAddr {
+            subdomain_id,
+            ip_addr_id,
+        })).unwrap();
+    }
+
+    fn ipaddr(db: &Database, value: &str) -> i32 {
+        db.insert_generic(Insert::IpAddr(NewIpAddr {
+            family: "4".to_string(),
+            value: value.to_string(),
+            continent: None,
+            continent_code: None,
+            country: None,
+            country_code: None,
+            city: None,
+            latitude: None,
+            longitude: None,
+            asn: None,
+            as_org: None,
+            description: None,
+            reverse_dns: None,
+            unscoped: false,
+        })).unwrap().unwrap().1
+    }
+
+    #[test]
+    fn test_display() {
+        let r = PrintableResolution {
+            ipaddr: "93.184.216.34".parse().unwrap(),
+            first_seen: NaiveDateTime::from_str("2020-03-14T16:20:23").unwrap(),
+            last_seen: NaiveDateTime::from_str("2020-04-01T08:00:00").unwrap(),
+        };
+        assert_eq!(r.to_string(), "93.184.216.34 (first seen 2020-03-14 16:20:23, last seen 2020-04-01 08:00:00)");
+    }
+
+    #[test]
+    fn test_resolution_periods() {
+        let db = Database::memory().unwrap();
+        let (_, domain_id) = db.insert_generic(Insert::Domain(NewDomain {
+            value: "example.com".to_string(),
+            unscoped: false,
+        })).unwrap().unwrap();
+        let (_, subdomain_id) = db.insert_generic(Insert::Subdomain(NewSubdomain {
+            domain_id,
+            value: "www.example.com".to_string(),
+            resolvable: None,
+            unscoped: false,
+        })).unwrap().unwrap();
+        let a = ipaddr(&db, "192.0.2.1");
+        let b = ipaddr(&db, "192.0.2.2");
+
+        // seeing the same address again extends the period
+        link(&db, subdomain_id, a);
+        link(&db, subdomain_id, a);
+        let subdomain = Subdomain::by_id(&db, subdomain_id).unwrap();
+        assert_eq!(Resolution::history(&db, &subdomain).unwrap().len(), 1);
+
+        // A -> B -> A is recorded as three periods
+        db.db().execute("DELETE FROM subdomain_ipaddrs").unwrap();
+        link(&db, subdomain_id, b);
+        db.db().execute("DELETE FROM subdomain_ipaddrs").unwrap();
+        link(&db, subdomain_id, a);
+        let history = Resolution::history(&db, &subdomain).unwrap();
+        assert_eq!(history.iter().map(|r| r.ip_addr_id).collect::<Vec<_>>(), vec![a, b, a]);
+
+        // only the ongoing period of A is hidden from the previous addresses
+        let detailed = subdomain.detailed_history(&db).unwrap().to_string();
+        assert!(detailed.contains("previously 192.0.2.2"));
+        assert!(detailed.contains("previously 192.0.2.1"));
+    }
+}
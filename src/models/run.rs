@@ -0,0 +1,55 @@
+use crate::errors::*;
+use serde::{Serialize, Deserialize};
+use crate::schema::runs;
+use diesel::prelude::*;
+use crate::models::*;
+use chrono::{NaiveDateTime, Utc};
+
+/// A module run, used to look up changes since a run has been started
+#[derive(Identifiable, Queryable, Serialize, Deserialize, PartialEq, Debug)]
+#[table_name="runs"]
+pub struct Run {
+    pub id: i32,
+    pub module: String,
+    pub version: String,
+    pub started: NaiveDateTime,
+}
+
+#[derive(Insertable, PartialEq, Debug)]
+#[table_name="runs"]
+pub struct NewRun<'a> {
+    pub module: &'a str,
+    pub version: &'a str,
+    pub started: NaiveDateTime,
+}
+
+impl Run {
+    pub fn start(db: &Database, module: &str, version: &str) -> Result<()> {
+        diesel::insert_into(runs::table)
+            .values(NewRun {
+                module,
+                version,
+                started: Utc::now().naive_utc(),
+            })
+            .execute(db.db())?;
+        Ok(())
+    }
+
+    pub fn get(db: &Database, my_id: i32) -> Result<Self> {
+        use crate::schema::runs::dsl::*;
+        runs
+            .filter(id.eq(my_id))
+            .first::<Self>(db.db())
+            .optional()?
+            .ok_or_else(|| NotFoundError(format!("Run doesn't exist: #{}", my_id)).into())
+    }
+
+    pub fn last(db: &Database) -> Result<Self> {
+        use crate::schema::runs::dsl::*;
+        runs
+            .order_by(id.desc())
+            .first::<Self>(db.db())
+            .optional()?
+            .ok_or_else(|| format_err!("No module has been run in this workspace yet"))
+    }
+}
@@ -1,8 +1,10 @@
 use crate::errors::*;
+use chrono::NaiveDateTime;
 use serde::{Serialize, Deserialize};
 use crate::fmt::colors::*;
 use diesel::prelude::*;
 use crate::models::*;
+use std::collections::HashSet;
 use std::result;
 
 #[derive(Identifiable, Queryable, Associations, Serialize, Deserialize, PartialEq, Debug)]
@@ -14,6 +16,10 @@ pub struct Subdomain {
     pub value: String,
     pub unscoped: bool,
     pub resolvable: Option<bool>,
+
+    pub seen_count: i32,
+    pub seen_first: Option<NaiveDateTime>,
+    pub seen_last: Option<NaiveDateTime>,
 }
 
 impl Model for Subdomain {
@@ -40,6 +46,30 @@ impl Model for Subdomain {
         Ok(results)
     }
 
+    fn filter_page(db: &Database, filter: &Filter, after: i32, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::subdomains::dsl::*;
+
+        let query = subdomains.filter(filter.sql())
+            .filter(id.gt(after))
+            .order(id)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
+    fn filter_range(db: &Database, filter: &Filter, offset: usize, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::subdomains::dsl::*;
+
+        let query = subdomains.filter(filter.sql())
+            .order(id)
+            .offset(offset as i64)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
     fn delete(db: &Database, filter: &Filter) -> Result<usize> {
         use crate::schema::subdomains::dsl::*;
 
@@ -94,6 +124,10 @@ impl Model for Subdomain {
 }
 
 impl Scopable for Subdomain {
+    fn table() -> crate::db::Table {
+        crate::db::Table::Subdomains
+    }
+
     fn scoped(&self) -> bool {
         !self.unscoped
     }
@@ -163,6 +197,9 @@ pub struct DetailedSubdomain {
     id: i32,
     value: String,
     ipaddrs: Vec<PrintableIpAddr>,
+    /// Addresses this subdomain doesn't resolve to anymore
+    previous: Vec<PrintableResolution>,
+    expand_history: bool,
     unscoped: bool,
 }
 
@@ -185,6 +222,13 @@ impl DisplayableDetailed for DetailedSubdomain {
         for ipaddr in &self.ipaddrs {
             w.child(ipaddr)?;
         }
+        if self.expand_history {
+            for resolution in &self.previous {
+                w.child(format!("previously {}", resolution))?;
+            }
+        } else if !self.previous.is_empty() {
+            w.child(format!("+{} previous ip addresses, use --history to show them", self.previous.len()))?;
+        }
         Ok(())
     }
 }
@@ -195,7 +239,18 @@ impl Detailed for Subdomain {
     type T = DetailedSubdomain;
 
     fn detailed(&self, db: &Database) -> Result<Self::T> {
-        let ipaddrs = self.ip_addrs(db)?.into_iter()
+        let ipaddrs = self.ip_addrs(db)?;
+        let mut current = ipaddrs.iter()
+            .map(|ip| ip.id)
+            .collect::<HashSet<_>>();
+
+        // the most recent period of a current address is still ongoing
+        let previous = Resolution::history(db, self)?.into_iter()
+            .filter(|r| !current.remove(&r.ip_addr_id))
+            .map(|r| r.printable(db))
+            .collect::<Result<_>>()?;
+
+        let ipaddrs = ipaddrs.into_iter()
             .map(|ip| ip.printable(db))
             .collect::<Result<_>>()?;
 
@@ -203,9 +258,17 @@ impl Detailed for Subdomain {
             id: self.id,
             value: self.value.to_string(),
             ipaddrs,
+            previous,
+            expand_history: false,
             unscoped: self.unscoped,
         })
     }
+
+    fn detailed_history(&self, db: &Database) -> Result<Self::T> {
+        let mut detailed = self.detailed(db)?;
+        detailed.expand_history = true;
+        Ok(detailed)
+    }
 }
 
 #[derive(Debug, Clone, Insertable, Serialize, Deserialize)]
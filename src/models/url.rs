@@ -1,6 +1,7 @@
 use crate::ser::StringOrBytes;
 use serde::{Serialize, Deserialize};
 use crate::errors::*;
+use chrono::NaiveDateTime;
 use crate::fmt::Write;
 use crate::fmt::colors::*;
 use crate::models::*;
@@ -20,6 +21,10 @@ pub struct Url {
     pub online: Option<bool>,
     pub title: Option<String>,
     pub redirect: Option<String>,
+
+    pub seen_count: i32,
+    pub seen_first: Option<NaiveDateTime>,
+    pub seen_last: Option<NaiveDateTime>,
 }
 
 impl Model for Url {
@@ -46,6 +51,30 @@ impl Model for Url {
         Ok(results)
     }
 
+    fn filter_page(db: &Database, filter: &Filter, after: i32, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::urls::dsl::*;
+
+        let query = urls.filter(filter.sql())
+            .filter(id.gt(after))
+            .order(id)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
+    fn filter_range(db: &Database, filter: &Filter, offset: usize, limit: usize) -> Result<Vec<Self>> {
+        use crate::schema::urls::dsl::*;
+
+        let query = urls.filter(filter.sql())
+            .order(id)
+            .offset(offset as i64)
+            .limit(limit as i64);
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
     fn delete(db: &Database, filter: &Filter) -> Result<usize> {
         use crate::schema::urls::dsl::*;
 
@@ -100,6 +129,10 @@ impl Model for Url {
 }
 
 impl Scopable for Url {
+    fn table() -> crate::db::Table {
+        crate::db::Table::Urls
+    }
+
     fn scoped(&self) -> bool {
         !self.unscoped
     }
@@ -0,0 +1,65 @@
+use crate::errors::*;
+use serde::{Serialize, Deserialize};
+use crate::schema::views;
+use diesel::prelude::*;
+use crate::models::*;
+
+/// A named filter that is saved in the workspace
+#[derive(Identifiable, Queryable, Serialize, Deserialize, PartialEq, Debug)]
+#[table_name="views"]
+pub struct View {
+    #[serde(skip)]
+    pub id: i32,
+    pub name: String,
+    pub query: String,
+}
+
+#[derive(Insertable, PartialEq, Debug)]
+#[table_name="views"]
+pub struct NewView<'a> {
+    pub name: &'a str,
+    pub query: &'a str,
+}
+
+impl View {
+    pub fn list(db: &Database) -> Result<Vec<Self>> {
+        use crate::schema::views::dsl::*;
+        views
+            .order_by(name.asc())
+            .load::<Self>(db.db())
+            .map_err(Error::from)
+    }
+
+    pub fn get(db: &Database, my_name: &str) -> Result<Self> {
+        use crate::schema::views::dsl::*;
+        views
+            .filter(name.eq(my_name))
+            .first::<Self>(db.db())
+            .optional()?
+            .ok_or_else(|| NotFoundError(format!("View doesn't exist: {:?}", my_name)).into())
+    }
+
+    /// Create the view or replace the filter if it already exists
+    pub fn save(db: &Database, my_name: &str, filter: &Filter) -> Result<()> {
+        diesel::replace_into(views::table)
+            .values(NewView {
+                name: my_name,
+                query: filter.query(),
+            })
+            .execute(db.db())?;
+        Ok(())
+    }
+
+    /// Returns false if the view didn't exist
+    pub fn delete(db: &Database, my_name: &str) -> Result<bool> {
+        use crate::schema::views::dsl::*;
+        let rows = diesel::delete(views.filter(name.eq(my_name)))
+            .execute(db.db())?;
+        Ok(rows > 0)
+    }
+
+    #[inline]
+    pub fn filter(&self) -> Filter {
+        Filter::new(self.query.as_str())
+    }
+}
@@ -0,0 +1,323 @@
+use crate::config::Config;
+use crate::errors::*;
+use crate::notify::Notification;
+use crate::notify::webhook::{self, default_retries};
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+#[inline]
+fn default_batch() -> usize {
+    20
+}
+
+#[inline]
+fn default_delay() -> u64 {
+    10
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Platform {
+    Slack,
+    Discord,
+}
+
+impl Platform {
+    /// The length of text the platform accepts in a section or embed
+    fn limit(self) -> usize {
+        match self {
+            Platform::Slack => 3000,
+            Platform::Discord => 4096,
+        }
+    }
+
+    fn bold(self, s: &str) -> String {
+        match self {
+            Platform::Slack => format!("*{}*", s),
+            Platform::Discord => format!("**{}**", s),
+        }
+    }
+
+    fn escape(self, s: &str) -> String {
+        match self {
+            Platform::Slack => escape_slack(s),
+            Platform::Discord => escape_discord(s),
+        }
+    }
+
+    /// Markup isn't applied in code spans, but slack still needs entities
+    fn code(self, s: &str) -> String {
+        let s = match self {
+            Platform::Slack => escape_slack(s),
+            Platform::Discord => s.to_string(),
+        };
+        format!("`{}`", s.replace('`', "'"))
+    }
+}
+
+/// An incoming webhook of a chat platform
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatConfig {
+    pub url: String,
+    /// The most events that are listed in a single message
+    #[serde(default = "default_batch")]
+    pub batch: usize,
+    /// Seconds to wait for more events before a message is sent
+    #[serde(default = "default_delay")]
+    pub delay: u64,
+    #[serde(default = "default_retries")]
+    pub retries: usize,
+}
+
+impl ChatConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.batch == 0 {
+            bail!("batch needs to be at least 1");
+        }
+        webhook::validate_url(&self.url)
+    }
+}
+
+/// A notification that is waiting to be sent with other events of the burst
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    /// Set for database events, the entity type and the change
+    pub entity: Option<(String, String)>,
+    pub value: String,
+}
+
+impl Event {
+    pub fn new(topic: &str, notification: &Notification) -> Event {
+        // db:<family>:<value>:<action>, the value may contain colons itself
+        let entity = topic.strip_prefix("db:")
+            .and_then(|x| x.split_once(':'))
+            .and_then(|(family, rest)| {
+                let (value, action) = rest.rsplit_once(':')?;
+                Some((family, value, action))
+            });
+
+        match entity {
+            Some((family, value, action)) => Event {
+                entity: Some((family.to_string(), action.to_string())),
+                value: value.to_string(),
+            },
+            None => Event {
+                entity: None,
+                value: notification.subject.clone(),
+            },
+        }
+    }
+
+    fn is_insert(&self) -> bool {
+        matches!(&self.entity, Some((_, action)) if action == "insert")
+    }
+}
+
+/// Events that are coalesced into one message
+#[derive(Debug)]
+pub struct Batch {
+    workspace: String,
+    started: Instant,
+    events: Vec<Event>,
+}
+
+impl Batch {
+    pub fn new(workspace: &str) -> Batch {
+        Batch {
+            workspace: workspace.to_string(),
+            started: Instant::now(),
+            events: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn push(&mut self, event: Event) {
+        self.events.push(event);
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// During a run this is checked regularly, outside of runs only after
+    /// a command of the shell finished
+    pub fn is_due(&self, config: &ChatConfig) -> bool {
+        self.len() >= config.batch || self.started.elapsed() >= Duration::from_secs(config.delay)
+    }
+
+    fn title(&self) -> String {
+        let n = self.events.len();
+        if self.events.iter().all(Event::is_insert) {
+            match &self.events[..] {
+                [Event { entity: Some((family, _)), .. }] => format!("New {} in {}", family, self.workspace),
+                _ => format!("{} new entities in {}", n, self.workspace),
+            }
+        } else if n == 1 {
+            format!("Notification from {}", self.workspace)
+        } else {
+            format!("{} notifications from {}", n, self.workspace)
+        }
+    }
+
+    fn lines(&self, platform: Platform) -> String {
+        let mut out = String::new();
+        for (i, event) in self.events.iter().enumerate() {
+            let mut line = match &event.entity {
+                Some((family, action)) => {
+                    let mut line = format!("{} {}", platform.bold(family), platform.code(&event.value));
+                    if action != "insert" {
+                        write!(line, " ({})", action).expect("out of memory");
+                    }
+                    line
+                },
+                None => platform.escape(&event.value),
+            };
+            if !out.is_empty() {
+                line.insert(0, '\n');
+            }
+
+            // leave room for the note about the missing events
+            if out.len() + line.len() + 32 > platform.limit() {
+                write!(out, "\n... and {} more", self.events.len() - i).expect("out of memory");
+                break;
+            }
+            out.push_str(&line);
+        }
+        out
+    }
+
+    pub fn message(&self, platform: Platform) -> serde_json::Value {
+        let title = self.title();
+        match platform {
+            Platform::Slack => serde_json::json!({
+                "text": title,
+                "blocks": [
+                    {
+                        "type": "header",
+                        "text": { "type": "plain_text", "text": title },
+                    },
+                    {
+                        "type": "section",
+                        "text": { "type": "mrkdwn", "text": self.lines(Platform::Slack) },
+                    },
+                ],
+            }),
+            Platform::Discord => serde_json::json!({
+                "embeds": [
+                    {
+                        "title": title,
+                        "description": self.lines(Platform::Discord),
+                        "footer": { "text": "sn0int" },
+                    },
+                ],
+            }),
+        }
+    }
+
+    pub fn send(&self, config: &Config, chat: &ChatConfig, platform: Platform) -> Result<()> {
+        webhook::post(config, &chat.url, &HashMap::new(), chat.retries, &self.message(platform))
+    }
+}
+
+/// Slack only needs the control characters of its markup escaped
+fn escape_slack(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn escape_discord(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if "\\*_~|>#[]()".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(topic: &str) -> Event {
+        Event::new(topic, &Notification {
+            subject: "Login worked".to_string(),
+            body: None,
+            entity: None,
+        })
+    }
+
+    #[test]
+    fn test_event_topic() {
+        assert_eq!(event("db:url:https://example.com/:insert"), Event {
+            entity: Some(("url".to_string(), "insert".to_string())),
+            value: "https://example.com/".to_string(),
+        });
+        assert_eq!(event("module:harness/login-check:success"), Event {
+            entity: None,
+            value: "Login worked".to_string(),
+        });
+    }
+
+    #[test]
+    fn test_slack_message() {
+        let mut batch = Batch::new("demo");
+        batch.push(event("db:domain:example.com:insert"));
+        batch.push(event("db:subdomain:<www>.example.com:insert"));
+        assert_eq!(batch.message(Platform::Slack), serde_json::json!({
+            "text": "2 new entities in demo",
+            "blocks": [
+                {
+                    "type": "header",
+                    "text": { "type": "plain_text", "text": "2 new entities in demo" },
+                },
+                {
+                    "type": "section",
+                    "text": { "type": "mrkdwn", "text": "*domain* `example.com`\n*subdomain* `&lt;www&gt;.example.com`" },
+                },
+            ],
+        }));
+    }
+
+    #[test]
+    fn test_discord_message() {
+        let mut batch = Batch::new("demo");
+        batch.push(event("db:subdomain:www.example.com:insert"));
+        assert_eq!(batch.message(Platform::Discord)["embeds"][0]["title"], "New subdomain in demo");
+
+        batch.push(event("db:ipaddr:192.0.2.1:update"));
+        batch.push(Event::new("module:harness/login-check:success", &Notification {
+            subject: "Login worked for *admin*".to_string(),
+            body: None,
+            entity: None,
+        }));
+        let msg = batch.message(Platform::Discord);
+        assert_eq!(msg["embeds"][0]["title"], "3 notifications from demo");
+        assert_eq!(msg["embeds"][0]["description"], "**subdomain** `www.example.com`\n**ipaddr** `192.0.2.1` (update)\nLogin worked for \\*admin\\*");
+    }
+
+    #[test]
+    fn test_truncate() {
+        let mut batch = Batch::new("demo");
+        for i in 0..200 {
+            batch.push(event(&format!("db:url:https://example.com/{}/{}:insert", "a".repeat(40), i)));
+        }
+        let text = batch.lines(Platform::Slack);
+        assert!(text.len() <= 3000);
+        assert!(text.ends_with(" more"));
+    }
+
+    #[test]
+    fn test_due() {
+        let config: ChatConfig = toml::from_str("url = \"https://hooks.slack.com/services/x\"\nbatch = 2").unwrap();
+        let mut batch = Batch::new("demo");
+        batch.push(event("db:domain:example.com:insert"));
+        assert!(!batch.is_due(&config));
+        batch.push(event("db:domain:example.org:insert"));
+        assert!(batch.is_due(&config));
+    }
+}
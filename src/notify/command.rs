@@ -0,0 +1,162 @@
+use crate::errors::*;
+use crate::keyring;
+use serde::{Serialize, Deserialize};
+use std::env;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[inline]
+fn default_timeout() -> u64 {
+    30
+}
+
+/// A local program that receives the event as json on stdin
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandConfig {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Seconds until the process is killed
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    /// Send all events of a run as one json array once the run finishes
+    #[serde(default)]
+    pub batch: bool,
+}
+
+impl CommandConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.command.is_empty() {
+            bail!("command can't be empty");
+        }
+        if self.timeout == 0 {
+            bail!("timeout needs to be at least 1 second");
+        }
+        Ok(())
+    }
+
+    /// Keys and passphrases that were passed through the environment are only
+    /// meant for us
+    fn command(&self) -> Command {
+        let mut cmd = Command::new(&self.command);
+        cmd.args(&self.args);
+        for (key, _) in env::vars_os() {
+            if keyring::is_secret_env(&key.to_string_lossy()) {
+                cmd.env_remove(key);
+            }
+        }
+        cmd.stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped());
+        cmd
+    }
+
+    pub fn run(&self, input: &serde_json::Value) -> Result<()> {
+        let mut input = serde_json::to_vec(input)?;
+        input.push(b'\n');
+
+        debug!("Executing {:?} with {:?}", self.command, self.args);
+        let mut child = self.command()
+            .spawn()
+            .context(format_err!("Failed to execute {:?}", self.command))?;
+
+        // write and read in the background so a child that ignores stdin or
+        // writes a lot to stderr can't block us past the timeout
+        let mut stdin = child.stdin.take().expect("Failed to take child stdin");
+        thread::spawn(move || {
+            if let Err(err) = stdin.write_all(&input) {
+                debug!("Failed to write event to child: {}", err);
+            }
+        });
+        let mut stderr = child.stderr.take().expect("Failed to take child stderr");
+        let stderr = thread::spawn(move || {
+            let mut buf = Vec::new();
+            let _ = stderr.read_to_end(&mut buf);
+            buf
+        });
+
+        let deadline = Instant::now() + Duration::from_secs(self.timeout);
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                child.kill()?;
+                child.wait()?;
+                bail!("{:?} didn't finish within {} seconds", self.command, self.timeout);
+            }
+            thread::sleep(Duration::from_millis(20));
+        };
+
+        if !status.success() {
+            let stderr = stderr.join().unwrap_or_default();
+            let stderr = String::from_utf8_lossy(&stderr);
+            bail!("{:?} failed with {}: {}", self.command, status, stderr.trim());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> CommandConfig {
+        CommandConfig {
+            command: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+            timeout: 5,
+            batch: false,
+        }
+    }
+
+    #[test]
+    fn test_stdin() {
+        let config = sh(r#"read -r line && test "$line" = '{"subject":"hello"}'"#);
+        config.run(&serde_json::json!({"subject": "hello"})).unwrap();
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let err = sh("echo broken >&2; exit 3").run(&serde_json::json!({})).unwrap_err();
+        let err = err.to_string();
+        assert!(err.contains("exit status: 3"), "{}", err);
+        assert!(err.ends_with(": broken"), "{}", err);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut config = sh("sleep 10");
+        config.timeout = 1;
+        let start = Instant::now();
+        assert!(config.run(&serde_json::json!({})).is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    /// Removes the variable again once the test is done, even if it fails
+    struct EnvGuard(&'static str);
+
+    impl EnvGuard {
+        fn set(key: &'static str, value: &str) -> EnvGuard {
+            env::set_var(key, value);
+            EnvGuard(key)
+        }
+    }
+
+    impl Drop for EnvGuard {
+        fn drop(&mut self) {
+            env::remove_var(self.0);
+        }
+    }
+
+    #[test]
+    fn test_no_secrets() {
+        let _key = EnvGuard::set("SN0INT_KEY_notifytest__token", "secret");
+        let _keyring = EnvGuard::set("SN0INT_KEYRING_PASSPHRASE", "secret");
+        let _workspace = EnvGuard::set("SN0INT_WORKSPACE_PASSPHRASE", "secret");
+        sh(r#"test -z "$SN0INT_KEY_notifytest__token" && test -z "$SN0INT_KEYRING_PASSPHRASE" && test -z "$SN0INT_WORKSPACE_PASSPHRASE""#)
+            .run(&serde_json::json!({})).unwrap();
+    }
+}
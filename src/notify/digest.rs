@@ -0,0 +1,180 @@
+use crate::db::Filter;
+use crate::errors::*;
+use crate::notify::Notification;
+use crate::notify::chat::Event;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::result;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// How many values are listed in a digest, the rest is only counted
+const VALUES: usize = 10;
+
+/// When the events of a digest are sent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Window {
+    EndOfRun,
+    Every(Duration),
+}
+
+impl FromStr for Window {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Window> {
+        if s == "end-of-run" {
+            return Ok(Window::EndOfRun);
+        }
+        let window = Filter::parse_age(s)
+            .and_then(|x| x.to_std().ok())
+            .filter(|x| !x.is_zero())
+            .ok_or_else(|| format_err!("Invalid batch {:?}, expected end-of-run or something like 90s, 15m or 12h", s))?;
+        Ok(Window::Every(window))
+    }
+}
+
+impl Serialize for Window {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        match self {
+            Window::EndOfRun => serializer.serialize_str("end-of-run"),
+            Window::Every(window) => serializer.serialize_str(&format!("{}s", window.as_secs())),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Window {
+    fn deserialize<D>(deserializer: D) -> result::Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let s = String::deserialize(deserializer)?;
+        FromStr::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+/// Events of a route that are summarized into one notification
+#[derive(Debug)]
+pub struct Digest {
+    started: Instant,
+    events: Vec<Event>,
+}
+
+impl Digest {
+    pub fn start() -> Digest {
+        Digest {
+            started: Instant::now(),
+            events: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn push(&mut self, event: Event) {
+        self.events.push(event);
+    }
+
+    pub fn is_due(&self, window: Window) -> bool {
+        match window {
+            Window::EndOfRun => false,
+            Window::Every(window) => self.started.elapsed() >= window,
+        }
+    }
+
+    fn label(event: &Event) -> &str {
+        match &event.entity {
+            Some((family, _)) => family,
+            None => "notification",
+        }
+    }
+
+    fn counts(&self) -> BTreeMap<&str, usize> {
+        let mut counts = BTreeMap::new();
+        for event in &self.events {
+            *counts.entry(Self::label(event)).or_default() += 1;
+        }
+        counts
+    }
+
+    pub fn notification(&self) -> Notification {
+        let counts = self.counts();
+        let total = self.events.len();
+
+        let mut subject = if total == 1 {
+            String::from("1 event: ")
+        } else {
+            format!("{} events: ", total)
+        };
+        let summary = counts.iter()
+            .map(|(label, n)| format!("{} {}", n, label))
+            .collect::<Vec<_>>();
+        subject.push_str(&summary.join(", "));
+
+        let mut body = String::new();
+        for event in self.events.iter().take(VALUES) {
+            if !body.is_empty() {
+                body.push('\n');
+            }
+            write!(body, "{} {}", Self::label(event), event.value).expect("out of memory");
+        }
+        if total > VALUES {
+            write!(body, "\n... and {} more", total - VALUES).expect("out of memory");
+        }
+
+        let values = self.events.iter()
+            .take(VALUES)
+            .map(|event| event.value.as_str())
+            .collect::<Vec<_>>();
+        Notification {
+            subject,
+            body: Some(body),
+            entity: Some(serde_json::json!({
+                "total": total,
+                "counts": counts,
+                "values": values,
+            })),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(topic: &str) -> Event {
+        Event::new(topic, &Notification {
+            subject: "Login worked".to_string(),
+            body: None,
+            entity: None,
+        })
+    }
+
+    #[test]
+    fn test_window() {
+        assert_eq!("end-of-run".parse::<Window>().unwrap(), Window::EndOfRun);
+        assert_eq!("5m".parse::<Window>().unwrap(), Window::Every(Duration::from_secs(300)));
+        assert!("0s".parse::<Window>().is_err());
+        assert!("later".parse::<Window>().is_err());
+    }
+
+    #[test]
+    fn test_digest() {
+        let mut digest = Digest::start();
+        for i in 0..12 {
+            digest.push(event(&format!("db:subdomain:{}.example.com:insert", i)));
+        }
+        digest.push(event("db:ipaddr:192.0.2.1:insert"));
+        digest.push(event("module:harness/login-check:success"));
+        assert!(!digest.is_due(Window::EndOfRun));
+
+        let n = digest.notification();
+        assert_eq!(n.subject, "14 events: 1 ipaddr, 1 notification, 12 subdomain");
+        let body = n.body.unwrap();
+        assert!(body.starts_with("subdomain 0.example.com\nsubdomain 1.example.com\n"));
+        assert!(body.ends_with("subdomain 9.example.com\n... and 4 more"));
+        let entity = n.entity.unwrap();
+        assert_eq!(entity["counts"]["subdomain"], 12);
+        assert_eq!(entity["values"].as_array().unwrap().len(), 10);
+    }
+}
@@ -0,0 +1,193 @@
+use crate::config::Config;
+use crate::errors::*;
+use crate::keyring::{KeyName, KeyRing};
+use crate::notify::Notification;
+use crate::notify::webhook::{self, default_retries, TIMEOUT};
+use crate::utils;
+use chrootable_https::{HttpClient, Request, Uri};
+use chrootable_https::header::{AUTHORIZATION, CONTENT_TYPE, USER_AGENT};
+use percent_encoding::{percent_encode, NON_ALPHANUMERIC};
+use serde::{Serialize, Deserialize};
+use std::cmp;
+use std::thread;
+use std::time::Duration;
+
+/// Backoff after transient errors never waits longer than this
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatrixConfig {
+    /// The base url of the homeserver, like `https://matrix.org`
+    pub homeserver: String,
+    /// The id of the room, like `!abcdef:matrix.org`
+    pub room: String,
+    /// The keyring entry with the access token as secret
+    pub key: String,
+    #[serde(default = "default_retries")]
+    pub retries: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct RateLimited {
+    retry_after_ms: Option<u64>,
+}
+
+/// How long to wait before the next attempt, `None` if retrying won't help
+fn retry_after(status: Option<u16>, body: &[u8], attempt: u32) -> Option<Duration> {
+    let backoff = cmp::min(Duration::from_secs(1 << cmp::min(attempt, 6)), MAX_BACKOFF);
+    match status {
+        // the connection failed or timed out
+        None => Some(backoff),
+        Some(429) => {
+            let wait = serde_json::from_slice::<RateLimited>(body).ok()
+                .and_then(|x| x.retry_after_ms)
+                .map(Duration::from_millis)
+                .unwrap_or(backoff);
+            Some(cmp::min(wait, MAX_BACKOFF))
+        },
+        Some(500..=599) => Some(backoff),
+        _ => None,
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl MatrixConfig {
+    pub fn validate(&self) -> Result<()> {
+        webhook::validate_url(&self.homeserver)?;
+        if !self.room.starts_with('!') || !self.room.contains(':') {
+            bail!("Matrix room needs to be a room id like !abcdef:matrix.org: {:?}", self.room);
+        }
+        self.key.parse::<KeyName>()
+            .context(format_err!("Invalid keyring entry for the access token: {:?}", self.key))?;
+        Ok(())
+    }
+
+    fn url(&self, txn: &str) -> String {
+        format!("{}/_matrix/client/v3/rooms/{}/send/m.room.message/{}",
+            self.homeserver.trim_end_matches('/'),
+            percent_encode(self.room.as_bytes(), NON_ALPHANUMERIC),
+            txn)
+    }
+
+    /// The access token is never written to the config, it's read from the
+    /// keyring every time
+    pub fn token(&self, keyring: &KeyRing, workspace: &str) -> Result<String> {
+        let key = self.key.parse::<KeyName>()?;
+        if !keyring.is_visible(&key, workspace) {
+            bail!("Keyring entry {} is not available in this workspace", key);
+        }
+        keyring.get(&key)
+            .and_then(|entry| entry.secret_key)
+            .ok_or_else(|| NotFoundError(format!("Keyring entry {} with the matrix access token not found", key)).into())
+    }
+
+    pub fn message(workspace: &str, notification: &Notification) -> serde_json::Value {
+        let mut body = format!("[{}] {}", workspace, notification.subject);
+        let mut html = format!("<b>[{}]</b> {}", escape_html(workspace), escape_html(&notification.subject));
+        if let Some(text) = &notification.body {
+            body.push_str("\n\n");
+            body.push_str(text);
+            html.push_str("<br><br>");
+            html.push_str(&escape_html(text).replace('\n', "<br>"));
+        }
+
+        serde_json::json!({
+            "msgtype": "m.text",
+            "body": body,
+            "format": "org.matrix.custom.html",
+            "formatted_body": html,
+        })
+    }
+
+    /// Send a message to the room, the transaction id stays the same for all
+    /// attempts so the homeserver can drop duplicates
+    pub fn send(&self, config: &Config, token: &str, message: &serde_json::Value) -> Result<()> {
+        let client = webhook::client(config)?;
+        let user_agent = webhook::user_agent(config);
+        let url = self.url(&utils::random_string(16)).parse::<Uri>()?;
+        let body = serde_json::to_string(message)?;
+
+        let mut attempt = 0;
+        loop {
+            let mut request = Request::put(url.clone());
+            request.header(CONTENT_TYPE, "application/json");
+            request.header(USER_AGENT, user_agent.as_str());
+            request.header(AUTHORIZATION, format!("Bearer {}", token));
+            let request = request.body(body.clone().into())?;
+
+            let (status, err) = match client.request(request).with_timeout(Some(TIMEOUT)).wait_for_response() {
+                Ok(resp) if (200..300).contains(&resp.status) => return Ok(()),
+                Ok(resp) => {
+                    let wait = retry_after(Some(resp.status), &resp.body, attempt);
+                    (wait, format_err!("Homeserver returned http status {}: {}", resp.status, String::from_utf8_lossy(&resp.body)))
+                },
+                Err(err) => (retry_after(None, &[], attempt), err),
+            };
+
+            match status {
+                Some(wait) if (attempt as usize) < self.retries => {
+                    attempt += 1;
+                    debug!("Sending to matrix room {:?} failed, retrying in {:?} ({}/{}): {}", self.room, wait, attempt, self.retries, err);
+                    thread::sleep(wait);
+                },
+                _ => return Err(err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matrix() -> MatrixConfig {
+        toml::from_str(r#"
+            homeserver = "https://matrix.example.com/"
+            room = "!abcdef:example.com"
+            key = "matrix:@sn0int:example.com"
+        "#).unwrap()
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut config = matrix();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.url("txn1"), "https://matrix.example.com/_matrix/client/v3/rooms/%21abcdef%3Aexample%2Ecom/send/m.room.message/txn1");
+        config.room = "#ops:example.com".to_string();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_token() {
+        let config = matrix();
+        let mut keyring = KeyRing::default();
+        assert!(config.token(&keyring, "default").is_err());
+        keyring.load_env(vec![("SN0INT_KEY_matrix__@sn0int:example.com".to_string(), "syt_secret".to_string())]);
+        assert_eq!(config.token(&keyring, "default").unwrap(), "syt_secret");
+    }
+
+    #[test]
+    fn test_message() {
+        let msg = MatrixConfig::message("demo", &Notification {
+            subject: "Added url \"https://example.com/?a=1&b=<2>\"".to_string(),
+            body: Some("line 1\nline 2".to_string()),
+            entity: None,
+        });
+        assert_eq!(msg["body"], "[demo] Added url \"https://example.com/?a=1&b=<2>\"\n\nline 1\nline 2");
+        assert_eq!(msg["formatted_body"], "<b>[demo]</b> Added url &quot;https://example.com/?a=1&amp;b=&lt;2&gt;&quot;<br><br>line 1<br>line 2");
+    }
+
+    #[test]
+    fn test_retry_after() {
+        assert_eq!(retry_after(Some(429), br#"{"errcode":"M_LIMIT_EXCEEDED","retry_after_ms":1500}"#, 0), Some(Duration::from_millis(1500)));
+        assert_eq!(retry_after(Some(502), b"", 2), Some(Duration::from_secs(4)));
+        assert_eq!(retry_after(None, b"", 10), Some(MAX_BACKOFF));
+        assert_eq!(retry_after(Some(403), b"", 0), None);
+    }
+}
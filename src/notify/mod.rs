@@ -1,25 +1,46 @@
+mod chat;
+mod command;
+mod digest;
+mod matrix;
 mod rules;
+mod webhook;
 
 use clap::Parser;
 use crate::cmd::run_cmd::Params;
 use crate::cmd::run_cmd::prepare_keyring;
+use crate::db::{Database, Filter};
 use crate::engine::Module;
 use crate::errors::*;
+use crate::ipc::files::ReadGrants;
 use crate::options;
+use crate::output::OutputFormat;
+use crate::sandbox::SandboxMode;
 use crate::shell::Shell;
-use crate::term::SpinLogger;
+use crate::term::{SpinLogger, Stack};
 use crate::worker;
+use chrono::Utc;
+use self::chat::{Batch, Event, Platform};
+use self::digest::Digest;
 use self::rules::Glob;
+pub use self::chat::ChatConfig;
+pub use self::command::CommandConfig;
+pub use self::digest::Window;
+pub use self::matrix::MatrixConfig;
+pub use self::webhook::WebhookConfig;
 use serde::{Serialize, Deserialize};
 use sn0int_common::metadata::Source;
 use sn0int_std::blobs::Blob;
 use sn0int_std::ratelimits::Ratelimiter;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 #[derive(Debug, Parser, Serialize)]
 pub struct Notification {
     pub subject: String,
     pub body: Option<String>,
+    /// The entity that triggered the notification, if any
+    #[arg(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entity: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -28,9 +49,45 @@ pub struct NotificationConfig {
     pub workspaces: Vec<String>,
     #[serde(default)]
     pub topics: Vec<Glob>,
-    pub script: String,
+    pub script: Option<String>,
     #[serde(default)]
     pub options: Vec<options::Opt>,
+    pub webhook: Option<WebhookConfig>,
+    pub slack: Option<ChatConfig>,
+    pub discord: Option<ChatConfig>,
+    pub matrix: Option<MatrixConfig>,
+    pub exec: Option<CommandConfig>,
+    /// Collect the events and send a summary at the end of the run or
+    /// after the window
+    pub batch: Option<Window>,
+    /// A filter like in select that the entity of the event needs to match
+    pub filter: Option<toml::Spanned<String>>,
+}
+
+/// Why a notification rule did or didn't fire for an event
+#[derive(Debug, PartialEq)]
+pub enum Route {
+    Matches,
+    Workspace,
+    Topic,
+    Filter,
+}
+
+/// Notification state that is kept until the end of a run
+#[derive(Debug, Default)]
+pub struct RunState {
+    failed: HashSet<String>,
+    batches: BTreeMap<String, Batch>,
+    /// Events for commands that are executed once per run
+    commands: BTreeMap<String, Vec<serde_json::Value>>,
+    digests: BTreeMap<String, Digest>,
+}
+
+impl RunState {
+    /// Remember a failed sink, returns true if it's the first failure
+    fn failed(&mut self, name: &str) -> bool {
+        self.failed.insert(name.to_string())
+    }
 }
 
 fn apply_rule<T>(name: &str, filters: &[T], value: &str, cmp: fn(&T, &str) -> Option<bool>) -> bool {
@@ -57,16 +114,133 @@ fn apply_rule<T>(name: &str, filters: &[T], value: &str, cmp: fn(&T, &str) -> Op
 }
 
 impl NotificationConfig {
-    fn matches(&self, name: &str, workspace: &str, topic: &str) -> bool {
+    pub fn validate(&self) -> Result<()> {
+        let sinks = [self.script.is_some(), self.webhook.is_some(), self.slack.is_some(), self.discord.is_some(), self.matrix.is_some(), self.exec.is_some()];
+        match sinks.iter().filter(|x| **x).count() {
+            0 => bail!("One of script, webhook, slack, discord, matrix or exec needs to be set"),
+            1 => (),
+            _ => bail!("Only one of script, webhook, slack, discord, matrix and exec can be set"),
+        }
+
+        if let Some(webhook) = &self.webhook {
+            webhook.validate()?;
+        }
+        if let Some(matrix) = &self.matrix {
+            matrix.validate()?;
+        }
+        if let Some(exec) = &self.exec {
+            exec.validate()?;
+        }
+        if let Some((_, chat)) = self.chat() {
+            chat.validate()?;
+        }
+        Ok(())
+    }
+
+    fn chat(&self) -> Option<(Platform, &ChatConfig)> {
+        match (&self.slack, &self.discord) {
+            (Some(slack), _) => Some((Platform::Slack, slack)),
+            (_, Some(discord)) => Some((Platform::Discord, discord)),
+            _ => None,
+        }
+    }
+
+    pub fn validate_filter(expr: &str) -> Result<()> {
+        Filter::parse_expr(None, expr)?
+            .validate()
+    }
+
+    /// Notifications without an entity never match a filter
+    fn matches_entity(&self, db: &Database, entity: Option<&serde_json::Value>) -> bool {
+        let Some(filter) = &self.filter else { return true };
+        let Some(entity) = entity else {
+            debug!("Notification has no entity to match the filter against");
+            return false;
+        };
+
+        let result = Filter::parse_expr(None, filter.get_ref())
+            .and_then(|filter| filter.matches_json(db, entity));
+        match result {
+            Ok(matches) => matches,
+            Err(err) => {
+                // columns that don't exist on this kind of entity end up here
+                debug!("Filter {:?} can't be applied to entity: {}", filter.get_ref(), err);
+                false
+            },
+        }
+    }
+
+    fn route_topic(&self, name: &str, workspace: &str, topic: &str) -> Route {
         debug!("Testing notification with rules: {:?}", name);
         if !apply_rule("workspace", &self.workspaces, workspace, |filter, value| if filter == value { Some(true) } else { None }) {
-            return false;
+            return Route::Workspace;
         }
         if !apply_rule("topic", &self.topics, topic, |filter, value| filter.matches(value)) {
-            return false;
+            return Route::Topic;
+        }
+        Route::Matches
+    }
+
+    pub fn route(&self, name: &str, workspace: &str, topic: &str, db: &Database, entity: Option<&serde_json::Value>) -> Route {
+        match self.route_topic(name, workspace, topic) {
+            Route::Matches if !self.matches_entity(db, entity) => Route::Filter,
+            Route::Matches => {
+                debug!("Notification matches this config");
+                Route::Matches
+            },
+            route => route,
+        }
+    }
+
+    #[cfg(test)]
+    fn matches(&self, name: &str, workspace: &str, topic: &str) -> bool {
+        self.route_topic(name, workspace, topic) == Route::Matches
+    }
+}
+
+/// Notifications of a run are queued while the database changes that caused
+/// them are written, sinks may block for a while with their retries and the
+/// write lock would be held during that time. They are sent once the changes
+/// have been committed.
+#[derive(Debug, Default)]
+pub struct Outbox {
+    queued: Vec<(String, String, Notification)>,
+}
+
+impl Outbox {
+    /// Queue notifications of a source, they're logged with its name
+    pub fn extend(&mut self, source: &str, notifications: Vec<(String, Notification)>) {
+        for (topic, notification) in notifications {
+            self.queued.push((source.to_string(), topic, notification));
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.queued.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.queued.is_empty()
+    }
+
+    /// Forget the queued notifications, returns how many there were
+    pub fn discard(&mut self) -> usize {
+        let len = self.len();
+        self.queued.clear();
+        len
+    }
+
+    /// Send everything in the queue, this must not be called while a batch
+    /// of database changes is still open
+    pub fn send<S: Stack>(&mut self, rl: &mut Shell, stack: &mut S, ratelimit: &mut Ratelimiter) {
+        for (source, topic, notification) in std::mem::take(&mut self.queued) {
+            let spinner = &mut stack.prefixed(source);
+            if let Err(err) = trigger_notify_event(rl, spinner, ratelimit, &topic, &notification) {
+                spinner.error(&format!("Failed to send notifications: {}", err));
+            }
         }
-        debug!("Notification matches this config");
-        true
     }
 }
 
@@ -90,7 +264,9 @@ pub fn exec(rl: &mut Shell, module: &Module, ratelimit: &mut Ratelimiter, option
     let params = Params {
         module: None,
         threads: 1,
+        max_concurrency: None,
         verbose,
+        quiet: false,
         stdin: false,
         grants: &[],
         grant_full_keyring: false,
@@ -98,18 +274,237 @@ pub fn exec(rl: &mut Shell, module: &Module, ratelimit: &mut Ratelimiter, option
         exit_on_error: false,
         proxy: None,
         user_agent: None,
+        precondition: None,
+        allow_read: &[],
+        sandbox: SandboxMode::Strict,
+        no_prompt: true,
+        output: OutputFormat::Human,
     };
 
     prepare_keyring(rl.keyring_mut(), module, &params)?;
     let args = vec![prepare_arg(notification)?];
 
     debug!("Executing notification module {:?}", module_name);
-    let errors = worker::spawn(rl, module, ratelimit, args, &params, rl.config().network.proxy, None, options);
+    let errors = worker::spawn(rl, module, ratelimit, args, &params, rl.config().network.proxy, None, options, ReadGrants::default()).errors;
     debug!("Notification module {:?} exited with {:?} errors", module_name, errors);
 
     Ok(errors)
 }
 
+fn send_webhook<T: SpinLogger>(rl: &mut Shell, spinner: &mut T, dry_run: bool, name: &str, webhook: &WebhookConfig, topic: &str, notification: &Notification) {
+    let body = webhook.body(rl.workspace(), topic, notification, &Utc::now().to_rfc3339());
+    if dry_run {
+        spinner.success(&format!("Sent webhook for {:?} (dry-run)", name));
+        return;
+    }
+
+    match webhook.send(rl.config(), &body) {
+        Ok(()) => spinner.success(&format!("Sent webhook for {:?}", name)),
+        Err(err) => report_failure(rl, spinner, name, err),
+    }
+}
+
+fn send_matrix<T: SpinLogger>(rl: &mut Shell, spinner: &mut T, dry_run: bool, name: &str, matrix: &MatrixConfig, notification: &Notification) {
+    let message = MatrixConfig::message(rl.workspace(), notification);
+    if dry_run {
+        spinner.success(&format!("Sent matrix message for {:?} (dry-run)", name));
+        return;
+    }
+
+    let result = matrix.token(rl.keyring(), rl.workspace())
+        .and_then(|token| matrix.send(rl.config(), &token, &message));
+    match result {
+        Ok(()) => spinner.success(&format!("Sent matrix message for {:?}", name)),
+        Err(err) => report_failure(rl, spinner, name, err),
+    }
+}
+
+fn run_command<T: SpinLogger>(rl: &mut Shell, spinner: &mut T, dry_run: bool, name: &str, exec: &CommandConfig, topic: &str, notification: &Notification) {
+    let event = serde_json::Value::Object(webhook::event(rl.workspace(), topic, notification, &Utc::now().to_rfc3339()));
+    if dry_run {
+        spinner.success(&format!("Executed {:?} for {:?} (dry-run)", exec.command, name));
+    } else if exec.batch {
+        rl.notify_state_mut().commands.entry(name.to_string())
+            .or_default()
+            .push(event);
+    } else {
+        match exec.run(&event) {
+            Ok(()) => spinner.success(&format!("Executed {:?} for {:?}", exec.command, name)),
+            Err(err) => report_failure(rl, spinner, name, err),
+        }
+    }
+}
+
+/// A broken endpoint would otherwise be reported for every single event
+fn report_failure<T: SpinLogger>(rl: &mut Shell, spinner: &mut T, name: &str, err: Error) {
+    if rl.notify_state_mut().failed(name) {
+        spinner.error(&format!("Failed to notify {:?}, further failures in this run are not reported: {}", name, err));
+    } else {
+        debug!("Failed to notify {:?}: {}", name, err);
+    }
+}
+
+fn send_batch<T: SpinLogger>(rl: &mut Shell, spinner: &mut T, name: &str, platform: Platform, chat: &ChatConfig, batch: Batch) {
+    match batch.send(rl.config(), chat, platform) {
+        Ok(()) if batch.len() == 1 => spinner.success(&format!("Sent 1 event to {:?}", name)),
+        Ok(()) => spinner.success(&format!("Sent {} events to {:?}", batch.len(), name)),
+        Err(err) => report_failure(rl, spinner, name, err),
+    }
+}
+
+/// Chat messages are coalesced, bursts of events would run into the rate
+/// limits of the platform otherwise
+fn queue_chat<T: SpinLogger>(rl: &mut Shell, spinner: &mut T, dry_run: bool, name: &str, platform: Platform, chat: &ChatConfig, topic: &str, notification: &Notification) {
+    if dry_run {
+        spinner.success(&format!("Queued message for {:?} (dry-run)", name));
+        return;
+    }
+
+    let workspace = rl.workspace().to_string();
+    let batches = &mut rl.notify_state_mut().batches;
+    let batch = batches.entry(name.to_string())
+        .or_insert_with(|| Batch::new(&workspace));
+    batch.push(Event::new(topic, notification));
+    if batch.is_due(chat) {
+        if let Some(batch) = batches.remove(name) {
+            send_batch(rl, spinner, name, platform, chat, batch);
+        }
+    }
+}
+
+/// Summarize the digests, if `all` is false only the ones whose window closed
+fn flush_digests<T: SpinLogger>(rl: &mut Shell, spinner: &mut T, all: bool) {
+    if rl.notify_state_mut().digests.is_empty() {
+        return;
+    }
+    let configs = rl.config().notifications.clone();
+    let digests = std::mem::take(&mut rl.notify_state_mut().digests);
+    let mut ratelimit = Ratelimiter::new();
+    for (name, digest) in digests {
+        let Some(config) = configs.get(&name) else { continue };
+        let Some(window) = config.batch else { continue };
+        if !all && !digest.is_due(window) {
+            rl.notify_state_mut().digests.insert(name, digest);
+            continue;
+        }
+
+        let topic = format!("digest:{}", name);
+        if let Err(err) = deliver(rl, spinner, &mut ratelimit, false, &name, config, &topic, &digest.notification()) {
+            report_failure(rl, spinner, &name, err);
+        }
+    }
+}
+
+fn flush_queued<T: SpinLogger>(rl: &mut Shell, spinner: &mut T) {
+    let batches = std::mem::take(&mut rl.notify_state_mut().batches);
+    for (name, batch) in batches {
+        let chat = rl.config().notifications.get(&name)
+            .and_then(|config| config.chat())
+            .map(|(platform, chat)| (platform, chat.clone()));
+        if let Some((platform, chat)) = chat {
+            send_batch(rl, spinner, &name, platform, &chat, batch);
+        }
+    }
+    let commands = std::mem::take(&mut rl.notify_state_mut().commands);
+    for (name, events) in commands {
+        let exec = rl.config().notifications.get(&name)
+            .and_then(|config| config.exec.clone());
+        if let Some(exec) = exec {
+            match exec.run(&serde_json::Value::Array(events)) {
+                Ok(()) => spinner.success(&format!("Executed {:?} for {:?}", exec.command, name)),
+                Err(err) => report_failure(rl, spinner, &name, err),
+            }
+        }
+    }
+}
+
+/// Send everything that's still queued and forget about failures
+pub fn finish_run<T: SpinLogger>(rl: &mut Shell, spinner: &mut T) {
+    flush_digests(rl, spinner, true);
+    flush_queued(rl, spinner);
+    *rl.notify_state_mut() = RunState::default();
+}
+
+/// Called regularly during a run, so windows that closed are sent even if no
+/// further events arrive
+pub fn flush_expired<T: SpinLogger>(rl: &mut Shell, spinner: &mut T) {
+    flush_digests(rl, spinner, false);
+
+    let due = rl.notify_state_mut().batches.keys()
+        .cloned()
+        .collect::<Vec<_>>();
+    for name in due {
+        let chat = rl.config().notifications.get(&name)
+            .and_then(|config| config.chat())
+            .map(|(platform, chat)| (platform, chat.clone()));
+        let Some((platform, chat)) = chat else { continue };
+        let batches = &mut rl.notify_state_mut().batches;
+        if batches.get(&name).map(|batch| batch.is_due(&chat)).unwrap_or(false) {
+            if let Some(batch) = batches.remove(&name) {
+                send_batch(rl, spinner, &name, platform, &chat, batch);
+            }
+        }
+    }
+}
+
+/// Used outside of runs, digests are only sent once their window closed and
+/// are checked again later
+pub fn flush_due<T: SpinLogger>(rl: &mut Shell, spinner: &mut T) {
+    flush_digests(rl, spinner, false);
+    flush_queued(rl, spinner);
+}
+
+/// Hand a notification to the sink of a route
+fn deliver<T: SpinLogger>(rl: &mut Shell, spinner: &mut T, ratelimit: &mut Ratelimiter, dry_run: bool, name: &str, config: &NotificationConfig, topic: &str, notification: &Notification) -> Result<()> {
+    if let Some(webhook) = &config.webhook {
+        send_webhook(rl, spinner, dry_run, name, webhook, topic, notification);
+    } else if let Some(matrix) = &config.matrix {
+        send_matrix(rl, spinner, dry_run, name, matrix, notification);
+    } else if let Some((platform, chat)) = config.chat() {
+        queue_chat(rl, spinner, dry_run, name, platform, chat, topic, notification);
+    } else if let Some(exec) = &config.exec {
+        run_command(rl, spinner, dry_run, name, exec, topic, notification);
+    } else if let Some(script) = &config.script {
+        let module = rl.library().get(script)?.clone();
+        if dry_run {
+            spinner.success(&format!("Executed {} for {:?} (dry-run)", module.canonical(), name));
+        } else {
+            let options = options::Opt::collect(&config.options);
+            match exec(rl, &module, ratelimit, options, 0, notification) {
+                Ok(0) => {
+                    let msg = format!("Executed {} for {:?}", module.canonical(), name);
+                    spinner.success(&msg);
+                },
+                Ok(errors) => {
+                    let msg = format!("Executed {} for {:?} ({} errors)", module.canonical(), name, errors);
+                    spinner.error(&msg);
+                },
+                Err(err) => {
+                    spinner.error(&format!("Fatal {} for {:?}: {}", module.canonical(), name, err));
+                },
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Collect the event for the summary of the route, the digest is sent right
+/// away if its window closed
+fn queue_digest<T: SpinLogger>(rl: &mut Shell, spinner: &mut T, dry_run: bool, name: &str, window: Window, topic: &str, notification: &Notification) {
+    if dry_run {
+        spinner.success(&format!("Added event to digest of {:?} (dry-run)", name));
+        return;
+    }
+
+    let digests = &mut rl.notify_state_mut().digests;
+    let digest = digests.entry(name.to_string())
+        .or_insert_with(Digest::start);
+    digest.push(Event::new(topic, notification));
+    if digest.is_due(window) {
+        flush_digests(rl, spinner, false);
+    }
+}
+
 pub fn run_router<T: SpinLogger>(rl: &mut Shell, spinner: &mut T, ratelimit: &mut Ratelimiter, dry_run: bool, topic: &str, notification: &Notification) -> Result<()> {
     let configs = rl.config().notifications.clone();
 
@@ -120,26 +515,14 @@ pub fn run_router<T: SpinLogger>(rl: &mut Shell, spinner: &mut T, ratelimit: &mu
             break;
         }
 
-        if config.matches(&name, rl.workspace(), topic) {
-            let module = rl.library().get(&config.script)?.clone();
-            if dry_run {
-                spinner.success(&format!("Executed {} for {:?} (dry-run)", module.canonical(), name));
-            } else {
-                let options = options::Opt::collect(&config.options);
-                match exec(rl, &module, ratelimit, options, 0, notification) {
-                    Ok(0) => {
-                        let msg = format!("Executed {} for {:?}", module.canonical(), name);
-                        spinner.success(&msg);
-                    },
-                    Ok(errors) => {
-                        let msg = format!("Executed {} for {:?} ({} errors)", module.canonical(), name, errors);
-                        spinner.error(&msg);
-                    },
-                    Err(err) => {
-                        spinner.error(&format!("Fatal {} for {:?}: {}", module.canonical(), name, err));
-                    },
-                }
-            }
+        if config.route(&name, rl.workspace(), topic, rl.db(), notification.entity.as_ref()) != Route::Matches {
+            continue;
+        }
+
+        if let Some(window) = config.batch {
+            queue_digest(rl, spinner, dry_run, &name, window, topic, notification);
+        } else {
+            deliver(rl, spinner, ratelimit, dry_run, &name, &config, topic, notification)?;
         }
     }
     debug!("Notification router finished");
@@ -147,6 +530,20 @@ pub fn run_router<T: SpinLogger>(rl: &mut Shell, spinner: &mut T, ratelimit: &mu
     Ok(())
 }
 
+/// Send a notification with one config, even if its rules wouldn't match
+pub fn send_route<T: SpinLogger>(rl: &mut Shell, spinner: &mut T, ratelimit: &mut Ratelimiter, dry_run: bool, name: &str, topic: &str, notification: &Notification) -> Result<()> {
+    let config = rl.config().notifications.get(name)
+        .cloned()
+        .ok_or_else(|| NotFoundError(format!("Notification config {:?} doesn't exist", name)))?;
+
+    if let Some(window) = config.batch {
+        queue_digest(rl, spinner, dry_run, name, window, topic, notification);
+        Ok(())
+    } else {
+        deliver(rl, spinner, ratelimit, dry_run, name, &config, topic, notification)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -159,11 +556,69 @@ mod tests {
         NotificationConfig {
             workspaces: Vec::new(),
             topics,
-            script: "some/script".to_string(),
+            script: Some("some/script".to_string()),
             options: Vec::new(),
+            webhook: None,
+            slack: None,
+            discord: None,
+            matrix: None,
+            exec: None,
+            batch: None,
+            filter: None,
         }
     }
 
+    #[test]
+    fn test_validate_sink() {
+        let mut config = mkconfig(&[]);
+        assert!(config.validate().is_ok());
+        config.webhook = Some(toml::from_str("url = \"https://example.com/hook\"").unwrap());
+        assert!(config.validate().is_err());
+        config.script = None;
+        assert!(config.validate().is_ok());
+        config.webhook = None;
+        assert!(config.validate().is_err());
+        config.discord = Some(toml::from_str("url = \"https://discord.com/api/webhooks/1/x\"").unwrap());
+        assert!(config.validate().is_ok());
+        config.slack = config.discord.clone();
+        assert!(config.validate().is_err());
+        config.slack = None;
+        config.discord = None;
+        config.exec = Some(toml::from_str("command = \"/usr/local/bin/alert\"\ntimeout = 0").unwrap());
+        assert!(config.validate().is_err());
+    }
+
+    fn notification(subject: &str) -> Notification {
+        Notification {
+            subject: subject.to_string(),
+            body: None,
+            entity: None,
+        }
+    }
+
+    #[test]
+    fn test_outbox() {
+        let mut outbox = Outbox::default();
+        assert!(outbox.is_empty());
+        outbox.extend("\"example.com\"", vec![
+            ("db:subdomain:www.example.com:insert".to_string(), notification("a")),
+            ("db:subdomain:mail.example.com:insert".to_string(), notification("b")),
+        ]);
+        outbox.extend("\"example.org\"", vec![
+            ("db:subdomain:www.example.org:insert".to_string(), notification("c")),
+        ]);
+        let queued = outbox.queued.iter()
+            .map(|(source, _, n)| (source.as_str(), n.subject.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(queued, &[
+            ("\"example.com\"", "a"),
+            ("\"example.com\"", "b"),
+            ("\"example.org\"", "c"),
+        ]);
+        assert_eq!(outbox.discard(), 3);
+        assert!(outbox.is_empty());
+    }
+
     #[test]
     fn test_empty_topic() {
         let config = mkconfig(&[]);
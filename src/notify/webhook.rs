@@ -0,0 +1,231 @@
+use crate::config::Config;
+use crate::errors::*;
+use crate::notify::Notification;
+use chrootable_https::{self, HttpClient, Request, Uri};
+use chrootable_https::header::{CONTENT_TYPE, USER_AGENT};
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+
+pub(super) const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[inline]
+pub(super) fn default_retries() -> usize {
+    3
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// How often a failed delivery is attempted again
+    #[serde(default = "default_retries")]
+    pub retries: usize,
+    /// The json body that is sent, placeholders like `{topic}` in strings are
+    /// replaced. Defaults to an object with all fields.
+    pub template: Option<serde_json::Value>,
+}
+
+/// Replace placeholders in every string of the template. A string that is
+/// only a placeholder is replaced with the value itself, so the entity stays
+/// an object.
+fn render(template: &serde_json::Value, vars: &serde_json::Map<String, serde_json::Value>) -> serde_json::Value {
+    match template {
+        serde_json::Value::String(s) => {
+            if let Some(value) = s.strip_prefix('{')
+                .and_then(|s| s.strip_suffix('}'))
+                .and_then(|key| vars.get(key))
+            {
+                return value.clone();
+            }
+
+            let mut out = s.clone();
+            for (key, value) in vars {
+                let value = match value {
+                    serde_json::Value::String(value) => value.clone(),
+                    serde_json::Value::Null => String::new(),
+                    value => value.to_string(),
+                };
+                out = out.replace(&format!("{{{}}}", key), &value);
+            }
+            serde_json::Value::String(out)
+        },
+        serde_json::Value::Array(list) => {
+            serde_json::Value::Array(list.iter()
+                .map(|x| render(x, vars))
+                .collect())
+        },
+        serde_json::Value::Object(map) => {
+            serde_json::Value::Object(map.iter()
+                .map(|(k, v)| (k.clone(), render(v, vars)))
+                .collect())
+        },
+        value => value.clone(),
+    }
+}
+
+pub(super) fn validate_url(url: &str) -> Result<()> {
+    let uri = url.parse::<Uri>()
+        .context(format_err!("Invalid webhook url: {:?}", url))?;
+    match uri.scheme_str() {
+        Some("http") | Some("https") => Ok(()),
+        _ => bail!("Webhook url needs to be http:// or https://: {:?}", url),
+    }
+}
+
+/// The fields of an event that are available to webhooks and commands
+pub(super) fn event(workspace: &str, topic: &str, notification: &Notification, time: &str) -> serde_json::Map<String, serde_json::Value> {
+    let mut vars = serde_json::Map::new();
+    vars.insert("topic".into(), topic.into());
+    vars.insert("workspace".into(), workspace.into());
+    vars.insert("subject".into(), notification.subject.as_str().into());
+    vars.insert("body".into(), notification.body.clone().into());
+    vars.insert("entity".into(), notification.entity.clone().unwrap_or_default());
+    vars.insert("time".into(), time.into());
+    vars
+}
+
+impl WebhookConfig {
+    #[inline]
+    pub fn validate(&self) -> Result<()> {
+        validate_url(&self.url)
+    }
+
+    pub fn body(&self, workspace: &str, topic: &str, notification: &Notification, time: &str) -> serde_json::Value {
+        let vars = event(workspace, topic, notification, time);
+        match &self.template {
+            Some(template) => render(template, &vars),
+            None => serde_json::Value::Object(vars),
+        }
+    }
+
+    pub fn send(&self, config: &Config, body: &serde_json::Value) -> Result<()> {
+        post(config, &self.url, &self.headers, self.retries, body)
+    }
+}
+
+fn post_once(client: &chrootable_https::Client<chrootable_https::Resolver>, url: &str, headers: &HashMap<String, String>, user_agent: &str, body: &str) -> Result<()> {
+    let mut request = Request::post(url.parse::<Uri>()?);
+    request.header(CONTENT_TYPE, "application/json; charset=utf-8");
+    request.header(USER_AGENT, user_agent);
+    for (key, value) in headers {
+        request.header(key.as_str(), value.as_str());
+    }
+    let request = request.body(body.to_string().into())?;
+
+    let resp = client.request(request)
+        .with_timeout(Some(TIMEOUT))
+        .wait_for_response()?;
+    if !(200..300).contains(&resp.status) {
+        bail!("Webhook returned http status {}", resp.status);
+    }
+    Ok(())
+}
+
+/// A client for notifications, this follows the proxy settings
+pub(super) fn client(config: &Config) -> Result<chrootable_https::Client<chrootable_https::Resolver>> {
+    if config.network.offline {
+        bail!("Can't send notifications in offline mode");
+    }
+
+    let client = if let Some(proxy) = config.network.proxy {
+        chrootable_https::Client::with_socks5(proxy)
+    } else {
+        chrootable_https::Client::with_system_resolver_v4()?
+    };
+    Ok(client)
+}
+
+pub(super) fn user_agent(config: &Config) -> String {
+    config.network.user_agent.clone()
+        .unwrap_or_else(|| format!("sn0int/{}", env!("CARGO_PKG_VERSION")))
+}
+
+/// Post json to a url, failed attempts are retried after a short delay
+pub fn post(config: &Config, url: &str, headers: &HashMap<String, String>, retries: usize, body: &serde_json::Value) -> Result<()> {
+    let client = client(config)?;
+    let user_agent = user_agent(config);
+    let body = serde_json::to_string(body)?;
+
+    let mut attempt = 0;
+    loop {
+        match post_once(&client, url, headers, &user_agent, &body) {
+            Ok(()) => return Ok(()),
+            Err(err) if attempt < retries => {
+                attempt += 1;
+                debug!("Webhook to {:?} failed, retrying ({}/{}): {}", url, attempt, retries, err);
+                thread::sleep(Duration::from_secs(attempt as u64));
+            },
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification() -> Notification {
+        Notification {
+            subject: "Added subdomain \"www.example.com\"".to_string(),
+            body: None,
+            entity: Some(serde_json::json!({
+                "id": 1,
+                "value": "www.example.com",
+            })),
+        }
+    }
+
+    fn webhook(toml: &str) -> WebhookConfig {
+        toml::from_str(&format!("url = \"https://example.com/hook\"\n{}", toml)).unwrap()
+    }
+
+    #[test]
+    fn test_default_body() {
+        let webhook = webhook("");
+        assert_eq!(webhook.retries, 3);
+        let body = webhook.body("demo", "db:subdomain:www.example.com:insert", &notification(), "2026-10-14T12:00:00+00:00");
+        assert_eq!(body, serde_json::json!({
+            "topic": "db:subdomain:www.example.com:insert",
+            "workspace": "demo",
+            "subject": "Added subdomain \"www.example.com\"",
+            "body": null,
+            "entity": {
+                "id": 1,
+                "value": "www.example.com",
+            },
+            "time": "2026-10-14T12:00:00+00:00",
+        }));
+    }
+
+    #[test]
+    fn test_template() {
+        let webhook = webhook(r#"
+            retries = 0
+            [template]
+            text = "[{workspace}] {subject}"
+            event = { name = "{topic}", target = "{entity}", tags = ["sn0int", "{workspace}"] }
+        "#);
+        let body = webhook.body("demo", "db:subdomain:www.example.com:insert", &notification(), "now");
+        assert_eq!(body, serde_json::json!({
+            "text": "[demo] Added subdomain \"www.example.com\"",
+            "event": {
+                "name": "db:subdomain:www.example.com:insert",
+                "target": {
+                    "id": 1,
+                    "value": "www.example.com",
+                },
+                "tags": ["sn0int", "demo"],
+            },
+        }));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(webhook("").validate().is_ok());
+        let webhook: WebhookConfig = toml::from_str("url = \"ftp://example.com/\"").unwrap();
+        assert!(webhook.validate().is_err());
+    }
+}
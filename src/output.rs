@@ -0,0 +1,233 @@
+use chrono::Utc;
+use clap::ValueEnum;
+use crate::errors::*;
+use crate::term::{Level, SpinLogger, Stack};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::io::{self, Write};
+
+/// Bumped for every change of the json output that isn't only a new event
+/// or a new field
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    /// Colored output with spinners for the terminal
+    #[default]
+    Human,
+    /// One json object per line on stdout, everything else goes to stderr
+    Json,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum RunEvent {
+    RunStarted {
+        module: String,
+        module_version: String,
+        workspace: String,
+        targets: usize,
+    },
+    SourceStarted,
+    EntityInserted {
+        family: String,
+        value: String,
+        entity: Option<serde_json::Value>,
+    },
+    EntityUpdated {
+        family: String,
+        value: String,
+        changes: String,
+        entity: Option<serde_json::Value>,
+    },
+    Log {
+        level: Level,
+        message: String,
+    },
+    SourceFinished,
+    SourceFailed {
+        error: String,
+    },
+    RunFinished {
+        module: String,
+        targets: usize,
+        errors: usize,
+        offline: usize,
+    },
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    version: u32,
+    time: String,
+    /// The investigation the event belongs to, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<&'a str>,
+    #[serde(flatten)]
+    event: &'a RunEvent,
+}
+
+fn line(time: String, source: Option<&str>, event: &RunEvent) -> String {
+    serde_json::to_string(&Line {
+        version: SCHEMA_VERSION,
+        time,
+        source,
+        event,
+    }).expect("Failed to serialize event")
+}
+
+/// Write an event to stdout
+pub fn emit(source: Option<&str>, event: &RunEvent) {
+    let line = line(Utc::now().to_rfc3339(), source, event);
+    let mut stdout = io::stdout().lock();
+    if let Err(err) = writeln!(stdout, "{}", line).and_then(|_| stdout.flush()) {
+        debug!("Failed to write event to stdout: {}", err);
+    }
+}
+
+/// Writes every event of a run as a line of json
+#[derive(Debug, Default)]
+pub struct JsonLines {
+    warnings: HashSet<String>,
+}
+
+impl JsonLines {
+    #[inline]
+    fn message(&mut self, name: Option<&str>, level: Level, line: &str) {
+        emit(name, &RunEvent::Log {
+            level,
+            message: line.to_string(),
+        });
+    }
+}
+
+impl SpinLogger for JsonLines {
+    #[inline]
+    fn log(&mut self, line: &str) {
+        self.message(None, Level::Info, line)
+    }
+
+    #[inline]
+    fn debug(&mut self, line: &str) {
+        self.message(None, Level::Debug, line)
+    }
+
+    #[inline]
+    fn success(&mut self, line: &str) {
+        self.message(None, Level::Success, line)
+    }
+
+    #[inline]
+    fn error(&mut self, line: &str) {
+        self.message(None, Level::Error, line)
+    }
+
+    #[inline]
+    fn warn(&mut self, line: &str) {
+        self.message(None, Level::Warn, line)
+    }
+
+    fn warn_once(&mut self, line: &str) {
+        if self.warnings.insert(line.to_string()) {
+            self.warn(line);
+        }
+    }
+
+    #[inline]
+    fn status(&mut self, _status: String) {
+    }
+
+    #[inline]
+    fn stacked_status(&mut self, _name: &str, _status: String) {
+    }
+
+    #[inline]
+    fn log_for(&mut self, name: &str, level: Level, line: &str) {
+        self.message(Some(name), level, line)
+    }
+
+    #[inline]
+    fn event(&mut self, name: Option<&str>, event: RunEvent) {
+        emit(name, &event)
+    }
+
+    #[inline]
+    fn structured(&self) -> bool {
+        true
+    }
+}
+
+impl Stack for JsonLines {
+    #[inline]
+    fn add(&mut self, key: String, _status: String) {
+        emit(Some(&key), &RunEvent::SourceStarted)
+    }
+
+    #[inline]
+    fn remove(&mut self, _key: &str) {
+    }
+
+    #[inline]
+    fn jump2start(&mut self) {
+    }
+
+    #[inline]
+    fn tick(&mut self) {
+    }
+
+    #[inline]
+    fn clear(&self) {
+    }
+
+    #[inline]
+    fn source_name(target: &str) -> String {
+        target.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIME: &str = "2026-10-14T12:00:00+00:00";
+
+    #[test]
+    fn test_line() {
+        let event = RunEvent::EntityInserted {
+            family: "subdomain".to_string(),
+            value: "www.example.com".to_string(),
+            entity: Some(serde_json::json!({"id": 1, "value": "www.example.com"})),
+        };
+        assert_eq!(line(TIME.to_string(), Some("example.com"), &event),
+            r#"{"version":1,"time":"2026-10-14T12:00:00+00:00","source":"example.com","event":"entity-inserted","family":"subdomain","value":"www.example.com","entity":{"id":1,"value":"www.example.com"}}"#);
+    }
+
+    #[test]
+    fn test_line_without_fields() {
+        assert_eq!(line(TIME.to_string(), Some("example.com"), &RunEvent::SourceStarted),
+            r#"{"version":1,"time":"2026-10-14T12:00:00+00:00","source":"example.com","event":"source-started"}"#);
+        let event = RunEvent::Log {
+            level: Level::Warn,
+            message: "careful".to_string(),
+        };
+        assert_eq!(line(TIME.to_string(), None, &event),
+            r#"{"version":1,"time":"2026-10-14T12:00:00+00:00","event":"log","level":"warn","message":"careful"}"#);
+    }
+
+    #[test]
+    fn test_line_run_started() {
+        let event = RunEvent::RunStarted {
+            module: "kpcyrd/ctlogs".to_string(),
+            module_version: "0.8.0".to_string(),
+            workspace: "default".to_string(),
+            targets: 1,
+        };
+        let line = line(TIME.to_string(), None, &event);
+        // the version of the schema must not be shadowed by a field of the event
+        assert_eq!(line.matches(r#""version":"#).count(), 1, "{}", line);
+        let json = serde_json::from_str::<serde_json::Value>(&line).unwrap();
+        assert_eq!(json["version"], SCHEMA_VERSION);
+        assert_eq!(json["event"], "run-started");
+        assert_eq!(json["module_version"], "0.8.0");
+    }
+}
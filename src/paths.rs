@@ -13,12 +13,6 @@ pub fn sn0int_dir() -> Result<PathBuf> {
     Ok(path)
 }
 
-pub fn history_path() -> Result<PathBuf> {
-    let path = sn0int_dir()?
-        .join("history");
-    Ok(path)
-}
-
 pub fn module_dir() -> Result<PathBuf> {
     let path = sn0int_dir()?
         .join("modules");
@@ -43,6 +37,20 @@ pub fn workspace_dir(workspace: &Workspace) -> Result<PathBuf> {
     Ok(path)
 }
 
+/// Every workspace has a shell history of its own
+pub fn history_path(workspace: &Workspace) -> Result<PathBuf> {
+    let path = workspace_dir(workspace)?
+        .join("history");
+    Ok(path)
+}
+
+/// The history of older versions, it was shared by every workspace
+pub fn legacy_history_path() -> Result<PathBuf> {
+    let path = sn0int_dir()?
+        .join("history");
+    Ok(path)
+}
+
 pub fn blobs_dir(workspace: &Workspace) -> Result<PathBuf> {
     let path = workspace_dir(workspace)?
         .join("blobs");
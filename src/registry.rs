@@ -1,37 +1,207 @@
 use crate::errors::*;
-use crate::args::{Args, Publish, Install, Search};
+use crate::args::{Args, Publish, Install, Package, Search, Rate};
 use crate::api::Client;
 use crate::auth;
-use crate::config::Config;
+use crate::capabilities::{self, AcceptedCapabilities};
+use crate::changelog::{self, Change};
+use crate::config::{Config, Registry, DEFAULT_REGISTRY};
 use crate::engine::{Library, Module};
-use colored::{Color, Colorize};
+use crate::fmt::colors::{paint, Style};
+use crate::git::{self, GitOrigin, GitOrigins, GitSource};
+use crate::lockfile::{self, LockedModule, Lockfile};
+use crate::manifest::SigningKey;
+use crate::signing::{self, TrustedKeys, Verification};
 use separator::Separatable;
 use std::fmt::Write;
 use sn0int_common::ModuleID;
-use sn0int_common::api::ModuleInfoResponse;
+use serde::Serialize;
+use sn0int_common::api::{DownloadResponse, ModuleInfoResponse, SearchRequest, SearchResponse, SearchSort};
+use sn0int_common::metadata::{self, Metadata, EMITTABLE};
+use std::cmp::Reverse;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use crate::paths;
 use crate::term;
+use crate::utils;
 use crate::worker::{self, Task, EventSender, LogEvent};
 
 
-pub struct Updater {
+/// A downloaded module that can't be installed until the user confirmed its
+/// capabilities
+pub struct PendingInstall {
+    module: ModuleID,
+    metadata: Metadata,
+    code: String,
+    origin: Option<GitOrigin>,
+    verification: Verification,
+    registry: Option<String>,
+    /// What changed compared to the installed version
+    changes: Vec<Change>,
+}
+
+/// An installed module that has a newer release in its registry
+#[derive(Debug)]
+pub struct Outdated {
+    pub canonical: String,
+    pub installed: String,
+    pub latest: String,
+    pub registry: String,
+    pub redirect: Option<ModuleID>,
+    pub changes: Vec<Change>,
+}
+
+impl Outdated {
+    #[inline]
+    pub fn needs_review(&self) -> bool {
+        self.changes.iter().any(|c| c.needs_review)
+    }
+}
+
+/// A configured registry and the client that talks to it
+struct RegistryClient {
+    registry: Registry,
     client: Client,
 }
 
+pub struct Updater {
+    config: Config,
+    registries: Vec<RegistryClient>,
+    accepted: Mutex<AcceptedCapabilities>,
+    pending: Mutex<Vec<PendingInstall>>,
+    origins: Mutex<GitOrigins>,
+    lockfile: Mutex<Lockfile>,
+    trusted: Mutex<TrustedKeys>,
+    /// Install modules even if their signature doesn't match
+    skip_verify: bool,
+    /// Checkouts share a cache folder, only one of them can run at a time
+    git: Mutex<()>,
+}
+
 impl Updater {
     pub fn new(config: &Config) -> Result<Updater> {
-        let client = Client::new(config)?;
+        let registries = config.registries()
+            .into_iter()
+            .map(|registry| {
+                let client = Client::with_server(config, &registry.url)?;
+                Ok(RegistryClient {
+                    registry,
+                    client,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let accepted = AcceptedCapabilities::load()?;
+        let origins = GitOrigins::load()?;
+        let lockfile = Lockfile::load()?;
+        let trusted = TrustedKeys::load()?;
         Ok(Updater {
-            client,
+            config: config.clone(),
+            registries,
+            accepted: Mutex::new(accepted),
+            pending: Mutex::new(Vec::new()),
+            origins: Mutex::new(origins),
+            lockfile: Mutex::new(lockfile),
+            trusted: Mutex::new(trusted),
+            skip_verify: false,
+            git: Mutex::new(()),
         })
     }
 
     #[inline]
-    pub fn query_module(&self, module: &ModuleID) -> Result<ModuleInfoResponse> {
-        self.client.query_module(module)
+    pub fn skip_verify(&mut self, skip: bool) {
+        self.skip_verify = skip;
+    }
+
+    fn registry(&self, name: &str) -> Result<&RegistryClient> {
+        self.registries.iter()
+            .find(|x| x.registry.name == name)
+            .ok_or_else(|| format_err!("Registry {:?} isn't configured", name))
+    }
+
+    /// Ask the registries that may serve the module in order, the first one
+    /// that knows it is used. Only a module that doesn't exist falls through
+    /// to the next registry, any other error is returned so a registry that
+    /// is down can't be shadowed by a later one.
+    fn lookup_module(&self, module: &ModuleID, registry: Option<&str>) -> Result<(&RegistryClient, ModuleInfoResponse)> {
+        if let Some(name) = registry {
+            let registry = self.registry(name)?;
+            let infos = registry.client.query_module(module)?;
+            return Ok((registry, infos));
+        }
+
+        let mut errors = Vec::new();
+        for candidate in self.config.registries_for(&module.author) {
+            let registry = self.registry(&candidate.name)?;
+            match registry.client.query_module(module) {
+                Ok(infos) => return Ok((registry, infos)),
+                Err(err) if err.downcast_ref::<NotFoundError>().is_some() => {
+                    debug!("{} not found in {}: {}", module, candidate.name, err);
+                    errors.push(format!("{}: {}", candidate.name, err));
+                },
+                Err(err) => {
+                    return Err(err.context(format!("Failed to query {} from {}", module, candidate.name)).into());
+                },
+            }
+        }
+        Err(NotFoundError(format!("{} wasn't found in any registry ({})", module, errors.join(", "))).into())
+    }
+
+    /// Query a module, returns the name of the registry that answered
+    pub fn query_module(&self, module: &ModuleID, registry: Option<&str>) -> Result<(String, ModuleInfoResponse)> {
+        let (registry, infos) = self.lookup_module(module, registry)?;
+        Ok((registry.registry.name.clone(), infos))
+    }
+
+    /// The metadata of the version that is currently installed
+    fn installed_metadata(&self, module: &ModuleID) -> Option<Metadata> {
+        let code = fs::read_to_string(self.path(module).ok()?).ok()?;
+        code.parse().ok()
+    }
+
+    /// Check if a newer release of an installed module exists, the new
+    /// release is downloaded to show what changed
+    pub fn outdated(&self, module: &Module) -> Result<Option<Outdated>> {
+        let canonical = module.canonical();
+        let registry = self.locked_registry(&canonical);
+        let (registry, infos) = self.lookup_module(&module.id(), registry.as_deref())?;
+        let latest = infos.latest
+            .ok_or_else(|| format_err!("Module doesn't have any released versions"))?;
+
+        if infos.redirect.is_none() && !is_outdated(module.version(), &latest) {
+            return Ok(None);
+        }
+
+        let changes = if infos.redirect.is_none() {
+            let download = registry.client.download_module(&module.id(), &latest)
+                .context(format_err!("Failed to download {} v{}", canonical, latest))?;
+            let new = download.code.parse::<Metadata>()
+                .map_err(|e| format_err!("Failed to parse module metadata: {}", e))?;
+            let old = module.code().parse::<Metadata>()
+                .map_err(|e| format_err!("Failed to parse module metadata: {}", e))?;
+            changelog::diff(&old, &new)
+        } else {
+            Vec::new()
+        };
+
+        Ok(Some(Outdated {
+            canonical,
+            installed: module.version().to_string(),
+            latest,
+            registry: registry.registry.name.clone(),
+            redirect: infos.redirect,
+            changes,
+        }))
+    }
+
+    pub fn locked(&self, canonical: &str) -> Option<LockedModule> {
+        let lockfile = self.lockfile.lock().unwrap();
+        lockfile.get(canonical).cloned()
+    }
+
+    /// The registry an installed module came from
+    pub fn locked_registry(&self, canonical: &str) -> Option<String> {
+        let lockfile = self.lockfile.lock().unwrap();
+        lockfile.get(canonical)?.registry.clone()
     }
 
     fn path(&self, module: &ModuleID) -> Result<PathBuf> {
@@ -41,30 +211,95 @@ impl Updater {
         Ok(path)
     }
 
-    pub fn install(&self, install: Install) -> Result<String> {
-        if let Some(version) = install.version {
-            let module = self.client.download_module(&install.module, &version)
-                .context("Failed to download module")?;
+    fn write(&self, module: &ModuleID, metadata: &Metadata, code: &str, origin: Option<GitOrigin>, verification: Verification, registry: Option<String>) -> Result<Module> {
+        let path = self.path(module)?;
+
+        // the key is pinned once the release has been accepted
+        if let Verification::Verified(key) = &verification {
+            let name = registry.as_deref().unwrap_or(DEFAULT_REGISTRY);
+            let mut trusted = self.trusted.lock().unwrap();
+            if trusted.pin(name, &module.author, key)? {
+                trusted.save()?;
+            }
+        }
+
+        fs::create_dir_all(path.parent().unwrap())
+            .context("Failed to create folder")?;
+
+        fs::write(&path, code)
+            .context(format_err!("Failed to write to {:?}", path))?;
+
+        let mut accepted = self.accepted.lock().unwrap();
+        accepted.accept(&module.to_string(), metadata);
+        accepted.save()?;
 
-            let path = self.path(&install.module)?;
+        let mut lockfile = self.lockfile.lock().unwrap();
+        lockfile.installed(module.to_string(), metadata.version.clone(), code, origin.clone(), verification, registry);
+        lockfile.save()?;
 
-            fs::create_dir_all(path.parent().unwrap())
-                .context("Failed to create folder")?;
+        let mut origins = self.origins.lock().unwrap();
+        match origin {
+            Some(origin) => {
+                origins.insert(module.to_string(), origin);
+                origins.save()?;
+            },
+            None => if origins.get(&module.to_string()).is_some() {
+                origins.remove(&module.to_string());
+                origins.save()?;
+            },
+        }
+
+        Module::load(&path, &module.author, &module.name, false)
+    }
+
+    /// Check the signature of a download before anything is written to disk
+    fn verify(&self, registry: &str, module: &ModuleID, version: &str, download: &DownloadResponse) -> Result<Verification> {
+        let trusted = self.trusted.lock().unwrap();
+        match trusted.check(registry, module, version, &download.code, download.signature.as_ref()) {
+            Ok(verification) => Ok(verification),
+            Err(err) if self.skip_verify => {
+                term::warn(&format!("Installing {} v{} anyway: {}", module, version, err));
+                Ok(Verification::Skipped)
+            },
+            Err(err) => bail!("{}, use --insecure-skip-verify to install it anyway", err),
+        }
+    }
 
-            fs::write(&path, module.code)
-                .context(format_err!("Failed to write to {:?}", path))?;
+    /// Install a module from the registry, returns `None` if it has been
+    /// queued for review because it uses capabilities that haven't been
+    /// accepted yet
+    pub fn install(&self, install: Install) -> Result<Option<Module>> {
+        let module = match install.module {
+            Some(Package::Registry(module)) => module,
+            Some(Package::Git(source)) => bail!("{} is not in the registry", source),
+            None => bail!("No module to install"),
+        };
+        ensure_registry_author(&module)?;
 
-            Ok(version)
+        if let Some(version) = install.version {
+            let registry = match &install.registry {
+                Some(name) => self.registry(name)?,
+                None => self.lookup_module(&module, None)
+                    .context("Failed to query module infos")?.0,
+            };
+            let download = registry.client.download_module(&module, &version)
+                .context("Failed to download module")?;
+            let verification = self.verify(&registry.registry.name, &module, &version, &download)?;
+            self.install_code(module, download.code, None, verification, Some(registry.registry.name.clone()), install.yes)
         } else {
-            let infos = self.query_module(&install.module)
+            let (registry, infos) = self.lookup_module(&module, install.registry.as_deref())
                         .context("Failed to query module infos")?;
 
             if !install.force {
                 if let Some(redirect) = infos.redirect {
                     return self.install(Install {
-                        module: redirect,
+                        module: Some(Package::Registry(redirect)),
                         version: None,
+                        locked: None,
                         force: install.force,
+                        yes: install.yes,
+                        insecure_skip_verify: install.insecure_skip_verify,
+                        registry: install.registry,
                     });
                 }
             }
@@ -73,13 +308,235 @@ impl Updater {
                         .latest
                         .ok_or_else(|| format_err!("Module doesn't have a latest version"))?;
             self.install(Install {
-                module: install.module,
+                module: Some(Package::Registry(module)),
                 version: Some(latest),
+                locked: None,
                 force: install.force,
+                yes: install.yes,
+                insecure_skip_verify: install.insecure_skip_verify,
+                registry: Some(registry.registry.name.clone()),
             })
         }
     }
 
+    /// Install the modules of a git repository, returns the modules that
+    /// didn't need to be queued for review
+    pub fn install_git(&self, source: &GitSource, yes: bool) -> Result<Vec<Module>> {
+        let author = source.author()?;
+
+        let (modules, commit) = {
+            let _lock = self.git.lock().unwrap();
+            let (dir, commit) = source.checkout()?;
+            (source.modules(&dir)?, commit)
+        };
+
+        let mut installed = Vec::new();
+        for module in modules {
+            let id = ModuleID {
+                author: author.clone(),
+                name: module.name,
+            };
+            let origin = GitOrigin {
+                url: source.url.clone(),
+                reference: source.reference.clone(),
+                file: module.file,
+                commit: commit.clone(),
+            };
+            if let Some(module) = self.install_code(id, module.code, Some(origin), Verification::Unsigned, None, yes)? {
+                installed.push(module);
+            }
+        }
+
+        Ok(installed)
+    }
+
+    /// Fetch the repository of a module that has been installed from git,
+    /// returns `None` if the module didn't change
+    pub fn fetch_git(&self, module: &Module, origin: &GitOrigin) -> Result<Option<(String, GitOrigin)>> {
+        let source = origin.source();
+        let _lock = self.git.lock().unwrap();
+        let (dir, commit) = source.checkout()?;
+        if commit == origin.commit {
+            return Ok(None);
+        }
+
+        let new = source.module(&dir, &origin.file)?;
+        let origin = GitOrigin {
+            commit,
+            ..origin.clone()
+        };
+
+        if new.code == module.code() {
+            let mut origins = self.origins.lock().unwrap();
+            origins.insert(module.canonical(), origin);
+            origins.save()?;
+            Ok(None)
+        } else {
+            Ok(Some((new.code, origin)))
+        }
+    }
+
+    /// Validate the metadata and write the module, or queue it if the
+    /// capabilities need to be reviewed
+    pub fn install_code(&self, module: ModuleID, code: String, origin: Option<GitOrigin>, verification: Verification, registry: Option<String>, yes: bool) -> Result<Option<Module>> {
+        let metadata = code.parse::<Metadata>()
+            .map_err(|e| format_err!("Failed to parse module metadata: {}", e))?;
+
+        let changes = self.installed_metadata(&module)
+            .map(|old| changelog::diff(&old, &metadata))
+            .unwrap_or_default();
+
+        let needs_review = {
+            let accepted = self.accepted.lock().unwrap();
+            accepted.needs_review(&module.to_string(), &metadata)
+        } || changes.iter().any(|c| c.needs_review);
+
+        if yes || !needs_review {
+            self.write(&module, &metadata, &code, origin, verification, registry)
+                .map(Some)
+        } else {
+            let mut pending = self.pending.lock().unwrap();
+            pending.push(PendingInstall {
+                module,
+                metadata,
+                code,
+                origin,
+                verification,
+                registry,
+                changes,
+            });
+            Ok(None)
+        }
+    }
+
+    /// Install the exact versions of a lockfile, the downloaded code must match
+    /// the recorded hash
+    pub fn install_locked(&self, locked: &Lockfile) -> Result<Vec<Module>> {
+        let mut installed = Vec::new();
+
+        for (canonical, entry) in locked.iter() {
+            let module = canonical.parse::<ModuleID>()
+                .map_err(|e| format_err!("Invalid module in lockfile: {}", e))?;
+
+            let (code, origin, download, registry) = if let Some(origin) = &entry.git {
+                let source = GitSource {
+                    url: origin.url.clone(),
+                    reference: Some(origin.commit.clone()),
+                    path: None,
+                };
+                let _lock = self.git.lock().unwrap();
+                let (dir, _) = source.checkout()?;
+                let code = source.module(&dir, &origin.file)?.code;
+                (code, Some(origin.clone()), None, None)
+            } else {
+                ensure_registry_author(&module)?;
+                let registry = match &entry.registry {
+                    Some(name) => self.registry(name)?,
+                    None => self.lookup_module(&module, None)?.0,
+                };
+                let download = registry.client.download_module(&module, &entry.version)
+                    .context(format_err!("Failed to download {} v{} from {}", canonical, entry.version, registry.registry.name))?;
+                (download.code.clone(), None, Some(download), Some(registry.registry.name.clone()))
+            };
+
+            let sha256 = lockfile::hash(&code);
+            if sha256 != entry.sha256 {
+                bail!("Checksum mismatch for {}: expected {}, got {}", canonical, entry.sha256, sha256);
+            }
+
+            let verification = match (&download, &registry) {
+                (Some(download), Some(registry)) => self.verify(registry, &module, &entry.version, download)?,
+                _ => Verification::Unsigned,
+            };
+
+            if let Some(module) = self.install_code(module, code, origin, verification, registry, false)? {
+                installed.push(module);
+            }
+        }
+
+        Ok(installed)
+    }
+
+    pub fn is_from_git(&self, canonical: &str) -> bool {
+        let origins = self.origins.lock().unwrap();
+        origins.get(canonical).is_some()
+    }
+
+    #[inline]
+    pub fn is_pinned(&self, canonical: &str) -> bool {
+        let lockfile = self.lockfile.lock().unwrap();
+        lockfile.is_pinned(canonical)
+    }
+
+    /// Pin or unpin an installed module
+    pub fn pin(&self, module: &Module, pinned: bool) -> Result<()> {
+        let canonical = module.canonical();
+        let mut lockfile = self.lockfile.lock().unwrap();
+        if !lockfile.set_pinned(&canonical, pinned) {
+            let origin = {
+                let origins = self.origins.lock().unwrap();
+                origins.get(&canonical).cloned()
+            };
+            lockfile.installed(canonical.clone(), module.version().to_string(), module.code(), origin, Verification::Unsigned, None);
+            lockfile.set_pinned(&canonical, pinned);
+        }
+        lockfile.save()
+    }
+
+    /// Set the pins of modules that are pinned in a lockfile
+    pub fn copy_pins(&self, locked: &Lockfile) -> Result<()> {
+        let mut lockfile = self.lockfile.lock().unwrap();
+        for (canonical, entry) in locked.iter() {
+            if entry.pinned {
+                lockfile.set_pinned(canonical, true);
+            }
+        }
+        lockfile.save()
+    }
+
+    /// Record installed modules that are missing from the lockfile
+    pub fn sync_lockfile(&self, library: &Library) -> Result<()> {
+        let origins = self.origins.lock().unwrap();
+        let mut lockfile = self.lockfile.lock().unwrap();
+        if lockfile.sync(library, &origins) {
+            lockfile.save()?;
+        }
+        Ok(())
+    }
+
+    /// Ask the user to confirm the capabilities of every queued module
+    pub fn review_pending(&self) -> Result<Vec<Module>> {
+        let pending = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.drain(..).collect::<Vec<_>>()
+        };
+
+        let mut installed = Vec::new();
+        for install in pending {
+            let canonical = install.module.to_string();
+            for change in install.changes.iter().filter(|c| c.needs_review) {
+                term::warn(&format!("{} v{} changed {}", canonical, install.metadata.version, change));
+            }
+            if self.accepted.lock().unwrap().is_legacy(&canonical) {
+                term::warn(&format!("{} was installed before capabilities existed and is going to be limited to what it requests", canonical));
+            }
+            let declared = capabilities::format_list(install.metadata.capabilities.as_deref());
+            term::info(&format!("{} v{} requests these capabilities: {}",
+                                canonical,
+                                install.metadata.version,
+                                declared));
+
+            if utils::no_else_yes(&format!("Install {}?", canonical))? {
+                let module = self.write(&install.module, &install.metadata, &install.code, install.origin, install.verification, install.registry)?;
+                installed.push(module);
+            } else {
+                term::warn(&format!("Skipped {}", canonical));
+            }
+        }
+
+        Ok(installed)
+    }
+
     pub fn uninstall(&self, module: &ModuleID) -> Result<()> {
         let path = self.path(module)?;
         fs::remove_file(&path)?;
@@ -89,17 +546,36 @@ impl Updater {
             fs::remove_dir(parent).ok();
         }
 
+        let mut accepted = self.accepted.lock().unwrap();
+        accepted.remove(&module.to_string());
+        accepted.save()?;
+
+        let mut origins = self.origins.lock().unwrap();
+        if origins.get(&module.to_string()).is_some() {
+            origins.remove(&module.to_string());
+            origins.save()?;
+        }
+
+        let mut lockfile = self.lockfile.lock().unwrap();
+        if lockfile.remove(&module.to_string()) {
+            lockfile.save()?;
+        }
+
         Ok(())
     }
 }
 
 pub fn run_publish(_args: &Args, publish: &Publish, config: &Config) -> Result<()> {
-    let session = auth::load_token()
-        .context("Failed to load auth token, login first")?;
+    let registry = config.registry(publish.registry.as_deref())?;
+    let session = auth::load_session(&registry)?;
 
-    let mut client = Client::new(config)?;
+    let mut client = Client::with_server(config, &registry.url)?;
     client.authenticate(session);
 
+    let author = client.verify_session()?;
+    let key = SigningKey::load_or_create()?;
+    term::info(&format!("Signing modules with {}", key.public_key()));
+
     for path in &publish.paths {
         let path = Path::new(path);
         let name = path.file_stem().ok_or_else(|| format_err!("Couldn't get file name"))?;
@@ -113,15 +589,20 @@ pub fn run_publish(_args: &Args, publish: &Publish, config: &Config) -> Result<(
             .map_err(|_| format_err!("Failed to decode file name"))?;
 
         let module = Module::load(path, "anonymous", &name, false)?;
+        let signature = signing::sign(&key, &ModuleID {
+            author: author.clone(),
+            name: name.clone(),
+        }, module.version(), module.code());
 
         let label = format!("Uploading {} {} ({:?})", name, module.version(), path);
         match worker::spawn_fn(&label, || {
-            client.publish_module(&name, module.code().to_string())
+            client.publish_module(&name, module.code().to_string(), signature)
         }, true) {
-            Ok(result) => term::info(&format!("Published {}/{} {} ({:?})",
+            Ok(result) => term::info(&format!("Published {}/{} {} to {} ({:?})",
                                               result.author,
                                               result.name,
                                               result.version,
+                                              registry.name,
                                               path)),
             Err(err) => term::error(&format!("Failed to publish {} {} ({:?}): {}",
                                              name,
@@ -156,12 +637,16 @@ impl Task for InstallTask {
 
     #[inline]
     fn name(&self) -> String {
-        self.install.module.to_string()
+        self.install.module.as_ref()
+            .map(Package::to_string)
+            .unwrap_or_default()
     }
 
     fn run(self, tx: &EventSender) -> Result<()> {
-        let version = self.client.install(self.install)?;
-        let label = format!("installed v{}", version);
+        let label = match self.client.install(self.install)? {
+            Some(module) => format!("installed v{} (capabilities: {})", module.version(), module.capabilities()),
+            None => String::from("waiting for review of capabilities"),
+        };
         tx.log(LogEvent::Success(label));
         Ok(())
     }
@@ -169,25 +654,67 @@ impl Task for InstallTask {
 
 
 pub fn run_install(arg: Install, config: &Config) -> Result<()> {
-    let label = format!("Installing {}", arg.module);
-    worker::spawn_fn(&label, || {
-        let client = Updater::new(config)?;
-        client.install(arg)?;
-        Ok(())
-    }, false)
+    let mut client = Updater::new(config)?;
+    client.skip_verify(arg.insecure_skip_verify);
+
+    let modules = if let Some(path) = &arg.locked {
+        let locked = Lockfile::load_from(path)?;
+        let label = format!("Installing modules from {:?}", path);
+        let mut modules = worker::spawn_fn(&label, || {
+            client.install_locked(&locked)
+        }, false)?;
+        modules.extend(client.review_pending()?);
+        client.copy_pins(&locked)?;
+        modules
+    } else {
+        let package = arg.module.clone()
+            .ok_or_else(|| format_err!("No module to install"))?;
+        let label = format!("Installing {}", package);
+        let mut modules = worker::spawn_fn(&label, || {
+            match &package {
+                Package::Registry(_) => client.install(arg)
+                    .map(|module| module.into_iter().collect()),
+                Package::Git(source) => client.install_git(source, arg.yes),
+            }
+        }, false)?;
+        modules.extend(client.review_pending()?);
+        modules
+    };
+
+    for module in modules {
+        term::info(&format!("Capabilities of {}: {}", module.canonical(), module.capabilities()));
+    }
+    Ok(())
 }
 
 pub struct UpdateTask {
     module: Module,
     client: Arc<Updater>,
+    origin: Option<GitOrigin>,
 }
 
 impl UpdateTask {
-    pub fn new(module: Module, client: Arc<Updater>) -> UpdateTask {
+    pub fn new(module: Module, client: Arc<Updater>, origin: Option<GitOrigin>) -> UpdateTask {
         UpdateTask {
             module,
             client,
+            origin,
+        }
+    }
+
+    fn run_git(&self, tx: &EventSender, origin: &GitOrigin) -> Result<()> {
+        if let Some((code, new)) = self.client.fetch_git(&self.module, origin)? {
+            let label = format!("Updating {}: {} -> {}", self.name(), origin.short_commit(), new.short_commit());
+            tx.log(LogEvent::Status(label));
+
+            let label = format!("{} -> {}", origin.short_commit(), new.short_commit());
+            let label = match self.client.install_code(self.module.id(), code, Some(new), Verification::Unsigned, None, false)? {
+                Some(_) => format!("updated {}", label),
+                None => format!("{} is waiting for review of capabilities", label),
+            };
+            tx.log(LogEvent::Success(label));
         }
+        Ok(())
     }
 }
 
@@ -203,9 +730,14 @@ impl Task for UpdateTask {
     }
 
     fn run(self, tx: &EventSender) -> Result<()> {
+        if let Some(origin) = &self.origin {
+            return self.run_git(tx, origin);
+        }
+
         let installed = self.module.version();
 
-        let infos = self.client.query_module(&self.module.id())?;
+        let registry = self.client.locked_registry(&self.name());
+        let (registry, infos) = self.client.query_module(&self.module.id(), registry.as_deref())?;
         debug!("Latest version: {:?}", infos);
         let latest = infos.latest.ok_or_else(|| format_err!("Module doesn't have any released versions"))?;
 
@@ -213,26 +745,38 @@ impl Task for UpdateTask {
             let label = format!("Replacing {}: {}", self.name(), redirect);
             tx.log(LogEvent::Status(label));
 
-            self.client.install(Install {
-                module: self.module.id(),
+            let label = match self.client.install(Install {
+                module: Some(Package::Registry(self.module.id())),
                 version: None,
+                locked: None,
                 force: false,
-            })?;
-            self.client.uninstall(&self.module.id())?;
-
-            let label = format!("replaced with {}", redirect);
+                yes: false,
+                insecure_skip_verify: false,
+                registry: Some(registry.clone()),
+            })? {
+                Some(_) => {
+                    self.client.uninstall(&self.module.id())?;
+                    format!("replaced with {}", redirect)
+                },
+                None => format!("replacement {} is waiting for review of capabilities", redirect),
+            };
             tx.log(LogEvent::Success(label));
         } else if installed != latest {
             let label = format!("Updating {}: v{} -> v{}", self.name(), installed, latest);
             tx.log(LogEvent::Status(label));
 
-            self.client.install(Install {
-                module: self.module.id(),
+            let label = match self.client.install(Install {
+                module: Some(Package::Registry(self.module.id())),
                 version: Some(latest.clone()),
+                locked: None,
                 force: false,
-            })?;
-
-            let label = format!("updated v{} -> v{}", installed, latest);
+                yes: false,
+                insecure_skip_verify: false,
+                registry: Some(registry),
+            })? {
+                Some(_) => format!("updated v{} -> v{}", installed, latest),
+                None => format!("v{} is waiting for review of capabilities", latest),
+            };
             tx.log(LogEvent::Success(label));
         }
 
@@ -240,19 +784,167 @@ impl Task for UpdateTask {
     }
 }
 
+/// The git namespace is reserved for modules installed from a repository
+fn ensure_registry_author(module: &ModuleID) -> Result<()> {
+    if module.author.starts_with(git::GIT_NAMESPACE) {
+        bail!("{} can only be installed from git, authors starting with {:?} are reserved", module, git::GIT_NAMESPACE);
+    }
+    Ok(())
+}
+
 #[inline]
-fn write_tag(out: &mut String, color: Color, txt: &str) -> Result<()> {
-    write!(out, " [{}]", txt.color(color))?;
+fn write_tag(out: &mut String, style: Style, txt: &str) -> Result<()> {
+    write!(out, " [{}]", paint(style, txt))?;
+    Ok(())
+}
+
+fn format_rating(rating: Option<f32>, ratings: i64) -> Option<String> {
+    let rating = rating?;
+    let label = if ratings == 1 { "rating" } else { "ratings" };
+    Some(format!("{:.1}/5 ({} {})", rating, ratings.separated_string(), label))
+}
+
+/// A search result and the version that is installed locally
+#[derive(Debug, Serialize)]
+struct SearchMatch<'a> {
+    #[serde(flatten)]
+    module: &'a SearchResponse,
+    installed: Option<String>,
+    outdated: bool,
+}
+
+impl<'a> SearchMatch<'a> {
+    fn new(module: &'a SearchResponse, installed: Option<&Module>) -> SearchMatch<'a> {
+        let installed = installed.map(|x| x.version().to_string());
+        let outdated = installed.as_ref()
+            .map(|version| is_outdated(version, &module.latest))
+            .unwrap_or(false);
+        SearchMatch {
+            module,
+            installed,
+            outdated,
+        }
+    }
+}
+
+/// Compare as semver if possible, any other difference counts as outdated
+fn is_outdated(installed: &str, latest: &str) -> bool {
+    match (semver::Version::parse(installed), semver::Version::parse(latest)) {
+        (Ok(installed), Ok(latest)) => installed < latest,
+        _ => installed != latest,
+    }
+}
+
+/// Apply the filters and sorting on our side as well, older registries ignore
+/// them. Results without a source are kept since we can't tell.
+fn filter_search_results(mut modules: Vec<SearchResponse>, search: &SearchRequest) -> Vec<SearchResponse> {
+    modules.retain(|module| {
+        if let Some(author) = &search.author {
+            if module.author != *author {
+                return false;
+            }
+        }
+        if let (Some(source), Some(module)) = (&search.source, &module.source) {
+            if source != module {
+                return false;
+            }
+        }
+        true
+    });
+
+    match search.sort {
+        Some(SearchSort::Downloads) => modules.sort_by_key(|x| Reverse(x.downloads)),
+        Some(SearchSort::Recent) => modules.sort_by_key(|x| Reverse(x.published)),
+        None => (),
+    }
+
+    modules
+}
+
+fn print_search_result(result: &SearchMatch) -> Result<()> {
+    let module = result.module;
+    let mut out = format!("{} {} - {} downloads",
+        paint(Style::Bold, format!("{}/{}", module.author, module.name)),
+        paint(Style::Blue, &module.latest),
+        module.downloads.separated_string(),
+    );
+
+    if let Some(rating) = format_rating(module.rating, module.ratings) {
+        write!(out, " - {}", paint(Style::Yellow, rating))?;
+    }
+
+    if module.featured {
+        write_tag(&mut out, Style::Blue, "featured")?;
+    }
+
+    match &result.installed {
+        Some(version) if result.outdated => {
+            write_tag(&mut out, Style::Yellow, &format!("installed {}, update available", version))?;
+        },
+        Some(version) if *version != module.latest => {
+            write_tag(&mut out, Style::Green, &format!("installed {}", version))?;
+        },
+        Some(_) => write_tag(&mut out, Style::Green, "installed")?,
+        None => (),
+    }
+
+    println!("{}", out);
+    println!("    {}", module.description);
+    if let Some(source) = &module.source {
+        println!("    source: {}", source);
+    }
+    if !module.emits.is_empty() {
+        println!("    emits: {}", module.emits.join(", "));
+    }
+
     Ok(())
 }
 
 pub fn run_search(library: &Library, search: &Search, config: &Config) -> Result<()> {
-    let client = Client::new(config)?;
+    if let Some(emits) = &search.emits {
+        if !EMITTABLE.contains(&emits.as_str()) {
+            bail!("Unknown entity type {:?}, expected one of: {}", emits, EMITTABLE.join(", "));
+        }
+    }
 
-    let label = format!("Searching {:?}", search.query);
+    let source = if let Some(source) = &search.source {
+        let group = metadata::source_group(source)
+            .ok_or_else(|| format_err!("Unknown source {:?}, expected one of: {}", source, metadata::SOURCE_GROUPS.join(", ")))?;
+        Some(group.to_string())
+    } else {
+        None
+    };
+
+    let request = SearchRequest {
+        q: search.query.clone().unwrap_or_default(),
+        emits: search.emits.clone(),
+        source,
+        author: search.author.clone(),
+        sort: search.sort,
+    };
+
+    let registry = config.registry(search.registry.as_deref())?;
+    let client = Client::with_server(config, &registry.url)?;
+
+    let mut filters = Vec::new();
+    if let Some(emits) = &request.emits {
+        filters.push(format!("emits {}", emits));
+    }
+    if let Some(source) = &request.source {
+        filters.push(format!("source {}", source));
+    }
+    if let Some(author) = &request.author {
+        filters.push(format!("by {}", author));
+    }
+    let label = if filters.is_empty() {
+        format!("Searching {:?}", request.q)
+    } else {
+        format!("Searching {:?} ({})", request.q, filters.join(", "))
+    };
     let modules = worker::spawn_fn(&label, || {
-        client.search(&search.query)
+        client.search(&request)
     }, true)?;
+    let modules = filter_search_results(modules, &request);
 
     for module in &modules {
         let canonical = module.canonical();
@@ -262,24 +954,107 @@ pub fn run_search(library: &Library, search: &Search, config: &Config) -> Result
             continue;
         }
 
-        let mut out = format!("{}/{} {} - {} downloads",
-            module.author.purple(),
-            module.name,
-            module.latest.blue(),
-            module.downloads.separated_string(),
-        );
-
-        if module.featured {
-            write_tag(&mut out, Color::Cyan, "featured")?;
+        let result = SearchMatch::new(module, installed);
+        if search.json {
+            println!("{}", serde_json::to_string(&result)?);
+        } else {
+            print_search_result(&result)?;
         }
+    }
+
+    Ok(())
+}
+
+pub fn run_rate(rate: &Rate, config: &Config) -> Result<()> {
+    let session = auth::load_session(&config.registry(None)?)?;
+
+    let mut client = Client::new(config)?;
+    client.authenticate(session);
+
+    let label = format!("Rating {} with {} stars", rate.module, rate.stars);
+    let result = worker::spawn_fn(&label, || {
+        client.rate_module(&rate.module, rate.stars)
+    }, true)?;
+
+    let summary = format_rating(result.rating, result.ratings)
+        .unwrap_or_else(|| String::from("no ratings"));
+    term::info(&format!("Rated {}/{}, now at {}", result.author, result.name, summary));
 
-        if installed.is_some() {
-            write_tag(&mut out, Color::Green, "installed")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_rating() {
+        assert_eq!(format_rating(None, 0), None);
+        assert_eq!(format_rating(Some(4.0), 1), Some("4.0/5 (1 rating)".to_string()));
+        assert_eq!(format_rating(Some(3.6666), 1234), Some("3.7/5 (1,234 ratings)".to_string()));
+    }
+
+    #[test]
+    fn test_ensure_registry_author() {
+        assert!(ensure_registry_author(&"kpcyrd/foo".parse().unwrap()).is_ok());
+        assert!(ensure_registry_author(&"github-com-kpcyrd/foo".parse().unwrap()).is_ok());
+        assert!(ensure_registry_author(&"git-github-com-kpcyrd/foo".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_is_outdated() {
+        assert!(is_outdated("0.9.0", "0.10.0"));
+        assert!(!is_outdated("0.10.0", "0.10.0"));
+        assert!(!is_outdated("0.11.0", "0.10.0"));
+        assert!(is_outdated("foo", "0.1.0"));
+    }
+
+    fn result(author: &str, downloads: i64, source: Option<&str>, published: Option<u64>) -> SearchResponse {
+        SearchResponse {
+            author: author.to_string(),
+            name: format!("m{}", downloads),
+            description: String::new(),
+            latest: "0.1.0".to_string(),
+            downloads,
+            featured: false,
+            rating: None,
+            ratings: 0,
+            emits: Vec::new(),
+            source: source.map(String::from),
+            published,
         }
+    }
 
-        println!("{}", out.bold());
-        println!("    {}", module.description);
+    #[test]
+    fn test_filter_search_results() {
+        let modules = vec![
+            result("kpcyrd", 10, Some("domains"), Some(300)),
+            result("kpcyrd", 30, Some("subdomains"), Some(100)),
+            result("kpcyrd", 20, None, None),
+            result("other", 40, Some("subdomains"), Some(200)),
+        ];
+        let modules = filter_search_results(modules, &SearchRequest {
+            source: Some("subdomains".to_string()),
+            author: Some("kpcyrd".to_string()),
+            sort: Some(SearchSort::Downloads),
+            ..Default::default()
+        });
+        let names = modules.iter().map(|x| x.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, &["m30", "m20"]);
     }
 
-    Ok(())
+    #[test]
+    fn test_sort_search_results_recent() {
+        let modules = vec![
+            result("a", 1, None, None),
+            result("a", 2, None, Some(100)),
+            result("a", 3, None, Some(300)),
+        ];
+        let modules = filter_search_results(modules, &SearchRequest {
+            sort: Some(SearchSort::Recent),
+            ..Default::default()
+        });
+        let names = modules.iter().map(|x| x.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, &["m3", "m2", "m1"]);
+    }
 }
@@ -2,6 +2,7 @@ use crate::config::Config;
 use crate::errors::*;
 use crate::engine::{ctx, Environment};
 use crate::ipc::child::DummyIpcChild;
+use crate::ipc::network::{Broker, NetworkPolicy};
 use crate::engine::ctx::{State, LuaState};
 use crate::geoip::{Maxmind, AsnDB, GeoIP};
 use crate::hlua::{Lua, AnyLuaValue};
@@ -92,17 +93,26 @@ pub fn run(config: &Config) -> Result<()> {
     let env = Environment {
         verbose: 0, // this doesn't do anything since we use a dummy ipc child
         keyring,
-        dns_config,
-        proxy,
         user_agent,
         options: HashMap::new(),
         blobs: Vec::new(),
+        memory_limit: None,
+        timeout: None,
+        capabilities: None,
+        audit: false,
+        concurrency: false,
+        module: None,
         psl,
         geoip,
         asn,
     };
 
-    let tx = DummyIpcChild::create();
+    let policy = NetworkPolicy {
+        offline: config.network.offline,
+        proxy,
+        ..Default::default()
+    };
+    let tx = DummyIpcChild::create(Broker::new(policy, dns_config));
     let (lua, state) = ctx::ctx(env, tx);
     let mut repl = Repl::new(lua, state)?;
 
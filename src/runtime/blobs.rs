@@ -10,6 +10,8 @@ pub fn create_blob<S>(lua: &mut hlua::Lua, state: Arc<S>)
     where S: State + BlobState + 'static
 {
     lua.set("create_blob", hlua::function1(move |bytes: AnyLuaValue| -> Result<String> {
+        state.check_blob()
+            .map_err(|err| state.set_error(err))?;
         let bytes = byte_array(bytes)
             .map_err(|err| state.set_error(err))?;
 
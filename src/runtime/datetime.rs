@@ -2,7 +2,9 @@ use crate::errors::*;
 use crate::engine::ctx::State;
 use crate::hlua;
 use chrono::{NaiveDateTime, Utc};
+use lazy_static::lazy_static;
 use std::sync::Arc;
+use std::time::Instant;
 
 
 const SN0INT_DATETIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
@@ -57,6 +59,16 @@ pub fn time_unix(lua: &mut hlua::Lua, _: Arc<dyn State>) {
     }))
 }
 
+lazy_static! {
+    static ref MONOTONIC_START: Instant = Instant::now();
+}
+
+pub fn time_monotonic(lua: &mut hlua::Lua, _: Arc<dyn State>) {
+    lua.set("time_monotonic", hlua::function0(move || -> f64 {
+        MONOTONIC_START.elapsed().as_secs_f64()
+    }))
+}
+
 #[cfg(test)]
 mod tests {
     use crate::engine::ctx::Script;
@@ -145,4 +157,18 @@ mod tests {
         "#).expect("Failed to load script");
         script.test().expect("Script failed");
     }
+
+    #[test]
+    fn verify_time_monotonic() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            a = time_monotonic()
+            b = time_monotonic()
+            if b < a then
+                return 'time went backwards'
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
 }
@@ -1,6 +1,6 @@
 use crate::errors::*;
 
-use crate::db::Family;
+use crate::db::{Condition, Family, Query};
 use crate::engine::ctx::State;
 use crate::engine::structs;
 use crate::hlua::{self, AnyLuaValue};
@@ -89,11 +89,12 @@ pub fn db_add(lua: &mut hlua::Lua, state: Arc<dyn State>) {
             .map_err(|e| state.set_error(e))?;
 
         let r = match r {
-            // Found is technically unreachable
+            // Found and Rows are technically unreachable
             DatabaseResponse::Inserted(id) => Some(id),
             DatabaseResponse::Updated(id) => Some(id),
             DatabaseResponse::NoChange(id) => Some(id),
             DatabaseResponse::Found(id) => Some(id),
+            DatabaseResponse::Rows(_) => None,
             DatabaseResponse::None => None,
         };
         Ok(r)
@@ -113,11 +114,12 @@ pub fn db_add_ttl(lua: &mut hlua::Lua, state: Arc<dyn State>) {
             .map_err(|e| state.set_error(e))?;
 
         let r = match r {
-            // Found is technically unreachable
+            // Found and Rows are technically unreachable
             DatabaseResponse::Inserted(id) => Some(id),
             DatabaseResponse::Updated(id) => Some(id),
             DatabaseResponse::NoChange(id) => Some(id),
             DatabaseResponse::Found(id) => Some(id),
+            DatabaseResponse::Rows(_) => None,
             DatabaseResponse::None => None,
         };
         Ok(r)
@@ -136,11 +138,85 @@ pub fn db_activity(lua: &mut hlua::Lua, state: Arc<dyn State>) {
     }))
 }
 
+/// Upper limit for rows returned by a single db_select call
+const SELECT_MAX_LIMIT: usize = 1000;
+const SELECT_DEFAULT_LIMIT: usize = 100;
+
+fn into_conditions(filter: AnyLuaValue) -> Result<Vec<Condition>> {
+    let filter = match filter {
+        AnyLuaValue::LuaArray(filter) => filter,
+        _ => bail!("Filter must be a table"),
+    };
+
+    let mut conditions = Vec::new();
+    for (key, value) in filter {
+        let condition = match (key, value) {
+            (AnyLuaValue::LuaString(column), value) => Condition {
+                column,
+                op: "=".to_string(),
+                value: LuaJsonValue::from(value).into(),
+            },
+            (AnyLuaValue::LuaNumber(_), AnyLuaValue::LuaArray(triple)) => {
+                let mut triple = triple.into_iter()
+                    .map(|(_, v)| v);
+                match (triple.next(), triple.next(), triple.next(), triple.next()) {
+                    (Some(AnyLuaValue::LuaString(column)), Some(AnyLuaValue::LuaString(op)), Some(value), None) => Condition {
+                        column,
+                        op,
+                        value: LuaJsonValue::from(value).into(),
+                    },
+                    _ => bail!("Filter expressions must be {{column, op, value}}"),
+                }
+            },
+            (key, _) => bail!("Invalid filter key: {:?}", key),
+        };
+        conditions.push(condition);
+    }
+
+    Ok(conditions)
+}
+
+fn into_query(family: Family, filter: AnyLuaValue, options: AnyLuaValue) -> Result<Query> {
+    let conditions = into_conditions(filter)?;
+
+    let mut limit = SELECT_DEFAULT_LIMIT;
+    let mut offset = 0;
+    if let AnyLuaValue::LuaArray(options) = options {
+        for (key, value) in options {
+            match (key, value) {
+                (AnyLuaValue::LuaString(key), AnyLuaValue::LuaNumber(n)) if key == "limit" => limit = n as usize,
+                (AnyLuaValue::LuaString(key), AnyLuaValue::LuaNumber(n)) if key == "offset" => offset = n as usize,
+                (key, _) => bail!("Invalid option: {:?}", key),
+            }
+        }
+    }
+
+    Ok(Query {
+        family,
+        conditions,
+        limit: limit.min(SELECT_MAX_LIMIT),
+        offset,
+    })
+}
+
 pub fn db_select(lua: &mut hlua::Lua, state: Arc<dyn State>) {
-    lua.set("db_select", hlua::function2(move |family: String, value: String| -> Result<Option<i32>> {
+    lua.set("db_select", hlua::function3(move |family: String, filter: AnyLuaValue, options: AnyLuaValue| -> Result<AnyLuaValue> {
         let family = Family::from_str(&family)
             .map_err(|e| state.set_error(e.into()))?;
 
+        // a string is a lookup by value, a table is a filter
+        let value = match filter {
+            AnyLuaValue::LuaString(value) => value,
+            filter => {
+                let query = into_query(family, filter, options)
+                    .map_err(|e| state.set_error(e))?;
+                let rows = state.db_query(query)
+                    .map_err(|e| state.set_error(e))?;
+                let rows = LuaJsonValue::from(serde_json::Value::Array(rows));
+                return Ok(rows.into());
+            },
+        };
+
         let r = state.db_select(family, value)
             .map_err(|e| state.set_error(e))?;
 
@@ -150,16 +226,44 @@ pub fn db_select(lua: &mut hlua::Lua, state: Arc<dyn State>) {
             DatabaseResponse::Updated(id) => Some(id),
             DatabaseResponse::NoChange(id) => Some(id),
             DatabaseResponse::Found(id) => Some(id),
+            DatabaseResponse::Rows(_) => None,
             DatabaseResponse::None => None,
         };
-        Ok(r)
+        Ok(r.map(|id| AnyLuaValue::LuaNumber(id.into()))
+            .unwrap_or(AnyLuaValue::LuaNil))
     }))
 }
 
+/// Serde ignores fields it doesn't know, compare with the parsed struct so a
+/// typo doesn't silently drop the change
+fn check_fields<U: serde::Serialize>(fields: Vec<String>, parsed: &U) -> Result<()> {
+    let known = match serde_json::to_value(parsed)? {
+        serde_json::Value::Object(known) => known,
+        _ => bail!("Update isn't an object"),
+    };
+
+    let mut unknown = fields.into_iter()
+        .filter(|key| !known.contains_key(key))
+        .collect::<Vec<_>>();
+    if !unknown.is_empty() {
+        unknown.sort();
+        bail!("Unknown or immutable field: {:?}", unknown);
+    }
+
+    Ok(())
+}
+
+fn field_names(update: &LuaJsonValue) -> Vec<String> {
+    match update {
+        LuaJsonValue::Object(update) => update.keys().cloned().collect(),
+        _ => Vec::new(),
+    }
+}
+
 fn gen_changeset<T: Model, U: Updateable<T>>(object: LuaJsonValue, mut update: LuaJsonValue) -> Result<(i32, String, U)>
     where
         for<'de> T: serde::Deserialize<'de>,
-        for<'de> U: serde::Deserialize<'de>,
+        for<'de> U: serde::Deserialize<'de> + serde::Serialize,
 {
     let existing = structs::from_lua::<T>(object)?;
 
@@ -168,7 +272,10 @@ fn gen_changeset<T: Model, U: Updateable<T>>(object: LuaJsonValue, mut update: L
         update.insert("id".into(), LuaJsonValue::Number(existing.id().into()));
     }
 
-    let mut update = structs::from_lua::<U>(update)?;
+    let fields = field_names(&update);
+    let parsed = structs::from_lua::<U>(update)?;
+    check_fields(fields, &parsed)?;
+    let mut update = parsed;
 
     let value = existing.to_string();
     update.changeset(&existing);
@@ -179,7 +286,7 @@ fn gen_changeset<T: Model, U: Updateable<T>>(object: LuaJsonValue, mut update: L
 fn gen_changeset2<T: Model, U: UpdateToChangeset<C>, C: Updateable<T>>(object: LuaJsonValue, mut update: LuaJsonValue) -> Result<(i32, String, C)>
     where
         for<'de> T: serde::Deserialize<'de>,
-        for<'de> U: serde::Deserialize<'de>,
+        for<'de> U: serde::Deserialize<'de> + serde::Serialize,
         for<'de> C: serde::Deserialize<'de>,
 {
     let existing = structs::from_lua::<T>(object)?;
@@ -189,8 +296,10 @@ fn gen_changeset2<T: Model, U: UpdateToChangeset<C>, C: Updateable<T>>(object: L
         update.insert("id".into(), LuaJsonValue::Number(existing.id().into()));
     }
 
-    let update = structs::from_lua::<U>(update)?;
-    let mut update = update.try_into_changeset()?;
+    let fields = field_names(&update);
+    let parsed = structs::from_lua::<U>(update)?;
+    check_fields(fields, &parsed)?;
+    let mut update = parsed.try_into_changeset()?;
 
     let value = existing.to_string();
     update.changeset(&existing);
@@ -198,9 +307,51 @@ fn gen_changeset2<T: Model, U: UpdateToChangeset<C>, C: Updateable<T>>(object: L
     Ok((existing.id(), value, update))
 }
 
-fn run_update(state: Arc<dyn State>, family: &str, object: AnyLuaValue, update: AnyLuaValue) -> Result<Option<i32>> {
+/// The query to look up an entity by id or value, out of scope entities are
+/// included so they can still be updated
+fn resolve_query(family: &Family, column: &str, value: serde_json::Value) -> Query {
+    Query {
+        family: family.clone(),
+        conditions: vec![
+            Condition {
+                column: column.to_string(),
+                op: "=".to_string(),
+                value,
+            },
+            // mentioning unscoped disables the implicit scope filter
+            Condition {
+                column: "unscoped".to_string(),
+                op: ">=".to_string(),
+                value: serde_json::Value::from(0),
+            },
+        ],
+        limit: 1,
+        offset: 0,
+    }
+}
+
+/// Load the entity if it's referenced by id or value instead of passing the
+/// table we've been called with, `None` if it doesn't exist
+fn resolve_entity(state: &Arc<dyn State>, family: &Family, object: AnyLuaValue) -> Result<Option<LuaJsonValue>> {
+    let (column, value) = match object {
+        AnyLuaValue::LuaNumber(id) => ("id", serde_json::Value::from(id as i32)),
+        AnyLuaValue::LuaString(value) => ("value", serde_json::Value::from(value.as_str())),
+        object => return Ok(Some(LuaJsonValue::from(object))),
+    };
+
+    let entity = state.db_query(resolve_query(family, column, value))?
+        .into_iter()
+        .next()
+        .map(LuaJsonValue::from);
+    Ok(entity)
+}
+
+fn run_update(state: Arc<dyn State>, family: &str, object: AnyLuaValue, update: AnyLuaValue) -> Result<(Option<i32>, bool)> {
     let family = Family::from_str(family)?;
-    let object = LuaJsonValue::from(object);
+    let object = match resolve_entity(&state, &family, object)? {
+        Some(object) => object,
+        None => return Ok((None, false)),
+    };
     let update = LuaJsonValue::from(update);
 
     let (id, value, update) = match family {
@@ -239,22 +390,162 @@ fn run_update(state: Arc<dyn State>, family: &str, object: AnyLuaValue, update:
 
     if update.is_dirty() {
         let r = match state.db_update(family, value, update)? {
-            // Inserted and Found are technically unreachable
-            DatabaseResponse::Inserted(id) => Some(id),
-            DatabaseResponse::Updated(id) => Some(id),
-            DatabaseResponse::NoChange(id) => Some(id),
-            DatabaseResponse::Found(id) => Some(id),
-            DatabaseResponse::None => None,
+            DatabaseResponse::Updated(id) => (Some(id), true),
+            // Inserted, Found and Rows are technically unreachable
+            DatabaseResponse::Inserted(id) => (Some(id), false),
+            DatabaseResponse::NoChange(id) => (Some(id), false),
+            DatabaseResponse::Found(id) => (Some(id), false),
+            DatabaseResponse::Rows(_) => (None, false),
+            DatabaseResponse::None => (None, false),
         };
         Ok(r)
     } else {
-        Ok(Some(id))
+        Ok((Some(id), false))
     }
 }
 
 pub fn db_update(lua: &mut hlua::Lua, state: Arc<dyn State>) {
-    lua.set("db_update", hlua::function3(move |family: String, object: AnyLuaValue, update: AnyLuaValue| -> Result<Option<i32>> {
+    lua.set("db_update", hlua::function3(move |family: String, object: AnyLuaValue, update: AnyLuaValue| -> Result<(Option<i32>, bool)> {
         run_update(state.clone(), &family, object, update)
             .map_err(|e| state.set_error(e))
     }))
 }
+
+pub fn db_update_fields(lua: &mut hlua::Lua, state: Arc<dyn State>) {
+    lua.set("db_update_fields", hlua::function3(move |family: String, id: i32, fields: AnyLuaValue| -> Result<Option<i32>> {
+        let family = Family::from_str(&family)
+            .map_err(|e| state.set_error(e.into()))?;
+
+        let fields = match serde_json::Value::from(LuaJsonValue::from(fields)) {
+            serde_json::Value::Object(fields) => fields,
+            _ => return Err(state.set_error(format_err!("Fields must be a table"))),
+        };
+
+        let r = match state.db_update_fields(family, id, fields).map_err(|e| state.set_error(e))? {
+            // Inserted, Found and Rows are technically unreachable
+            DatabaseResponse::Inserted(id) => Some(id),
+            DatabaseResponse::Updated(id) => Some(id),
+            DatabaseResponse::NoChange(id) => Some(id),
+            DatabaseResponse::Found(id) => Some(id),
+            DatabaseResponse::Rows(_) => None,
+            DatabaseResponse::None => None,
+        };
+        Ok(r)
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lua_table(items: Vec<(AnyLuaValue, AnyLuaValue)>) -> AnyLuaValue {
+        AnyLuaValue::LuaArray(items)
+    }
+
+    fn lua_str(s: &str) -> AnyLuaValue {
+        AnyLuaValue::LuaString(s.to_string())
+    }
+
+    #[test]
+    fn test_select_filter_table() {
+        let filter = lua_table(vec![
+            (lua_str("value"), lua_str("example.com")),
+            (AnyLuaValue::LuaNumber(1.0), lua_table(vec![
+                (AnyLuaValue::LuaNumber(1.0), lua_str("id")),
+                (AnyLuaValue::LuaNumber(2.0), lua_str(">")),
+                (AnyLuaValue::LuaNumber(3.0), AnyLuaValue::LuaNumber(3.0)),
+            ])),
+        ]);
+        let options = lua_table(vec![
+            (lua_str("limit"), AnyLuaValue::LuaNumber(5000.0)),
+            (lua_str("offset"), AnyLuaValue::LuaNumber(10.0)),
+        ]);
+        let query = into_query(Family::Subdomain, filter, options).unwrap();
+        assert_eq!(query.conditions, vec![
+            Condition {
+                column: "value".to_string(),
+                op: "=".to_string(),
+                value: "example.com".into(),
+            },
+            Condition {
+                column: "id".to_string(),
+                op: ">".to_string(),
+                value: 3.into(),
+            },
+        ]);
+        assert_eq!(query.limit, SELECT_MAX_LIMIT);
+        assert_eq!(query.offset, 10);
+    }
+
+    #[test]
+    fn test_select_filter_defaults() {
+        let query = into_query(Family::Domain, lua_table(vec![]), AnyLuaValue::LuaNil).unwrap();
+        assert!(query.conditions.is_empty());
+        assert_eq!(query.limit, SELECT_DEFAULT_LIMIT);
+        assert_eq!(query.offset, 0);
+    }
+
+    #[test]
+    fn test_select_filter_invalid_triple() {
+        let filter = lua_table(vec![
+            (AnyLuaValue::LuaNumber(1.0), lua_table(vec![
+                (AnyLuaValue::LuaNumber(1.0), lua_str("id")),
+                (AnyLuaValue::LuaNumber(2.0), lua_str(">")),
+            ])),
+        ]);
+        assert!(into_query(Family::Domain, filter, AnyLuaValue::LuaNil).is_err());
+    }
+
+    fn subdomain() -> LuaJsonValue {
+        LuaJsonValue::from(serde_json::json!({
+            "id": 3,
+            "domain_id": 1,
+            "value": "www.example.com",
+            "unscoped": false,
+            "resolvable": null,
+            "seen_count": 1,
+            "seen_first": null,
+            "seen_last": null,
+        }))
+    }
+
+    #[test]
+    fn test_update_changeset() {
+        let update = LuaJsonValue::from(serde_json::json!({"resolvable": true}));
+        let (id, value, update) = gen_changeset::<Subdomain, SubdomainUpdate>(subdomain(), update).unwrap();
+        assert_eq!(id, 3);
+        assert_eq!(value, "www.example.com");
+        assert_eq!(update.resolvable, Some(true));
+        assert!(update.is_dirty());
+
+        let update = LuaJsonValue::from(serde_json::json!({"resolvable": null}));
+        let (_, _, update) = gen_changeset::<Subdomain, SubdomainUpdate>(subdomain(), update).unwrap();
+        assert!(!update.is_dirty());
+    }
+
+    #[test]
+    fn test_update_unknown_field() {
+        let update = LuaJsonValue::from(serde_json::json!({"resolvable": true, "resolved": true}));
+        let r = gen_changeset::<Subdomain, SubdomainUpdate>(subdomain(), update);
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn test_resolve_unscoped() {
+        let db = crate::db::Database::memory().unwrap();
+        db.insert_generic(Insert::Domain(NewDomain {
+            value: "example.com".to_string(),
+            unscoped: false,
+        })).unwrap();
+        db.set_scoped::<Domain>(&crate::db::Filter::column_eq("value", "example.com"), false).unwrap();
+
+        let rows = db.query(&resolve_query(&Family::Domain, "value", "example.com".into())).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["unscoped"], true);
+        let rows = db.query(&resolve_query(&Family::Domain, "id", 1.into())).unwrap();
+        assert_eq!(rows.len(), 1);
+        let rows = db.query(&resolve_query(&Family::Domain, "value", "example.org".into())).unwrap();
+        assert!(rows.is_empty());
+    }
+}
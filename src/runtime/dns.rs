@@ -1,9 +1,11 @@
 use crate::errors::*;
 use serde::Deserialize;
 
-use chrootable_https::dns::{Resolver, DnsResolver, RecordType};
+use chrootable_https::dns::RecordType;
 use crate::engine;
 use crate::engine::ctx::State;
+use crate::ipc::network::ResolveRequest;
+use sn0int_std::concurrency::Slot;
 use crate::json::LuaJsonValue;
 use crate::hlua::{self, AnyLuaValue};
 use std::sync::Arc;
@@ -52,31 +54,26 @@ impl ResolveOptions {
 
 pub fn dns(lua: &mut hlua::Lua, state: Arc<dyn State>) {
     lua.set("dns", hlua::function2(move |name: String, options: AnyLuaValue| -> Result<AnyLuaValue> {
-        if state.proxy().is_some() {
-            let e = format_err!("dns is disabled if a proxy is active");
-            return Err(state.set_error(e));
-        }
-
         let options = ResolveOptions::from_lua(options)
             .map_err(|e| state.set_error(e))?;
+        options.record_type()
+            .map_err(|e| state.set_error(e))?;
 
-        let ns = match options.nameserver {
-            Some(ns) => vec![ns],
-            None => state.dns_config().ns.clone(),
-        };
-
-        let timeout = options.timeout
-            .map(Duration::from_millis);
-
-        let resolver = Resolver {
-            ns,
+        // the query is sent by the parent, which also checks if dns is allowed
+        let req = ResolveRequest {
+            name,
+            record: options.record,
+            nameserver: options.nameserver,
             tcp: options.tcp,
-            timeout,
+            timeout: options.timeout.map(Duration::from_millis),
         };
 
-        let reply = resolver.resolve(&name, options.record_type()?)
-            .wait_for_response()
+        let slot = Slot::acquire(state.as_ref())
             .map_err(|e| state.set_error(e))?;
+        let reply = state.dns(&req)
+            .map_err(|e| state.set_error(e));
+        drop(slot);
+        let reply = reply?;
 
         let reply = serde_json::to_value(reply)
             .map_err(|e| state.set_error(e.into()))?;
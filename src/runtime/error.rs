@@ -18,8 +18,110 @@ pub fn last_err(lua: &mut hlua::Lua, state: Arc<dyn State>) {
     }))
 }
 
+pub fn last_err_info(lua: &mut hlua::Lua, state: Arc<dyn State>) {
+    lua.set("last_err_info", hlua::function0(move || -> AnyLuaValue {
+        match state.last_error_info() {
+            Some(info) => info.to_lua(),
+            None => AnyLuaValue::LuaNil,
+        }
+    }))
+}
+
 pub fn set_err(lua: &mut hlua::Lua, state: Arc<dyn State>) {
     lua.set("set_err", hlua::function1(move |err: String| {
         state.set_error(failure::err_msg(err));
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::ctx::Script;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn verify_last_err_info_set_err() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            if last_err_info() ~= nil then
+                return 'unexpected error'
+            end
+
+            set_err('oops')
+            local err = last_err_info()
+            clear_err()
+            if err['kind'] ~= 'other' then
+                return 'wrong kind: ' .. err['kind']
+            end
+            if err['message'] ~= 'oops' then
+                return 'wrong message: ' .. err['message']
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_last_err_info_connection() {
+        // find a port that nothing is listening on
+        let port = TcpListener::bind("127.0.0.1:0").unwrap()
+            .local_addr().unwrap()
+            .port();
+
+        let script = Script::load_unchecked(format!(r#"
+        function run()
+            sock_connect('127.0.0.1', {}, {{}})
+            local err = last_err_info()
+            clear_err()
+            if err == nil then
+                return 'expected an error'
+            end
+            if err['kind'] ~= 'connection' then
+                return 'wrong kind: ' .. err['kind']
+            end
+            if err['os_error'] == nil then
+                return 'missing os error'
+            end
+        end
+        "#, port)).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_last_err_info_http_status() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0; 4096];
+                let _ = stream.read(&mut buf);
+                stream.write_all(b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").ok();
+            }
+        });
+
+        let script = Script::load_unchecked(format!(r#"
+        function run()
+            local session = http_mksession()
+            local req = http_request(session, 'GET', 'http://127.0.0.1:{}/', {{}})
+            http_fetch(req)
+            local err = last_err_info()
+            clear_err()
+            if err == nil then
+                return 'expected an error'
+            end
+            if err['kind'] ~= 'http-status' then
+                return 'wrong kind: ' .. err['kind']
+            end
+            if err['status'] ~= 500 then
+                return 'wrong status'
+            end
+            if last_err() ~= nil then
+                return 'error should have been cleared'
+            end
+        end
+        "#, port)).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+}
@@ -0,0 +1,23 @@
+use crate::errors::*;
+
+use crate::engine::ctx::State;
+use crate::hlua::{self, AnyLuaValue};
+use std::sync::Arc;
+
+
+pub fn read_dir(lua: &mut hlua::Lua, state: Arc<dyn State>) {
+    lua.set("read_dir", hlua::function1(move |path: String| -> Result<Vec<AnyLuaValue>> {
+        state.read_dir(&path)
+            .map_err(|e| state.set_error(e))
+            .map(|entries| entries.into_iter()
+                .map(AnyLuaValue::LuaString)
+                .collect())
+    }))
+}
+
+pub fn read_file(lua: &mut hlua::Lua, state: Arc<dyn State>) {
+    lua.set("read_file", hlua::function1(move |path: String| -> Result<String> {
+        state.read_file(&path)
+            .map_err(|e| state.set_error(e))
+    }))
+}
@@ -1,14 +1,20 @@
 use crate::errors::*;
 
 use crate::engine::ctx::State;
+use crate::error_info::HttpStatusError;
 use crate::hlua::{self, AnyLuaValue, AnyHashableLuaValue};
 use crate::json;
 use sn0int_std::blobs::BlobState;
 use sn0int_std::web::WebState;
 use std::sync::Arc;
 use std::collections::HashMap;
-use crate::web::{RequestOptions, HttpRequest};
+use crate::web::{RequestOptions, HttpRequest, Response};
+use sn0int_common::metadata::Capability;
 
+fn send<S: State + WebState>(state: &S, req: &mut HttpRequest) -> Result<Response> {
+    state.check_capability(Capability::Http)?;
+    req.send(state)
+}
 
 pub fn http_mksession(lua: &mut hlua::Lua, state: Arc<dyn State>) {
     lua.set("http_mksession", hlua::function0(move || -> String {
@@ -35,7 +41,7 @@ pub fn http_send<S>(lua: &mut hlua::Lua, state: Arc<S>)
             .context("invalid http request object")
             .map_err(|err| state.set_error(err.into()))?;
 
-        let resp = req.send(state.as_ref())
+        let resp = send(state.as_ref(), &mut req)
             .map_err(|err| state.set_error(err))?;
 
         req.response_to_lua(state.as_ref(), resp)
@@ -52,11 +58,11 @@ pub fn http_fetch<S>(lua: &mut hlua::Lua, state: Arc<S>)
             .context("invalid http request object")
             .map_err(|err| state.set_error(err.into()))?;
 
-        let resp = req.send(state.as_ref())
+        let resp = send(state.as_ref(), &mut req)
             .map_err(|err| state.set_error(err))?;
 
         if resp.status < 200 || resp.status > 299 {
-            return Err(state.set_error(format_err!("http status error: {}", resp.status)));
+            return Err(state.set_error(HttpStatusError { status: resp.status }.into()));
         }
 
         req.response_to_lua(state.as_ref(), resp)
@@ -73,11 +79,11 @@ pub fn http_fetch_json<S>(lua: &mut hlua::Lua, state: Arc<S>)
             .context("invalid http request object")
             .map_err(|err| state.set_error(err.into()))?;
 
-        let resp = req.send(state.as_ref())
+        let resp = send(state.as_ref(), &mut req)
             .map_err(|err| state.set_error(err))?;
 
         if resp.status < 200 || resp.status > 299 {
-            return Err(state.set_error(format_err!("http status error: {}", resp.status)));
+            return Err(state.set_error(HttpStatusError { status: resp.status }.into()));
         }
 
         json::decode(&resp.body)
@@ -89,8 +95,35 @@ pub fn http_fetch_json<S>(lua: &mut hlua::Lua, state: Arc<S>)
 #[cfg(test)]
 mod tests {
     use crate::engine::ctx::Script;
+    use crate::ipc::network::NetworkPolicy;
+    use sn0int_common::metadata::EgressRule;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
     use std::time::{Instant, Duration};
 
+    /// Serve static redirects on localhost, `routes` maps a path to a location
+    fn redirect_server(routes: &'static [(&'static str, &'static str)]) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0; 4096];
+                let n = stream.read(&mut buf).unwrap();
+                let req = String::from_utf8_lossy(&buf[..n]);
+                let path = req.split(' ').nth(1).unwrap_or("/");
+
+                let res = match routes.iter().find(|(p, _)| *p == path) {
+                    Some((_, location)) => format!("HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", location),
+                    None => "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok".to_string(),
+                };
+                stream.write_all(res.as_bytes()).ok();
+            }
+        });
+        port
+    }
+
     #[test]
     #[ignore]
     fn verify_request() {
@@ -311,4 +344,166 @@ mod tests {
         "#).expect("failed to load script");
         script.test().expect("Script failed");
     }
+
+    #[test]
+    #[ignore]
+    fn verify_request_http2() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            session = http_mksession()
+            req = http_request(session, "GET", "https://github.com", {
+                http_version='h2',
+            })
+            x = http_send(req)
+            if last_err() then return end
+
+            if x['version'] ~= 'HTTP/2' then
+                return 'wrong http version: ' .. x['version']
+            end
+            if x['status'] ~= 200 then
+                return 'wrong status code'
+            end
+        end
+        "#).expect("failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_request_version() {
+        let port = redirect_server(&[]);
+        let script = Script::load_unchecked(format!(r#"
+        function run()
+            session = http_mksession()
+            req = http_request(session, "GET", "http://127.0.0.1:{}/", {{
+                http_version='h1',
+            }})
+            x = http_send(req)
+            if last_err() then return end
+            if x['version'] ~= 'HTTP/1.1' then
+                return 'wrong http version: ' .. x['version']
+            end
+        end
+        "#, port)).expect("failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    #[cfg(not(feature = "http3"))]
+    fn verify_request_http3_unsupported() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            session = http_mksession()
+            req = http_request(session, "GET", "https://github.com", {
+                http_version='h3',
+            })
+            x = http_send(req)
+            if last_err() then return end
+        end
+        "#).expect("failed to load script");
+        let err = script.test().expect_err("Script should have failed");
+        assert!(err.to_string().contains("built without http3 support"), "unexpected error: {}", err);
+    }
+
+    #[test]
+    #[ignore]
+    #[cfg(feature = "http3")]
+    fn verify_request_http3() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            session = http_mksession()
+            req = http_request(session, "GET", "https://cloudflare.com/cdn-cgi/trace", {
+                http_version='h3',
+            })
+            x = http_send(req)
+            if last_err() then return end
+
+            if x['version'] ~= 'HTTP/3' then
+                return 'wrong http version: ' .. x['version']
+            end
+            if x['status'] ~= 200 then
+                return 'wrong status code'
+            end
+        end
+        "#).expect("failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_redirect_chain() {
+        let port = redirect_server(&[("/a", "/b"), ("/b", "/c")]);
+        let script = Script::load_unchecked(format!(r#"
+        function run()
+            session = http_mksession()
+            req = http_request(session, "GET", "http://127.0.0.1:{}/a", {{
+                follow_redirects=5,
+            }})
+            x = http_send(req)
+            if last_err() then return end
+
+            if x['status'] ~= 200 then return 'redirect wasn\'t followed' end
+            if x['url'] ~= 'http://127.0.0.1:{}/c' then return 'wrong final url: ' .. x['url'] end
+            if #x['redirects'] ~= 2 then return 'wrong number of redirects' end
+            if x['redirects'][1]['location'] ~= 'http://127.0.0.1:{}/b' then return 'wrong redirect chain' end
+        end
+        "#, port, port, port)).expect("failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_redirect_loop() {
+        let port = redirect_server(&[("/a", "/b"), ("/b", "/a")]);
+        let script = Script::load_unchecked(format!(r#"
+        function run()
+            session = http_mksession()
+            req = http_request(session, "GET", "http://127.0.0.1:{}/a", {{
+                follow_redirects=5,
+            }})
+            x = http_send(req)
+            if last_err() then return end
+        end
+        "#, port)).expect("failed to load script");
+        let err = script.test().expect_err("Script should have failed");
+        assert!(err.to_string().contains("Redirect loop detected"), "unexpected error: {}", err);
+    }
+
+    #[test]
+    fn verify_redirect_egress_blocked() {
+        let port = redirect_server(&[("/a", "http://10.13.37.1/")]);
+        let script = Script::load_unchecked(format!(r#"
+        function run()
+            session = http_mksession()
+            req = http_request(session, "GET", "http://127.0.0.1:{}/a", {{
+                follow_redirects=5,
+            }})
+            x = http_send(req)
+            if last_err() then return end
+        end
+        "#, port)).expect("failed to load script");
+        let policy = NetworkPolicy {
+            egress: Some(EgressRule::parse_list("127.0.0.1").unwrap()),
+            ..Default::default()
+        };
+        let err = script.test_with_policy(Script::test_env().unwrap(), policy).expect_err("Script should have failed");
+        assert!(err.to_string().contains(r#"Connection to "10.13.37.1" is blocked"#), "unexpected error: {}", err);
+    }
+
+    #[test]
+    fn verify_redirect_cross_origin() {
+        let port = redirect_server(&[("/a", "http://localhost:1/")]);
+        let script = Script::load_unchecked(format!(r#"
+        function run()
+            session = http_mksession()
+            req = http_request(session, "GET", "http://127.0.0.1:{}/a", {{
+                follow_redirects=5,
+                redirect_cross_origin=false,
+            }})
+            x = http_send(req)
+            if last_err() then return end
+
+            if x['status'] ~= 302 then return 'cross-origin redirect was followed' end
+            if #x['redirects'] ~= 0 then return 'redirect was recorded' end
+        end
+        "#, port)).expect("failed to load script");
+        script.test().expect("Script failed");
+    }
 }
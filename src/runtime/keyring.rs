@@ -1,12 +1,92 @@
+use crate::errors::*;
 use crate::engine::ctx::State;
 use crate::hlua::{self, AnyLuaValue};
+use crate::json::LuaJsonValue;
+use crate::keyring::{KeyName, KeyRingEntry};
+use chrono::NaiveDateTime;
+use std::str::FromStr;
 use std::sync::Arc;
 
 
 pub fn keyring(lua: &mut hlua::Lua, state: Arc<dyn State>) {
-    lua.set("keyring", hlua::function1(move |query: String| -> Vec<AnyLuaValue> {
-        state.keyring(&query).into_iter()
+    lua.set("keyring", hlua::function1(move |query: String| -> Result<Vec<AnyLuaValue>> {
+        let keys = state.keyring(&query);
+        if !keys.is_empty() {
+            state.keyring_used(keys.iter().map(|x| x.name()).collect())
+                .map_err(|err| state.set_error(err))?;
+        }
+        Ok(keys.into_iter()
             .map(|x| x.to_lua().unwrap())
-            .collect()
+            .collect())
     }))
 }
+
+/// Accepts `namespace:name` or an entry returned by `keyring`
+fn key_name(key: AnyLuaValue) -> Result<KeyName> {
+    match key {
+        AnyLuaValue::LuaString(key) => key.parse(),
+        key => {
+            let key: KeyRingEntry = serde_json::from_value(LuaJsonValue::from(key).into())
+                .context("Expected a keyring entry")?;
+            Ok(key.name())
+        },
+    }
+}
+
+/// Accepts a unix timestamp or a sn0int time string
+fn until(until: AnyLuaValue) -> Result<NaiveDateTime> {
+    match until {
+        AnyLuaValue::LuaNumber(time) => NaiveDateTime::from_timestamp_opt(time as i64, 0)
+            .ok_or_else(|| format_err!("Failed to get time from timestamp")),
+        AnyLuaValue::LuaString(time) => NaiveDateTime::from_str(&time)
+            .map_err(Error::from),
+        _ => bail!("Expected timestamp or datetime string"),
+    }
+}
+
+pub fn keyring_exhausted(lua: &mut hlua::Lua, state: Arc<dyn State>) {
+    lua.set("keyring_exhausted", hlua::function2(move |key: AnyLuaValue, time: AnyLuaValue| -> Result<()> {
+        key_name(key)
+            .and_then(|key| Ok((key, until(time)?)))
+            .and_then(|(key, until)| state.keyring_exhausted(key, until))
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+pub fn keyring_rotate(lua: &mut hlua::Lua, state: Arc<dyn State>) {
+    lua.set("keyring_rotate", hlua::function1(move |namespace: String| -> Result<AnyLuaValue> {
+        let key = state.keyring_rotate(&namespace)
+            .map_err(|err| state.set_error(err))?;
+        match key {
+            Some(key) => key.to_lua()
+                .map_err(|err| state.set_error(err)),
+            None => Ok(AnyLuaValue::LuaNil),
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::ctx::Script;
+
+    #[test]
+    fn verify_exhausted_invalid_key() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            keyring_exhausted('shodan', 1600000000)
+        end
+        "#).expect("Failed to load script");
+        let err = script.test().unwrap_err();
+        assert!(err.to_string().contains("Missing namespace"), "{}", err);
+    }
+
+    #[test]
+    fn verify_exhausted_invalid_time() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            keyring_exhausted({namespace='shodan', access_key='a'}, 'tomorrow')
+        end
+        "#).expect("Failed to load script");
+        assert!(script.test().is_err());
+    }
+}
@@ -12,6 +12,7 @@ import_fns!(db);
 import_fns!(dns);
 import_fns!(encoding);
 import_fns!(error);
+import_fns!(files);
 import_fns!(geo);
 import_fns!(geoip);
 import_fns!(gfx);
@@ -24,8 +25,10 @@ import_fns!(json);
 import_fns!(keyring);
 import_fns!(logger);
 import_fns!(mqtt);
+import_fns!(notify);
 import_fns!(options);
 import_fns!(pgp);
+import_fns!(prompt);
 import_fns!(psl);
 import_fns!(ratelimits);
 import_fns!(regex);
@@ -0,0 +1,72 @@
+use crate::errors::*;
+
+use crate::engine::ctx::State;
+use crate::engine::structs;
+use crate::hlua::{self, AnyLuaValue};
+use crate::json::LuaJsonValue;
+use crate::worker::NotifyEvent;
+use serde::Deserialize;
+use std::sync::Arc;
+
+
+#[derive(Debug, Default, Deserialize)]
+pub struct NotifyOptions {
+    body: Option<String>,
+}
+
+fn into_notify(topic: String, subject: String, opts: AnyLuaValue) -> Result<NotifyEvent> {
+    if topic.is_empty() {
+        bail!("Topic can't be empty");
+    }
+    if topic.chars().any(char::is_whitespace) {
+        bail!("Topic can't contain whitespace");
+    }
+
+    let opts = match opts {
+        AnyLuaValue::LuaNil => NotifyOptions::default(),
+        opts => structs::from_lua::<NotifyOptions>(LuaJsonValue::from(opts))?,
+    };
+
+    Ok(NotifyEvent {
+        topic,
+        subject,
+        body: opts.body,
+    })
+}
+
+pub fn notify(lua: &mut hlua::Lua, state: Arc<dyn State>) {
+    lua.set("notify", hlua::function3(move |topic: String, subject: String, opts: AnyLuaValue| -> Result<bool> {
+        let event = into_notify(topic, subject, opts)
+            .map_err(|err| state.set_error(err))?;
+        state.notify(event)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notify_body() {
+        let opts = AnyLuaValue::LuaArray(vec![
+            (AnyLuaValue::LuaString("body".into()), AnyLuaValue::LuaString("hello world".into())),
+        ]);
+        let event = into_notify("login".into(), "Login worked".into(), opts).unwrap();
+        assert_eq!(event.topic, "login");
+        assert_eq!(event.subject, "Login worked");
+        assert_eq!(event.body.as_deref(), Some("hello world"));
+    }
+
+    #[test]
+    fn test_notify_no_opts() {
+        let event = into_notify("takeover".into(), "Confirmed".into(), AnyLuaValue::LuaNil).unwrap();
+        assert_eq!(event.body, None);
+    }
+
+    #[test]
+    fn test_notify_invalid_topic() {
+        assert!(into_notify("".into(), "x".into(), AnyLuaValue::LuaNil).is_err());
+        assert!(into_notify("a b".into(), "x".into(), AnyLuaValue::LuaNil).is_err());
+    }
+}
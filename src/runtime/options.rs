@@ -1,5 +1,7 @@
+use crate::errors::*;
 use crate::engine::ctx::State;
-use crate::hlua;
+use crate::hlua::{self, AnyLuaValue};
+use crate::json::LuaJsonValue;
 use std::sync::Arc;
 
 
@@ -10,9 +12,20 @@ pub fn getopt(lua: &mut hlua::Lua, state: Arc<dyn State>) {
     }))
 }
 
+pub fn module_info(lua: &mut hlua::Lua, state: Arc<dyn State>) {
+    lua.set("module_info", hlua::function0(move || -> Result<AnyLuaValue> {
+        let info = match state.module_info() {
+            Some(info) => serde_json::to_value(info)
+                .map_err(|err| state.set_error(err.into()))?,
+            None => return Ok(AnyLuaValue::LuaNil),
+        };
+        Ok(LuaJsonValue::from(info).into())
+    }))
+}
 
 #[cfg(test)]
 mod tests {
+    use crate::engine::ModuleInfo;
     use crate::engine::ctx::Script;
 
     #[test]
@@ -27,4 +40,45 @@ mod tests {
         "#).expect("Failed to load script");
         script.test().expect("Script failed");
     }
+
+    #[test]
+    fn verify_module_info() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            info = module_info()
+            if info['canonical'] ~= 'kpcyrd/ctlogs' or info['version'] ~= '0.2.0' then
+                return 'Wrong module: ' .. info['canonical'] .. ' ' .. info['version']
+            end
+            if info['source'] ~= 'domains' or info['emits'][1] ~= 'subdomain' then
+                return 'Wrong header'
+            end
+        end
+        "#).expect("Failed to load script");
+
+        let mut env = Script::test_env().unwrap();
+        env.module = Some(ModuleInfo {
+            author: "kpcyrd".to_string(),
+            name: "ctlogs".to_string(),
+            canonical: "kpcyrd/ctlogs".to_string(),
+            version: "0.2.0".to_string(),
+            description: "Query certificate transparency logs".to_string(),
+            source: Some("domains".to_string()),
+            emits: vec!["subdomain".to_string()],
+            keyring_access: Vec::new(),
+            stealth: "passive".to_string(),
+        });
+        script.test_with(env).expect("Script failed");
+    }
+
+    #[test]
+    fn verify_module_info_without_module() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            if module_info() ~= nil then
+                return 'Expected nil'
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
 }
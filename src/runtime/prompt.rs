@@ -0,0 +1,130 @@
+use crate::errors::*;
+
+use crate::engine::ctx::State;
+use crate::engine::structs;
+use crate::hlua::{self, AnyLuaValue};
+use crate::json::LuaJsonValue;
+use crate::worker::{PromptEvent, PromptResponse};
+use serde::Deserialize;
+use std::sync::Arc;
+
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SelectOptions {
+    text: Option<String>,
+    default: Option<usize>,
+}
+
+fn into_select(options: AnyLuaValue, opts: AnyLuaValue) -> Result<PromptEvent> {
+    let options = structs::from_lua::<Vec<String>>(LuaJsonValue::from(options))
+        .context("Options must be a list of strings")?;
+    if options.is_empty() {
+        bail!("Options must not be empty");
+    }
+
+    let opts = match opts {
+        AnyLuaValue::LuaNil => SelectOptions::default(),
+        opts => structs::from_lua::<SelectOptions>(LuaJsonValue::from(opts))?,
+    };
+
+    let default = match opts.default {
+        Some(idx) if idx >= 1 && idx <= options.len() => Some(idx - 1),
+        Some(idx) => bail!("Default is out of range: {}", idx),
+        None => None,
+    };
+
+    Ok(PromptEvent::Select {
+        text: opts.text.unwrap_or_else(|| String::from("Select an option")),
+        options,
+        default,
+    })
+}
+
+fn ask_text(state: &Arc<dyn State>, prompt: PromptEvent) -> Result<String> {
+    match state.prompt(prompt) {
+        Ok(PromptResponse::Text(answer)) => Ok(answer),
+        Ok(r) => Err(state.set_error(format_err!("Unexpected prompt response: {:?}", r))),
+        Err(err) => Err(state.set_error(err)),
+    }
+}
+
+pub fn prompt(lua: &mut hlua::Lua, state: Arc<dyn State>) {
+    lua.set("prompt", hlua::function1(move |text: String| -> Result<String> {
+        ask_text(&state, PromptEvent::Text { text })
+    }))
+}
+
+pub fn prompt_password(lua: &mut hlua::Lua, state: Arc<dyn State>) {
+    lua.set("prompt_password", hlua::function1(move |text: String| -> Result<String> {
+        ask_text(&state, PromptEvent::Password { text })
+    }))
+}
+
+pub fn prompt_select(lua: &mut hlua::Lua, state: Arc<dyn State>) {
+    lua.set("prompt_select", hlua::function2(move |options: AnyLuaValue, opts: AnyLuaValue| -> Result<u32> {
+        let prompt = into_select(options, opts)
+            .map_err(|err| state.set_error(err))?;
+
+        match state.prompt(prompt) {
+            Ok(PromptResponse::Select(idx)) => Ok(idx as u32 + 1),
+            Ok(r) => Err(state.set_error(format_err!("Unexpected prompt response: {:?}", r))),
+            Err(err) => Err(state.set_error(err)),
+        }
+    }))
+}
+
+pub fn prompt_confirm(lua: &mut hlua::Lua, state: Arc<dyn State>) {
+    lua.set("prompt_confirm", hlua::function2(move |text: String, default: AnyLuaValue| -> Result<bool> {
+        let default = match default {
+            AnyLuaValue::LuaBoolean(default) => Some(default),
+            AnyLuaValue::LuaNil => None,
+            _ => return Err(state.set_error(format_err!("Default must be a boolean"))),
+        };
+
+        match state.prompt(PromptEvent::Confirm { text, default }) {
+            Ok(PromptResponse::Confirm(answer)) => Ok(answer),
+            Ok(r) => Err(state.set_error(format_err!("Unexpected prompt response: {:?}", r))),
+            Err(err) => Err(state.set_error(err)),
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lua_list(items: &[&str]) -> AnyLuaValue {
+        AnyLuaValue::LuaArray(items.iter()
+            .enumerate()
+            .map(|(i, x)| (AnyLuaValue::LuaNumber((i + 1) as f64), AnyLuaValue::LuaString(x.to_string())))
+            .collect())
+    }
+
+    #[test]
+    fn test_select_default() {
+        let opts = AnyLuaValue::LuaArray(vec![
+            (AnyLuaValue::LuaString("default".into()), AnyLuaValue::LuaNumber(2.0)),
+        ]);
+        let prompt = into_select(lua_list(&["a", "b"]), opts).unwrap();
+        match prompt {
+            PromptEvent::Select { options, default, .. } => {
+                assert_eq!(options, vec!["a", "b"]);
+                assert_eq!(default, Some(1));
+            },
+            _ => panic!("unexpected prompt: {:?}", prompt),
+        }
+    }
+
+    #[test]
+    fn test_select_default_out_of_range() {
+        let opts = AnyLuaValue::LuaArray(vec![
+            (AnyLuaValue::LuaString("default".into()), AnyLuaValue::LuaNumber(3.0)),
+        ]);
+        assert!(into_select(lua_list(&["a", "b"]), opts).is_err());
+    }
+
+    #[test]
+    fn test_select_empty() {
+        assert!(into_select(lua_list(&[]), AnyLuaValue::LuaNil).is_err());
+    }
+}
@@ -1,12 +1,116 @@
+use crate::errors::*;
 use crate::engine::ctx::State;
 use crate::hlua;
+use rand::{thread_rng, Rng};
+use std::io;
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 
-pub fn sleep(lua: &mut hlua::Lua, _: Arc<dyn State>) {
-    lua.set("sleep", hlua::function1(move |n: i32| {
-        thread::sleep(Duration::from_secs(n as u64));
+/// Sleep unless this would take us past the deadline of the source, in which
+/// case we fail right away instead of waiting for nothing
+fn sleep_before(deadline: Option<Instant>, duration: Duration) -> Result<()> {
+    if let Some(deadline) = deadline {
+        if Instant::now() + duration > deadline {
+            let msg = format!("Sleeping for {:?} exceeds the timeout of this source", duration);
+            return Err(io::Error::new(io::ErrorKind::TimedOut, msg).into());
+        }
+    }
+    thread::sleep(duration);
+    Ok(())
+}
+
+pub fn sleep(lua: &mut hlua::Lua, state: Arc<dyn State>) {
+    lua.set("sleep", hlua::function1(move |n: i32| -> Result<()> {
+        sleep_before(state.deadline(), Duration::from_secs(n.max(0) as u64))
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+fn jitter(min: f64, max: f64) -> Result<Duration> {
+    if !min.is_finite() || !max.is_finite() || min < 0.0 {
+        bail!("Invalid sleep range: {} to {}", min, max);
+    }
+    if min > max {
+        bail!("Minimum sleep is larger than maximum: {} > {}", min, max);
+    }
+
+    let secs = if min < max {
+        thread_rng().gen_range(min..max)
+    } else {
+        min
+    };
+    Ok(Duration::from_secs_f64(secs))
+}
+
+pub fn sleep_jitter(lua: &mut hlua::Lua, state: Arc<dyn State>) {
+    lua.set("sleep_jitter", hlua::function2(move |min: f64, max: f64| -> Result<()> {
+        jitter(min, max)
+            .and_then(|duration| sleep_before(state.deadline(), duration))
+            .map_err(|err| state.set_error(err))
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::ctx::Script;
+    use sn0int_std::error_info::{ErrorInfo, ErrorKind};
+
+    #[test]
+    fn test_jitter_range() {
+        for _ in 0..100 {
+            let d = jitter(0.5, 1.5).unwrap();
+            assert!(d >= Duration::from_millis(500) && d < Duration::from_millis(1500));
+        }
+        assert_eq!(jitter(1.0, 1.0).unwrap(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_jitter_invalid() {
+        assert!(jitter(2.0, 1.0).is_err());
+        assert!(jitter(-1.0, 1.0).is_err());
+        assert!(jitter(0.0, f64::INFINITY).is_err());
+    }
+
+    #[test]
+    fn verify_sleep_jitter() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            start = time_monotonic()
+            sleep_jitter(0.01, 0.02)
+            if last_err() then return end
+            if time_monotonic() - start < 0.01 then
+                return 'sleep was too short'
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn test_sleep_past_deadline() {
+        let deadline = Some(Instant::now() + Duration::from_millis(50));
+        let start = Instant::now();
+        let err = sleep_before(deadline, Duration::from_secs(60)).unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(ErrorInfo::new(&err).kind, ErrorKind::Timeout);
+        sleep_before(deadline, Duration::from_millis(1)).unwrap();
+    }
+
+    #[test]
+    fn verify_sleep_jitter_timeout() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            sleep_jitter(30, 60)
+            if last_err() then return end
+            return 'sleep should have failed'
+        end
+        "#).expect("Failed to load script");
+        let mut env = Script::test_env().unwrap();
+        env.timeout = Some(Duration::from_millis(100));
+        let err = script.test_with(env).unwrap_err();
+        assert!(err.to_string().contains("timeout"), "{}", err);
+    }
+}
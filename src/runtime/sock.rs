@@ -1,7 +1,7 @@
 use crate::errors::*;
 
 use crate::engine::ctx::State;
-use crate::engine::structs::{byte_array, lua_bytes};
+use crate::engine::structs::{byte_array, lua_bytes, LuaMap};
 use crate::hlua::{self, AnyLuaValue};
 use crate::sockets::SocketOptions;
 use std::sync::Arc;
@@ -31,6 +31,41 @@ pub fn sock_upgrade_tls(lua: &mut hlua::Lua, state: Arc<dyn State>) {
     }))
 }
 
+fn fingerprint(client: &Option<String>, server: &Option<String>) -> AnyLuaValue {
+    let mut map = LuaMap::new();
+    if let Some(client) = client {
+        map.insert_str("client", client.clone());
+    }
+    if let Some(server) = server {
+        map.insert_str("server", server.clone());
+    }
+    map.into()
+}
+
+pub fn sock_ja3(lua: &mut hlua::Lua, state: Arc<dyn State>) {
+    lua.set("sock_ja3", hlua::function1(move |sock: String| -> Result<AnyLuaValue> {
+        let sock = state.get_sock(&sock);
+        let sock = sock.lock().unwrap();
+
+        let tls = sock.tls_data()
+            .ok_or_else(|| state.set_error(format_err!("Socket isn't using tls")))?;
+        let fp = tls.fingerprints();
+        Ok(fingerprint(&fp.ja3, &fp.ja3s))
+    }))
+}
+
+pub fn sock_ja4(lua: &mut hlua::Lua, state: Arc<dyn State>) {
+    lua.set("sock_ja4", hlua::function1(move |sock: String| -> Result<AnyLuaValue> {
+        let sock = state.get_sock(&sock);
+        let sock = sock.lock().unwrap();
+
+        let tls = sock.tls_data()
+            .ok_or_else(|| state.set_error(format_err!("Socket isn't using tls")))?;
+        let fp = tls.fingerprints();
+        Ok(fingerprint(&fp.ja4, &fp.ja4s))
+    }))
+}
+
 pub fn sock_options(lua: &mut hlua::Lua, state: Arc<dyn State>) {
     lua.set("sock_options", hlua::function2(move |sock: String, options: AnyLuaValue| -> Result<()> {
         let options = SocketOptions::try_from(options)
@@ -192,6 +227,108 @@ pub fn sock_newline(lua: &mut hlua::Lua, state: Arc<dyn State>) {
 #[cfg(test)]
 mod tests {
     use crate::engine::ctx::Script;
+    use crate::ipc::network::NetworkPolicy;
+    use sn0int_common::metadata::EgressRule;
+    use std::net::TcpListener;
+
+    fn egress_script() -> (TcpListener, Script) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let script = Script::load_unchecked(format!(r#"
+        function run()
+            sock = sock_connect('127.0.0.1', {}, {{}})
+            if last_err() then return end
+        end
+        "#, port)).expect("failed to load script");
+        (listener, script)
+    }
+
+    #[test]
+    fn verify_egress_allowed() {
+        let (_listener, script) = egress_script();
+        let policy = NetworkPolicy {
+            egress: Some(EgressRule::parse_list("example.com, 127.0.0.0/8").unwrap()),
+            ..Default::default()
+        };
+        script.test_with_policy(Script::test_env().unwrap(), policy).expect("Script failed");
+    }
+
+    #[test]
+    fn verify_egress_blocked() {
+        let (_listener, script) = egress_script();
+        let policy = NetworkPolicy {
+            egress: Some(EgressRule::parse_list("example.com").unwrap()),
+            ..Default::default()
+        };
+        let err = script.test_with_policy(Script::test_env().unwrap(), policy).unwrap_err();
+        assert!(err.to_string().contains(r#"Connection to "127.0.0.1" is blocked by the egress policy"#), "{}", err);
+
+        let policy = NetworkPolicy {
+            egress: Some(vec![]),
+            ..Default::default()
+        };
+        let err = script.test_with_policy(Script::test_env().unwrap(), policy).unwrap_err();
+        assert!(err.to_string().contains("blocked by the egress policy"), "{}", err);
+    }
+
+    #[test]
+    fn verify_dns_capability() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            sock_connect('localhost', 1, {})
+            local err = last_err_info()
+            if not err or err['kind'] ~= 'capability' then
+                return 'expected capability error'
+            end
+            clear_err()
+
+            dns('localhost', 'A')
+            err = last_err_info()
+            if not err or err['kind'] ~= 'capability' then
+                return 'expected capability error'
+            end
+            clear_err()
+        end
+        "#).expect("failed to load script");
+        let env = Script::test_env().unwrap();
+        let policy = NetworkPolicy {
+            dns: false,
+            ..Default::default()
+        };
+        script.test_with_policy(env, policy).expect("Script failed");
+    }
+
+    #[test]
+    fn verify_offline() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            sock_connect('127.0.0.1', 1, {})
+            local err = last_err_info()
+            if not err or err['kind'] ~= 'offline' then
+                return 'expected offline error'
+            end
+            clear_err()
+
+            dns('localhost', 'A')
+            err = last_err_info()
+            if not err or err['kind'] ~= 'offline' then
+                return 'expected offline error'
+            end
+            clear_err()
+
+            -- everything that doesn't need the network keeps working
+            if sha2_256('a') == nil then
+                return 'expected hash'
+            end
+        end
+        "#).expect("failed to load script");
+        let env = Script::test_env().unwrap();
+        let policy = NetworkPolicy {
+            offline: true,
+            ..Default::default()
+        };
+        script.test_with_policy(env, policy).expect("Script failed");
+    }
 
     #[test]
     #[ignore]
@@ -218,6 +355,25 @@ mod tests {
         script.test().expect("Script failed");
     }
 
+    #[test]
+    #[ignore]
+    fn verify_tls_ja3() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            sock = sock_connect('badssl.com', 443, {
+                tls=true,
+            })
+            if last_err() then return end
+            ja3 = sock_ja3(sock)
+            if last_err() then return end
+            if ja3['client'] == nil or ja3['server'] == nil then
+                return 'ja3 fingerprint is missing'
+            end
+        end
+        "#).expect("failed to load script");
+        script.test().expect("Script failed");
+    }
+
     #[test]
     #[ignore]
     fn verify_tls_upgrade() {
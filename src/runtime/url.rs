@@ -1,13 +1,83 @@
 use crate::errors::*;
 use crate::engine::ctx::State;
-use crate::engine::structs::LuaMap;
+use crate::engine::structs::{LuaMap, LuaList};
 use crate::hlua::{self, AnyLuaValue};
 use crate::json::LuaJsonValue;
 use url::Url;
 use percent_encoding::{percent_encode, percent_decode, NON_ALPHANUMERIC};
 use serde_json::Value;
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
+
+fn query_value(key: &str, value: &Value) -> Result<Option<String>> {
+    match value {
+        Value::Null => Ok(None),
+        Value::Bool(b) => Ok(Some(b.to_string())),
+        Value::Number(n) => Ok(Some(n.to_string())),
+        Value::String(s) => Ok(Some(s.to_string())),
+        _ => bail!("Unsupported value for query parameter {:?}: {:?}", key, value),
+    }
+}
+
+fn set_query(mut url: Url, params: Value) -> Result<Url> {
+    let params = match params {
+        Value::Object(params) => params,
+        // empty lua tables are detected as lists
+        Value::Array(list) if list.is_empty() => serde_json::Map::new(),
+        _ => bail!("Query parameters need to be a table"),
+    };
+
+    let mut pairs = Vec::new();
+    for (key, value) in &params {
+        match value {
+            Value::Array(values) => {
+                for value in values {
+                    if let Some(value) = query_value(key, value)? {
+                        pairs.push((key, value));
+                    }
+                }
+            },
+            value => if let Some(value) = query_value(key, value)? {
+                pairs.push((key, value));
+            },
+        }
+    }
+
+    if pairs.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut()
+            .clear()
+            .extend_pairs(pairs);
+    }
+
+    Ok(url)
+}
+
+fn query(url: &Url) -> LuaMap {
+    let mut params = BTreeMap::<_, Vec<_>>::new();
+    for (key, value) in url.query_pairs() {
+        params.entry(key.into_owned())
+            .or_default()
+            .push(value.into_owned());
+    }
+
+    let mut map = LuaMap::new();
+    for (key, mut values) in params {
+        if values.len() == 1 {
+            map.insert_str(key, values.remove(0));
+        } else {
+            let mut list = LuaList::new();
+            for value in values {
+                list.push_str(value);
+            }
+            map.insert(key, list);
+        }
+    }
+    map
+}
+
 pub fn url_join(lua: &mut hlua::Lua, state: Arc<dyn State>) {
     lua.set("url_join", hlua::function2(move |base: String, update: String| -> Result<String> {
         let base = Url::parse(&base)
@@ -59,6 +129,34 @@ pub fn url_parse(lua: &mut hlua::Lua, state: Arc<dyn State>) {
     }))
 }
 
+pub fn url_set_query(lua: &mut hlua::Lua, state: Arc<dyn State>) {
+    lua.set("url_set_query", hlua::function2(move |url: String, params: AnyLuaValue| -> Result<String> {
+        let url = Url::parse(&url)
+            .map_err(|err| state.set_error(Error::from(err)))?;
+        let params: LuaJsonValue = params.into();
+        set_query(url, params.into())
+            .map_err(|err| state.set_error(err))
+            .map(String::from)
+    }))
+}
+
+pub fn url_query(lua: &mut hlua::Lua, state: Arc<dyn State>) {
+    lua.set("url_query", hlua::function1(move |url: String| -> Result<AnyLuaValue> {
+        let url = Url::parse(&url)
+            .map_err(|err| state.set_error(Error::from(err)))?;
+        Ok(query(&url).into())
+    }))
+}
+
+pub fn url_normalize(lua: &mut hlua::Lua, state: Arc<dyn State>) {
+    lua.set("url_normalize", hlua::function1(move |url: String| -> Result<String> {
+        // serializing a parsed url applies the normalization of the WHATWG url standard
+        let url = Url::parse(&url)
+            .map_err(|err| state.set_error(Error::from(err)))?;
+        Ok(url.into())
+    }))
+}
+
 pub fn url_encode(lua: &mut hlua::Lua, state: Arc<dyn State>) {
     lua.set("url_encode", hlua::function1(move |v: AnyLuaValue| -> Result<String> {
         let v: LuaJsonValue = v.into();
@@ -298,4 +396,60 @@ mod tests {
         "#).expect("Failed to load script");
         script.test().expect("Script failed");
     }
+
+    #[test]
+    fn verify_url_set_query() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            v = url_set_query('https://example.com/search?old=1#top', {
+                q='a b&c=d',
+                page=2,
+                tag={'x', 'y'},
+            })
+            print(v)
+            if v ~= 'https://example.com/search?page=2&q=a+b%26c%3Dd&tag=x&tag=y#top' then
+                return 'wrong value'
+            end
+
+            v = url_set_query('https://example.com/?a=1', {})
+            if v ~= 'https://example.com/' then return 'query not removed' end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_url_query() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            v = url_query('https://example.com/?q=a+b%26c&x=1&x=2&empty=')
+            print(v)
+            if v['q'] ~= 'a b&c' then return 'wrong q' end
+            if v['x'][1] ~= '1' or v['x'][2] ~= '2' then return 'wrong x' end
+            if v['empty'] ~= '' then return 'wrong empty' end
+
+            v = url_query('https://example.com/')
+            if v['q'] ~= nil or v['x'] ~= nil then return 'expected empty table' end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_url_normalize() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            v = url_normalize('HTTPS://Example.COM:443/a/./b/../c?x=%41 b')
+            print(v)
+            if v ~= 'https://example.com/a/c?x=%41%20b' then return 'wrong value: ' .. v end
+
+            v = url_normalize('http://example.com:8080')
+            if v ~= 'http://example.com:8080/' then return 'wrong port: ' .. v end
+
+            v = url_normalize('https://bücher.example/ä')
+            if v ~= 'https://xn--bcher-kva.example/%C3%A4' then return 'wrong idna: ' .. v end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
 }
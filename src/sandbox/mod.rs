@@ -1,6 +1,9 @@
 use crate::errors::*;
+use crate::engine::Module;
 #[cfg(target_os = "linux")]
 use caps::{self, CapSet};
+use sn0int_common::metadata::Capability;
+use strum_macros::{EnumString, IntoStaticStr};
 
 #[cfg(target_os = "openbsd")]
 use pledge::pledge;
@@ -13,6 +16,46 @@ pub mod seccomp;
 #[cfg(target_os = "linux")]
 static CHROOT: &str = "/var/empty";
 
+/// Selected with `run --sandbox`, compat is meant for debugging modules that
+/// break with a tighter profile
+#[derive(Debug, Clone, Copy, PartialEq, EnumString, IntoStaticStr)]
+#[strum(serialize_all = "kebab_case")]
+pub enum SandboxMode {
+    Strict,
+    Compat,
+}
+
+/// The syscalls a module child is allowed to use
+#[derive(Debug, Clone, Copy, PartialEq, EnumString, IntoStaticStr)]
+#[strum(serialize_all = "kebab_case")]
+pub enum Profile {
+    /// Everything a module could need, this is the only profile in compat mode
+    Network,
+    /// The module declared `Egress: none` or no capability that needs the
+    /// network, no socket syscalls at all
+    Offline,
+}
+
+impl Profile {
+    pub fn for_module(module: &Module, mode: SandboxMode) -> Profile {
+        if mode != SandboxMode::Strict {
+            return Profile::Network;
+        }
+
+        let capabilities = Capability::declared_or_default(module.declared_capabilities());
+        if module.egress() == Some(&[]) || !capabilities.iter().any(Capability::uses_network) {
+            Profile::Offline
+        } else {
+            Profile::Network
+        }
+    }
+
+    #[inline]
+    pub fn as_str(&self) -> &'static str {
+        self.into()
+    }
+}
+
 
 #[cfg(target_os = "linux")]
 /// Drop all privileges that are only needed to setup the sandbox
@@ -30,18 +73,21 @@ pub fn fasten_seatbelt() -> Result<()> {
     Ok(())
 }
 
-pub fn init() -> Result<()> {
+pub fn init(profile: Profile) -> Result<()> {
     #[cfg(target_os = "linux")]
-    init_linux()?;
+    init_linux(profile)?;
 
     #[cfg(target_os = "openbsd")]
-    init_openbsd()?;
+    init_openbsd(profile)?;
+
+    #[cfg(not(any(target_os = "linux", target_os = "openbsd")))]
+    let _ = profile;
 
     Ok(())
 }
 
 #[cfg(target_os = "linux")]
-pub fn init_linux() -> Result<()> {
+pub fn init_linux(profile: Profile) -> Result<()> {
     if let Err(err) = nix::unistd::chroot(CHROOT) {
         // TODO: add setting to make this a hard fail
         warn!("Failed to chroot: {:?}", err);
@@ -53,13 +99,13 @@ pub fn init_linux() -> Result<()> {
     fasten_seatbelt()?;
 
     #[cfg(target_os = "linux")]
-    seccomp::init()?;
+    seccomp::init(profile)?;
 
     Ok(())
 }
 
 #[cfg(target_os = "openbsd")]
-pub fn init_openbsd() -> Result<()> {
+pub fn init_openbsd(profile: Profile) -> Result<()> {
     unveil("/etc/resolv.conf", "r")
         .map_err(|_| format_err!("Failed to call unveil"))?;
 
@@ -70,7 +116,39 @@ pub fn init_openbsd() -> Result<()> {
     unveil("", "")
         .map_err(|_| format_err!("Failed to call unveil"))?;
 
-    pledge![Stdio Rpath Dns Inet,]?;
+    match profile {
+        Profile::Network => pledge![Stdio Rpath Dns Inet,]?,
+        Profile::Offline => pledge![Stdio Rpath,]?,
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module(header: &str) -> Module {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.lua");
+        let code = format!("-- Description: test\n-- Version: 0.1.0\n{}-- License: GPL-3.0\n\nfunction run() end\n", header);
+        std::fs::write(&path, code).unwrap();
+        Module::load(&path, "anonymous", "test", false).unwrap()
+    }
+
+    #[test]
+    fn test_profile_for_module() {
+        assert_eq!(Profile::for_module(&module(""), SandboxMode::Strict), Profile::Network);
+        assert_eq!(Profile::for_module(&module("-- Egress: example.com\n"), SandboxMode::Strict), Profile::Network);
+        assert_eq!(Profile::for_module(&module("-- Egress: none\n"), SandboxMode::Strict), Profile::Offline);
+        assert_eq!(Profile::for_module(&module("-- Egress: none\n"), SandboxMode::Compat), Profile::Network);
+    }
+
+    #[test]
+    fn test_profile_for_capabilities() {
+        assert_eq!(Profile::for_module(&module("-- Capabilities: blob, notify\n"), SandboxMode::Strict), Profile::Offline);
+        assert_eq!(Profile::for_module(&module("-- Capabilities: none\n"), SandboxMode::Strict), Profile::Offline);
+        assert_eq!(Profile::for_module(&module("-- Capabilities: blob, socket\n"), SandboxMode::Strict), Profile::Network);
+        assert_eq!(Profile::for_module(&module("-- Capabilities: stdin\n"), SandboxMode::Compat), Profile::Network);
+    }
+}
@@ -1,9 +1,142 @@
 use crate::errors::*;
+use crate::sandbox::Profile;
+use std::sync::OnceLock;
 use syscallz::{Context, Syscall, Action};
+#[cfg(target_arch = "x86")]
+use syscallz::{Cmp, Comparator};
 
+/// The socketcall operations from linux/net.h that the Network profile
+/// allows, anything else (like socket or connect) would bypass the broker
+#[cfg(target_arch = "x86")]
+static SOCKETCALL_OPS: &[u64] = &[
+    7,  // SYS_GETPEERNAME
+    9,  // SYS_SEND
+    10, // SYS_RECV
+    11, // SYS_SENDTO
+    12, // SYS_RECVFROM
+    14, // SYS_SETSOCKOPT
+    15, // SYS_GETSOCKOPT
+    17, // SYS_RECVMSG
+];
 
-pub fn init() -> Result<()> {
-    let mut ctx = Context::init()?;
+/// The socketcall operations the quic client uses on its udp socket
+#[cfg(all(target_arch = "x86", feature = "http3"))]
+static SOCKETCALL_OPS_HTTP3: &[u64] = &[
+    6,  // SYS_GETSOCKNAME
+    16, // SYS_SENDMSG
+    19, // SYS_RECVMMSG
+    20, // SYS_SENDMMSG
+];
+
+/// Syscalls that are looked up by name when the sandbox is violated, anything
+/// else is reported by number
+static KNOWN_SYSCALLS: &[&str] = &[
+    "accept", "accept4", "access", "bind", "chdir", "chroot", "connect",
+    "eventfd2", "execve", "execveat", "faccessat", "faccessat2", "fork",
+    "getegid", "geteuid", "getgid", "getpeername", "getpid", "getresgid",
+    "getresuid", "getrlimit", "getsockname", "getsockopt", "gettid", "getuid",
+    "inotify_init1", "kill", "listen", "lstat", "mkdir", "mount",
+    "newfstatat", "open", "openat", "pidfd_open", "prlimit64", "ptrace",
+    "readlink", "readlinkat", "recv", "recvfrom", "recvmsg", "rename",
+    "sched_getparam", "send", "sendmsg", "sendto", "setgid", "setsockopt",
+    "setuid", "shutdown", "socket", "socketcall", "socketpair", "stat",
+    "sysinfo", "tgkill", "timerfd_create", "unlink", "vfork", "wait4",
+];
+
+static SYSCALL_NAMES: OnceLock<Vec<(i32, &'static str)>> = OnceLock::new();
+static PROFILE: OnceLock<&'static str> = OnceLock::new();
+
+/// The part of `siginfo_t` that's filled in for SIGSYS
+#[repr(C)]
+struct SigSysInfo {
+    si_signo: libc::c_int,
+    si_errno: libc::c_int,
+    si_code: libc::c_int,
+    call_addr: *mut libc::c_void,
+    syscall: libc::c_int,
+    arch: libc::c_uint,
+}
+
+struct Buf {
+    data: [u8; 256],
+    len: usize,
+}
+
+impl Buf {
+    fn push(&mut self, s: &[u8]) {
+        for b in s {
+            if self.len < self.data.len() {
+                self.data[self.len] = *b;
+                self.len += 1;
+            }
+        }
+    }
+
+    fn push_num(&mut self, mut n: u32) {
+        let mut digits = [0u8; 10];
+        let mut i = digits.len();
+        loop {
+            i -= 1;
+            digits[i] = b'0' + (n % 10) as u8;
+            n /= 10;
+            if n == 0 {
+                break;
+            }
+        }
+        self.push(&digits[i..]);
+    }
+}
+
+/// Report the blocked syscall to the parent and exit, this runs inside of a
+/// signal handler so it must not allocate or take any locks
+extern "C" fn on_sigsys(_signal: libc::c_int, info: *mut libc::siginfo_t, _ctx: *mut libc::c_void) {
+    let syscall = unsafe { (*(info as *const SigSysInfo)).syscall };
+
+    let mut buf = Buf {
+        data: [0; 256],
+        len: 0,
+    };
+    buf.push(br#"{"Log":{"Error":"Sandbox killed the module for calling a blocked syscall: "#);
+    match SYSCALL_NAMES.get().and_then(|names| names.iter().find(|(nr, _)| *nr == syscall)) {
+        Some((_, name)) => buf.push(name.as_bytes()),
+        None => {
+            buf.push(b"#");
+            buf.push_num(syscall as u32);
+        },
+    }
+    buf.push(b" (");
+    buf.push(PROFILE.get().unwrap_or(&"").as_bytes());
+    buf.push(b" profile)\"}}\n");
+
+    unsafe {
+        libc::write(libc::STDOUT_FILENO, buf.data.as_ptr() as *const libc::c_void, buf.len);
+        libc::_exit(128 + libc::SIGSYS);
+    }
+}
+
+fn install_handler(profile: Profile) -> Result<()> {
+    let names = KNOWN_SYSCALLS.iter()
+        .filter_map(|name| Syscall::from_name(name).map(|s| (s.into_i32(), *name)))
+        .collect();
+    let _ = SYSCALL_NAMES.set(names);
+    let _ = PROFILE.set(profile.as_str());
+
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_sigsys as *const () as usize;
+        action.sa_flags = libc::SA_SIGINFO;
+        if libc::sigaction(libc::SIGSYS, &action, std::ptr::null_mut()) != 0 {
+            bail!("Failed to install SIGSYS handler");
+        }
+    }
+
+    Ok(())
+}
+
+pub fn init(profile: Profile) -> Result<()> {
+    info!("Loading {} seccomp profile", profile.as_str());
+    install_handler(profile)?;
+    let mut ctx = Context::init_with_action(Action::Trap)?;
 
     ctx.allow_syscall(Syscall::read)?;
     ctx.allow_syscall(Syscall::write)?;
@@ -25,23 +158,11 @@ pub fn init() -> Result<()> {
     ctx.allow_syscall(Syscall::pipe2)?;
     ctx.allow_syscall(Syscall::epoll_ctl)?;
     ctx.allow_syscall(Syscall::sched_getaffinity)?;
-    ctx.allow_syscall(Syscall::socket)?;
-    ctx.allow_syscall(Syscall::connect)?;
-    #[cfg(target_arch = "x86")]
-    ctx.allow_syscall(Syscall::socketcall)?;
     #[cfg(not(any(target_arch = "aarch64", target_arch = "riscv64")))]
     ctx.allow_syscall(Syscall::epoll_wait)?;
     ctx.allow_syscall(Syscall::epoll_pwait)?;
     ctx.allow_syscall(Syscall::getrandom)?;
-    ctx.allow_syscall(Syscall::bind)?;
     ctx.allow_syscall(Syscall::ioctl)?;
-    #[cfg(target_arch = "arm")]
-    ctx.allow_syscall(Syscall::send)?;
-    ctx.allow_syscall(Syscall::sendto)?;
-    #[cfg(target_arch = "arm")]
-    ctx.allow_syscall(Syscall::recv)?;
-    ctx.allow_syscall(Syscall::recvfrom)?;
-    ctx.allow_syscall(Syscall::getsockopt)?;
     #[cfg(not(target_arch = "arm"))]
     ctx.allow_syscall(Syscall::mmap)?;
     #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "riscv64")))]
@@ -52,7 +173,6 @@ pub fn init() -> Result<()> {
     ctx.allow_syscall(Syscall::set_robust_list)?;
     ctx.allow_syscall(Syscall::prctl)?;
     ctx.allow_syscall(Syscall::sched_yield)?;
-    ctx.allow_syscall(Syscall::setsockopt)?;
     ctx.allow_syscall(Syscall::madvise)?;
     ctx.allow_syscall(Syscall::rt_sigaction)?;
     ctx.allow_syscall(Syscall::rseq)?;
@@ -69,7 +189,6 @@ pub fn init() -> Result<()> {
     ctx.allow_syscall(Syscall::exit_group)?;
     ctx.allow_syscall(Syscall::brk)?;
     ctx.allow_syscall(Syscall::rt_sigprocmask)?;
-    ctx.allow_syscall(Syscall::getpeername)?;
     ctx.allow_syscall(Syscall::gettimeofday)?;
     ctx.allow_syscall(Syscall::membarrier)?;
     ctx.allow_syscall(Syscall::statx)?;
@@ -78,6 +197,43 @@ pub fn init() -> Result<()> {
     #[cfg(target_arch = "arm")]
     ctx.allow_syscall(Syscall::_llseek)?;
     ctx.allow_syscall(Syscall::clone3)?;
+    ctx.allow_syscall(Syscall::gettid)?;
+
+    // sockets and dns lookups are opened by the parent, the child only uses
+    // the connections it has been given
+    if profile == Profile::Network {
+        #[cfg(target_arch = "x86")]
+        for op in SOCKETCALL_OPS {
+            let call = Comparator::new(0, Cmp::Eq, *op, None);
+            ctx.set_rule_for_syscall(Action::Allow, Syscall::socketcall, &[call])?;
+        }
+        #[cfg(target_arch = "arm")]
+        ctx.allow_syscall(Syscall::send)?;
+        ctx.allow_syscall(Syscall::sendto)?;
+        #[cfg(target_arch = "arm")]
+        ctx.allow_syscall(Syscall::recv)?;
+        ctx.allow_syscall(Syscall::recvfrom)?;
+        ctx.allow_syscall(Syscall::recvmsg)?;
+        ctx.allow_syscall(Syscall::getsockopt)?;
+        ctx.allow_syscall(Syscall::setsockopt)?;
+        ctx.allow_syscall(Syscall::getpeername)?;
+
+        // quic sends batches of datagrams with control messages and the
+        // runtime of the quic client wakes itself up with an eventfd
+        #[cfg(feature = "http3")]
+        {
+            #[cfg(target_arch = "x86")]
+            for op in SOCKETCALL_OPS_HTTP3 {
+                let call = Comparator::new(0, Cmp::Eq, *op, None);
+                ctx.set_rule_for_syscall(Action::Allow, Syscall::socketcall, &[call])?;
+            }
+            ctx.allow_syscall(Syscall::getsockname)?;
+            ctx.allow_syscall(Syscall::sendmsg)?;
+            ctx.allow_syscall(Syscall::sendmmsg)?;
+            ctx.allow_syscall(Syscall::recvmmsg)?;
+            ctx.allow_syscall(Syscall::eventfd2)?;
+        }
+    }
 
     ctx.set_action_for_syscall(Action::Errno(1), Syscall::openat)?;
     #[cfg(not(any(target_arch = "aarch64", target_arch = "riscv64")))]
@@ -0,0 +1,547 @@
+use crate::config::Config;
+use crate::db::Filter;
+use crate::errors::*;
+use crate::notify::NotificationConfig;
+use crate::options::Opt;
+use crate::paths;
+use crate::term;
+use crate::workspaces::Workspace;
+use chrono::{NaiveDateTime, Utc};
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::result;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The scheduler wakes up at least this often, even if no job is due
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// How often a job runs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Interval(Duration);
+
+impl Interval {
+    #[inline]
+    pub fn as_duration(&self) -> chrono::Duration {
+        chrono::Duration::from_std(self.0).expect("Interval out of range")
+    }
+}
+
+impl FromStr for Interval {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Interval> {
+        let interval = Filter::parse_age(s)
+            .and_then(|x| x.to_std().ok())
+            .filter(|x| !x.is_zero())
+            .ok_or_else(|| format_err!("Invalid interval {:?}, expected something like 90s, 15m or 12h", s))?;
+        Ok(Interval(interval))
+    }
+}
+
+impl Serialize for Interval {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_str(&format!("{}s", self.0.as_secs()))
+    }
+}
+
+impl<'de> Deserialize<'de> for Interval {
+    fn deserialize<D>(deserializer: D) -> result::Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let s = String::deserialize(deserializer)?;
+        FromStr::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct SchedulerConfig {
+    /// How many jobs may run at the same time
+    #[serde(default="default_parallel")]
+    pub parallel: usize,
+    #[serde(default)]
+    pub jobs: HashMap<String, JobConfig>,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> SchedulerConfig {
+        SchedulerConfig {
+            parallel: default_parallel(),
+            jobs: HashMap::new(),
+        }
+    }
+}
+
+fn default_parallel() -> usize {
+    1
+}
+
+impl SchedulerConfig {
+    pub fn validate(&self, notifications: &HashMap<String, NotificationConfig>) -> Result<()> {
+        if self.parallel == 0 {
+            bail!("scheduler.parallel needs to be at least 1");
+        }
+        for (name, job) in &self.jobs {
+            job.validate(notifications)
+                .context(format_err!("Invalid scheduler job {:?}", name))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct JobConfig {
+    #[serde(default="default_workspace")]
+    pub workspace: String,
+    pub module: String,
+    /// A filter like in select for the entities the module runs on
+    pub filter: Option<String>,
+    pub interval: Interval,
+    #[serde(default)]
+    pub options: Vec<Opt>,
+    /// The notification config that is used if the job fails, otherwise the
+    /// failure is routed like any other notification
+    pub notify: Option<String>,
+}
+
+fn default_workspace() -> String {
+    String::from("default")
+}
+
+impl JobConfig {
+    fn validate(&self, notifications: &HashMap<String, NotificationConfig>) -> Result<()> {
+        Workspace::from_str(&self.workspace)?;
+        if self.module.is_empty() {
+            bail!("Module can't be empty");
+        }
+        if let Some(filter) = &self.filter {
+            Filter::parse_expr(None, filter)?;
+        }
+        if let Some(notify) = &self.notify {
+            if !notifications.contains_key(notify) {
+                return Err(NotFoundError(format!("Notification config {:?} doesn't exist", notify)).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// The arguments to run the job with `sn0int run`
+    fn args(&self) -> Vec<String> {
+        let mut args = vec![
+            String::from("-w"),
+            self.workspace.clone(),
+            String::from("run"),
+            self.module.clone(),
+            String::from("--output=json"),
+            String::from("--no-prompt"),
+        ];
+        if let Some(filter) = &self.filter {
+            args.push(String::from("--target"));
+            args.push(format!("where {}", filter));
+        }
+        for option in &self.options {
+            args.push(String::from("-o"));
+            args.push(option.to_string());
+        }
+        args
+    }
+}
+
+/// What happened during the last runs of a job, kept across restarts
+#[derive(Debug, Default, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct JobState {
+    pub last_run: Option<NaiveDateTime>,
+    pub last_finished: Option<NaiveDateTime>,
+    /// The summary of the last successful run
+    pub last_summary: Option<String>,
+    pub last_error: Option<String>,
+    /// Failed runs since the last successful one
+    #[serde(default)]
+    pub failures: usize,
+}
+
+impl JobState {
+    /// Jobs that never ran are due right away
+    pub fn next_run(&self, interval: Interval) -> Option<NaiveDateTime> {
+        self.last_run.map(|last_run| last_run + interval.as_duration())
+    }
+
+    pub fn is_due(&self, interval: Interval, now: NaiveDateTime) -> bool {
+        self.next_run(interval).is_none_or(|next| next <= now)
+    }
+}
+
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct State {
+    #[serde(default)]
+    pub jobs: BTreeMap<String, JobState>,
+}
+
+impl State {
+    #[inline]
+    fn path() -> Result<PathBuf> {
+        let path = paths::sn0int_dir()?;
+        Ok(path.join("scheduler.json"))
+    }
+
+    pub fn load() -> Result<State> {
+        let path = State::path()?;
+        if !path.exists() {
+            return Ok(State::default());
+        }
+        let buf = fs::read(&path)
+            .context(format_err!("Failed to read {:?}", path))?;
+        let state = serde_json::from_slice(&buf)
+            .context(format_err!("Failed to parse {:?}", path))?;
+        Ok(state)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let state = serde_json::to_string(&self)?;
+        fs::write(State::path()?, state)
+            .context("Failed to write scheduler state")?;
+        Ok(())
+    }
+
+    /// A scheduler that can't write its state keeps running the jobs, a
+    /// restart may run them early
+    fn save_or_warn(&self) {
+        if let Err(err) = self.save() {
+            term::warn(&format!("{:#}", err));
+        }
+    }
+
+    #[inline]
+    pub fn job(&self, name: &str) -> JobState {
+        self.jobs.get(name).cloned().unwrap_or_default()
+    }
+}
+
+/// The outcome of a run, collected from the events it printed
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    pub targets: usize,
+    pub inserted: usize,
+    pub updated: usize,
+    pub errors: usize,
+    finished: bool,
+}
+
+impl Summary {
+    fn read<R: BufRead>(reader: R) -> Result<Summary> {
+        let mut summary = Summary::default();
+        for line in reader.lines() {
+            let line = line?;
+            let event = match serde_json::from_str::<serde_json::Value>(&line) {
+                Ok(event) => event,
+                Err(_) => continue,
+            };
+            match event["event"].as_str() {
+                Some("entity-inserted") => summary.inserted += 1,
+                Some("entity-updated") => summary.updated += 1,
+                Some("run-finished") => {
+                    summary.targets = event["targets"].as_u64().unwrap_or_default() as usize;
+                    summary.errors = event["errors"].as_u64().unwrap_or_default() as usize;
+                    summary.finished = true;
+                },
+                _ => (),
+            }
+        }
+        Ok(summary)
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, w: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(w, "{} targets, {} inserted, {} updated", self.targets, self.inserted, self.updated)?;
+        if self.errors > 0 {
+            write!(w, ", {} errors", self.errors)?;
+        }
+        Ok(())
+    }
+}
+
+/// The last line that was printed to stderr, usually the error of a run
+/// that failed
+fn last_line<R: Read>(reader: R) -> Option<String> {
+    BufReader::new(reader)
+        .lines()
+        .map_while(|line| line.ok())
+        .filter(|line| !line.trim().is_empty())
+        .last()
+}
+
+/// A run fails if the module couldn't be started or if it failed on any of
+/// its targets
+fn execute(exe: &Path, job: &JobConfig) -> Result<Summary> {
+    let mut child = Command::new(exe)
+        .args(job.args())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to start sn0int run")?;
+
+    let stderr = child.stderr.take()
+        .ok_or_else(|| format_err!("Failed to capture stderr"))?;
+    let stderr = thread::spawn(move || last_line(stderr));
+
+    let stdout = child.stdout.take()
+        .ok_or_else(|| format_err!("Failed to capture stdout"))?;
+    let summary = Summary::read(BufReader::new(stdout));
+
+    let status = child.wait()?;
+    let stderr = stderr.join()
+        .map_err(|_| format_err!("Failed to read stderr"))?;
+    let summary = summary?;
+
+    if !status.success() || !summary.finished {
+        let error = stderr.as_deref()
+            .map(|line| line.trim_start_matches("Error: ").to_string())
+            .unwrap_or_else(|| format!("sn0int run exited with {}", status));
+        bail!("{}", error);
+    }
+    if summary.errors > 0 {
+        bail!("{} of {} targets failed", summary.errors, summary.targets);
+    }
+    Ok(summary)
+}
+
+/// Failures are sent with `sn0int notify send`, so they are routed and
+/// delivered in the workspace of the job
+fn notify_failure(exe: &Path, name: &str, job: &JobConfig, error: &str) -> Result<()> {
+    let mut cmd = Command::new(exe);
+    cmd.args(["-w", &job.workspace, "notify", "send"]);
+    if let Some(route) = &job.notify {
+        cmd.arg("--route").arg(route);
+    }
+    let status = cmd.arg(format!("scheduler:{}:failed", name))
+        .arg(format!("Scheduled job {:?} failed", name))
+        .arg(error)
+        .stdin(Stdio::null())
+        .status()
+        .context("Failed to run sn0int notify send")?;
+    if !status.success() {
+        bail!("sn0int notify send exited with {}", status);
+    }
+    Ok(())
+}
+
+fn finish(state: &mut State, exe: &Path, name: &str, job: &JobConfig, started: Instant, result: Result<Summary>) {
+    let entry = state.jobs.entry(name.to_string()).or_default();
+    entry.last_finished = Some(Utc::now().naive_utc());
+    let elapsed = started.elapsed();
+
+    match result {
+        Ok(summary) => {
+            term::success(&format!("Job {:?} finished after {:.2?}: {}", name, elapsed, summary));
+            entry.last_summary = Some(summary.to_string());
+            entry.last_error = None;
+            entry.failures = 0;
+        },
+        Err(err) => {
+            let error = format!("{:#}", err);
+            term::error(&format!("Job {:?} failed after {:.2?}: {}", name, elapsed, error));
+            entry.last_error = Some(error.clone());
+            entry.failures += 1;
+            if let Err(err) = notify_failure(exe, name, job, &error) {
+                term::warn(&format!("Failed to send notification for job {:?}: {:#}", name, err));
+            }
+        },
+    }
+    state.save_or_warn();
+}
+
+/// Held while a scheduler runs, a second one would run the same jobs and
+/// overwrite the state of the first. The lock is released by the os when the
+/// file is closed, even if the process has been killed.
+fn lock(path: &Path) -> Result<File> {
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(path)
+        .context(format_err!("Failed to open {:?}", path))?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => bail!("Another scheduler is already running"),
+        Err(TryLockError::Error(err)) => Err(err).context(format_err!("Failed to lock {:?}", path))?,
+    }
+}
+
+/// Run jobs whenever they are due, until the process is stopped. With `once`
+/// every job that is due runs a single time and the scheduler exits after.
+pub fn run(config: &Config, once: bool) -> Result<()> {
+    let scheduler = &config.scheduler;
+    if scheduler.jobs.is_empty() {
+        bail!("No jobs are configured, add them to the [scheduler.jobs] section of the config");
+    }
+    let exe = std::env::current_exe()
+        .context("Failed to find sn0int executable")?;
+    let _lock = lock(&paths::sn0int_dir()?.join("scheduler.lock"))?;
+    let mut state = State::load()?;
+
+    let mut names = scheduler.jobs.keys().cloned().collect::<Vec<_>>();
+    names.sort();
+    term::info(&format!("Scheduling {} jobs, running up to {} at the same time", names.len(), scheduler.parallel));
+
+    let (tx, rx) = channel::unbounded();
+    let mut running = HashMap::<String, Instant>::new();
+    let mut done = HashSet::new();
+
+    loop {
+        let now = Utc::now().naive_utc();
+        for name in &names {
+            if running.len() >= scheduler.parallel {
+                break;
+            }
+            let job = &scheduler.jobs[name];
+            if running.contains_key(name) || done.contains(name) || !state.job(name).is_due(job.interval, now) {
+                continue;
+            }
+
+            // saved before the job runs, so a restart doesn't run it twice
+            state.jobs.entry(name.clone()).or_default().last_run = Some(now);
+            state.save_or_warn();
+            term::info(&format!("Starting job {:?}: {} in {:?}", name, job.module, job.workspace));
+            info!("Starting job {:?}: {:?}", name, job.args());
+
+            running.insert(name.clone(), Instant::now());
+            let tx = tx.clone();
+            let exe = exe.clone();
+            let job = job.clone();
+            let name = name.clone();
+            thread::spawn(move || {
+                let result = execute(&exe, &job);
+                tx.send((name, result)).ok();
+            });
+        }
+
+        if once {
+            let pending = names.iter()
+                .any(|name| !done.contains(name) && state.job(name).is_due(scheduler.jobs[name].interval, now));
+            if running.is_empty() && !pending {
+                break;
+            }
+        }
+
+        let sleep = names.iter()
+            .filter_map(|name| state.job(name).next_run(scheduler.jobs[name].interval))
+            .min()
+            .and_then(|next| (next - now).to_std().ok())
+            .unwrap_or(MAX_SLEEP)
+            .clamp(Duration::from_secs(1), MAX_SLEEP);
+
+        if let Ok((name, result)) = rx.recv_timeout(sleep) {
+            let started = running.remove(&name)
+                .unwrap_or_else(Instant::now);
+            if once {
+                done.insert(name.clone());
+            }
+            finish(&mut state, &exe, &name, &scheduler.jobs[&name], started, result);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn job() -> JobConfig {
+        toml::from_str(r#"
+workspace = "example"
+module = "kpcyrd/ctlogs"
+filter = "value like %.example.com"
+interval = "6h"
+options = ["foo=bar"]
+"#).unwrap()
+    }
+
+    #[test]
+    fn test_interval() {
+        assert_eq!("90s".parse::<Interval>().unwrap(), Interval(Duration::from_secs(90)));
+        assert_eq!("12h".parse::<Interval>().unwrap(), Interval(Duration::from_secs(12 * 3600)));
+        assert!("0s".parse::<Interval>().is_err());
+        assert!("daily".parse::<Interval>().is_err());
+    }
+
+    #[test]
+    fn test_args() {
+        assert_eq!(job().args(), vec![
+            "-w", "example", "run", "kpcyrd/ctlogs", "--output=json", "--no-prompt",
+            "--target", "where value like %.example.com",
+            "-o", "foo=bar",
+        ]);
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut notifications = HashMap::new();
+        let mut job = job();
+        assert!(job.validate(&notifications).is_ok());
+
+        job.notify = Some("ops".to_string());
+        assert!(job.validate(&notifications).is_err());
+        notifications.insert("ops".to_string(), NotificationConfig::default());
+        assert!(job.validate(&notifications).is_ok());
+
+        job.filter = Some("value = 'unbalanced".to_string());
+        assert!(job.validate(&notifications).is_err());
+    }
+
+    #[test]
+    fn test_is_due() {
+        let interval = "1h".parse::<Interval>().unwrap();
+        let now = NaiveDateTime::from_str("2020-03-14T16:20:23").unwrap();
+        assert!(JobState::default().is_due(interval, now));
+
+        let state = JobState {
+            last_run: Some(NaiveDateTime::from_str("2020-03-14T15:30:00").unwrap()),
+            ..Default::default()
+        };
+        assert!(!state.is_due(interval, now));
+        assert_eq!(state.next_run(interval), Some(NaiveDateTime::from_str("2020-03-14T16:30:00").unwrap()));
+        assert!(state.is_due(interval, NaiveDateTime::from_str("2020-03-14T16:30:00").unwrap()));
+    }
+
+    #[test]
+    fn test_summary() {
+        let output = [
+            r#"{"version":1,"time":"2020-03-14T16:20:23+00:00","event":"run-started","module":"kpcyrd/ctlogs","module_version":"0.1.0","workspace":"example","targets":2}"#,
+            r#"{"version":1,"time":"2020-03-14T16:20:24+00:00","event":"entity-inserted","family":"subdomain","value":"www.example.com"}"#,
+            r#"{"version":1,"time":"2020-03-14T16:20:24+00:00","event":"entity-updated","family":"subdomain","value":"api.example.com","changes":""}"#,
+            r#"{"version":1,"time":"2020-03-14T16:20:25+00:00","event":"run-finished","module":"kpcyrd/ctlogs","targets":2,"errors":1,"offline":0}"#,
+        ].join("\n");
+        let summary = Summary::read(Cursor::new(output)).unwrap();
+        assert_eq!(summary, Summary {
+            targets: 2,
+            inserted: 1,
+            updated: 1,
+            errors: 1,
+            finished: true,
+        });
+        assert_eq!(summary.to_string(), "2 targets, 1 inserted, 1 updated, 1 errors");
+    }
+
+    #[test]
+    fn test_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("scheduler.lock");
+        let first = lock(&path).unwrap();
+        let err = lock(&path).err().unwrap();
+        assert_eq!(err.to_string(), "Another scheduler is already running");
+        drop(first);
+        lock(&path).unwrap();
+    }
+}
@@ -12,6 +12,9 @@ table! {
         phonenumber -> Nullable<Text>,
         profile_pic -> Nullable<Text>,
         birthday -> Nullable<Text>,
+        seen_count -> Integer,
+        seen_first -> Nullable<Timestamp>,
+        seen_last -> Nullable<Timestamp>,
     }
 }
 
@@ -51,6 +54,9 @@ table! {
         id -> Integer,
         value -> Text,
         unscoped -> Bool,
+        seen_count -> Integer,
+        seen_first -> Nullable<Timestamp>,
+        seen_last -> Nullable<Timestamp>,
     }
 }
 
@@ -66,6 +72,9 @@ table! {
         last_withdrawal -> Nullable<Timestamp>,
         unscoped -> Bool,
         description -> Nullable<Text>,
+        seen_count -> Integer,
+        seen_first -> Nullable<Timestamp>,
+        seen_last -> Nullable<Timestamp>,
     }
 }
 
@@ -78,6 +87,9 @@ table! {
         vendor -> Nullable<Text>,
         unscoped -> Bool,
         last_seen -> Nullable<Timestamp>,
+        seen_count -> Integer,
+        seen_first -> Nullable<Timestamp>,
+        seen_last -> Nullable<Timestamp>,
     }
 }
 
@@ -86,6 +98,9 @@ table! {
         id -> Integer,
         value -> Text,
         unscoped -> Bool,
+        seen_count -> Integer,
+        seen_first -> Nullable<Timestamp>,
+        seen_last -> Nullable<Timestamp>,
     }
 }
 
@@ -96,6 +111,10 @@ table! {
         unscoped -> Bool,
         valid -> Nullable<Bool>,
         displayname -> Nullable<Text>,
+        seen_count -> Integer,
+        seen_first -> Nullable<Timestamp>,
+        seen_last -> Nullable<Timestamp>,
+        syntax_valid -> Nullable<Bool>,
     }
 }
 
@@ -115,6 +134,9 @@ table! {
         dhash -> Nullable<Text>,
         phash -> Nullable<Text>,
         unscoped -> Bool,
+        seen_count -> Integer,
+        seen_first -> Nullable<Timestamp>,
+        seen_last -> Nullable<Timestamp>,
     }
 }
 
@@ -135,6 +157,9 @@ table! {
         as_org -> Nullable<Text>,
         description -> Nullable<Text>,
         reverse_dns -> Nullable<Text>,
+        seen_count -> Integer,
+        seen_first -> Nullable<Timestamp>,
+        seen_last -> Nullable<Timestamp>,
     }
 }
 
@@ -147,6 +172,28 @@ table! {
         asn -> Nullable<Integer>,
         as_org -> Nullable<Text>,
         description -> Nullable<Text>,
+        seen_count -> Integer,
+        seen_first -> Nullable<Timestamp>,
+        seen_last -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    network_audit (id) {
+        id -> Integer,
+        time -> Timestamp,
+        module -> Nullable<Text>,
+        target -> Nullable<Text>,
+        kind -> Text,
+        host -> Text,
+        port -> Nullable<Integer>,
+        method -> Nullable<Text>,
+        url -> Nullable<Text>,
+        headers -> Nullable<Text>,
+        status -> Nullable<Integer>,
+        bytes -> Nullable<BigInt>,
+        error -> Nullable<Text>,
+        ipaddr -> Nullable<Text>,
     }
 }
 
@@ -168,6 +215,9 @@ table! {
         latitude -> Nullable<Float>,
         longitude -> Nullable<Float>,
         description -> Nullable<Text>,
+        seen_count -> Integer,
+        seen_first -> Nullable<Timestamp>,
+        seen_last -> Nullable<Timestamp>,
     }
 }
 
@@ -186,6 +236,9 @@ table! {
         last_ported -> Nullable<Timestamp>,
         caller_name -> Nullable<Text>,
         caller_type -> Nullable<Text>,
+        seen_count -> Integer,
+        seen_first -> Nullable<Timestamp>,
+        seen_last -> Nullable<Timestamp>,
     }
 }
 
@@ -202,6 +255,45 @@ table! {
         banner -> Nullable<Text>,
         service -> Nullable<Text>,
         version -> Nullable<Text>,
+        seen_count -> Integer,
+        seen_first -> Nullable<Timestamp>,
+        seen_last -> Nullable<Timestamp>,
+        ja3s -> Nullable<Text>,
+        ja4s -> Nullable<Text>,
+    }
+}
+
+table! {
+    provenance (id) {
+        id -> Integer,
+        family -> Text,
+        value -> Text,
+        time -> Timestamp,
+        action -> Text,
+        module -> Nullable<Text>,
+        version -> Nullable<Text>,
+        target -> Nullable<Text>,
+        details -> Nullable<Text>,
+        changes -> Nullable<Text>,
+    }
+}
+
+table! {
+    resolutions (id) {
+        id -> Integer,
+        subdomain_id -> Integer,
+        ip_addr_id -> Integer,
+        first_seen -> Timestamp,
+        last_seen -> Timestamp,
+    }
+}
+
+table! {
+    runs (id) {
+        id -> Integer,
+        module -> Text,
+        version -> Text,
+        started -> Timestamp,
     }
 }
 
@@ -220,6 +312,9 @@ table! {
         value -> Text,
         unscoped -> Bool,
         resolvable -> Nullable<Bool>,
+        seen_count -> Integer,
+        seen_first -> Nullable<Timestamp>,
+        seen_last -> Nullable<Timestamp>,
     }
 }
 
@@ -245,6 +340,17 @@ table! {
         online -> Nullable<Bool>,
         title -> Nullable<Text>,
         redirect -> Nullable<Text>,
+        seen_count -> Integer,
+        seen_first -> Nullable<Timestamp>,
+        seen_last -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    views (id) {
+        id -> Integer,
+        name -> Text,
+        query -> Text,
     }
 }
 
@@ -253,6 +359,8 @@ joinable!(breach_emails -> emails (email_id));
 joinable!(network_devices -> devices (device_id));
 joinable!(network_devices -> networks (network_id));
 joinable!(ports -> ipaddrs (ip_addr_id));
+joinable!(resolutions -> ipaddrs (ip_addr_id));
+joinable!(resolutions -> subdomains (subdomain_id));
 joinable!(subdomain_ipaddrs -> ipaddrs (ip_addr_id));
 joinable!(subdomain_ipaddrs -> subdomains (subdomain_id));
 joinable!(subdomains -> domains (domain_id));
@@ -271,12 +379,17 @@ allow_tables_to_appear_in_same_query!(
     images,
     ipaddrs,
     netblocks,
+    network_audit,
     network_devices,
     networks,
     phonenumbers,
     ports,
+    provenance,
+    resolutions,
+    runs,
     subdomain_ipaddrs,
     subdomains,
     ttls,
     urls,
+    views,
 );
@@ -0,0 +1,347 @@
+use crate::blobs::BlobStorage;
+use crate::cmd::stats_cmd::Stats;
+use crate::db::{Database, Filter, Table};
+use crate::db::tree::Node;
+use crate::errors::*;
+use crate::models::*;
+use crate::serve::http::{Request, Response};
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+use std::str::FromStr;
+
+/// Entities returned by a list request unless `limit` is set
+const DEFAULT_LIMIT: usize = 100;
+/// The largest `limit` that's accepted, larger pages need `offset`
+const MAX_LIMIT: usize = 1000;
+/// How many links are followed for the children of an entity unless `depth`
+/// is set
+const DEFAULT_DEPTH: usize = 1;
+
+/// An entity that's linked below the requested one
+#[derive(Debug, PartialEq, Serialize)]
+struct Child {
+    family: &'static str,
+    value: String,
+    scoped: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    children: Vec<Child>,
+}
+
+impl From<Node> for Child {
+    fn from(node: Node) -> Child {
+        Child {
+            family: node.table.family().as_str(),
+            value: node.value,
+            scoped: node.scoped,
+            children: node.children.into_iter().map(Child::from).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct EntityResponse {
+    entity: serde_json::Value,
+    children: Vec<Child>,
+}
+
+fn load<T: Model + Serialize>(db: &Database, filter: &Filter, offset: usize, limit: usize) -> Result<Vec<serde_json::Value>> {
+    db.filter_range::<T>(filter, offset, limit)?
+        .iter()
+        .map(|entity| serde_json::to_value(entity).map_err(Error::from))
+        .collect()
+}
+
+fn select(db: &Database, table: Table, filter: &Filter, offset: usize, limit: usize) -> Result<Vec<serde_json::Value>> {
+    match table {
+        Table::Domains => load::<Domain>(db, filter, offset, limit),
+        Table::Subdomains => load::<Subdomain>(db, filter, offset, limit),
+        Table::Ipaddrs => load::<IpAddr>(db, filter, offset, limit),
+        Table::Urls => load::<Url>(db, filter, offset, limit),
+        Table::Emails => load::<Email>(db, filter, offset, limit),
+        Table::Phonenumbers => load::<PhoneNumber>(db, filter, offset, limit),
+        Table::Devices => load::<Device>(db, filter, offset, limit),
+        Table::Networks => load::<Network>(db, filter, offset, limit),
+        Table::Accounts => load::<Account>(db, filter, offset, limit),
+        Table::Breaches => load::<Breach>(db, filter, offset, limit),
+        Table::Images => load::<Image>(db, filter, offset, limit),
+        Table::Ports => load::<Port>(db, filter, offset, limit),
+        Table::Netblocks => load::<Netblock>(db, filter, offset, limit),
+        Table::Cryptoaddrs => load::<CryptoAddr>(db, filter, offset, limit),
+        Table::SubdomainIpaddrs | Table::NetworkDevices | Table::BreachEmails => {
+            bail!("{} is a link between entities", table.as_str())
+        },
+    }
+}
+
+/// The tables of entities, the tables that link entities aren't exposed
+fn entity_table(name: &str) -> Option<Table> {
+    match Table::from_str(name).ok()? {
+        Table::SubdomainIpaddrs | Table::NetworkDevices | Table::BreachEmails => None,
+        table => Some(table),
+    }
+}
+
+fn usize_param(req: &Request, key: &str, default: usize) -> std::result::Result<usize, Response> {
+    match req.param(key) {
+        Some(value) => value.parse()
+            .map_err(|_| Response::error(400, &format!("{} needs to be a number", key))),
+        None => Ok(default),
+    }
+}
+
+fn not_found() -> Response {
+    Response::error(404, "Not found")
+}
+
+pub struct Api<'a> {
+    db: &'a Database,
+    blobs: &'a BlobStorage,
+    token: String,
+}
+
+impl<'a> Api<'a> {
+    pub fn new(db: &'a Database, blobs: &'a BlobStorage, token: String) -> Api<'a> {
+        Api {
+            db,
+            blobs,
+            token,
+        }
+    }
+
+    fn authorized(&self, req: &Request) -> bool {
+        match req.bearer() {
+            Some(token) => token.len() == self.token.len()
+                && sodiumoxide::utils::memcmp(token.as_bytes(), self.token.as_bytes()),
+            None => false,
+        }
+    }
+
+    /// Errors of the database end up as a 500, everything the client got
+    /// wrong is answered before that. The error is only logged, the client
+    /// doesn't learn anything about the internals.
+    pub fn handle(&self, req: &Request) -> Response {
+        match self.route(req) {
+            Ok(response) => response,
+            Err(err) => {
+                error!("Failed to serve {:?}: {:#}", req.path, err);
+                Response::error(500, "Internal server error")
+            },
+        }
+    }
+
+    fn route(&self, req: &Request) -> Result<Response> {
+        if req.method != "GET" {
+            return Ok(Response::error(405, "The api is read-only, only GET requests are supported"));
+        }
+        if !self.authorized(req) {
+            return Ok(Response::error(401, "Missing or invalid bearer token"));
+        }
+
+        let path = match req.path.strip_prefix("/api/v1/") {
+            Some(path) => path,
+            None => return Ok(not_found()),
+        };
+        let (resource, value) = match path.split_once('/') {
+            Some((resource, value)) => {
+                let value = match percent_decode_str(value).decode_utf8() {
+                    Ok(value) => value,
+                    Err(_) => return Ok(Response::error(400, "Path is invalid utf-8")),
+                };
+                (resource, Some(value))
+            },
+            None => (path, None),
+        };
+
+        match (resource, value) {
+            ("stats", None) => self.stats(),
+            ("blobs", Some(id)) => self.blob(&id),
+            (resource, value) => match (entity_table(resource), value) {
+                (Some(table), None) => self.list(table, req),
+                (Some(table), Some(value)) => self.entity(table, &value, req),
+                (None, _) => Ok(not_found()),
+            },
+        }
+    }
+
+    fn stats(&self) -> Result<Response> {
+        let mut stats = Stats::count(self.db.name().to_string(), self.db, None)?;
+        stats.add_blob_usage(self.blobs)?;
+        Response::json(&stats)
+    }
+
+    fn blob(&self, id: &str) -> Result<Response> {
+        let path = match self.blobs.join(id) {
+            Ok(path) => path,
+            Err(_) => return Ok(Response::error(400, "Invalid blob id")),
+        };
+        if !path.exists() {
+            return Ok(not_found());
+        }
+        let blob = self.blobs.load(id)?;
+        Ok(Response::bytes(blob.bytes.to_vec()))
+    }
+
+    fn list(&self, table: Table, req: &Request) -> Result<Response> {
+        let filter = match req.param("filter") {
+            Some(filter) => match Filter::parse_expr(Some(table), filter) {
+                Ok(filter) => filter,
+                Err(err) => return Ok(Response::error(400, &format!("Invalid filter: {}", err))),
+            },
+            None => Filter::any(),
+        };
+        let params = usize_param(req, "offset", 0)
+            .and_then(|offset| Ok((offset, usize_param(req, "limit", DEFAULT_LIMIT)?)));
+        let (offset, limit) = match params {
+            Ok(params) => params,
+            Err(response) => return Ok(response),
+        };
+        if limit > MAX_LIMIT {
+            return Ok(Response::error(400, &format!("limit can't be larger than {}", MAX_LIMIT)));
+        }
+
+        let entities = match select(self.db, table, &filter, offset, limit) {
+            Ok(entities) => entities,
+            // the filter is valid syntax, but not for this table
+            Err(err) => return Ok(Response::error(400, &format!("Invalid filter: {}", err))),
+        };
+        Response::json(&entities)
+    }
+
+    fn entity(&self, table: Table, value: &str, req: &Request) -> Result<Response> {
+        let depth = match usize_param(req, "depth", DEFAULT_DEPTH) {
+            Ok(depth) => depth,
+            Err(response) => return Ok(response),
+        };
+
+        let entity = select(self.db, table, &Filter::column_eq("value", value), 0, 1)?
+            .pop();
+        let entity = match entity {
+            Some(entity) => entity,
+            None => return Ok(not_found()),
+        };
+
+        let tree = Node::build(self.db, table, value, depth)?;
+        Response::json(&EntityResponse {
+            entity,
+            children: tree.children.into_iter().map(Child::from).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blobs::Blob;
+
+    fn insert_subdomain(db: &Database, value: &str) {
+        let domain_id = db.insert_generic(Insert::Domain(NewDomain {
+            value: "example.com".to_string(),
+            unscoped: false,
+        })).unwrap().unwrap().1;
+        db.insert_generic(Insert::Subdomain(NewSubdomain {
+            domain_id,
+            value: value.to_string(),
+            resolvable: None,
+            unscoped: false,
+        })).unwrap();
+    }
+
+    fn get(api: &Api, target: &str, token: &str) -> Response {
+        let head = format!("GET {} HTTP/1.1\r\nAuthorization: Bearer {}\r\n\r\n", target, token);
+        let req = Request::parse(head.as_bytes()).unwrap().unwrap();
+        api.handle(&req)
+    }
+
+    fn json(response: &Response) -> serde_json::Value {
+        serde_json::from_slice(response.body()).unwrap()
+    }
+
+    #[test]
+    fn test_auth() {
+        let db = Database::memory().unwrap();
+        let blobs = BlobStorage::new("/nonexistent");
+        let api = Api::new(&db, &blobs, "secret".to_string());
+
+        assert_eq!(get(&api, "/api/v1/domains", "wrong").status, 401);
+        assert_eq!(get(&api, "/api/v1/domains", "secre").status, 401);
+        assert_eq!(get(&api, "/api/v1/domains", "secret").status, 200);
+
+        let req = Request::parse(b"GET /api/v1/domains HTTP/1.1\r\n\r\n").unwrap().unwrap();
+        assert_eq!(api.handle(&req).status, 401);
+        let req = Request::parse(b"DELETE /api/v1/domains HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n").unwrap().unwrap();
+        assert_eq!(api.handle(&req).status, 405);
+    }
+
+    #[test]
+    fn test_list() {
+        let db = Database::memory().unwrap();
+        insert_subdomain(&db, "www.example.com");
+        insert_subdomain(&db, "api.example.com");
+        let blobs = BlobStorage::new("/nonexistent");
+        let api = Api::new(&db, &blobs, "secret".to_string());
+
+        let values = |target: &str| {
+            let response = get(&api, target, "secret");
+            assert_eq!(response.status, 200);
+            json(&response).as_array().unwrap()
+                .iter()
+                .map(|entity| entity["value"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(values("/api/v1/subdomains"), vec!["www.example.com", "api.example.com"]);
+        assert_eq!(values("/api/v1/subdomains?filter=value+like+api.%25"), vec!["api.example.com"]);
+        assert_eq!(values("/api/v1/subdomains?offset=1&limit=5"), vec!["api.example.com"]);
+        assert_eq!(values("/api/v1/subdomains?limit=1"), vec!["www.example.com"]);
+
+        assert_eq!(get(&api, "/api/v1/subdomains?limit=x", "secret").status, 400);
+        assert_eq!(get(&api, "/api/v1/subdomains?limit=1000", "secret").status, 200);
+        assert_eq!(get(&api, "/api/v1/subdomains?limit=1001", "secret").status, 400);
+        assert_eq!(get(&api, "/api/v1/subdomains?filter=%22", "secret").status, 400);
+        assert_eq!(get(&api, "/api/v1/subdomains?filter=asdf+%3D+1", "secret").status, 400);
+        assert_eq!(get(&api, "/api/v1/subdomain_ipaddrs", "secret").status, 404);
+        assert_eq!(get(&api, "/api/v1/nope", "secret").status, 404);
+        assert_eq!(get(&api, "/", "secret").status, 404);
+    }
+
+    #[test]
+    fn test_entity() {
+        let db = Database::memory().unwrap();
+        insert_subdomain(&db, "www.example.com");
+        let blobs = BlobStorage::new("/nonexistent");
+        let api = Api::new(&db, &blobs, "secret".to_string());
+
+        let response = get(&api, "/api/v1/domains/example.com", "secret");
+        assert_eq!(response.status, 200);
+        let body = json(&response);
+        assert_eq!(body["entity"]["value"], "example.com");
+        assert_eq!(body["children"], serde_json::json!([
+            {"family": "subdomain", "value": "www.example.com", "scoped": true},
+        ]));
+
+        assert_eq!(get(&api, "/api/v1/domains/example.org", "secret").status, 404);
+        assert_eq!(get(&api, "/api/v1/domains/example.com?depth=-1", "secret").status, 400);
+    }
+
+    #[test]
+    fn test_stats_and_blobs() {
+        let db = Database::memory().unwrap();
+        insert_subdomain(&db, "www.example.com");
+        let dir = tempfile::tempdir().unwrap();
+        let blobs = BlobStorage::new(dir.path());
+        let blob = Blob::create(bytes::Bytes::from(&b"asdf"[..]));
+        blobs.save(&blob).unwrap();
+        let api = Api::new(&db, &blobs, "secret".to_string());
+
+        let response = get(&api, "/api/v1/stats", "secret");
+        assert_eq!(response.status, 200);
+        let body = json(&response);
+        assert_eq!(body["subdomains"], 1);
+        assert_eq!(body["blobs"]["count"], 1);
+
+        let response = get(&api, &format!("/api/v1/blobs/{}", blob.id), "secret");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body(), b"asdf");
+        assert_eq!(get(&api, "/api/v1/blobs/DTTV3EjpHBNJx3Zw7eJsVPm4bYXKmNkJQpVNkcvTtTSa", "secret").status, 404);
+        assert_eq!(get(&api, "/api/v1/blobs/..%2F..%2Fetc%2Fpasswd", "secret").status, 400);
+    }
+}
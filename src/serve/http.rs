@@ -0,0 +1,194 @@
+use crate::errors::*;
+use serde::Serialize;
+use std::io::{Read, Write};
+
+/// Only requests without a body are served, anything with a longer head is
+/// refused
+const MAX_HEAD: usize = 16 * 1024;
+const MAX_HEADERS: usize = 32;
+
+#[derive(Debug, PartialEq)]
+pub struct Request {
+    pub method: String,
+    /// The path as it was sent, percent-encoded
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    pub authorization: Option<String>,
+}
+
+impl Request {
+    /// Parse the head of a request, returns `None` if it isn't complete yet
+    pub fn parse(buf: &[u8]) -> Result<Option<Request>> {
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut req = httparse::Request::new(&mut headers);
+        if req.parse(buf).context("Invalid http request")?.is_partial() {
+            return Ok(None);
+        }
+
+        let method = req.method
+            .ok_or_else(|| format_err!("Request is missing a method"))?;
+        let target = req.path
+            .ok_or_else(|| format_err!("Request is missing a path"))?;
+        let (path, query) = target.split_once('?')
+            .unwrap_or((target, ""));
+        let query = url::form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect();
+
+        let authorization = req.headers.iter()
+            .find(|header| header.name.eq_ignore_ascii_case("authorization"))
+            .map(|header| String::from_utf8_lossy(header.value).into_owned());
+
+        Ok(Some(Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            query,
+            authorization,
+        }))
+    }
+
+    pub fn read<R: Read>(r: &mut R) -> Result<Request> {
+        let mut buf = Vec::new();
+        let mut chunk = [0; 1024];
+        loop {
+            let n = r.read(&mut chunk)?;
+            if n == 0 {
+                bail!("Connection closed before the request was complete");
+            }
+            buf.extend(&chunk[..n]);
+            if let Some(req) = Request::parse(&buf)? {
+                return Ok(req);
+            }
+            if buf.len() > MAX_HEAD {
+                bail!("Request is too large");
+            }
+        }
+    }
+
+    /// The first value of a query parameter
+    pub fn param(&self, key: &str) -> Option<&str> {
+        self.query.iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The token of an `Authorization: Bearer` header
+    pub fn bearer(&self) -> Option<&str> {
+        let (scheme, token) = self.authorization.as_deref()?
+            .split_once(' ')?;
+        if scheme.eq_ignore_ascii_case("bearer") {
+            Some(token.trim())
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    pub fn json<T: Serialize>(value: &T) -> Result<Response> {
+        Ok(Response {
+            status: 200,
+            content_type: "application/json",
+            body: serde_json::to_vec(value)?,
+        })
+    }
+
+    pub fn bytes(body: Vec<u8>) -> Response {
+        Response {
+            status: 200,
+            content_type: "application/octet-stream",
+            body,
+        }
+    }
+
+    pub fn error(status: u16, message: &str) -> Response {
+        let body = serde_json::json!({ "error": message });
+        Response {
+            status,
+            content_type: "application/json",
+            body: body.to_string().into_bytes(),
+        }
+    }
+
+    #[inline]
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Internal Server Error",
+        }
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<()> {
+        let mut head = format!("HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            self.status, self.reason(), self.content_type, self.body.len());
+        match self.status {
+            401 => head.push_str("WWW-Authenticate: Bearer\r\n"),
+            405 => head.push_str("Allow: GET\r\n"),
+            _ => (),
+        }
+        head.push_str("\r\n");
+
+        w.write_all(head.as_bytes())?;
+        w.write_all(&self.body)?;
+        w.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        let req = Request::parse(b"GET /api/v1/subdomains?filter=value+like+%25example%25&limit=5 HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer asdf\r\n\r\n").unwrap().unwrap();
+        assert_eq!(req, Request {
+            method: "GET".to_string(),
+            path: "/api/v1/subdomains".to_string(),
+            query: vec![
+                ("filter".to_string(), "value like %example%".to_string()),
+                ("limit".to_string(), "5".to_string()),
+            ],
+            authorization: Some("Bearer asdf".to_string()),
+        });
+        assert_eq!(req.param("limit"), Some("5"));
+        assert_eq!(req.param("offset"), None);
+        assert_eq!(req.bearer(), Some("asdf"));
+    }
+
+    #[test]
+    fn test_parse_partial() {
+        assert_eq!(Request::parse(b"GET /api/v1/stats HTTP/1.1\r\nHost: loc").unwrap(), None);
+        assert!(Request::parse(b"\x00\x01\x02\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_bearer() {
+        let mut req = Request::parse(b"GET / HTTP/1.1\r\nauthorization: Basic asdf\r\n\r\n").unwrap().unwrap();
+        assert_eq!(req.bearer(), None);
+        req.authorization = None;
+        assert_eq!(req.bearer(), None);
+    }
+
+    #[test]
+    fn test_write_response() {
+        let mut buf = Vec::new();
+        Response::error(401, "Invalid token").write_to(&mut buf).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "HTTP/1.1 401 Unauthorized\r\nContent-Type: application/json\r\nContent-Length: 25\r\nConnection: close\r\nWWW-Authenticate: Bearer\r\n\r\n{\"error\":\"Invalid token\"}");
+    }
+}
@@ -0,0 +1,130 @@
+mod api;
+mod http;
+
+pub use self::api::Api;
+use self::http::{Request, Response};
+use crate::errors::*;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+/// A client that doesn't finish its request in time is dropped, so it
+/// doesn't hold up everybody else. This covers reading the request and
+/// writing the response, not every single read.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A connection that fails every read and write once the deadline passed
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    until: Instant,
+}
+
+impl<'a> Deadline<'a> {
+    fn new(stream: &'a TcpStream, timeout: Duration) -> Deadline<'a> {
+        Deadline {
+            stream,
+            until: Instant::now() + timeout,
+        }
+    }
+
+    /// The time that's left, set as timeout of the next read or write
+    fn remaining(&self) -> io::Result<Duration> {
+        match self.until.checked_duration_since(Instant::now()) {
+            Some(remaining) if !remaining.is_zero() => Ok(remaining),
+            _ => Err(timed_out()),
+        }
+    }
+}
+
+fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "Request took too long")
+}
+
+/// A socket timeout means the deadline has been reached
+fn check_timeout<T>(result: io::Result<T>) -> io::Result<T> {
+    match result {
+        Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => Err(timed_out()),
+        result => result,
+    }
+}
+
+impl<'a> Read for Deadline<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.set_read_timeout(Some(self.remaining()?))?;
+        check_timeout(self.stream.read(buf))
+    }
+}
+
+impl<'a> Write for Deadline<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.set_write_timeout(Some(self.remaining()?))?;
+        check_timeout(self.stream.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+fn handle(api: &Api, stream: &TcpStream) -> Result<()> {
+    let mut stream = Deadline::new(stream, TIMEOUT);
+
+    let response = match Request::read(&mut stream) {
+        Ok(req) => {
+            let response = api.handle(&req);
+            info!("{} {} - {}", req.method, req.path, response.status);
+            response
+        },
+        Err(err) => Response::error(400, &err.to_string()),
+    };
+    response.write_to(&mut stream)
+}
+
+/// Answer requests one at a time until the process is stopped. There's a
+/// single connection to the database, a request only has to wait for the
+/// ones before it, and for at most `TIMEOUT` each.
+pub fn run(api: &Api, listener: TcpListener) -> Result<()> {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!("Failed to accept connection: {}", err);
+                continue;
+            },
+        };
+        if let Err(err) = handle(api, &stream) {
+            debug!("Connection failed: {:#}", err);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_deadline() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // a client that trickles in a byte at a time, never finishing the head
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            for _ in 0..20 {
+                if stream.write_all(b"G").is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        let start = Instant::now();
+        let mut stream = Deadline::new(&stream, Duration::from_millis(300));
+        let err = Request::read(&mut stream).err().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(err.to_string(), "Request took too long");
+        client.join().unwrap();
+    }
+}
@@ -1,26 +1,190 @@
 use crate::autonoscope::RuleType;
+use crate::config::DatabaseConfig;
+use crate::db::{self, Database, Family, Table};
+use crate::errors::*;
+use crate::fmt::colors::{paint, Style};
+use crate::workspaces::Workspace;
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Text};
 use rustyline::{self, Context};
 use rustyline::completion::Completer;
 use rustyline::highlight::Highlighter;
 use rustyline::hint::Hinter;
 use std::borrow::Cow::{self, Owned};
+use std::cell::{Cell, Ref, RefCell};
+use std::collections::HashMap;
+use std::fmt;
 use std::str::FromStr;
+use std::time::{Duration, Instant};
 use crate::shell::Command;
 use crate::workspaces;
 
+/// Values offered for a single completion, there's no point in listing more
+const VALUE_LIMIT: i64 = 50;
+/// A failed connection is only retried after a while, not on every key press
+const RETRY_AFTER: Duration = Duration::from_secs(5);
+
+#[derive(QueryableByName)]
+struct ValueRow {
+    #[sql_type = "Text"]
+    value: String,
+}
+
+#[derive(QueryableByName)]
+struct ColumnRow {
+    #[sql_type = "Text"]
+    name: String,
+}
+
+/// Looks up the values of entities while a line is typed. This needs a
+/// connection of its own, the database of the shell can't be borrowed by the
+/// editor.
+pub struct Lookup {
+    db: SqliteConnection,
+    columns: HashMap<Table, Vec<String>>,
+}
+
+impl fmt::Debug for Lookup {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        w.debug_struct("Lookup")
+            .field("columns", &self.columns)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Lookup {
+    /// The columns don't change while the shell is running, they are only
+    /// read once
+    pub fn new(db: SqliteConnection) -> Result<Lookup> {
+        let mut columns = HashMap::new();
+        for table in Table::ALL {
+            let names = diesel::sql_query(format!("PRAGMA table_info({})", table.as_str()))
+                .load::<ColumnRow>(&db)?
+                .into_iter()
+                .map(|column| column.name)
+                .collect();
+            columns.insert(*table, names);
+        }
+        Ok(Lookup {
+            db,
+            columns,
+        })
+    }
+
+    /// Completion must never make the shell wait, a lookup fails instead of
+    /// waiting for a lock and nothing can be written
+    pub fn connect(workspace: &Workspace, config: &DatabaseConfig) -> Result<Lookup> {
+        let config = DatabaseConfig {
+            busy_timeout: 0,
+            ..config.clone()
+        };
+        let db = Database::connect(workspace, &config)?;
+        db.batch_execute("PRAGMA busy_timeout = 0; PRAGMA query_only = ON")
+            .context("Failed to setup connection for completion")?;
+        Lookup::new(db)
+    }
+
+    /// Values that start with the prefix, this is a range on the unique
+    /// index of the value so it stays fast on large workspaces
+    fn values(&self, table: Table, prefix: &str) -> Vec<String> {
+        let query = format!("SELECT value FROM {} WHERE value >= ? AND value < ? ORDER BY value LIMIT ?", table.as_str());
+        let rows = diesel::sql_query(query)
+            .bind::<Text, _>(prefix)
+            .bind::<Text, _>(format!("{}\u{10ffff}", prefix))
+            .bind::<BigInt, _>(VALUE_LIMIT)
+            .load::<ValueRow>(&self.db);
+        match rows {
+            Ok(rows) => rows.into_iter().map(|row| row.value).collect(),
+            Err(err) => {
+                debug!("Failed to complete values of {}: {}", table.as_str(), err);
+                Vec::new()
+            },
+        }
+    }
+
+    fn columns(&self, table: Table) -> &[String] {
+        self.columns.get(&table)
+            .map(|columns| columns.as_slice())
+            .unwrap_or_default()
+    }
+}
+
+type Connect = Box<dyn Fn() -> Result<Lookup>>;
+
+/// The connection of the completion. If it can't be opened, for example
+/// because the database is locked, it's tried again on a later completion
+/// instead of completing without values for the rest of the session.
+#[derive(Default)]
+pub struct LookupSlot {
+    connect: Option<Connect>,
+    lookup: RefCell<Option<Lookup>>,
+    failed_at: Cell<Option<Instant>>,
+}
+
+impl fmt::Debug for LookupSlot {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        w.debug_struct("LookupSlot")
+            .field("lookup", &self.lookup)
+            .field("failed_at", &self.failed_at)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LookupSlot {
+    pub fn new<F: Fn() -> Result<Lookup> + 'static>(connect: F) -> LookupSlot {
+        let slot = LookupSlot {
+            connect: Some(Box::new(connect)),
+            ..Default::default()
+        };
+        slot.try_connect();
+        slot
+    }
+
+    /// Values are only completed in a slot that knows how to connect
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.connect.is_some()
+    }
+
+    fn try_connect(&self) {
+        let connect = match &self.connect {
+            Some(connect) => connect,
+            None => return,
+        };
+        match connect() {
+            Ok(lookup) => {
+                self.lookup.replace(Some(lookup));
+                self.failed_at.set(None);
+            },
+            Err(err) => {
+                debug!("Values can't be completed: {:#}", err);
+                self.failed_at.set(Some(Instant::now()));
+            },
+        }
+    }
+
+    fn get(&self) -> Option<Ref<'_, Lookup>> {
+        let retry = self.lookup.borrow().is_none()
+            && self.failed_at.get().is_none_or(|at| at.elapsed() >= RETRY_AFTER);
+        if retry {
+            self.try_connect();
+        }
+        Ref::filter_map(self.lookup.borrow(), Option::as_ref).ok()
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct CmdCompleter {
     pub modules: Vec<String>,
     pub keyring: Vec<String>,
+    pub lookup: LookupSlot,
 }
 
 impl CmdCompleter {
-    pub fn filter(&self, cmd: &str, args: &[String]) -> rustyline::Result<(usize, Vec<String>)> {
-        // we can only complete the 2nd argument
-        if args.len() != 2 {
-            Ok((0, vec![]))
-        } else {
-            Ok(filter_options(cmd, &[
+    pub fn filter(&self, line: &str, args: &[String]) -> rustyline::Result<(usize, Vec<String>)> {
+        match args.len() {
+            2 => Ok(filter_options(&args[0], &[
                 "domains",
                 "subdomains",
                 "ipaddrs",
@@ -35,11 +199,122 @@ impl CmdCompleter {
                 "ports",
                 "netblocks",
                 "cryptoaddrs",
-            ], &args[1]))
+            ], &args[1])),
+            3 => Ok(complete_word(line, &args[2], vec![String::from("where")])),
+            _ => {
+                let table = match Table::from_str(&args[1]) {
+                    Ok(table) if args[2].to_lowercase() == "where" => table,
+                    _ => return Ok((0, vec![])),
+                };
+                Ok(self.filter_expr(line, table, &args[3..]))
+            },
+        }
+    }
+
+    /// Complete the last word of a filter, depending on what came before it:
+    /// a column, an operator after a column or the value of an entity
+    fn filter_expr(&self, line: &str, table: Table, words: &[String]) -> (usize, Vec<String>) {
+        let lookup = match self.lookup.get() {
+            Some(lookup) => lookup,
+            None => return (0, vec![]),
+        };
+        let current = &words[words.len() - 1];
+        let prev = words.len().checked_sub(2)
+            .map(|idx| words[idx].to_lowercase());
+
+        // value=www.example.com
+        if let Some((column, prefix)) = current.split_once('=') {
+            if column == "value" {
+                let options = lookup.values(table, prefix).into_iter()
+                    .map(|value| format!("value={}", shellwords::escape(&value)))
+                    .collect();
+                return complete_raw(line, options);
+            }
+            return (0, vec![]);
+        }
+
+        let columns = lookup.columns(table);
+        let options = match prev.as_deref() {
+            None | Some("and") | Some("or") | Some("not") => {
+                let mut options = columns.to_vec();
+                options.push(String::from("related-to"));
+                options
+            },
+            Some(op) if db::OPERATORS.contains(&op) => {
+                if words.len() >= 3 && words[words.len() - 3] == "value" {
+                    lookup.values(table, current).into_iter()
+                        .map(|value| shellwords::escape(&value))
+                        .collect()
+                } else {
+                    return (0, vec![]);
+                }
+            },
+            Some(column) if columns.iter().any(|x| x == column) => {
+                db::OPERATORS.iter().map(|op| op.to_string()).collect()
+            },
+            _ => vec![String::from("and"), String::from("or")],
+        };
+        complete_word(line, current, options)
+    }
+
+    /// Values of the entities of an `add` command, like an existing domain to
+    /// start a subdomain with
+    fn add_value(&self, line: &str, family: &str, current: &str) -> (usize, Vec<String>) {
+        match (self.lookup.get(), Family::from_str(family)) {
+            (Some(lookup), Ok(family)) => {
+                let options = lookup.values(family.table(), current).into_iter()
+                    .map(|value| shellwords::escape(&value))
+                    .collect();
+                complete_word(line, current, options)
+            },
+            _ => (0, vec![]),
         }
     }
 }
 
+/// Where the last word of the line starts, with the same quoting rules as
+/// the words are split with
+fn last_word_start(line: &str) -> usize {
+    let mut start = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for (idx, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if let Some(q) = quote {
+            if c == q {
+                quote = None;
+            }
+        } else if c == '\'' || c == '"' {
+            quote = Some(c);
+        } else if c.is_whitespace() {
+            start = idx + c.len_utf8();
+        }
+    }
+    start
+}
+
+/// Replace the last word of the line with every option that it's a prefix of
+fn complete_word(line: &str, current: &str, options: Vec<String>) -> (usize, Vec<String>) {
+    let options = options.into_iter()
+        .filter(|x| x.starts_with(current))
+        .collect();
+    complete_raw(line, options)
+}
+
+/// Replace the last word of the line with options that are already escaped,
+/// an option needs to continue what has been typed so far
+fn complete_raw(line: &str, options: Vec<String>) -> (usize, Vec<String>) {
+    let base = &line[..last_word_start(line)];
+    let results = options.into_iter()
+        .map(|x| format!("{}{} ", base, x))
+        .filter(|x| x.starts_with(line))
+        .collect();
+    (0, results)
+}
+
 fn filter_options(prefix: &str, options: &[&str], arg: &str) -> (usize, Vec<String>) {
     let results: Vec<String> = options.iter()
         .filter(|x| x.starts_with(arg))
@@ -86,8 +361,9 @@ impl Completer for CmdCompleter {
 
             match action {
                 Command::Add => {
-                    // we can only complete the 2nd argument
-                    if args != 2 {
+                    if args == 3 {
+                        Ok(self.add_value(line, &cmd[1], &cmd[2]))
+                    } else if args != 2 {
                         Ok((0, vec![]))
                     } else {
                         let arg = &cmd[1];
@@ -142,7 +418,7 @@ impl Completer for CmdCompleter {
                         },
                     }
                 },
-                Command::Delete => self.filter("delete", &cmd),
+                Command::Delete => self.filter(line, &cmd),
                 Command::Keyring => {
                     match (args, cmd.get(1).map(|x| x.as_str())) {
                         (2, _) => {
@@ -242,7 +518,7 @@ impl Completer for CmdCompleter {
                         (_, _) => Ok((0, vec![])),
                     }
                 },
-                Command::Noscope => self.filter("noscope", &cmd),
+                Command::Noscope => self.filter(line, &cmd),
                 Command::Use => {
                     // we can only complete the 2nd argument
                     if args != 2 {
@@ -257,9 +533,9 @@ impl Completer for CmdCompleter {
                         Ok((0, results))
                     }
                 },
-                Command::Rescope => self.filter("rescope", &cmd),
-                Command::Scope => self.filter("scope", &cmd),
-                Command::Select => self.filter("select", &cmd),
+                Command::Rescope => self.filter(line, &cmd),
+                Command::Scope => self.filter(line, &cmd),
+                Command::Select => self.filter(line, &cmd),
                 Command::Workspace => {
                     // we can only complete the 2nd argument
                     if args != 2 {
@@ -307,9 +583,128 @@ impl Hinter for CmdCompleter {
 impl Highlighter for CmdCompleter {
     #[inline]
     fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
-        Owned("\x1b[90m".to_owned() + hint + "\x1b[m")
+        Owned(paint(Style::Grey, hint).to_string())
     }
 }
 
 impl rustyline::Helper for CmdCompleter {}
 impl rustyline::validate::Validator for CmdCompleter {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::*;
+    use rustyline::history::History;
+
+    fn completer(dir: &tempfile::TempDir) -> CmdCompleter {
+        let path = dir.path().join("test.db");
+        let path = path.to_str().unwrap();
+        let db = Database::scratch(path, "test").unwrap();
+        let domain_id = db.insert_generic(Insert::Domain(NewDomain {
+            value: "example.com".to_string(),
+            unscoped: false,
+        })).unwrap().unwrap().1;
+        for value in ["www.example.com", "www2.example.com", "mail.example.com"] {
+            db.insert_generic(Insert::Subdomain(NewSubdomain {
+                domain_id,
+                value: value.to_string(),
+                resolvable: None,
+                unscoped: false,
+            })).unwrap();
+        }
+
+        let conn = SqliteConnection::establish(path).unwrap();
+        CmdCompleter {
+            lookup: LookupSlot {
+                lookup: RefCell::new(Some(Lookup::new(conn).unwrap())),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn complete(completer: &CmdCompleter, line: &str) -> Vec<String> {
+        let history = History::new();
+        let ctx = Context::new(&history);
+        completer.complete(line, line.len(), &ctx).unwrap().1
+    }
+
+    #[test]
+    fn test_last_word_start() {
+        assert_eq!(last_word_start("select subdomains"), 7);
+        assert_eq!(last_word_start("select subdomains "), 18);
+        assert_eq!(last_word_start("where value=a\\ b"), 6);
+        assert_eq!(last_word_start("where value='a b"), 6);
+    }
+
+    #[test]
+    fn test_complete_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let completer = completer(&dir);
+
+        assert_eq!(complete(&completer, "select subdomains wh"), vec!["select subdomains where "]);
+        assert_eq!(complete(&completer, "select subdomains where value=www"), vec![
+            "select subdomains where value=www.example.com ",
+            "select subdomains where value=www2.example.com ",
+        ]);
+        assert_eq!(complete(&completer, "delete subdomains where value like ma"), vec![
+            "delete subdomains where value like mail.example.com ",
+        ]);
+        assert_eq!(complete(&completer, "add subdomain www2"), vec!["add subdomain www2.example.com "]);
+        assert!(complete(&completer, "select domains where value=www").is_empty());
+        assert!(complete(&completer, "select subdomains where unscoped=").is_empty());
+    }
+
+    #[test]
+    fn test_complete_columns() {
+        let dir = tempfile::tempdir().unwrap();
+        let completer = completer(&dir);
+
+        assert_eq!(complete(&completer, "select subdomains where res"), vec!["select subdomains where resolvable "]);
+        assert_eq!(complete(&completer, "select subdomains where value like %.com and seen_"), vec![
+            "select subdomains where value like %.com and seen_count ",
+            "select subdomains where value like %.com and seen_first ",
+            "select subdomains where value like %.com and seen_last ",
+        ]);
+        assert_eq!(complete(&completer, "select subdomains where resolvable !"), vec!["select subdomains where resolvable != "]);
+        assert_eq!(complete(&completer, "select subdomains where resolvable=1 a"), vec!["select subdomains where resolvable=1 and "]);
+        assert_eq!(complete(&completer, "select subdomains where value=www.example.com "), vec![
+            "select subdomains where value=www.example.com and ",
+            "select subdomains where value=www.example.com or ",
+        ]);
+    }
+
+    #[test]
+    fn test_complete_without_lookup() {
+        let completer = CmdCompleter::default();
+        assert_eq!(complete(&completer, "select sub"), vec!["select subdomains "]);
+        assert!(complete(&completer, "select subdomains where value=www").is_empty());
+        assert!(complete(&completer, "add subdomain www").is_empty());
+    }
+
+    #[test]
+    fn test_lookup_retry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.db");
+        let attempts = std::rc::Rc::new(Cell::new(0));
+        let counter = attempts.clone();
+        let slot = LookupSlot::new(move || {
+            counter.set(counter.get() + 1);
+            let path = path.to_str().unwrap();
+            if !std::path::Path::new(path).exists() {
+                bail!("Database doesn't exist yet");
+            }
+            Lookup::new(SqliteConnection::establish(path).unwrap())
+        });
+        assert!(slot.is_enabled());
+        assert!(slot.get().is_none());
+        // not retried right away
+        assert_eq!(attempts.get(), 1);
+
+        completer(&dir);
+        slot.failed_at.set(Some(Instant::now() - RETRY_AFTER));
+        assert!(slot.get().is_some());
+        assert!(slot.get().is_some());
+        assert_eq!(attempts.get(), 2);
+    }
+}
@@ -0,0 +1,47 @@
+use crate::keyring::KeyRing;
+
+/// Lines that must not end up in the history file, because they add a key to
+/// the keyring or contain a key that's in the keyring already
+pub fn is_private(line: &str, keyring: &KeyRing) -> bool {
+    if let Ok(args) = shellwords::split(line) {
+        if let [cmd, subcommand, ..] = args.as_slice() {
+            if cmd == "keyring" && subcommand == "add" {
+                return true;
+            }
+        }
+    }
+    keyring.contains_secret(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_private() {
+        let mut keyring = KeyRing::default();
+        keyring.load_env(vec![
+            ("SN0INT_KEY_shodan__asdfghjkl".to_string(), String::new()),
+        ]);
+        assert!(is_private("keyring add shodan:qwertzuiop", &keyring));
+        assert!(is_private("  keyring  add aws:AKIA1234 secret", &keyring));
+        assert!(is_private("set api_key asdfghjkl", &keyring));
+        assert!(!is_private("keyring list", &keyring));
+        assert!(!is_private("select domains", &keyring));
+    }
+
+    #[test]
+    fn test_is_private_names() {
+        let mut keyring = KeyRing::default();
+        keyring.load_env(vec![
+            ("SN0INT_KEY_github__main".to_string(), String::new()),
+            ("SN0INT_KEY_instagram__user".to_string(), "hunter2secret".to_string()),
+        ]);
+        // short names are only secrets if they show up as a word of their own
+        assert!(!is_private("use kpcyrd/maintenance", &keyring));
+        assert!(is_private("keyring delete github:main", &keyring));
+        // the username is only the access key for the password
+        assert!(!is_private("select accounts where username=user", &keyring));
+        assert!(is_private("set password hunter2secret", &keyring));
+    }
+}
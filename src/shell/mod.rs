@@ -4,8 +4,12 @@ use crate::args::Args;
 use crate::blobs::{Blob, BlobStorage};
 use crate::cmd::*;
 use crate::config::Config;
+use crate::encryption::{self, Encryption};
 use crate::db::ttl;
+use crate::fmt::colors::{paint, Style};
 use crate::keyring::KeyRing;
+use crate::lockfile::Lockfile;
+use crate::notify;
 use crate::worker::{self, VoidSender};
 use colored::Colorize;
 use crate::db::{self, Database};
@@ -13,43 +17,57 @@ use crate::engine::{Library, Module};
 use crate::update::AutoUpdater;
 use lazy_static::lazy_static;
 use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use crate::term::{self, Prompt};
+use crate::term::{self, Prompt, Tag};
 use crate::paths;
 use crate::psl::{Psl, PslReader};
 use crate::lazy::Lazy;
-use crate::workspaces::Workspace;
 
 pub mod complete;
-use self::complete::CmdCompleter;
+pub mod history;
+use self::complete::{CmdCompleter, Lookup, LookupSlot};
+pub mod pager;
 pub mod readline;
 use self::readline::{Readline, ReadlineError};
+use rustyline::history::History;
 
 
 #[derive(Debug)]
 pub enum Command {
     Activity,
     Add,
+    Audit,
     Autonoscope,
     Autoscope,
     Back,
+    Blobs,
+    Db,
     Delete,
+    Diff,
     Help,
+    History,
     Keyring,
     Mod,
     Noscope,
     Pkg,
+    Provenance,
     Rescope,
     Run,
     Scope,
+    Search,
     Set,
     Select,
     Stats,
     Target,
+    Timeline,
+    Tree,
     Use,
     Quickstart,
+    View,
     Workspace,
     Cal,
 
@@ -65,26 +83,36 @@ impl Command {
         match *self {
             Command::Activity => "activity",
             Command::Add => "add",
+            Command::Audit => "audit",
             Command::Autonoscope => "autonoscope",
             Command::Autoscope => "autoscope",
             Command::Back => "back",
+            Command::Blobs => "blobs",
+            Command::Db => "db",
             Command::Delete => "delete",
+            Command::Diff => "diff",
             Command::Exit => "exit",
             Command::Help => "help",
+            Command::History => "history",
             Command::Keyring => "keyring",
             Command::Mod => "mod",
             Command::Noscope => "noscope",
             Command::Pkg => "pkg",
+            Command::Provenance => "provenance",
             Command::Rescope => "rescope",
             Command::Run => "run",
             Command::Scope => "scope",
+            Command::Search => "search",
             Command::Set => "set",
             Command::Select => "select",
             Command::Stats => "stats",
             Command::Target => "target",
+            Command::Timeline => "timeline",
+            Command::Tree => "tree",
             Command::Use => "use",
             Command::Quickstart => "quickstart",
             Command::Quit => "quit",
+            Command::View => "view",
             Command::Workspace => "workspace",
             Command::Cal => "cal",
             Command::Interrupt => unreachable!(),
@@ -97,24 +125,34 @@ impl Command {
             static ref COMMANDS: Vec<&'static str> = vec![
                 Command::Activity.as_str(),
                 Command::Add.as_str(),
+                Command::Audit.as_str(),
                 Command::Autonoscope.as_str(),
                 Command::Autoscope.as_str(),
                 Command::Back.as_str(),
+                Command::Blobs.as_str(),
+                Command::Db.as_str(),
                 Command::Delete.as_str(),
+                Command::Diff.as_str(),
                 Command::Exit.as_str(),
                 Command::Help.as_str(),
+                Command::History.as_str(),
                 Command::Keyring.as_str(),
                 Command::Noscope.as_str(),
                 Command::Pkg.as_str(),
+                Command::Provenance.as_str(),
                 Command::Rescope.as_str(),
                 Command::Run.as_str(),
                 Command::Scope.as_str(),
+                Command::Search.as_str(),
                 Command::Set.as_str(),
                 Command::Select.as_str(),
                 Command::Stats.as_str(),
                 Command::Target.as_str(),
+                Command::Timeline.as_str(),
+                Command::Tree.as_str(),
                 Command::Use.as_str(),
                 Command::Quit.as_str(),
+                Command::View.as_str(),
                 Command::Workspace.as_str(),
                 Command::Cal.as_str(),
             ];
@@ -131,26 +169,36 @@ impl FromStr for Command {
         match s {
             "activity" => Ok(Command::Activity),
             "add" => Ok(Command::Add),
+            "audit" => Ok(Command::Audit),
             "autonoscope" => Ok(Command::Autonoscope),
             "autoscope" => Ok(Command::Autoscope),
             "back" => Ok(Command::Back),
+            "blobs" => Ok(Command::Blobs),
+            "db" => Ok(Command::Db),
             "delete" => Ok(Command::Delete),
+            "diff" => Ok(Command::Diff),
             "exit" => Ok(Command::Exit),
             "help" => Ok(Command::Help),
+            "history" => Ok(Command::History),
             "keyring" => Ok(Command::Keyring),
             "mod" => Ok(Command::Mod),
             "noscope" => Ok(Command::Noscope),
             "pkg" => Ok(Command::Pkg),
+            "provenance" => Ok(Command::Provenance),
             "rescope" => Ok(Command::Rescope),
             "run" => Ok(Command::Run),
             "scope" => Ok(Command::Scope),
+            "search" => Ok(Command::Search),
             "set" => Ok(Command::Set),
             "select" => Ok(Command::Select),
             "stats" => Ok(Command::Stats),
             "target" => Ok(Command::Target),
+            "timeline" => Ok(Command::Timeline),
+            "tree" => Ok(Command::Tree),
             "use" => Ok(Command::Use),
             "quickstart" => Ok(Command::Quickstart),
             "quit" => Ok(Command::Quit),
+            "view" => Ok(Command::View),
             "workspace" => Ok(Command::Workspace),
             "cal" => Ok(Command::Cal),
             x => bail!("unknown command: {:?}, try \"help\"", x),
@@ -171,12 +219,14 @@ pub struct Shell<'a> {
     options: Option<HashMap<String, String>>,
     signal_register: Arc<SignalRegister>,
     cancel_twice: u8,
+    notify: notify::RunState,
 }
 
 impl<'a> Shell<'a> {
     pub fn new(config: &'a Config, db: Database, blobs: BlobStorage, psl: PslReader, library: Library<'a>, keyring: KeyRing) -> Result<Shell<'a>> {
         let h = CmdCompleter::default();
-        let rl = Readline::with(h)?;
+        let mut rl = Readline::with(h)?;
+        rl.set_history_size(config.core.history_size);
 
         let prompt = Prompt::new(db.name().to_string());
 
@@ -192,6 +242,7 @@ impl<'a> Shell<'a> {
             options: None,
             signal_register: Arc::new(SignalRegister::new()),
             cancel_twice: 0,
+            notify: notify::RunState::default(),
         };
 
         rl.reload_module_cache();
@@ -260,6 +311,12 @@ impl<'a> Shell<'a> {
     pub fn set_db(&mut self, db: Database) {
         self.prompt.workspace = db.name().to_string();
         self.db = db;
+        if let Err(err) = self.load_history() {
+            term::warn(&format!("Failed to load history: {:#}", err));
+        }
+        if self.rl.helper_mut().is_some_and(|helper| helper.lookup.is_enabled()) {
+            self.reload_lookup();
+        }
     }
 
     #[inline(always)]
@@ -277,6 +334,11 @@ impl<'a> Shell<'a> {
         Ok(self.psl.get()?)
     }
 
+    #[inline(always)]
+    pub fn notify_state_mut(&mut self) -> &mut notify::RunState {
+        &mut self.notify
+    }
+
     #[inline(always)]
     pub fn config(&self) -> &Config {
         self.config
@@ -303,7 +365,10 @@ impl<'a> Shell<'a> {
     }
 
     pub fn readline(&mut self) -> Option<(Command, Vec<String>)> {
-        let readline = self.rl.readline(&self.prompt.to_string());
+        let prompt = self.prompt.render(self.config.core.prompt.as_ref(), &self.db);
+        term::set_printer(self.rl.external_printer());
+        let readline = self.rl.readline(&prompt);
+        term::set_printer(None);
 
         if readline.is_ok() {
             self.cancel_twice = 0;
@@ -317,7 +382,7 @@ impl<'a> Shell<'a> {
                 } else {
                     debug!("Readline returned {:?}", line);
 
-                    self.rl.add_history_entry(line.as_str());
+                    self.add_history(&line);
 
                     if line.starts_with('#') {
                         return None;
@@ -406,12 +471,83 @@ impl<'a> Shell<'a> {
         }
     }
 
+    /// Connect the completion to the database of the current workspace,
+    /// without it only commands and their options are completed
+    pub fn reload_lookup(&mut self) {
+        let workspace = self.db.workspace().clone();
+        let config = self.config.database.clone();
+        let lookup = LookupSlot::new(move || Lookup::connect(&workspace, &config));
+
+        if let Some(helper) = self.rl.helper_mut() {
+            helper.lookup = lookup;
+        }
+    }
+
+    /// The history file of the current workspace. Encrypted workspaces don't
+    /// keep one, it would store their queries in plaintext.
+    fn history_path(&self) -> Result<Option<PathBuf>> {
+        let workspace = self.db.workspace();
+        if self.config.core.history_size == 0 || Encryption::load(workspace)?.is_some() {
+            return Ok(None);
+        }
+        Ok(Some(paths::history_path(workspace)?))
+    }
+
+    /// Replace the history with the one of the current workspace. The
+    /// history that older versions shared between workspaces is moved to the
+    /// first workspace that's opened without a history of its own.
     pub fn load_history(&mut self) -> Result<()> {
-        self.rl.load_history(&paths::history_path()?)
+        self.rl.clear_history();
+        let path = match self.history_path()? {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let legacy = paths::legacy_history_path()?;
+        if legacy.exists() && !path.exists() {
+            fs::rename(&legacy, &path)
+                .context("Failed to move old history to workspace")?;
+            term::info(&format!("Moved the shell history of older versions to workspace {:?}", self.db.name()));
+        }
+
+        if path.exists() {
+            self.rl.load_history(&path)?;
+        }
+        Ok(())
     }
 
     pub fn save_history(&mut self) -> Result<()> {
-        self.rl.save_history(&paths::history_path()?)
+        match self.history_path()? {
+            Some(path) => self.rl.append_history(&path),
+            None => Ok(()),
+        }
+    }
+
+    /// Ask a question with the line editor of the shell, answers aren't
+    /// added to the history since they're not commands
+    pub fn question(&mut self, text: &str) -> Result<String> {
+        let prompt = format!("{} {}: ", Tag(Style::Blue, '?'), text);
+        let line = self.rl.readline(&prompt)?;
+        Ok(line)
+    }
+
+    /// Every line is written right away, so it's kept even if the shell is
+    /// killed with ctrl-c
+    fn add_history(&mut self, line: &str) {
+        if history::is_private(line, &self.keyring) {
+            debug!("Line contains a secret, not adding it to the history");
+            return;
+        }
+        if self.rl.add_history_entry(line) {
+            if let Err(err) = self.save_history() {
+                debug!("Failed to save history: {:#}", err);
+            }
+        }
+    }
+
+    #[inline(always)]
+    pub fn history(&self) -> &History {
+        self.rl.history()
     }
 
     pub fn set_signal_handler(&self) -> Result<()> {
@@ -439,6 +575,7 @@ impl<'a> Shell<'a> {
 
     pub fn store_blob(&self, tx: VoidSender, blob: &Blob) {
         let result = self.blobs.save(blob)
+            .map(|_| ())
             .map_err(|err| err.to_string());
         tx.send(result).unwrap();
     }
@@ -500,20 +637,28 @@ pub fn run_once(rl: &mut Shell) -> Result<bool> {
     match line {
         Some((Command::Activity, args)) => cmd::<activity_cmd::Args>(rl, &args)?,
         Some((Command::Add, args)) => cmd::<add_cmd::Args>(rl, &args)?,
+        Some((Command::Audit, args)) => cmd::<audit_cmd::Args>(rl, &args)?,
         Some((Command::Autonoscope, args)) => cmd::<autonoscope_cmd::Args>(rl, &args)?,
         Some((Command::Autoscope, args)) => cmd::<autoscope_cmd::Args>(rl, &args)?,
         Some((Command::Back, _)) => if rl.take_module().is_none() {
             return Ok(true);
         },
+        Some((Command::Blobs, args)) => cmd::<blobs_cmd::Args>(rl, &args)?,
+        Some((Command::Db, args)) => cmd::<db_cmd::Args>(rl, &args)?,
         Some((Command::Delete, args)) => delete_cmd::run(rl, &args)?,
+        Some((Command::Diff, args)) => cmd::<diff_cmd::Args>(rl, &args)?,
         Some((Command::Help, args)) => help_cmd::run(rl, &args)?,
-        Some((Command::Keyring, args)) => keyring_cmd::run(rl, &args)?,
+        Some((Command::History, args)) => cmd::<history_cmd::Args>(rl, &args)?,
+        Some((Command::Keyring, args)) => cmd::<keyring_cmd::Args>(rl, &args)?,
         Some((Command::Mod, args)) => {
-            term::warn("The \x1b[1mmod\x1b[0m command is deprecated, use \x1b[1mpkg\x1b[0m");
+            term::warn(&format!("The {} command is deprecated, use {}", paint(Style::Bold, "mod"), paint(Style::Bold, "pkg")));
             cmd::<pkg_cmd::ArgsInteractive>(rl, &args)?
         },
         Some((Command::Noscope, args)) => noscope_cmd::run(rl, &args)?,
         Some((Command::Pkg, args)) => cmd::<pkg_cmd::ArgsInteractive>(rl, &args)?,
+        Some((Command::Provenance, args)) => cmd::<provenance_cmd::Args>(rl, &args)?,
+        Some((Command::Tree, args)) => cmd::<tree_cmd::Args>(rl, &args)?,
+        Some((Command::Search, args)) => cmd::<search_cmd::Args>(rl, &args)?,
         Some((Command::Rescope, args)) => cmd::<rescope_cmd::Args>(rl, &args)?,
         Some((Command::Run, args)) => cmd::<run_cmd::Args>(rl, &args)?,
         Some((Command::Scope, args)) => scope_cmd::run(rl, &args)?,
@@ -521,8 +666,10 @@ pub fn run_once(rl: &mut Shell) -> Result<bool> {
         Some((Command::Select, args)) => cmd::<select_cmd::Args>(rl, &args)?,
         Some((Command::Stats, args)) => cmd::<stats_cmd::Args>(rl, &args)?,
         Some((Command::Target, args)) => target_cmd::run(rl, &args)?,
+        Some((Command::Timeline, args)) => cmd::<timeline_cmd::Args>(rl, &args)?,
         Some((Command::Use, args)) => use_cmd::run(rl, &args)?,
         Some((Command::Quickstart, args)) => quickstart_cmd::run(rl, &args)?,
+        Some((Command::View, args)) => cmd::<view_cmd::Args>(rl, &args)?,
         Some((Command::Workspace, args)) => cmd::<workspace_cmd::Args>(rl, &args)?,
         Some((Command::Cal, args)) => cmd::<cal_cmd::Args>(rl, &args)?,
 
@@ -539,35 +686,39 @@ pub fn run_once(rl: &mut Shell) -> Result<bool> {
 }
 
 pub fn init<'a>(args: &Args, config: &'a Config, verbose_init: bool) -> Result<Shell<'a>> {
-    let workspace = match args.workspace {
-        Some(ref workspace) => workspace.clone(),
-        None => Workspace::from_str("default").unwrap(),
-    };
-
+    let workspace = args.workspace();
     workspace.migrate()?;
 
-    let blobs = BlobStorage::workspace(&workspace)?;
+    let key = encryption::unlock(&workspace, &config.database)?;
+    let blobs = BlobStorage::workspace(&workspace)?
+        .with_key(key.as_ref());
     let db = if verbose_init {
-        Database::establish(workspace)?
+        Database::establish(workspace, &config.database)?
     } else {
-        Database::establish_quiet(workspace)?
+        Database::establish_quiet(workspace, &config.database)?
     };
 
     let cache_dir = paths::cache_dir()?;
-    let psl = PslReader::open_or_download(&cache_dir,
-            |cb| worker::spawn_fn("Downloading public suffix list", cb, false))
-        .context("Failed to download public suffix list")?;
+    let psl = if config.network.offline {
+        PslReader::open(&cache_dir)
+            .context("Public suffix list isn't cached and can't be downloaded in offline mode")?
+    } else {
+        PslReader::open_or_download(&cache_dir, config.network.proxy,
+                |cb| worker::spawn_fn("Downloading public suffix list", cb, false))
+            .context("Failed to download public suffix list")?
+    };
     let library = Library::new(verbose_init, config)?;
     let keyring = KeyRing::init()?;
 
     if verbose_init && library.list().is_empty() {
-        term::success("No modules found, run \x1b[1mpkg quickstart\x1b[0m to install default modules");
+        term::success(&format!("No modules found, run {} to install default modules", paint(Style::Bold, "pkg quickstart")));
         term::success("New to sn0int? Follow https://sn0int.rtfd.io/en/stable/usage.html");
     }
 
     let autoupdate = AutoUpdater::load()?;
-    if autoupdate.outdated() > 0 {
-        term::warn(&format!("{} modules are outdated, run: \x1b[1mpkg update\x1b[0m", autoupdate.outdated()));
+    let outdated = autoupdate.outdated(&Lockfile::load()?);
+    if outdated > 0 {
+        term::warn(&format!("{} modules are outdated, run: {}", outdated, paint(Style::Bold, "pkg update")));
     }
     autoupdate.check_background(config, library.list());
 
@@ -602,7 +753,10 @@ pub fn run(args: &Args, config: &Config) -> Result<()> {
     print_banner();
 
     let mut rl = init(args, config, true)?;
-    rl.load_history().ok();
+    if let Err(err) = rl.load_history() {
+        term::warn(&format!("Failed to load history: {:#}", err));
+    }
+    rl.reload_lookup();
 
     rl.set_signal_handler()
         .context("Failed to set signal handler")?;
@@ -618,7 +772,10 @@ pub fn run(args: &Args, config: &Config) -> Result<()> {
                 }
             },
         }
+        // digests of events outside of runs are sent once their window closed
+        notify::flush_due(&mut rl, &mut term::Term);
     }
+    notify::finish_run(&mut rl, &mut term::Term);
 
     rl.save_history()?;
 
@@ -0,0 +1,251 @@
+use crate::errors::*;
+use crate::shell::{Shell, SignalRegister};
+use crate::term;
+use std::env;
+use std::io::{self, ErrorKind, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+
+/// `-R` keeps the colors
+const DEFAULT_PAGER: &str = "less -R";
+
+#[cfg(unix)]
+fn terminal_height() -> Option<usize> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let ret = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if ret == 0 && size.ws_row > 0 {
+        Some(size.ws_row as usize)
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+fn terminal_height() -> Option<usize> {
+    None
+}
+
+fn pager_cmd() -> Result<Vec<String>> {
+    let cmd = env::var("PAGER").ok()
+        .filter(|x| !x.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_PAGER.to_string());
+    let cmd = shellwords::split(&cmd)
+        .map_err(|_| format_err!("Invalid $PAGER: {:?}", cmd))?;
+    if cmd.is_empty() {
+        bail!("$PAGER is empty");
+    }
+    Ok(cmd)
+}
+
+/// The pager is only used on a terminal and if it hasn't been disabled
+#[inline]
+fn is_enabled(no_pager: bool, config_no_pager: bool, tty: bool) -> bool {
+    !no_pager && !config_no_pager && tty
+}
+
+/// Output that is buffered until it doesn't fit on the terminal anymore, from
+/// then on it's written into `$PAGER`. Without a terminal it's written to
+/// stdout directly.
+pub struct Pager {
+    height: Option<usize>,
+    buf: Vec<u8>,
+    lines: usize,
+    child: Option<Child>,
+    /// The pager has been closed, the rest of the output is discarded
+    closed: bool,
+    signal_register: Arc<SignalRegister>,
+    stdout: Box<dyn Write>,
+    /// Used instead of `$PAGER` if set
+    command: Option<Vec<String>>,
+}
+
+impl Pager {
+    pub fn new(rl: &Shell, no_pager: bool) -> Pager {
+        let enabled = is_enabled(no_pager, rl.config().core.no_pager, atty::is(atty::Stream::Stdout));
+        let height = if enabled { terminal_height() } else { None };
+        Pager::with_height(height, rl.signal_register().clone(), Box::new(io::stdout()))
+    }
+
+    fn with_height(height: Option<usize>, signal_register: Arc<SignalRegister>, stdout: Box<dyn Write>) -> Pager {
+        Pager {
+            height,
+            buf: Vec::new(),
+            lines: 0,
+            child: None,
+            closed: false,
+            signal_register,
+            stdout,
+            command: None,
+        }
+    }
+
+    fn spawn(&mut self) -> Result<()> {
+        let cmd = match &self.command {
+            Some(cmd) => cmd.clone(),
+            None => pager_cmd()?,
+        };
+        let child = Command::new(&cmd[0])
+            .args(&cmd[1..])
+            .stdin(Stdio::piped())
+            .spawn()
+            .context(format_err!("Failed to start pager {:?}", cmd[0]))?;
+        debug!("Started pager: {:?}", cmd);
+        // ^C is for the pager now
+        self.signal_register.catch_ctrl();
+        self.child = Some(child);
+
+        let buf = std::mem::take(&mut self.buf);
+        self.write_pager(&buf)?;
+        Ok(())
+    }
+
+    fn write_pager(&mut self, data: &[u8]) -> io::Result<()> {
+        let stdin = self.child.as_mut()
+            .and_then(|child| child.stdin.as_mut())
+            .expect("Pager has no stdin");
+        match stdin.write_all(data) {
+            Err(err) if err.kind() == ErrorKind::BrokenPipe => {
+                self.closed = true;
+                Ok(())
+            },
+            res => res,
+        }
+    }
+
+    /// Print the output if it was short and wait for the pager to exit
+    pub fn finish(&mut self) -> Result<()> {
+        if let Some(mut child) = self.child.take() {
+            drop(child.stdin.take());
+            let status = child.wait();
+            self.signal_register.reset_ctrlc();
+            status.context("Failed to wait for pager")?;
+        } else if !self.buf.is_empty() {
+            self.stdout.write_all(&self.buf)?;
+            self.stdout.flush()?;
+            self.buf.clear();
+        }
+        Ok(())
+    }
+}
+
+impl Write for Pager {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.closed {
+            return Ok(data.len());
+        }
+        if self.child.is_some() {
+            self.write_pager(data)?;
+            return Ok(data.len());
+        }
+
+        let height = match self.height {
+            Some(height) => height,
+            None => return self.stdout.write(data),
+        };
+        self.buf.extend_from_slice(data);
+        self.lines += data.iter().filter(|b| **b == b'\n').count();
+        // keep a line for the prompt
+        if self.lines >= height {
+            if let Err(err) = self.spawn() {
+                term::warn(&err.to_string());
+                self.height = None;
+                let buf = std::mem::take(&mut self.buf);
+                self.stdout.write_all(&buf)?;
+            }
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        match &mut self.child {
+            Some(child) => child.stdin.as_mut().map_or(Ok(()), |stdin| stdin.flush()),
+            None if self.height.is_none() => self.stdout.flush(),
+            // still buffered, the pager may be needed yet
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Pager {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            debug!("Failed to finish pager: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::fs;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Output {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    impl Write for Output {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(data)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn pager(height: Option<usize>) -> (Pager, Output) {
+        let out = Output::default();
+        let pager = Pager::with_height(height, Arc::new(SignalRegister::new()), Box::new(out.clone()));
+        (pager, out)
+    }
+
+    #[test]
+    fn test_is_enabled() {
+        assert!(is_enabled(false, false, true));
+        assert!(!is_enabled(false, false, false));
+        assert!(!is_enabled(true, false, true));
+        assert!(!is_enabled(false, true, true));
+    }
+
+    #[test]
+    fn test_without_terminal() {
+        let (mut pager, out) = pager(None);
+        writeln!(pager, "a").unwrap();
+        assert_eq!(out.text(), "a\n");
+        pager.finish().unwrap();
+        assert_eq!(out.text(), "a\n");
+    }
+
+    #[test]
+    fn test_short_output() {
+        let (mut pager, out) = pager(Some(10));
+        writeln!(pager, "a\nb").unwrap();
+        assert_eq!(out.text(), "");
+        pager.finish().unwrap();
+        assert_eq!(out.text(), "a\nb\n");
+    }
+
+    #[test]
+    fn test_long_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("paged");
+        let (mut pager, out) = pager(Some(3));
+        pager.command = Some(vec!["sh".to_string(), "-c".to_string(), format!("cat > '{}'", path.display())]);
+        for i in 0..5 {
+            writeln!(pager, "{}", i).unwrap();
+        }
+        pager.finish().unwrap();
+        assert_eq!(out.text(), "");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "0\n1\n2\n3\n4\n");
+    }
+}
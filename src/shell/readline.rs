@@ -1,6 +1,7 @@
 use crate::errors::*;
 use std::path::Path;
-use rustyline::{self, CompletionType, EditMode, Editor};
+use rustyline::{self, CompletionType, EditMode, Editor, ExternalPrinter};
+use rustyline::history::History;
 pub use rustyline::error::ReadlineError;
 
 
@@ -25,6 +26,7 @@ impl<T: rustyline::Helper> Readline<T> {
         let rl_config = rustyline::Config::builder()
             .completion_type(CompletionType::List)
             .edit_mode(EditMode::Emacs)
+            .history_ignore_dups(true)
             .build();
 
         let mut rl: Editor<T> = Editor::with_config(rl_config)?;
@@ -47,9 +49,33 @@ impl<T: rustyline::Helper> Readline<T> {
             .map_err(Error::from)
     }
 
+    /// Write the entries that have been added since the file has been read,
+    /// other shells may append to the file at the same time
     #[inline]
-    pub fn add_history_entry<S: AsRef<str> + Into<String>>(&mut self, line: S) {
-        self.rl.add_history_entry(line);
+    pub fn append_history<P: AsRef<Path>>(&mut self, path: &P) -> Result<()> {
+        self.rl.append_history(path)
+            .map_err(Error::from)
+    }
+
+    #[inline]
+    pub fn clear_history(&mut self) {
+        self.rl.clear_history();
+    }
+
+    #[inline]
+    pub fn set_history_size(&mut self, len: usize) {
+        self.rl.history_mut().set_max_len(len);
+    }
+
+    #[inline]
+    pub fn history(&self) -> &History {
+        self.rl.history()
+    }
+
+    /// Returns false if the line is ignored, like a repeat of the last line
+    #[inline]
+    pub fn add_history_entry<S: AsRef<str> + Into<String>>(&mut self, line: S) -> bool {
+        self.rl.add_history_entry(line)
     }
 
     #[inline]
@@ -57,6 +83,18 @@ impl<T: rustyline::Helper> Readline<T> {
         self.rl.readline(prompt)
     }
 
+    /// Prints output of other threads while a line is read, this isn't
+    /// available if stdin or stdout isn't a terminal
+    pub fn external_printer(&mut self) -> Option<Box<dyn ExternalPrinter + Send>> {
+        match self.rl.create_external_printer() {
+            Ok(printer) => Some(Box::new(printer)),
+            Err(err) => {
+                debug!("External printer isn't available: {}", err);
+                None
+            },
+        }
+    }
+
     #[inline]
     pub fn helper_mut(&mut self) -> Option<&mut T> {
         self.rl.helper_mut()
@@ -0,0 +1,249 @@
+use crate::errors::*;
+use crate::config::DEFAULT_REGISTRY;
+use crate::manifest::SigningKey;
+use crate::paths;
+use data_encoding::BASE64;
+use serde::{Serialize, Deserialize};
+use sn0int_common::ModuleID;
+use sn0int_common::api::ModuleSignature;
+use sodiumoxide::crypto::sign::{self, PublicKey, Signature};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+/// The bytes that are signed for a release, the signature can't be reused
+/// for a different module or version
+fn signed_data(module: &ModuleID, version: &str, code: &str) -> Vec<u8> {
+    format!("sn0int-module\n{}\n{}\n{}", module, version, code).into_bytes()
+}
+
+pub fn sign(key: &SigningKey, module: &ModuleID, version: &str, code: &str) -> ModuleSignature {
+    let data = signed_data(module, version, code);
+    ModuleSignature {
+        public_key: key.public_key(),
+        signature: key.sign(&data),
+    }
+}
+
+pub fn verify(signature: &ModuleSignature, module: &ModuleID, version: &str, code: &str) -> Result<()> {
+    let public_key = BASE64.decode(signature.public_key.as_bytes())
+        .context("Failed to decode public key")?;
+    let public_key = PublicKey::from_slice(&public_key)
+        .ok_or_else(|| format_err!("Public key has wrong length"))?;
+    let sig = BASE64.decode(signature.signature.as_bytes())
+        .context("Failed to decode signature")?;
+    let sig = Signature::from_bytes(&sig)
+        .map_err(|_| format_err!("Signature has wrong length"))?;
+
+    let data = signed_data(module, version, code);
+    if !sign::verify_detached(&sig, &data, &public_key) {
+        bail!("Signature of {} v{} is invalid", module, version);
+    }
+    Ok(())
+}
+
+/// How the signature of an installed module has been checked
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Verification {
+    #[default]
+    Unsigned,
+    /// Signed by the pinned key of the author
+    Verified(String),
+    /// Installed with `--insecure-skip-verify` after the verification failed
+    Skipped,
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Verification::Unsigned => write!(w, "unsigned"),
+            Verification::Verified(key) => write!(w, "verified ({})", key),
+            Verification::Skipped => write!(w, "verification skipped"),
+        }
+    }
+}
+
+/// The public keys of authors, pinned the first time we install a signed
+/// module of them. Keys are pinned per registry, the same author name on two
+/// registries doesn't have to be the same person.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TrustedKeys {
+    /// Keys that have been pinned before they were kept per registry, these
+    /// belong to the default registry
+    #[serde(default, skip_serializing)]
+    authors: BTreeMap<String, String>,
+    #[serde(default)]
+    registries: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl TrustedKeys {
+    fn path() -> Result<PathBuf> {
+        let path = paths::sn0int_dir()?;
+        Ok(path.join("trusted-keys.json"))
+    }
+
+    pub fn load() -> Result<TrustedKeys> {
+        let path = TrustedKeys::path()?;
+        if !path.exists() {
+            return Ok(TrustedKeys::default());
+        }
+
+        let data = fs::read(&path)
+            .context("Failed to read trusted keys")?;
+        TrustedKeys::parse(&data)
+    }
+
+    fn parse(data: &[u8]) -> Result<TrustedKeys> {
+        let mut keys = serde_json::from_slice::<TrustedKeys>(data)
+            .context("Failed to parse trusted keys")?;
+        let legacy = std::mem::take(&mut keys.authors);
+        let default = keys.registries.entry(DEFAULT_REGISTRY.to_string()).or_default();
+        for (author, key) in legacy {
+            default.entry(author).or_insert(key);
+        }
+        Ok(keys)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let data = serde_json::to_string_pretty(&self)?;
+        fs::write(TrustedKeys::path()?, data + "\n")
+            .context("Failed to write trusted keys")?;
+        Ok(())
+    }
+
+    pub fn get(&self, registry: &str, author: &str) -> Option<&String> {
+        self.registries.get(registry)?.get(author)
+    }
+
+    /// Verify a downloaded release, releases of authors with a pinned key
+    /// need to be signed by that key. The key isn't pinned here, this
+    /// happens with `pin` once the release has been installed.
+    pub fn check(&self, registry: &str, module: &ModuleID, version: &str, code: &str, signature: Option<&ModuleSignature>) -> Result<Verification> {
+        match (signature, self.get(registry, &module.author)) {
+            (Some(signature), pinned) => {
+                verify(signature, module, version, code)?;
+                if let Some(pinned) = pinned {
+                    if *pinned != signature.public_key {
+                        bail!("{} v{} is signed by {}, but the key of {} is {}",
+                              module, version, signature.public_key, module.author, pinned);
+                    }
+                }
+                Ok(Verification::Verified(signature.public_key.clone()))
+            },
+            (None, Some(pinned)) => {
+                bail!("{} v{} isn't signed, but {} signs their modules with {}",
+                      module, version, module.author, pinned);
+            },
+            (None, None) => Ok(Verification::Unsigned),
+        }
+    }
+
+    /// Pin the key of an author if we haven't seen it before, returns true if
+    /// the key is new. This fails if a different key is pinned already.
+    pub fn pin(&mut self, registry: &str, author: &str, public_key: &str) -> Result<bool> {
+        let authors = self.registries.entry(registry.to_string()).or_default();
+        match authors.get(author) {
+            Some(pinned) if pinned != public_key => {
+                bail!("{} is signed with {}, but the key of {} is {}", author, public_key, author, pinned);
+            },
+            Some(_) => Ok(false),
+            None => {
+                info!("Pinning key of {} on {}: {}", author, registry, public_key);
+                authors.insert(author.to_string(), public_key.to_string());
+                Ok(true)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module() -> ModuleID {
+        ModuleID {
+            author: "kpcyrd".to_string(),
+            name: "ctlogs".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let key = SigningKey::generate().unwrap();
+        let signature = sign(&key, &module(), "0.1.0", "-- a");
+        verify(&signature, &module(), "0.1.0", "-- a").unwrap();
+        assert!(verify(&signature, &module(), "0.1.0", "-- b").is_err());
+        assert!(verify(&signature, &module(), "0.2.0", "-- a").is_err());
+        assert!(verify(&signature, &ModuleID {
+            author: "kpcyrd".to_string(),
+            name: "asn".to_string(),
+        }, "0.1.0", "-- a").is_err());
+    }
+
+    #[test]
+    fn test_trust_on_first_use() {
+        let key = SigningKey::generate().unwrap();
+        let mut trusted = TrustedKeys::default();
+
+        let signature = sign(&key, &module(), "0.1.0", "-- a");
+        let v = trusted.check("default", &module(), "0.1.0", "-- a", Some(&signature)).unwrap();
+        assert_eq!(v, Verification::Verified(key.public_key()));
+        // checking doesn't pin, the install might still be declined
+        assert_eq!(trusted.get("default", "kpcyrd"), None);
+        assert!(trusted.pin("default", "kpcyrd", &key.public_key()).unwrap());
+        assert!(!trusted.pin("default", "kpcyrd", &key.public_key()).unwrap());
+        assert_eq!(trusted.get("default", "kpcyrd"), Some(&key.public_key()));
+
+        let signature = sign(&key, &module(), "0.2.0", "-- b");
+        let v = trusted.check("default", &module(), "0.2.0", "-- b", Some(&signature)).unwrap();
+        assert_eq!(v, Verification::Verified(key.public_key()));
+    }
+
+    #[test]
+    fn test_pinned_key_mismatch() {
+        let mut trusted = TrustedKeys::default();
+        let signature = sign(&SigningKey::generate().unwrap(), &module(), "0.1.0", "-- a");
+        trusted.check("default", &module(), "0.1.0", "-- a", Some(&signature)).unwrap();
+        trusted.pin("default", "kpcyrd", &signature.public_key).unwrap();
+
+        let signature = sign(&SigningKey::generate().unwrap(), &module(), "0.2.0", "-- b");
+        assert!(trusted.check("default", &module(), "0.2.0", "-- b", Some(&signature)).is_err());
+        assert!(trusted.pin("default", "kpcyrd", &signature.public_key).is_err());
+        // an unsigned release isn't accepted either once the key is pinned
+        assert!(trusted.check("default", &module(), "0.2.0", "-- b", None).is_err());
+    }
+
+    #[test]
+    fn test_unsigned() {
+        let trusted = TrustedKeys::default();
+        let v = trusted.check("default", &module(), "0.1.0", "-- a", None).unwrap();
+        assert_eq!(v, Verification::Unsigned);
+        assert_eq!(trusted.get("default", "kpcyrd"), None);
+    }
+
+    #[test]
+    fn test_pinned_per_registry() {
+        let mut trusted = TrustedKeys::default();
+        let signature = sign(&SigningKey::generate().unwrap(), &module(), "0.1.0", "-- a");
+        trusted.pin("default", "kpcyrd", &signature.public_key).unwrap();
+
+        // the same author name on a different registry has its own key
+        let other = sign(&SigningKey::generate().unwrap(), &module(), "0.1.0", "-- a");
+        let v = trusted.check("internal", &module(), "0.1.0", "-- a", Some(&other)).unwrap();
+        assert_eq!(v, Verification::Verified(other.public_key.clone()));
+        assert!(trusted.pin("internal", "kpcyrd", &other.public_key).unwrap());
+        assert!(trusted.check("default", &module(), "0.1.0", "-- a", Some(&other)).is_err());
+        assert!(trusted.check("internal", &module(), "0.1.0", "-- a", Some(&signature)).is_err());
+    }
+
+    #[test]
+    fn test_legacy_keys() {
+        let trusted = TrustedKeys::parse(br#"{"authors":{"kpcyrd":"abc"}}"#).unwrap();
+        assert_eq!(trusted.get("default", "kpcyrd"), Some(&"abc".to_string()));
+        assert_eq!(trusted.get("internal", "kpcyrd"), None);
+        let json = serde_json::to_string(&trusted).unwrap();
+        assert_eq!(json, r#"{"registries":{"default":{"kpcyrd":"abc"}}}"#);
+    }
+}
@@ -1,14 +1,26 @@
 #![allow(clippy::print_literal)]
 
 use atty::{self, Stream};
-use crate::db;
+use crate::db::{self, Database, Table};
+use crate::errors::*;
 use crate::engine::Module;
+use crate::fmt::colors::{self, Style};
+use crate::output::RunEvent;
 use lazy_static::lazy_static;
 use rand::prelude::*;
-use std::collections::{HashMap, HashSet};
-use std::fmt;
+use rustyline::ExternalPrinter;
+use serde::{de, Serialize, Serializer, Deserialize, Deserializer};
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::{self, Write as _};
 use std::io;
 use std::io::prelude::*;
+use std::mem;
+use std::result;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 // https://github.com/Gallopsled/pwntools/blob/dev/pwnlib/term/spinners.py
 // https://github.com/gernest/wow/blob/master/spin/spinners.go
@@ -54,7 +66,6 @@ pub static SPINNERS: &[&[&str]] = &[
 ];
 
 pub struct TermSettings {
-    // colors: bool,
     indicate_progress: bool,
 }
 
@@ -74,6 +85,76 @@ impl TermSettings {
 
 lazy_static! {
     pub static ref TERM_SETTINGS: TermSettings = TermSettings::from_env();
+    /// Set while the shell waits for input
+    static ref PRINTER: Mutex<Option<Box<dyn ExternalPrinter + Send>>> = Mutex::new(None);
+}
+
+/// Set if stdout is reserved for machine readable output
+static DIAGNOSTICS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Print messages for humans to stderr from now on, without spinners
+pub fn diagnostics_to_stderr() {
+    DIAGNOSTICS_TO_STDERR.store(true, Ordering::Relaxed);
+}
+
+#[inline]
+fn indicate_progress() -> bool {
+    TERM_SETTINGS.indicate_progress && !DIAGNOSTICS_TO_STDERR.load(Ordering::Relaxed)
+}
+
+/// Hand output to readline while the shell waits for input, readline prints
+/// it above the prompt and draws the prompt and the input again
+pub fn set_printer(printer: Option<Box<dyn ExternalPrinter + Send>>) {
+    *PRINTER.lock().unwrap() = printer;
+}
+
+fn print_line(stderr: bool, line: fmt::Arguments) {
+    if let Some(printer) = PRINTER.lock().unwrap().as_mut() {
+        if printer.print(format!("{}\n", line)).is_ok() {
+            return;
+        }
+    }
+    if stderr {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
+fn print_diagnostic(line: fmt::Arguments) {
+    print_line(DIAGNOSTICS_TO_STDERR.load(Ordering::Relaxed), line);
+}
+
+/// Spinners are redrawn on the same line, this is skipped if there are none
+#[inline]
+fn clear_line() -> &'static str {
+    if indicate_progress() {
+        "\r\x1b[2K"
+    } else {
+        ""
+    }
+}
+
+/// The `[+]` in front of a line
+pub struct Tag<D>(pub Style, pub D);
+
+impl<D: fmt::Display> fmt::Display for Tag<D> {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        match colors::code(self.0) {
+            Some(code) => write!(w, "\x1b[1m[\x1b[{}m{}\x1b[0;1m]\x1b[0m", code, self.1),
+            None => write!(w, "[{}]", self.1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Info,
+    Debug,
+    Success,
+    Error,
+    Warn,
 }
 
 pub trait SpinLogger {
@@ -92,6 +173,26 @@ pub trait SpinLogger {
     fn status(&mut self, status: String);
 
     fn stacked_status(&mut self, name: &str, status: String);
+
+    /// Log a line of one of the investigations of a run
+    fn log_for(&mut self, name: &str, level: Level, line: &str) {
+        let line = format!("{:50}: {}", name, line);
+        match level {
+            Level::Info => self.log(&line),
+            Level::Debug => self.debug(&line),
+            Level::Success => self.success(&line),
+            Level::Error => self.error(&line),
+            Level::Warn => self.warn(&line),
+        }
+    }
+
+    /// Events for machine readable output, terminals only show the log lines
+    fn event(&mut self, _name: Option<&str>, _event: RunEvent) {}
+
+    /// Set if events are written somewhere, so they are worth building
+    fn structured(&self) -> bool {
+        false
+    }
 }
 
 pub struct Spinner {
@@ -100,17 +201,19 @@ pub struct Spinner {
     i: usize,
     dummy: bool,
     warnings: HashSet<String>,
+    started: Instant,
 }
 
 impl Spinner {
     pub fn new(indicator: &'static [&'static str], status: String) -> Spinner {
-        let dummy = !TERM_SETTINGS.indicate_progress;
+        let dummy = !indicate_progress();
         Spinner {
             indicator,
             status,
             i: 0,
             dummy,
             warnings: HashSet::new(),
+            started: Instant::now(),
         }
     }
 
@@ -130,7 +233,7 @@ impl Spinner {
             self.i = 0;
         }
 
-        let s = format!("\r\x1b[2K\x1b[1m[\x1b[32m{}\x1b[0;1m]\x1b[0m {}...", self.indicator[self.i], self.status);
+        let s = format!("\r\x1b[2K{} {}...", Tag(Style::Green, self.indicator[self.i]), self.status);
         self.i += 1;
 
         s
@@ -138,7 +241,7 @@ impl Spinner {
 
     pub fn done(&self) {
         if self.dummy { return; }
-        println!("\r\x1b[2K\x1b[1m[\x1b[32m{}\x1b[0;1m]\x1b[0m {}", '+', self.status);
+        println!("{}{} {}", clear_line(), Tag(Style::Green, '+'), self.status);
         io::stdout().flush().unwrap();
     }
 
@@ -163,27 +266,27 @@ impl Spinner {
 impl SpinLogger for Spinner {
     fn log(&mut self, line: &str) {
         if self.dummy { return; }
-        println!("\r\x1b[2K\x1b[1m[\x1b[34m{}\x1b[0;1m]\x1b[0m {}", '*', line);
+        println!("{}{} {}", clear_line(), Tag(Style::Blue, '*'), line);
     }
 
     fn debug(&mut self, line: &str) {
         if self.dummy { return; }
-        println!("\r\x1b[2K\x1b[1m[\x1b[34m{}\x1b[0;1m]\x1b[0m {}", '#', line);
+        println!("{}{} {}", clear_line(), Tag(Style::Blue, '#'), line);
     }
 
     fn success(&mut self, line: &str) {
         if self.dummy { return; }
-        println!("\r\x1b[2K\x1b[1m[\x1b[32m{}\x1b[0;1m]\x1b[0m {}", '+', line);
+        println!("{}{} {}", clear_line(), Tag(Style::Green, '+'), line);
     }
 
     fn error(&mut self, line: &str) {
         if self.dummy { return; }
-        println!("\r\x1b[2K\x1b[1m[\x1b[31m{}\x1b[0;1m]\x1b[0m {}", '-', line);
+        println!("{}{} {}", clear_line(), Tag(Style::Red, '-'), line);
     }
 
     fn warn(&mut self, line: &str) {
         if self.dummy { return; }
-        println!("\r\x1b[2K\x1b[1m[\x1b[33m{}\x1b[0;1m]\x1b[0m {}", '!', line);
+        println!("{}{} {}", clear_line(), Tag(Style::Yellow, '!'), line);
     }
 
     fn warn_once(&mut self, line: &str) {
@@ -205,23 +308,23 @@ impl SpinLogger for Spinner {
 }
 
 pub fn success(line: &str) {
-    println!("\x1b[1m[\x1b[34m{}\x1b[0;1m]\x1b[0m {}", '*', line);
+    print_diagnostic(format_args!("{} {}", Tag(Style::Blue, '*'), line));
 }
 
 pub fn info(line: &str) {
-    println!("\x1b[1m[\x1b[32m{}\x1b[0;1m]\x1b[0m {}", '+', line);
+    print_diagnostic(format_args!("{} {}", Tag(Style::Green, '+'), line));
 }
 
 pub fn debug(line: &str) {
-    println!("\x1b[2K\x1b[1m[\x1b[34m{}\x1b[0;1m]\x1b[0m {}", '#', line);
+    print_diagnostic(format_args!("{}{} {}", clear_line(), Tag(Style::Blue, '#'), line));
 }
 
 pub fn warn(line: &str) {
-    eprintln!("\x1b[1m[\x1b[33m{}\x1b[0;1m]\x1b[0m {}", '!', line);
+    print_line(true, format_args!("{} {}", Tag(Style::Yellow, '!'), line));
 }
 
 pub fn error(line: &str) {
-    eprintln!("\x1b[1m[\x1b[31m{}\x1b[0;1m]\x1b[0m {}", '-', line);
+    print_line(true, format_args!("{} {}", Tag(Style::Red, '-'), line));
 }
 
 pub struct Term;
@@ -290,11 +393,261 @@ impl fmt::Display for Prompt {
     }
 }
 
+impl Prompt {
+    /// The prompt in a custom format, counts that can't be read show up as `?`
+    pub fn render(&self, format: Option<&PromptFormat>, db: &Database) -> String {
+        let format = match format {
+            Some(format) => format,
+            None => return self.to_string(),
+        };
+
+        let mut prompt = String::new();
+        for segment in &format.segments {
+            match segment {
+                Segment::Text(text) => prompt.push_str(text),
+                Segment::Workspace => prompt.push_str(&self.workspace),
+                Segment::Module => if let Some(module) = &self.module {
+                    prompt.push_str(&module.canonical());
+                },
+                Segment::Scoped(table) => match db.count_scoped_cached(*table) {
+                    Ok(count) => write!(prompt, "{}", count).expect("out of memory"),
+                    Err(err) => {
+                        debug!("Failed to count {} for prompt: {}", table.as_str(), err);
+                        prompt.push('?');
+                    },
+                },
+            }
+        }
+        prompt
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Text(String),
+    Workspace,
+    Module,
+    /// The number of entities in scope
+    Scoped(Table),
+}
+
+impl FromStr for Segment {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Segment> {
+        match s {
+            "workspace" => Ok(Segment::Workspace),
+            "module" => Ok(Segment::Module),
+            _ => match s.parse::<Table>() {
+                Ok(Table::SubdomainIpaddrs | Table::NetworkDevices | Table::BreachEmails) | Err(_) => {
+                    bail!("Unknown placeholder {{{}}} in prompt, expected {{workspace}}, {{module}} or a table like {{subdomains}}", s)
+                },
+                Ok(table) => Ok(Segment::Scoped(table)),
+            },
+        }
+    }
+}
+
+/// The prompt of the shell from `core.prompt`, like `"[{workspace}] {module}> "`.
+/// Literal braces are written as `{{` and `}}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PromptFormat {
+    format: String,
+    segments: Vec<Segment>,
+}
+
+impl FromStr for PromptFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<PromptFormat> {
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut rest = s;
+        while let Some(c) = rest.chars().next() {
+            rest = &rest[c.len_utf8()..];
+            match c {
+                '{' | '}' if rest.starts_with(c) => {
+                    text.push(c);
+                    rest = &rest[1..];
+                },
+                '{' => {
+                    let end = rest.find('}')
+                        .ok_or_else(|| format_err!("Placeholder in prompt isn't closed, use {{{{ for a literal {{"))?;
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(mem::take(&mut text)));
+                    }
+                    segments.push(rest[..end].parse()?);
+                    rest = &rest[end + 1..];
+                },
+                '}' => bail!("Unmatched }} in prompt, use }}}} for a literal }}"),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+
+        Ok(PromptFormat {
+            format: s.to_string(),
+            segments,
+        })
+    }
+}
+
+impl Serialize for PromptFormat {
+    fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_str(&self.format)
+    }
+}
+
+impl<'de> Deserialize<'de> for PromptFormat {
+    fn deserialize<D>(deserializer: D) -> result::Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let s = String::deserialize(deserializer)?;
+        FromStr::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+/// The output of a run, with a line for every investigation in progress
+pub trait Stack: SpinLogger + Sized {
+    fn add(&mut self, key: String, status: String);
+
+    /// Show the progress of a run with this many investigations
+    fn track(&mut self, _total: usize) {
+    }
+
+    fn finished(&mut self, _outcome: Outcome) {
+    }
+
+    fn remove(&mut self, key: &str);
+
+    fn jump2start(&mut self);
+
+    fn tick(&mut self);
+
+    fn clear(&self);
+
+    /// How an investigation of a target is called in the output
+    fn source_name(target: &str) -> String {
+        format!("{:?}", target)
+    }
+
+    #[inline]
+    fn prefixed<I: Into<String>>(&mut self, name: I) -> PrefixedLogger<Self> {
+        PrefixedLogger::new(self, name)
+    }
+}
+
+/// How an investigation of a run ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Ok,
+    Failed,
+    /// The module couldn't run, for example because of offline mode
+    Skipped,
+}
+
+/// The rate is estimated from the last finished investigations only
+const RATE_WINDOW: usize = 20;
+/// How often the progress is printed if there are no spinners
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(10);
+const BAR_WIDTH: usize = 30;
+
+/// Short durations like `45s`, `3m05s` or `1h02m`
+fn duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    if secs < 60 {
+        format!("{}s", secs)
+    } else if secs < 3600 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    }
+}
+
+/// The progress of a run with a known number of targets
+#[derive(Debug)]
+pub struct Progress {
+    total: usize,
+    ok: usize,
+    failed: usize,
+    skipped: usize,
+    started: Instant,
+    finished: VecDeque<Instant>,
+}
+
+impl Progress {
+    pub fn new(total: usize, now: Instant) -> Progress {
+        Progress {
+            total,
+            ok: 0,
+            failed: 0,
+            skipped: 0,
+            started: now,
+            finished: VecDeque::new(),
+        }
+    }
+
+    pub fn done(&mut self, outcome: Outcome, now: Instant) {
+        match outcome {
+            Outcome::Ok => self.ok += 1,
+            Outcome::Failed => self.failed += 1,
+            Outcome::Skipped => self.skipped += 1,
+        }
+        if self.finished.len() == RATE_WINDOW {
+            self.finished.pop_front();
+        }
+        self.finished.push_back(now);
+    }
+
+    #[inline]
+    fn completed(&self) -> usize {
+        self.ok + self.failed + self.skipped
+    }
+
+    /// The remaining time at the rate of the last investigations, this
+    /// needs at least one finished investigation
+    pub fn eta(&self, now: Instant) -> Option<Duration> {
+        let (since, n) = if self.finished.len() == RATE_WINDOW {
+            (self.finished[0], RATE_WINDOW - 1)
+        } else {
+            (self.started, self.finished.len())
+        };
+        if n == 0 {
+            return None;
+        }
+        let remaining = self.total.saturating_sub(self.completed());
+        let elapsed = now.duration_since(since);
+        Some(elapsed.mul_f64(remaining as f64 / n as f64))
+    }
+
+    fn summary(&self, now: Instant) -> String {
+        let mut summary = format!("{}/{} done, {} ok, {} failed, {} skipped",
+            self.completed(), self.total, self.ok, self.failed, self.skipped);
+        if let Some(eta) = self.eta(now) {
+            write!(summary, ", eta {}", duration(eta)).expect("out of memory");
+        }
+        summary
+    }
+
+    fn bar(&self, now: Instant) -> String {
+        let filled = (self.completed() * BAR_WIDTH).checked_div(self.total)
+            .map(|x| cmp::min(x, BAR_WIDTH))
+            .unwrap_or(BAR_WIDTH);
+        format!("[{}{}] {}", "=".repeat(filled), " ".repeat(BAR_WIDTH - filled), self.summary(now))
+    }
+}
+
 pub struct StackedSpinners {
     spinners: HashMap<String, Spinner>,
     drawn: usize,
     dummy: bool,
     warnings: HashSet<String>,
+    progress: Option<Progress>,
+    summarized: Instant,
 }
 
 impl Default for StackedSpinners {
@@ -306,12 +659,14 @@ impl Default for StackedSpinners {
 impl StackedSpinners {
     #[inline]
     pub fn new() -> StackedSpinners {
-        let dummy = !TERM_SETTINGS.indicate_progress;
+        let dummy = !indicate_progress();
         StackedSpinners {
             spinners: HashMap::new(),
             drawn: 0,
             dummy,
             warnings: HashSet::new(),
+            progress: None,
+            summarized: Instant::now(),
         }
     }
 
@@ -335,21 +690,34 @@ impl StackedSpinners {
     }
 
     pub fn tick(&mut self) {
-        if self.dummy { return; }
+        let now = Instant::now();
+        if self.dummy {
+            // without a terminal the progress is printed as a line every now
+            // and then, on stderr so it doesn't end up in the output
+            if let Some(progress) = &self.progress {
+                if now.duration_since(self.summarized) >= SUMMARY_INTERVAL {
+                    eprintln!("{} {}, {} running", Tag(Style::Blue, '*'), progress.summary(now), self.spinners.len());
+                    self.summarized = now;
+                }
+            }
+            return;
+        }
         self.jump2start();
 
-        if self.spinners.is_empty() {
-            return;
+        let mut lines = Vec::new();
+        if let Some(progress) = &self.progress {
+            lines.push(format!("\r\x1b[2K{}", progress.bar(now)));
+        }
+        for s in self.spinners.values_mut() {
+            let elapsed = duration(now.duration_since(s.started));
+            lines.push(format!("{} ({})", s.tick_bytes(), elapsed));
         }
 
-        let n = self.spinners.len() -1;
-        for (i, (_, s)) in self.spinners.iter_mut().enumerate() {
-            print!("{}", s.tick_bytes());
-            if i < n {
-                println!();
-                self.drawn += 1;
-            }
+        if lines.is_empty() {
+            return;
         }
+        print!("{}", lines.join("\n"));
+        self.drawn = lines.len() - 1;
         io::stdout().flush().unwrap();
     }
 
@@ -364,37 +732,69 @@ impl StackedSpinners {
         print!("\r\x1b[2K");
         io::stdout().flush().unwrap();
     }
+}
 
+impl Stack for StackedSpinners {
     #[inline]
-    pub fn prefixed<I: Into<String>>(&mut self, name: I) -> PrefixedLogger<StackedSpinners> {
-        PrefixedLogger::new(self, name)
+    fn add(&mut self, key: String, status: String) {
+        StackedSpinners::add(self, key, status)
+    }
+
+    fn track(&mut self, total: usize) {
+        self.progress = Some(Progress::new(total, Instant::now()));
+    }
+
+    fn finished(&mut self, outcome: Outcome) {
+        if let Some(progress) = &mut self.progress {
+            progress.done(outcome, Instant::now());
+        }
+    }
+
+    #[inline]
+    fn remove(&mut self, key: &str) {
+        StackedSpinners::remove(self, key);
+    }
+
+    #[inline]
+    fn jump2start(&mut self) {
+        StackedSpinners::jump2start(self)
+    }
+
+    #[inline]
+    fn tick(&mut self) {
+        StackedSpinners::tick(self)
+    }
+
+    #[inline]
+    fn clear(&self) {
+        StackedSpinners::clear(self)
     }
 }
 
 impl SpinLogger for StackedSpinners {
     fn log(&mut self, line: &str) {
         self.jump2start();
-        println!("\r\x1b[2K\x1b[1m[\x1b[34m{}\x1b[0;1m]\x1b[0m {}", '*', line);
+        println!("{}{} {}", clear_line(), Tag(Style::Blue, '*'), line);
     }
 
     fn debug(&mut self, line: &str) {
         self.jump2start();
-        println!("\r\x1b[2K\x1b[1m[\x1b[34m{}\x1b[0;1m]\x1b[0m {}", '#', line);
+        println!("{}{} {}", clear_line(), Tag(Style::Blue, '#'), line);
     }
 
     fn success(&mut self, line: &str) {
         self.jump2start();
-        println!("\r\x1b[2K\x1b[1m[\x1b[32m{}\x1b[0;1m]\x1b[0m {}", '+', line);
+        println!("{}{} {}", clear_line(), Tag(Style::Green, '+'), line);
     }
 
     fn error(&mut self, line: &str) {
         self.jump2start();
-        println!("\r\x1b[2K\x1b[1m[\x1b[31m{}\x1b[0;1m]\x1b[0m {}", '-', line);
+        println!("{}{} {}", clear_line(), Tag(Style::Red, '-'), line);
     }
 
     fn warn(&mut self, line: &str) {
         self.jump2start();
-        println!("\r\x1b[2K\x1b[1m[\x1b[33m{}\x1b[0;1m]\x1b[0m {}", '!', line);
+        println!("{}{} {}", clear_line(), Tag(Style::Yellow, '!'), line);
     }
 
     #[inline]
@@ -434,27 +834,27 @@ impl<'a, T: SpinLogger> PrefixedLogger<'a, T> {
 impl<'a, T: SpinLogger> SpinLogger for PrefixedLogger<'a, T> {
     #[inline]
     fn log(&mut self, line: &str) {
-        self.s.log(&format!("{:50}: {}", self.prefix, line))
+        self.s.log_for(&self.prefix, Level::Info, line)
     }
 
     #[inline]
     fn debug(&mut self, line: &str) {
-        self.s.debug(&format!("{:50}: {}", self.prefix, line))
+        self.s.log_for(&self.prefix, Level::Debug, line)
     }
 
     #[inline]
     fn success(&mut self, line: &str) {
-        self.s.success(&format!("{:50}: {}", self.prefix, line))
+        self.s.log_for(&self.prefix, Level::Success, line)
     }
 
     #[inline]
     fn error(&mut self, line: &str) {
-        self.s.error(&format!("{:50}: {}", self.prefix, line))
+        self.s.log_for(&self.prefix, Level::Error, line)
     }
 
     #[inline]
     fn warn(&mut self, line: &str) {
-        self.s.warn(&format!("{:50}: {}", self.prefix, line))
+        self.s.log_for(&self.prefix, Level::Warn, line)
     }
 
     #[inline]
@@ -471,4 +871,69 @@ impl<'a, T: SpinLogger> SpinLogger for PrefixedLogger<'a, T> {
     fn stacked_status(&mut self, prefix: &str, status: String) {
         self.s.stacked_status(prefix, status)
     }
+
+    #[inline]
+    fn event(&mut self, name: Option<&str>, event: RunEvent) {
+        self.s.event(Some(name.unwrap_or(&self.prefix)), event)
+    }
+
+    #[inline]
+    fn structured(&self) -> bool {
+        self.s.structured()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::connection::SimpleConnection;
+
+    #[test]
+    fn test_prompt_format() {
+        let db = Database::memory().unwrap();
+        db.db().batch_execute("INSERT INTO domains (value, unscoped) VALUES ('example.com', 0), ('example.org', 1)").unwrap();
+        let prompt = Prompt::new("demo".to_string());
+
+        let format = "[{workspace}] {{{domains}/{ipaddrs}}} {module}> ".parse::<PromptFormat>().unwrap();
+        assert_eq!(prompt.render(Some(&format), &db), "[demo] {1/0} > ");
+        assert_eq!(prompt.render(None, &db), "[sn0int][demo] > ");
+    }
+
+    #[test]
+    fn test_progress() {
+        let start = Instant::now();
+        let mut progress = Progress::new(10, start);
+        assert_eq!(progress.eta(start), None);
+        assert_eq!(progress.bar(start), format!("[{}] 0/10 done, 0 ok, 0 failed, 0 skipped", " ".repeat(30)));
+
+        progress.done(Outcome::Ok, start + Duration::from_secs(10));
+        progress.done(Outcome::Failed, start + Duration::from_secs(20));
+        progress.done(Outcome::Skipped, start + Duration::from_secs(20));
+        let now = start + Duration::from_secs(30);
+        // 3 in 30s, 7 to go
+        assert_eq!(progress.eta(now), Some(Duration::from_secs(70)));
+        assert_eq!(progress.summary(now), "3/10 done, 1 ok, 1 failed, 1 skipped, eta 1m10s");
+        assert!(progress.bar(now).starts_with("[=========                     ]"));
+    }
+
+    #[test]
+    fn test_progress_rolling_rate() {
+        let start = Instant::now();
+        let mut progress = Progress::new(100, start);
+        // a slow start doesn't matter once enough investigations finished
+        for i in 0..30 {
+            progress.done(Outcome::Ok, start + Duration::from_secs(600 + i));
+        }
+        // one per second since the oldest one of the window, 70 to go
+        let now = start + Duration::from_secs(629);
+        assert_eq!(progress.eta(now).unwrap().as_secs_f64().round(), 70.0);
+    }
+
+    #[test]
+    fn test_prompt_format_invalid() {
+        assert!("{workspace".parse::<PromptFormat>().is_err());
+        assert!("workspace}".parse::<PromptFormat>().is_err());
+        assert!("{target}".parse::<PromptFormat>().is_err());
+        assert!("{subdomain_ipaddrs}".parse::<PromptFormat>().is_err());
+    }
 }
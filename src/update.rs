@@ -1,7 +1,9 @@
 use crate::errors::*;
 use crate::api::Client;
-use crate::config::Config;
+use crate::config::{Config, DEFAULT_REGISTRY};
 use crate::engine;
+use crate::git::GitOrigins;
+use crate::lockfile::Lockfile;
 use crate::paths;
 use serde::{Serialize, Deserialize};
 use std::collections::HashSet;
@@ -39,9 +41,12 @@ impl AutoUpdater {
             .as_secs()
     }
 
+    /// Pinned modules are skipped by `pkg update` and aren't counted
     #[inline]
-    pub fn outdated(&self) -> usize {
-        self.outdated.len()
+    pub fn outdated(&self, lockfile: &Lockfile) -> usize {
+        self.outdated.iter()
+            .filter(|module| !lockfile.is_pinned(module))
+            .count()
     }
 
     #[inline]
@@ -72,7 +77,7 @@ impl AutoUpdater {
     }
 
     pub fn check_background(mut self, config: &Config, modules: Vec<&engine::Module>) {
-        if config.core.no_autoupdate {
+        if config.core.no_autoupdate || config.network.offline {
             debug!("Auto update has been disabled, skipping");
             return;
         }
@@ -105,6 +110,8 @@ impl AutoUpdater {
 
         if latest.time != self.registry {
             let mut outdated = HashSet::new();
+            let origins = GitOrigins::load()?;
+            let lockfile = Lockfile::load()?;
 
             for module in modules {
                 if module.is_private() {
@@ -112,6 +119,19 @@ impl AutoUpdater {
                     continue;
                 }
 
+                if origins.get(&module.canonical()).is_some() {
+                    debug!("{} has been installed from git, skipping", module.canonical());
+                    continue;
+                }
+
+                // only the default registry is checked in the background
+                if let Some(registry) = lockfile.get(&module.canonical()).and_then(|x| x.registry.as_ref()) {
+                    if registry != DEFAULT_REGISTRY {
+                        debug!("{} has been installed from {}, skipping", module.canonical(), registry);
+                        continue;
+                    }
+                }
+
                 let installed = module.version();
                 if let Ok(infos) = client.query_module(&module.id()) {
                     debug!("Latest version: {:?}", infos);
@@ -1,4 +1,6 @@
 use crate::errors::*;
+use crate::fmt::colors::Style;
+use crate::term::Tag;
 use rand::{Rng, thread_rng};
 use rand::distributions::Alphanumeric;
 use rustyline::error::ReadlineError;
@@ -30,10 +32,57 @@ pub fn read_line(prompt: &str) -> Result<String> {
 }
 
 pub fn question(text: &str) -> Result<String> {
-    let prompt = format!("\x1b[1m[\x1b[34m?\x1b[0;1m]\x1b[0m {}: ", text);
+    let prompt = format!("{} {}: ", Tag(Style::Blue, '?'), text);
     read_line(&prompt)
 }
 
+/// Read a line from the terminal without echoing it
+#[cfg(unix)]
+pub fn question_password(text: &str) -> Result<String> {
+    use std::io::{self, BufRead, Write};
+    use std::mem;
+
+    struct EchoGuard(libc::termios);
+
+    impl Drop for EchoGuard {
+        fn drop(&mut self) {
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.0) };
+        }
+    }
+
+    print!("{} {}: ", Tag(Style::Blue, '?'), text);
+    io::stdout().flush()?;
+
+    let _guard = unsafe {
+        let mut term: libc::termios = mem::zeroed();
+        if libc::tcgetattr(libc::STDIN_FILENO, &mut term) != 0 {
+            bail!("Failed to get terminal attributes: {}", io::Error::last_os_error());
+        }
+        let guard = EchoGuard(term);
+
+        term.c_lflag &= !libc::ECHO;
+        term.c_lflag |= libc::ECHONL;
+        if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &term) != 0 {
+            bail!("Failed to disable terminal echo: {}", io::Error::last_os_error());
+        }
+        guard
+    };
+
+    let mut line = String::new();
+    if io::stdin().lock().read_line(&mut line)? == 0 {
+        bail!("Failed to read line from input");
+    }
+    if let Some(idx) = line.find('\n') {
+        line.truncate(idx);
+    }
+    Ok(line)
+}
+
+#[cfg(not(unix))]
+pub fn question_password(_text: &str) -> Result<String> {
+    bail!("Password prompts are not supported on this platform")
+}
+
 pub fn question_opt(text: &str) -> Result<Option<String>> {
     let answer = question(text)?;
 
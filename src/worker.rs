@@ -3,31 +3,53 @@ use serde::{Serialize, Deserialize};
 
 use crate::blobs::Blob;
 use crate::channel;
+use crate::compression;
 use crate::cmd::run_cmd::Params;
-use crate::db::{DbChange, Family};
+use crate::db::{DbChange, Family, Query, ScopeDecision};
+use crate::db::batch::Batch;
 use crate::db::ttl::Ttl;
-use crate::engine::Module;
+use crate::config::{NetworkConfig, SandboxConfig};
+use crate::engine::{Entrypoint, Module};
+use crate::error_info::OfflineError;
 use crate::ipc;
+use crate::ipc::concurrency::{ConcurrencyEvent, ConcurrencyLimit};
+use crate::ipc::files::{FileEvent, ReadGrants};
+use crate::ipc::network::NetworkEvent;
 use crate::ipc::parent::IpcParent;
+use crate::keyring::{KeyName, KeyRingEntry, KeyRotation, UsageCounter};
 use crate::models::*;
-use crate::notify::{self, Notification};
+use crate::notify::{self, Notification, Outbox};
+use crate::output::{JsonLines, OutputFormat, RunEvent};
 use crate::ratelimits::{Ratelimiter, RatelimitResponse};
-use crate::shell::Shell;
+use crate::sandbox::Profile;
+use crate::shell::{Shell, SignalRegister};
+use sn0int_common::metadata::Source;
 use sn0int_std::ratelimits::RatelimitSender;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::fmt::Write;
+use std::mem;
 use std::result;
 use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::AtomicUsize;
 use std::time::Duration;
 use std::thread;
-use std::io::{Stdin, Read, BufRead, BufReader};
+use std::io::BufRead;
 use std::net::SocketAddr;
-use crate::term::{Spinner, StackedSpinners, SpinLogger};
+use crate::fmt::colors::Style;
+use crate::term::{self, Outcome, Spinner, Stack, StackedSpinners, SpinLogger, Tag};
+use crate::utils;
+use chrono::{NaiveDateTime, Utc};
 use threadpool::ThreadPool;
 
 
 type DbSender = mpsc::Sender<result::Result<DatabaseResponse, String>>;
 pub type VoidSender = mpsc::Sender<result::Result<(), String>>;
+type PromptSender = mpsc::Sender<result::Result<PromptResponse, String>>;
+type NotifySender = mpsc::Sender<result::Result<bool, String>>;
+type KeyringSender = mpsc::Sender<result::Result<Option<KeyRingEntry>, String>>;
+
+/// Number of notifications a module may send directly during a single run
+const NOTIFY_LIMIT: usize = 10;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum DatabaseResponse {
@@ -35,6 +57,7 @@ pub enum DatabaseResponse {
     Updated(i32),
     Found(i32),
     NoChange(i32),
+    Rows(Vec<serde_json::Value>),
     None,
 }
 
@@ -43,8 +66,16 @@ pub enum Event {
     Log(LogEvent),
     Database(Box<DatabaseEvent>),
     Stdio(StdioEvent),
+    File(FileEvent),
     Ratelimit(RatelimitEvent),
+    Concurrency(ConcurrencyEvent),
+    Network(NetworkEvent),
+    Keyring(KeyringEvent),
     Blob(Blob),
+    Prompt(PromptEvent),
+    Notify(NotifyEvent),
+    /// The script defines an `on_finish` function
+    OnFinish,
     Exit(ExitEvent),
 }
 
@@ -54,7 +85,11 @@ pub enum Event2 {
     Log(LogEvent),
     Database(Box<(DatabaseEvent, DbSender)>),
     Ratelimit((RatelimitEvent, RatelimitSender)),
+    Keyring((KeyringEvent, KeyringSender)),
     Blob((Blob, VoidSender)),
+    Prompt((PromptEvent, PromptSender)),
+    Notify((NotifyEvent, NotifySender)),
+    OnFinish,
     Exit(ExitEvent),
 }
 
@@ -109,6 +144,8 @@ pub trait EventWithCallback {
 pub enum ExitEvent {
     Ok,
     Err(String),
+    /// The module failed because it tried to use the network in offline mode
+    Offline(String),
     SetupFailed(String),
 }
 
@@ -117,11 +154,17 @@ impl From<Result<()>> for ExitEvent {
         match result {
             Ok(_) => ExitEvent::Ok,
             Err(err) => {
+                let offline = err.iter_chain()
+                    .any(|e| e.downcast_ref::<OfflineError>().is_some());
                 let err = err.iter_chain()
                     .map(|e| e.to_string())
                     .collect::<Vec<_>>()
                     .join(": ");
-                ExitEvent::Err(err)
+                if offline {
+                    ExitEvent::Offline(err)
+                } else {
+                    ExitEvent::Err(err)
+                }
             },
         }
     }
@@ -139,6 +182,12 @@ pub enum LogEvent {
 }
 
 impl LogEvent {
+    /// Info and debug lines are hidden with `-q`
+    #[inline]
+    fn is_chatter(&self) -> bool {
+        matches!(self, LogEvent::Info(_) | LogEvent::Debug(_))
+    }
+
     pub fn apply<T: SpinLogger>(self, spinner: &mut T) {
         match self {
             LogEvent::Info(info) => spinner.log(&info),
@@ -157,8 +206,11 @@ pub enum DatabaseEvent {
     Insert(Insert),
     InsertTtl((Insert, i32)),
     Activity(NewActivity),
+    NetworkAudit(InsertNetworkAudit),
     Select((Family, String)),
+    Query(Query),
     Update((Family, String, Update)),
+    UpdateFields((Family, i32, serde_json::Map<String, serde_json::Value>)),
 }
 
 impl EventWithCallback for DatabaseEvent {
@@ -170,36 +222,67 @@ impl EventWithCallback for DatabaseEvent {
 }
 
 impl DatabaseEvent {
-    fn notify<T: SpinLogger>(rl: &mut Shell, spinner: &mut T, ratelimit: &mut Ratelimiter, topic: &str, subject: String) {
-        if let Err(err) = notify::trigger_notify_event(rl, spinner, ratelimit, topic, &Notification {
+    /// Notifications are delivered after the change has been committed
+    fn notify(queued: &mut Vec<(String, Notification)>, topic: String, subject: String, entity: Option<serde_json::Value>) {
+        queued.push((topic, Notification {
             subject,
             body: None,
-        }) {
-            spinner.error(&format!("Failed to send notifications: {}", err));
+            entity,
+        }));
+    }
+
+    /// The entity is only looked up if there are notification rules that may
+    /// need it or it's part of the output
+    fn entity(rl: &Shell, structured: bool, family: &str, id: i32) -> Option<serde_json::Value> {
+        if !structured && rl.config().notifications.is_empty() {
+            return None;
+        }
+        let family = family.parse::<Family>().ok()?;
+        match rl.db().entity_json(&family, id) {
+            Ok(entity) => Some(entity),
+            Err(err) => {
+                debug!("Failed to load entity for notification: {:?}", err);
+                None
+            },
         }
     }
 
-    fn on_insert<T: SpinLogger>(rl: &mut Shell, spinner: &mut T, ratelimit: &mut Ratelimiter, family: &str, value: &str) {
+    fn on_insert<T: SpinLogger>(rl: &mut Shell, spinner: &mut T, queued: &mut Vec<(String, Notification)>, family: &str, value: &str, id: i32) {
         // TODO: also include fields, see update
         let log = format!("Adding {} {:?}", family, value);
         spinner.log(&log);
 
+        let entity = Self::entity(rl, spinner.structured(), family, id);
+        spinner.event(None, RunEvent::EntityInserted {
+            family: family.to_string(),
+            value: value.to_string(),
+            entity: entity.clone(),
+        });
+
         let subject = format!("Added {} {:?}", family, value);
         let topic = format!("db:{}:{}:insert", family, value);
-        Self::notify(rl, spinner, ratelimit, &topic, subject);
+        Self::notify(queued, topic, subject, entity);
     }
 
-    fn on_update<T: SpinLogger>(rl: &mut Shell, spinner: &mut T, ratelimit: &mut Ratelimiter, family: &str, value: &str, update: &Update) {
+    fn on_update<T: SpinLogger>(rl: &mut Shell, spinner: &mut T, queued: &mut Vec<(String, Notification)>, family: &str, value: &str, id: i32, update: &Update) {
         spinner.log(&format!("Updating {} {:?} ({})", family, value, update.to_term_str()));
 
         // TODO: in the future we could consider firing multiple events, one for each column
         // TODO: this would be super noisy if a lot of fields change though
+        let entity = Self::entity(rl, spinner.structured(), family, id);
+        spinner.event(None, RunEvent::EntityUpdated {
+            family: family.to_string(),
+            value: value.to_string(),
+            changes: update.to_plain_str(),
+            entity: entity.clone(),
+        });
+
         let subject = format!("Updated {} {:?} ({})", family, value, update.to_plain_str());
         let topic = format!("db:{}:{}:update", family, value);
-        Self::notify(rl, spinner, ratelimit, &topic, subject);
+        Self::notify(queued, topic, subject, entity);
     }
 
-    fn on_activity<T: SpinLogger>(rl: &mut Shell, spinner: &mut T, ratelimit: &mut Ratelimiter, object: &NewActivity, verbose: u8) {
+    fn on_activity<T: SpinLogger>(spinner: &mut T, queued: &mut Vec<(String, Notification)>, object: &NewActivity, verbose: u8) {
         Self::spinner_log_new_activity(spinner, object, verbose);
 
         // TODO: we don't want to copy the match arms everywhere
@@ -208,7 +291,8 @@ impl DatabaseEvent {
             write!(subject, " ({:?})", uniq).expect("out of memory");
         }
         let topic = format!("activity:{}", object.topic);
-        Self::notify(rl, spinner, ratelimit, &topic, subject);
+        let entity = serde_json::to_value(object).ok();
+        Self::notify(queued, topic, subject, entity);
     }
 
     fn spinner_log_new_activity<T: SpinLogger>(spinner: &mut T, object: &NewActivity, verbose: u8) {
@@ -233,11 +317,14 @@ impl DatabaseEvent {
         spinner.log(&log);
     }
 
-    fn insert<T: SpinLogger>(rl: &mut Shell, spinner: &mut T, ratelimit: &mut Ratelimiter, object: Insert, ttl: Option<i32>, tx: DbSender, verbose: u8) {
+    fn insert<T: SpinLogger>(rl: &mut Shell, spinner: &mut T, queued: &mut Vec<(String, Notification)>, object: Insert, ttl: Option<i32>, tx: DbSender, verbose: u8) {
         let db = rl.db();
-        if verbose >= 1 {
+        let decision = if verbose >= 1 {
             spinner.debug(&format!("Inserting: {:?}", object));
-        }
+            db.scope_decision(&object).ok()
+        } else {
+            None
+        };
 
         let result = db.insert_generic(object.clone());
         debug!("{:?} => {:?}", object, result);
@@ -246,13 +333,21 @@ impl DatabaseEvent {
             Ok(Some((DbChange::Insert, id))) => {
                 match object.value(rl.db()) {
                     Ok(value) => {
+                        match decision {
+                            Some(ScopeDecision::Default) | None => (),
+                            Some(decision) => {
+                                let scope = if decision.scoped() { "in scope" } else { "out of scope" };
+                                spinner.debug(&format!("{:?} is {}, {}", value.to_string(), scope, decision));
+                            },
+                        }
+
                         if let Some(ttl) = ttl {
                             if let Err(err) = Ttl::create(&object, id, value.to_string(), ttl, db) {
                                 spinner.error(&format!("Failed to set ttl: {:?}", err));
                             }
                         }
 
-                        Self::on_insert(rl, spinner, ratelimit, object.family(), &value);
+                        Self::on_insert(rl, spinner, queued, object.family(), &value, id);
                     }
                     Err(err) => {
                         spinner.error(&format!("Failed to query necessary fields for {:?}: {:?}", object, err));
@@ -269,7 +364,7 @@ impl DatabaseEvent {
                 }
 
                 match object.value(rl.db()) {
-                    Ok(value) => Self::on_update(rl, spinner, ratelimit, object.family(), &value, &update),
+                    Ok(value) => Self::on_update(rl, spinner, queued, object.family(), &value, id, &update),
                     Err(err) => {
                         // TODO: this should be unreachable
                         spinner.error(&format!("Failed to get label for {:?}: {:?}", object, err));
@@ -299,14 +394,15 @@ impl DatabaseEvent {
     }
 
 
-    pub fn activity<T: SpinLogger>(rl: &mut Shell, spinner: &mut T, ratelimit: &mut Ratelimiter, object: NewActivity, tx: DbSender, verbose: u8) {
+    pub fn activity<T: SpinLogger>(rl: &mut Shell, spinner: &mut T, queued: &mut Vec<(String, Notification)>, object: NewActivity, tx: DbSender, verbose: u8) {
         let db = rl.db();
-        let result = db.insert_activity(object.clone());
+        let dedup = rl.config().activity.dedup_policy(&object.topic);
+        let result = db.insert_activity(object.clone(), dedup);
         debug!("{:?} => {:?}", object, result);
 
         let result = match result {
             Ok(true) => {
-                Self::on_activity(rl, spinner, ratelimit, &object, verbose);
+                Self::on_activity(spinner, queued, &object, verbose);
                 Ok(DatabaseResponse::Inserted(0))
             },
             Ok(false) => Ok(DatabaseResponse::NoChange(0)),
@@ -320,7 +416,7 @@ impl DatabaseEvent {
         tx.send(result).expect("Failed to send db result to channel");
     }
 
-    pub fn update<T: SpinLogger>(rl: &mut Shell, spinner: &mut T, ratelimit: &mut Ratelimiter, family: &str, value: &str, update: &Update, tx: DbSender, verbose: u8) {
+    pub fn update<T: SpinLogger>(rl: &mut Shell, spinner: &mut T, queued: &mut Vec<(String, Notification)>, family: &str, value: &str, update: &Update, tx: DbSender, verbose: u8) {
         let db = rl.db();
         if verbose >= 1 {
             spinner.debug(&format!("Updating: {:?}", update));
@@ -331,7 +427,7 @@ impl DatabaseEvent {
 
         let result = match result {
             Ok(id) => {
-                Self::on_update(rl, spinner, ratelimit, family, value, update);
+                Self::on_update(rl, spinner, queued, family, value, id, update);
                 Ok(DatabaseResponse::Updated(id))
             },
             Err(err) => {
@@ -344,11 +440,35 @@ impl DatabaseEvent {
         tx.send(result).expect("Failed to send db result to channel");
     }
 
-    pub fn apply<T: SpinLogger>(self, rl: &mut Shell, spinner: &mut T, ratelimit: &mut Ratelimiter, tx: DbSender, verbose: u8) {
+    pub fn update_fields<T: SpinLogger>(rl: &mut Shell, spinner: &mut T, queued: &mut Vec<(String, Notification)>, family: &Family, id: i32, fields: serde_json::Map<String, serde_json::Value>, tx: DbSender, verbose: u8) {
+        let db = rl.db();
+        match db.prepare_fields_update(family, id, fields) {
+            Ok((value, update)) if update.is_dirty() => {
+                Self::update(rl, spinner, queued, family.as_str(), &value, &Update::Fields(update), tx, verbose);
+            },
+            Ok(_) => {
+                tx.send(Ok(DatabaseResponse::NoChange(id))).expect("Failed to send db result to channel");
+            },
+            Err(err) => {
+                let err = err.to_string();
+                spinner.error(&err);
+                tx.send(Err(err)).expect("Failed to send db result to channel");
+            },
+        }
+    }
+
+    pub fn apply<T: SpinLogger>(self, rl: &mut Shell, spinner: &mut T, queued: &mut Vec<(String, Notification)>, tx: DbSender, verbose: u8) {
         match self {
-            DatabaseEvent::Insert(object) => Self::insert(rl, spinner, ratelimit, object, None, tx, verbose),
-            DatabaseEvent::InsertTtl((object, ttl)) => Self::insert(rl, spinner, ratelimit, object, Some(ttl), tx, verbose),
-            DatabaseEvent::Activity(object) => Self::activity(rl, spinner, ratelimit, object, tx, verbose),
+            DatabaseEvent::Insert(object) => Self::insert(rl, spinner, queued, object, None, tx, verbose),
+            DatabaseEvent::InsertTtl((object, ttl)) => Self::insert(rl, spinner, queued, object, Some(ttl), tx, verbose),
+            DatabaseEvent::Activity(object) => Self::activity(rl, spinner, queued, object, tx, verbose),
+            DatabaseEvent::NetworkAudit(object) => {
+                let result = rl.db().insert_network_audit(object)
+                    .map(|_| DatabaseResponse::Inserted(0))
+                    .map_err(|e| e.to_string());
+
+                tx.send(result).expect("Failed to send db result to channel");
+            },
             DatabaseEvent::Select((family, value)) => {
                 let db = rl.db();
                 let result = match db.get_opt(&family, &value) {
@@ -359,7 +479,15 @@ impl DatabaseEvent {
 
                 tx.send(result).expect("Failed to send db result to channel");
             },
-            DatabaseEvent::Update((family, value, update)) => Self::update(rl, spinner, ratelimit, family.as_str(), &value, &update, tx, verbose),
+            DatabaseEvent::Query(query) => {
+                let result = rl.db().query(&query)
+                    .map(DatabaseResponse::Rows)
+                    .map_err(|e| e.to_string());
+
+                tx.send(result).expect("Failed to send db result to channel");
+            },
+            DatabaseEvent::Update((family, value, update)) => Self::update(rl, spinner, queued, family.as_str(), &value, &update, tx, verbose),
+            DatabaseEvent::UpdateFields((family, id, fields)) => Self::update_fields(rl, spinner, queued, &family, id, fields, tx, verbose),
         }
     }
 }
@@ -370,9 +498,30 @@ pub enum StdioEvent {
     ToEnd,
 }
 
+/// Stdin of sn0int, shared by all children of a run
+///
+/// It's only checked for compression once the first module reads from it, so
+/// modules that never read stdin don't block.
+#[derive(Default)]
+pub struct SharedStdin {
+    reader: Mutex<Option<Box<dyn BufRead + Send>>>,
+}
+
+impl SharedStdin {
+    fn with<T, F: FnOnce(&mut dyn BufRead) -> Result<T>>(&self, f: F) -> Result<T> {
+        let mut reader = self.reader.lock().unwrap();
+        let reader = match &mut *reader {
+            Some(reader) => reader,
+            reader => reader.insert(compression::stdin()?),
+        };
+        f(reader)
+    }
+}
+
 impl StdioEvent {
-    fn read_line(reader: &mut Option<BufReader<Stdin>>) -> Result<Option<String>> {
-        if let Some(ref mut reader) = reader {
+    fn read_line(stdin: Option<&SharedStdin>) -> Result<Option<String>> {
+        let stdin = stdin.ok_or_else(|| format_err!("stdin is unavailable"))?;
+        stdin.with(|reader| {
             let mut line = String::new();
             let len = reader.read_line(&mut line)?;
             debug!("stdin: {:?}", line);
@@ -382,13 +531,12 @@ impl StdioEvent {
             } else {
                 Ok(None)
             }
-        } else {
-            bail!("stdin is unavailable");
-        }
+        })
     }
 
-    fn read_to_end(reader: &mut Option<BufReader<Stdin>>) -> Result<Option<String>> {
-        if let Some(ref mut reader) = reader {
+    fn read_to_end(stdin: Option<&SharedStdin>) -> Result<Option<String>> {
+        let stdin = stdin.ok_or_else(|| format_err!("stdin is unavailable"))?;
+        stdin.with(|reader| {
             let mut buf = String::new();
             let len = reader.read_to_string(&mut buf)?;
 
@@ -397,15 +545,13 @@ impl StdioEvent {
             } else {
                 Ok(None)
             }
-        } else {
-            bail!("stdin is unavailable");
-        }
+        })
     }
 
-    pub fn apply(self, ipc_parent: &mut IpcParent, tx: &EventSender, reader: &mut Option<BufReader<Stdin>>) {
+    pub fn apply(self, ipc_parent: &mut IpcParent, tx: &EventSender, stdin: Option<&SharedStdin>) {
         let reply = match self {
-            StdioEvent::Readline => Self::read_line(reader),
-            StdioEvent::ToEnd => Self::read_to_end(reader),
+            StdioEvent::Readline => Self::read_line(stdin),
+            StdioEvent::ToEnd => Self::read_to_end(stdin),
         };
         let reply = reply.map_err(|e| e.to_string());
         ipc_parent.send_struct(reply, tx);
@@ -438,68 +584,413 @@ impl RatelimitEvent {
     }
 }
 
-pub fn spawn(rl: &mut Shell,
-             module: &Module,
-             ratelimit: &mut Ratelimiter,
-             args: Vec<(serde_json::Value, Option<String>, Vec<Blob>)>,
-             params: &Params,
-             proxy: Option<SocketAddr>,
-             user_agent: Option<String>,
-             options: HashMap<String, String>,
-) -> usize {
-    // This function hangs if args is empty, so return early if that's the case
-    if args.is_empty() {
-        return 0;
+#[derive(Debug, Serialize, Deserialize)]
+pub enum KeyringEvent {
+    /// Request the next key of a namespace
+    Rotate(String),
+    /// Skip this key until the timestamp passes
+    Exhausted((KeyName, NaiveDateTime)),
+    /// The module read these keys with `keyring`
+    Used(Vec<KeyName>),
+}
+
+impl EventWithCallback for KeyringEvent {
+    type Payload = Option<KeyRingEntry>;
+
+    fn with_callback(self, tx: mpsc::Sender<result::Result<Self::Payload, String>>) -> Event2 {
+        Event2::Keyring((self, tx))
     }
+}
 
-    let verbose = params.verbose;
-    let has_stdin = params.stdin;
-    let keyring = rl.keyring().request_keys(module);
+impl KeyringEvent {
+    fn reply(self, rl: &mut Shell, rotation: &mut KeyRotation, usage: &mut UsageCounter, keys: &[KeyRingEntry]) -> Result<Option<KeyRingEntry>> {
+        let now = Utc::now().naive_utc();
+        match self {
+            KeyringEvent::Rotate(namespace) => {
+                let key = rotation.next(rl.keyring(), keys, &namespace, now);
+                if let Some(key) = &key {
+                    usage.add(key.name());
+                }
+                Ok(key)
+            },
+            KeyringEvent::Exhausted((key, until)) => {
+                if !keys.iter().any(|x| x.name() == key) {
+                    bail!("Module has no access to key: {}", key);
+                }
+                rl.keyring_mut().exhaust(&key, until, now)?;
+                Ok(None)
+            },
+            KeyringEvent::Used(used) => {
+                for key in used {
+                    if keys.iter().any(|x| x.name() == key) {
+                        usage.add(key);
+                    }
+                }
+                Ok(None)
+            },
+        }
+    }
 
-    let mut stack = StackedSpinners::new();
+    pub fn apply(self, rl: &mut Shell, rotation: &mut KeyRotation, usage: &mut UsageCounter, keys: &[KeyRingEntry], tx: KeyringSender) {
+        let reply = self.reply(rl, rotation, usage, keys)
+            .map_err(|e| e.to_string());
+        tx.send(reply).expect("Failed to send keyring result to channel");
+    }
+}
 
-    let (tx, rx) = channel::bounded(1);
-    let pool = ThreadPool::new(params.threads);
+#[derive(Debug, Serialize, Deserialize)]
+pub enum PromptEvent {
+    Select {
+        text: String,
+        options: Vec<String>,
+        default: Option<usize>,
+    },
+    Confirm {
+        text: String,
+        default: Option<bool>,
+    },
+    Text {
+        text: String,
+    },
+    Password {
+        text: String,
+    },
+}
 
-    let mut expected = 0;
-    debug!("Preparing to spawn scripts for {:?} structs", args.len());
-    for (arg, pretty_arg, blobs) in args {
-        let name = match pretty_arg {
-            Some(pretty_arg) => format!("{:?}", pretty_arg),
-            None => module.canonical(),
+/// Reads the answers to prompts of a module
+pub trait Prompter {
+    fn question(&mut self, text: &str) -> Result<String>;
+
+    /// Read an answer without echoing it
+    fn question_password(&mut self, text: &str) -> Result<String>;
+}
+
+impl Prompter for Shell<'_> {
+    fn question(&mut self, text: &str) -> Result<String> {
+        Shell::question(self, text)
+    }
+
+    fn question_password(&mut self, text: &str) -> Result<String> {
+        utils::question_password(text)
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum PromptResponse {
+    Select(usize),
+    Confirm(bool),
+    Text(String),
+}
+
+impl EventWithCallback for PromptEvent {
+    type Payload = PromptResponse;
+
+    fn with_callback(self, tx: mpsc::Sender<result::Result<Self::Payload, String>>) -> Event2 {
+        Event2::Prompt((self, tx))
+    }
+}
+
+impl PromptEvent {
+    fn default_answer(&self) -> Result<PromptResponse> {
+        match self {
+            PromptEvent::Select { default: Some(idx), .. } => Ok(PromptResponse::Select(*idx)),
+            PromptEvent::Confirm { default: Some(answer), .. } => Ok(PromptResponse::Confirm(*answer)),
+            PromptEvent::Text { .. } | PromptEvent::Password { .. } => {
+                bail!("Can't ask for input, sn0int isn't running interactively")
+            },
+            _ => bail!("Not running interactively and prompt has no default"),
+        }
+    }
+
+    fn ask<P: Prompter>(&self, prompter: &mut P, name: &str) -> Result<PromptResponse> {
+        match self {
+            PromptEvent::Select { text, options, default } => {
+                println!("{} {}: {}", Tag(Style::Blue, '?'), name, text);
+                for (i, option) in options.iter().enumerate() {
+                    println!("  {}) {}", i + 1, option);
+                }
+
+                loop {
+                    let answer = prompter.question("Select")?;
+                    let answer = match (answer.as_str(), default) {
+                        ("", Some(idx)) => return Ok(PromptResponse::Select(*idx)),
+                        (answer, _) => answer.parse::<usize>(),
+                    };
+
+                    match answer {
+                        Ok(n) if n >= 1 && n <= options.len() => return Ok(PromptResponse::Select(n - 1)),
+                        _ => term::error(&format!("Enter a number from 1 to {}", options.len())),
+                    }
+                }
+            },
+            PromptEvent::Confirm { text, default } => {
+                let default = default.unwrap_or(false);
+                let hint = if default { "[Y/n]" } else { "[y/N]" };
+                loop {
+                    let answer = prompter.question(&format!("{}: {} {}", name, text, hint))?;
+                    match answer.to_lowercase().as_str() {
+                        "" => return Ok(PromptResponse::Confirm(default)),
+                        "y" | "yes" => return Ok(PromptResponse::Confirm(true)),
+                        "n" | "no" => return Ok(PromptResponse::Confirm(false)),
+                        _ => term::error("Enter y or n"),
+                    }
+                }
+            },
+            PromptEvent::Text { text } => {
+                let answer = prompter.question(&format!("{}: {}", name, text))?;
+                Ok(PromptResponse::Text(answer))
+            },
+            PromptEvent::Password { text } => {
+                let answer = prompter.question_password(&format!("{}: {}", name, text))?;
+                Ok(PromptResponse::Text(answer))
+            },
+        }
+    }
+
+    pub fn apply<S: Stack, P: Prompter>(self, stack: &mut S, prompter: &mut P, name: &str, tx: PromptSender, interactive: bool) {
+        let reply = if interactive {
+            stack.jump2start();
+            self.ask(prompter, name)
+        } else {
+            self.default_answer()
         };
+        let reply = reply.map_err(|e| e.to_string());
+        tx.send(reply).expect("Failed to send prompt result to channel");
+    }
+}
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NotifyEvent {
+    pub topic: String,
+    pub subject: String,
+    pub body: Option<String>,
+}
+
+impl EventWithCallback for NotifyEvent {
+    type Payload = bool;
+
+    fn with_callback(self, tx: mpsc::Sender<result::Result<Self::Payload, String>>) -> Event2 {
+        Event2::Notify((self, tx))
+    }
+}
+
+impl NotifyEvent {
+    /// The topic is prefixed with the module that sent it, so a module can't
+    /// impersonate database events or other modules
+    fn topic(&self, module: &Module) -> String {
+        format!("module:{}:{}", module.canonical(), self.topic)
+    }
+
+    /// Queue the notification, it's delivered once the database changes of
+    /// the batch have been committed
+    fn queue<T: SpinLogger>(self, spinner: &mut T, module: &Module, sent: &mut usize, queued: &mut Vec<(String, Notification)>) -> Result<bool> {
+        if *module.source() == Some(Source::Notifications) {
+            bail!("Notification modules can't send notifications");
+        }
+
+        if *sent >= NOTIFY_LIMIT {
+            spinner.warn_once(&format!("Notification limit of {} per run reached, dropping further notifications", NOTIFY_LIMIT));
+            return Ok(false);
+        }
+        *sent += 1;
+
+        let topic = self.topic(module);
+        spinner.log(&format!("Sending notification {:?}: {}", topic, self.subject));
+        queued.push((topic, Notification {
+            subject: self.subject,
+            body: self.body,
+            entity: None,
+        }));
+
+        Ok(true)
+    }
+
+    pub fn apply<T: SpinLogger>(self, spinner: &mut T, module: &Module, sent: &mut usize, queued: &mut Vec<(String, Notification)>, tx: NotifySender) {
+        let reply = self.queue(spinner, module, sent, queued)
+            .map_err(|e| e.to_string());
+        tx.send(reply).expect("Failed to send notify result to channel");
+    }
+}
+
+/// The number of scripts that failed, including those that only failed due to offline mode
+#[derive(Debug, Default, PartialEq)]
+pub struct Failures {
+    pub errors: usize,
+    pub offline: usize,
+}
+
+/// Prompts are only shown if there's a terminal we can read the answer from
+/// and we don't have to interrupt multiple modules running in parallel
+fn is_interactive(params: &Params) -> bool {
+    params.output == OutputFormat::Human && !params.stdin && params.threads <= 1 && atty::is(atty::Stream::Stdin) && atty::is(atty::Stream::Stdout)
+}
+
+/// Everything that's needed to start a sandbox child for the module
+#[derive(Clone)]
+struct Job {
+    module: Module,
+    keyring: Vec<KeyRingEntry>,
+    verbose: u8,
+    stdin: Option<Arc<SharedStdin>>,
+    proxy: Option<SocketAddr>,
+    user_agent: Option<String>,
+    options: HashMap<String, String>,
+    read_grants: ReadGrants,
+    signal_register: Arc<SignalRegister>,
+    network: NetworkConfig,
+    profile: Profile,
+    sandbox: SandboxConfig,
+    concurrency: Option<Arc<ConcurrencyLimit>>,
+    /// Rows inserted by all sources of the run, for sandbox.max-output-rows
+    rows: Arc<AtomicUsize>,
+}
+
+impl Job {
+    fn execute(&self, pool: &ThreadPool, tx: &channel::Sender<MultiEvent>, name: String, entrypoint: Entrypoint, arg: serde_json::Value, blobs: Vec<Blob>) {
         let tx = tx.clone();
-        let module = module.clone();
-        let keyring = keyring.clone();
-        let user_agent = user_agent.clone();
-        let options = options.clone();
-        let signal_register = rl.signal_register().clone();
+        let job = self.clone();
         pool.execute(move || {
             debug!("Thread pool job became active");
             let tx = EventSender::new(name, tx);
 
-            if signal_register.ctrlc_received() {
+            if job.signal_register.ctrlc_received() {
                 debug!("Thread pool job exits due to ctrl-c");
                 tx.send(Event2::Exit(ExitEvent::Ok));
                 return;
             }
 
             tx.send(Event2::Start);
-            let event = match ipc::parent::run(module, &tx, arg, keyring, verbose, has_stdin, proxy, user_agent, options, blobs) {
+            let event = match ipc::parent::run(job.module, &tx, entrypoint, arg, job.keyring, job.verbose, job.stdin.as_deref(), job.proxy, job.user_agent, job.options, blobs, &job.read_grants, &job.network, job.profile, &job.sandbox, job.concurrency.as_deref(), &job.rows) {
                 Ok(exit) => exit,
                 // TODO: this should include the whole error chain
                 Err(err) => ExitEvent::SetupFailed(err.to_string()),
             };
             tx.send(Event2::Exit(event));
         });
+    }
+}
+
+pub fn spawn(rl: &mut Shell,
+             module: &Module,
+             ratelimit: &mut Ratelimiter,
+             args: Vec<(serde_json::Value, Option<String>, Vec<Blob>)>,
+             params: &Params,
+             proxy: Option<SocketAddr>,
+             user_agent: Option<String>,
+             options: HashMap<String, String>,
+             read_grants: ReadGrants,
+) -> Failures {
+    match params.output {
+        OutputFormat::Human => spawn_with(rl, module, ratelimit, args, params, proxy, user_agent, options, read_grants, StackedSpinners::new()),
+        OutputFormat::Json => spawn_with(rl, module, ratelimit, args, params, proxy, user_agent, options, read_grants, JsonLines::default()),
+    }
+}
+
+fn spawn_with<S: Stack>(rl: &mut Shell,
+             module: &Module,
+             ratelimit: &mut Ratelimiter,
+             args: Vec<(serde_json::Value, Option<String>, Vec<Blob>)>,
+             params: &Params,
+             proxy: Option<SocketAddr>,
+             user_agent: Option<String>,
+             options: HashMap<String, String>,
+             read_grants: ReadGrants,
+             mut stack: S,
+) -> Failures {
+    // This function hangs if args is empty, so return early if that's the case
+    if args.is_empty() {
+        return Failures::default();
+    }
+
+    let verbose = params.verbose;
+    let quiet = params.quiet;
+    let stdin = if params.stdin {
+        Some(Arc::new(SharedStdin::default()))
+    } else {
+        None
+    };
+    let interactive = is_interactive(params);
+    let keyring = rl.keyring().request_keys(module, rl.workspace());
+
+    let now = Utc::now().naive_utc();
+    for (namespace, until) in rl.keyring().exhausted_namespaces(module.keyring_access(), &keyring, now) {
+        term::warn(&format!("All {:?} keys are exhausted until {}, the module is likely going to fail", namespace, until));
+    }
+
+    let (tx, rx) = channel::bounded(1);
+    let pool = ThreadPool::new(params.threads);
+
+    let job = Job {
+        module: module.clone(),
+        keyring: keyring.clone(),
+        verbose,
+        stdin,
+        proxy,
+        user_agent,
+        options,
+        read_grants,
+        signal_register: rl.signal_register().clone(),
+        network: rl.config().network.clone(),
+        profile: params.get_profile(module),
+        sandbox: rl.config().sandbox.clone(),
+        concurrency: params.max_concurrency
+            .or(rl.config().network.max_concurrency)
+            .map(|limit| Arc::new(ConcurrencyLimit::new(limit))),
+        rows: Arc::new(AtomicUsize::new(0)),
+    };
+
+    let mut expected = 0;
+    debug!("Preparing to spawn scripts for {:?} structs", args.len());
+    let mut origins = HashMap::new();
+    for (arg, pretty_arg, blobs) in args {
+        let name = match &pretty_arg {
+            Some(pretty_arg) => S::source_name(pretty_arg),
+            None => module.canonical(),
+        };
+        origins.insert(name.clone(), Origin {
+            module: module.canonical(),
+            version: module.version().to_string(),
+            target: pretty_arg,
+            source: module.source().as_ref().and_then(Family::from_source),
+        });
+
+        job.execute(&pool, &tx, name, Entrypoint::Run, arg, blobs);
         expected += 1;
     }
 
-    let mut errors = 0;
+    let targets = expected;
+    let mut pending_finish = false;
+    stack.track(targets);
+
+    let mut failures = Failures::default();
+    let mut notifications = 0;
+    let mut rotation = KeyRotation::default();
+    let mut usage = UsageCounter::default();
     let mut failed = Vec::new();
+    let mut finishing = false;
+    let mut batch = Batch::new(rl.config().core.batch_size);
+    rl.db().cache_lookups(true);
+    // the investigations with changes in the batch
+    let mut batched = BTreeSet::new();
+    let mut outbox = Outbox::default();
+    // the investigations whose changes have been rolled back
+    let mut rolled_back = BTreeSet::new();
     let timeout = Duration::from_millis(100);
     loop {
+        // the batch is committed before waiting for the next event, so it
+        // isn't kept open by a slow module
+        if batch.is_due() {
+            rolled_back.extend(commit_batch(rl, &mut stack, &mut batch, &mut batched, &mut outbox));
+        }
+        if batch.is_empty() {
+            outbox.send(rl, &mut stack, ratelimit);
+            // the windows of digests also close if no further events arrive,
+            // notification modules are started while they're being flushed
+            if *module.source() != Some(Source::Notifications) {
+                notify::flush_expired(rl, &mut stack);
+            }
+        }
+
         select! {
             recv(rx) -> msg => match msg.ok() {
                 Some(event) => {
@@ -510,31 +1001,106 @@ pub fn spawn(rl: &mut Shell,
                             let label = format!("Investigating {}", name);
                             stack.add(name, label);
                         },
-                        Event2::Log(log) => log.apply(&mut stack.prefixed(name)),
+                        Event2::Log(log) => if !(quiet && log.is_chatter()) {
+                            log.apply(&mut stack.prefixed(name))
+                        },
                         Event2::Database(tuple) => {
                             let (db, tx) = *tuple;
-                            db.apply(rl, &mut stack.prefixed(name), ratelimit, tx, verbose)
+                            // the child still holds ids of rows that don't exist anymore
+                            if rolled_back.contains(&name) {
+                                let err = "Earlier database changes of this run have been rolled back".to_string();
+                                tx.send(Err(err)).expect("Failed to send db result to channel");
+                            } else {
+                                rl.db_mut().set_origin(origins.get(&name).cloned());
+                                if let Err(err) = batch.row(rl.db()) {
+                                    warn!("Failed to start batch, writing without: {}", err);
+                                } else {
+                                    batched.insert(name.clone());
+                                }
+                                let mut queued = Vec::new();
+                                db.apply(rl, &mut stack.prefixed(name.clone()), &mut queued, tx, verbose);
+                                outbox.extend(&name, queued);
+                            }
                         },
                         Event2::Ratelimit((req, tx)) => ratelimit.pass(tx, &req.key, req.passes, req.time),
+                        Event2::Keyring((req, tx)) => req.apply(rl, &mut rotation, &mut usage, &keyring, tx),
                         Event2::Blob((blob, tx)) => rl.store_blob(tx, &blob),
+                        Event2::Prompt((prompt, tx)) => {
+                            // don't keep other connections waiting for the answer
+                            rolled_back.extend(commit_batch(rl, &mut stack, &mut batch, &mut batched, &mut outbox));
+                            prompt.apply(&mut stack, rl, &name, tx, interactive);
+                        },
+                        Event2::Notify((event, tx)) => {
+                            let mut queued = Vec::new();
+                            event.apply(&mut stack.prefixed(name.clone()), module, &mut notifications, &mut queued, tx);
+                            outbox.extend(&name, queued);
+                        },
+                        Event2::OnFinish => pending_finish |= !finishing,
                         Event2::Exit(event) => {
                             debug!("Received exit: {:?} -> {:?}", name, event);
+                            let event = match event {
+                                ExitEvent::Ok if rolled_back.contains(&name) => {
+                                    ExitEvent::Err("Database changes have been rolled back".to_string())
+                                },
+                                event => event,
+                            };
                             stack.remove(&name);
 
+                            match &event {
+                                ExitEvent::Ok => stack.event(Some(&name), RunEvent::SourceFinished),
+                                ExitEvent::Err(error) | ExitEvent::Offline(error) | ExitEvent::SetupFailed(error) => {
+                                    stack.event(Some(&name), RunEvent::SourceFailed {
+                                        error: error.clone(),
+                                    });
+                                },
+                            }
+
+                            // on_finish isn't one of the targets
+                            if !finishing {
+                                stack.finished(match &event {
+                                    ExitEvent::Ok => Outcome::Ok,
+                                    ExitEvent::Offline(_) => Outcome::Skipped,
+                                    ExitEvent::Err(_) | ExitEvent::SetupFailed(_) => Outcome::Failed,
+                                });
+                            }
+
                             if ExitEvent::Ok != event {
                                 trace!("bumping error counter");
-                                errors += 1;
+                                failures.errors += 1;
+                            }
+
+                            if let ExitEvent::Offline(_) = event {
+                                failures.offline += 1;
                             }
 
                             if let ExitEvent::SetupFailed(error) = event {
                                 failed.push((name, error));
                             }
 
-                            // if every task reported back, exit
+                            // if every task reported back, run on_finish or exit
                             expected -= 1;
                             info!("spawn_all is expecting {} more results", expected);
                             if expected == 0 {
-                                break;
+                                if !pending_finish || rl.signal_register().ctrlc_received() {
+                                    break;
+                                }
+                                pending_finish = false;
+                                finishing = true;
+
+                                let name = format!("{} on_finish", module.canonical());
+                                origins.insert(name.clone(), Origin {
+                                    module: module.canonical(),
+                                    version: module.version().to_string(),
+                                    target: None,
+                                    source: None,
+                                });
+                                let arg = serde_json::json!({
+                                    "targets": targets,
+                                    "errors": failures.errors,
+                                    "failed": failures.errors > 0,
+                                });
+                                job.execute(&pool, &tx, name, Entrypoint::Finish, arg, Vec::new());
+                                expected += 1;
                             }
                         },
                     }
@@ -545,6 +1111,19 @@ pub fn spawn(rl: &mut Shell,
         }
         stack.tick();
     }
+    if !commit_batch(rl, &mut stack, &mut batch, &mut batched, &mut outbox).is_empty() {
+        failures.errors += 1;
+    }
+    outbox.send(rl, &mut stack, ratelimit);
+
+    rl.db().cache_lookups(false);
+    rl.db_mut().set_origin(None);
+
+    if !usage.is_empty() {
+        if let Err(err) = rl.keyring_mut().record_usage(&usage, Utc::now().naive_utc()) {
+            stack.error(&format!("Failed to record keyring usage: {}", err));
+        }
+    }
 
     for (name, fail) in &failed {
         stack.error(&format!("Failed {}: {}", name, fail));
@@ -552,7 +1131,28 @@ pub fn spawn(rl: &mut Shell,
 
     stack.clear();
 
-    errors
+    failures
+}
+
+/// Commit the database changes of the batch, if this fails they are lost and
+/// so are the notifications about them. Returns the investigations whose
+/// changes have been rolled back.
+fn commit_batch<S: Stack>(rl: &Shell, stack: &mut S, batch: &mut Batch, batched: &mut BTreeSet<String>, outbox: &mut Outbox) -> BTreeSet<String> {
+    let rows = batch.len();
+    if let Err(err) = batch.commit(rl.db()) {
+        let sources = batched.iter()
+            .map(|x| x.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        stack.error(&format!("Failed to write {} database changes of {}, they have been rolled back: {}", rows, sources, err));
+        let dropped = outbox.discard();
+        if dropped > 0 {
+            stack.warn(&format!("Dropped {} notifications about the changes that have been rolled back", dropped));
+        }
+        return mem::take(batched);
+    }
+    batched.clear();
+    BTreeSet::new()
 }
 
 pub fn spawn_fn<F, T>(label: &str, f: F, clear: bool) -> Result<T>
@@ -571,11 +1171,19 @@ pub fn spawn_fn<F, T>(label: &str, f: F, clear: bool) -> Result<T>
                     Some(Event::Log(log)) => log.apply(&mut *spinner),
                     Some(Event::Database(_)) => (),
                     Some(Event::Stdio(_)) => (),
+                    Some(Event::File(_)) => (),
                     Some(Event::Ratelimit(_)) => (),
+                    Some(Event::Concurrency(_)) => (),
+                    Some(Event::Network(_)) => (),
+                    Some(Event::Keyring(_)) => (),
                     Some(Event::Blob(_)) => (),
+                    Some(Event::Prompt(_)) => (),
+                    Some(Event::Notify(_)) => (),
+                    Some(Event::OnFinish) => (),
                     // TODO: refactor
                     Some(Event::Exit(ExitEvent::Ok)) => break,
                     Some(Event::Exit(ExitEvent::Err(error))) => spinner.error(&error),
+                    Some(Event::Exit(ExitEvent::Offline(error))) => spinner.error(&error),
                     Some(Event::Exit(ExitEvent::SetupFailed(error))) => spinner.error(&error),
                     None => break, // channel closed
                 },
@@ -655,14 +1263,18 @@ pub fn spawn_multi<T: Task, F>(tasks: Vec<T>, mut done_fn: F, threads: usize) ->
                         Event2::Log(log) => log.apply(&mut stack.prefixed(&name)),
                         Event2::Database(_) => (),
                         Event2::Ratelimit(_) => (),
+                        Event2::Keyring(_) => (),
                         Event2::Blob(_) => (),
+                        Event2::Prompt(_) => (),
+                        Event2::Notify(_) => (),
+                        Event2::OnFinish => (),
                         Event2::Exit(event) => {
                             debug!("Received exit: {:?} -> {:?}", name, event);
                             stack.remove(&name);
 
                             match event {
                                 ExitEvent::Ok => done_fn(name),
-                                ExitEvent::Err(err) => {
+                                ExitEvent::Err(err) | ExitEvent::Offline(err) => {
                                     LogEvent::Error(err).apply(&mut stack.prefixed(&name));
                                 },
                                 ExitEvent::SetupFailed(_) => (),
@@ -688,3 +1300,109 @@ pub fn spawn_multi<T: Task, F>(tasks: Vec<T>, mut done_fn: F, threads: usize) ->
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    struct Answers {
+        answers: VecDeque<&'static str>,
+        asked: Vec<String>,
+    }
+
+    impl Answers {
+        fn new(answers: &[&'static str]) -> Answers {
+            Answers {
+                answers: answers.iter().copied().collect(),
+                asked: Vec::new(),
+            }
+        }
+    }
+
+    impl Prompter for Answers {
+        fn question(&mut self, text: &str) -> Result<String> {
+            self.asked.push(text.to_string());
+            self.answers.pop_front()
+                .map(String::from)
+                .ok_or_else(|| format_err!("eof"))
+        }
+
+        fn question_password(&mut self, text: &str) -> Result<String> {
+            self.question(&format!("password {}", text))
+        }
+    }
+
+    #[test]
+    fn test_prompt_select() {
+        let prompt = PromptEvent::Select {
+            text: String::from("Which one?"),
+            options: vec![String::from("a"), String::from("b")],
+            default: None,
+        };
+        // invalid answers are asked again
+        let mut answers = Answers::new(&["3", "x", "2"]);
+        assert_eq!(prompt.ask(&mut answers, "test").unwrap(), PromptResponse::Select(1));
+        assert_eq!(answers.asked.len(), 3);
+
+        let prompt = PromptEvent::Select {
+            text: String::from("Which one?"),
+            options: vec![String::from("a"), String::from("b")],
+            default: Some(1),
+        };
+        let mut answers = Answers::new(&[""]);
+        assert_eq!(prompt.ask(&mut answers, "test").unwrap(), PromptResponse::Select(1));
+    }
+
+    #[test]
+    fn test_prompt_confirm() {
+        let prompt = PromptEvent::Confirm {
+            text: String::from("Continue?"),
+            default: Some(true),
+        };
+        let mut answers = Answers::new(&[""]);
+        assert_eq!(prompt.ask(&mut answers, "test").unwrap(), PromptResponse::Confirm(true));
+        assert_eq!(answers.asked, &["test: Continue? [Y/n]"]);
+
+        let prompt = PromptEvent::Confirm {
+            text: String::from("Continue?"),
+            default: None,
+        };
+        let mut answers = Answers::new(&["maybe", "Y"]);
+        assert_eq!(prompt.ask(&mut answers, "test").unwrap(), PromptResponse::Confirm(true));
+        assert_eq!(answers.asked, &["test: Continue? [y/N]", "test: Continue? [y/N]"]);
+
+        let mut answers = Answers::new(&[]);
+        assert!(prompt.ask(&mut answers, "test").is_err());
+    }
+
+    #[test]
+    fn test_prompt_text_and_password() {
+        let prompt = PromptEvent::Text {
+            text: String::from("2fa code"),
+        };
+        let mut answers = Answers::new(&["123456"]);
+        assert_eq!(prompt.ask(&mut answers, "test").unwrap(), PromptResponse::Text(String::from("123456")));
+
+        let prompt = PromptEvent::Password {
+            text: String::from("password"),
+        };
+        let mut answers = Answers::new(&["hunter2"]);
+        assert_eq!(prompt.ask(&mut answers, "test").unwrap(), PromptResponse::Text(String::from("hunter2")));
+        assert_eq!(answers.asked, &["password test: password"]);
+    }
+
+    #[test]
+    fn test_prompt_not_interactive() {
+        let prompt = PromptEvent::Confirm {
+            text: String::from("Continue?"),
+            default: Some(false),
+        };
+        assert_eq!(prompt.default_answer().unwrap(), PromptResponse::Confirm(false));
+
+        let prompt = PromptEvent::Text {
+            text: String::from("2fa code"),
+        };
+        assert!(prompt.default_answer().is_err());
+    }
+}